@@ -30,6 +30,22 @@
 //! let console = ConsoleComponent::new(board_kernel, uart_mux)
 //!    .finalize(console_component_static!());
 //! ```
+//!
+//! To share received bytes between the userspace console and the process
+//! console by channel instead of copying them to both, configure the mux with
+//! a prefixed receive policy and give each console its own channel:
+//!
+//! ```rust
+//! let uart_mux = UartMuxComponent::new(&sam4l::usart::USART3, 115200)
+//!     .with_receive_policy(ReceivePolicy::Prefixed { escape: 0x10 })
+//!     .finalize(components::uart_mux_component_static!());
+//! let console = ConsoleComponent::new(board_kernel, uart_mux)
+//!    .with_receive_channel(0)
+//!    .finalize(console_component_static!());
+//! let pconsole = ProcessConsoleComponent::new(board_kernel, uart_mux, ...)
+//!    .with_receive_channel(1)
+//!    .finalize(process_console_component_static!());
+//! ```
 // Author: Philip Levis <pal@cs.stanford.edu>
 // Last modified: 1/08/2023
 
@@ -37,7 +53,7 @@ use capsules_core::console;
 use capsules_core::console_ordered::ConsoleOrdered;
 
 use capsules_core::virtualizers::virtual_alarm::{MuxAlarm, VirtualMuxAlarm};
-use capsules_core::virtualizers::virtual_uart::{MuxUart, UartDevice};
+use capsules_core::virtualizers::virtual_uart::{MuxUart, ReceivePolicy, UartDevice};
 use core::mem::MaybeUninit;
 use kernel::capabilities;
 use kernel::component::Component;
@@ -69,6 +85,7 @@ macro_rules! uart_mux_component_static {
 pub struct UartMuxComponent<const RX_BUF_LEN: usize> {
     uart: &'static dyn uart::Uart<'static>,
     baud_rate: u32,
+    receive_policy: ReceivePolicy,
 }
 
 impl<const RX_BUF_LEN: usize> UartMuxComponent<RX_BUF_LEN> {
@@ -76,7 +93,20 @@ impl<const RX_BUF_LEN: usize> UartMuxComponent<RX_BUF_LEN> {
         uart: &'static dyn uart::Uart<'static>,
         baud_rate: u32,
     ) -> UartMuxComponent<RX_BUF_LEN> {
-        UartMuxComponent { uart, baud_rate }
+        UartMuxComponent {
+            uart,
+            baud_rate,
+            receive_policy: ReceivePolicy::Broadcast,
+        }
+    }
+
+    /// Distribute received bytes according to `policy` instead of copying
+    /// them to every receiving device.
+    pub fn with_receive_policy(self, policy: ReceivePolicy) -> Self {
+        Self {
+            receive_policy: policy,
+            ..self
+        }
     }
 }
 
@@ -91,6 +121,7 @@ impl<const RX_BUF_LEN: usize> Component for UartMuxComponent<RX_BUF_LEN> {
         let rx_buf = s.1.write([0; RX_BUF_LEN]);
         let uart_mux = s.0.write(MuxUart::new(self.uart, rx_buf, self.baud_rate));
         kernel::deferred_call::DeferredCallClient::register(uart_mux);
+        uart_mux.set_receive_policy(self.receive_policy);
 
         uart_mux.initialize();
         hil::uart::Transmit::set_transmit_client(self.uart, uart_mux);
@@ -126,6 +157,7 @@ pub struct ConsoleComponent<const RX_BUF_LEN: usize, const TX_BUF_LEN: usize> {
     board_kernel: &'static kernel::Kernel,
     driver_num: usize,
     uart_mux: &'static MuxUart<'static>,
+    receive_channel: Option<u8>,
 }
 
 impl<const RX_BUF_LEN: usize, const TX_BUF_LEN: usize> ConsoleComponent<RX_BUF_LEN, TX_BUF_LEN> {
@@ -138,6 +170,16 @@ impl<const RX_BUF_LEN: usize, const TX_BUF_LEN: usize> ConsoleComponent<RX_BUF_L
             board_kernel,
             driver_num,
             uart_mux,
+            receive_channel: None,
+        }
+    }
+
+    /// Receive only channel `channel` when the UART mux uses
+    /// `ReceivePolicy::Prefixed`.
+    pub fn with_receive_channel(self, channel: u8) -> Self {
+        Self {
+            receive_channel: Some(channel),
+            ..self
         }
     }
 }
@@ -162,6 +204,10 @@ impl<const RX_BUF_LEN: usize, const TX_BUF_LEN: usize> Component
 
         let console_uart = s.2.write(UartDevice::new(self.uart_mux, true));
         console_uart.setup();
+        console_uart.set_label("console");
+        if let Some(channel) = self.receive_channel {
+            console_uart.set_receive_channel(channel);
+        }
 
         let console = s.3.write(console::Console::new(
             console_uart,
@@ -195,6 +241,7 @@ pub struct ConsoleOrderedComponent<A: 'static + time::Alarm<'static>> {
     atomic_size: usize,
     retry_timer: u32,
     write_timer: u32,
+    receive_channel: Option<u8>,
 }
 
 impl<A: 'static + time::Alarm<'static>> ConsoleOrderedComponent<A> {
@@ -215,6 +262,16 @@ impl<A: 'static + time::Alarm<'static>> ConsoleOrderedComponent<A> {
             atomic_size,
             retry_timer,
             write_timer,
+            receive_channel: None,
+        }
+    }
+
+    /// Receive only channel `channel` when the UART mux uses
+    /// `ReceivePolicy::Prefixed`.
+    pub fn with_receive_channel(self, channel: u8) -> Self {
+        Self {
+            receive_channel: Some(channel),
+            ..self
         }
     }
 }
@@ -238,6 +295,10 @@ impl<A: 'static + time::Alarm<'static>> Component for ConsoleOrderedComponent<A>
 
         let console_uart = static_buffer.2.write(UartDevice::new(self.uart_mux, true));
         console_uart.setup();
+        console_uart.set_label("console");
+        if let Some(channel) = self.receive_channel {
+            console_uart.set_receive_channel(channel);
+        }
 
         let console = static_buffer.3.write(ConsoleOrdered::new(
             console_uart,
@@ -0,0 +1,77 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2024.
+
+//! Component for saving panic output to a nonvolatile crash region.
+//!
+//! Usage
+//! -----
+//! ```rust
+//! let crash_dump = components::crash_dump::NonvolatileCrashDumpComponent::new(
+//!     crash_dump_storage,
+//!     0x60000,
+//!     0x1000,
+//! )
+//! .finalize(components::nonvolatile_crash_dump_component_static!());
+//! ```
+
+use capsules_extra::crash_dump::NonvolatileCrashDump;
+use core::mem::MaybeUninit;
+use kernel::component::Component;
+use kernel::hil;
+
+#[macro_export]
+macro_rules! nonvolatile_crash_dump_component_static {
+    () => {{
+        $crate::nonvolatile_crash_dump_component_static!(capsules_extra::crash_dump::BUF_LEN)
+    };};
+    ($BUF_LEN:expr $(,)?) => {{
+        let crash_dump =
+            kernel::static_buf!(capsules_extra::crash_dump::NonvolatileCrashDump<'static>);
+        let buffer = kernel::static_buf!([u8; $BUF_LEN]);
+
+        (crash_dump, buffer)
+    };};
+}
+
+pub struct NonvolatileCrashDumpComponent<const BUF_LEN: usize> {
+    storage: &'static dyn hil::nonvolatile_storage::NonvolatileStorage<'static>,
+    start: usize,
+    length: usize,
+}
+
+impl<const BUF_LEN: usize> NonvolatileCrashDumpComponent<BUF_LEN> {
+    /// Save panic output to the `length` bytes of `storage` at `start`.
+    pub fn new(
+        storage: &'static dyn hil::nonvolatile_storage::NonvolatileStorage<'static>,
+        start: usize,
+        length: usize,
+    ) -> Self {
+        Self {
+            storage,
+            start,
+            length,
+        }
+    }
+}
+
+impl<const BUF_LEN: usize> Component for NonvolatileCrashDumpComponent<BUF_LEN> {
+    type StaticInput = (
+        &'static mut MaybeUninit<NonvolatileCrashDump<'static>>,
+        &'static mut MaybeUninit<[u8; BUF_LEN]>,
+    );
+    type Output = &'static NonvolatileCrashDump<'static>;
+
+    fn finalize(self, s: Self::StaticInput) -> Self::Output {
+        let buffer = s.1.write([0; BUF_LEN]);
+        let crash_dump = s.0.write(NonvolatileCrashDump::new(
+            self.storage,
+            self.start,
+            self.length,
+            buffer,
+        ));
+        self.storage.set_client(crash_dump);
+
+        crash_dump
+    }
+}
@@ -0,0 +1,54 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2024.
+
+//! Component for the debug output snapshot driver.
+//!
+//! Usage
+//! -----
+//! ```rust
+//! let debug_snapshot = DebugSnapshotComponent::new(
+//!     board_kernel,
+//!     capsules_extra::debug_snapshot::DRIVER_NUM,
+//! )
+//! .finalize(components::debug_snapshot_component_static!());
+//! ```
+
+use capsules_extra::debug_snapshot::DebugSnapshot;
+use core::mem::MaybeUninit;
+use kernel::capabilities;
+use kernel::component::Component;
+use kernel::create_capability;
+
+#[macro_export]
+macro_rules! debug_snapshot_component_static {
+    () => {{
+        kernel::static_buf!(capsules_extra::debug_snapshot::DebugSnapshot)
+    };};
+}
+
+pub struct DebugSnapshotComponent {
+    board_kernel: &'static kernel::Kernel,
+    driver_num: usize,
+}
+
+impl DebugSnapshotComponent {
+    pub fn new(board_kernel: &'static kernel::Kernel, driver_num: usize) -> DebugSnapshotComponent {
+        DebugSnapshotComponent {
+            board_kernel,
+            driver_num,
+        }
+    }
+}
+
+impl Component for DebugSnapshotComponent {
+    type StaticInput = &'static mut MaybeUninit<DebugSnapshot>;
+    type Output = &'static DebugSnapshot;
+
+    fn finalize(self, s: Self::StaticInput) -> Self::Output {
+        let grant_cap = create_capability!(capabilities::MemoryAllocationCapability);
+        let grant = self.board_kernel.create_grant(self.driver_num, &grant_cap);
+
+        s.write(DebugSnapshot::new(grant))
+    }
+}
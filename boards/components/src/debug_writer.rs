@@ -18,6 +18,17 @@
 //! )
 //! .finalize(());
 //! ```
+//!
+//! The static macros take either the total size of the debug buffers in KiB,
+//! or the sizes of the output buffer and of the ring buffer in bytes. The
+//! output buffer is the most passed to the UART at a time, so boards whose
+//! UART can send large buffers in one transfer can drain a backlog with fewer
+//! transmissions, while the ring buffer holds output waiting to be sent:
+//!
+//! ```rust
+//! DebugWriterComponent::new(uart_mux)
+//!     .finalize(components::debug_writer_component_static!(512, 3584));
+//! ```
 
 // Author: Brad Campbell <bradjc@virginia.edu>
 // Last modified: 11/07/2019
@@ -37,41 +48,57 @@ use kernel::hil::uart;
 // can choose to pass in their own buffers with different lengths.
 pub const DEFAULT_DEBUG_BUFFER_KBYTE: usize = 2;
 
-// Bytes [0, DEBUG_BUFFER_SPLIT) are used for output_buf while bytes
-// [DEBUG_BUFFER_SPLIT, DEFAULT_DEBUG_BUFFER_KBYTE * 1024) are used for internal_buf.
-const DEBUG_BUFFER_SPLIT: usize = 64;
+// Size of the output buffer when boards give the total size in KiB. The rest
+// of the total is used for the ring buffer.
+pub const DEFAULT_DEBUG_OUTPUT_BUF_LEN: usize = 64;
 
-/// The optional argument to this macro allows boards to specify the size of the in-RAM
-/// buffer used for storing debug messages. Increase this value to be able to send more debug
-/// messages in quick succession.
+/// The optional arguments to this macro allow boards to specify the size of the in-RAM
+/// buffers used for storing debug messages, either as one total in KiB or as the lengths of
+/// the output buffer and the ring buffer. Increase the ring buffer to be able to send more
+/// debug messages in quick succession.
 #[macro_export]
 macro_rules! debug_writer_component_static {
-    ($BUF_SIZE_KB:expr) => {{
+    ($OUTPUT_BUF_LEN:expr, $RING_BUF_LEN:expr $(,)?) => {{
         let uart = kernel::static_buf!(capsules_core::virtualizers::virtual_uart::UartDevice);
         let ring = kernel::static_buf!(kernel::collections::ring_buffer::RingBuffer<'static, u8>);
-        let buffer = kernel::static_buf!([u8; 1024 * $BUF_SIZE_KB]);
+        let output_buffer = kernel::static_buf!([u8; $OUTPUT_BUF_LEN]);
+        let ring_buffer = kernel::static_buf!([u8; $RING_BUF_LEN]);
         let debug = kernel::static_buf!(kernel::debug::DebugWriter);
         let debug_wrapper = kernel::static_buf!(kernel::debug::DebugWriterWrapper);
 
-        (uart, ring, buffer, debug, debug_wrapper)
+        (uart, ring, output_buffer, ring_buffer, debug, debug_wrapper)
+    };};
+    ($BUF_SIZE_KB:expr) => {{
+        $crate::debug_writer_component_static!(
+            $crate::debug_writer::DEFAULT_DEBUG_OUTPUT_BUF_LEN,
+            1024 * $BUF_SIZE_KB - $crate::debug_writer::DEFAULT_DEBUG_OUTPUT_BUF_LEN
+        )
     };};
     () => {{
         $crate::debug_writer_component_static!($crate::debug_writer::DEFAULT_DEBUG_BUFFER_KBYTE)
     };};
 }
 
-/// The optional argument to this macro allows boards to specify the size of the in-RAM
-/// buffer used for storing debug messages. Increase this value to be able to send more debug
-/// messages in quick succession.
+/// The optional arguments to this macro allow boards to specify the size of the in-RAM
+/// buffers used for storing debug messages, either as one total in KiB or as the lengths of
+/// the output buffer and the ring buffer. Increase the ring buffer to be able to send more
+/// debug messages in quick succession.
 #[macro_export]
 macro_rules! debug_writer_no_mux_component_static {
-    ($BUF_SIZE_KB:expr) => {{
+    ($OUTPUT_BUF_LEN:expr, $RING_BUF_LEN:expr $(,)?) => {{
         let ring = kernel::static_buf!(kernel::collections::ring_buffer::RingBuffer<'static, u8>);
-        let buffer = kernel::static_buf!([u8; 1024 * $BUF_SIZE_KB]);
+        let output_buffer = kernel::static_buf!([u8; $OUTPUT_BUF_LEN]);
+        let ring_buffer = kernel::static_buf!([u8; $RING_BUF_LEN]);
         let debug = kernel::static_buf!(kernel::debug::DebugWriter);
         let debug_wrapper = kernel::static_buf!(kernel::debug::DebugWriterWrapper);
 
-        (ring, buffer, debug, debug_wrapper)
+        (ring, output_buffer, ring_buffer, debug, debug_wrapper)
+    };};
+    ($BUF_SIZE_KB:expr) => {{
+        $crate::debug_writer_no_mux_component_static!(
+            $crate::debug_writer::DEFAULT_DEBUG_OUTPUT_BUF_LEN,
+            1024 * $BUF_SIZE_KB - $crate::debug_writer::DEFAULT_DEBUG_OUTPUT_BUF_LEN
+        )
     };};
     () => {{
         use $crate::debug_writer::DEFAULT_DEBUG_BUFFER_KBYTE;
@@ -79,50 +106,51 @@ macro_rules! debug_writer_no_mux_component_static {
     };};
 }
 
-pub struct DebugWriterComponent<const BUF_SIZE_BYTES: usize> {
+pub struct DebugWriterComponent<const OUTPUT_BUF_LEN: usize, const RING_BUF_LEN: usize> {
     uart_mux: &'static MuxUart<'static>,
-    marker: core::marker::PhantomData<[u8; BUF_SIZE_BYTES]>,
 }
 
-impl<const BUF_SIZE_BYTES: usize> DebugWriterComponent<BUF_SIZE_BYTES> {
+impl<const OUTPUT_BUF_LEN: usize, const RING_BUF_LEN: usize>
+    DebugWriterComponent<OUTPUT_BUF_LEN, RING_BUF_LEN>
+{
     pub fn new(uart_mux: &'static MuxUart) -> Self {
-        Self {
-            uart_mux,
-            marker: core::marker::PhantomData,
-        }
+        Self { uart_mux }
     }
 }
 
 pub struct Capability;
 unsafe impl capabilities::ProcessManagementCapability for Capability {}
 
-impl<const BUF_SIZE_BYTES: usize> Component for DebugWriterComponent<BUF_SIZE_BYTES> {
+impl<const OUTPUT_BUF_LEN: usize, const RING_BUF_LEN: usize> Component
+    for DebugWriterComponent<OUTPUT_BUF_LEN, RING_BUF_LEN>
+{
     type StaticInput = (
         &'static mut MaybeUninit<UartDevice<'static>>,
         &'static mut MaybeUninit<RingBuffer<'static, u8>>,
-        &'static mut MaybeUninit<[u8; BUF_SIZE_BYTES]>,
+        &'static mut MaybeUninit<[u8; OUTPUT_BUF_LEN]>,
+        &'static mut MaybeUninit<[u8; RING_BUF_LEN]>,
         &'static mut MaybeUninit<kernel::debug::DebugWriter>,
         &'static mut MaybeUninit<kernel::debug::DebugWriterWrapper>,
     );
     type Output = ();
 
     fn finalize(self, s: Self::StaticInput) -> Self::Output {
-        let buf = s.2.write([0; BUF_SIZE_BYTES]);
-
-        let (output_buf, internal_buf) = buf.split_at_mut(DEBUG_BUFFER_SPLIT);
+        let output_buf = s.2.write([0; OUTPUT_BUF_LEN]);
+        let internal_buf = s.3.write([0; RING_BUF_LEN]);
 
         // Create virtual device for kernel debug.
         let debugger_uart = s.0.write(UartDevice::new(self.uart_mux, false));
         debugger_uart.setup();
+        debugger_uart.set_label("debug");
         let ring_buffer = s.1.write(RingBuffer::new(internal_buf));
-        let debugger = s.3.write(kernel::debug::DebugWriter::new(
+        let debugger = s.4.write(kernel::debug::DebugWriter::new(
             debugger_uart,
             output_buf,
             ring_buffer,
         ));
         hil::uart::Transmit::set_transmit_client(debugger_uart, debugger);
 
-        let debug_wrapper = s.4.write(kernel::debug::DebugWriterWrapper::new(debugger));
+        let debug_wrapper = s.5.write(kernel::debug::DebugWriterWrapper::new(debugger));
         unsafe {
             kernel::debug::set_debug_writer_wrapper(debug_wrapper);
         }
@@ -131,48 +159,52 @@ impl<const BUF_SIZE_BYTES: usize> Component for DebugWriterComponent<BUF_SIZE_BY
 
 pub struct DebugWriterNoMuxComponent<
     U: uart::Uart<'static> + uart::Transmit<'static> + 'static,
-    const BUF_SIZE_BYTES: usize,
+    const OUTPUT_BUF_LEN: usize,
+    const RING_BUF_LEN: usize,
 > {
     uart: &'static U,
-    marker: core::marker::PhantomData<[u8; BUF_SIZE_BYTES]>,
 }
 
-impl<U: uart::Uart<'static> + uart::Transmit<'static> + 'static, const BUF_SIZE_BYTES: usize>
-    DebugWriterNoMuxComponent<U, BUF_SIZE_BYTES>
+impl<
+        U: uart::Uart<'static> + uart::Transmit<'static> + 'static,
+        const OUTPUT_BUF_LEN: usize,
+        const RING_BUF_LEN: usize,
+    > DebugWriterNoMuxComponent<U, OUTPUT_BUF_LEN, RING_BUF_LEN>
 {
     pub fn new(uart: &'static U) -> Self {
-        Self {
-            uart,
-            marker: core::marker::PhantomData,
-        }
+        Self { uart }
     }
 }
 
-impl<U: uart::Uart<'static> + uart::Transmit<'static> + 'static, const BUF_SIZE_BYTES: usize>
-    Component for DebugWriterNoMuxComponent<U, BUF_SIZE_BYTES>
+impl<
+        U: uart::Uart<'static> + uart::Transmit<'static> + 'static,
+        const OUTPUT_BUF_LEN: usize,
+        const RING_BUF_LEN: usize,
+    > Component for DebugWriterNoMuxComponent<U, OUTPUT_BUF_LEN, RING_BUF_LEN>
 {
     type StaticInput = (
         &'static mut MaybeUninit<RingBuffer<'static, u8>>,
-        &'static mut MaybeUninit<[u8; BUF_SIZE_BYTES]>,
+        &'static mut MaybeUninit<[u8; OUTPUT_BUF_LEN]>,
+        &'static mut MaybeUninit<[u8; RING_BUF_LEN]>,
         &'static mut MaybeUninit<kernel::debug::DebugWriter>,
         &'static mut MaybeUninit<kernel::debug::DebugWriterWrapper>,
     );
     type Output = ();
 
     fn finalize(self, s: Self::StaticInput) -> Self::Output {
-        let buf = s.1.write([0; BUF_SIZE_BYTES]);
-        let (output_buf, internal_buf) = buf.split_at_mut(DEBUG_BUFFER_SPLIT);
+        let output_buf = s.1.write([0; OUTPUT_BUF_LEN]);
+        let internal_buf = s.2.write([0; RING_BUF_LEN]);
 
         // Create virtual device for kernel debug.
         let ring_buffer = s.0.write(RingBuffer::new(internal_buf));
-        let debugger = s.2.write(kernel::debug::DebugWriter::new(
+        let debugger = s.3.write(kernel::debug::DebugWriter::new(
             self.uart,
             output_buf,
             ring_buffer,
         ));
         hil::uart::Transmit::set_transmit_client(self.uart, debugger);
 
-        let debug_wrapper = s.3.write(kernel::debug::DebugWriterWrapper::new(debugger));
+        let debug_wrapper = s.4.write(kernel::debug::DebugWriterWrapper::new(debugger));
         unsafe {
             kernel::debug::set_debug_writer_wrapper(debug_wrapper);
         }
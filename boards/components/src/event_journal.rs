@@ -0,0 +1,113 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2024.
+
+//! Component for the persistent kernel event journal.
+//!
+//! The log passed to the component should be a circular log in a flash volume
+//! of its own, and must not be shared with another client, as the component
+//! sets itself as its read and append client. The last argument to the static
+//! macro is how many events can wait in RAM to be written.
+//!
+//! Usage
+//! -----
+//! ```rust
+//! let journal = components::event_journal::EventJournalComponent::new(
+//!     board_kernel,
+//!     capsules_extra::event_journal::DRIVER_NUM,
+//!     journal_log,
+//!     &base_peripherals.rtc,
+//! )
+//! .finalize(components::event_journal_component_static!(
+//!     capsules_extra::log::Log<'static, nrf52840::nvmc::Nvmc>,
+//!     nrf52::rtc::Rtc,
+//!     8,
+//! ));
+//! ```
+
+use capsules_extra::event_journal::{Event, EventJournal, RECORD_LEN};
+use core::mem::MaybeUninit;
+use kernel::capabilities;
+use kernel::collections::ring_buffer::RingBuffer;
+use kernel::component::Component;
+use kernel::create_capability;
+use kernel::hil::log::{LogRead, LogWrite};
+use kernel::hil::time::Time;
+
+#[macro_export]
+macro_rules! event_journal_component_static {
+    ($L:ty, $T:ty, $QUEUE_LEN:expr $(,)?) => {{
+        let journal =
+            kernel::static_buf!(capsules_extra::event_journal::EventJournal<'static, $L, $T>);
+        let queue = kernel::static_buf!([capsules_extra::event_journal::Event; $QUEUE_LEN]);
+        let write_buffer = kernel::static_buf!([u8; capsules_extra::event_journal::RECORD_LEN]);
+        let read_buffer = kernel::static_buf!([u8; capsules_extra::event_journal::RECORD_LEN]);
+
+        (journal, queue, write_buffer, read_buffer)
+    };};
+}
+
+pub type EventJournalComponentType<L, T> = EventJournal<'static, L, T>;
+
+pub struct EventJournalComponent<
+    L: LogRead<'static, EntryID = usize> + LogWrite<'static> + 'static,
+    T: Time + 'static,
+    const QUEUE_LEN: usize,
+> {
+    board_kernel: &'static kernel::Kernel,
+    driver_num: usize,
+    log: &'static L,
+    time: &'static T,
+}
+
+impl<L: LogRead<'static, EntryID = usize> + LogWrite<'static>, T: Time, const QUEUE_LEN: usize>
+    EventJournalComponent<L, T, QUEUE_LEN>
+{
+    pub fn new(
+        board_kernel: &'static kernel::Kernel,
+        driver_num: usize,
+        log: &'static L,
+        time: &'static T,
+    ) -> Self {
+        Self {
+            board_kernel,
+            driver_num,
+            log,
+            time,
+        }
+    }
+}
+
+impl<L: LogRead<'static, EntryID = usize> + LogWrite<'static>, T: Time, const QUEUE_LEN: usize>
+    Component for EventJournalComponent<L, T, QUEUE_LEN>
+{
+    type StaticInput = (
+        &'static mut MaybeUninit<EventJournal<'static, L, T>>,
+        &'static mut MaybeUninit<[Event; QUEUE_LEN]>,
+        &'static mut MaybeUninit<[u8; RECORD_LEN]>,
+        &'static mut MaybeUninit<[u8; RECORD_LEN]>,
+    );
+    type Output = &'static EventJournal<'static, L, T>;
+
+    fn finalize(self, s: Self::StaticInput) -> Self::Output {
+        let grant_cap = create_capability!(capabilities::MemoryAllocationCapability);
+
+        let queue = s.1.write([Event::default(); QUEUE_LEN]);
+        let write_buffer = s.2.write([0; RECORD_LEN]);
+        let read_buffer = s.3.write([0; RECORD_LEN]);
+
+        let journal = s.0.write(EventJournal::new(
+            self.log,
+            self.time,
+            self.board_kernel.create_grant(self.driver_num, &grant_cap),
+            RingBuffer::new(queue),
+            write_buffer,
+            read_buffer,
+        ));
+
+        self.log.set_read_client(journal);
+        self.log.set_append_client(journal);
+
+        journal
+    }
+}
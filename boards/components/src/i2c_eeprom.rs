@@ -0,0 +1,93 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2024.
+
+//! Component for 24xx-style I2C EEPROMs.
+//!
+//! The last argument to the static macro is the length of the I2C buffer. It
+//! should be the EEPROM's page size plus two, for the memory address, so a
+//! whole page can be written at once.
+//!
+//! Usage
+//! -----
+//! ```rust
+//! // A 24LC256: 32 KiB with 64 byte pages.
+//! let eeprom = components::i2c_eeprom::I2CEepromComponent::new(mux_i2c, 0x50, 64, 0x8000)
+//!     .finalize(components::i2c_eeprom_component_static!(nrf52840::i2c::TWI, 66));
+//! ```
+
+use capsules_core::virtualizers::virtual_i2c::{I2CDevice, MuxI2C};
+use capsules_extra::i2c_eeprom::I2CEeprom;
+use core::mem::MaybeUninit;
+use kernel::component::Component;
+use kernel::hil::i2c;
+
+// Setup static space for the objects.
+#[macro_export]
+macro_rules! i2c_eeprom_component_static {
+    ($I:ty, $BUF_LEN:expr $(,)?) => {{
+        let i2c_device =
+            kernel::static_buf!(capsules_core::virtualizers::virtual_i2c::I2CDevice<$I>);
+        let buffer = kernel::static_buf!([u8; $BUF_LEN]);
+        let eeprom = kernel::static_buf!(
+            capsules_extra::i2c_eeprom::I2CEeprom<
+                'static,
+                capsules_core::virtualizers::virtual_i2c::I2CDevice<$I>,
+            >
+        );
+
+        (i2c_device, buffer, eeprom)
+    };};
+}
+
+pub type I2CEepromComponentType<I> = I2CEeprom<'static, I2CDevice<'static, I>>;
+
+pub struct I2CEepromComponent<I: 'static + i2c::I2CMaster<'static>, const BUF_LEN: usize> {
+    i2c_mux: &'static MuxI2C<'static, I>,
+    i2c_address: u8,
+    page_size: usize,
+    total_size: usize,
+}
+
+impl<I: 'static + i2c::I2CMaster<'static>, const BUF_LEN: usize> I2CEepromComponent<I, BUF_LEN> {
+    pub fn new(
+        i2c_mux: &'static MuxI2C<'static, I>,
+        i2c_address: u8,
+        page_size: usize,
+        total_size: usize,
+    ) -> Self {
+        Self {
+            i2c_mux,
+            i2c_address,
+            page_size,
+            total_size,
+        }
+    }
+}
+
+impl<I: 'static + i2c::I2CMaster<'static>, const BUF_LEN: usize> Component
+    for I2CEepromComponent<I, BUF_LEN>
+{
+    type StaticInput = (
+        &'static mut MaybeUninit<I2CDevice<'static, I>>,
+        &'static mut MaybeUninit<[u8; BUF_LEN]>,
+        &'static mut MaybeUninit<I2CEeprom<'static, I2CDevice<'static, I>>>,
+    );
+    type Output = &'static I2CEeprom<'static, I2CDevice<'static, I>>;
+
+    fn finalize(self, static_buffer: Self::StaticInput) -> Self::Output {
+        let eeprom_i2c = static_buffer
+            .0
+            .write(I2CDevice::new(self.i2c_mux, self.i2c_address));
+        let buffer = static_buffer.1.write([0; BUF_LEN]);
+        let eeprom = static_buffer.2.write(I2CEeprom::new(
+            eeprom_i2c,
+            buffer,
+            self.page_size,
+            self.total_size,
+        ));
+
+        eeprom_i2c.set_client(eeprom);
+        eeprom
+    }
+}
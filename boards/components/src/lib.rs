@@ -25,13 +25,16 @@ pub mod ccs811;
 pub mod cdc;
 pub mod chirp_i2c_moisture;
 pub mod console;
+pub mod crash_dump;
 pub mod crc;
 pub mod ctap;
 pub mod dac;
 pub mod date_time;
 pub mod debug_queue;
+pub mod debug_snapshot;
 pub mod debug_writer;
 pub mod eui64;
+pub mod event_journal;
 pub mod flash;
 pub mod fm25cl;
 pub mod ft6x06;
@@ -43,6 +46,7 @@ pub mod hs3003;
 pub mod hts221;
 pub mod humidity;
 pub mod i2c;
+pub mod i2c_eeprom;
 pub mod ieee802154;
 pub mod isl29035;
 pub mod keyboard_hid;
@@ -65,12 +69,20 @@ pub mod mx25r6435f;
 pub mod ninedof;
 pub mod nonvolatile_storage;
 pub mod nrf51822;
+pub mod ota_staging;
 pub mod panic_button;
+pub mod persistent_seed;
+pub mod power_fail_record;
 pub mod pressure;
 pub mod process_console;
+pub mod process_console_backup;
+pub mod process_console_storage;
+pub mod process_console_upcall;
+pub mod process_console_watch;
 pub mod process_printer;
 pub mod proximity;
 pub mod pwm;
+pub mod reset_reason;
 pub mod rf233;
 pub mod rng;
 pub mod sched;
@@ -79,15 +91,28 @@ pub mod segger_rtt;
 pub mod servo;
 pub mod sh1106;
 pub mod sha;
+pub mod shared_blobs;
 pub mod sht3x;
 pub mod sht4x;
 pub mod si7021;
 pub mod siphash;
 pub mod sound_pressure;
 pub mod spi;
+pub mod spi_nor;
 pub mod ssd1306;
 pub mod st77xx;
+pub mod storage_audit;
+pub mod storage_backup;
+pub mod storage_batch_writes;
+pub mod storage_low_space;
+pub mod storage_mapped_reads;
+pub mod storage_pages;
+pub mod storage_partition;
 pub mod storage_permissions;
+pub mod storage_provisioning_lock;
+pub mod storage_read_cache;
+pub mod storage_verify;
+pub mod storage_wear;
 pub mod temperature;
 pub mod temperature_rp2040;
 pub mod temperature_stm;
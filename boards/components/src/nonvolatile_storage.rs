@@ -4,74 +4,298 @@
 
 //! Component for non-volatile storage Drivers.
 //!
-//! This provides one component, NonvolatileStorageComponent, which provides
-//! a system call interface to non-volatile storage.
+//! This provides NonvolatileToPagesComponent, which turns a flash into
+//! nonvolatile storage, and NonvolatileStorageComponent, which provides a
+//! system call interface to non-volatile storage.
 //!
 //! Usage
 //! -----
 //! ```rust
+//! let nv_to_page = components::nonvolatile_storage::NonvolatileToPagesComponent::new(
+//!     &sam4l::flashcalw::FLASH_CONTROLLER,
+//! )
+//! .finalize(components::nonvolatile_to_pages_component_static!(
+//!     sam4l::flashcalw::FLASHCALW
+//! ));
+//!
 //! let nonvolatile_storage = components::nonvolatile_storage::NonvolatileStorageComponent::new(
 //!     board_kernel,
-//!     &sam4l::flashcalw::FLASH_CONTROLLER,
+//!     capsules_extra::nonvolatile_storage_driver::DRIVER_NUM,
+//!     nv_to_page,
 //!     0x60000,
 //!     0x20000,
 //!     core::ptr::addr_of!(_sstorage) as usize,
 //!     core::ptr::addr_of!(_estorage) as usize,
 //! )
-//! .finalize(components::nonvolatile_storage_component_static!(
-//!     sam4l::flashcalw::FLASHCALW
-//! ));
+//! .finalize(components::nonvolatile_storage_component_static!());
+//! ```
+//!
+//! Capsules that add to the driver, like `StorageVerify` or `StorageWear`,
+//! are built on `nv_to_page` with their own components, and the topmost one
+//! is given to the driver instead of `nv_to_page`. The provisioning lock and
+//! the stored wear counters are handed the driver once it is built, see
+//! `storage_provisioning_lock` and `storage_wear`.
+//!
+//! The kernel and userspace regions must not overlap, and `finalize()` panics
+//! if they do. Boards that intend the kernel to access userspace data must say
+//! so explicitly:
+//!
+//! ```rust
+//! let overlap_cap = create_capability!(capabilities::StorageRegionOverlapCapability);
+//! let nonvolatile_storage = components::nonvolatile_storage::NonvolatileStorageComponent::new(
+//!     // ...
+//! )
+//! .allow_kernel_userspace_writes(&overlap_cap)
+//! .finalize(/* ... */);
+//! ```
+//!
+//! `finalize()` also panics, naming the offending region and values, if a
+//! region does not fit in the storage or cannot be written with its write
+//! granularity. On internal flash, where storage addresses are flash
+//! addresses, boards can have the regions checked against the flash holding
+//! the kernel too:
+//!
+//! ```rust
+//! let nonvolatile_storage = components::nonvolatile_storage::NonvolatileStorageComponent::new(
+//!     // ...
+//! )
+//! .with_kernel_flash(
+//!     core::ptr::addr_of!(_stext) as usize,
+//!     core::ptr::addr_of!(_etext) as usize - core::ptr::addr_of!(_stext) as usize,
+//! )
+//! .finalize(/* ... */);
+//! ```
+//!
+//! To share the userspace region only with apps that were granted access to a
+//! storage identifier, for example in their TBF header when the board loads
+//! processes with `TbfHeaderStoragePermissions`:
+//!
+//! ```rust
+//! let nonvolatile_storage = components::nonvolatile_storage::NonvolatileStorageComponent::new(
+//!     // ...
+//! )
+//! .with_userspace_storage_id(0x1234)
+//! .finalize(/* ... */);
+//! ```
+//!
+//! On flash that can lock regions of itself, keep the provisioned region
+//! locked except while the driver writes it:
+//!
+//! ```rust
+//! let nonvolatile_storage = components::nonvolatile_storage::NonvolatileStorageComponent::new(
+//!     // ...
+//! )
+//! .with_provisioned_region(0x7f000, 0x1000)
+//! .with_write_protection(&sam4l::flashcalw::FLASHCALW)
+//! .finalize(/* ... */);
+//! ```
+//!
+//! To print the regions and the free space around them at boot, for example
+//! while bringing up a board:
+//!
+//! ```rust
+//! let nonvolatile_storage = components::nonvolatile_storage::NonvolatileStorageComponent::new(
+//!     // ...
+//! )
+//! .with_storage_map_report()
+//! .finalize(/* ... */);
+//! ```
+//!
+//! To fail storage operations that do not finish within a second, give the
+//! driver an alarm:
+//!
+//! ```rust
+//! let storage_alarm = static_init!(
+//!     VirtualMuxAlarm<'static, nrf52840::rtc::Rtc>,
+//!     VirtualMuxAlarm::new(mux_alarm)
+//! );
+//! storage_alarm.setup();
+//! nonvolatile_storage.set_operation_timeout(storage_alarm, 1000);
+//! hil::time::Alarm::set_alarm_client(storage_alarm, nonvolatile_storage);
+//! ```
+//!
+//! To borrow buffers for app reads and writes from a pool shared with other
+//! storage capsules, give the component the pool. The driver's own buffer is
+//! then only a fallback for when every pooled buffer is lent out, and can be
+//! made smaller:
+//!
+//! ```rust
+//! let nonvolatile_storage = components::nonvolatile_storage::NonvolatileStorageComponent::new(
+//!     // ...
+//! )
+//! .with_buffer_pool(storage_buffer_pool)
+//! .finalize(components::nonvolatile_storage_component_static!(64));
+//! ```
+//!
+//! To share the kernel interface between several kernel clients, each limited
+//! to its own window of storage:
+//!
+//! ```rust
+//! let mux_storage = components::nonvolatile_storage::NonvolatileStorageMuxComponent::new(
+//!     nonvolatile_storage,
+//! )
+//! .finalize(components::nonvolatile_storage_mux_component_static!());
+//!
+//! let crash_dump_storage = components::nonvolatile_storage::NonvolatileStorageUserComponent::new(
+//!     mux_storage,
+//!     0x60000,
+//!     0x1000,
+//! )
+//! .finalize(components::nonvolatile_storage_user_component_static!());
+//! ```
+//!
+//! A client that saves data while the system is failing, like a crash dump,
+//! can have its operations run before those of the other clients:
+//!
+//! ```rust
+//! let priority_cap = create_capability!(capabilities::StoragePriorityCapability);
+//! let crash_dump_storage = components::nonvolatile_storage::NonvolatileStorageUserComponent::new(
+//!     mux_storage,
+//!     0x60000,
+//!     0x1000,
+//! )
+//! .with_priority(&priority_cap)
+//! .finalize(components::nonvolatile_storage_user_component_static!());
 //! ```
 
+use capsules_core::virtualizers::virtual_nonvolatile_storage::{
+    MuxNonvolatileStorage, NonvolatileStorageUser,
+};
 use capsules_extra::nonvolatile_storage_driver::NonvolatileStorage;
 use capsules_extra::nonvolatile_to_pages::NonvolatileToPages;
+use core::cmp;
 use core::mem::MaybeUninit;
 use kernel::capabilities;
 use kernel::component::Component;
 use kernel::create_capability;
+use kernel::debug;
 use kernel::hil;
+use kernel::utilities::buffer_pool::BufferPool;
 
 // Setup static space for the objects.
 #[macro_export]
-macro_rules! nonvolatile_storage_component_static {
+macro_rules! nonvolatile_to_pages_component_static {
     ($F:ty $(,)?) => {{
         let page = kernel::static_buf!(<$F as kernel::hil::flash::Flash>::Page);
         let ntp = kernel::static_buf!(
             capsules_extra::nonvolatile_to_pages::NonvolatileToPages<'static, $F>
         );
+
+        (page, ntp)
+    };};
+}
+
+#[macro_export]
+macro_rules! nonvolatile_storage_component_static {
+    () => {{
+        $crate::nonvolatile_storage_component_static!(
+            capsules_extra::nonvolatile_storage_driver::BUF_LEN
+        )
+    };};
+    ($BUF_LEN:expr $(,)?) => {{
         let ns = kernel::static_buf!(
             capsules_extra::nonvolatile_storage_driver::NonvolatileStorage<'static>
         );
-        let buffer = kernel::static_buf!([u8; capsules_extra::nonvolatile_storage_driver::BUF_LEN]);
+        let buffer = kernel::static_buf!([u8; $BUF_LEN]);
+
+        (ns, buffer)
+    };};
+}
+
+#[macro_export]
+macro_rules! nonvolatile_storage_mux_component_static {
+    () => {{
+        kernel::static_buf!(
+            capsules_core::virtualizers::virtual_nonvolatile_storage::MuxNonvolatileStorage<
+                'static,
+            >
+        )
+    };};
+}
 
-        (page, ntp, ns, buffer)
+#[macro_export]
+macro_rules! nonvolatile_storage_user_component_static {
+    () => {{
+        kernel::static_buf!(
+            capsules_core::virtualizers::virtual_nonvolatile_storage::NonvolatileStorageUser<
+                'static,
+            >
+        )
     };};
 }
 
+pub struct NonvolatileToPagesComponent<
+    F: 'static + hil::flash::Flash + hil::flash::HasClient<'static, NonvolatileToPages<'static, F>>,
+> {
+    flash: &'static F,
+}
+
+impl<
+        F: 'static
+            + hil::flash::Flash
+            + hil::flash::HasClient<'static, NonvolatileToPages<'static, F>>,
+    > NonvolatileToPagesComponent<F>
+{
+    pub fn new(flash: &'static F) -> Self {
+        Self { flash }
+    }
+}
+
+impl<
+        F: 'static
+            + hil::flash::Flash
+            + hil::flash::HasClient<'static, NonvolatileToPages<'static, F>>,
+    > Component for NonvolatileToPagesComponent<F>
+{
+    type StaticInput = (
+        &'static mut MaybeUninit<<F as hil::flash::Flash>::Page>,
+        &'static mut MaybeUninit<NonvolatileToPages<'static, F>>,
+    );
+    type Output = &'static NonvolatileToPages<'static, F>;
+
+    fn finalize(self, static_buffer: Self::StaticInput) -> Self::Output {
+        let flash_pagebuffer = static_buffer
+            .0
+            .write(<F as hil::flash::Flash>::Page::default());
+
+        let nv_to_page = static_buffer
+            .1
+            .write(NonvolatileToPages::new(self.flash, flash_pagebuffer));
+        hil::flash::HasClient::set_client(self.flash, nv_to_page);
+
+        nv_to_page
+    }
+}
+
 pub type NonvolatileStorageComponentType = NonvolatileStorage<'static>;
 
 pub struct NonvolatileStorageComponent<
-    F: 'static + hil::flash::Flash + hil::flash::HasClient<'static, NonvolatileToPages<'static, F>>,
+    S: 'static + hil::nonvolatile_storage::NonvolatileStorage<'static>,
+    const BUF_LEN: usize,
 > {
     board_kernel: &'static kernel::Kernel,
     driver_num: usize,
-    flash: &'static F,
+    storage: &'static S,
     userspace_start: usize,
     userspace_length: usize,
     kernel_start: usize,
     kernel_length: usize,
+    kernel_userspace_writes: bool,
+    userspace_storage_id: Option<u32>,
+    provisioned_region: Option<(usize, usize)>,
+    buffer_pool: Option<&'static BufferPool>,
+    write_protection: Option<&'static dyn hil::flash::WriteProtection>,
+    kernel_flash: Option<(usize, usize)>,
+    storage_map_report: bool,
 }
 
-impl<
-        F: 'static
-            + hil::flash::Flash
-            + hil::flash::HasClient<'static, NonvolatileToPages<'static, F>>,
-    > NonvolatileStorageComponent<F>
+impl<S: 'static + hil::nonvolatile_storage::NonvolatileStorage<'static>, const BUF_LEN: usize>
+    NonvolatileStorageComponent<S, BUF_LEN>
 {
     pub fn new(
         board_kernel: &'static kernel::Kernel,
         driver_num: usize,
-        flash: &'static F,
+        storage: &'static S,
         userspace_start: usize,
         userspace_length: usize,
         kernel_start: usize,
@@ -80,47 +304,232 @@ impl<
         Self {
             board_kernel,
             driver_num,
-            flash,
+            storage,
             userspace_start,
             userspace_length,
             kernel_start,
             kernel_length,
+            kernel_userspace_writes: false,
+            userspace_storage_id: None,
+            provisioned_region: None,
+            buffer_pool: None,
+            write_protection: None,
+            kernel_flash: None,
+            storage_map_report: false,
+        }
+    }
+
+    /// Allow the kernel region to overlap the userspace region, and let kernel
+    /// clients write to the overlapping part.
+    pub fn allow_kernel_userspace_writes(
+        self,
+        _capability: &dyn capabilities::StorageRegionOverlapCapability,
+    ) -> Self {
+        Self {
+            kernel_userspace_writes: true,
+            ..self
+        }
+    }
+
+    /// Require apps to have storage permissions for `storage_id` to access the
+    /// userspace region.
+    pub fn with_userspace_storage_id(self, storage_id: u32) -> Self {
+        Self {
+            userspace_storage_id: Some(storage_id),
+            ..self
+        }
+    }
+
+    /// Let apps read, but not write, `length` bytes starting at `start`, for
+    /// example factory calibration data. The kernel region must cover it for
+    /// the kernel to write it.
+    pub fn with_provisioned_region(self, start: usize, length: usize) -> Self {
+        Self {
+            provisioned_region: Some((start, length)),
+            ..self
+        }
+    }
+
+    /// Borrow buffers for app reads and writes from `pool`, and only use the
+    /// `BUF_LEN` byte buffer of the component when none of the free ones is
+    /// large enough.
+    pub fn with_buffer_pool(self, pool: &'static BufferPool) -> Self {
+        Self {
+            buffer_pool: Some(pool),
+            ..self
+        }
+    }
+
+    /// Lock the provisioned region in hardware with `protection`, which must
+    /// be the flash beneath the storage given to `new()`.
+    pub fn with_write_protection(
+        self,
+        protection: &'static dyn hil::flash::WriteProtection,
+    ) -> Self {
+        Self {
+            write_protection: Some(protection),
+            ..self
+        }
+    }
+
+    /// Panic in `finalize()` if a storage region overlaps the `length` bytes
+    /// of flash starting at `start` that hold the kernel. Only meaningful if
+    /// storage addresses are flash addresses.
+    pub fn with_kernel_flash(self, start: usize, length: usize) -> Self {
+        Self {
+            kernel_flash: Some((start, length)),
+            ..self
+        }
+    }
+
+    /// Print the storage regions and the free space left around them with
+    /// `debug!` in `finalize()`, to spot misconfigured regions at boot.
+    pub fn with_storage_map_report(self) -> Self {
+        Self {
+            storage_map_report: true,
+            ..self
+        }
+    }
+
+    // Print every region, and the space no region covers if the size of the
+    // storage is known.
+    fn report_regions(&self, geometry: Option<hil::nonvolatile_storage::StorageGeometry>) {
+        let (provisioned_start, provisioned_length) = self.provisioned_region.unwrap_or((0, 0));
+        let mut regions = [
+            ("userspace", self.userspace_start, self.userspace_length),
+            ("provisioned", provisioned_start, provisioned_length),
+            ("kernel", self.kernel_start, self.kernel_length),
+        ];
+
+        debug!("Nonvolatile storage map:");
+        for (name, start, length) in regions {
+            if length == 0 {
+                debug!("  {:<12} none", name);
+            } else {
+                debug!(
+                    "  {:<12} {:#010x}..{:#010x} ({} bytes)",
+                    name,
+                    start,
+                    start.saturating_add(length),
+                    length,
+                );
+            }
+        }
+        match self.userspace_storage_id {
+            Some(storage_id) => debug!(
+                "  apps         share the userspace region, storage id {:#x}",
+                storage_id
+            ),
+            None => debug!("  apps         share the userspace region"),
+        }
+
+        let geometry = match geometry {
+            Some(geometry) => geometry,
+            None => {
+                debug!("  free         unknown, the storage size is not known");
+                return;
+            }
+        };
+        // Walk the regions by address. The kernel region may cover the others,
+        // so only the space past the furthest end seen so far is free.
+        regions.sort_unstable_by_key(|&(_, start, _)| start);
+        let mut covered = 0;
+        let mut free = 0;
+        for (_, start, length) in regions {
+            if length == 0 {
+                continue;
+            }
+            if start > covered {
+                debug!("  free         {:#010x}..{:#010x}", covered, start);
+                free += start - covered;
+            }
+            covered = cmp::max(covered, start.saturating_add(length));
+        }
+        if geometry.total_size > covered {
+            debug!(
+                "  free         {:#010x}..{:#010x}",
+                covered, geometry.total_size
+            );
+            free += geometry.total_size - covered;
+        }
+        debug!(
+            "  {} of {} bytes not in any region",
+            free, geometry.total_size
+        );
+    }
+
+    // Panic with the offending values if a region wraps around the address
+    // space, overlaps the kernel flash, does not fit in the storage, or cannot
+    // be written with its write granularity.
+    fn validate_regions(&self, geometry: Option<hil::nonvolatile_storage::StorageGeometry>) {
+        let (provisioned_start, provisioned_length) = self.provisioned_region.unwrap_or((0, 0));
+        let regions = [
+            ("userspace", self.userspace_start, self.userspace_length),
+            ("provisioned", provisioned_start, provisioned_length),
+            ("kernel", self.kernel_start, self.kernel_length),
+        ];
+        for (name, start, length) in regions {
+            if length == 0 {
+                continue;
+            }
+            let end = match start.checked_add(length) {
+                Some(end) => end,
+                None => panic!(
+                    "Nonvolatile storage {} region at {:#x} with length {:#x} wraps around \
+                     the address space.",
+                    name, start, length,
+                ),
+            };
+            if let Some((flash_start, flash_length)) = self.kernel_flash {
+                if start < flash_start.saturating_add(flash_length) && flash_start < end {
+                    panic!(
+                        "Nonvolatile storage {} region {:#x}..{:#x} overlaps kernel flash \
+                         {:#x}..{:#x}.",
+                        name,
+                        start,
+                        end,
+                        flash_start,
+                        flash_start.saturating_add(flash_length),
+                    );
+                }
+            }
+            if let Some(geometry) = geometry {
+                if end > geometry.total_size {
+                    panic!(
+                        "Nonvolatile storage {} region {:#x}..{:#x} ends past the end of the \
+                         storage at {:#x}.",
+                        name, start, end, geometry.total_size,
+                    );
+                }
+                let granularity = cmp::max(geometry.write_granularity, 1);
+                if start % granularity != 0 || length % granularity != 0 {
+                    panic!(
+                        "Nonvolatile storage {} region {:#x}..{:#x} is not aligned to the \
+                         storage write granularity of {:#x} bytes.",
+                        name, start, end, granularity,
+                    );
+                }
+            }
         }
     }
 }
 
-impl<
-        F: 'static
-            + hil::flash::Flash
-            + hil::flash::HasClient<'static, NonvolatileToPages<'static, F>>,
-    > Component for NonvolatileStorageComponent<F>
+impl<S: 'static + hil::nonvolatile_storage::NonvolatileStorage<'static>, const BUF_LEN: usize>
+    Component for NonvolatileStorageComponent<S, BUF_LEN>
 {
     type StaticInput = (
-        &'static mut MaybeUninit<<F as hil::flash::Flash>::Page>,
-        &'static mut MaybeUninit<NonvolatileToPages<'static, F>>,
         &'static mut MaybeUninit<NonvolatileStorage<'static>>,
-        &'static mut MaybeUninit<[u8; capsules_extra::nonvolatile_storage_driver::BUF_LEN]>,
+        &'static mut MaybeUninit<[u8; BUF_LEN]>,
     );
     type Output = &'static NonvolatileStorage<'static>;
 
     fn finalize(self, static_buffer: Self::StaticInput) -> Self::Output {
         let grant_cap = create_capability!(capabilities::MemoryAllocationCapability);
 
-        let buffer = static_buffer
-            .3
-            .write([0; capsules_extra::nonvolatile_storage_driver::BUF_LEN]);
-
-        let flash_pagebuffer = static_buffer
-            .0
-            .write(<F as hil::flash::Flash>::Page::default());
-
-        let nv_to_page = static_buffer
-            .1
-            .write(NonvolatileToPages::new(self.flash, flash_pagebuffer));
-        hil::flash::HasClient::set_client(self.flash, nv_to_page);
+        let buffer = static_buffer.1.write([0; BUF_LEN]);
 
-        let nonvolatile_storage = static_buffer.2.write(NonvolatileStorage::new(
-            nv_to_page,
+        let nonvolatile_storage = static_buffer.0.write(NonvolatileStorage::new(
+            self.storage,
             self.board_kernel.create_grant(self.driver_num, &grant_cap),
             self.userspace_start, // Start address for userspace accessible region
             self.userspace_length, // Length of userspace accessible region
@@ -128,7 +537,142 @@ impl<
             self.kernel_length,   // Length of kernel region
             buffer,
         ));
-        hil::nonvolatile_storage::NonvolatileStorage::set_client(nv_to_page, nonvolatile_storage);
+        if self.kernel_userspace_writes {
+            let overlap_cap = create_capability!(capabilities::StorageRegionOverlapCapability);
+            nonvolatile_storage.allow_kernel_userspace_writes(&overlap_cap);
+        } else if nonvolatile_storage.kernel_region_overlaps_userspace() {
+            panic!(
+                "Nonvolatile storage kernel region {:#x}..{:#x} overlaps userspace region \
+                 {:#x}..{:#x}. Separate the regions, or use allow_kernel_userspace_writes() \
+                 if the overlap is intended.",
+                self.kernel_start,
+                self.kernel_start + self.kernel_length,
+                self.userspace_start,
+                self.userspace_start + self.userspace_length,
+            );
+        }
+
+        if let Some((start, length)) = self.provisioned_region {
+            if nonvolatile_storage
+                .set_provisioned_region(start, length)
+                .is_err()
+            {
+                panic!(
+                    "Nonvolatile storage provisioned region {:#x}..{:#x} overlaps userspace \
+                     region {:#x}..{:#x}.",
+                    start,
+                    start + length,
+                    self.userspace_start,
+                    self.userspace_start + self.userspace_length,
+                );
+            }
+        }
+
+        let geometry = self.storage.geometry();
+        self.validate_regions(geometry);
+        if self.storage_map_report {
+            self.report_regions(geometry);
+        }
+
+        if let Some(protection) = self.write_protection {
+            if let Err(e) = nonvolatile_storage.set_write_protection(protection) {
+                panic!(
+                    "Nonvolatile storage provisioned region {:x?} cannot be write protected \
+                     without covering userspace region {:#x}..{:#x}: {:?}",
+                    self.provisioned_region,
+                    self.userspace_start,
+                    self.userspace_start + self.userspace_length,
+                    e,
+                );
+            }
+        }
+
+        if let Some(storage_id) = self.userspace_storage_id {
+            nonvolatile_storage.set_userspace_storage_id(storage_id);
+        }
+
+        if let Some(pool) = self.buffer_pool {
+            nonvolatile_storage.set_buffer_pool(pool);
+        }
+
+        self.storage.set_client(nonvolatile_storage);
+        kernel::deferred_call::DeferredCallClient::register(nonvolatile_storage);
         nonvolatile_storage
     }
 }
+
+pub struct NonvolatileStorageMuxComponent {
+    storage: &'static dyn hil::nonvolatile_storage::NonvolatileStorage<'static>,
+}
+
+impl NonvolatileStorageMuxComponent {
+    pub fn new(
+        storage: &'static dyn hil::nonvolatile_storage::NonvolatileStorage<'static>,
+    ) -> Self {
+        Self { storage }
+    }
+}
+
+impl Component for NonvolatileStorageMuxComponent {
+    type StaticInput = &'static mut MaybeUninit<MuxNonvolatileStorage<'static>>;
+    type Output = &'static MuxNonvolatileStorage<'static>;
+
+    fn finalize(self, s: Self::StaticInput) -> Self::Output {
+        let mux_storage = s.write(MuxNonvolatileStorage::new(self.storage));
+        self.storage.set_client(mux_storage);
+
+        mux_storage
+    }
+}
+
+pub struct NonvolatileStorageUserComponent {
+    mux_storage: &'static MuxNonvolatileStorage<'static>,
+    start: usize,
+    length: usize,
+    priority: bool,
+}
+
+impl NonvolatileStorageUserComponent {
+    /// Create a user that may only access `length` bytes at `start`.
+    pub fn new(
+        mux_storage: &'static MuxNonvolatileStorage<'static>,
+        start: usize,
+        length: usize,
+    ) -> Self {
+        Self {
+            mux_storage,
+            start,
+            length,
+            priority: false,
+        }
+    }
+
+    /// Run the user's operations before those of users without priority, for
+    /// example to save a crash dump.
+    pub fn with_priority(self, _capability: &dyn capabilities::StoragePriorityCapability) -> Self {
+        Self {
+            priority: true,
+            ..self
+        }
+    }
+}
+
+impl Component for NonvolatileStorageUserComponent {
+    type StaticInput = &'static mut MaybeUninit<NonvolatileStorageUser<'static>>;
+    type Output = &'static NonvolatileStorageUser<'static>;
+
+    fn finalize(self, s: Self::StaticInput) -> Self::Output {
+        let user = s.write(NonvolatileStorageUser::new(
+            self.mux_storage,
+            self.start,
+            self.length,
+        ));
+        user.setup();
+        if self.priority {
+            let priority_cap = create_capability!(capabilities::StoragePriorityCapability);
+            user.set_priority(&priority_cap);
+        }
+
+        user
+    }
+}
@@ -0,0 +1,146 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2024.
+
+//! Component for the A/B OTA staging area.
+//!
+//! The storage passed to the component must not be shared with another
+//! client, as the component sets itself as its client.
+//!
+//! Usage
+//! -----
+//! ```rust
+//! let ota = components::ota_staging::OtaStagingComponent::new(
+//!     board_kernel,
+//!     capsules_extra::ota_staging::DRIVER_NUM,
+//!     ota_storage,
+//!     sha,
+//!     verifier,
+//!     0x80000,
+//!     0xA0000,
+//!     0x20000,
+//!     0xC0000,
+//! )
+//! .finalize(components::ota_staging_component_static!(Verifier, Sha, 32, 64));
+//! ```
+
+use capsules_extra::ota_staging::OtaStaging;
+use core::mem::MaybeUninit;
+use kernel::capabilities;
+use kernel::component::Component;
+use kernel::create_capability;
+use kernel::hil;
+
+#[macro_export]
+macro_rules! ota_staging_component_static {
+    ($S:ty, $H:ty, $HL:expr, $SL:expr $(,)?) => {{
+        let ota =
+            kernel::static_buf!(capsules_extra::ota_staging::OtaStaging<'static, $S, $H, $HL, $SL>);
+        let buffer = kernel::static_buf!([u8; capsules_extra::ota_staging::BUF_LEN]);
+        let record_buffer = kernel::static_buf!([u8; capsules_extra::ota_staging::BOOT_RECORD_LEN]);
+        let hash_buffer = kernel::static_buf!([u8; $HL]);
+        let signature_buffer = kernel::static_buf!([u8; $SL]);
+
+        (ota, buffer, record_buffer, hash_buffer, signature_buffer)
+    };};
+}
+
+pub type OtaStagingComponentType<S, H, const HL: usize, const SL: usize> =
+    OtaStaging<'static, S, H, HL, SL>;
+
+pub struct OtaStagingComponent<
+    S: hil::public_key_crypto::signature::SignatureVerify<'static, HL, SL> + 'static,
+    H: hil::digest::DigestDataHash<'static, HL> + 'static,
+    const HL: usize,
+    const SL: usize,
+> {
+    board_kernel: &'static kernel::Kernel,
+    driver_num: usize,
+    storage: &'static dyn hil::nonvolatile_storage::NonvolatileStorage<'static>,
+    hasher: &'static H,
+    verifier: &'static S,
+    slot_a_address: usize,
+    slot_b_address: usize,
+    slot_length: usize,
+    record_address: usize,
+}
+
+impl<
+        S: hil::public_key_crypto::signature::SignatureVerify<'static, HL, SL>,
+        H: hil::digest::DigestDataHash<'static, HL>,
+        const HL: usize,
+        const SL: usize,
+    > OtaStagingComponent<S, H, HL, SL>
+{
+    pub fn new(
+        board_kernel: &'static kernel::Kernel,
+        driver_num: usize,
+        storage: &'static dyn hil::nonvolatile_storage::NonvolatileStorage<'static>,
+        hasher: &'static H,
+        verifier: &'static S,
+        slot_a_address: usize,
+        slot_b_address: usize,
+        slot_length: usize,
+        record_address: usize,
+    ) -> Self {
+        Self {
+            board_kernel,
+            driver_num,
+            storage,
+            hasher,
+            verifier,
+            slot_a_address,
+            slot_b_address,
+            slot_length,
+            record_address,
+        }
+    }
+}
+
+impl<
+        S: hil::public_key_crypto::signature::SignatureVerify<'static, HL, SL>,
+        H: hil::digest::DigestDataHash<'static, HL>,
+        const HL: usize,
+        const SL: usize,
+    > Component for OtaStagingComponent<S, H, HL, SL>
+{
+    type StaticInput = (
+        &'static mut MaybeUninit<OtaStaging<'static, S, H, HL, SL>>,
+        &'static mut MaybeUninit<[u8; capsules_extra::ota_staging::BUF_LEN]>,
+        &'static mut MaybeUninit<[u8; capsules_extra::ota_staging::BOOT_RECORD_LEN]>,
+        &'static mut MaybeUninit<[u8; HL]>,
+        &'static mut MaybeUninit<[u8; SL]>,
+    );
+    type Output = &'static OtaStaging<'static, S, H, HL, SL>;
+
+    fn finalize(self, s: Self::StaticInput) -> Self::Output {
+        let grant_cap = create_capability!(capabilities::MemoryAllocationCapability);
+
+        let buffer = s.1.write([0; capsules_extra::ota_staging::BUF_LEN]);
+        let record_buffer = s.2.write([0; capsules_extra::ota_staging::BOOT_RECORD_LEN]);
+        let hash_buffer = s.3.write([0; HL]);
+        let signature_buffer = s.4.write([0; SL]);
+
+        let ota = s.0.write(OtaStaging::new(
+            self.storage,
+            self.hasher,
+            self.verifier,
+            self.board_kernel.create_grant(self.driver_num, &grant_cap),
+            self.slot_a_address,
+            self.slot_b_address,
+            self.slot_length,
+            self.record_address,
+            buffer,
+            record_buffer,
+            hash_buffer,
+            signature_buffer,
+        ));
+
+        self.storage.set_client(ota);
+        hil::digest::DigestDataHash::set_client(self.hasher, ota);
+        hil::public_key_crypto::signature::SignatureVerify::set_verify_client(self.verifier, ota);
+        let _ = ota.load_boot_record();
+
+        ota
+    }
+}
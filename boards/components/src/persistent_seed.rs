@@ -0,0 +1,84 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2024.
+
+//! Component for an entropy source that keeps a seed in nonvolatile storage.
+//!
+//! The component makes the capsule the client of the TRNG and of the storage,
+//! and reads the saved seed. `address` must be in the kernel region of the
+//! storage, with `SEED_LEN` bytes reserved for the seed.
+//!
+//! Usage
+//! -----
+//! ```rust
+//! let seeded_entropy = components::persistent_seed::PersistentSeedComponent::new(
+//!     &peripherals.trng,
+//!     nonvolatile_storage,
+//!     0x7fd0,
+//! )
+//! .finalize(components::persistent_seed_component_static!(nrf52840::trng::Trng));
+//! ```
+
+use capsules_extra::persistent_seed::{PersistentSeedEntropy, SEED_LEN};
+use core::mem::MaybeUninit;
+use kernel::component::Component;
+use kernel::deferred_call::DeferredCallClient;
+use kernel::hil::entropy::Entropy32;
+use kernel::hil::nonvolatile_storage::NonvolatileStorage;
+
+#[macro_export]
+macro_rules! persistent_seed_component_static {
+    ($E:ty $(,)?) => {{
+        let seed = kernel::static_buf!(
+            capsules_extra::persistent_seed::PersistentSeedEntropy<'static, $E>
+        );
+        let buffer = kernel::static_buf!([u8; capsules_extra::persistent_seed::SEED_LEN]);
+
+        (seed, buffer)
+    };};
+}
+
+pub struct PersistentSeedComponent<E: Entropy32<'static> + 'static> {
+    trng: &'static E,
+    storage: &'static dyn NonvolatileStorage<'static>,
+    address: usize,
+}
+
+impl<E: Entropy32<'static>> PersistentSeedComponent<E> {
+    pub fn new(
+        trng: &'static E,
+        storage: &'static dyn NonvolatileStorage<'static>,
+        address: usize,
+    ) -> Self {
+        Self {
+            trng,
+            storage,
+            address,
+        }
+    }
+}
+
+impl<E: Entropy32<'static>> Component for PersistentSeedComponent<E> {
+    type StaticInput = (
+        &'static mut MaybeUninit<PersistentSeedEntropy<'static, E>>,
+        &'static mut MaybeUninit<[u8; SEED_LEN]>,
+    );
+    type Output = &'static PersistentSeedEntropy<'static, E>;
+
+    fn finalize(self, s: Self::StaticInput) -> Self::Output {
+        let buffer = s.1.write([0; SEED_LEN]);
+        let seed = s.0.write(PersistentSeedEntropy::new(
+            self.trng,
+            self.storage,
+            self.address,
+            buffer,
+        ));
+        seed.register();
+        self.trng.set_client(seed);
+        self.storage.set_client(seed);
+        // Without a saved seed the capsule waits for the TRNG.
+        let _ = seed.start();
+
+        seed
+    }
+}
@@ -0,0 +1,96 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2024.
+
+//! Component for saving a record of a power failure to nonvolatile storage.
+//!
+//! The component sets itself as the client of the power-fail warning and
+//! enables it. The storage must support `write_now`, for example the kernel
+//! interface of the nonvolatile storage driver on FRAM, and `address` must be
+//! in its kernel region.
+//!
+//! Usage
+//! -----
+//! ```rust
+//! let power_fail_recorder = components::power_fail_record::PowerFailRecorderComponent::new(
+//!     board_kernel,
+//!     &peripherals.power,
+//!     nonvolatile_storage,
+//!     0x7ff0,
+//!     &base_peripherals.rtc,
+//! )
+//! .finalize(components::power_fail_recorder_component_static!(nrf52::rtc::Rtc));
+//! ```
+
+use capsules_extra::power_fail_record::PowerFailRecorder;
+use core::mem::MaybeUninit;
+use kernel::capabilities;
+use kernel::component::Component;
+use kernel::hil::nonvolatile_storage::NonvolatileStorage;
+use kernel::hil::power_fail::PowerFailWarning;
+use kernel::hil::time::Time;
+
+#[macro_export]
+macro_rules! power_fail_recorder_component_static {
+    ($T:ty $(,)?) => {{
+        kernel::static_buf!(
+            capsules_extra::power_fail_record::PowerFailRecorder<
+                'static,
+                $T,
+                components::power_fail_record::Capability,
+            >
+        )
+    };};
+}
+
+pub struct Capability;
+unsafe impl capabilities::ProcessManagementCapability for Capability {}
+
+pub type PowerFailRecorderComponentType<T> = PowerFailRecorder<'static, T, Capability>;
+
+pub struct PowerFailRecorderComponent<T: Time + 'static> {
+    board_kernel: &'static kernel::Kernel,
+    power_fail: &'static dyn PowerFailWarning<'static>,
+    storage: &'static dyn NonvolatileStorage<'static>,
+    address: usize,
+    time: &'static T,
+}
+
+impl<T: Time> PowerFailRecorderComponent<T> {
+    pub fn new(
+        board_kernel: &'static kernel::Kernel,
+        power_fail: &'static dyn PowerFailWarning<'static>,
+        storage: &'static dyn NonvolatileStorage<'static>,
+        address: usize,
+        time: &'static T,
+    ) -> Self {
+        Self {
+            board_kernel,
+            power_fail,
+            storage,
+            address,
+            time,
+        }
+    }
+}
+
+impl<T: Time> Component for PowerFailRecorderComponent<T> {
+    type StaticInput = &'static mut MaybeUninit<PowerFailRecorder<'static, T, Capability>>;
+    type Output = &'static PowerFailRecorder<'static, T, Capability>;
+
+    fn finalize(self, s: Self::StaticInput) -> Self::Output {
+        let recorder = s.write(PowerFailRecorder::new(
+            self.board_kernel,
+            Capability,
+            self.storage,
+            self.address,
+            self.time,
+        ));
+
+        self.power_fail.set_client(recorder);
+        // Boards without a power-fail warning simply never get a record.
+        let _ = self.power_fail.enable();
+
+        recorder
+    }
+}
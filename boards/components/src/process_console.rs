@@ -14,22 +14,84 @@
 //! let pconsole = ProcessConsoleComponent::new(board_kernel, uart_mux, alarm_mux, process_printer, Some(reset_function))
 //!     .finalize(process_console_component_static!());
 //! ```
+//!
+//! The board can have the console run commands on its own once it has
+//! started:
+//!
+//! ```rust
+//! pconsole.set_boot_commands(&["list", "uptime"]);
+//! ```
+//!
+//! Optional commands are capsules of their own, added to the console by their
+//! components: `process_console_storage` for `storage` and `source`,
+//! `process_console_backup` for `backup`, `process_console_watch` for
+//! `watch`, and `process_console_upcall` for `upcall`. Boards that do not
+//! create them do not include their code.
+//!
+//! To attach the console to more than one transport, add a session for each
+//! further transport's UART mux. The sessions share the engine of the first
+//! one, and with it the commands added to it. Each session has its own input
+//! state and history, and is named in the mux's transmit statistics by its
+//! label:
+//!
+//! ```ignore
+//! let uart_console = ProcessConsoleComponent::new(board_kernel, uart_mux, alarm_mux, process_printer, Some(reset_function))
+//!     .finalize(process_console_component_static!(nrf52840::rtc::Rtc<'static>));
+//! let rtt_console = ProcessConsoleSessionComponent::new(uart_console.engine(), rtt_mux, alarm_mux)
+//!     .with_label("process_console_rtt")
+//!     .finalize(process_console_session_component_static!(nrf52840::rtc::Rtc<'static>));
+//! let _ = uart_console.start();
+//! let _ = rtt_console.start();
+//! ```
+//!
+//! The commands that start, stop, fault, terminate, and restart processes ask
+//! for confirmation before they run. Production builds can refuse them:
+//!
+//! ```rust
+//! let pconsole = ProcessConsoleComponent::new(board_kernel, uart_mux, alarm_mux, process_printer, None)
+//!     .without_process_control()
+//!     .finalize(process_console_component_static!());
+//! ```
 
 // Author: Philip Levis <pal@cs.stanford.edu>
 // Last modified: 6/20/2018
 
-use capsules_core::process_console::{self, ProcessConsole};
+use capsules_core::process_console::{self, ConsoleEngine, ProcessConsole};
 use capsules_core::virtualizers::virtual_alarm::{MuxAlarm, VirtualMuxAlarm};
 use capsules_core::virtualizers::virtual_uart::{MuxUart, UartDevice};
 use core::mem::MaybeUninit;
 use kernel::capabilities;
 use kernel::component::Component;
+use kernel::create_capability;
 use kernel::hil;
 use kernel::hil::time::Alarm;
 use kernel::process::ProcessPrinter;
 
 #[macro_export]
 macro_rules! process_console_component_static {
+    ($A: ty, $COMMAND_HISTORY_LEN: expr $(,)?) => {{
+        let alarm = kernel::static_buf!(
+            capsules_core::virtualizers::virtual_alarm::VirtualMuxAlarm<'static, $A>
+        );
+        let engine = kernel::static_buf!(
+            capsules_core::process_console::ConsoleEngine<
+                capsules_core::virtualizers::virtual_alarm::VirtualMuxAlarm<'static, $A>,
+                components::process_console::Capability,
+            >
+        );
+        let session = $crate::process_console_session_component_static!($A, $COMMAND_HISTORY_LEN);
+
+        (alarm, engine, session)
+    };};
+    ($A: ty $(,)?) => {{
+        $crate::process_console_component_static!($A, {
+            capsules_core::process_console::DEFAULT_COMMAND_HISTORY_LEN
+        })
+    };};
+}
+
+#[macro_export]
+macro_rules! process_console_session_component_static {
     ($A: ty, $COMMAND_HISTORY_LEN: expr $(,)?) => {{
         let alarm = kernel::static_buf!(capsules_core::virtualizers::virtual_alarm::VirtualMuxAlarm<'static, $A>);
         let uart = kernel::static_buf!(capsules_core::virtualizers::virtual_uart::UartDevice);
@@ -61,16 +123,22 @@ macro_rules! process_console_component_static {
         )
     };};
     ($A: ty $(,)?) => {{
-        $crate::process_console_component_static!($A, { capsules_core::process_console::DEFAULT_COMMAND_HISTORY_LEN })
+        $crate::process_console_session_component_static!($A, { capsules_core::process_console::DEFAULT_COMMAND_HISTORY_LEN })
     };};
 }
 
+pub type ProcessConsoleEngineType<A> =
+    ConsoleEngine<'static, VirtualMuxAlarm<'static, A>, Capability>;
+
 pub struct ProcessConsoleComponent<const COMMAND_HISTORY_LEN: usize, A: 'static + Alarm<'static>> {
     board_kernel: &'static kernel::Kernel,
     uart_mux: &'static MuxUart<'static>,
     alarm_mux: &'static MuxAlarm<'static, A>,
     process_printer: &'static dyn ProcessPrinter,
     reset_function: Option<fn() -> !>,
+    receive_channel: Option<u8>,
+    process_control: bool,
+    label: &'static str,
 }
 
 impl<const COMMAND_HISTORY_LEN: usize, A: 'static + Alarm<'static>>
@@ -89,6 +157,33 @@ impl<const COMMAND_HISTORY_LEN: usize, A: 'static + Alarm<'static>>
             alarm_mux,
             process_printer,
             reset_function,
+            receive_channel: None,
+            process_control: true,
+            label: "process_console",
+        }
+    }
+
+    /// Receive only channel `channel` when the UART mux uses
+    /// `ReceivePolicy::Prefixed`.
+    pub fn with_receive_channel(self, channel: u8) -> Self {
+        Self {
+            receive_channel: Some(channel),
+            ..self
+        }
+    }
+
+    /// Name the console's UART device `label` in the transmit statistics of
+    /// the UART mux, to tell sessions apart.
+    pub fn with_label(self, label: &'static str) -> Self {
+        Self { label, ..self }
+    }
+
+    /// Refuse the commands that start, stop, fault, terminate, and restart
+    /// processes, for example in production builds.
+    pub fn without_process_control(self) -> Self {
+        Self {
+            process_control: false,
+            ..self
         }
     }
 }
@@ -115,15 +210,8 @@ impl<const COMMAND_HISTORY_LEN: usize, A: 'static + Alarm<'static>> Component
 {
     type StaticInput = (
         &'static mut MaybeUninit<VirtualMuxAlarm<'static, A>>,
-        &'static mut MaybeUninit<UartDevice<'static>>,
-        &'static mut MaybeUninit<[u8; capsules_core::process_console::WRITE_BUF_LEN]>,
-        &'static mut MaybeUninit<[u8; capsules_core::process_console::READ_BUF_LEN]>,
-        &'static mut MaybeUninit<[u8; capsules_core::process_console::QUEUE_BUF_LEN]>,
-        &'static mut MaybeUninit<[u8; capsules_core::process_console::COMMAND_BUF_LEN]>,
-        &'static mut MaybeUninit<[capsules_core::process_console::Command; COMMAND_HISTORY_LEN]>,
-        &'static mut MaybeUninit<
-            ProcessConsole<'static, COMMAND_HISTORY_LEN, VirtualMuxAlarm<'static, A>, Capability>,
-        >,
+        &'static mut MaybeUninit<ProcessConsoleEngineType<A>>,
+        <ProcessConsoleSessionComponent<COMMAND_HISTORY_LEN, A> as Component>::StaticInput,
     );
     type Output = &'static process_console::ProcessConsole<
         'static,
@@ -133,10 +221,6 @@ impl<const COMMAND_HISTORY_LEN: usize, A: 'static + Alarm<'static>> Component
     >;
 
     fn finalize(self, static_buffer: Self::StaticInput) -> Self::Output {
-        // Create virtual device for console.
-        let console_uart = static_buffer.1.write(UartDevice::new(self.uart_mux, true));
-        console_uart.setup();
-
         // Get addresses of where the kernel is placed to enable additional
         // debugging in process console.
         // SAFETY: These statics are defined by the linker script, and we are merely creating
@@ -155,6 +239,110 @@ impl<const COMMAND_HISTORY_LEN: usize, A: 'static + Alarm<'static>> Component
             }
         };
 
+        // The engine only reads the counter of its alarm, to count the
+        // uptime.
+        let engine_alarm = static_buffer.0.write(VirtualMuxAlarm::new(self.alarm_mux));
+        let engine = static_buffer.1.write(ConsoleEngine::new(
+            engine_alarm,
+            self.process_printer,
+            self.board_kernel,
+            kernel_addresses,
+            self.reset_function,
+            Capability,
+        ));
+        if self.process_control {
+            let control_cap = create_capability!(capabilities::ProcessControlCapability);
+            engine.enable_process_control(&control_cap);
+        }
+
+        ProcessConsoleSessionComponent {
+            engine,
+            uart_mux: self.uart_mux,
+            alarm_mux: self.alarm_mux,
+            receive_channel: self.receive_channel,
+            label: self.label,
+        }
+        .finalize(static_buffer.2)
+    }
+}
+
+pub struct ProcessConsoleSessionComponent<
+    const COMMAND_HISTORY_LEN: usize,
+    A: 'static + Alarm<'static>,
+> {
+    engine: &'static ProcessConsoleEngineType<A>,
+    uart_mux: &'static MuxUart<'static>,
+    alarm_mux: &'static MuxAlarm<'static, A>,
+    receive_channel: Option<u8>,
+    label: &'static str,
+}
+
+impl<const COMMAND_HISTORY_LEN: usize, A: 'static + Alarm<'static>>
+    ProcessConsoleSessionComponent<COMMAND_HISTORY_LEN, A>
+{
+    /// A session on `uart_mux` that shares `engine`, such as the engine of
+    /// the first session, with the other sessions.
+    pub fn new(
+        engine: &'static ProcessConsoleEngineType<A>,
+        uart_mux: &'static MuxUart,
+        alarm_mux: &'static MuxAlarm<'static, A>,
+    ) -> Self {
+        Self {
+            engine,
+            uart_mux,
+            alarm_mux,
+            receive_channel: None,
+            label: "process_console",
+        }
+    }
+
+    /// Receive only channel `channel` when the UART mux uses
+    /// `ReceivePolicy::Prefixed`.
+    pub fn with_receive_channel(self, channel: u8) -> Self {
+        Self {
+            receive_channel: Some(channel),
+            ..self
+        }
+    }
+
+    /// Name the session's UART device `label` in the transmit statistics of
+    /// the UART mux, to tell sessions apart.
+    pub fn with_label(self, label: &'static str) -> Self {
+        Self { label, ..self }
+    }
+}
+
+impl<const COMMAND_HISTORY_LEN: usize, A: 'static + Alarm<'static>> Component
+    for ProcessConsoleSessionComponent<COMMAND_HISTORY_LEN, A>
+{
+    type StaticInput = (
+        &'static mut MaybeUninit<VirtualMuxAlarm<'static, A>>,
+        &'static mut MaybeUninit<UartDevice<'static>>,
+        &'static mut MaybeUninit<[u8; capsules_core::process_console::WRITE_BUF_LEN]>,
+        &'static mut MaybeUninit<[u8; capsules_core::process_console::READ_BUF_LEN]>,
+        &'static mut MaybeUninit<[u8; capsules_core::process_console::QUEUE_BUF_LEN]>,
+        &'static mut MaybeUninit<[u8; capsules_core::process_console::COMMAND_BUF_LEN]>,
+        &'static mut MaybeUninit<[capsules_core::process_console::Command; COMMAND_HISTORY_LEN]>,
+        &'static mut MaybeUninit<
+            ProcessConsole<'static, COMMAND_HISTORY_LEN, VirtualMuxAlarm<'static, A>, Capability>,
+        >,
+    );
+    type Output = &'static process_console::ProcessConsole<
+        'static,
+        COMMAND_HISTORY_LEN,
+        VirtualMuxAlarm<'static, A>,
+        Capability,
+    >;
+
+    fn finalize(self, static_buffer: Self::StaticInput) -> Self::Output {
+        // Create virtual device for console.
+        let console_uart = static_buffer.1.write(UartDevice::new(self.uart_mux, true));
+        console_uart.setup();
+        console_uart.set_label(self.label);
+        if let Some(channel) = self.receive_channel {
+            console_uart.set_receive_channel(channel);
+        }
+
         let console_alarm = static_buffer.0.write(VirtualMuxAlarm::new(self.alarm_mux));
         console_alarm.setup();
 
@@ -175,22 +363,19 @@ impl<const COMMAND_HISTORY_LEN: usize, A: 'static + Alarm<'static>> Component
             .write([capsules_core::process_console::Command::default(); COMMAND_HISTORY_LEN]);
 
         let console = static_buffer.7.write(ProcessConsole::new(
+            self.engine,
             console_uart,
             console_alarm,
-            self.process_printer,
             write_buffer,
             read_buffer,
             queue_buffer,
             command_buffer,
             command_history_buffer,
-            self.board_kernel,
-            kernel_addresses,
-            self.reset_function,
-            Capability,
         ));
         hil::uart::Transmit::set_transmit_client(console_uart, console);
         hil::uart::Receive::set_receive_client(console_uart, console);
         console_alarm.set_alarm_client(console);
+        console.setup();
 
         console
     }
@@ -0,0 +1,69 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2024.
+
+//! Component for the `backup` command of the process console.
+//!
+//! The command becomes the client of the storage backup, and needs the board
+//! to hold the storage backup capability.
+//!
+//! Usage
+//! -----
+//! ```rust
+//! let backup_cap = create_capability!(capabilities::StorageBackupCapability);
+//! components::process_console_backup::BackupCommandComponent::new(
+//!     pconsole.engine(),
+//!     storage_backup,
+//!     &backup_cap,
+//! )
+//! .finalize(components::backup_command_component_static!());
+//! ```
+
+use capsules_extra::process_console_backup::BackupCommand;
+use core::mem::MaybeUninit;
+use kernel::capabilities::StorageBackupCapability;
+use kernel::component::Component;
+use kernel::hil::nonvolatile_storage::StorageBackup;
+use kernel::hil::time::Alarm;
+
+use crate::process_console::ProcessConsoleEngineType;
+
+#[macro_export]
+macro_rules! backup_command_component_static {
+    () => {{
+        kernel::static_buf!(capsules_extra::process_console_backup::BackupCommand<'static>)
+    };};
+}
+
+pub struct BackupCommandComponent<'c, C: 'static + Alarm<'static>> {
+    engine: &'static ProcessConsoleEngineType<C>,
+    backup: &'static dyn StorageBackup<'static>,
+    capability: &'c dyn StorageBackupCapability,
+}
+
+impl<'c, C: 'static + Alarm<'static>> BackupCommandComponent<'c, C> {
+    pub fn new(
+        engine: &'static ProcessConsoleEngineType<C>,
+        backup: &'static dyn StorageBackup<'static>,
+        capability: &'c dyn StorageBackupCapability,
+    ) -> Self {
+        Self {
+            engine,
+            backup,
+            capability,
+        }
+    }
+}
+
+impl<C: 'static + Alarm<'static>> Component for BackupCommandComponent<'_, C> {
+    type StaticInput = &'static mut MaybeUninit<BackupCommand<'static>>;
+    type Output = &'static BackupCommand<'static>;
+
+    fn finalize(self, static_buffer: Self::StaticInput) -> Self::Output {
+        let command = static_buffer.write(BackupCommand::new(self.backup, self.capability));
+        self.backup.set_client(command);
+        self.engine.add_commands(command);
+
+        command
+    }
+}
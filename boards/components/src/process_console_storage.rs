@@ -0,0 +1,121 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2024.
+
+//! Component for the `storage` and `source` commands of the process console.
+//!
+//! The commands are added to the engine of a console created with
+//! `ProcessConsoleComponent`, so all its sessions can run them. They become
+//! the client of the storage, for example the kernel interface of the
+//! nonvolatile storage driver. `storage selftest` may overwrite the scratch
+//! area given to the component.
+//!
+//! Usage
+//! -----
+//! ```rust
+//! components::process_console_storage::StorageCommandsComponent::new(
+//!     pconsole.engine(),
+//!     mux_alarm,
+//!     nonvolatile_storage,
+//!     scratch_start,
+//!     scratch_length,
+//! )
+//! .with_script_region(script_start, script_length)
+//! .finalize(components::storage_commands_component_static!(nrf52840::rtc::Rtc<'static>));
+//! ```
+
+use capsules_core::virtualizers::virtual_alarm::{MuxAlarm, VirtualMuxAlarm};
+use capsules_extra::process_console_storage::{StorageCommands, BUF_LEN};
+use core::mem::MaybeUninit;
+use kernel::component::Component;
+use kernel::hil::nonvolatile_storage::NonvolatileStorage;
+use kernel::hil::time::Alarm;
+
+use crate::process_console::ProcessConsoleEngineType;
+
+#[macro_export]
+macro_rules! storage_commands_component_static {
+    ($A: ty $(,)?) => {{
+        let alarm = kernel::static_buf!(
+            capsules_core::virtualizers::virtual_alarm::VirtualMuxAlarm<'static, $A>
+        );
+        let commands = kernel::static_buf!(
+            capsules_extra::process_console_storage::StorageCommands<
+                'static,
+                capsules_core::virtualizers::virtual_alarm::VirtualMuxAlarm<'static, $A>,
+            >
+        );
+        let buffer = kernel::static_buf!([u8; capsules_extra::process_console_storage::BUF_LEN]);
+
+        (alarm, commands, buffer)
+    };};
+}
+
+pub struct StorageCommandsComponent<A: 'static + Alarm<'static>, C: 'static + Alarm<'static>> {
+    engine: &'static ProcessConsoleEngineType<C>,
+    alarm_mux: &'static MuxAlarm<'static, A>,
+    storage: &'static dyn NonvolatileStorage<'static>,
+    scratch_address: usize,
+    scratch_length: usize,
+    script_region: Option<(usize, usize)>,
+}
+
+impl<A: 'static + Alarm<'static>, C: 'static + Alarm<'static>> StorageCommandsComponent<A, C> {
+    pub fn new(
+        engine: &'static ProcessConsoleEngineType<C>,
+        alarm_mux: &'static MuxAlarm<'static, A>,
+        storage: &'static dyn NonvolatileStorage<'static>,
+        scratch_address: usize,
+        scratch_length: usize,
+    ) -> Self {
+        Self {
+            engine,
+            alarm_mux,
+            storage,
+            scratch_address,
+            scratch_length,
+            script_region: None,
+        }
+    }
+
+    /// Let the `source` command run the commands stored in the area of the
+    /// storage at `address`.
+    pub fn with_script_region(self, address: usize, length: usize) -> Self {
+        Self {
+            script_region: Some((address, length)),
+            ..self
+        }
+    }
+}
+
+impl<A: 'static + Alarm<'static>, C: 'static + Alarm<'static>> Component
+    for StorageCommandsComponent<A, C>
+{
+    type StaticInput = (
+        &'static mut MaybeUninit<VirtualMuxAlarm<'static, A>>,
+        &'static mut MaybeUninit<StorageCommands<'static, VirtualMuxAlarm<'static, A>>>,
+        &'static mut MaybeUninit<[u8; BUF_LEN]>,
+    );
+    type Output = &'static StorageCommands<'static, VirtualMuxAlarm<'static, A>>;
+
+    fn finalize(self, static_buffer: Self::StaticInput) -> Self::Output {
+        let alarm = static_buffer.0.write(VirtualMuxAlarm::new(self.alarm_mux));
+        alarm.setup();
+
+        let buffer = static_buffer.2.write([0; BUF_LEN]);
+        let commands = static_buffer.1.write(StorageCommands::new(
+            alarm,
+            self.storage,
+            self.scratch_address,
+            self.scratch_length,
+            buffer,
+        ));
+        if let Some((address, length)) = self.script_region {
+            commands.set_script_region(address, length);
+        }
+        self.storage.set_client(commands);
+        self.engine.add_commands(commands);
+
+        commands
+    }
+}
@@ -0,0 +1,76 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2024.
+
+//! Component for the `upcall` command of the process console.
+//!
+//! The command schedules upcalls into processes, so it needs the upcall
+//! injection capability. Production boards should not create it.
+//!
+//! Usage
+//! -----
+//! ```rust
+//! let upcall_cap = create_capability!(capabilities::UpcallInjectionCapability);
+//! components::process_console_upcall::UpcallCommandComponent::new(
+//!     board_kernel,
+//!     pconsole.engine(),
+//!     &upcall_cap,
+//! )
+//! .finalize(components::upcall_command_component_static!());
+//! ```
+
+use capsules_extra::process_console_upcall::UpcallCommand;
+use core::mem::MaybeUninit;
+use kernel::capabilities::UpcallInjectionCapability;
+use kernel::component::Component;
+use kernel::hil::time::Alarm;
+
+use crate::process_console::{Capability, ProcessConsoleEngineType};
+
+#[macro_export]
+macro_rules! upcall_command_component_static {
+    () => {{
+        kernel::static_buf!(
+            capsules_extra::process_console_upcall::UpcallCommand<
+                'static,
+                components::process_console::Capability,
+            >
+        )
+    };};
+}
+
+pub struct UpcallCommandComponent<'c, C: 'static + Alarm<'static>> {
+    board_kernel: &'static kernel::Kernel,
+    engine: &'static ProcessConsoleEngineType<C>,
+    injection_capability: &'c dyn UpcallInjectionCapability,
+}
+
+impl<'c, C: 'static + Alarm<'static>> UpcallCommandComponent<'c, C> {
+    pub fn new(
+        board_kernel: &'static kernel::Kernel,
+        engine: &'static ProcessConsoleEngineType<C>,
+        injection_capability: &'c dyn UpcallInjectionCapability,
+    ) -> Self {
+        Self {
+            board_kernel,
+            engine,
+            injection_capability,
+        }
+    }
+}
+
+impl<C: 'static + Alarm<'static>> Component for UpcallCommandComponent<'_, C> {
+    type StaticInput = &'static mut MaybeUninit<UpcallCommand<'static, Capability>>;
+    type Output = &'static UpcallCommand<'static, Capability>;
+
+    fn finalize(self, static_buffer: Self::StaticInput) -> Self::Output {
+        let command = static_buffer.write(UpcallCommand::new(
+            self.board_kernel,
+            self.injection_capability,
+            Capability,
+        ));
+        self.engine.add_commands(command);
+
+        command
+    }
+}
@@ -0,0 +1,75 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2024.
+
+//! Component for the `watch` command of the process console.
+//!
+//! The command is added to the engine of a console created with
+//! `ProcessConsoleComponent`, and gets its own virtual alarm to time the runs.
+//!
+//! Usage
+//! -----
+//! ```rust
+//! components::process_console_watch::WatchCommandComponent::new(pconsole.engine(), mux_alarm)
+//!     .finalize(components::watch_command_component_static!(nrf52840::rtc::Rtc<'static>));
+//! ```
+
+use capsules_core::virtualizers::virtual_alarm::{MuxAlarm, VirtualMuxAlarm};
+use capsules_extra::process_console_watch::WatchCommand;
+use core::mem::MaybeUninit;
+use kernel::component::Component;
+use kernel::hil::time::Alarm;
+
+use crate::process_console::ProcessConsoleEngineType;
+
+#[macro_export]
+macro_rules! watch_command_component_static {
+    ($A: ty $(,)?) => {{
+        let alarm = kernel::static_buf!(
+            capsules_core::virtualizers::virtual_alarm::VirtualMuxAlarm<'static, $A>
+        );
+        let command = kernel::static_buf!(
+            capsules_extra::process_console_watch::WatchCommand<
+                'static,
+                capsules_core::virtualizers::virtual_alarm::VirtualMuxAlarm<'static, $A>,
+            >
+        );
+
+        (alarm, command)
+    };};
+}
+
+pub struct WatchCommandComponent<A: 'static + Alarm<'static>, C: 'static + Alarm<'static>> {
+    engine: &'static ProcessConsoleEngineType<C>,
+    alarm_mux: &'static MuxAlarm<'static, A>,
+}
+
+impl<A: 'static + Alarm<'static>, C: 'static + Alarm<'static>> WatchCommandComponent<A, C> {
+    pub fn new(
+        engine: &'static ProcessConsoleEngineType<C>,
+        alarm_mux: &'static MuxAlarm<'static, A>,
+    ) -> Self {
+        Self { engine, alarm_mux }
+    }
+}
+
+impl<A: 'static + Alarm<'static>, C: 'static + Alarm<'static>> Component
+    for WatchCommandComponent<A, C>
+{
+    type StaticInput = (
+        &'static mut MaybeUninit<VirtualMuxAlarm<'static, A>>,
+        &'static mut MaybeUninit<WatchCommand<'static, VirtualMuxAlarm<'static, A>>>,
+    );
+    type Output = &'static WatchCommand<'static, VirtualMuxAlarm<'static, A>>;
+
+    fn finalize(self, static_buffer: Self::StaticInput) -> Self::Output {
+        let alarm = static_buffer.0.write(VirtualMuxAlarm::new(self.alarm_mux));
+        alarm.setup();
+
+        let command = static_buffer.1.write(WatchCommand::new(alarm));
+        alarm.set_alarm_client(command);
+        self.engine.add_commands(command);
+
+        command
+    }
+}
@@ -10,6 +10,17 @@
 //! let process_printer = ProcessPrinterTextComponent::new()
 //!     .finalize(components::process_printer_component_static!());
 //! ```
+//!
+//! Capsules that implement `ProcessStateReporter`, like the nonvolatile
+//! storage driver, can add the state they keep for a process to its overview:
+//!
+//! ```rust
+//! let reporters = static_init!(
+//!     [&'static dyn kernel::process::ProcessStateReporter; 1],
+//!     [nonvolatile_storage]
+//! );
+//! process_printer.set_state_reporters(reporters);
+//! ```
 
 use core::mem::MaybeUninit;
 use kernel::component::Component;
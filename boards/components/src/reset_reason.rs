@@ -0,0 +1,43 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2024.
+
+//! Component for the reset reason driver.
+//!
+//! Usage
+//! -----
+//! ```rust
+//! let reset_reason = ResetReasonComponent::new(&base_peripherals.pwr_clk)
+//!     .finalize(components::reset_reason_component_static!());
+//! ```
+
+use capsules_extra::reset_reason::ResetReasonDriver;
+use core::mem::MaybeUninit;
+use kernel::component::Component;
+use kernel::hil::reset_reason::ResetReason;
+
+#[macro_export]
+macro_rules! reset_reason_component_static {
+    () => {{
+        kernel::static_buf!(capsules_extra::reset_reason::ResetReasonDriver)
+    };};
+}
+
+pub struct ResetReasonComponent<R: 'static + ResetReason> {
+    reset_reason: &'static R,
+}
+
+impl<R: 'static + ResetReason> ResetReasonComponent<R> {
+    pub fn new(reset_reason: &'static R) -> Self {
+        Self { reset_reason }
+    }
+}
+
+impl<R: 'static + ResetReason> Component for ResetReasonComponent<R> {
+    type StaticInput = &'static mut MaybeUninit<ResetReasonDriver>;
+    type Output = &'static ResetReasonDriver;
+
+    fn finalize(self, s: Self::StaticInput) -> Self::Output {
+        s.write(ResetReasonDriver::new(self.reset_reason))
+    }
+}
@@ -0,0 +1,67 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2024.
+
+//! Component that publishes small read-only blobs to every app.
+//!
+//! Usage
+//! -----
+//! ```rust
+//! static BLOBS: [SharedBlob; 1] = [SharedBlob {
+//!     id: 1,
+//!     data: b"board-rev-3",
+//! }];
+//! let shared_blobs = components::shared_blobs::SharedBlobsComponent::new(
+//!     board_kernel,
+//!     capsules_extra::shared_blobs::DRIVER_NUM,
+//!     &BLOBS,
+//! )
+//! .finalize(components::shared_blobs_component_static!());
+//! ```
+
+use capsules_extra::shared_blobs::{SharedBlob, SharedBlobs};
+use core::mem::MaybeUninit;
+use kernel::capabilities;
+use kernel::component::Component;
+use kernel::create_capability;
+
+#[macro_export]
+macro_rules! shared_blobs_component_static {
+    () => {{
+        kernel::static_buf!(capsules_extra::shared_blobs::SharedBlobs)
+    };};
+}
+
+pub struct SharedBlobsComponent {
+    board_kernel: &'static kernel::Kernel,
+    driver_num: usize,
+    blobs: &'static [SharedBlob],
+}
+
+impl SharedBlobsComponent {
+    pub fn new(
+        board_kernel: &'static kernel::Kernel,
+        driver_num: usize,
+        blobs: &'static [SharedBlob],
+    ) -> Self {
+        Self {
+            board_kernel,
+            driver_num,
+            blobs,
+        }
+    }
+}
+
+impl Component for SharedBlobsComponent {
+    type StaticInput = &'static mut MaybeUninit<SharedBlobs>;
+    type Output = &'static SharedBlobs;
+
+    fn finalize(self, s: Self::StaticInput) -> Self::Output {
+        let grant_cap = create_capability!(capabilities::MemoryAllocationCapability);
+
+        s.write(SharedBlobs::new(
+            self.board_kernel.create_grant(self.driver_num, &grant_cap),
+            self.blobs,
+        ))
+    }
+}
@@ -0,0 +1,127 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2024.
+
+//! Component for SPI NOR flash chips that support JEDEC SFDP.
+//!
+//! The board calls `probe()` on the driver once the kernel is running. The
+//! flash returns `OFF` until probing has found a supported chip.
+//!
+//! Usage
+//! -----
+//! ```rust
+//! let spi_nor = components::spi_nor::SpiNorComponent::new(
+//!     &nrf52840::gpio::PORT[Pin::P0_17],
+//!     mux_alarm,
+//!     mux_spi,
+//! )
+//! .finalize(components::spi_nor_component_static!(
+//!     nrf52840::spi::SPIM,
+//!     nrf52840::rtc::Rtc
+//! ));
+//! spi_nor.probe().unwrap();
+//! ```
+
+use capsules_core::virtualizers::virtual_alarm::{MuxAlarm, VirtualMuxAlarm};
+use capsules_core::virtualizers::virtual_spi::{MuxSpiMaster, VirtualSpiMasterDevice};
+use capsules_extra::spi_nor::SpiNor;
+use core::mem::MaybeUninit;
+use kernel::component::Component;
+use kernel::hil;
+use kernel::hil::spi::SpiMasterDevice;
+use kernel::hil::time::Alarm;
+
+// Setup static space for the objects.
+#[macro_export]
+macro_rules! spi_nor_component_static {
+    ($S:ty, $A:ty $(,)?) => {{
+        let spi_device = kernel::static_buf!(
+            capsules_core::virtualizers::virtual_spi::VirtualSpiMasterDevice<'static, $S>
+        );
+        let alarm = kernel::static_buf!(
+            capsules_core::virtualizers::virtual_alarm::VirtualMuxAlarm<'static, $A>
+        );
+        let spi_nor = kernel::static_buf!(
+            capsules_extra::spi_nor::SpiNor<
+                'static,
+                capsules_core::virtualizers::virtual_spi::VirtualSpiMasterDevice<'static, $S>,
+                capsules_core::virtualizers::virtual_alarm::VirtualMuxAlarm<'static, $A>,
+            >
+        );
+
+        let tx_buf = kernel::static_buf!([u8; capsules_extra::spi_nor::TX_BUF_LEN]);
+        let rx_buf = kernel::static_buf!([u8; capsules_extra::spi_nor::RX_BUF_LEN]);
+
+        (spi_device, alarm, spi_nor, tx_buf, rx_buf)
+    };};
+}
+
+pub type SpiNorComponentType<S, A> = capsules_extra::spi_nor::SpiNor<
+    'static,
+    capsules_core::virtualizers::virtual_spi::VirtualSpiMasterDevice<'static, S>,
+    VirtualMuxAlarm<'static, A>,
+>;
+
+pub struct SpiNorComponent<
+    S: 'static + hil::spi::SpiMaster<'static>,
+    A: 'static + hil::time::Alarm<'static>,
+> {
+    chip_select: S::ChipSelect,
+    mux_alarm: &'static MuxAlarm<'static, A>,
+    mux_spi: &'static MuxSpiMaster<'static, S>,
+}
+
+impl<S: 'static + hil::spi::SpiMaster<'static>, A: 'static + hil::time::Alarm<'static>>
+    SpiNorComponent<S, A>
+{
+    pub fn new<CS: kernel::hil::spi::cs::IntoChipSelect<S::ChipSelect, hil::spi::cs::ActiveLow>>(
+        chip_select: CS,
+        mux_alarm: &'static MuxAlarm<'static, A>,
+        mux_spi: &'static MuxSpiMaster<'static, S>,
+    ) -> SpiNorComponent<S, A> {
+        SpiNorComponent {
+            chip_select: chip_select.into_cs(),
+            mux_alarm,
+            mux_spi,
+        }
+    }
+}
+
+impl<S: 'static + hil::spi::SpiMaster<'static>, A: 'static + hil::time::Alarm<'static>> Component
+    for SpiNorComponent<S, A>
+{
+    type StaticInput = (
+        &'static mut MaybeUninit<VirtualSpiMasterDevice<'static, S>>,
+        &'static mut MaybeUninit<VirtualMuxAlarm<'static, A>>,
+        &'static mut MaybeUninit<
+            SpiNor<'static, VirtualSpiMasterDevice<'static, S>, VirtualMuxAlarm<'static, A>>,
+        >,
+        &'static mut MaybeUninit<[u8; capsules_extra::spi_nor::TX_BUF_LEN]>,
+        &'static mut MaybeUninit<[u8; capsules_extra::spi_nor::RX_BUF_LEN]>,
+    );
+    type Output =
+        &'static SpiNor<'static, VirtualSpiMasterDevice<'static, S>, VirtualMuxAlarm<'static, A>>;
+
+    fn finalize(self, static_buffer: Self::StaticInput) -> Self::Output {
+        let spi_device = static_buffer
+            .0
+            .write(VirtualSpiMasterDevice::new(self.mux_spi, self.chip_select));
+        let virtual_alarm = static_buffer.1.write(VirtualMuxAlarm::new(self.mux_alarm));
+        virtual_alarm.setup();
+
+        let tx_buf = static_buffer
+            .3
+            .write([0; capsules_extra::spi_nor::TX_BUF_LEN]);
+        let rx_buf = static_buffer
+            .4
+            .write([0; capsules_extra::spi_nor::RX_BUF_LEN]);
+
+        let spi_nor = static_buffer
+            .2
+            .write(SpiNor::new(spi_device, virtual_alarm, tx_buf, rx_buf));
+        spi_device.setup();
+        spi_device.set_client(spi_nor);
+        virtual_alarm.set_alarm_client(spi_nor);
+        spi_nor
+    }
+}
@@ -0,0 +1,57 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2024.
+
+//! Component that reports every read and write of nonvolatile storage to an
+//! auditor.
+//!
+//! The component makes the layer the client of the storage. Give the layer
+//! the nonvolatile storage driver with `set_users()` once the driver is
+//! built, so accesses are reported with the app they were for.
+//!
+//! Usage
+//! -----
+//! ```rust
+//! let audit = components::storage_audit::StorageAuditComponent::new(nv_to_page, auditor)
+//!     .finalize(components::storage_audit_component_static!());
+//! // ...
+//! audit.set_users(nonvolatile_storage);
+//! ```
+
+use capsules_extra::storage_audit::{StorageAudit, StorageAuditor};
+use core::mem::MaybeUninit;
+use kernel::component::Component;
+use kernel::hil::nonvolatile_storage::NonvolatileStorage;
+
+#[macro_export]
+macro_rules! storage_audit_component_static {
+    () => {{
+        kernel::static_buf!(capsules_extra::storage_audit::StorageAudit<'static>)
+    };};
+}
+
+pub struct StorageAuditComponent {
+    storage: &'static dyn NonvolatileStorage<'static>,
+    auditor: &'static dyn StorageAuditor,
+}
+
+impl StorageAuditComponent {
+    pub fn new(
+        storage: &'static dyn NonvolatileStorage<'static>,
+        auditor: &'static dyn StorageAuditor,
+    ) -> Self {
+        Self { storage, auditor }
+    }
+}
+
+impl Component for StorageAuditComponent {
+    type StaticInput = &'static mut MaybeUninit<StorageAudit<'static>>;
+    type Output = &'static StorageAudit<'static>;
+
+    fn finalize(self, s: Self::StaticInput) -> Self::Output {
+        let audit = s.write(StorageAudit::new(self.storage, self.auditor));
+        self.storage.set_client(audit);
+
+        audit
+    }
+}
@@ -0,0 +1,86 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2024.
+
+//! Component for copying storage regions to a second storage device.
+//!
+//! The component makes the backup the client of both storages. The last
+//! argument to the static macro is the length of the buffer regions are
+//! copied through, and defaults to
+//! `capsules_extra::storage_backup::BUF_LEN`.
+//!
+//! Usage
+//! -----
+//! ```rust
+//! let backup = components::storage_backup::StorageBackupComponent::new(
+//!     storage_window,
+//!     backup_flash,
+//!     &[BackupRegion {
+//!         name: "apps",
+//!         source_address: 0x60000,
+//!         length: 0x20000,
+//!         destination_address: 0,
+//!     }],
+//! )
+//! .finalize(components::storage_backup_component_static!());
+//! ```
+
+use capsules_extra::storage_backup::{BackupRegion, RegionBackup};
+use core::mem::MaybeUninit;
+use kernel::component::Component;
+use kernel::hil::nonvolatile_storage::NonvolatileStorage;
+
+#[macro_export]
+macro_rules! storage_backup_component_static {
+    () => {{
+        $crate::storage_backup_component_static!(capsules_extra::storage_backup::BUF_LEN)
+    };};
+    ($BUF_LEN:expr $(,)?) => {{
+        let backup = kernel::static_buf!(capsules_extra::storage_backup::RegionBackup<'static>);
+        let buffer = kernel::static_buf!([u8; $BUF_LEN]);
+
+        (backup, buffer)
+    };};
+}
+
+pub struct StorageBackupComponent<const BUF_LEN: usize> {
+    source: &'static dyn NonvolatileStorage<'static>,
+    destination: &'static dyn NonvolatileStorage<'static>,
+    regions: &'static [BackupRegion],
+}
+
+impl<const BUF_LEN: usize> StorageBackupComponent<BUF_LEN> {
+    pub fn new(
+        source: &'static dyn NonvolatileStorage<'static>,
+        destination: &'static dyn NonvolatileStorage<'static>,
+        regions: &'static [BackupRegion],
+    ) -> Self {
+        Self {
+            source,
+            destination,
+            regions,
+        }
+    }
+}
+
+impl<const BUF_LEN: usize> Component for StorageBackupComponent<BUF_LEN> {
+    type StaticInput = (
+        &'static mut MaybeUninit<RegionBackup<'static>>,
+        &'static mut MaybeUninit<[u8; BUF_LEN]>,
+    );
+    type Output = &'static RegionBackup<'static>;
+
+    fn finalize(self, s: Self::StaticInput) -> Self::Output {
+        let buffer = s.1.write([0; BUF_LEN]);
+        let backup = s.0.write(RegionBackup::new(
+            self.source,
+            self.destination,
+            self.regions,
+            buffer,
+        ));
+        self.source.set_client(backup);
+        self.destination.set_client(backup);
+
+        backup
+    }
+}
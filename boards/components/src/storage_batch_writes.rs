@@ -0,0 +1,92 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2024.
+
+//! Component that lets apps write several segments of storage with one
+//! command.
+//!
+//! The batches are written through an `AppStorage`, usually the nonvolatile
+//! storage driver, and the component makes the capsule its client. The write
+//! granularity is that of the storage below the `AppStorage`. The optional
+//! argument to the static macro is the longest segment.
+//!
+//! Usage
+//! -----
+//! ```rust
+//! let batch_writes = components::storage_batch_writes::StorageBatchWritesComponent::new(
+//!     board_kernel,
+//!     capsules_extra::storage_batch_writes::DRIVER_NUM,
+//!     nonvolatile_storage,
+//!     4,
+//! )
+//! .finalize(components::storage_batch_writes_component_static!());
+//! ```
+
+use capsules_extra::storage_batch_writes::StorageBatchWrites;
+use core::mem::MaybeUninit;
+use kernel::capabilities;
+use kernel::component::Component;
+use kernel::create_capability;
+use kernel::hil::app_storage::AppStorage;
+
+#[macro_export]
+macro_rules! storage_batch_writes_component_static {
+    () => {{
+        $crate::storage_batch_writes_component_static!(
+            capsules_extra::nonvolatile_storage_driver::BUF_LEN
+        )
+    };};
+    ($BUF_LEN:expr $(,)?) => {{
+        let batch_writes =
+            kernel::static_buf!(capsules_extra::storage_batch_writes::StorageBatchWrites<'static>);
+        let buffer = kernel::static_buf!([u8; $BUF_LEN]);
+
+        (batch_writes, buffer)
+    };};
+}
+
+pub struct StorageBatchWritesComponent<const BUF_LEN: usize> {
+    board_kernel: &'static kernel::Kernel,
+    driver_num: usize,
+    storage: &'static dyn AppStorage<'static>,
+    write_granularity: usize,
+}
+
+impl<const BUF_LEN: usize> StorageBatchWritesComponent<BUF_LEN> {
+    pub fn new(
+        board_kernel: &'static kernel::Kernel,
+        driver_num: usize,
+        storage: &'static dyn AppStorage<'static>,
+        write_granularity: usize,
+    ) -> Self {
+        Self {
+            board_kernel,
+            driver_num,
+            storage,
+            write_granularity,
+        }
+    }
+}
+
+impl<const BUF_LEN: usize> Component for StorageBatchWritesComponent<BUF_LEN> {
+    type StaticInput = (
+        &'static mut MaybeUninit<StorageBatchWrites<'static>>,
+        &'static mut MaybeUninit<[u8; BUF_LEN]>,
+    );
+    type Output = &'static StorageBatchWrites<'static>;
+
+    fn finalize(self, static_buffer: Self::StaticInput) -> Self::Output {
+        let grant_cap = create_capability!(capabilities::MemoryAllocationCapability);
+
+        let buffer = static_buffer.1.write([0; BUF_LEN]);
+        let batch_writes = static_buffer.0.write(StorageBatchWrites::new(
+            self.storage,
+            self.board_kernel.create_grant(self.driver_num, &grant_cap),
+            buffer,
+            self.write_granularity,
+        ));
+        self.storage.set_client(batch_writes);
+
+        batch_writes
+    }
+}
@@ -0,0 +1,74 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2024.
+
+//! Component that warns apps before the userspace region of nonvolatile
+//! storage runs out.
+//!
+//! The component makes the layer the client of the storage. Give the layer
+//! the nonvolatile storage driver with `set_users()` once the driver is
+//! built.
+//!
+//! Usage
+//! -----
+//! ```rust
+//! let low_space = components::storage_low_space::StorageLowSpaceComponent::new(
+//!     board_kernel,
+//!     capsules_extra::storage_low_space::DRIVER_NUM,
+//!     nv_to_page,
+//! )
+//! .finalize(components::storage_low_space_component_static!());
+//! // ...
+//! low_space.set_users(nonvolatile_storage);
+//! ```
+
+use capsules_extra::storage_low_space::StorageLowSpace;
+use core::mem::MaybeUninit;
+use kernel::capabilities;
+use kernel::component::Component;
+use kernel::create_capability;
+use kernel::hil::nonvolatile_storage::NonvolatileStorage;
+
+#[macro_export]
+macro_rules! storage_low_space_component_static {
+    () => {{
+        kernel::static_buf!(capsules_extra::storage_low_space::StorageLowSpace<'static>)
+    };};
+}
+
+pub struct StorageLowSpaceComponent {
+    board_kernel: &'static kernel::Kernel,
+    driver_num: usize,
+    storage: &'static dyn NonvolatileStorage<'static>,
+}
+
+impl StorageLowSpaceComponent {
+    pub fn new(
+        board_kernel: &'static kernel::Kernel,
+        driver_num: usize,
+        storage: &'static dyn NonvolatileStorage<'static>,
+    ) -> Self {
+        Self {
+            board_kernel,
+            driver_num,
+            storage,
+        }
+    }
+}
+
+impl Component for StorageLowSpaceComponent {
+    type StaticInput = &'static mut MaybeUninit<StorageLowSpace<'static>>;
+    type Output = &'static StorageLowSpace<'static>;
+
+    fn finalize(self, s: Self::StaticInput) -> Self::Output {
+        let grant_cap = create_capability!(capabilities::MemoryAllocationCapability);
+
+        let low_space = s.write(StorageLowSpace::new(
+            self.storage,
+            self.board_kernel.create_grant(self.driver_num, &grant_cap),
+        ));
+        self.storage.set_client(low_space);
+
+        low_space
+    }
+}
@@ -0,0 +1,82 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2024.
+
+//! Component that lets apps read nonvolatile storage that is mapped into
+//! memory without waiting for the storage.
+//!
+//! Only for storage whose addresses are the addresses it is mapped at, like
+//! internal flash. The component makes the layer the client of the storage.
+//! Give the layer the nonvolatile storage driver with `set_users()` once the
+//! driver is built.
+//!
+//! Usage
+//! -----
+//! ```rust
+//! let mapping_cap = create_capability!(capabilities::ReadOnlyMappingCapability);
+//! let mapped_reads = components::storage_mapped_reads::StorageMappedReadsComponent::new(
+//!     board_kernel,
+//!     capsules_extra::storage_mapped_reads::DRIVER_NUM,
+//!     nv_to_page,
+//!     &mapping_cap,
+//! )
+//! .finalize(components::storage_mapped_reads_component_static!());
+//! // ...
+//! mapped_reads.set_users(nonvolatile_storage);
+//! ```
+
+use capsules_extra::storage_mapped_reads::StorageMappedReads;
+use core::mem::MaybeUninit;
+use kernel::capabilities;
+use kernel::component::Component;
+use kernel::create_capability;
+use kernel::hil::nonvolatile_storage::NonvolatileStorage;
+use kernel::process::ReadOnlyMapper;
+
+#[macro_export]
+macro_rules! storage_mapped_reads_component_static {
+    () => {{
+        kernel::static_buf!(capsules_extra::storage_mapped_reads::StorageMappedReads<'static>)
+    };};
+}
+
+pub struct StorageMappedReadsComponent {
+    board_kernel: &'static kernel::Kernel,
+    driver_num: usize,
+    storage: &'static dyn NonvolatileStorage<'static>,
+    mapper: ReadOnlyMapper,
+}
+
+impl StorageMappedReadsComponent {
+    pub fn new(
+        board_kernel: &'static kernel::Kernel,
+        driver_num: usize,
+        storage: &'static dyn NonvolatileStorage<'static>,
+        capability: &dyn capabilities::ReadOnlyMappingCapability,
+    ) -> Self {
+        Self {
+            board_kernel,
+            driver_num,
+            storage,
+            mapper: board_kernel.readonly_mapper(capability),
+        }
+    }
+}
+
+impl Component for StorageMappedReadsComponent {
+    type StaticInput = &'static mut MaybeUninit<StorageMappedReads<'static>>;
+    type Output = &'static StorageMappedReads<'static>;
+
+    fn finalize(self, s: Self::StaticInput) -> Self::Output {
+        let grant_cap = create_capability!(capabilities::MemoryAllocationCapability);
+
+        let mapped_reads = s.write(StorageMappedReads::new(
+            self.storage,
+            self.board_kernel.create_grant(self.driver_num, &grant_cap),
+            self.mapper,
+        ));
+        self.storage.set_client(mapped_reads);
+
+        mapped_reads
+    }
+}
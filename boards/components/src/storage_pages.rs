@@ -0,0 +1,76 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2024.
+
+//! Component for flash pages on a region of nonvolatile storage.
+//!
+//! The component makes the pages the client of the storage. The argument to
+//! the static macro is the page size.
+//!
+//! Usage
+//! -----
+//! ```rust
+//! let pages = components::storage_pages::StoragePagesComponent::new(
+//!     app_storage_window,
+//!     0x60000,
+//!     0x8000,
+//! )
+//! .finalize(components::storage_pages_component_static!(512));
+//! ```
+
+use capsules_extra::storage_pages::StoragePages;
+use core::mem::MaybeUninit;
+use kernel::component::Component;
+use kernel::hil::nonvolatile_storage::NonvolatileStorage;
+
+#[macro_export]
+macro_rules! storage_pages_component_static {
+    ($PAGE_SIZE:expr $(,)?) => {{
+        let pages =
+            kernel::static_buf!(capsules_extra::storage_pages::StoragePages<'static, $PAGE_SIZE>);
+        let buffer = kernel::static_buf!([u8; $PAGE_SIZE]);
+
+        (pages, buffer)
+    };};
+}
+
+pub struct StoragePagesComponent<const PAGE_SIZE: usize> {
+    storage: &'static dyn NonvolatileStorage<'static>,
+    start_address: usize,
+    length: usize,
+}
+
+impl<const PAGE_SIZE: usize> StoragePagesComponent<PAGE_SIZE> {
+    pub fn new(
+        storage: &'static dyn NonvolatileStorage<'static>,
+        start_address: usize,
+        length: usize,
+    ) -> Self {
+        Self {
+            storage,
+            start_address,
+            length,
+        }
+    }
+}
+
+impl<const PAGE_SIZE: usize> Component for StoragePagesComponent<PAGE_SIZE> {
+    type StaticInput = (
+        &'static mut MaybeUninit<StoragePages<'static, PAGE_SIZE>>,
+        &'static mut MaybeUninit<[u8; PAGE_SIZE]>,
+    );
+    type Output = &'static StoragePages<'static, PAGE_SIZE>;
+
+    fn finalize(self, s: Self::StaticInput) -> Self::Output {
+        let buffer = s.1.write([0; PAGE_SIZE]);
+        let pages = s.0.write(StoragePages::new(
+            self.storage,
+            self.start_address,
+            self.length,
+            buffer,
+        ));
+        self.storage.set_client(pages);
+
+        pages
+    }
+}
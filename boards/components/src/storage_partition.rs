@@ -0,0 +1,242 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2024.
+
+//! Component that splits one storage volume between a kernel log and the
+//! userspace nonvolatile storage driver.
+//!
+//! The first `log_quota` bytes of the volume hold a circular log for the
+//! kernel, and the rest is the userspace region of the driver. Both share the
+//! flash through a flash mux. `finalize()` panics unless the volume and both
+//! quotas are whole flash pages, so the parts cannot overlap. The driver gets
+//! no kernel region; kernel clients use the log.
+//!
+//! Usage
+//! -----
+//! ```rust
+//! storage_volume!(STORAGE, 32);
+//!
+//! let (log, nonvolatile_storage, partition) =
+//!     components::storage_partition::StoragePartitionComponent::new(
+//!         board_kernel,
+//!         capsules_extra::nonvolatile_storage_driver::DRIVER_NUM,
+//!         &base_peripherals.nvmc,
+//!         &STORAGE,
+//!         0x2000, // Log quota, the rest is for apps.
+//!     )
+//!     .finalize(components::storage_partition_component_static!(
+//!         nrf52840::nvmc::Nvmc
+//!     ));
+//! ```
+//!
+//! The log can be handed to its users, like the event journal, and
+//! `partition.usage()` reports how much of each quota is used.
+
+use capsules_core::virtualizers::virtual_flash::{FlashUser, MuxFlash};
+use capsules_extra::log::Log;
+use capsules_extra::nonvolatile_storage_driver::NonvolatileStorage;
+use capsules_extra::nonvolatile_to_pages::NonvolatileToPages;
+use capsules_extra::storage_partition::StoragePartition;
+use core::mem::MaybeUninit;
+use kernel::capabilities;
+use kernel::component::Component;
+use kernel::create_capability;
+use kernel::hil;
+use kernel::hil::flash::HasClient;
+
+// Setup static space for the objects.
+#[macro_export]
+macro_rules! storage_partition_component_static {
+    ($F:ty $(,)?) => {{
+        $crate::storage_partition_component_static!(
+            $F,
+            capsules_extra::nonvolatile_storage_driver::BUF_LEN
+        )
+    };};
+    ($F:ty, $BUF_LEN:expr $(,)?) => {{
+        let mux =
+            kernel::static_buf!(capsules_core::virtualizers::virtual_flash::MuxFlash<'static, $F>);
+        let log_user =
+            kernel::static_buf!(capsules_core::virtualizers::virtual_flash::FlashUser<'static, $F>);
+        let log_page = kernel::static_buf!(<$F as kernel::hil::flash::Flash>::Page);
+        let log = kernel::static_buf!(
+            capsules_extra::log::Log<
+                'static,
+                capsules_core::virtualizers::virtual_flash::FlashUser<'static, $F>,
+            >
+        );
+        let storage_user =
+            kernel::static_buf!(capsules_core::virtualizers::virtual_flash::FlashUser<'static, $F>);
+        let storage_page = kernel::static_buf!(<$F as kernel::hil::flash::Flash>::Page);
+        let ntp = kernel::static_buf!(
+            capsules_extra::nonvolatile_to_pages::NonvolatileToPages<
+                'static,
+                capsules_core::virtualizers::virtual_flash::FlashUser<'static, $F>,
+            >
+        );
+        let ns = kernel::static_buf!(
+            capsules_extra::nonvolatile_storage_driver::NonvolatileStorage<'static>
+        );
+        let buffer = kernel::static_buf!([u8; $BUF_LEN]);
+        let partition = kernel::static_buf!(
+            capsules_extra::storage_partition::StoragePartition<
+                'static,
+                capsules_extra::log::Log<
+                    'static,
+                    capsules_core::virtualizers::virtual_flash::FlashUser<'static, $F>,
+                >,
+            >
+        );
+
+        (
+            mux,
+            log_user,
+            log_page,
+            log,
+            storage_user,
+            storage_page,
+            ntp,
+            ns,
+            buffer,
+            partition,
+        )
+    };};
+}
+
+pub type StoragePartitionLog<F> = Log<'static, FlashUser<'static, F>>;
+
+pub struct StoragePartitionComponent<
+    F: 'static + hil::flash::Flash + HasClient<'static, MuxFlash<'static, F>>,
+    const BUF_LEN: usize,
+> {
+    board_kernel: &'static kernel::Kernel,
+    driver_num: usize,
+    flash: &'static F,
+    volume: &'static [u8],
+    log_quota: usize,
+    circular_log: bool,
+}
+
+impl<
+        F: 'static + hil::flash::Flash + HasClient<'static, MuxFlash<'static, F>>,
+        const BUF_LEN: usize,
+    > StoragePartitionComponent<F, BUF_LEN>
+{
+    /// Give the first `log_quota` bytes of `volume` to the log, and the rest
+    /// to apps.
+    pub fn new(
+        board_kernel: &'static kernel::Kernel,
+        driver_num: usize,
+        flash: &'static F,
+        volume: &'static [u8],
+        log_quota: usize,
+    ) -> Self {
+        Self {
+            board_kernel,
+            driver_num,
+            flash,
+            volume,
+            log_quota,
+            circular_log: true,
+        }
+    }
+
+    /// Make the log linear, so it rejects entries once it is full instead of
+    /// overwriting the oldest ones.
+    pub fn with_linear_log(self) -> Self {
+        Self {
+            circular_log: false,
+            ..self
+        }
+    }
+}
+
+impl<
+        F: 'static + hil::flash::Flash + HasClient<'static, MuxFlash<'static, F>>,
+        const BUF_LEN: usize,
+    > Component for StoragePartitionComponent<F, BUF_LEN>
+{
+    type StaticInput = (
+        &'static mut MaybeUninit<MuxFlash<'static, F>>,
+        &'static mut MaybeUninit<FlashUser<'static, F>>,
+        &'static mut MaybeUninit<<F as hil::flash::Flash>::Page>,
+        &'static mut MaybeUninit<StoragePartitionLog<F>>,
+        &'static mut MaybeUninit<FlashUser<'static, F>>,
+        &'static mut MaybeUninit<<F as hil::flash::Flash>::Page>,
+        &'static mut MaybeUninit<NonvolatileToPages<'static, FlashUser<'static, F>>>,
+        &'static mut MaybeUninit<NonvolatileStorage<'static>>,
+        &'static mut MaybeUninit<[u8; BUF_LEN]>,
+        &'static mut MaybeUninit<StoragePartition<'static, StoragePartitionLog<F>>>,
+    );
+    type Output = (
+        &'static StoragePartitionLog<F>,
+        &'static NonvolatileStorage<'static>,
+        &'static StoragePartition<'static, StoragePartitionLog<F>>,
+    );
+
+    fn finalize(self, s: Self::StaticInput) -> Self::Output {
+        let grant_cap = create_capability!(capabilities::MemoryAllocationCapability);
+
+        let log_page = s.2.write(<F as hil::flash::Flash>::Page::default());
+        let page_size = log_page.as_mut().len();
+        let start = self.volume.as_ptr() as usize;
+        if start % page_size != 0
+            || self.volume.len() % page_size != 0
+            || self.log_quota % page_size != 0
+            || self.log_quota == 0
+            || self.log_quota >= self.volume.len()
+        {
+            panic!(
+                "Storage volume {:#x}..{:#x} cannot be split at {:#x} into whole {} byte pages \
+                 for both the log and apps.",
+                start,
+                start + self.volume.len(),
+                start + self.log_quota,
+                page_size,
+            );
+        }
+        let userspace_start = start + self.log_quota;
+        let userspace_quota = self.volume.len() - self.log_quota;
+
+        let mux_flash = s.0.write(MuxFlash::new(self.flash));
+        HasClient::set_client(self.flash, mux_flash);
+
+        let log_user = s.1.write(FlashUser::new(mux_flash));
+        let log = s.3.write(Log::new(
+            &self.volume[..self.log_quota],
+            log_user,
+            log_page,
+            self.circular_log,
+        ));
+        HasClient::set_client(log_user, log);
+        kernel::deferred_call::DeferredCallClient::register(log);
+
+        let storage_user = s.4.write(FlashUser::new(mux_flash));
+        let storage_page = s.5.write(<F as hil::flash::Flash>::Page::default());
+        let nv_to_page =
+            s.6.write(NonvolatileToPages::new(storage_user, storage_page));
+        HasClient::set_client(storage_user, nv_to_page);
+
+        let buffer = s.8.write([0; BUF_LEN]);
+        let nonvolatile_storage = s.7.write(NonvolatileStorage::new(
+            nv_to_page,
+            self.board_kernel.create_grant(self.driver_num, &grant_cap),
+            userspace_start,
+            userspace_quota,
+            userspace_start + userspace_quota, // No kernel region.
+            0,
+            buffer,
+        ));
+        hil::nonvolatile_storage::NonvolatileStorage::set_client(nv_to_page, nonvolatile_storage);
+        kernel::deferred_call::DeferredCallClient::register(nonvolatile_storage);
+
+        let partition = s.9.write(StoragePartition::new(
+            log,
+            self.log_quota,
+            userspace_start,
+            userspace_quota,
+        ));
+
+        (log, nonvolatile_storage, partition)
+    }
+}
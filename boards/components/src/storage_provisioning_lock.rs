@@ -0,0 +1,139 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2024.
+
+//! Component for the provisioning lock of the nonvolatile storage driver.
+//!
+//! `StorageProvisioningLockComponent` makes the lock the client of the
+//! storage. The lock is then the storage of the nonvolatile storage driver,
+//! and `ProvisioningLockDriverComponent` gives the driver the lock once the
+//! driver is built. The driver needs a provisioned region, and the lock must
+//! be in its kernel region.
+//!
+//! Usage
+//! -----
+//! ```rust
+//! let provisioning_lock =
+//!     components::storage_provisioning_lock::StorageProvisioningLockComponent::new(
+//!         nv_to_page,
+//!         0x7effc,
+//!         ShortId::Fixed(NonZeroU32::new(0x5).unwrap()),
+//!     )
+//!     .finalize(components::storage_provisioning_lock_component_static!());
+//! let nonvolatile_storage = components::nonvolatile_storage::NonvolatileStorageComponent::new(
+//!     board_kernel,
+//!     capsules_extra::nonvolatile_storage_driver::DRIVER_NUM,
+//!     provisioning_lock,
+//!     // ...
+//! )
+//! .with_provisioned_region(0x7f000, 0x1000)
+//! .finalize(components::nonvolatile_storage_component_static!());
+//! components::storage_provisioning_lock::ProvisioningLockDriverComponent::new(
+//!     provisioning_lock,
+//!     nonvolatile_storage,
+//! )
+//! .finalize(());
+//! ```
+
+use capsules_extra::nonvolatile_storage_driver::NonvolatileStorage as NonvolatileStorageDriver;
+use capsules_extra::storage_provisioning_lock::{
+    ProvisioningLock, StorageProvisioningLock, PROVISIONING_LOCK,
+};
+use core::mem::MaybeUninit;
+use kernel::component::Component;
+use kernel::hil::nonvolatile_storage::NonvolatileStorage;
+use kernel::process::ShortId;
+
+#[macro_export]
+macro_rules! storage_provisioning_lock_component_static {
+    () => {{
+        let lock = kernel::static_buf!(
+            capsules_extra::storage_provisioning_lock::StorageProvisioningLock<'static>
+        );
+        let buffer = kernel::static_buf!(
+            [u8; capsules_extra::storage_provisioning_lock::PROVISIONING_LOCK.len()]
+        );
+
+        (lock, buffer)
+    };};
+}
+
+pub struct StorageProvisioningLockComponent {
+    storage: &'static dyn NonvolatileStorage<'static>,
+    address: usize,
+    provisioner: ShortId,
+}
+
+impl StorageProvisioningLockComponent {
+    /// Keep the lock at the absolute storage address `address`, and let the
+    /// app with `provisioner` write the provisioned region until it sets the
+    /// lock.
+    pub fn new(
+        storage: &'static dyn NonvolatileStorage<'static>,
+        address: usize,
+        provisioner: ShortId,
+    ) -> Self {
+        Self {
+            storage,
+            address,
+            provisioner,
+        }
+    }
+}
+
+impl Component for StorageProvisioningLockComponent {
+    type StaticInput = (
+        &'static mut MaybeUninit<StorageProvisioningLock<'static>>,
+        &'static mut MaybeUninit<[u8; PROVISIONING_LOCK.len()]>,
+    );
+    type Output = &'static StorageProvisioningLock<'static>;
+
+    fn finalize(self, static_buffer: Self::StaticInput) -> Self::Output {
+        let buffer = static_buffer.1.write([0; PROVISIONING_LOCK.len()]);
+        let lock = static_buffer.0.write(StorageProvisioningLock::new(
+            self.storage,
+            self.address,
+            self.provisioner,
+            buffer,
+        ));
+        self.storage.set_client(lock);
+
+        lock
+    }
+}
+
+pub struct ProvisioningLockDriverComponent {
+    lock: &'static StorageProvisioningLock<'static>,
+    nonvolatile_storage: &'static NonvolatileStorageDriver<'static>,
+}
+
+impl ProvisioningLockDriverComponent {
+    /// Let `nonvolatile_storage`, built on top of `lock`, use the lock for
+    /// its provisioned region.
+    pub fn new(
+        lock: &'static StorageProvisioningLock<'static>,
+        nonvolatile_storage: &'static NonvolatileStorageDriver<'static>,
+    ) -> Self {
+        Self {
+            lock,
+            nonvolatile_storage,
+        }
+    }
+}
+
+impl Component for ProvisioningLockDriverComponent {
+    type StaticInput = ();
+    type Output = ();
+
+    fn finalize(self, _static_buffer: Self::StaticInput) -> Self::Output {
+        ProvisioningLock::set_client(self.lock, self.nonvolatile_storage);
+        if let Err(e) = self.nonvolatile_storage.set_provisioning_lock(self.lock) {
+            panic!(
+                "Nonvolatile storage provisioning lock at {:#x} needs a provisioned region, \
+                 and must be in the kernel region outside the regions apps can access: {:?}",
+                self.lock.address(),
+                e,
+            );
+        }
+    }
+}
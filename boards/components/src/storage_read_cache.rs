@@ -0,0 +1,55 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2024.
+
+//! Component for a cache of small reads from nonvolatile storage.
+//!
+//! The component makes the cache the client of the storage. The arguments to
+//! the static macro are the number of entries and the largest read cached.
+//!
+//! Usage
+//! -----
+//! ```rust
+//! let read_cache = components::storage_read_cache::StorageReadCacheComponent::new(spi_nor)
+//!     .finalize(components::storage_read_cache_component_static!(8, 64));
+//! ```
+
+use capsules_extra::storage_read_cache::StorageReadCache;
+use core::mem::MaybeUninit;
+use kernel::component::Component;
+use kernel::deferred_call::DeferredCallClient;
+use kernel::hil::nonvolatile_storage::NonvolatileStorage;
+
+#[macro_export]
+macro_rules! storage_read_cache_component_static {
+    ($ENTRIES:expr, $CHUNK_LEN:expr $(,)?) => {{
+        kernel::static_buf!(
+            capsules_extra::storage_read_cache::StorageReadCache<'static, $ENTRIES, $CHUNK_LEN>
+        )
+    };};
+}
+
+pub struct StorageReadCacheComponent<const ENTRIES: usize, const CHUNK_LEN: usize> {
+    storage: &'static dyn NonvolatileStorage<'static>,
+}
+
+impl<const ENTRIES: usize, const CHUNK_LEN: usize> StorageReadCacheComponent<ENTRIES, CHUNK_LEN> {
+    pub fn new(storage: &'static dyn NonvolatileStorage<'static>) -> Self {
+        Self { storage }
+    }
+}
+
+impl<const ENTRIES: usize, const CHUNK_LEN: usize> Component
+    for StorageReadCacheComponent<ENTRIES, CHUNK_LEN>
+{
+    type StaticInput = &'static mut MaybeUninit<StorageReadCache<'static, ENTRIES, CHUNK_LEN>>;
+    type Output = &'static StorageReadCache<'static, ENTRIES, CHUNK_LEN>;
+
+    fn finalize(self, s: Self::StaticInput) -> Self::Output {
+        let read_cache = s.write(StorageReadCache::new(self.storage));
+        read_cache.register();
+        self.storage.set_client(read_cache);
+
+        read_cache
+    }
+}
@@ -0,0 +1,59 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2024.
+
+//! Component that checks every write to nonvolatile storage by reading it
+//! back.
+//!
+//! The component makes the layer the client of the storage. The argument to
+//! the static macro is the length of the buffer the data is read back into.
+//!
+//! Usage
+//! -----
+//! ```rust
+//! let verify = components::storage_verify::StorageVerifyComponent::new(nv_to_page)
+//!     .finalize(components::storage_verify_component_static!(64));
+//! ```
+
+use capsules_extra::storage_verify::StorageVerify;
+use core::mem::MaybeUninit;
+use kernel::component::Component;
+use kernel::hil::nonvolatile_storage::NonvolatileStorage;
+
+#[macro_export]
+macro_rules! storage_verify_component_static {
+    ($BUF_LEN:expr $(,)?) => {{
+        let verify = kernel::static_buf!(capsules_extra::storage_verify::StorageVerify<'static>);
+        let buffer = kernel::static_buf!([u8; $BUF_LEN]);
+
+        (verify, buffer)
+    };};
+}
+
+pub struct StorageVerifyComponent<const BUF_LEN: usize> {
+    storage: &'static dyn NonvolatileStorage<'static>,
+}
+
+impl<const BUF_LEN: usize> StorageVerifyComponent<BUF_LEN> {
+    pub fn new(storage: &'static dyn NonvolatileStorage<'static>) -> Self {
+        Self { storage }
+    }
+}
+
+impl<const BUF_LEN: usize> Component for StorageVerifyComponent<BUF_LEN> {
+    type StaticInput = (
+        &'static mut MaybeUninit<StorageVerify<'static>>,
+        &'static mut MaybeUninit<[u8; BUF_LEN]>,
+    );
+    type Output = &'static StorageVerify<'static>;
+
+    fn finalize(self, static_buffer: Self::StaticInput) -> Self::Output {
+        let buffer = static_buffer.1.write([0; BUF_LEN]);
+        let verify = static_buffer
+            .0
+            .write(StorageVerify::new(self.storage, buffer));
+        self.storage.set_client(verify);
+
+        verify
+    }
+}
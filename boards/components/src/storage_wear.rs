@@ -0,0 +1,128 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2024.
+
+//! Component that counts the bytes read from and written to each region of
+//! nonvolatile storage.
+//!
+//! `StorageWearComponent` makes the layer the client of the storage. The
+//! layer is then the storage of the nonvolatile storage driver. Once the
+//! driver is built, `StorageWearCountersComponent` gives the layer the driver
+//! and keeps the counters across reboots, storing them in the kernel region
+//! after every `batch` bytes written. Boards that do not keep the counters
+//! give the layer the driver with `set_users()` instead.
+//!
+//! Usage
+//! -----
+//! ```rust
+//! let wear = components::storage_wear::StorageWearComponent::new(nv_to_page)
+//!     .finalize(components::storage_wear_component_static!());
+//! let nonvolatile_storage = components::nonvolatile_storage::NonvolatileStorageComponent::new(
+//!     board_kernel,
+//!     capsules_extra::nonvolatile_storage_driver::DRIVER_NUM,
+//!     wear,
+//!     // ...
+//! )
+//! .finalize(components::nonvolatile_storage_component_static!());
+//! components::storage_wear::StorageWearCountersComponent::new(
+//!     wear,
+//!     nonvolatile_storage,
+//!     0x7efc0,
+//!     0x10000,
+//! )
+//! .finalize(());
+//! ```
+
+use capsules_extra::nonvolatile_storage_driver::NonvolatileStorage as NonvolatileStorageDriver;
+use capsules_extra::storage_wear::{StorageWear, WEAR_COUNTERS_LEN};
+use core::mem::MaybeUninit;
+use kernel::component::Component;
+use kernel::hil::nonvolatile_storage::NonvolatileStorage;
+
+#[macro_export]
+macro_rules! storage_wear_component_static {
+    () => {{
+        let wear = kernel::static_buf!(capsules_extra::storage_wear::StorageWear<'static>);
+        let buffer = kernel::static_buf!([u8; capsules_extra::storage_wear::WEAR_COUNTERS_LEN]);
+
+        (wear, buffer)
+    };};
+}
+
+pub struct StorageWearComponent {
+    storage: &'static dyn NonvolatileStorage<'static>,
+}
+
+impl StorageWearComponent {
+    pub fn new(storage: &'static dyn NonvolatileStorage<'static>) -> Self {
+        Self { storage }
+    }
+}
+
+impl Component for StorageWearComponent {
+    type StaticInput = (
+        &'static mut MaybeUninit<StorageWear<'static>>,
+        &'static mut MaybeUninit<[u8; WEAR_COUNTERS_LEN]>,
+    );
+    type Output = &'static StorageWear<'static>;
+
+    fn finalize(self, static_buffer: Self::StaticInput) -> Self::Output {
+        let buffer = static_buffer.1.write([0; WEAR_COUNTERS_LEN]);
+        let wear = static_buffer
+            .0
+            .write(StorageWear::new(self.storage, buffer));
+        self.storage.set_client(wear);
+
+        wear
+    }
+}
+
+pub struct StorageWearCountersComponent {
+    wear: &'static StorageWear<'static>,
+    nonvolatile_storage: &'static NonvolatileStorageDriver<'static>,
+    address: usize,
+    batch: usize,
+}
+
+impl StorageWearCountersComponent {
+    /// Keep the counters of `wear` at `address`, in the kernel region of
+    /// `nonvolatile_storage`, and store them after every `batch` bytes
+    /// written. `nonvolatile_storage` must be built on top of `wear`.
+    pub fn new(
+        wear: &'static StorageWear<'static>,
+        nonvolatile_storage: &'static NonvolatileStorageDriver<'static>,
+        address: usize,
+        batch: usize,
+    ) -> Self {
+        Self {
+            wear,
+            nonvolatile_storage,
+            address,
+            batch,
+        }
+    }
+}
+
+impl Component for StorageWearCountersComponent {
+    type StaticInput = ();
+    type Output = ();
+
+    fn finalize(self, _static_buffer: Self::StaticInput) -> Self::Output {
+        self.wear.set_users(self.nonvolatile_storage);
+        if let Err(e) = self
+            .nonvolatile_storage
+            .check_private_range(self.address, WEAR_COUNTERS_LEN)
+            .and_then(|()| self.wear.set_counters(self.address, self.batch))
+        {
+            panic!(
+                "Nonvolatile storage wear counters at {:#x}..{:#x}, stored every {:#x} bytes, \
+                 are not in the kernel region, overlap another region, are not aligned, or do \
+                 not fit the buffer of the wear counters: {:?}",
+                self.address,
+                self.address + WEAR_COUNTERS_LEN,
+                self.batch,
+                e,
+            );
+        }
+    }
+}
@@ -10,7 +10,9 @@
 #![cfg_attr(not(doc), no_main)]
 #![deny(missing_docs)]
 
-use capsules_core::test::capsule_test::{CapsuleTestClient, CapsuleTestError};
+use capsules_core::test::capsule_test::{CapsuleTest, CapsuleTestClient, CapsuleTestError};
+use capsules_extra::test::nonvolatile_storage::TestNonvolatileStorageErrors;
+use capsules_extra::test::nonvolatile_storage_latency::TestNonvolatileStorageLatency;
 use core::cell::Cell;
 use core::ptr::addr_of;
 use kernel::component::Component;
@@ -77,12 +79,20 @@ impl SyscallDriverLookup for Platform {
 struct TestLauncher {
     test_index: Cell<usize>,
     peripherals: &'static Nrf52DefaultPeripherals<'static>,
+    nonvolatile_storage_test: &'static TestNonvolatileStorageErrors<'static>,
+    nonvolatile_storage_latency_test: &'static TestNonvolatileStorageLatency<'static>,
 }
 impl TestLauncher {
-    fn new(peripherals: &'static Nrf52DefaultPeripherals<'static>) -> Self {
+    fn new(
+        peripherals: &'static Nrf52DefaultPeripherals<'static>,
+        nonvolatile_storage_test: &'static TestNonvolatileStorageErrors<'static>,
+        nonvolatile_storage_latency_test: &'static TestNonvolatileStorageLatency<'static>,
+    ) -> Self {
         Self {
             test_index: Cell::new(0),
             peripherals,
+            nonvolatile_storage_test,
+            nonvolatile_storage_latency_test,
         }
     }
 
@@ -96,6 +106,14 @@ impl TestLauncher {
             3 => unsafe { test::aes_test::run_aes128_ctr(&self.peripherals.ecb, self) },
             4 => unsafe { test::aes_test::run_aes128_cbc(&self.peripherals.ecb, self) },
             5 => unsafe { test::aes_test::run_aes128_ecb(&self.peripherals.ecb, self) },
+            6 => {
+                self.nonvolatile_storage_test.set_client(self);
+                self.nonvolatile_storage_test.run();
+            }
+            7 => {
+                self.nonvolatile_storage_latency_test.set_client(self);
+                self.nonvolatile_storage_latency_test.run();
+            }
             _ => kernel::debug!("All tests finished."),
         }
     }
@@ -227,6 +245,9 @@ pub unsafe fn main() {
         nrf52840::rtc::Rtc
     ));
 
+    // Panic messages take over the same UARTE.
+    io::set_panic_uart(&base_peripherals.uarte0);
+
     // Virtualize the UART channel for the console and for kernel debug.
     let uart_mux = components::console::UartMuxComponent::new(uart_channel, 115200)
         .finalize(components::uart_mux_component_static!());
@@ -253,7 +274,22 @@ pub unsafe fn main() {
         systick: cortexm4::systick::SysTick::new_with_calibration(64000000),
     };
 
-    let test_launcher = static_init!(TestLauncher, TestLauncher::new(base_peripherals));
+    // Tests that need grants must be created before the kernel loop starts.
+    let nonvolatile_storage_test =
+        test::nonvolatile_storage_test::static_init_test_nonvolatile_storage(board_kernel);
+    let nonvolatile_storage_latency_test =
+        test::nonvolatile_storage_latency_test::static_init_test_nonvolatile_storage_latency(
+            board_kernel,
+        );
+
+    let test_launcher = static_init!(
+        TestLauncher,
+        TestLauncher::new(
+            base_peripherals,
+            nonvolatile_storage_test,
+            nonvolatile_storage_latency_test
+        )
+    );
 
     //--------------------------------------------------------------------------
     // TESTS
@@ -0,0 +1,68 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2024.
+
+//! This tests how the nonvolatile storage driver recovers from operations
+//! that the underlying storage refuses to start. The driver allocates a grant,
+//! so the test must be created before the kernel loop starts:
+//! ```
+//! let t = test::nonvolatile_storage_test::static_init_test_nonvolatile_storage(board_kernel);
+//! ```
+
+use core::ptr::addr_of_mut;
+
+use capsules_extra::nonvolatile_storage_driver::NonvolatileStorage;
+use capsules_extra::test::nonvolatile_storage::{FaultyRamStorage, TestNonvolatileStorageErrors};
+use kernel::deferred_call::DeferredCallClient;
+use kernel::hil::nonvolatile_storage::NonvolatileStorage as NonvolatileStorageHil;
+use kernel::{capabilities, create_capability, static_init};
+
+pub static mut MEMORY: [u8; 128] = [0; 128];
+pub static mut DRIVER_BUF: [u8; 32] = [0; 32];
+pub static mut BUF1: [u8; 16] = [0; 16];
+pub static mut BUF2: [u8; 16] = [0; 16];
+
+pub unsafe fn static_init_test_nonvolatile_storage(
+    board_kernel: &'static kernel::Kernel,
+) -> &'static TestNonvolatileStorageErrors<'static> {
+    let grant_cap = create_capability!(capabilities::MemoryAllocationCapability);
+
+    let storage = static_init!(
+        FaultyRamStorage<'static>,
+        FaultyRamStorage::new(&mut *addr_of_mut!(MEMORY))
+    );
+    storage.register();
+
+    // The kernel gets the first half of the storage, userspace the second.
+    let driver = static_init!(
+        NonvolatileStorage<'static>,
+        NonvolatileStorage::new(
+            storage,
+            board_kernel.create_grant(
+                capsules_extra::nonvolatile_storage_driver::DRIVER_NUM,
+                &grant_cap
+            ),
+            64,
+            64,
+            0,
+            64,
+            &mut *addr_of_mut!(DRIVER_BUF),
+        )
+    );
+    storage.set_client(driver);
+    driver.register();
+
+    let test = static_init!(
+        TestNonvolatileStorageErrors<'static>,
+        TestNonvolatileStorageErrors::new(
+            driver,
+            storage,
+            &mut *addr_of_mut!(BUF1),
+            &mut *addr_of_mut!(BUF2)
+        )
+    );
+    driver.set_client(test);
+    test.register();
+
+    test
+}
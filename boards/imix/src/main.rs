@@ -171,6 +171,7 @@ struct Imix {
         &'static capsules_extra::nonvolatile_storage_driver::NonvolatileStorage<'static>,
     scheduler: &'static RoundRobinSched<'static>,
     systick: cortexm4::systick::SysTick,
+    context_switch_counter: &'static kernel::platform::ContextSwitchCounter,
 }
 
 impl SyscallDriverLookup for Imix {
@@ -212,7 +213,7 @@ impl KernelResources<sam4l::chip::Sam4l<Sam4lDefaultPeripherals>> for Imix {
     type Scheduler = RoundRobinSched<'static>;
     type SchedulerTimer = cortexm4::systick::SysTick;
     type WatchDog = ();
-    type ContextSwitchCallback = ();
+    type ContextSwitchCallback = kernel::platform::ContextSwitchCounter;
 
     fn syscall_driver_lookup(&self) -> &Self::SyscallDriverLookup {
         self
@@ -233,7 +234,7 @@ impl KernelResources<sam4l::chip::Sam4l<Sam4lDefaultPeripherals>> for Imix {
         &()
     }
     fn context_switch_callback(&self) -> &Self::ContextSwitchCallback {
-        &()
+        self.context_switch_counter
     }
 }
 
@@ -388,6 +389,15 @@ unsafe fn start() -> (
         sam4l::ast::Ast
     ));
 
+    // Count context switches for the process console's `scheduler` command.
+    let context_switch_counter = static_init!(
+        kernel::platform::ContextSwitchCounter,
+        kernel::platform::ContextSwitchCounter::new()
+    );
+    pconsole
+        .engine()
+        .set_context_switch_counter(context_switch_counter);
+
     let console = ConsoleOrderedComponent::new(
         board_kernel,
         capsules_core::console_ordered::DRIVER_NUM,
@@ -635,18 +645,23 @@ unsafe fn start() -> (
         static _estorage: u8;
     }
 
+    let nv_to_page = components::nonvolatile_storage::NonvolatileToPagesComponent::new(
+        &peripherals.flash_controller,
+    )
+    .finalize(components::nonvolatile_to_pages_component_static!(
+        sam4l::flashcalw::FLASHCALW
+    ));
+
     let nonvolatile_storage = components::nonvolatile_storage::NonvolatileStorageComponent::new(
         board_kernel,
         capsules_extra::nonvolatile_storage_driver::DRIVER_NUM,
-        &peripherals.flash_controller,
+        nv_to_page,
         0x60000, // Start address for userspace accessible region
         0x20000, // Length of userspace accessible region
         core::ptr::addr_of!(_sstorage) as usize, //start address of kernel region
         core::ptr::addr_of!(_estorage) as usize - core::ptr::addr_of!(_sstorage) as usize, // length of kernel region
     )
-    .finalize(components::nonvolatile_storage_component_static!(
-        sam4l::flashcalw::FLASHCALW
-    ));
+    .finalize(components::nonvolatile_storage_component_static!());
 
     let local_ip_ifaces = static_init!(
         [IPAddr; 3],
@@ -764,6 +779,7 @@ unsafe fn start() -> (
         nonvolatile_storage,
         scheduler,
         systick: cortexm4::systick::SysTick::new(),
+        context_switch_counter,
     };
 
     // Need to initialize the UART for the nRF51 serialization.
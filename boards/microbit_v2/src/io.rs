@@ -8,7 +8,6 @@ use core::panic::PanicInfo;
 use kernel::debug;
 use kernel::debug::IoWrite;
 use kernel::hil::led;
-use kernel::hil::uart;
 use nrf52833::gpio::Pin;
 use nrf52833::uart::{Uarte, UARTE0_BASE};
 
@@ -17,19 +16,10 @@ use crate::PROCESSES;
 use crate::PROCESS_PRINTER;
 
 /// Writer is used by kernel::debug to panic message to the serial port.
-pub struct Writer {
-    initialized: bool,
-}
-
-impl Writer {
-    /// Indicate that USART has already been initialized.
-    pub fn set_initialized(&mut self) {
-        self.initialized = true;
-    }
-}
+pub struct Writer {}
 
 /// Global static for debug writer
-pub static mut WRITER: Writer = Writer { initialized: false };
+pub static mut WRITER: Writer = Writer {};
 
 impl Write for Writer {
     fn write_str(&mut self, s: &str) -> ::core::fmt::Result {
@@ -40,27 +30,12 @@ impl Write for Writer {
 
 impl IoWrite for Writer {
     fn write(&mut self, buf: &[u8]) -> usize {
+        // Here, we create a second instance of the Uarte struct and take the
+        // hardware over from the kernel's instance. This is okay because we
+        // only call this during a panic, and the kernel's UART driver is
+        // never used again.
         let uart = Uarte::new(UARTE0_BASE);
-
-        use kernel::hil::uart::Configure;
-
-        if !self.initialized {
-            self.initialized = true;
-            let _ = uart.configure(uart::Parameters {
-                baud_rate: 115200,
-                stop_bits: uart::StopBits::One,
-                parity: uart::Parity::None,
-                hw_flow_control: false,
-                width: uart::Width::Eight,
-            });
-        }
-
-        unsafe {
-            for &c in buf {
-                uart.send_byte(c);
-                while !uart.tx_ready() {}
-            }
-        }
+        unsafe { uart.panic_takeover() }.write(buf);
         buf.len()
     }
 }
@@ -4,17 +4,21 @@
 
 use core::fmt::Write;
 use kernel::debug::IoWrite;
-use kernel::hil::uart;
-use kernel::hil::uart::Configure;
 
 use nrf52840::uart::{Uarte, UARTE0_BASE};
 
 enum Writer {
-    WriterUart(/* initialized */ bool),
+    WriterUart(Option<&'static Uarte<'static>>),
     WriterRtt(&'static segger::rtt::SeggerRttMemory<'static>),
 }
 
-static mut WRITER: Writer = Writer::WriterUart(false);
+static mut WRITER: Writer = Writer::WriterUart(None);
+
+/// Set the UART used to output panic messages. Until a board sets one, panic
+/// messages go to UARTE0.
+pub unsafe fn set_panic_uart(uart: &'static Uarte<'static>) {
+    WRITER = Writer::WriterUart(Some(uart));
+}
 
 /// Set the RTT memory buffer used to output panic messages.
 pub unsafe fn set_rtt_memory(rtt_memory: &'static segger::rtt::SeggerRttMemory<'static>) {
@@ -31,27 +35,19 @@ impl Write for Writer {
 impl IoWrite for Writer {
     fn write(&mut self, buf: &[u8]) -> usize {
         match self {
-            Writer::WriterUart(ref mut initialized) => {
-                // Here, we create a second instance of the Uarte struct.
-                // This is okay because we only call this during a panic, and
-                // we will never actually process the interrupts
-                let uart = Uarte::new(UARTE0_BASE);
-                if !*initialized {
-                    *initialized = true;
-                    let _ = uart.configure(uart::Parameters {
-                        baud_rate: 115200,
-                        stop_bits: uart::StopBits::One,
-                        parity: uart::Parity::None,
-                        hw_flow_control: false,
-                        width: uart::Width::Eight,
-                    });
-                }
-                for &c in buf {
-                    unsafe {
-                        uart.send_byte(c);
+            Writer::WriterUart(uart) => {
+                // If the board did not register its UARTE, create a second
+                // instance over UARTE0. Either way this is only called during
+                // a panic, so the kernel's UART driver is never used again.
+                let fallback;
+                let uart = match uart {
+                    Some(uart) => *uart,
+                    None => {
+                        fallback = Uarte::new(UARTE0_BASE);
+                        &fallback
                     }
-                    while !uart.tx_ready() {}
-                }
+                };
+                unsafe { uart.panic_takeover() }.write(buf);
             }
             Writer::WriterRtt(rtt_memory) => {
                 rtt_memory.write_sync(buf);
@@ -79,7 +75,7 @@ pub unsafe fn panic_fmt(pi: &core::panic::PanicInfo) -> ! {
     let led_kernel_pin = &nrf52840::gpio::GPIOPin::new(Pin::P0_13);
     let led = &mut led::LedLow::new(led_kernel_pin);
     let writer = &mut *addr_of_mut!(WRITER);
-    debug::panic(
+    debug::panic_with_blink_codes(
         &mut [led],
         writer,
         pi,
@@ -87,5 +83,6 @@ pub unsafe fn panic_fmt(pi: &core::panic::PanicInfo) -> ! {
         &*addr_of!(PROCESSES),
         &*addr_of!(CHIP),
         &*addr_of!(PROCESS_PRINTER),
+        &debug::PanicBlinkCodes::default(),
     )
 }
@@ -450,6 +450,9 @@ pub unsafe fn start() -> (
 
         UartChannel::Rtt(rtt_memory_refs)
     } else {
+        // Panic messages take over the UARTE the console uses.
+        self::io::set_panic_uart(&base_peripherals.uarte0);
+
         UartChannel::Pins(UartPins::new(UART_RTS, UART_TXD, UART_CTS, UART_RXD))
     };
 
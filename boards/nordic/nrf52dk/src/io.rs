@@ -7,7 +7,6 @@ use core::panic::PanicInfo;
 use kernel::debug;
 use kernel::debug::IoWrite;
 use kernel::hil::led;
-use kernel::hil::uart::{self, Configure};
 use nrf52832::gpio::Pin;
 use nrf52832::uart::{Uarte, UARTE0_BASE};
 
@@ -15,11 +14,9 @@ use crate::CHIP;
 use crate::PROCESSES;
 use crate::PROCESS_PRINTER;
 
-struct Writer {
-    initialized: bool,
-}
+struct Writer {}
 
-static mut WRITER: Writer = Writer { initialized: false };
+static mut WRITER: Writer = Writer {};
 
 impl Write for Writer {
     fn write_str(&mut self, s: &str) -> ::core::fmt::Result {
@@ -30,26 +27,12 @@ impl Write for Writer {
 
 impl IoWrite for Writer {
     fn write(&mut self, buf: &[u8]) -> usize {
-        // Here, we create a second instance of the Uarte struct.
-        // This is okay because we only call this during a panic, and
-        // we will never actually process the interrupts
+        // Here, we create a second instance of the Uarte struct and take the
+        // hardware over from the kernel's instance. This is okay because we
+        // only call this during a panic, and the kernel's UART driver is
+        // never used again.
         let uart = Uarte::new(UARTE0_BASE);
-        if !self.initialized {
-            self.initialized = true;
-            let _ = uart.configure(uart::Parameters {
-                baud_rate: 115200,
-                stop_bits: uart::StopBits::One,
-                parity: uart::Parity::None,
-                hw_flow_control: false,
-                width: uart::Width::Eight,
-            });
-        }
-        for &c in buf {
-            unsafe {
-                uart.send_byte(c);
-            }
-            while !uart.tx_ready() {}
-        }
+        unsafe { uart.panic_takeover() }.write(buf);
         buf.len()
     }
 }
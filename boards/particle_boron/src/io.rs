@@ -7,8 +7,6 @@ use core::panic::PanicInfo;
 use kernel::debug;
 use kernel::debug::IoWrite;
 use kernel::hil::led;
-use kernel::hil::uart;
-use kernel::hil::uart::Configure;
 use nrf52840::gpio::Pin;
 use nrf52840::uart::{Uarte, UARTE0_BASE};
 
@@ -18,10 +16,10 @@ use crate::PROCESS_PRINTER;
 
 // Expand here with more writing methods as required (rtt/cdc etc...)
 enum Writer {
-    WriterUart(/* initialized */ bool),
+    WriterUart,
 }
 
-static mut WRITER: Writer = Writer::WriterUart(false);
+static mut WRITER: Writer = Writer::WriterUart;
 
 impl Write for Writer {
     fn write_str(&mut self, s: &str) -> ::core::fmt::Result {
@@ -33,27 +31,13 @@ impl Write for Writer {
 impl IoWrite for Writer {
     fn write(&mut self, buf: &[u8]) -> usize {
         match self {
-            Writer::WriterUart(ref mut initialized) => {
-                // Here, we create a second instance of the Uarte struct.
-                // This is okay because we only call this during a panic, and
-                // we will never actually process the interrupts
+            Writer::WriterUart => {
+                // Here, we create a second instance of the Uarte struct and take the
+                // hardware over from the kernel's instance. This is okay because we
+                // only call this during a panic, and the kernel's UART driver is
+                // never used again.
                 let uart = Uarte::new(UARTE0_BASE);
-                if !*initialized {
-                    *initialized = true;
-                    let _ = uart.configure(uart::Parameters {
-                        baud_rate: 115200,
-                        stop_bits: uart::StopBits::One,
-                        parity: uart::Parity::None,
-                        hw_flow_control: false,
-                        width: uart::Width::Eight,
-                    });
-                }
-                for &c in buf {
-                    unsafe {
-                        uart.send_byte(c);
-                    }
-                    while !uart.tx_ready() {}
-                }
+                unsafe { uart.panic_takeover() }.write(buf);
             }
         };
         buf.len()
@@ -754,18 +754,22 @@ unsafe fn start() -> (
         static _estorage: u8;
     }
 
+    let nv_to_page =
+        components::nonvolatile_storage::NonvolatileToPagesComponent::new(&peripherals.flash)
+            .finalize(components::nonvolatile_to_pages_component_static!(
+                stm32f303xc::flash::Flash
+            ));
+
     let nonvolatile_storage = components::nonvolatile_storage::NonvolatileStorageComponent::new(
         board_kernel,
         capsules_extra::nonvolatile_storage_driver::DRIVER_NUM,
-        &peripherals.flash,
+        nv_to_page,
         0x08038000, // Start address for userspace accesible region
         0x8000,     // Length of userspace accesible region (16 pages)
         core::ptr::addr_of!(_sstorage) as usize,
         core::ptr::addr_of!(_estorage) as usize - core::ptr::addr_of!(_sstorage) as usize,
     )
-    .finalize(components::nonvolatile_storage_component_static!(
-        stm32f303xc::flash::Flash
-    ));
+    .finalize(components::nonvolatile_storage_component_static!());
 
     let process_printer = components::process_printer::ProcessPrinterTextComponent::new()
         .finalize(components::process_printer_text_component_static!());
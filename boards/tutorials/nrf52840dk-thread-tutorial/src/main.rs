@@ -165,19 +165,24 @@ pub unsafe fn main() {
     // 32kB of userspace-accessible storage, page aligned:
     kernel::storage_volume!(APP_STORAGE, 32);
 
+    let nv_to_page = components::nonvolatile_storage::NonvolatileToPagesComponent::new(
+        &nrf52840_peripherals.nrf52.nvmc,
+    )
+    .finalize(components::nonvolatile_to_pages_component_static!(
+        nrf52840::nvmc::Nvmc
+    ));
+
     let nonvolatile_storage = components::nonvolatile_storage::NonvolatileStorageComponent::new(
         board_kernel,
         capsules_extra::nonvolatile_storage_driver::DRIVER_NUM,
-        &nrf52840_peripherals.nrf52.nvmc,
+        nv_to_page,
         core::ptr::addr_of!(APP_STORAGE) as usize,
         APP_STORAGE.len(),
         // No kernel-writeable flash:
         core::ptr::null::<()>() as usize,
         0,
     )
-    .finalize(components::nonvolatile_storage_component_static!(
-        nrf52840::nvmc::Nvmc
-    ));
+    .finalize(components::nonvolatile_storage_component_static!());
 
     //--------------------------------------------------------------------------
     // PLATFORM SETUP, SCHEDULER, AND START KERNEL LOOP
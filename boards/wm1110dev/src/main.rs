@@ -444,18 +444,22 @@ pub unsafe fn start() -> (
     // NONVOLATILE STORAGE
     //--------------------------------------------------------------------------
 
+    let nv_to_page =
+        components::nonvolatile_storage::NonvolatileToPagesComponent::new(&base_peripherals.nvmc)
+            .finalize(components::nonvolatile_to_pages_component_static!(
+                nrf52840::nvmc::Nvmc
+            ));
+
     let nonvolatile_storage = components::nonvolatile_storage::NonvolatileStorageComponent::new(
         board_kernel,
         capsules_extra::nonvolatile_storage_driver::DRIVER_NUM,
-        &base_peripherals.nvmc,
+        nv_to_page,
         0xFC000,  // Start address for userspace accessible region
         4096 * 4, // Length of userspace accessible region (16 pages)
         0,        // No kernel access
         0,
     )
-    .finalize(components::nonvolatile_storage_component_static!(
-        nrf52840::nvmc::Nvmc
-    ));
+    .finalize(components::nonvolatile_storage_component_static!());
 
     //--------------------------------------------------------------------------
     // FINAL SETUP AND BOARD BOOT
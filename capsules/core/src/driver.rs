@@ -57,6 +57,13 @@ pub enum NUM {
     NvmStorage            = 0x50001,
     SdCard                = 0x50002,
     Kv                    = 0x50003,
+    OtaStaging            = 0x50004,
+    EventJournal          = 0x50005,
+    NvmStorageBatch       = 0x50006,
+    NvmStorageLowSpace    = 0x50007,
+    NvmStorageMapped      = 0x50008,
+    NvmStorageWear        = 0x50009,
+    SharedBlobs           = 0x5000A,
 
     // Sensors
     Temperature           = 0x60000,
@@ -96,5 +103,7 @@ pub enum NUM {
     DateTime              = 0x90007,
     CycleCount            = 0x90008,
     Servo                 = 0x90009,
+    DebugSnapshot         = 0x9000A,
+    ResetReason           = 0x9000B,
 }
 }
@@ -6,21 +6,62 @@
 //! a terminal to inspect and control userspace processes.
 //!
 //! For a more in-depth documentation check /doc/Process_Console.md
+//!
+//! The commands that control processes (`start`, `stop`, `fault`,
+//! `terminate`, `boot`, and `restart`) are only available once the board
+//! calls `ConsoleEngine::enable_process_control()`, and each asks for
+//! confirmation before it takes effect.
+//!
+//! The `uptime` command prints the time since the board's alarm counter
+//! started. The console counts it as it receives input, so with an alarm
+//! counter that wraps quickly, such as a 24-bit RTC, it falls behind if the
+//! console sits idle for longer than the counter takes to wrap.
+//!
+//! The `scheduler` command prints how often the kernel switched to a process
+//! and how often processes ran out of their timeslice, to help find apps that
+//! starve others. `list` shows the timeslice expirations of each process.
+//! Context switches are only counted on boards that use
+//! `kernel::platform::ContextSwitchCounter` as their `ContextSwitchCallback`
+//! and pass it to `ConsoleEngine::set_context_switch_counter()`.
+//!
+//! Boards add optional commands with capsules that implement
+//! `ConsoleCommands`, and pass them to `ConsoleEngine::add_commands()`. The
+//! commands that use nonvolatile storage (`storage` and `source`), `backup`,
+//! `watch`, and `upcall` are such capsules in `capsules_extra`. Boards that
+//! do not add them do not include their code. Added commands can print after
+//! they return, such as when a storage read completes, through the
+//! `ConsoleSession` the console hands them.
+//!
+//! A board can attach the console to several transports at once, for example
+//! a UART, RTT, and USB CDC, with one `ProcessConsole` session for each. The
+//! sessions share one `ConsoleEngine`, which runs their commands. Every
+//! session keeps its own command line, history, pending confirmation, and
+//! output queue, and only writes to its own transport, so a developer on one
+//! and a test harness on another do not see each other's input or get
+//! interleaved output. Added commands print in the session that ran them.
+//!
+//! The command line can be edited with the arrow, Home, End, and Delete keys
+//! in the forms common terminals send them, and takes UTF-8 text. Other
+//! escape sequences and invalid UTF-8 are dropped. Input that arrives while a
+//! command runs, such as a pasted block of lines, is held back and handled
+//! once the command is done.
 use core::cell::Cell;
 use core::cmp;
 use core::fmt;
 use core::fmt::write;
 use core::str;
-use kernel::capabilities::ProcessManagementCapability;
-use kernel::hil::time::ConvertTicks;
+use kernel::capabilities::{ProcessControlCapability, ProcessManagementCapability};
+use kernel::collections::list::{List, ListLink, ListNode};
+use kernel::hil::time::{ConvertTicks, Ticks};
 use kernel::utilities::cells::MapCell;
-use kernel::utilities::cells::TakeCell;
+use kernel::utilities::cells::{OptionalCell, TakeCell};
 use kernel::ProcessId;
 
 use kernel::debug;
-use kernel::hil::time::{Alarm, AlarmClient};
+use kernel::hil::time::{Alarm, AlarmClient, Frequency};
 use kernel::hil::uart;
 use kernel::introspection::KernelInfo;
+use kernel::platform::ContextSwitchCounter;
 use kernel::process::{ProcessPrinter, ProcessPrinterContext, State};
 use kernel::utilities::binary_write::BinaryWrite;
 use kernel::ErrorCode;
@@ -41,9 +82,9 @@ pub const COMMAND_BUF_LEN: usize = 32;
 pub const DEFAULT_COMMAND_HISTORY_LEN: usize = 10;
 
 /// List of valid commands for printing help. Consolidated as these are
-/// displayed in a few different cases.
+/// displayed in a few different cases. The names of added commands follow.
 const VALID_COMMANDS_STR: &[u8] =
-    b"help status list stop start fault boot terminate process kernel reset panic console-start console-stop\r\n";
+    b"help status list stop start fault boot terminate restart process kernel uptime scheduler dmesg reset panic console-start console-stop";
 
 /// Escape character for ANSI escape sequences.
 const ESC: u8 = b'\x1B';
@@ -66,8 +107,13 @@ const CR: u8 = b'\x0D';
 /// Newline ANSI character
 const NLINE: u8 = b'\x0A';
 
-/// Upper limit for ASCII characters
-const ASCII_LIMIT: u8 = 128;
+/// Number of received bytes held back while the console is busy, such as
+/// when a pasted line is being run.
+const INPUT_QUEUE_LEN: usize = 64;
+
+/// Room to leave in the output queue for echoing one input. Recalling a
+/// command from history clears and redraws the whole line.
+const INPUT_ECHO_LEN: usize = 5 * COMMAND_BUF_LEN;
 
 /// States used for state machine to allow printing large strings asynchronously
 /// across multiple calls. This reduces the size of the buffer needed to print
@@ -90,10 +136,25 @@ enum WriterState {
         index: isize,
         total: isize,
     },
+    /// Printing the queued debug output, starting with the byte at
+    /// `position` in all debug output so far.
+    DebugLog {
+        position: usize,
+    },
+}
+
+/// Batch of commands the process console is running on its own.
+#[derive(PartialEq, Eq, Copy, Clone)]
+enum ScriptState {
+    Idle,
+    /// Running the boot commands, `index` is the next one to run.
+    Boot {
+        index: usize,
+    },
 }
 
 /// Key that can be part from an escape sequence.
-#[derive(Copy, Clone)]
+#[derive(Copy, Clone, Debug, PartialEq)]
 enum EscKey {
     Up,
     Down,
@@ -104,89 +165,417 @@ enum EscKey {
     Delete,
 }
 
-/// Escape state machine to check if
-/// an escape sequence has occured
+/// Input the console acts on, decoded from received bytes.
+#[derive(Copy, Clone, Debug, PartialEq)]
+enum Input {
+    /// A printable character, either ASCII or a complete UTF-8 sequence of
+    /// the given length.
+    Text([u8; 4], usize),
+    /// A CR or LF byte.
+    Newline(u8),
+    Backspace,
+    Key(EscKey),
+}
+
+/// State of the input parser between bytes.
+#[derive(Copy, Clone)]
+enum InputState {
+    /// Not inside an escape or UTF-8 sequence.
+    Ground,
+    /// After an ESC byte.
+    Escape,
+    /// After `ESC [`, collecting the parameters of a control sequence.
+    Csi,
+    /// After `ESC O`, waiting for the key of a single shift sequence.
+    Ss3,
+    /// Inside a UTF-8 sequence, waiting for one, two or three continuation
+    /// bytes.
+    Utf8Need1,
+    Utf8Need2,
+    Utf8Need3,
+}
+
+/// Classes of bytes the input parser tells apart.
 #[derive(Copy, Clone)]
-enum EscState {
-    /// This state is reached when the character is a normal
-    /// ANSI character, and the escape sequence is bypassed.
-    Bypass,
-
-    /// This state is reached when an escape sequence
-    /// is completed, and the corresponding EscKey is processed.
-    Complete(EscKey),
-
-    /// This state is reached when an escape sequence has
-    /// just started and is waiting for the next
-    /// character to complete the sequence.
-    Started,
-
-    /// This state is reached when the escape sequence
-    /// starts with a bracket character '[' and is waiting
-    /// for the next character to determine the corresponding EscKey.
+enum ByteClass {
+    Esc,
+    Newline,
+    Backspace,
+    Delete,
+    /// Other C0 control characters, ignored.
+    Control,
+    /// `0x20..=0x2F`, intermediate bytes of a control sequence.
+    Intermediate,
+    /// `0x30..=0x3F`, parameter bytes of a control sequence.
+    Param,
+    /// `[`, which starts a control sequence after ESC.
     Bracket,
-    Bracket3,
-
-    /// This state is reached when the current character does not match
-    /// any of the expected characters in the escape sequence.
-    /// Once entered in this state, the escape sequence cannot be processed
-    /// and is waiting for an ascii alphabetic character to complete
-    /// the unrecognized sequence.
-    Unrecognized,
-
-    /// This state is reached when the escape sequence has ended with
-    /// an unrecognized character. This state waits for an ascii
-    /// alphabetic character to terminate the unrecognized sequence.
-    UnrecognizedDone,
+    /// `O`, which starts a single shift sequence after ESC.
+    LetterO,
+    /// Other `0x40..=0x7E` bytes, which end a control sequence.
+    Final,
+    /// UTF-8 continuation byte.
+    Continuation,
+    /// Lead bytes of two, three and four byte UTF-8 sequences.
+    Lead2,
+    Lead3,
+    Lead4,
+    /// Bytes that never appear in UTF-8.
+    Invalid,
 }
 
-impl EscState {
-    fn next_state(self, data: u8) -> Self {
-        use self::{
-            EscKey::{Delete, Down, End, Home, Left, Right, Up},
-            EscState::{
-                Bracket, Bracket3, Bypass, Complete, Started, Unrecognized, UnrecognizedDone,
-            },
-        };
-        match (self, data) {
-            (Bypass, ESC) | (UnrecognizedDone, ESC) | (Complete(_), ESC) => Started,
-            // This is a short-circuit.
-            // ASCII DEL and ANSI Escape Sequence "Delete" should be treated the same way.
-            (Bypass, DEL) | (UnrecognizedDone, DEL) | (Complete(_), DEL) => Complete(Delete),
-            (Bypass, _) | (UnrecognizedDone, _) | (Complete(_), _) => Bypass,
-            (Started, b'[') => Bracket,
-            (Bracket, b'A') => Complete(Up),
-            (Bracket, b'B') => Complete(Down),
-            (Bracket, b'D') => Complete(Left),
-            (Bracket, b'C') => Complete(Right),
-            (Bracket, b'H') => Complete(Home),
-            (Bracket, b'F') => Complete(End),
-            (Bracket, b'3') => Bracket3,
-            (Bracket3, b'~') => Complete(Delete),
-            _ => {
-                if EscState::terminator_esc_char(data) {
-                    UnrecognizedDone
+impl ByteClass {
+    fn of(byte: u8) -> ByteClass {
+        match byte {
+            ESC => ByteClass::Esc,
+            CR | NLINE => ByteClass::Newline,
+            BS => ByteClass::Backspace,
+            DEL => ByteClass::Delete,
+            0x00..=0x1F => ByteClass::Control,
+            0x20..=0x2F => ByteClass::Intermediate,
+            0x30..=0x3F => ByteClass::Param,
+            b'[' => ByteClass::Bracket,
+            b'O' => ByteClass::LetterO,
+            0x40..=0x7E => ByteClass::Final,
+            0x80..=0xBF => ByteClass::Continuation,
+            0xC2..=0xDF => ByteClass::Lead2,
+            0xE0..=0xEF => ByteClass::Lead3,
+            0xF0..=0xF4 => ByteClass::Lead4,
+            _ => ByteClass::Invalid,
+        }
+    }
+}
+
+/// What the input parser does with a byte.
+#[derive(Copy, Clone)]
+enum InputAction {
+    Ignore,
+    /// Insert the byte as a character.
+    Print,
+    Newline,
+    Backspace,
+    /// ASCII DEL, treated like the ANSI "Delete" key.
+    Delete,
+    /// Start an escape sequence.
+    Start,
+    /// Add a parameter byte to the control sequence.
+    Param,
+    /// End a control sequence.
+    CsiKey,
+    /// End a single shift sequence.
+    Ss3Key,
+    /// Start, continue or end a UTF-8 sequence.
+    Utf8Start,
+    Utf8Continue,
+    Utf8End,
+    /// Drop the unfinished sequence and parse the byte again from `Ground`.
+    Abort,
+}
+
+/// Next state and action for each state, by byte class.
+fn input_transition(state: InputState, class: ByteClass) -> (InputState, InputAction) {
+    use self::{
+        InputAction::{
+            Abort, Backspace, CsiKey, Delete, Ignore, Newline, Param, Print, Ss3Key, Start,
+            Utf8Continue, Utf8End, Utf8Start,
+        },
+        InputState::{Csi, Escape, Ground, Ss3, Utf8Need1, Utf8Need2, Utf8Need3},
+    };
+
+    // Columns follow the order of `ByteClass`: Esc, Newline, Backspace,
+    // Delete, Control, Intermediate, Param, Bracket, LetterO, Final,
+    // Continuation, Lead2, Lead3, Lead4, Invalid.
+    const TRANSITIONS: [[(InputState, InputAction); 15]; 7] = [
+        // Ground
+        [
+            (Escape, Start),
+            (Ground, Newline),
+            (Ground, Backspace),
+            (Ground, Delete),
+            (Ground, Ignore),
+            (Ground, Print),
+            (Ground, Print),
+            (Ground, Print),
+            (Ground, Print),
+            (Ground, Print),
+            (Ground, Ignore),
+            (Utf8Need1, Utf8Start),
+            (Utf8Need2, Utf8Start),
+            (Utf8Need3, Utf8Start),
+            (Ground, Ignore),
+        ],
+        // Escape
+        [
+            (Escape, Start),
+            (Ground, Abort),
+            (Ground, Abort),
+            (Ground, Abort),
+            (Ground, Abort),
+            (Ground, Ignore),
+            (Ground, Ignore),
+            (Csi, Ignore),
+            (Ss3, Ignore),
+            (Ground, Ignore),
+            (Ground, Abort),
+            (Ground, Abort),
+            (Ground, Abort),
+            (Ground, Abort),
+            (Ground, Abort),
+        ],
+        // Csi
+        [
+            (Escape, Start),
+            (Ground, Abort),
+            (Ground, Abort),
+            (Ground, Abort),
+            (Ground, Abort),
+            (Csi, Ignore),
+            (Csi, Param),
+            (Ground, CsiKey),
+            (Ground, CsiKey),
+            (Ground, CsiKey),
+            (Ground, Abort),
+            (Ground, Abort),
+            (Ground, Abort),
+            (Ground, Abort),
+            (Ground, Abort),
+        ],
+        // Ss3
+        [
+            (Escape, Start),
+            (Ground, Abort),
+            (Ground, Abort),
+            (Ground, Abort),
+            (Ground, Abort),
+            (Ground, Ignore),
+            (Ground, Ignore),
+            (Ground, Ss3Key),
+            (Ground, Ss3Key),
+            (Ground, Ss3Key),
+            (Ground, Abort),
+            (Ground, Abort),
+            (Ground, Abort),
+            (Ground, Abort),
+            (Ground, Abort),
+        ],
+        // Utf8Need1
+        [
+            (Ground, Abort),
+            (Ground, Abort),
+            (Ground, Abort),
+            (Ground, Abort),
+            (Ground, Abort),
+            (Ground, Abort),
+            (Ground, Abort),
+            (Ground, Abort),
+            (Ground, Abort),
+            (Ground, Abort),
+            (Ground, Utf8End),
+            (Ground, Abort),
+            (Ground, Abort),
+            (Ground, Abort),
+            (Ground, Abort),
+        ],
+        // Utf8Need2
+        [
+            (Ground, Abort),
+            (Ground, Abort),
+            (Ground, Abort),
+            (Ground, Abort),
+            (Ground, Abort),
+            (Ground, Abort),
+            (Ground, Abort),
+            (Ground, Abort),
+            (Ground, Abort),
+            (Ground, Abort),
+            (Utf8Need1, Utf8Continue),
+            (Ground, Abort),
+            (Ground, Abort),
+            (Ground, Abort),
+            (Ground, Abort),
+        ],
+        // Utf8Need3
+        [
+            (Ground, Abort),
+            (Ground, Abort),
+            (Ground, Abort),
+            (Ground, Abort),
+            (Ground, Abort),
+            (Ground, Abort),
+            (Ground, Abort),
+            (Ground, Abort),
+            (Ground, Abort),
+            (Ground, Abort),
+            (Utf8Need2, Utf8Continue),
+            (Ground, Abort),
+            (Ground, Abort),
+            (Ground, Abort),
+            (Ground, Abort),
+        ],
+    ];
+
+    TRANSITIONS[state as usize][class as usize]
+}
+
+/// Parser turning received bytes into `Input`s.
+///
+/// Escape sequences are parsed in full, so unknown ones are dropped rather
+/// than typed into the command. Invalid UTF-8 is dropped as well.
+#[derive(Copy, Clone)]
+struct InputParser {
+    state: InputState,
+    /// First numeric parameter of the control sequence.
+    param: u16,
+    /// Whether the first parameter has ended.
+    param_done: bool,
+    /// Bytes of the UTF-8 sequence so far.
+    utf8: [u8; 4],
+    utf8_len: usize,
+}
+
+impl InputParser {
+    const fn new() -> Self {
+        InputParser {
+            state: InputState::Ground,
+            param: 0,
+            param_done: false,
+            utf8: [0; 4],
+            utf8_len: 0,
+        }
+    }
+
+    fn next(&mut self, byte: u8) -> Option<Input> {
+        let (state, action) = input_transition(self.state, ByteClass::of(byte));
+        self.state = state;
+        match action {
+            InputAction::Ignore => None,
+            InputAction::Print => Some(Input::Text([byte, 0, 0, 0], 1)),
+            InputAction::Newline => Some(Input::Newline(byte)),
+            InputAction::Backspace => Some(Input::Backspace),
+            InputAction::Delete => Some(Input::Key(EscKey::Delete)),
+            InputAction::Start => {
+                self.param = 0;
+                self.param_done = false;
+                None
+            }
+            InputAction::Param => {
+                if byte.is_ascii_digit() && !self.param_done {
+                    self.param = self
+                        .param
+                        .saturating_mul(10)
+                        .saturating_add((byte - b'0') as u16);
                 } else {
-                    Unrecognized
+                    self.param_done = true;
                 }
+                None
+            }
+            InputAction::CsiKey => match (byte, self.param) {
+                (b'~', 1 | 7) => Some(EscKey::Home),
+                (b'~', 4 | 8) => Some(EscKey::End),
+                (b'~', 3) => Some(EscKey::Delete),
+                (b'~', _) => None,
+                (key, _) => Self::cursor_key(key),
+            }
+            .map(Input::Key),
+            InputAction::Ss3Key => Self::cursor_key(byte).map(Input::Key),
+            InputAction::Utf8Start => {
+                self.utf8[0] = byte;
+                self.utf8_len = 1;
+                None
             }
+            InputAction::Utf8Continue => {
+                self.utf8[self.utf8_len] = byte;
+                self.utf8_len += 1;
+                None
+            }
+            InputAction::Utf8End => {
+                self.utf8[self.utf8_len] = byte;
+                self.utf8_len += 1;
+                // The table checks the shape of the sequence, this rejects
+                // overlong encodings and surrogates.
+                str::from_utf8(&self.utf8[..self.utf8_len])
+                    .ok()
+                    .map(|_| Input::Text(self.utf8, self.utf8_len))
+            }
+            InputAction::Abort => self.next(byte),
+        }
+    }
+
+    /// Key for the final byte of `ESC [ x` or `ESC O x`.
+    fn cursor_key(byte: u8) -> Option<EscKey> {
+        match byte {
+            b'A' => Some(EscKey::Up),
+            b'B' => Some(EscKey::Down),
+            b'C' => Some(EscKey::Right),
+            b'D' => Some(EscKey::Left),
+            b'H' => Some(EscKey::Home),
+            b'F' => Some(EscKey::End),
+            _ => None,
         }
     }
+}
+
+/// Whether `byte` continues a UTF-8 character rather than starting one.
+fn is_utf8_continuation(byte: u8) -> bool {
+    byte & 0xC0 == 0x80
+}
+
+/// Number of characters, and so terminal columns, in `bytes`.
+fn columns(bytes: &[u8]) -> usize {
+    bytes.iter().filter(|b| !is_utf8_continuation(**b)).count()
+}
+
+/// Start of the character before `pos` in `command`, or `None` at the start
+/// of the command.
+fn char_before(command: &[u8], pos: usize) -> Option<usize> {
+    let mut start = pos.checked_sub(1)?;
+    while start > 0 && is_utf8_continuation(command[start]) {
+        start -= 1;
+    }
+    Some(start)
+}
+
+/// End of the character at `pos` in `command`, which ends at `end`.
+fn char_after(command: &[u8], pos: usize, end: usize) -> usize {
+    let mut next = pos + 1;
+    while next < end && is_utf8_continuation(command[next]) {
+        next += 1;
+    }
+    next
+}
 
-    /// Checks if the escape state machine is in the middle
-    /// of an escape sequence
-    fn in_progress(&self) -> bool {
-        matches!(self, EscState::Bracket) || matches!(self, EscState::Bracket3)
+/// Received bytes waiting for the console to catch up.
+struct InputQueue {
+    buf: [u8; INPUT_QUEUE_LEN],
+    start: usize,
+    len: usize,
+}
+
+impl InputQueue {
+    const fn new() -> Self {
+        InputQueue {
+            buf: [0; INPUT_QUEUE_LEN],
+            start: 0,
+            len: 0,
+        }
     }
 
-    /// Checks if the escape state machine is at the start
-    /// of processing an escape sequence
-    fn has_started(&self) -> bool {
-        matches!(self, EscState::Started)
+    /// Add a byte, dropping it if the queue is full.
+    fn push(&mut self, byte: u8) {
+        if self.len < INPUT_QUEUE_LEN {
+            self.buf[(self.start + self.len) % INPUT_QUEUE_LEN] = byte;
+            self.len += 1;
+        }
     }
 
-    fn terminator_esc_char(data: u8) -> bool {
-        data.is_ascii_alphabetic() || data == b'~'
+    fn pop(&mut self) -> Option<u8> {
+        if self.len == 0 {
+            return None;
+        }
+        let byte = self.buf[self.start];
+        self.start = (self.start + 1) % INPUT_QUEUE_LEN;
+        self.len -= 1;
+        Some(byte)
     }
 }
 
@@ -221,15 +610,133 @@ enum ProcessConsoleState {
     Hibernating,
 }
 
+/// What happened to a command passed to `ConsoleCommands::run()`.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum CommandStatus {
+    /// The command is not one of these commands.
+    Unknown,
+    /// The command ran, and the console can show the prompt.
+    Done,
+    /// The command keeps running. The prompt waits until the command calls
+    /// `ConsoleSession::command_done()`.
+    Running,
+}
+
+/// A session of the process console, as the commands added to it see it.
+pub trait ConsoleSession {
+    /// Print `bytes`. Bytes that do not fit in the output queue are dropped.
+    fn write(&self, bytes: &[u8]);
+
+    /// Print formatted text, such as from `format_args!()`.
+    fn print(&self, args: fmt::Arguments) {
+        let mut console_writer = ConsoleWriter::new();
+        let _ = write(&mut console_writer, args);
+        self.write(console_writer.as_bytes());
+    }
+
+    /// Show the prompt again, because a command that returned
+    /// `CommandStatus::Running` is done, or because a script stopped.
+    fn command_done(&self);
+
+    /// Echo `command` and run it as if it had been typed at the prompt. Only
+    /// call it while the console is not busy.
+    fn run_command(&self, command: &[u8]);
+
+    /// Whether the console is busy: it is not active, output or a command is
+    /// pending, someone is typing, or boot commands are still running. A
+    /// command waiting for confirmation does not make it busy, so scripts can
+    /// answer it.
+    fn is_busy(&self) -> bool;
+}
+
+/// Commands a board adds to the process console with
+/// `ConsoleEngine::add_commands()`. All sessions of the console can run them,
+/// and each call is passed the session it is about.
+pub trait ConsoleCommands<'a>: 'a {
+    /// Names of the commands, separated by spaces, for the help text.
+    fn names(&self) -> &'static str;
+
+    /// Start `command`, the trimmed command line, in `session` if it is one
+    /// of these commands. Only return `CommandStatus::Running` if the
+    /// command will call `command_done()` later.
+    fn run(&self, session: &'a dyn ConsoleSession, command: &str) -> CommandStatus;
+
+    /// `session` transmitted all its output and no command of the console
+    /// itself is running. Commands that print more than fits in the output
+    /// queue, or that run other commands, continue here.
+    fn output_drained(&self, _session: &'a dyn ConsoleSession) {}
+
+    /// A key was pressed in `session`. Returns `true` if the key stopped one
+    /// of these commands, and is not part of the next command.
+    fn key_pressed(&self, _session: &'a dyn ConsoleSession) -> bool {
+        false
+    }
+
+    fn next_commands(&'a self) -> &'a ListLink<'a, dyn ConsoleCommands<'a>>;
+}
+
+impl<'a> ListNode<'a, dyn ConsoleCommands<'a>> for dyn ConsoleCommands<'a> {
+    fn next(&'a self) -> &'a ListLink<'a, dyn ConsoleCommands<'a>> {
+        self.next_commands()
+    }
+}
+
+/// The commands of the process console and the kernel state they use, shared
+/// by all sessions of the console.
+pub struct ConsoleEngine<'a, A: Alarm<'a>, C: ProcessManagementCapability> {
+    alarm: &'a A,
+    process_printer: &'a dyn ProcessPrinter,
+
+    /// Reference to the kernel object so we can access process state.
+    kernel: &'static Kernel,
+
+    /// Memory addresses of where the kernel is placed in memory on chip.
+    kernel_addresses: KernelAddresses,
+
+    /// Function used to reset the device in bootloader mode
+    reset_function: Option<fn() -> !>,
+
+    /// This capsule needs to use potentially dangerous APIs related to
+    /// processes, and requires a capability to access those APIs.
+    capability: C,
+
+    /// Alarm ticks counted towards the uptime, and the value of the alarm
+    /// counter when they were last counted.
+    uptime_ticks: Cell<u64>,
+    uptime_last: Cell<A::Ticks>,
+
+    /// Counts the context switches for the `scheduler` command, if the board
+    /// counts them.
+    context_switch_counter: OptionalCell<&'a ContextSwitchCounter>,
+    /// Whether commands that control processes are allowed.
+    process_control: Cell<bool>,
+    /// Commands the board added.
+    commands: List<'a, dyn ConsoleCommands<'a>>,
+}
+
+/// What a session does once the engine ran a command.
+enum Outcome {
+    /// The command is done.
+    Done,
+    /// The command prints more with the writer state machine.
+    Print(WriterState),
+    /// The command waits for the user to confirm it.
+    Confirm(PendingControl),
+    /// An added command keeps running until it calls `command_done()`.
+    Running,
+}
+
+/// A session of the process console on one transport, with its own command
+/// line, history, and output queue.
 pub struct ProcessConsole<
     'a,
     const COMMAND_HISTORY_LEN: usize,
     A: Alarm<'a>,
     C: ProcessManagementCapability,
 > {
+    engine: &'a ConsoleEngine<'a, A, C>,
     uart: &'a dyn uart::UartData<'a>,
     alarm: &'a A,
-    process_printer: &'a dyn ProcessPrinter,
     tx_in_progress: Cell<bool>,
     tx_buffer: TakeCell<'static, [u8]>,
     queue_buffer: TakeCell<'static, [u8]>,
@@ -243,8 +750,11 @@ pub struct ProcessConsole<
     /// responding to commands.
     mode: Cell<ProcessConsoleState>,
 
-    /// Escape state machine in order to process an escape sequence
-    esc_state: Cell<EscState>,
+    /// Parser for received bytes, which handles escape sequences and UTF-8.
+    input_parser: Cell<InputParser>,
+
+    /// Bytes received while the console was busy.
+    input_queue: MapCell<InputQueue>,
 
     /// Keep a history of inserted commands
     command_history: MapCell<CommandHistory<'static, COMMAND_HISTORY_LEN>>,
@@ -260,18 +770,60 @@ pub struct ProcessConsole<
     /// received after finishing echoing the last newline character.
     execute: Cell<bool>,
 
-    /// Reference to the kernel object so we can access process state.
-    kernel: &'static Kernel,
+    /// Commands to run once the console has started.
+    boot_commands: OptionalCell<&'static [&'static str]>,
+    /// Script currently being run.
+    script_state: Cell<ScriptState>,
+    /// Process control command that runs if the user confirms it.
+    pending_control: OptionalCell<PendingControl>,
+    /// Whether an added command is running.
+    command_running: Cell<bool>,
+    /// This console, handed to added commands so they can print after they
+    /// return.
+    session: OptionalCell<&'a dyn ConsoleSession>,
+}
+/// Commands that change the state of a process.
+#[derive(Copy, Clone)]
+enum ControlAction {
+    Start,
+    Stop,
+    Fault,
+    Terminate,
+    Boot,
+    Restart,
+}
 
-    /// Memory addresses of where the kernel is placed in memory on chip.
-    kernel_addresses: KernelAddresses,
+impl ControlAction {
+    fn parse(command: &str) -> Option<ControlAction> {
+        match command {
+            "start" => Some(ControlAction::Start),
+            "stop" => Some(ControlAction::Stop),
+            "fault" => Some(ControlAction::Fault),
+            "terminate" => Some(ControlAction::Terminate),
+            "boot" => Some(ControlAction::Boot),
+            "restart" => Some(ControlAction::Restart),
+            _ => None,
+        }
+    }
 
-    /// Function used to reset the device in bootloader mode
-    reset_function: Option<fn() -> !>,
+    fn name(self) -> &'static str {
+        match self {
+            ControlAction::Start => "Start",
+            ControlAction::Stop => "Stop",
+            ControlAction::Fault => "Fault",
+            ControlAction::Terminate => "Terminate",
+            ControlAction::Boot => "Boot",
+            ControlAction::Restart => "Restart",
+        }
+    }
+}
 
-    /// This capsule needs to use potentially dangerous APIs related to
-    /// processes, and requires a capability to access those APIs.
-    capability: C,
+/// A process control command waiting for the user to confirm it.
+#[derive(Copy, Clone)]
+struct PendingControl {
+    action: ControlAction,
+    process_name: [u8; COMMAND_BUF_LEN],
+    len: usize,
 }
 
 #[derive(Copy, Clone)]
@@ -293,28 +845,6 @@ impl Command {
         (self.buf).copy_from_slice(buf);
     }
 
-    fn insert_byte(&mut self, byte: u8, pos: usize) {
-        for i in (pos..self.len).rev() {
-            self.buf[i + 1] = self.buf[i];
-        }
-
-        if let Some(buf_byte) = self.buf.get_mut(pos) {
-            *buf_byte = byte;
-            self.len += 1;
-        }
-    }
-
-    fn delete_byte(&mut self, pos: usize) {
-        for i in pos..self.len {
-            self.buf[i] = self.buf[i + 1];
-        }
-
-        if let Some(buf_byte) = self.buf.get_mut(self.len - 1) {
-            *buf_byte = EOL;
-            self.len -= 1;
-        }
-    }
-
     fn clear(&mut self) {
         self.buf.iter_mut().for_each(|x| *x = EOL);
         self.len = 0;
@@ -398,6 +928,14 @@ impl<'a, const COMMAND_HISTORY_LEN: usize> CommandHistory<'a, COMMAND_HISTORY_LE
     }
 }
 
+/// Parse a decimal or `0x` prefixed hexadecimal number.
+pub fn parse_number(s: &str) -> Option<usize> {
+    match s.strip_prefix("0x") {
+        Some(hex) => usize::from_str_radix(hex, 16).ok(),
+        None => s.parse().ok(),
+    }
+}
+
 pub struct ConsoleWriter {
     buf: [u8; 500],
     size: usize,
@@ -412,6 +950,11 @@ impl ConsoleWriter {
     pub fn clear(&mut self) {
         self.size = 0;
     }
+
+    /// The bytes written so far.
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.buf[..self.size]
+    }
 }
 impl fmt::Write for ConsoleWriter {
     fn write_str(&mut self, s: &str) -> fmt::Result {
@@ -433,99 +976,120 @@ impl BinaryWrite for ConsoleWriter {
     }
 }
 
-impl<'a, const COMMAND_HISTORY_LEN: usize, A: Alarm<'a>, C: ProcessManagementCapability>
-    ProcessConsole<'a, COMMAND_HISTORY_LEN, A, C>
-{
+impl<'a, A: Alarm<'a>, C: ProcessManagementCapability> ConsoleEngine<'a, A, C> {
+    /// `alarm` counts the uptime. It can be the alarm of a session.
     pub fn new(
-        uart: &'a dyn uart::UartData<'a>,
         alarm: &'a A,
         process_printer: &'a dyn ProcessPrinter,
-        tx_buffer: &'static mut [u8],
-        rx_buffer: &'static mut [u8],
-        queue_buffer: &'static mut [u8],
-        cmd_buffer: &'static mut [u8],
-        cmd_history_buffer: &'static mut [Command; COMMAND_HISTORY_LEN],
         kernel: &'static Kernel,
         kernel_addresses: KernelAddresses,
         reset_function: Option<fn() -> !>,
         capability: C,
-    ) -> ProcessConsole<'a, COMMAND_HISTORY_LEN, A, C> {
-        ProcessConsole {
-            uart,
+    ) -> ConsoleEngine<'a, A, C> {
+        ConsoleEngine {
             alarm,
             process_printer,
-            tx_in_progress: Cell::new(false),
-            tx_buffer: TakeCell::new(tx_buffer),
-            queue_buffer: TakeCell::new(queue_buffer),
-            queue_size: Cell::new(0),
-            writer_state: Cell::new(WriterState::Empty),
-            rx_buffer: TakeCell::new(rx_buffer),
-            command_buffer: TakeCell::new(cmd_buffer),
-            command_index: Cell::new(0),
-            mode: Cell::new(ProcessConsoleState::Off),
-            esc_state: Cell::new(EscState::Bypass),
-            command_history: MapCell::new(CommandHistory::new(cmd_history_buffer)),
-            cursor: Cell::new(0),
-            previous_byte: Cell::new(EOL),
-            execute: Cell::new(false),
             kernel,
             kernel_addresses,
             reset_function,
             capability,
+            uptime_ticks: Cell::new(0),
+            uptime_last: Cell::new(A::Ticks::from(0)),
+            context_switch_counter: OptionalCell::empty(),
+            process_control: Cell::new(false),
+            commands: List::new(),
         }
     }
 
-    /// Start the process console listening for user commands.
-    pub fn start(&self) -> Result<(), ErrorCode> {
-        if self.mode.get() == ProcessConsoleState::Off {
-            self.alarm
-                .set_alarm(self.alarm.now(), self.alarm.ticks_from_ms(100));
-            self.mode.set(ProcessConsoleState::Active);
-        }
-        Ok(())
+    /// Allow the commands that start, stop, fault, terminate, and restart
+    /// processes.
+    pub fn enable_process_control(&self, _capability: &dyn ProcessControlCapability) {
+        self.process_control.set(true);
     }
 
-    /// Start the process console listening but in a hibernated state.
-    ///
-    /// The process console will not respond to commands, but can be activated
-    /// with the `console-start` command.
-    pub fn start_hibernated(&self) -> Result<(), ErrorCode> {
-        if self.mode.get() == ProcessConsoleState::Off {
-            self.alarm
-                .set_alarm(self.alarm.now(), self.alarm.ticks_from_ms(100));
-            self.mode.set(ProcessConsoleState::Hibernating)
-        }
-        Ok(())
+    /// Report the context switches counted by `counter` in the `scheduler`
+    /// command.
+    pub fn set_context_switch_counter(&self, counter: &'a ContextSwitchCounter) {
+        self.context_switch_counter.set(counter);
     }
 
-    /// Print base information about the kernel version installed and the help
-    /// message.
-    pub fn display_welcome(&self) {
-        // Start if not already started.
-        if self.mode.get() == ProcessConsoleState::Off {
-            self.rx_buffer.take().map(|buffer| {
-                let _ = self.uart.receive_buffer(buffer, 1);
-                self.mode.set(ProcessConsoleState::Active);
+    // Run a process control command the user confirmed in `session`.
+    fn run_control(&self, session: &dyn ConsoleSession, pending: PendingControl) {
+        let name = match str::from_utf8(&pending.process_name[..pending.len]) {
+            Ok(name) => name,
+            Err(_) => return,
+        };
+        self.kernel
+            .process_each_capability(&self.capability, |proc| {
+                let proc_name = proc.get_process_name();
+                if proc_name != name {
+                    return;
+                }
+
+                let mut console_writer = ConsoleWriter::new();
+                match pending.action {
+                    ControlAction::Start => {
+                        proc.resume();
+                        let _ = write(
+                            &mut console_writer,
+                            format_args!("Process {} resumed.\r\n", name),
+                        );
+                    }
+                    ControlAction::Stop => {
+                        proc.stop();
+                        let _ = write(
+                            &mut console_writer,
+                            format_args!("Process {} stopped\r\n", proc_name),
+                        );
+                    }
+                    ControlAction::Fault => {
+                        proc.set_fault_state();
+                        let _ = write(
+                            &mut console_writer,
+                            format_args!("Process {} now faulted\r\n", proc_name),
+                        );
+                    }
+                    ControlAction::Terminate => {
+                        proc.terminate(None);
+                        let _ = write(
+                            &mut console_writer,
+                            format_args!("Process {} terminated\r\n", proc_name),
+                        );
+                    }
+                    ControlAction::Boot => {
+                        if proc.get_state() == State::Terminated {
+                            proc.try_restart(None);
+                        }
+                    }
+                    ControlAction::Restart => {
+                        proc.try_restart(None);
+                        let _ = write(
+                            &mut console_writer,
+                            format_args!("Process {} restarted\r\n", proc_name),
+                        );
+                    }
+                }
+
+                session.write(console_writer.as_bytes());
             });
-        }
+    }
 
-        // Display pconsole info.
-        let mut console_writer = ConsoleWriter::new();
-        let _ = write(
-            &mut console_writer,
-            format_args!(
-                "Kernel version: {}.{} (build {})\r\n",
-                kernel::KERNEL_MAJOR_VERSION,
-                kernel::KERNEL_MINOR_VERSION,
-                option_env!("TOCK_KERNEL_VERSION").unwrap_or("unknown"),
-            ),
-        );
-        let _ = self.write_bytes(&(console_writer.buf)[..console_writer.size]);
+    /// Add `commands` to all sessions of the console. Their names follow the
+    /// commands of the console itself in the help text.
+    pub fn add_commands(&self, commands: &'a dyn ConsoleCommands<'a>) {
+        self.commands.push_tail(commands);
+    }
 
-        let _ = self.write_bytes(b"Welcome to the process console.\r\n");
-        let _ = self.write_bytes(b"Valid commands are: ");
-        let _ = self.write_bytes(VALID_COMMANDS_STR);
-        self.prompt();
+    /// Print the names of the commands of the console and of the added
+    /// commands in `session`.
+    fn write_valid_commands(&self, session: &dyn ConsoleSession) {
+        session.write(b"Valid commands are: ");
+        session.write(VALID_COMMANDS_STR);
+        for commands in self.commands.iter() {
+            session.write(b" ");
+            session.write(commands.names().as_bytes());
+        }
+        session.write(b"\r\n");
     }
 
     /// Simple state machine helper function that identifies the next state for
@@ -557,12 +1121,16 @@ impl<'a, const COMMAND_HISTORY_LEN: usize, A: Alarm<'a>, C: ProcessManagementCap
                     }
                 }
             }
+            WriterState::DebugLog { position } => WriterState::DebugLog { position },
             WriterState::Empty => WriterState::Empty,
         }
     }
 
-    /// Create the debug message for each state in the state machine.
-    fn create_state_buffer(&self, state: WriterState) {
+    /// Print the next part of a long output in `session`, and return the
+    /// state to continue from once it has been transmitted. The output is
+    /// done once that is `WriterState::Empty`.
+    fn print_state(&self, session: &dyn ConsoleSession, state: WriterState) -> WriterState {
+        let state = self.next_state(state);
         match state {
             WriterState::KernelBss => {
                 let mut console_writer = ConsoleWriter::new();
@@ -582,7 +1150,7 @@ impl<'a, const COMMAND_HISTORY_LEN: usize, A: Alarm<'a>, C: ProcessManagementCap
                     ),
                 );
 
-                let _ = self.write_bytes(&(console_writer.buf)[..console_writer.size]);
+                session.write(console_writer.as_bytes());
             }
             WriterState::KernelInit => {
                 let mut console_writer = ConsoleWriter::new();
@@ -600,7 +1168,7 @@ impl<'a, const COMMAND_HISTORY_LEN: usize, A: Alarm<'a>, C: ProcessManagementCap
                         relocate_end, relocate_size
                     ),
                 );
-                let _ = self.write_bytes(&(console_writer.buf)[..console_writer.size]);
+                session.write(console_writer.as_bytes());
             }
             WriterState::KernelStack => {
                 let mut console_writer = ConsoleWriter::new();
@@ -619,7 +1187,7 @@ impl<'a, const COMMAND_HISTORY_LEN: usize, A: Alarm<'a>, C: ProcessManagementCap
                         stack_end, stack_size, stack_start
                     ),
                 );
-                let _ = self.write_bytes(&(console_writer.buf)[..console_writer.size]);
+                session.write(console_writer.as_bytes());
             }
             WriterState::KernelRoData => {
                 let mut console_writer = ConsoleWriter::new();
@@ -638,7 +1206,7 @@ impl<'a, const COMMAND_HISTORY_LEN: usize, A: Alarm<'a>, C: ProcessManagementCap
                         text_end, rodata_size
                     ),
                 );
-                let _ = self.write_bytes(&(console_writer.buf)[..console_writer.size]);
+                session.write(console_writer.as_bytes());
             }
             WriterState::KernelText => {
                 let mut console_writer = ConsoleWriter::new();
@@ -658,12 +1226,13 @@ impl<'a, const COMMAND_HISTORY_LEN: usize, A: Alarm<'a>, C: ProcessManagementCap
                         code_end, code_size, code_start
                     ),
                 );
-                let _ = self.write_bytes(&(console_writer.buf)[..console_writer.size]);
+                session.write(console_writer.as_bytes());
             }
             WriterState::ProcessPrint {
                 process_id,
                 context,
             } => {
+                let mut next = state;
                 self.kernel
                     .process_each_capability(&self.capability, |process| {
                         if process_id == process.processid() {
@@ -674,22 +1243,19 @@ impl<'a, const COMMAND_HISTORY_LEN: usize, A: Alarm<'a>, C: ProcessManagementCap
                                 context,
                             );
 
-                            let _ = self.write_bytes(&(console_writer.buf)[..console_writer.size]);
+                            session.write(console_writer.as_bytes());
 
-                            if new_context.is_some() {
-                                self.writer_state.replace(WriterState::ProcessPrint {
+                            next = if new_context.is_some() {
+                                WriterState::ProcessPrint {
                                     process_id,
                                     context: new_context,
-                                });
+                                }
                             } else {
-                                self.writer_state.replace(WriterState::Empty);
-                                // As setting the next state here to Empty does not
-                                // go through this match again before reading a new command,
-                                // we have to print the prompt here.
-                                self.prompt();
-                            }
+                                WriterState::Empty
+                            };
                         }
                     });
+                return next;
             }
             WriterState::List { index, total: _ } => {
                 let mut local_index = -1;
@@ -737,27 +1303,400 @@ impl<'a, const COMMAND_HISTORY_LEN: usize, A: Alarm<'a>, C: ProcessManagementCap
                                 ),
                             );
 
-                            let _ = self.write_bytes(&(console_writer.buf)[..console_writer.size]);
+                            session.write(console_writer.as_bytes());
                         }
                     });
             }
-            WriterState::Empty => {
-                self.prompt();
+            WriterState::DebugLog { position } => {
+                // The debug writer may transmit some of the output while it
+                // is printed here. That part is skipped.
+                let mut console_writer = ConsoleWriter::new();
+                let (next, count) =
+                    kernel::debug::debug_snapshot_at(position, &mut console_writer.buf);
+                if count > 0 {
+                    session.write(&(console_writer.buf)[..count]);
+                    return WriterState::DebugLog { position: next };
+                } else {
+                    session.write(b"\r\n");
+                    return WriterState::Empty;
+                }
             }
             _ => {}
         }
+        state
     }
 
-    // Process the command in the command buffer and clear the buffer.
-    fn read_command(&self) {
-        self.command_buffer.map(|command| {
-            let terminator = command.iter().position(|&x| x == 0).unwrap_or(0);
+    /// Run `command`, the trimmed command line, in `session`.
+    fn run(&self, session: &'a dyn ConsoleSession, command: &str) -> Outcome {
+        if let Some(action) = command
+            .split_whitespace()
+            .next()
+            .and_then(ControlAction::parse)
+        {
+            let name = match command.split_whitespace().nth(1) {
+                Some(name) => name,
+                None => return Outcome::Done,
+            };
+            if !self.process_control.get() {
+                session.write(b"Process control is disabled.\r\n");
+                return Outcome::Done;
+            }
 
-            // A command is valid only if it starts inside the buffer,
-            // ends before the beginning of the buffer, and ends after
-            // it starts.
-            if terminator > 0 {
-                let cmd_str = str::from_utf8(&command[0..terminator]);
+            let mut pending = PendingControl {
+                action,
+                process_name: [0; COMMAND_BUF_LEN],
+                len: name.len(),
+            };
+            pending.process_name[..name.len()].copy_from_slice(name.as_bytes());
+
+            let mut console_writer = ConsoleWriter::new();
+            let _ = write(
+                &mut console_writer,
+                format_args!("{} process {}? Type y to confirm.\r\n", action.name(), name),
+            );
+            session.write(console_writer.as_bytes());
+            Outcome::Confirm(pending)
+        } else if command.starts_with("list") {
+            session.write(b" PID    ShortID    Name                Quanta  ");
+            session.write(b"Syscalls  Restarts  Grants  State\r\n");
+
+            // Count the number of current processes.
+            let mut count = 0;
+            self.kernel.process_each_capability(&self.capability, |_| {
+                count += 1;
+            });
+
+            if count > 0 {
+                // Start the state machine to print each separately.
+                Outcome::Print(WriterState::List {
+                    index: -1,
+                    total: count,
+                })
+            } else {
+                Outcome::Done
+            }
+        } else if command.starts_with("status") {
+            let info: KernelInfo = KernelInfo::new(self.kernel);
+            let mut console_writer = ConsoleWriter::new();
+            let _ = write(
+                &mut console_writer,
+                format_args!(
+                    "Total processes: {}\r\n",
+                    info.number_loaded_processes(&self.capability)
+                ),
+            );
+            session.write(console_writer.as_bytes());
+            console_writer.clear();
+            let _ = write(
+                &mut console_writer,
+                format_args!(
+                    "Active processes: {}\r\n",
+                    info.number_active_processes(&self.capability)
+                ),
+            );
+            session.write(console_writer.as_bytes());
+            console_writer.clear();
+            let _ = write(
+                &mut console_writer,
+                format_args!(
+                    "Timeslice expirations: {}\r\n",
+                    info.timeslice_expirations(&self.capability)
+                ),
+            );
+            session.write(console_writer.as_bytes());
+            Outcome::Done
+        } else if command.starts_with("uptime") {
+            let uptime_ms = self.uptime_ms();
+            let seconds = uptime_ms / 1000;
+            let mut console_writer = ConsoleWriter::new();
+            let _ = write(
+                &mut console_writer,
+                format_args!(
+                    "Uptime: {}d {:02}:{:02}:{:02}.{:03}\r\n",
+                    seconds / 86400,
+                    seconds / 3600 % 24,
+                    seconds / 60 % 60,
+                    seconds % 60,
+                    uptime_ms % 1000
+                ),
+            );
+            session.write(console_writer.as_bytes());
+            Outcome::Done
+        } else if command.starts_with("scheduler") {
+            let info: KernelInfo = KernelInfo::new(self.kernel);
+            let mut console_writer = ConsoleWriter::new();
+            let _ = match self.context_switch_counter.get() {
+                Some(counter) => write(
+                    &mut console_writer,
+                    format_args!("Context switches: {}\r\n", counter.count()),
+                ),
+                None => write(
+                    &mut console_writer,
+                    format_args!("Context switches: not counted\r\n"),
+                ),
+            };
+            session.write(console_writer.as_bytes());
+            console_writer.clear();
+            let _ = write(
+                &mut console_writer,
+                format_args!(
+                    "Timeslice expirations: {}\r\n",
+                    info.timeslice_expirations(&self.capability)
+                ),
+            );
+            session.write(console_writer.as_bytes());
+            Outcome::Done
+        } else if command.starts_with("process") {
+            let mut outcome = Outcome::Done;
+            let argument = command.split_whitespace().nth(1);
+            argument.map(|name| {
+                // If two processes have the same name, only
+                // print the first one we find.
+                let mut found = false;
+                self.kernel
+                    .process_each_capability(&self.capability, |proc| {
+                        if found {
+                            return;
+                        }
+                        let proc_name = proc.get_process_name();
+                        if proc_name == name {
+                            let mut console_writer = ConsoleWriter::new();
+                            let mut context: Option<ProcessPrinterContext> = None;
+                            context = self.process_printer.print_overview(
+                                proc,
+                                &mut console_writer,
+                                context,
+                            );
+
+                            session.write(console_writer.as_bytes());
+
+                            if context.is_some() {
+                                outcome = Outcome::Print(WriterState::ProcessPrint {
+                                    process_id: proc.processid(),
+                                    context,
+                                });
+                            }
+
+                            found = true;
+                        }
+                    });
+            });
+            outcome
+        } else if command.starts_with("kernel") {
+            let mut console_writer = ConsoleWriter::new();
+            let _ = write(
+                &mut console_writer,
+                format_args!(
+                    "Kernel version: {}.{} (build {})\r\n",
+                    kernel::KERNEL_MAJOR_VERSION,
+                    kernel::KERNEL_MINOR_VERSION,
+                    option_env!("TOCK_KERNEL_VERSION").unwrap_or("unknown")
+                ),
+            );
+            session.write(console_writer.as_bytes());
+
+            // Prints kernel memory by moving the writer to the start state.
+            Outcome::Print(WriterState::KernelStart)
+        } else if command.starts_with("dmesg") {
+            // Prints the debug output that has not been transmitted yet,
+            // without removing it.
+            session.write(b"---| Queued debug output |---\r\n");
+            Outcome::Print(WriterState::DebugLog { position: 0 })
+        } else if let Some(status) = self.run_added_command(session, command) {
+            match status {
+                CommandStatus::Running => Outcome::Running,
+                _ => Outcome::Done,
+            }
+        } else if command.starts_with("reset") {
+            self.reset_function.map_or_else(
+                || {
+                    session.write(b"Reset function is not implemented");
+                },
+                |f| {
+                    f();
+                },
+            );
+            Outcome::Done
+        } else if command.starts_with("panic") {
+            panic!("Process Console forced a kernel panic.");
+        } else {
+            self.write_valid_commands(session);
+            Outcome::Done
+        }
+    }
+
+    /// Run `command` in `session` if it is one of the added commands.
+    fn run_added_command(
+        &self,
+        session: &'a dyn ConsoleSession,
+        command: &str,
+    ) -> Option<CommandStatus> {
+        self.commands
+            .iter()
+            .map(|commands| commands.run(session, command))
+            .find(|status| *status != CommandStatus::Unknown)
+    }
+
+    /// Tell the added commands that a key was pressed in `session`. Returns
+    /// `true` if the key stopped one of them.
+    fn key_pressed(&self, session: &'a dyn ConsoleSession) -> bool {
+        self.commands
+            .iter()
+            .any(|commands| commands.key_pressed(session))
+    }
+
+    /// Let the added commands continue, because `session` transmitted all
+    /// its output.
+    fn output_drained(&self, session: &'a dyn ConsoleSession) {
+        for commands in self.commands.iter() {
+            commands.output_drained(session);
+        }
+    }
+
+    /// Count the time since the uptime was last updated. The alarm counter
+    /// must not wrap more than once between updates, which happen with every
+    /// received byte.
+    fn update_uptime(&self) {
+        let now = self.alarm.now();
+        let elapsed = now.wrapping_sub(self.uptime_last.get());
+        self.uptime_last.set(now);
+        self.uptime_ticks
+            .set(self.uptime_ticks.get() + elapsed.into_usize() as u64);
+    }
+
+    /// Time since the alarm counter started, usually at boot, in
+    /// milliseconds.
+    fn uptime_ms(&self) -> u64 {
+        self.update_uptime();
+        self.uptime_ticks.get() * 1000 / u64::from(A::Frequency::frequency())
+    }
+}
+
+impl<'a, const COMMAND_HISTORY_LEN: usize, A: Alarm<'a>, C: ProcessManagementCapability>
+    ProcessConsole<'a, COMMAND_HISTORY_LEN, A, C>
+{
+    /// A session on `uart` that runs its commands with `engine`. `alarm`
+    /// delays the start of the session.
+    pub fn new(
+        engine: &'a ConsoleEngine<'a, A, C>,
+        uart: &'a dyn uart::UartData<'a>,
+        alarm: &'a A,
+        tx_buffer: &'static mut [u8],
+        rx_buffer: &'static mut [u8],
+        queue_buffer: &'static mut [u8],
+        cmd_buffer: &'static mut [u8],
+        cmd_history_buffer: &'static mut [Command; COMMAND_HISTORY_LEN],
+    ) -> ProcessConsole<'a, COMMAND_HISTORY_LEN, A, C> {
+        ProcessConsole {
+            engine,
+            uart,
+            alarm,
+            tx_in_progress: Cell::new(false),
+            tx_buffer: TakeCell::new(tx_buffer),
+            queue_buffer: TakeCell::new(queue_buffer),
+            queue_size: Cell::new(0),
+            writer_state: Cell::new(WriterState::Empty),
+            rx_buffer: TakeCell::new(rx_buffer),
+            command_buffer: TakeCell::new(cmd_buffer),
+            command_index: Cell::new(0),
+            mode: Cell::new(ProcessConsoleState::Off),
+            input_parser: Cell::new(InputParser::new()),
+            input_queue: MapCell::new(InputQueue::new()),
+            command_history: MapCell::new(CommandHistory::new(cmd_history_buffer)),
+            cursor: Cell::new(0),
+            previous_byte: Cell::new(EOL),
+            execute: Cell::new(false),
+            boot_commands: OptionalCell::empty(),
+            script_state: Cell::new(ScriptState::Idle),
+            pending_control: OptionalCell::empty(),
+            command_running: Cell::new(false),
+            session: OptionalCell::empty(),
+        }
+    }
+
+    /// The engine that runs the commands of this session, shared with the
+    /// other sessions of the console.
+    pub fn engine(&self) -> &'a ConsoleEngine<'a, A, C> {
+        self.engine
+    }
+
+    /// Run `commands`, in order, once the console has started.
+    ///
+    /// Each command runs after the output of the previous one has been
+    /// printed, as if it had been typed at the prompt.
+    pub fn set_boot_commands(&self, commands: &'static [&'static str]) {
+        self.boot_commands.set(commands);
+        self.script_state.set(ScriptState::Boot { index: 0 });
+    }
+
+    /// Hand this console to the added commands, so they can print after
+    /// they return, such as when a storage read completes.
+    pub fn setup(&'a self) {
+        self.session.set(self);
+    }
+
+    /// Start the process console listening for user commands.
+    pub fn start(&self) -> Result<(), ErrorCode> {
+        if self.mode.get() == ProcessConsoleState::Off {
+            self.alarm
+                .set_alarm(self.alarm.now(), self.alarm.ticks_from_ms(100));
+            self.mode.set(ProcessConsoleState::Active);
+        }
+        Ok(())
+    }
+
+    /// Start the process console listening but in a hibernated state.
+    ///
+    /// The process console will not respond to commands, but can be activated
+    /// with the `console-start` command.
+    pub fn start_hibernated(&self) -> Result<(), ErrorCode> {
+        if self.mode.get() == ProcessConsoleState::Off {
+            self.alarm
+                .set_alarm(self.alarm.now(), self.alarm.ticks_from_ms(100));
+            self.mode.set(ProcessConsoleState::Hibernating)
+        }
+        Ok(())
+    }
+
+    /// Print base information about the kernel version installed and the help
+    /// message.
+    pub fn display_welcome(&self) {
+        // Start if not already started.
+        if self.mode.get() == ProcessConsoleState::Off {
+            self.rx_buffer.take().map(|buffer| {
+                let _ = self.uart.receive_buffer(buffer, 1);
+                self.mode.set(ProcessConsoleState::Active);
+            });
+        }
+
+        // Display pconsole info.
+        let mut console_writer = ConsoleWriter::new();
+        let _ = write(
+            &mut console_writer,
+            format_args!(
+                "Kernel version: {}.{} (build {})\r\n",
+                kernel::KERNEL_MAJOR_VERSION,
+                kernel::KERNEL_MINOR_VERSION,
+                option_env!("TOCK_KERNEL_VERSION").unwrap_or("unknown"),
+            ),
+        );
+        let _ = self.write_bytes(&(console_writer.buf)[..console_writer.size]);
+
+        let _ = self.write_bytes(b"Welcome to the process console.\r\n");
+        self.engine.write_valid_commands(self);
+        self.prompt();
+    }
+
+    // Process the command in the command buffer and clear the buffer.
+    fn read_command(&self) {
+        self.command_buffer.map(|command| {
+            let terminator = command.iter().position(|&x| x == 0).unwrap_or(0);
+
+            // A command is valid only if it starts inside the buffer,
+            // ends before the beginning of the buffer, and ends after
+            // it starts.
+            if terminator > 0 {
+                let cmd_str = str::from_utf8(&command[0..terminator]);
 
                 match cmd_str {
                     Ok(s) => {
@@ -773,7 +1712,14 @@ impl<'a, const COMMAND_HISTORY_LEN: usize, A: Alarm<'a>, C: ProcessManagementCap
                             }
                         }
 
-                        if clean_str.starts_with("console-start") {
+                        if let Some(pending) = self.pending_control.take() {
+                            // Any answer but yes cancels the pending command.
+                            if clean_str == "y" || clean_str == "yes" {
+                                self.engine.run_control(self, pending);
+                            } else {
+                                let _ = self.write_bytes(b"Cancelled.\r\n");
+                            }
+                        } else if clean_str.starts_with("console-start") {
                             self.mode.set(ProcessConsoleState::Active);
                         } else if self.mode.get() == ProcessConsoleState::Hibernating {
                             // Ignore all commands in hibernating mode. We put
@@ -781,224 +1727,18 @@ impl<'a, const COMMAND_HISTORY_LEN: usize, A: Alarm<'a>, C: ProcessManagementCap
                             // even if the user typed a valid command.
                         } else if clean_str.starts_with("help") {
                             let _ = self.write_bytes(b"Welcome to the process console.\r\n");
-                            let _ = self.write_bytes(b"Valid commands are: ");
-                            let _ = self.write_bytes(VALID_COMMANDS_STR);
+                            self.engine.write_valid_commands(self);
                         } else if clean_str.starts_with("console-stop") {
                             let _ = self.write_bytes(b"Disabling the process console.\r\n");
                             let _ = self.write_bytes(b"Run console-start to reactivate.\r\n");
                             self.mode.set(ProcessConsoleState::Hibernating);
-                        } else if clean_str.starts_with("start") {
-                            let argument = clean_str.split_whitespace().nth(1);
-                            argument.map(|name| {
-                                self.kernel
-                                    .process_each_capability(&self.capability, |proc| {
-                                        let proc_name = proc.get_process_name();
-                                        if proc_name == name {
-                                            proc.resume();
-                                            let mut console_writer = ConsoleWriter::new();
-                                            let _ = write(
-                                                &mut console_writer,
-                                                format_args!("Process {} resumed.\r\n", name),
-                                            );
-
-                                            let _ = self.write_bytes(
-                                                &(console_writer.buf)[..console_writer.size],
-                                            );
-                                        }
-                                    });
-                            });
-                        } else if clean_str.starts_with("stop") {
-                            let argument = clean_str.split_whitespace().nth(1);
-                            argument.map(|name| {
-                                self.kernel
-                                    .process_each_capability(&self.capability, |proc| {
-                                        let proc_name = proc.get_process_name();
-                                        if proc_name == name {
-                                            proc.stop();
-                                            let mut console_writer = ConsoleWriter::new();
-                                            let _ = write(
-                                                &mut console_writer,
-                                                format_args!("Process {} stopped\r\n", proc_name),
-                                            );
-
-                                            let _ = self.write_bytes(
-                                                &(console_writer.buf)[..console_writer.size],
-                                            );
-                                        }
-                                    });
-                            });
-                        } else if clean_str.starts_with("fault") {
-                            let argument = clean_str.split_whitespace().nth(1);
-                            argument.map(|name| {
-                                self.kernel
-                                    .process_each_capability(&self.capability, |proc| {
-                                        let proc_name = proc.get_process_name();
-                                        if proc_name == name {
-                                            proc.set_fault_state();
-                                            let mut console_writer = ConsoleWriter::new();
-                                            let _ = write(
-                                                &mut console_writer,
-                                                format_args!(
-                                                    "Process {} now faulted\r\n",
-                                                    proc_name
-                                                ),
-                                            );
-
-                                            let _ = self.write_bytes(
-                                                &(console_writer.buf)[..console_writer.size],
-                                            );
-                                        }
-                                    });
-                            });
-                        } else if clean_str.starts_with("terminate") {
-                            let argument = clean_str.split_whitespace().nth(1);
-                            argument.map(|name| {
-                                self.kernel
-                                    .process_each_capability(&self.capability, |proc| {
-                                        let proc_name = proc.get_process_name();
-                                        if proc_name == name {
-                                            proc.terminate(None);
-                                            let mut console_writer = ConsoleWriter::new();
-                                            let _ = write(
-                                                &mut console_writer,
-                                                format_args!("Process {} terminated\n", proc_name),
-                                            );
-
-                                            let _ = self.write_bytes(
-                                                &(console_writer.buf)[..console_writer.size],
-                                            );
-                                        }
-                                    });
-                            });
-                        } else if clean_str.starts_with("boot") {
-                            let argument = clean_str.split_whitespace().nth(1);
-                            argument.map(|name| {
-                                self.kernel
-                                    .process_each_capability(&self.capability, |proc| {
-                                        let proc_name = proc.get_process_name();
-                                        if proc_name == name
-                                            && proc.get_state() == State::Terminated
-                                        {
-                                            proc.try_restart(None);
-                                        }
-                                    });
-                            });
-                        } else if clean_str.starts_with("list") {
-                            let _ = self
-                                .write_bytes(b" PID    ShortID    Name                Quanta  ");
-                            let _ = self.write_bytes(b"Syscalls  Restarts  Grants  State\r\n");
-
-                            // Count the number of current processes.
-                            let mut count = 0;
-                            self.kernel.process_each_capability(&self.capability, |_| {
-                                count += 1;
-                            });
-
-                            if count > 0 {
-                                // Start the state machine to print each separately.
-                                self.write_state(WriterState::List {
-                                    index: -1,
-                                    total: count,
-                                });
+                        } else if let Some(session) = self.session.get() {
+                            match self.engine.run(session, clean_str) {
+                                Outcome::Done => {}
+                                Outcome::Print(state) => self.writer_state.set(state),
+                                Outcome::Confirm(pending) => self.pending_control.set(pending),
+                                Outcome::Running => self.command_running.set(true),
                             }
-                        } else if clean_str.starts_with("status") {
-                            let info: KernelInfo = KernelInfo::new(self.kernel);
-                            let mut console_writer = ConsoleWriter::new();
-                            let _ = write(
-                                &mut console_writer,
-                                format_args!(
-                                    "Total processes: {}\r\n",
-                                    info.number_loaded_processes(&self.capability)
-                                ),
-                            );
-                            let _ = self.write_bytes(&(console_writer.buf)[..console_writer.size]);
-                            console_writer.clear();
-                            let _ = write(
-                                &mut console_writer,
-                                format_args!(
-                                    "Active processes: {}\r\n",
-                                    info.number_active_processes(&self.capability)
-                                ),
-                            );
-                            let _ = self.write_bytes(&(console_writer.buf)[..console_writer.size]);
-                            console_writer.clear();
-                            let _ = write(
-                                &mut console_writer,
-                                format_args!(
-                                    "Timeslice expirations: {}\r\n",
-                                    info.timeslice_expirations(&self.capability)
-                                ),
-                            );
-                            let _ = self.write_bytes(&(console_writer.buf)[..console_writer.size]);
-                        } else if clean_str.starts_with("process") {
-                            let argument = clean_str.split_whitespace().nth(1);
-                            argument.map(|name| {
-                                // If two processes have the same name, only
-                                // print the first one we find.
-                                let mut found = false;
-                                self.kernel
-                                    .process_each_capability(&self.capability, |proc| {
-                                        if found {
-                                            return;
-                                        }
-                                        let proc_name = proc.get_process_name();
-                                        if proc_name == name {
-                                            let mut console_writer = ConsoleWriter::new();
-                                            let mut context: Option<ProcessPrinterContext> = None;
-                                            context = self.process_printer.print_overview(
-                                                proc,
-                                                &mut console_writer,
-                                                context,
-                                            );
-
-                                            let _ = self.write_bytes(
-                                                &(console_writer.buf)[..console_writer.size],
-                                            );
-
-                                            if context.is_some() {
-                                                self.writer_state.replace(
-                                                    WriterState::ProcessPrint {
-                                                        process_id: proc.processid(),
-                                                        context,
-                                                    },
-                                                );
-                                            }
-
-                                            found = true;
-                                        }
-                                    });
-                            });
-                        } else if clean_str.starts_with("kernel") {
-                            let mut console_writer = ConsoleWriter::new();
-                            let _ = write(
-                                &mut console_writer,
-                                format_args!(
-                                    "Kernel version: {}.{} (build {})\r\n",
-                                    kernel::KERNEL_MAJOR_VERSION,
-                                    kernel::KERNEL_MINOR_VERSION,
-                                    option_env!("TOCK_KERNEL_VERSION").unwrap_or("unknown")
-                                ),
-                            );
-                            let _ = self.write_bytes(&(console_writer.buf)[..console_writer.size]);
-                            console_writer.clear();
-
-                            // Prints kernel memory by moving the writer to the
-                            // start state.
-                            self.writer_state.replace(WriterState::KernelStart);
-                        } else if clean_str.starts_with("reset") {
-                            self.reset_function.map_or_else(
-                                || {
-                                    let _ = self.write_bytes(b"Reset function is not implemented");
-                                },
-                                |f| {
-                                    f();
-                                },
-                            );
-                        } else if clean_str.starts_with("panic") {
-                            panic!("Process Console forced a kernel panic.");
-                        } else {
-                            let _ = self.write_bytes(b"Valid commands are: ");
-                            let _ = self.write_bytes(VALID_COMMANDS_STR);
                         }
                     }
                     Err(_e) => {
@@ -1016,11 +1756,41 @@ impl<'a, const COMMAND_HISTORY_LEN: usize, A: Alarm<'a>, C: ProcessManagementCap
             command[0] = 0;
         });
         self.command_index.set(0);
-        if self.writer_state.get() == WriterState::Empty {
+        if self.writer_state.get() == WriterState::Empty && !self.command_running.get() {
             self.prompt();
         }
     }
 
+    /// Run the next boot command, if the console is not busy with anything
+    /// else.
+    fn boot_step(&self) {
+        if !self.at_prompt() {
+            return;
+        }
+
+        if let ScriptState::Boot { index } = self.script_state.get() {
+            match self.boot_commands.get().and_then(|cmds| cmds.get(index)) {
+                Some(cmd) => {
+                    self.script_state
+                        .set(ScriptState::Boot { index: index + 1 });
+                    self.run_command(cmd.as_bytes());
+                }
+                None => self.script_state.set(ScriptState::Idle),
+            }
+        }
+    }
+
+    /// Whether the console shows an empty prompt, with nothing left to
+    /// transmit or run.
+    fn at_prompt(&self) -> bool {
+        self.mode.get() == ProcessConsoleState::Active
+            && !self.tx_in_progress.get()
+            && self.writer_state.get() == WriterState::Empty
+            && self.command_index.get() == 0
+            && !self.execute.get()
+            && !self.command_running.get()
+    }
+
     fn prompt(&self) {
         // Only display the prompt in active mode.
         match self.mode.get() {
@@ -1034,15 +1804,230 @@ impl<'a, const COMMAND_HISTORY_LEN: usize, A: Alarm<'a>, C: ProcessManagementCap
     /// Start or iterate the state machine for an asynchronous write operation
     /// spread across multiple callback cycles.
     fn write_state(&self, state: WriterState) {
-        self.writer_state.replace(self.next_state(state));
-        self.create_state_buffer(self.writer_state.get());
+        let state = self.engine.print_state(self, state);
+        self.writer_state.set(state);
+        if state == WriterState::Empty {
+            self.prompt();
+        }
+    }
+
+    /// Handle a received byte. Bytes go through the input queue, so a pasted
+    /// line waits for the commands before it to finish.
+    fn receive_byte(&self, byte: u8) {
+        self.engine.update_uptime();
+        // The key that stops an added command, such as `watch`, is not part
+        // of the next command.
+        let stopped = self
+            .session
+            .map_or(false, |session| self.engine.key_pressed(session));
+        if stopped {
+            return;
+        }
+        self.input_queue.map(|queue| queue.push(byte));
+        self.drain_input();
+    }
+
+    /// Whether received bytes have to wait: a command is about to run, or
+    /// the output queue has no room to echo them.
+    fn input_blocked(&self) -> bool {
+        self.execute.get()
+            || self.queue_buffer.map_or(false, |buf| {
+                self.queue_size.get() > buf.len().saturating_sub(INPUT_ECHO_LEN)
+            })
+    }
+
+    /// Handle queued input until the console has to wait again.
+    fn drain_input(&self) {
+        while !self.input_blocked() {
+            match self.input_queue.map(|queue| queue.pop()).flatten() {
+                Some(byte) => self.process_byte(byte),
+                None => break,
+            }
+        }
+    }
+
+    fn process_byte(&self, byte: u8) {
+        let previous_byte = self.previous_byte.replace(byte);
+        let mut parser = self.input_parser.get();
+        let input = parser.next(byte);
+        self.input_parser.set(parser);
+
+        if let Some(input) = input {
+            self.command_buffer
+                .map(|command| self.handle_input(command, input, previous_byte));
+        }
+    }
+
+    /// Edit the command being typed and echo the change.
+    fn handle_input(&self, command: &mut [u8], input: Input, previous_byte: u8) {
+        let index = self.command_index.get();
+        let cursor = self.cursor.get();
+
+        match input {
+            Input::Key(key @ (EscKey::Up | EscKey::Down)) if COMMAND_HISTORY_LEN >= 1 => {
+                self.recall_command(command, matches!(key, EscKey::Up));
+            }
+            Input::Key(EscKey::Left) => {
+                if let Some(start) = char_before(command, cursor) {
+                    let _ = self.write_byte(BS);
+                    self.cursor.set(start);
+                }
+            }
+            Input::Key(EscKey::Right) if cursor < index => {
+                let next = char_after(command, cursor, index);
+                let _ = self.write_bytes(&command[cursor..next]);
+                self.cursor.set(next);
+            }
+            Input::Key(EscKey::Home) if cursor > 0 => {
+                for _ in 0..columns(&command[..cursor]) {
+                    let _ = self.write_byte(BS);
+                }
+                self.cursor.set(0);
+            }
+            Input::Key(EscKey::End) if cursor < index => {
+                let _ = self.write_bytes(&command[cursor..index]);
+                self.cursor.set(index);
+            }
+            Input::Key(EscKey::Delete) if cursor < index => {
+                self.remove_text(command, cursor, char_after(command, cursor, index));
+            }
+            Input::Backspace => {
+                // Nothing to erase at the start of the command.
+                if let Some(start) = char_before(command, cursor) {
+                    let _ = self.write_byte(BS);
+                    self.cursor.set(start);
+                    self.remove_text(command, start, cursor);
+                }
+            }
+            Input::Newline(byte) => {
+                if (previous_byte == NLINE || previous_byte == CR) && previous_byte != byte {
+                    // Reset the sequence, when \r\n is received
+                    self.previous_byte.set(EOL);
+                } else {
+                    self.cursor.set(0);
+                    self.execute.set(true);
+
+                    let _ = self.write_bytes(&[CR, NLINE]);
+
+                    if COMMAND_HISTORY_LEN > 1 {
+                        // Clear the unfinished command
+                        self.command_history.map(|ht| {
+                            ht.cmd_idx = 0;
+                            ht.cmd_is_modified = false;
+                            ht.cmds[0].clear();
+                        });
+                    }
+                }
+            }
+            // Keep the last byte of the command for EOL.
+            Input::Text(bytes, len) if index + len < command.len() => {
+                self.insert_text(command, &bytes[..len]);
+            }
+            _ => {}
+        }
+    }
+
+    /// Insert `text` at the cursor and redraw the rest of the line.
+    fn insert_text(&self, command: &mut [u8], text: &[u8]) {
+        let index = self.command_index.get();
+        let cursor = self.cursor.get();
+        let end = cursor + text.len();
+
+        command.copy_within(cursor..index, end);
+        command[cursor..end].copy_from_slice(text);
+        command[index + text.len()] = EOL;
+
+        // Echo the new character and the rest of the command, then move the
+        // terminal cursor back to just after the new character.
+        let _ = self.write_bytes(&command[cursor..index + text.len()]);
+        for _ in 0..columns(&command[end..index + text.len()]) {
+            let _ = self.write_byte(BS);
+        }
+
+        self.cursor.set(end);
+        self.command_index.set(index + text.len());
+        self.sync_history(command);
+    }
+
+    /// Remove the character at `command[start..end]` and redraw the rest of
+    /// the line. The terminal cursor must already be at `start`.
+    fn remove_text(&self, command: &mut [u8], start: usize, end: usize) {
+        let index = self.command_index.get();
+        let new_index = index - (end - start);
+
+        command.copy_within(end..index, start);
+        command[new_index..=index].fill(EOL);
+
+        // Echo the rest of the command over the removed character, erase the
+        // "ghost" copy of the last character and move the terminal cursor
+        // back to `start`.
+        // abcd|ef -> abceff -> abcef
+        let rest = columns(&command[start..new_index]);
+        let _ = self.write_bytes(&command[start..new_index]);
+        let _ = self.write_byte(SPACE);
+        for _ in 0..rest + 1 {
+            let _ = self.write_byte(BS);
+        }
+
+        self.command_index.set(new_index);
+        self.sync_history(command);
+    }
+
+    /// Replace the command with the next (`up`) or previous command in the
+    /// history.
+    fn recall_command(&self, command: &mut [u8], up: bool) {
+        self.command_history.map(|ht| {
+            let next_index = match if up {
+                ht.next_cmd_idx()
+            } else {
+                ht.prev_cmd_idx()
+            } {
+                Some(next_index) => next_index,
+                None => return,
+            };
+            let index = self.command_index.get();
+            let cursor = self.cursor.get();
+            let next_command_len = ht.cmds[next_index].len;
+
+            for _ in 0..columns(&command[cursor..index]) {
+                let _ = self.write_byte(SPACE);
+            }
+
+            // Clear the displayed command
+            for _ in 0..columns(&command[..index]) {
+                let _ = self.write_bytes(&[BS, SPACE, BS]);
+            }
+
+            // Display the new command
+            command[..next_command_len]
+                .copy_from_slice(&ht.cmds[next_index].buf[..next_command_len]);
+            command[next_command_len] = EOL;
+            let _ = self.write_bytes(&command[..next_command_len]);
+
+            ht.cmd_is_modified = true;
+            self.command_index.set(next_command_len);
+            self.cursor.set(next_command_len);
+        });
+    }
+
+    /// Copy an edited command into the unfinished command of the history.
+    fn sync_history(&self, command: &[u8]) {
+        if COMMAND_HISTORY_LEN > 1 {
+            self.command_history.map(|ht| {
+                ht.write_to_first(command);
+                ht.cmd_is_modified = false;
+            });
+        }
     }
 
     fn write_byte(&self, byte: u8) -> Result<(), ErrorCode> {
         if self.tx_in_progress.get() {
             self.queue_buffer.map(|buf| {
-                buf[self.queue_size.get()] = byte;
-                self.queue_size.set(self.queue_size.get() + 1);
+                let size = self.queue_size.get();
+                if size < buf.len() {
+                    buf[size] = byte;
+                    self.queue_size.set(size + 1);
+                }
             });
             Err(ErrorCode::BUSY)
         } else {
@@ -1122,6 +2107,36 @@ impl<'a, const COMMAND_HISTORY_LEN: usize, A: Alarm<'a>, C: ProcessManagementCap
     }
 }
 
+impl<'a, const COMMAND_HISTORY_LEN: usize, A: Alarm<'a>, C: ProcessManagementCapability>
+    ConsoleSession for ProcessConsole<'a, COMMAND_HISTORY_LEN, A, C>
+{
+    fn write(&self, bytes: &[u8]) {
+        let _ = self.write_bytes(bytes);
+    }
+
+    fn command_done(&self) {
+        self.command_running.set(false);
+        self.prompt();
+    }
+
+    fn run_command(&self, command: &[u8]) {
+        let len = cmp::min(command.len(), COMMAND_BUF_LEN - 1);
+        self.command_buffer.map(|buffer| {
+            buffer[..len].copy_from_slice(&command[..len]);
+            buffer[len] = EOL;
+        });
+        // Like for typed commands, the command runs once the echo has been
+        // transmitted.
+        self.execute.set(true);
+        let _ = self.write_bytes(&command[..len]);
+        let _ = self.write_bytes(&[CR, NLINE]);
+    }
+
+    fn is_busy(&self) -> bool {
+        !self.at_prompt() || self.script_state.get() != ScriptState::Idle
+    }
+}
+
 impl<'a, const COMMAND_HISTORY_LEN: usize, A: Alarm<'a>, C: ProcessManagementCapability> AlarmClient
     for ProcessConsole<'a, COMMAND_HISTORY_LEN, A, C>
 {
@@ -1164,7 +2179,15 @@ impl<'a, const COMMAND_HISTORY_LEN: usize, A: Alarm<'a>, C: ProcessManagementCap
             if self.execute.get() {
                 self.execute.set(false);
                 self.read_command();
+                return;
             }
+
+            // Nothing left to print, so held back input, boot commands, and
+            // added commands can continue.
+            self.drain_input();
+            self.boot_step();
+            self.session
+                .map(|session| self.engine.output_drained(session));
         }
     }
 }
@@ -1182,235 +2205,182 @@ impl<'a, const COMMAND_HISTORY_LEN: usize, A: Alarm<'a>, C: ProcessManagementCap
         if error == uart::Error::None {
             match rx_len {
                 0 => debug!("ProcessConsole had read of 0 bytes"),
-                1 => {
-                    self.command_buffer.map(|command| {
-                        let esc_state = self.esc_state.get().next_state(read_buf[0]);
-                        self.esc_state.set(esc_state);
-
-                        let previous_byte = self.previous_byte.get();
-                        self.previous_byte.set(read_buf[0]);
-                        let index = self.command_index.get();
-
-                        let cursor = self.cursor.get();
-
-                        if let EscState::Complete(key) = esc_state {
-                            match key {
-                                EscKey::Up | EscKey::Down if COMMAND_HISTORY_LEN >= 1 => {
-                                    self.command_history.map(|ht| {
-                                        if let Some(next_index) = if matches!(key, EscKey::Up) {
-                                            ht.next_cmd_idx()
-                                        } else {
-                                            ht.prev_cmd_idx()
-                                        } {
-                                            let next_command_len = ht.cmds[next_index].len;
-
-                                            for _ in cursor..index {
-                                                let _ = self.write_byte(SPACE);
-                                            }
-
-                                            // Clear the displayed command
-                                            for _ in 0..index {
-                                                let _ = self.write_bytes(&[BS, SPACE, BS]);
-                                            }
-
-                                            // Display the new command
-                                            for i in 0..next_command_len {
-                                                let byte = ht.cmds[next_index].buf[i];
-                                                let _ = self.write_byte(byte);
-                                                command[i] = byte;
-                                            }
-
-                                            ht.cmd_is_modified = true;
-                                            self.command_index.set(next_command_len);
-                                            self.cursor.set(next_command_len);
-                                            command[next_command_len] = EOL;
-                                        };
-                                    });
-                                }
-                                EscKey::Left if cursor > 0 => {
-                                    let _ = self.write_byte(BS);
-                                    self.cursor.set(cursor - 1);
-                                }
-                                EscKey::Right if cursor < index => {
-                                    let _ = self.write_byte(command[cursor]);
-                                    self.cursor.set(cursor + 1);
-                                }
-                                EscKey::Home if cursor > 0 => {
-                                    for _ in 0..cursor {
-                                        let _ = self.write_byte(BS);
-                                    }
+                1 => self.receive_byte(read_buf[0]),
+                _ => debug!(
+                    "ProcessConsole issues reads of 1 byte, but receive_complete was length {}",
+                    rx_len
+                ),
+            };
+        }
+        let _ = self.uart.receive_buffer(read_buf, 1);
+    }
+}
 
-                                    self.cursor.set(0);
-                                }
-                                EscKey::End if cursor < index => {
-                                    for i in cursor..index {
-                                        let _ = self.write_byte(command[i]);
-                                    }
+#[cfg(test)]
+mod tests {
+    use super::{char_before, EscKey, Input, InputParser, InputQueue, INPUT_QUEUE_LEN};
+
+    /// The inputs `bytes` decode to, in order, continuing from `parser`.
+    fn feed(parser: &mut InputParser, bytes: &[u8]) -> [Option<Input>; 8] {
+        let mut out = [None; 8];
+        let mut len = 0;
+        for b in bytes {
+            if let Some(input) = parser.next(*b) {
+                out[len] = Some(input);
+                len += 1;
+            }
+        }
+        out
+    }
 
-                                    self.cursor.set(index);
-                                }
-                                EscKey::Delete if cursor < index => {
-                                    // Move the bytes one position to left
-                                    for i in cursor..(index - 1) {
-                                        command[i] = command[i + 1];
-                                        let _ = self.write_byte(command[i]);
-                                    }
-                                    // We don't want to write the EOL byte, but we want to copy it to the left
-                                    command[index - 1] = command[index];
-
-                                    // Now that we copied all bytes to the left, we are left over with
-                                    // a dublicate "ghost" character of the last byte,
-                                    // In case we deleted the first character, this doesn't do anything as
-                                    // the dublicate is not there.
-                                    // |abcdef -> bcdef
-                                    // abc|def -> abceff -> abcef
-                                    let _ = self.write_bytes(&[SPACE, BS]);
-
-                                    // Move the cursor to last position
-                                    for _ in cursor..(index - 1) {
-                                        let _ = self.write_byte(BS);
-                                    }
-
-                                    self.command_index.set(index - 1);
-
-                                    // Remove the byte from the command in order
-                                    // not to permit accumulation of the text
-                                    if COMMAND_HISTORY_LEN > 1 {
-                                        self.command_history.map(|ht| {
-                                            if ht.cmd_is_modified {
-                                                // Copy the last command into the unfinished command
-
-                                                ht.cmds[0].clear();
-                                                ht.write_to_first(command);
-                                                ht.cmd_is_modified = false;
-                                            } else {
-                                                ht.cmds[0].delete_byte(cursor);
-                                            }
-                                        });
-                                    }
-                                }
-                                _ => {}
-                            };
-                        } else if read_buf[0] == NLINE || read_buf[0] == CR {
-                            if (previous_byte == NLINE || previous_byte == CR)
-                                && previous_byte != read_buf[0]
-                            {
-                                // Reset the sequence, when \r\n is received
-                                self.previous_byte.set(EOL);
-                            } else {
-                                self.cursor.set(0);
-                                self.execute.set(true);
-
-                                let _ = self.write_bytes(&[CR, NLINE]);
-
-                                if COMMAND_HISTORY_LEN > 1 {
-                                    // Clear the unfinished command
-                                    self.command_history.map(|ht| {
-                                        ht.cmd_idx = 0;
-                                        ht.cmd_is_modified = false;
-                                        ht.cmds[0].clear();
-                                    });
-                                }
-                            }
-                        } else if read_buf[0] == BS {
-                            if cursor > 0 {
-                                // Backspace, echo and remove the byte
-                                // preceding the cursor
-                                // Note echo is '\b \b' to erase
-                                let _ = self.write_bytes(&[BS, SPACE, BS]);
-
-                                // Move the bytes one position to left
-                                for i in (cursor - 1)..(index - 1) {
-                                    command[i] = command[i + 1];
-                                    let _ = self.write_byte(command[i]);
-                                }
-                                // We don't want to write the EOL byte, but we want to copy it to the left
-                                command[index - 1] = command[index];
-
-                                // Now that we copied all bytes to the left, we are left over with
-                                // a dublicate "ghost" character of the last byte,
-                                // In case we deleted the last character, this doesn't do anything as
-                                // the dublicate is not there.
-                                // abcdef| -> abcdef
-                                // abcd|ef -> abceff -> abcef
-                                let _ = self.write_bytes(&[SPACE, BS]);
-
-                                // Move the cursor to last position
-                                for _ in cursor..index {
-                                    let _ = self.write_byte(BS);
-                                }
+    fn inputs(bytes: &[u8]) -> [Option<Input>; 8] {
+        feed(&mut InputParser::new(), bytes)
+    }
 
-                                self.command_index.set(index - 1);
-                                self.cursor.set(cursor - 1);
-
-                                // Remove the byte from the command in order
-                                // not to permit accumulation of the text
-                                if COMMAND_HISTORY_LEN > 1 {
-                                    self.command_history.map(|ht| {
-                                        if ht.cmd_is_modified {
-                                            // Copy the last command into the unfinished command
-
-                                            ht.cmds[0].clear();
-                                            ht.write_to_first(command);
-                                            ht.cmd_is_modified = false;
-                                        } else {
-                                            ht.cmds[0].delete_byte(cursor - 1);
-                                        }
-                                    });
-                                }
-                            }
-                        } else if index < (command.len() - 1)
-                            && read_buf[0] < ASCII_LIMIT
-                            && !esc_state.has_started()
-                            && !esc_state.in_progress()
-                        {
-                            // For some reason, sometimes reads return > 127 but no error,
-                            // which causes utf-8 decoding failure, so check byte is < 128. -pal
-
-                            // Echo the typed byte
-                            let _ = self.write_byte(read_buf[0]);
-
-                            // Echo the rest of the bytes from the command
-                            for i in cursor..index {
-                                let _ = self.write_byte(command[i]);
-                            }
+    fn key(key: EscKey) -> Option<Input> {
+        Some(Input::Key(key))
+    }
 
-                            // Make space for the newest byte
-                            for i in (cursor..(index + 1)).rev() {
-                                command[i + 1] = command[i];
-                            }
+    fn ascii(byte: u8) -> Option<Input> {
+        Some(Input::Text([byte, 0, 0, 0], 1))
+    }
 
-                            // Move the cursor to the last position
-                            for _ in cursor..index {
-                                let _ = self.write_byte(BS);
-                            }
+    #[test]
+    fn parser_plain_bytes() {
+        assert_eq!(
+            inputs(b"ab\r\n\x08\x7f")[..6],
+            [
+                ascii(b'a'),
+                ascii(b'b'),
+                Some(Input::Newline(b'\r')),
+                Some(Input::Newline(b'\n')),
+                Some(Input::Backspace),
+                key(EscKey::Delete),
+            ]
+        );
+    }
 
-                            command[cursor] = read_buf[0];
-                            self.cursor.set(cursor + 1);
-                            self.command_index.set(index + 1);
+    #[test]
+    fn parser_cursor_keys() {
+        assert_eq!(
+            inputs(b"\x1b[A\x1b[B\x1b[C\x1b[D\x1bOH\x1bOF")[..6],
+            [
+                key(EscKey::Up),
+                key(EscKey::Down),
+                key(EscKey::Right),
+                key(EscKey::Left),
+                key(EscKey::Home),
+                key(EscKey::End),
+            ]
+        );
+    }
 
-                            if COMMAND_HISTORY_LEN > 1 {
-                                self.command_history.map(|ht| {
-                                    if ht.cmd_is_modified {
-                                        // Copy the last command into the unfinished command
-
-                                        ht.cmds[0].clear();
-                                        ht.write_to_first(command);
-                                        ht.cmd_is_modified = false;
-                                    } else {
-                                        ht.cmds[0].insert_byte(read_buf[0], cursor);
-                                    }
-                                });
-                            }
-                        }
-                    });
-                }
-                _ => debug!(
-                    "ProcessConsole issues reads of 1 byte, but receive_complete was length {}",
-                    rx_len
-                ),
-            };
+    #[test]
+    fn parser_tilde_keys() {
+        assert_eq!(
+            inputs(b"\x1b[1~\x1b[7~\x1b[4~\x1b[8~\x1b[3~")[..5],
+            [
+                key(EscKey::Home),
+                key(EscKey::Home),
+                key(EscKey::End),
+                key(EscKey::End),
+                key(EscKey::Delete),
+            ]
+        );
+    }
+
+    #[test]
+    fn parser_modified_key() {
+        // Ctrl+Right, the modifier parameter is ignored.
+        assert_eq!(inputs(b"\x1b[1;5C")[..2], [key(EscKey::Right), None]);
+    }
+
+    #[test]
+    fn parser_drops_unknown_sequences() {
+        assert_eq!(inputs(b"\x1b[2~\x1b[Zx")[..2], [ascii(b'x'), None]);
+    }
+
+    #[test]
+    fn parser_split_sequence() {
+        let mut parser = InputParser::new();
+        assert_eq!(feed(&mut parser, b"\x1b")[0], None);
+        assert_eq!(feed(&mut parser, b"[1")[0], None);
+        assert_eq!(feed(&mut parser, b";5")[0], None);
+        assert_eq!(feed(&mut parser, b"D")[..2], [key(EscKey::Left), None]);
+        assert_eq!(feed(&mut parser, b"a")[0], ascii(b'a'));
+    }
+
+    #[test]
+    fn parser_aborted_sequence() {
+        // A control byte ends the sequence and still acts.
+        assert_eq!(inputs(b"\x1b\r")[..2], [Some(Input::Newline(b'\r')), None]);
+        assert_eq!(inputs(b"\x1b[\x08")[..2], [Some(Input::Backspace), None]);
+        // A second escape starts a new sequence.
+        assert_eq!(inputs(b"\x1b\x1b[A")[..2], [key(EscKey::Up), None]);
+    }
+
+    #[test]
+    fn parser_utf8() {
+        assert_eq!(
+            inputs("é€😀".as_bytes())[..4],
+            [
+                Some(Input::Text([0xc3, 0xa9, 0, 0], 2)),
+                Some(Input::Text([0xe2, 0x82, 0xac, 0], 3)),
+                Some(Input::Text([0xf0, 0x9f, 0x98, 0x80], 4)),
+                None,
+            ]
+        );
+    }
+
+    #[test]
+    fn parser_split_utf8() {
+        let mut parser = InputParser::new();
+        assert_eq!(feed(&mut parser, &[0xe2, 0x82])[0], None);
+        assert_eq!(
+            feed(&mut parser, &[0xac])[0],
+            Some(Input::Text([0xe2, 0x82, 0xac, 0], 3))
+        );
+    }
+
+    #[test]
+    fn parser_drops_invalid_utf8() {
+        // Truncated sequence, the next byte still counts.
+        assert_eq!(inputs(&[0xe2, 0x82, b'a'])[..2], [ascii(b'a'), None]);
+        // Overlong encodings and lone continuation bytes.
+        assert_eq!(inputs(&[0xc0, 0x80, 0xe0, 0x80, 0x80])[0], None);
+        assert_eq!(inputs(&[0x80, 0xbf, b'b'])[..2], [ascii(b'b'), None]);
+    }
+
+    #[test]
+    fn backspace_at_start_of_command() {
+        assert_eq!(char_before(b"", 0), None);
+        assert_eq!(char_before(b"ab", 0), None);
+        assert_eq!(char_before(b"ab", 2), Some(1));
+        assert_eq!(char_before("aé".as_bytes(), 3), Some(1));
+        assert_eq!(char_before("é".as_bytes(), 2), Some(0));
+    }
+
+    #[test]
+    fn input_queue_drops_overflow() {
+        let mut queue = InputQueue::new();
+        for i in 0..INPUT_QUEUE_LEN + 6 {
+            queue.push(i as u8);
         }
-        let _ = self.uart.receive_buffer(read_buf, 1);
+        for i in 0..INPUT_QUEUE_LEN {
+            assert_eq!(queue.pop(), Some(i as u8));
+        }
+        assert_eq!(queue.pop(), None);
+    }
+
+    #[test]
+    fn input_queue_wraps() {
+        let mut queue = InputQueue::new();
+        for i in 0..3 * INPUT_QUEUE_LEN {
+            queue.push(i as u8);
+            queue.push(!(i as u8));
+            assert_eq!(queue.pop(), Some(i as u8));
+            assert_eq!(queue.pop(), Some(!(i as u8)));
+        }
+        assert_eq!(queue.pop(), None);
     }
 }
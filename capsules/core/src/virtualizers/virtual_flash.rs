@@ -236,4 +236,12 @@ impl<F: hil::flash::Flash> hil::flash::Flash for FlashUser<'_, F> {
         self.mux.do_next_op();
         Ok(())
     }
+
+    fn geometry(&self) -> Option<hil::nonvolatile_storage::StorageGeometry> {
+        self.mux.flash.geometry()
+    }
+
+    fn read_mapped(&self, address: usize, buffer: &mut [u8]) -> Result<(), ErrorCode> {
+        self.mux.flash.read_mapped(address, buffer)
+    }
 }
@@ -0,0 +1,289 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2024.
+
+//! Virtualize the kernel interface to nonvolatile storage.
+//!
+//! `MuxNonvolatileStorage` shares one `NonvolatileStorage` implementation, for
+//! example the kernel interface of the nonvolatile storage driver, between
+//! several kernel clients. Each client uses its own
+//! `NonvolatileStorageUser`, which is given a window of the storage when it is
+//! created. Addresses stay absolute, but a user rejects any read or write that
+//! does not fall entirely inside its window with `INVAL`, so a client that
+//! computes a bad address cannot reach another client's data.
+//!
+//! Operations from different users are run one at a time, in the order the
+//! users are found in the mux's list. Each user can have one operation
+//! outstanding.
+//!
+//! A user the board calls `set_priority()` on, with the
+//! `StoragePriorityCapability`, goes ahead of all other users whenever the mux
+//! picks the next operation. This is meant for data that must be saved while
+//! the system is failing, like a crash dump, which should not wait for the
+//! writes of other users. An operation that has already been issued to the
+//! storage is not interrupted.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust,ignore
+//! # use kernel::{capabilities, create_capability, hil, static_init};
+//!
+//! let mux_storage = static_init!(
+//!     capsules_core::virtualizers::virtual_nonvolatile_storage::MuxNonvolatileStorage<'static>,
+//!     capsules_core::virtualizers::virtual_nonvolatile_storage::MuxNonvolatileStorage::new(
+//!         nonvolatile_storage
+//!     )
+//! );
+//! hil::nonvolatile_storage::NonvolatileStorage::set_client(nonvolatile_storage, mux_storage);
+//!
+//! // A client that may only touch 0x1000 bytes at 0x60000.
+//! let crash_dump_storage = static_init!(
+//!     capsules_core::virtualizers::virtual_nonvolatile_storage::NonvolatileStorageUser<'static>,
+//!     capsules_core::virtualizers::virtual_nonvolatile_storage::NonvolatileStorageUser::new(
+//!         mux_storage,
+//!         0x60000,
+//!         0x1000,
+//!     )
+//! );
+//! crash_dump_storage.setup();
+//!
+//! // Crash dumps are written before the operations of other users.
+//! let priority_cap = create_capability!(capabilities::StoragePriorityCapability);
+//! crash_dump_storage.set_priority(&priority_cap);
+//! ```
+
+use core::cell::Cell;
+
+use kernel::capabilities;
+use kernel::collections::list::{List, ListLink, ListNode};
+use kernel::hil;
+use kernel::utilities::cells::{OptionalCell, TakeCell};
+use kernel::ErrorCode;
+
+/// Keeps the list of users of the storage and serializes their requests.
+/// After each completed request the list is checked to see if there is another
+/// user with an outstanding read, write, or sync.
+pub struct MuxNonvolatileStorage<'a> {
+    storage: &'a dyn hil::nonvolatile_storage::NonvolatileStorage<'a>,
+    users: List<'a, NonvolatileStorageUser<'a>>,
+    inflight: OptionalCell<&'a NonvolatileStorageUser<'a>>,
+}
+
+impl<'a> MuxNonvolatileStorage<'a> {
+    pub const fn new(
+        storage: &'a dyn hil::nonvolatile_storage::NonvolatileStorage<'a>,
+    ) -> MuxNonvolatileStorage<'a> {
+        MuxNonvolatileStorage {
+            storage,
+            users: List::new(),
+            inflight: OptionalCell::empty(),
+        }
+    }
+
+    /// Find the first priority user with a pending request, or else the first
+    /// user with one, and issue it to the storage. A sync the storage refuses
+    /// is reported to its user with `sync_done`, a refused read or write is
+    /// dropped.
+    fn do_next_op(&self) {
+        while self.inflight.is_none() {
+            let node = match self
+                .users
+                .iter()
+                .find(|node| node.priority.get() && node.operation.get() != Op::Idle)
+                .or_else(|| {
+                    self.users
+                        .iter()
+                        .find(|node| node.operation.get() != Op::Idle)
+                }) {
+                Some(node) => node,
+                None => return,
+            };
+            let operation = node.operation.get();
+            node.operation.set(Op::Idle);
+            let result = match operation {
+                Op::Read(address, length) => {
+                    node.buffer.take().map_or(Err(ErrorCode::NOMEM), |buf| {
+                        self.storage.read(buf, address, length)
+                    })
+                }
+                Op::Write(address, length) => {
+                    node.buffer.take().map_or(Err(ErrorCode::NOMEM), |buf| {
+                        self.storage.write(buf, address, length)
+                    })
+                }
+                Op::Sync => self.storage.sync(),
+                Op::Idle => Err(ErrorCode::FAIL), // Can't get here...
+            };
+            match result {
+                Ok(()) => self.inflight.set(node),
+                Err(e) => {
+                    if operation == Op::Sync {
+                        node.client.map(|client| client.sync_done(Err(e)));
+                    }
+                }
+            }
+        }
+    }
+}
+
+impl hil::nonvolatile_storage::NonvolatileStorageClient for MuxNonvolatileStorage<'_> {
+    fn read_done(&self, buffer: &'static mut [u8], length: usize) {
+        self.inflight.take().map(move |user| {
+            user.client
+                .map(move |client| client.read_done(buffer, length));
+        });
+        self.do_next_op();
+    }
+
+    fn read_done_status(
+        &self,
+        buffer: &'static mut [u8],
+        length: usize,
+        status: hil::nonvolatile_storage::ReadStatus,
+    ) {
+        self.inflight.take().map(move |user| {
+            user.client
+                .map(move |client| client.read_done_status(buffer, length, status));
+        });
+        self.do_next_op();
+    }
+
+    fn write_done(&self, buffer: &'static mut [u8], length: usize) {
+        self.inflight.take().map(move |user| {
+            user.client
+                .map(move |client| client.write_done(buffer, length));
+        });
+        self.do_next_op();
+    }
+
+    fn sync_done(&self, result: Result<(), ErrorCode>) {
+        self.inflight.take().map(|user| {
+            user.client.map(|client| client.sync_done(result));
+        });
+        self.do_next_op();
+    }
+}
+
+#[derive(Copy, Clone, PartialEq)]
+enum Op {
+    Idle,
+    Read(usize, usize),
+    Write(usize, usize),
+    Sync,
+}
+
+/// Keeps the state of one kernel client of the shared storage, and the window
+/// of the storage it may access.
+pub struct NonvolatileStorageUser<'a> {
+    mux: &'a MuxNonvolatileStorage<'a>,
+    start: usize,
+    length: usize,
+    buffer: TakeCell<'static, [u8]>,
+    operation: Cell<Op>,
+    priority: Cell<bool>,
+    next: ListLink<'a, NonvolatileStorageUser<'a>>,
+    client: OptionalCell<&'a dyn hil::nonvolatile_storage::NonvolatileStorageClient>,
+}
+
+impl<'a> NonvolatileStorageUser<'a> {
+    /// Create a user that may access `length` bytes starting at `start`.
+    pub fn new(
+        mux: &'a MuxNonvolatileStorage<'a>,
+        start: usize,
+        length: usize,
+    ) -> NonvolatileStorageUser<'a> {
+        NonvolatileStorageUser {
+            mux,
+            start,
+            length,
+            buffer: TakeCell::empty(),
+            operation: Cell::new(Op::Idle),
+            priority: Cell::new(false),
+            next: ListLink::empty(),
+            client: OptionalCell::empty(),
+        }
+    }
+
+    /// Must be called right after `static_init!()`.
+    pub fn setup(&'a self) {
+        self.mux.users.push_head(self);
+    }
+
+    /// Run this user's operations before those of users without priority.
+    pub fn set_priority(&self, _capability: &dyn capabilities::StoragePriorityCapability) {
+        self.priority.set(true);
+    }
+
+    fn in_window(&self, address: usize, length: usize) -> bool {
+        hil::nonvolatile_storage::StorageRange::new(self.start, self.length)
+            .contains(address, length)
+    }
+
+    fn enqueue(&self, operation: Op, buffer: Option<&'static mut [u8]>) -> Result<(), ErrorCode> {
+        let inflight = self
+            .mux
+            .inflight
+            .map_or(false, |user| core::ptr::eq(user, self));
+        if inflight || self.operation.get() != Op::Idle {
+            return Err(ErrorCode::BUSY);
+        }
+        if let Some(buffer) = buffer {
+            self.buffer.replace(buffer);
+        }
+        self.operation.set(operation);
+        self.mux.do_next_op();
+        Ok(())
+    }
+}
+
+impl<'a> ListNode<'a, NonvolatileStorageUser<'a>> for NonvolatileStorageUser<'a> {
+    fn next(&'a self) -> &'a ListLink<'a, NonvolatileStorageUser<'a>> {
+        &self.next
+    }
+}
+
+impl<'a> hil::nonvolatile_storage::NonvolatileStorage<'a> for NonvolatileStorageUser<'a> {
+    fn set_client(&self, client: &'a dyn hil::nonvolatile_storage::NonvolatileStorageClient) {
+        self.client.set(client);
+    }
+
+    fn read(
+        &self,
+        buffer: &'static mut [u8],
+        address: usize,
+        length: usize,
+    ) -> Result<(), ErrorCode> {
+        if !self.in_window(address, length) {
+            return Err(ErrorCode::INVAL);
+        }
+        self.enqueue(Op::Read(address, length), Some(buffer))
+    }
+
+    fn write(
+        &self,
+        buffer: &'static mut [u8],
+        address: usize,
+        length: usize,
+    ) -> Result<(), ErrorCode> {
+        if !self.in_window(address, length) {
+            return Err(ErrorCode::INVAL);
+        }
+        self.enqueue(Op::Write(address, length), Some(buffer))
+    }
+
+    fn sync(&self) -> Result<(), ErrorCode> {
+        self.enqueue(Op::Sync, None)
+    }
+
+    fn geometry(&self) -> Option<hil::nonvolatile_storage::StorageGeometry> {
+        self.mux.storage.geometry()
+    }
+
+    fn write_now(&self, address: usize, data: &[u8]) -> Result<(), ErrorCode> {
+        if !self.in_window(address, data.len()) {
+            return Err(ErrorCode::INVAL);
+        }
+        self.mux.storage.write_now(address, data)
+    }
+}
@@ -118,6 +118,23 @@ impl<'a, Spi: hil::spi::SpiMaster<'a>> MuxSpiMaster<'a, Spi> {
         }
     }
 
+    // Write `data` for the device with `configuration` before returning, if
+    // no device is transferring.
+    fn write_bytes_now(
+        &self,
+        configuration: SpiConfiguration<'a, Spi>,
+        data: &[u8],
+    ) -> Result<(), ErrorCode> {
+        if self.inflight.is_some() {
+            return Err(ErrorCode::BUSY);
+        }
+        self.spi.specify_chip_select(configuration.chip_select)?;
+        self.spi.set_rate(configuration.rate)?;
+        self.spi.set_polarity(configuration.polarity)?;
+        self.spi.set_phase(configuration.phase)?;
+        self.spi.write_bytes_now(data)
+    }
+
     /// Asynchronously executes the next operation, if any. Used by calls
     /// to trigger do_next_op such that it will execute after the call
     /// returns. This is important in case the operation triggers an error,
@@ -320,6 +337,13 @@ impl<'a, Spi: hil::spi::SpiMaster<'a>> hil::spi::SpiMasterDevice<'a>
     fn get_rate(&self) -> u32 {
         self.configuration.get().rate
     }
+
+    fn write_bytes_now(&self, data: &[u8]) -> Result<(), ErrorCode> {
+        if self.operation.get() != Op::Idle {
+            return Err(ErrorCode::BUSY);
+        }
+        self.mux.write_bytes_now(self.configuration.get(), data)
+    }
 }
 
 pub struct SpiSlaveDevice<'a, Spi: hil::spi::SpiSlave<'a>> {
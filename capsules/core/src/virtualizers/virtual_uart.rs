@@ -8,12 +8,26 @@
 //! most useful for `printf()` like applications where multiple things want to
 //! write to the same UART channel.
 //!
-//! Clients can choose if they want to receive. Incoming messages will be sent
-//! to all clients that have enabled receiving.
+//! Clients can choose if they want to receive. By default, incoming messages
+//! will be sent to all clients that have enabled receiving.
+//!
+//! Alternatively, the mux can be configured with
+//! [`ReceivePolicy::Prefixed`] so that the sender selects which client
+//! receives the following bytes. Each receiving `UartDevice` can be assigned a
+//! channel number with [`UartDevice::set_receive_channel`]. The escape byte
+//! followed by a channel number switches all following bytes to that channel,
+//! and the escape byte sent twice is delivered as a single literal escape byte.
+//! Devices without a channel receive the bytes of every channel, but never the
+//! framing bytes themselves. This lets, for example, the process console and
+//! the userspace console share one UART without racing for input.
 //!
 //! `MuxUart` provides shared access to a single UART bus for multiple users.
 //! `UartDevice` provides access for a single client.
 //!
+//! The mux keeps transmit statistics for each device (see [`TxStats`]) to help
+//! find clients that keep others from transmitting on busy boards. They can be
+//! printed with [`MuxUart::write_tx_stats`].
+//!
 //! Usage
 //! -----
 //!
@@ -47,6 +61,7 @@
 
 use core::cell::Cell;
 use core::cmp;
+use core::fmt;
 
 use kernel::collections::list::{List, ListLink, ListNode};
 use kernel::deferred_call::{DeferredCall, DeferredCallClient};
@@ -56,6 +71,75 @@ use kernel::ErrorCode;
 
 pub const RX_BUF_LEN: usize = 64;
 
+/// A transmit operation that waits for more than this many transmissions of
+/// other devices is counted as starved.
+pub const STARVATION_THRESHOLD: usize = 8;
+
+/// Transmit statistics of a single `UartDevice`.
+///
+/// Waiting is measured in transmissions of other devices, since the mux has
+/// no time source.
+#[derive(Copy, Clone, Default, PartialEq, Debug)]
+pub struct TxStats {
+    /// Bytes transmitted from buffers.
+    pub bytes: usize,
+    /// Started transmit operations, both buffers and words.
+    pub transactions: usize,
+    /// Most transmissions of other devices an operation waited for.
+    pub max_queue_wait: usize,
+    /// Operations that waited for more than `STARVATION_THRESHOLD`
+    /// transmissions of other devices.
+    pub starved: usize,
+}
+
+/// How received bytes are distributed among the receiving devices.
+#[derive(Copy, Clone, PartialEq)]
+pub enum ReceivePolicy {
+    /// Every received byte is copied to every receiving device.
+    Broadcast,
+    /// Received bytes are only delivered to devices on the currently selected
+    /// channel. `escape` followed by a channel number selects the channel,
+    /// `escape` followed by `escape` is a literal `escape` byte.
+    Prefixed { escape: u8 },
+}
+
+/// Receive framing state for [`ReceivePolicy::Prefixed`].
+#[derive(Copy, Clone, PartialEq)]
+struct FramingState {
+    /// Channel that data bytes currently belong to.
+    channel: u8,
+    /// Whether the previous byte was the escape byte.
+    escaped: bool,
+}
+
+impl FramingState {
+    const fn new() -> Self {
+        FramingState {
+            channel: 0,
+            escaped: false,
+        }
+    }
+
+    /// Advance the framing state by one received byte. Returns the channel the
+    /// byte should be delivered to, or `None` if it was a framing byte.
+    fn next(&mut self, escape: u8, byte: u8) -> Option<u8> {
+        if self.escaped {
+            self.escaped = false;
+            if byte == escape {
+                Some(self.channel)
+            } else {
+                self.channel = byte;
+                None
+            }
+        } else if byte == escape {
+            self.escaped = true;
+            None
+        } else {
+            Some(self.channel)
+        }
+    }
+}
+
 pub struct MuxUart<'a> {
     uart: &'a dyn uart::Uart<'a>,
     speed: u32,
@@ -64,6 +148,8 @@ pub struct MuxUart<'a> {
     buffer: TakeCell<'static, [u8]>,
     completing_read: Cell<bool>,
     deferred_call: DeferredCall,
+    receive_policy: Cell<ReceivePolicy>,
+    framing: Cell<FramingState>,
 }
 
 impl<'a> uart::TransmitClient for MuxUart<'a> {
@@ -75,6 +161,9 @@ impl<'a> uart::TransmitClient for MuxUart<'a> {
     ) {
         self.inflight.map(move |device| {
             self.inflight.clear();
+            let mut stats = device.tx_stats.get();
+            stats.bytes += tx_len;
+            device.tx_stats.set(stats);
             device.transmitted_buffer(tx_buffer, tx_len, rcode);
         });
         self.do_next_op();
@@ -113,18 +202,50 @@ impl<'a> uart::ReceiveClient for MuxUart<'a> {
                     // Copy the read into the buffer starting at rx_position
                     let position = device.rx_position.get();
                     let remaining = device.rx_len.get() - position;
-                    let len = cmp::min(rx_len, remaining);
-                    if state == UartDeviceReceiveState::Receiving
-                        || state == UartDeviceReceiveState::Aborting
-                    {
-                        // debug!("Have {} bytes, copying in bytes {}-{}, {} remain", rx_len, position, position + len, remaining);
-                        rxbuf[position..(len + position)].copy_from_slice(&buffer[..len]);
-                    }
+                    let copy = state == UartDeviceReceiveState::Receiving
+                        || state == UartDeviceReceiveState::Aborting;
+                    let len = match self.receive_policy.get() {
+                        ReceivePolicy::Broadcast => {
+                            let len = cmp::min(rx_len, remaining);
+                            if copy {
+                                // debug!("Have {} bytes, copying in bytes {}-{}, {} remain", rx_len, position, position + len, remaining);
+                                rxbuf[position..(len + position)].copy_from_slice(&buffer[..len]);
+                            }
+                            len
+                        }
+                        ReceivePolicy::Prefixed { escape } => {
+                            // Replay the framing from the state at the start
+                            // of this read, delivering only this device's bytes.
+                            let mut framing = self.framing.get();
+                            let mut len = 0;
+                            for &byte in buffer[..rx_len].iter() {
+                                if let Some(channel) = framing.next(escape, byte) {
+                                    if len < remaining && device.accepts_channel(channel) {
+                                        if copy {
+                                            rxbuf[position + len] = byte;
+                                        }
+                                        len += 1;
+                                    }
+                                }
+                            }
+                            len
+                        }
+                    };
                     device.rx_position.set(position + len);
                     device.rx_buffer.replace(rxbuf);
                 });
             }
         });
+
+        // Now that every device has its bytes, move the shared framing state
+        // past this read.
+        if let ReceivePolicy::Prefixed { escape } = self.receive_policy.get() {
+            let mut framing = self.framing.get();
+            for &byte in buffer[..rx_len].iter() {
+                let _ = framing.next(escape, byte);
+            }
+            self.framing.set(framing);
+        }
         // If the underlying read completes a client read, issue a callback to
         // that client. In the meanwhile, compute the length of the next
         // underlying UART read as the shortest outstanding read, including and
@@ -220,9 +341,18 @@ impl<'a> MuxUart<'a> {
             buffer: TakeCell::new(buffer),
             completing_read: Cell::new(false),
             deferred_call: DeferredCall::new(),
+            receive_policy: Cell::new(ReceivePolicy::Broadcast),
+            framing: Cell::new(FramingState::new()),
         }
     }
 
+    /// Set how received bytes are distributed among the devices. Changing the
+    /// policy resets the selected channel to 0.
+    pub fn set_receive_policy(&self, policy: ReceivePolicy) {
+        self.receive_policy.set(policy);
+        self.framing.set(FramingState::new());
+    }
+
     pub fn initialize(&self) {
         let _ = self.uart.configure(uart::Parameters {
             baud_rate: self.speed,
@@ -233,10 +363,47 @@ impl<'a> MuxUart<'a> {
         });
     }
 
+    /// Write the transmit statistics of every device to `writer`, one line
+    /// per device.
+    pub fn write_tx_stats(&self, writer: &mut dyn fmt::Write) -> fmt::Result {
+        for (index, device) in self.devices.iter().enumerate() {
+            let stats = device.tx_stats();
+            write!(writer, "uart device {}", index)?;
+            if let Some(label) = device.label.get() {
+                write!(writer, " ({})", label)?;
+            }
+            writeln!(
+                writer,
+                ": {} bytes, {} transactions, max wait {}, starved {}",
+                stats.bytes, stats.transactions, stats.max_queue_wait, stats.starved
+            )?;
+        }
+        Ok(())
+    }
+
+    /// Update the statistics when `node` gets to transmit.
+    fn record_tx_start(&self, node: &UartDevice<'a>) {
+        let waited = node.tx_waited.replace(0);
+        let mut stats = node.tx_stats.get();
+        stats.transactions += 1;
+        stats.max_queue_wait = cmp::max(stats.max_queue_wait, waited);
+        if waited > STARVATION_THRESHOLD {
+            stats.starved += 1;
+        }
+        node.tx_stats.set(stats);
+
+        // Everyone else with a pending operation has to wait another round.
+        self.devices
+            .iter()
+            .filter(|other| !core::ptr::eq(*other, node) && other.operation.is_some())
+            .for_each(|other| other.tx_waited.set(other.tx_waited.get() + 1));
+    }
+
     fn do_next_op(&self) {
         if self.inflight.is_none() {
             let mnode = self.devices.iter().find(|node| node.operation.is_some());
             mnode.map(|node| {
+                self.record_tx_start(node);
                 node.tx_buffer.take().map(|buf| {
                     node.operation.take().map(move |op| match op {
                         Operation::Transmit { len } => match self.uart.transmit_buffer(buf, len) {
@@ -349,6 +516,15 @@ pub struct UartDevice<'a> {
     next: ListLink<'a, UartDevice<'a>>,
     rx_client: OptionalCell<&'a dyn uart::ReceiveClient>,
     tx_client: OptionalCell<&'a dyn uart::TransmitClient>,
+    /// Channel this device receives when the mux uses
+    /// `ReceivePolicy::Prefixed`. Devices without a channel receive all
+    /// channels.
+    rx_channel: OptionalCell<u8>,
+    /// Name used for this device by `MuxUart::write_tx_stats`.
+    label: OptionalCell<&'static str>,
+    tx_stats: Cell<TxStats>,
+    /// Transmissions of other devices since the pending operation was queued.
+    tx_waited: Cell<usize>,
 }
 
 impl<'a> UartDevice<'a> {
@@ -366,6 +542,10 @@ impl<'a> UartDevice<'a> {
             next: ListLink::empty(),
             rx_client: OptionalCell::empty(),
             tx_client: OptionalCell::empty(),
+            rx_channel: OptionalCell::empty(),
+            label: OptionalCell::empty(),
+            tx_stats: Cell::new(TxStats::default()),
+            tx_waited: Cell::new(0),
         }
     }
 
@@ -373,6 +553,27 @@ impl<'a> UartDevice<'a> {
     pub fn setup(&'a self) {
         self.mux.devices.push_head(self);
     }
+
+    /// Only receive bytes sent on `channel` when the mux uses
+    /// `ReceivePolicy::Prefixed`. Has no effect with `ReceivePolicy::Broadcast`.
+    pub fn set_receive_channel(&self, channel: u8) {
+        self.rx_channel.set(channel);
+    }
+
+    /// Name this device in the output of `MuxUart::write_tx_stats`.
+    pub fn set_label(&self, label: &'static str) {
+        self.label.set(label);
+    }
+
+    /// Transmit statistics of this device.
+    pub fn tx_stats(&self) -> TxStats {
+        self.tx_stats.get()
+    }
+
+    fn accepts_channel(&self, channel: u8) -> bool {
+        self.rx_channel
+            .map_or(true, |rx_channel| rx_channel == channel)
+    }
 }
 
 impl<'a> uart::TransmitClient for UartDevice<'a> {
@@ -494,3 +695,39 @@ impl<'a> uart::Receive<'a> for UartDevice<'a> {
         Err(ErrorCode::FAIL)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::FramingState;
+
+    const ESC: u8 = 0x10;
+
+    fn channels(bytes: &[u8]) -> [Option<u8>; 8] {
+        let mut framing = FramingState::new();
+        let mut out = [None; 8];
+        for (i, b) in bytes.iter().enumerate() {
+            out[i] = framing.next(ESC, *b);
+        }
+        out
+    }
+
+    #[test]
+    fn framing_defaults_to_channel_zero() {
+        assert_eq!(channels(b"ab")[..2], [Some(0), Some(0)]);
+    }
+
+    #[test]
+    fn framing_switches_channel() {
+        let out = channels(&[b'a', ESC, 2, b'b', ESC, 0, b'c']);
+        assert_eq!(
+            out[..7],
+            [Some(0), None, None, Some(2), None, None, Some(0)]
+        );
+    }
+
+    #[test]
+    fn framing_escaped_escape_is_data() {
+        let out = channels(&[ESC, 1, ESC, ESC, b'x']);
+        assert_eq!(out[..5], [None, None, None, Some(1), Some(1)]);
+    }
+}
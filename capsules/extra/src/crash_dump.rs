@@ -0,0 +1,204 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2024.
+
+//! Save panic output, such as process state, to a nonvolatile crash region.
+//!
+//! `NonvolatileCrashDump` appends text to a region of nonvolatile storage,
+//! usually a window of the kernel region from the nonvolatile storage
+//! virtualizer. The text in the region is always terminated with a zero byte,
+//! so it can be read back after the next boot even if a later dump was
+//! shorter.
+//!
+//! Storage operations complete asynchronously, but after a panic the kernel
+//! loop no longer runs. The panic handler therefore writes through a
+//! [`CrashDumpWriter`], which is given a function that services interrupts
+//! and deferred calls. Each time the writer's buffer is full it hands the
+//! buffer to the storage and calls that function until the write is done.
+//! Output that does not fit in the region is dropped.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust,ignore
+//! let crash_dump = components::crash_dump::NonvolatileCrashDumpComponent::new(
+//!     crash_dump_storage,
+//!     0x60000,
+//!     0x1000,
+//! )
+//! .finalize(components::nonvolatile_crash_dump_component_static!());
+//! CRASH_DUMP = Some(crash_dump);
+//! ```
+//!
+//! And in the board's panic handler:
+//!
+//! ```rust,ignore
+//! let poll = || {
+//!     chip.service_pending_interrupts();
+//!     while DeferredCall::has_tasks() {
+//!         DeferredCall::service_next_pending();
+//!     }
+//! };
+//! let mut writer = crash_dump.writer(&poll);
+//! kernel::debug::print_process_info(&*addr_of!(PROCESSES), process_printer, &mut writer);
+//! writer.finish();
+//! ```
+
+use core::cell::Cell;
+use core::cmp;
+
+use kernel::debug::IoWrite;
+use kernel::hil;
+use kernel::utilities::cells::TakeCell;
+use kernel::ErrorCode;
+
+/// Default length of the buffer output is collected in before it is written.
+pub const BUF_LEN: usize = 256;
+
+/// How many times a writer calls its poll function while waiting for one
+/// write to finish before giving up.
+const MAX_POLLS: usize = 1_000_000;
+
+pub struct NonvolatileCrashDump<'a> {
+    storage: &'a dyn hil::nonvolatile_storage::NonvolatileStorage<'a>,
+    start: usize,
+    length: usize,
+    buffer: TakeCell<'static, [u8]>,
+    // Where the next output goes in the region.
+    position: Cell<usize>,
+    // How many bytes of `buffer` hold output that was not written yet.
+    buffered: Cell<usize>,
+    writing: Cell<bool>,
+}
+
+impl<'a> NonvolatileCrashDump<'a> {
+    /// Append to the `length` bytes of `storage` at `start`. `buffer` must be
+    /// at least 2 bytes long.
+    pub fn new(
+        storage: &'a dyn hil::nonvolatile_storage::NonvolatileStorage<'a>,
+        start: usize,
+        length: usize,
+        buffer: &'static mut [u8],
+    ) -> NonvolatileCrashDump<'a> {
+        NonvolatileCrashDump {
+            storage,
+            start,
+            length,
+            buffer: TakeCell::new(buffer),
+            position: Cell::new(0),
+            buffered: Cell::new(0),
+            writing: Cell::new(false),
+        }
+    }
+
+    /// Get a writer that appends to the region. `poll` must service
+    /// interrupts and deferred calls until the storage can complete a write.
+    pub fn writer<'b>(&'b self, poll: &'b dyn Fn()) -> CrashDumpWriter<'a, 'b> {
+        CrashDumpWriter { dump: self, poll }
+    }
+
+    /// Start the next dump at the beginning of the region again.
+    pub fn reset(&self) {
+        self.position.set(0);
+        self.buffered.set(0);
+    }
+
+    // Copy as much of `data` into the buffer as fits in it and in the region,
+    // keeping room for the terminating zero.
+    fn append(&self, data: &[u8]) -> usize {
+        let buffered = self.buffered.get();
+        let region_left = self
+            .length
+            .saturating_sub(self.position.get() + buffered + 1);
+        self.buffer.map_or(0, |buffer| {
+            let count = cmp::min(
+                data.len(),
+                cmp::min(region_left, buffer.len().saturating_sub(buffered + 1)),
+            );
+            buffer[buffered..buffered + count].copy_from_slice(&data[..count]);
+            self.buffered.set(buffered + count);
+            count
+        })
+    }
+
+    // Write the buffered output and the terminating zero after it.
+    fn flush(&self) -> Result<(), ErrorCode> {
+        let buffered = self.buffered.get();
+        if buffered == 0 {
+            return Ok(());
+        }
+        let buffer = self.buffer.take().ok_or(ErrorCode::BUSY)?;
+        buffer[buffered] = 0;
+        self.writing.set(true);
+        self.storage
+            .write(buffer, self.start + self.position.get(), buffered + 1)
+            .inspect_err(|_| {
+                // The buffer is not returned on error, and nothing more can
+                // be saved.
+                self.writing.set(false);
+            })
+    }
+}
+
+impl hil::nonvolatile_storage::NonvolatileStorageClient for NonvolatileCrashDump<'_> {
+    fn read_done(&self, buffer: &'static mut [u8], _length: usize) {
+        self.buffer.replace(buffer);
+    }
+
+    fn write_done(&self, buffer: &'static mut [u8], length: usize) {
+        self.buffer.replace(buffer);
+        self.writing.set(false);
+        if length > 0 {
+            // The terminating zero is overwritten by the next output.
+            self.position.set(self.position.get() + length - 1);
+        }
+        self.buffered.set(0);
+    }
+}
+
+/// Synchronous writer for a [`NonvolatileCrashDump`], for use after a panic.
+pub struct CrashDumpWriter<'a, 'b> {
+    dump: &'b NonvolatileCrashDump<'a>,
+    poll: &'b dyn Fn(),
+}
+
+impl CrashDumpWriter<'_, '_> {
+    // Write the buffered output and wait for the storage to finish.
+    fn flush_and_wait(&self) -> Result<(), ErrorCode> {
+        self.dump.flush()?;
+        for _ in 0..MAX_POLLS {
+            if !self.dump.writing.get() {
+                return Ok(());
+            }
+            (self.poll)();
+        }
+        Err(ErrorCode::FAIL)
+    }
+
+    /// Write any output that is still buffered.
+    pub fn finish(self) -> Result<(), ErrorCode> {
+        self.flush_and_wait()
+    }
+}
+
+impl IoWrite for CrashDumpWriter<'_, '_> {
+    fn write(&mut self, buf: &[u8]) -> usize {
+        let mut written = 0;
+        while written < buf.len() {
+            let count = self.dump.append(&buf[written..]);
+            written += count;
+            if count == 0 && (self.dump.buffered.get() == 0 || self.flush_and_wait().is_err()) {
+                // The region is full or the storage failed.
+                break;
+            }
+        }
+        written
+    }
+}
+
+impl core::fmt::Write for CrashDumpWriter<'_, '_> {
+    fn write_str(&mut self, s: &str) -> core::fmt::Result {
+        IoWrite::write(self, s.as_bytes());
+        Ok(())
+    }
+}
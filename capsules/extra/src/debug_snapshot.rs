@@ -0,0 +1,100 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2024.
+
+//! Provides a read-only snapshot of the kernel debug output to userspace.
+//!
+//! `debug!()` output is queued in the debug writer's ring buffer until the
+//! debug UART sends it. This capsule lets an app copy the queued output,
+//! oldest byte first, without removing it, so a companion app can forward
+//! kernel logs over another link on boards without an accessible UART.
+//!
+//! Kernel logs can contain sensitive information. Boards should only grant
+//! this driver to a trusted app, for example with a syscall filter such as
+//! `TbfHeaderFilterDefaultAllow`.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust,ignore
+//! let debug_snapshot = components::debug_snapshot::DebugSnapshotComponent::new(
+//!     board_kernel,
+//!     capsules_extra::debug_snapshot::DRIVER_NUM,
+//! )
+//! .finalize(components::debug_snapshot_component_static!());
+//! ```
+
+/// Syscall driver number.
+use capsules_core::driver;
+pub const DRIVER_NUM: usize = driver::NUM::DebugSnapshot as usize;
+
+use kernel::grant::{AllowRoCount, AllowRwCount, Grant, UpcallCount};
+use kernel::processbuffer::WriteableProcessBuffer;
+use kernel::syscall::{CommandReturn, SyscallDriver};
+use kernel::{ErrorCode, ProcessId};
+
+/// Ids for read-write allow buffers
+mod rw_allow {
+    /// Buffer the debug output is copied into.
+    pub const OUTPUT: usize = 0;
+    /// The number of allow buffers the kernel stores for this grant
+    pub const COUNT: u8 = 1;
+}
+
+#[derive(Default)]
+pub struct App;
+
+pub struct DebugSnapshot {
+    apps: Grant<App, UpcallCount<0>, AllowRoCount<0>, AllowRwCount<{ rw_allow::COUNT }>>,
+}
+
+impl DebugSnapshot {
+    pub fn new(
+        grant: Grant<App, UpcallCount<0>, AllowRoCount<0>, AllowRwCount<{ rw_allow::COUNT }>>,
+    ) -> Self {
+        Self { apps: grant }
+    }
+}
+
+impl SyscallDriver for DebugSnapshot {
+    /// Read the kernel debug output.
+    ///
+    /// ### `command_num`
+    ///
+    /// - `0`: Driver check.
+    /// - `1`: Copy the debug output that has not been transmitted yet into the
+    ///   allowed buffer. Returns the number of bytes copied.
+    /// - `2`: Return the number of bytes that can still be queued before the
+    ///   debug buffer is full.
+    fn command(
+        &self,
+        command_num: usize,
+        _data: usize,
+        _: usize,
+        processid: ProcessId,
+    ) -> CommandReturn {
+        match command_num {
+            0 => CommandReturn::success(),
+
+            1 => self
+                .apps
+                .enter(processid, |_, kernel_data| {
+                    kernel_data
+                        .get_readwrite_processbuffer(rw_allow::OUTPUT)
+                        .and_then(|output| output.mut_enter(kernel::debug::debug_snapshot))
+                        .map_or(CommandReturn::failure(ErrorCode::RESERVE), |count| {
+                            CommandReturn::success_u32(count as u32)
+                        })
+                })
+                .unwrap_or_else(|err| CommandReturn::failure(err.into())),
+
+            2 => CommandReturn::success_u32(kernel::debug::debug_available_len() as u32),
+
+            _ => CommandReturn::failure(ErrorCode::NOSUPPORT),
+        }
+    }
+
+    fn allocate_grant(&self, processid: ProcessId) -> Result<(), kernel::process::Error> {
+        self.apps.enter(processid, |_, _| {})
+    }
+}
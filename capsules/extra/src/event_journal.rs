@@ -0,0 +1,422 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2024.
+
+//! Persistent journal of kernel events.
+//!
+//! Kernel subsystems record compact, timestamped events (process faults,
+//! watchdog resets, storage errors, brown-outs) with
+//! [`EventRecorder::record`]. The journal appends each event to a circular
+//! [`hil::log`](kernel::hil::log) in its own flash volume, so once the volume
+//! is full the oldest events are overwritten. A diagnostics app can later page
+//! through the events that survived.
+//!
+//! Events are queued in RAM and written one at a time, and the log is synced
+//! after each one. If the queue is full the event is dropped and counted.
+//!
+//! Record Format
+//! -------------
+//!
+//! Every event is stored, and handed to userspace, as a `RECORD_LEN` byte
+//! record with little-endian fields:
+//!
+//! ```text
+//! 0      2      4              8              12
+//! +------+------+--------------+--------------+
+//! | kind | code | timestamp_ms | data         |
+//! +------+------+--------------+--------------+
+//! ```
+//!
+//! `timestamp_ms` is the time since boot, and wraps with the underlying
+//! timer. `code` and `data` depend on the kind of event.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust,ignore
+//! storage_volume!(EVENT_JOURNAL, 8);
+//!
+//! let journal_log = static_init!(
+//!     capsules_extra::log::Log<'static, nrf52840::nvmc::Nvmc>,
+//!     capsules_extra::log::Log::new(&EVENT_JOURNAL, &base_peripherals.nvmc, pagebuffer, true)
+//! );
+//! kernel::deferred_call::DeferredCallClient::register(journal_log);
+//! hil::flash::HasClient::set_client(&base_peripherals.nvmc, journal_log);
+//!
+//! let journal = components::event_journal::EventJournalComponent::new(
+//!     board_kernel,
+//!     capsules_extra::event_journal::DRIVER_NUM,
+//!     journal_log,
+//!     &base_peripherals.rtc,
+//! )
+//! .finalize(components::event_journal_component_static!(
+//!     capsules_extra::log::Log<'static, nrf52840::nvmc::Nvmc>,
+//!     nrf52::rtc::Rtc,
+//!     8,
+//! ));
+//! ```
+
+use core::cell::Cell;
+
+use kernel::collections::queue::Queue;
+use kernel::collections::ring_buffer::RingBuffer;
+use kernel::errorcode::into_statuscode;
+use kernel::grant::{AllowRoCount, AllowRwCount, Grant, UpcallCount};
+use kernel::hil::log::{LogRead, LogReadClient, LogWrite, LogWriteClient};
+use kernel::hil::time::{ConvertTicks, Time};
+use kernel::process::{self, Process, ProcessFaultPolicy, ShortId};
+use kernel::processbuffer::WriteableProcessBuffer;
+use kernel::syscall::{CommandReturn, SyscallDriver};
+use kernel::utilities::cells::{MapCell, OptionalCell, TakeCell};
+use kernel::{ErrorCode, ProcessId};
+
+/// Syscall driver number.
+use capsules_core::driver;
+pub const DRIVER_NUM: usize = driver::NUM::EventJournal as usize;
+
+/// Length of one event record in the log and in userspace buffers.
+pub const RECORD_LEN: usize = 12;
+
+/// IDs for subscribed upcalls.
+mod upcall {
+    /// The next event was copied into the allowed buffer.
+    pub const READ_DONE: usize = 0;
+    /// Reading was moved back to the oldest event.
+    pub const REWIND_DONE: usize = 1;
+    /// Number of upcalls.
+    pub const COUNT: u8 = 2;
+}
+
+/// Ids for read-write allow buffers
+mod rw_allow {
+    /// Buffer the next event is copied into.
+    pub const EVENT: usize = 0;
+    /// The number of allow buffers the kernel stores for this grant
+    pub const COUNT: u8 = 1;
+}
+
+/// Kinds of events the journal records.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum EventKind {
+    /// A process faulted. `code` is the action taken (0 panic, 1 restart,
+    /// 2 stop) and `data` the fixed `ShortId` of the app, or 0.
+    ProcessFault = 1,
+    /// The chip was reset by its watchdog.
+    WatchdogReset = 2,
+    /// A storage operation failed. `code` is the `ErrorCode`.
+    StorageError = 3,
+    /// The chip detected a brown-out.
+    BrownOut = 4,
+}
+
+/// One journal entry.
+#[derive(Clone, Copy, Default)]
+pub struct Event {
+    kind: u16,
+    code: u16,
+    timestamp_ms: u32,
+    data: u32,
+}
+
+impl Event {
+    fn encode(&self, buffer: &mut [u8]) {
+        buffer[0..2].copy_from_slice(&self.kind.to_le_bytes());
+        buffer[2..4].copy_from_slice(&self.code.to_le_bytes());
+        buffer[4..8].copy_from_slice(&self.timestamp_ms.to_le_bytes());
+        buffer[8..12].copy_from_slice(&self.data.to_le_bytes());
+    }
+}
+
+/// Interface for kernel code to record events without naming the journal's
+/// log and timer types.
+pub trait EventRecorder {
+    /// Record an event. Returns `NOMEM` if too many events are waiting to be
+    /// written, in which case the event is dropped.
+    fn record(&self, kind: EventKind, code: u16, data: u32) -> Result<(), ErrorCode>;
+}
+
+#[derive(Default)]
+pub struct App {}
+
+pub struct EventJournal<'a, L: LogRead<'a> + LogWrite<'a>, T: Time> {
+    log: &'a L,
+    time: &'a T,
+    apps: Grant<
+        App,
+        UpcallCount<{ upcall::COUNT }>,
+        AllowRoCount<0>,
+        AllowRwCount<{ rw_allow::COUNT }>,
+    >,
+    // Events waiting to be appended to the log.
+    pending: MapCell<RingBuffer<'static, Event>>,
+    // Event taken from the queue that the log was too busy to accept.
+    unwritten: OptionalCell<Event>,
+    write_buffer: TakeCell<'static, [u8]>,
+    read_buffer: TakeCell<'static, [u8]>,
+    // An append and the sync after it are in progress.
+    writing: Cell<bool>,
+    // Events dropped because the queue was full or the append failed.
+    dropped: Cell<u32>,
+    // Process waiting for a read or rewind to finish.
+    reader: OptionalCell<ProcessId>,
+}
+
+impl<'a, L: LogRead<'a, EntryID = usize> + LogWrite<'a>, T: Time> EventJournal<'a, L, T> {
+    pub fn new(
+        log: &'a L,
+        time: &'a T,
+        grant: Grant<
+            App,
+            UpcallCount<{ upcall::COUNT }>,
+            AllowRoCount<0>,
+            AllowRwCount<{ rw_allow::COUNT }>,
+        >,
+        pending: RingBuffer<'static, Event>,
+        write_buffer: &'static mut [u8; RECORD_LEN],
+        read_buffer: &'static mut [u8; RECORD_LEN],
+    ) -> Self {
+        Self {
+            log,
+            time,
+            apps: grant,
+            pending: MapCell::new(pending),
+            unwritten: OptionalCell::empty(),
+            write_buffer: TakeCell::new(write_buffer),
+            read_buffer: TakeCell::new(read_buffer),
+            writing: Cell::new(false),
+            dropped: Cell::new(0),
+            reader: OptionalCell::empty(),
+        }
+    }
+
+    /// Number of events dropped since boot.
+    pub fn dropped_events(&self) -> u32 {
+        self.dropped.get()
+    }
+
+    fn drop_event(&self) {
+        self.dropped.set(self.dropped.get().saturating_add(1));
+    }
+
+    // Start appending the oldest queued event, unless a write is already in
+    // progress. If the log is busy with a read this is retried when the read
+    // finishes.
+    fn append_next(&self) {
+        if self.writing.get() {
+            return;
+        }
+        let event = match self
+            .unwritten
+            .take()
+            .or_else(|| self.pending.map(|pending| pending.dequeue()).flatten())
+        {
+            Some(event) => event,
+            None => return,
+        };
+        self.write_buffer.take().map(|buffer| {
+            event.encode(buffer);
+            match self.log.append(buffer, RECORD_LEN) {
+                Ok(()) => self.writing.set(true),
+                Err((ErrorCode::BUSY, buffer)) => {
+                    self.write_buffer.replace(buffer);
+                    self.unwritten.set(event);
+                }
+                Err((_, buffer)) => {
+                    self.write_buffer.replace(buffer);
+                    self.drop_event();
+                }
+            }
+        });
+    }
+
+    fn read_next(&self, processid: ProcessId) -> Result<(), ErrorCode> {
+        if self.reader.is_some() {
+            return Err(ErrorCode::BUSY);
+        }
+        let buffer = self.read_buffer.take().ok_or(ErrorCode::BUSY)?;
+        match self.log.read(buffer, RECORD_LEN) {
+            Ok(()) => {
+                self.reader.set(processid);
+                Ok(())
+            }
+            Err((e, buffer)) => {
+                self.read_buffer.replace(buffer);
+                Err(e)
+            }
+        }
+    }
+
+    fn rewind(&self, processid: ProcessId) -> Result<(), ErrorCode> {
+        if self.reader.is_some() {
+            return Err(ErrorCode::BUSY);
+        }
+        self.log.seek(self.log.log_start())?;
+        self.reader.set(processid);
+        Ok(())
+    }
+}
+
+impl<'a, L: LogRead<'a, EntryID = usize> + LogWrite<'a>, T: Time> EventRecorder
+    for EventJournal<'a, L, T>
+{
+    fn record(&self, kind: EventKind, code: u16, data: u32) -> Result<(), ErrorCode> {
+        let event = Event {
+            kind: kind as u16,
+            code,
+            timestamp_ms: self.time.ticks_to_ms(self.time.now()),
+            data,
+        };
+        let queued = self.pending.map_or(false, |pending| pending.enqueue(event));
+        if !queued {
+            self.drop_event();
+            return Err(ErrorCode::NOMEM);
+        }
+        self.append_next();
+        Ok(())
+    }
+}
+
+impl<'a, L: LogRead<'a, EntryID = usize> + LogWrite<'a>, T: Time> LogWriteClient
+    for EventJournal<'a, L, T>
+{
+    fn append_done(
+        &self,
+        buffer: &'static mut [u8],
+        _length: usize,
+        _records_lost: bool,
+        error: Result<(), ErrorCode>,
+    ) {
+        self.write_buffer.replace(buffer);
+        if error.is_err() {
+            self.drop_event();
+        }
+        if error.is_err() || self.log.sync().is_err() {
+            self.writing.set(false);
+            self.append_next();
+        }
+    }
+
+    fn sync_done(&self, _error: Result<(), ErrorCode>) {
+        self.writing.set(false);
+        self.append_next();
+    }
+
+    fn erase_done(&self, _error: Result<(), ErrorCode>) {}
+}
+
+impl<'a, L: LogRead<'a, EntryID = usize> + LogWrite<'a>, T: Time> LogReadClient
+    for EventJournal<'a, L, T>
+{
+    fn read_done(&self, buffer: &'static mut [u8], length: usize, error: Result<(), ErrorCode>) {
+        self.reader.take().map(|processid| {
+            let _ = self.apps.enter(processid, |_, kernel_data| {
+                let copied = kernel_data
+                    .get_readwrite_processbuffer(rw_allow::EVENT)
+                    .and_then(|event| {
+                        event.mut_enter(|event| {
+                            let len = core::cmp::min(event.len(), length);
+                            event[..len].copy_from_slice(&buffer[..len]);
+                            len
+                        })
+                    })
+                    .unwrap_or(0);
+                let result = error.and(if copied == RECORD_LEN {
+                    Ok(())
+                } else {
+                    Err(ErrorCode::SIZE)
+                });
+                kernel_data
+                    .schedule_upcall(upcall::READ_DONE, (into_statuscode(result), copied, 0))
+                    .ok();
+            });
+        });
+        self.read_buffer.replace(buffer);
+        // An append may have been deferred while the log was reading.
+        self.append_next();
+    }
+
+    fn seek_done(&self, error: Result<(), ErrorCode>) {
+        self.reader.take().map(|processid| {
+            let _ = self.apps.enter(processid, |_, kernel_data| {
+                kernel_data
+                    .schedule_upcall(upcall::REWIND_DONE, (into_statuscode(error), 0, 0))
+                    .ok();
+            });
+        });
+        self.append_next();
+    }
+}
+
+impl<'a, L: LogRead<'a, EntryID = usize> + LogWrite<'a>, T: Time> SyscallDriver
+    for EventJournal<'a, L, T>
+{
+    /// Command interface.
+    ///
+    /// ### `command_num`
+    ///
+    /// - `0`: Return Ok(()) if this driver is included on the platform.
+    /// - `1`: Move reading back to the oldest event in the journal. Completes
+    ///   with the rewind upcall.
+    /// - `2`: Copy the next event into the allowed buffer. Completes with the
+    ///   read upcall, which carries the number of bytes copied. Returns `FAIL`
+    ///   once all events have been read.
+    /// - `3`: Return the number of events dropped since boot.
+    ///
+    /// Only one read or rewind can be in progress at a time. Either returns
+    /// `BUSY` while the journal is writing an event; try again later.
+    fn command(
+        &self,
+        command_num: usize,
+        _arg1: usize,
+        _arg2: usize,
+        processid: ProcessId,
+    ) -> CommandReturn {
+        match command_num {
+            0 => CommandReturn::success(),
+
+            1 => self.rewind(processid).into(),
+
+            2 => self.read_next(processid).into(),
+
+            3 => CommandReturn::success_u32(self.dropped.get()),
+
+            _ => CommandReturn::failure(ErrorCode::NOSUPPORT),
+        }
+    }
+
+    fn allocate_grant(&self, processid: ProcessId) -> Result<(), kernel::process::Error> {
+        self.apps.enter(processid, |_, _| {})
+    }
+}
+
+/// Process fault policy that records each fault in the event journal and
+/// then defers to another policy for the action to take.
+pub struct JournalingFaultPolicy<'a, P: ProcessFaultPolicy, R: EventRecorder> {
+    policy: P,
+    journal: &'a R,
+}
+
+impl<'a, P: ProcessFaultPolicy, R: EventRecorder> JournalingFaultPolicy<'a, P, R> {
+    pub fn new(policy: P, journal: &'a R) -> Self {
+        Self { policy, journal }
+    }
+}
+
+impl<P: ProcessFaultPolicy, R: EventRecorder> ProcessFaultPolicy
+    for JournalingFaultPolicy<'_, P, R>
+{
+    fn action(&self, process: &dyn Process) -> process::FaultAction {
+        let action = self.policy.action(process);
+        let code = match action {
+            process::FaultAction::Panic => 0,
+            process::FaultAction::Restart => 1,
+            process::FaultAction::Stop => 2,
+        };
+        let short_id = match process.short_app_id() {
+            ShortId::Fixed(id) => id.get(),
+            ShortId::LocallyUnique => 0,
+        };
+        let _ = self.journal.record(EventKind::ProcessFault, code, short_id);
+        action
+    }
+}
@@ -43,6 +43,15 @@
 //! this driver to work with capsules like the `nonvolatile_storage_driver`
 //! that provide virtualization and a userspace interface. The second is a
 //! custom interface that exposes other chip-specific functions.
+//!
+//! FRAM has no erase cycle and can be written one byte at a time, which the
+//! generic interface reports through its geometry. Capsules that check writes
+//! against the geometry, like the `nonvolatile_storage_driver`, then pass any
+//! write straight through, with no alignment or erase block restrictions.
+//!
+//! FRAM also writes in place as fast as the bus runs, so the generic interface
+//! supports `write_now` for saving a few bytes before returning, on SPI
+//! controllers that can write synchronously.
 
 use core::cell::Cell;
 use core::cmp;
@@ -53,6 +62,9 @@ use kernel::ErrorCode;
 
 pub const BUF_LEN: usize = 512;
 
+/// Size of the FM25CL64B in bytes.
+pub const SIZE: usize = 8192;
+
 const SPI_SPEED: u32 = 4000000;
 
 #[allow(dead_code)]
@@ -328,6 +340,9 @@ impl<'a, S: hil::spi::SpiMasterDevice<'a>> hil::nonvolatile_storage::Nonvolatile
         address: usize,
         length: usize,
     ) -> Result<(), ErrorCode> {
+        if address.checked_add(length).map_or(true, |end| end > SIZE) {
+            return Err(ErrorCode::INVAL);
+        }
         self.read(address as u16, buffer, length as u16)
     }
 
@@ -337,6 +352,46 @@ impl<'a, S: hil::spi::SpiMasterDevice<'a>> hil::nonvolatile_storage::Nonvolatile
         address: usize,
         length: usize,
     ) -> Result<(), ErrorCode> {
+        if address.checked_add(length).map_or(true, |end| end > SIZE) {
+            return Err(ErrorCode::INVAL);
+        }
         self.write(address as u16, buffer, length as u16)
     }
+
+    fn write_now(&self, address: usize, data: &[u8]) -> Result<(), ErrorCode> {
+        if address
+            .checked_add(data.len())
+            .map_or(true, |end| end > SIZE)
+        {
+            return Err(ErrorCode::INVAL);
+        }
+        if self.state.get() != State::Idle {
+            return Err(ErrorCode::BUSY);
+        }
+        self.configure_spi()?;
+
+        self.txbuffer.map_or(Err(ErrorCode::RESERVE), |txbuffer| {
+            // The opcode and address come before the data.
+            let length = data.len() + 3;
+            if length > txbuffer.len() {
+                return Err(ErrorCode::SIZE);
+            }
+            // The write enable latches when the chip select is raised after
+            // it, so it is a transfer of its own.
+            self.spi.write_bytes_now(&[Opcodes::WriteEnable as u8])?;
+            txbuffer[0] = Opcodes::WriteMemory as u8;
+            txbuffer[1] = ((address >> 8) & 0xFF) as u8;
+            txbuffer[2] = (address & 0xFF) as u8;
+            txbuffer[3..length].copy_from_slice(data);
+            self.spi.write_bytes_now(&txbuffer[..length])
+        })
+    }
+
+    fn geometry(&self) -> Option<hil::nonvolatile_storage::StorageGeometry> {
+        Some(hil::nonvolatile_storage::StorageGeometry {
+            erase_block_size: 1,
+            write_granularity: 1,
+            total_size: SIZE,
+        })
+    }
 }
@@ -0,0 +1,293 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2024.
+
+//! Driver for 24xx-style I2C EEPROMs, such as the 24LC256 and AT24C256.
+//!
+//! Provides the byte-addressed `NonvolatileStorage` interface directly, so it
+//! can back the nonvolatile storage driver without a page layer in between.
+//! It works with EEPROMs that take a two-byte memory address, which covers
+//! parts from 4 KiB (24C32) to 64 KiB (24C512).
+//!
+//! Writes are split at the EEPROM's page boundaries, since a page write that
+//! crosses one wraps around to the start of the page. After each page the
+//! EEPROM is busy with its internal write cycle and does not acknowledge its
+//! address. The driver polls it until it acknowledges again before writing the
+//! next page or reporting the write done, so a finished write is durable.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust,ignore
+//! let eeprom = components::i2c_eeprom::I2CEepromComponent::new(
+//!     mux_i2c,
+//!     0x50,   // I2C address
+//!     64,     // Page size
+//!     0x8000, // Total size
+//! )
+//! .finalize(components::i2c_eeprom_component_static!(nrf52840::i2c::TWI, 66));
+//!
+//! let storage_buffer = static_init!(
+//!     [u8; capsules_extra::nonvolatile_storage_driver::BUF_LEN],
+//!     [0; capsules_extra::nonvolatile_storage_driver::BUF_LEN]
+//! );
+//! let nonvolatile_storage = static_init!(
+//!     capsules_extra::nonvolatile_storage_driver::NonvolatileStorage<'static>,
+//!     capsules_extra::nonvolatile_storage_driver::NonvolatileStorage::new(
+//!         eeprom,
+//!         board_kernel.create_grant(
+//!             capsules_extra::nonvolatile_storage_driver::DRIVER_NUM,
+//!             &grant_cap
+//!         ),
+//!         0x0,    // Start of the userspace region.
+//!         0x7000, // Length of the userspace region.
+//!         0x7000, // Start of the kernel region.
+//!         0x1000, // Length of the kernel region.
+//!         storage_buffer,
+//!     )
+//! );
+//! hil::nonvolatile_storage::NonvolatileStorage::set_client(eeprom, nonvolatile_storage);
+//! kernel::deferred_call::DeferredCallClient::register(nonvolatile_storage);
+//! ```
+
+use core::cell::Cell;
+use core::cmp;
+
+use kernel::hil;
+use kernel::hil::i2c::{self, I2CClient, I2CDevice};
+use kernel::utilities::cells::{OptionalCell, TakeCell};
+use kernel::ErrorCode;
+
+/// How many times to poll for the end of a write cycle before giving up. At
+/// 100 kHz each poll takes about 0.3 ms, far longer than the 5 ms write cycle
+/// of common parts needs.
+const MAX_POLLS: usize = 200;
+
+#[derive(Clone, Copy, PartialEq)]
+enum State {
+    Idle,
+    Reading,
+    Writing,
+    /// Waiting for the EEPROM to finish writing a page.
+    Polling,
+}
+
+pub struct I2CEeprom<'a, I: I2CDevice> {
+    i2c: &'a I,
+    page_size: usize,
+    total_size: usize,
+    // Holds the memory address followed by the data of one transfer.
+    i2c_buffer: TakeCell<'static, [u8]>,
+    client_buffer: TakeCell<'static, [u8]>,
+    client: OptionalCell<&'a dyn hil::nonvolatile_storage::NonvolatileStorageClient>,
+    state: Cell<State>,
+    // Start and length of the current read or write.
+    address: Cell<usize>,
+    length: Cell<usize>,
+    // How many bytes of it are done, and how many are in the transfer in
+    // progress.
+    done: Cell<usize>,
+    chunk: Cell<usize>,
+    polls: Cell<usize>,
+}
+
+impl<'a, I: I2CDevice> I2CEeprom<'a, I> {
+    /// `buffer` must be at least 3 bytes long. Page writes are fastest if it
+    /// holds a whole page plus two address bytes.
+    pub fn new(i2c: &'a I, buffer: &'static mut [u8], page_size: usize, total_size: usize) -> Self {
+        Self {
+            i2c,
+            page_size,
+            total_size,
+            i2c_buffer: TakeCell::new(buffer),
+            client_buffer: TakeCell::empty(),
+            client: OptionalCell::empty(),
+            state: Cell::new(State::Idle),
+            address: Cell::new(0),
+            length: Cell::new(0),
+            done: Cell::new(0),
+            chunk: Cell::new(0),
+            polls: Cell::new(0),
+        }
+    }
+
+    fn start(
+        &self,
+        state: State,
+        buffer: &'static mut [u8],
+        address: usize,
+        length: usize,
+    ) -> Result<(), ErrorCode> {
+        if self.state.get() != State::Idle {
+            return Err(ErrorCode::BUSY);
+        }
+        if length == 0
+            || length > buffer.len()
+            || address
+                .checked_add(length)
+                .map_or(true, |end| end > self.total_size)
+        {
+            return Err(ErrorCode::INVAL);
+        }
+
+        self.client_buffer.replace(buffer);
+        self.address.set(address);
+        self.length.set(length);
+        self.done.set(0);
+        self.state.set(state);
+        self.i2c.enable();
+        self.next_transfer().inspect_err(|_| {
+            self.client_buffer.take();
+            self.i2c.disable();
+            self.state.set(State::Idle);
+        })
+    }
+
+    // Start the next read or page write of the current operation.
+    fn next_transfer(&self) -> Result<(), ErrorCode> {
+        let i2c_buffer = self.i2c_buffer.take().ok_or(ErrorCode::RESERVE)?;
+        let done = self.done.get();
+        let address = self.address.get() + done;
+        let remaining = self.length.get() - done;
+        i2c_buffer[0] = (address >> 8) as u8;
+        i2c_buffer[1] = address as u8;
+
+        let result = match self.state.get() {
+            State::Reading => {
+                let length = cmp::min(remaining, i2c_buffer.len());
+                self.chunk.set(length);
+                self.i2c.write_read(i2c_buffer, 2, length)
+            }
+            _ => {
+                let length = cmp::min(
+                    cmp::min(remaining, i2c_buffer.len() - 2),
+                    self.page_size - address % self.page_size,
+                );
+                self.client_buffer.map(|buffer| {
+                    i2c_buffer[2..length + 2].copy_from_slice(&buffer[done..done + length]);
+                });
+                self.chunk.set(length);
+                self.state.set(State::Writing);
+                self.i2c.write(i2c_buffer, length + 2)
+            }
+        };
+        result.map_err(|(error, i2c_buffer)| {
+            self.i2c_buffer.replace(i2c_buffer);
+            error.into()
+        })
+    }
+
+    // Address the EEPROM without writing data. It only acknowledges once its
+    // write cycle is over.
+    fn poll(&self) -> Result<(), ErrorCode> {
+        let i2c_buffer = self.i2c_buffer.take().ok_or(ErrorCode::RESERVE)?;
+        self.state.set(State::Polling);
+        self.i2c
+            .write(i2c_buffer, 2)
+            .map_err(|(error, i2c_buffer)| {
+                self.i2c_buffer.replace(i2c_buffer);
+                error.into()
+            })
+    }
+
+    // Start the next transfer if the last one succeeded and there is more to
+    // do, otherwise report the operation done.
+    fn continue_or_finish(&self, succeeded: bool) {
+        if succeeded && self.done.get() < self.length.get() && self.next_transfer().is_ok() {
+            return;
+        }
+        self.finish();
+    }
+
+    // Report the operation done with the bytes transferred so far.
+    fn finish(&self) {
+        let state = self.state.get();
+        self.state.set(State::Idle);
+        self.i2c.disable();
+        let length = self.done.get();
+        self.client_buffer.take().map(|buffer| {
+            self.client.map(|client| match state {
+                State::Reading => client.read_done(buffer, length),
+                _ => client.write_done(buffer, length),
+            });
+        });
+    }
+}
+
+impl<I: I2CDevice> I2CClient for I2CEeprom<'_, I> {
+    fn command_complete(&self, buffer: &'static mut [u8], status: Result<(), i2c::Error>) {
+        let chunk = self.chunk.get();
+        match self.state.get() {
+            State::Idle => {
+                self.i2c_buffer.replace(buffer);
+            }
+            State::Reading => {
+                if status.is_ok() {
+                    let done = self.done.get();
+                    self.client_buffer.map(|client_buffer| {
+                        client_buffer[done..done + chunk].copy_from_slice(&buffer[..chunk]);
+                    });
+                    self.done.set(done + chunk);
+                }
+                self.i2c_buffer.replace(buffer);
+                self.continue_or_finish(status.is_ok());
+            }
+            State::Writing => {
+                self.i2c_buffer.replace(buffer);
+                if status.is_ok() {
+                    self.done.set(self.done.get() + chunk);
+                    self.polls.set(0);
+                    if self.poll().is_ok() {
+                        return;
+                    }
+                }
+                self.finish();
+            }
+            State::Polling => {
+                self.i2c_buffer.replace(buffer);
+                match status {
+                    Ok(()) => self.continue_or_finish(true),
+                    Err(i2c::Error::AddressNak) if self.polls.get() < MAX_POLLS => {
+                        self.polls.set(self.polls.get() + 1);
+                        if self.poll().is_err() {
+                            self.finish();
+                        }
+                    }
+                    Err(_) => self.finish(),
+                }
+            }
+        }
+    }
+}
+
+impl<'a, I: I2CDevice> hil::nonvolatile_storage::NonvolatileStorage<'a> for I2CEeprom<'a, I> {
+    fn set_client(&self, client: &'a dyn hil::nonvolatile_storage::NonvolatileStorageClient) {
+        self.client.set(client);
+    }
+
+    fn read(
+        &self,
+        buffer: &'static mut [u8],
+        address: usize,
+        length: usize,
+    ) -> Result<(), ErrorCode> {
+        self.start(State::Reading, buffer, address, length)
+    }
+
+    fn write(
+        &self,
+        buffer: &'static mut [u8],
+        address: usize,
+        length: usize,
+    ) -> Result<(), ErrorCode> {
+        self.start(State::Writing, buffer, address, length)
+    }
+
+    fn geometry(&self) -> Option<hil::nonvolatile_storage::StorageGeometry> {
+        Some(hil::nonvolatile_storage::StorageGeometry {
+            erase_block_size: self.page_size,
+            write_granularity: 1,
+            total_size: self.total_size,
+        })
+    }
+}
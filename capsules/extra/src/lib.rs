@@ -30,13 +30,16 @@ pub mod buzzer_pwm;
 pub mod can;
 pub mod ccs811;
 pub mod chirp_i2c_moisture;
+pub mod crash_dump;
 pub mod crc;
 pub mod cycle_count;
 pub mod dac;
 pub mod date_time;
 pub mod debug_process_restart;
+pub mod debug_snapshot;
 pub mod distance;
 pub mod eui64;
+pub mod event_journal;
 pub mod fm25cl;
 pub mod ft6x06;
 pub mod fxos8700cq;
@@ -48,6 +51,7 @@ pub mod hmac_sha256;
 pub mod hs3003;
 pub mod hts221;
 pub mod humidity;
+pub mod i2c_eeprom;
 pub mod ieee802154;
 pub mod isl29035;
 pub mod kv_driver;
@@ -72,13 +76,22 @@ pub mod ninedof;
 pub mod nonvolatile_storage_driver;
 pub mod nonvolatile_to_pages;
 pub mod nrf51822_serialization;
+pub mod ota_staging;
 pub mod panic_button;
 pub mod pca9544a;
+pub mod persistent_seed;
+pub mod power_fail_record;
 pub mod pressure;
+pub mod process_console_backup;
+pub mod process_console_storage;
+pub mod process_console_upcall;
+pub mod process_console_watch;
 pub mod proximity;
 pub mod public_key_crypto;
 pub mod pwm;
+pub mod quiesce_group;
 pub mod read_only_state;
+pub mod reset_reason;
 pub mod rf233;
 pub mod rf233_const;
 pub mod screen;
@@ -90,13 +103,27 @@ pub mod sg90;
 pub mod sh1106;
 pub mod sha;
 pub mod sha256;
+pub mod shared_blobs;
 pub mod sht3x;
 pub mod sht4x;
 pub mod si7021;
 pub mod sip_hash;
 pub mod sound_pressure;
+pub mod spi_nor;
 pub mod ssd1306;
 pub mod st77xx;
+pub mod storage_audit;
+pub mod storage_backup;
+pub mod storage_batch_writes;
+pub mod storage_low_space;
+pub mod storage_mapped_reads;
+pub mod storage_pages;
+pub mod storage_partition;
+pub mod storage_provisioning_lock;
+pub mod storage_read_cache;
+pub mod storage_request;
+pub mod storage_verify;
+pub mod storage_wear;
 pub mod symmetric_encryption;
 pub mod temperature;
 pub mod temperature_rp2040;
@@ -11,7 +11,20 @@
 //!
 //! However, the kernel accessible memory does not have to be the same range
 //! as the userspace accessible address space. The kernel memory can overlap
-//! if desired, or can be a completely separate range.
+//! if desired, or can be a completely separate range. Kernel writes to the part
+//! of the kernel range that overlaps the userspace range are rejected unless
+//! the board allows them with `allow_kernel_userspace_writes()`.
+//!
+//! Kernel services can use the userspace region on behalf of apps through the
+//! `AppStorage` HIL. Boards can require a storage permission for the
+//! userspace region, and give apps a read-only provisioned region, selected
+//! with `PROVISIONED_REGION` in the command number. Kernel operations run
+//! first, then `AppStorage` operations, then apps in turn.
+//!
+//! Optional features are separate capsules stacked between this driver and
+//! the storage, such as `StorageVerify`, `StorageWear` or
+//! `StorageProvisioningLock`. They learn the driver's regions and users
+//! through `StorageUsers`.
 //!
 //! Here is a diagram of the expected stack with this capsule:
 //! Boxes are components and between the boxes are the traits that are the
@@ -56,30 +69,58 @@
 //!         3000,                        // The length of the kernel region.
 //!         &mut capsules::nonvolatile_storage_driver::BUFFER));
 //! hil::nonvolatile_storage::NonvolatileStorage::set_client(fm25cl, nonvolatile_storage);
+//! kernel::deferred_call::DeferredCallClient::register(nonvolatile_storage);
 //! ```
 
 use core::cell::Cell;
 use core::cmp;
 
-use kernel::grant::{AllowRoCount, AllowRwCount, Grant, UpcallCount};
+use kernel::capabilities;
+use kernel::deferred_call::{DeferredCall, DeferredCallClient};
+use kernel::grant::{AllowRoCount, AllowRwCount, Grant, GrantKernelData, UpcallCount};
 use kernel::hil;
+use kernel::hil::nonvolatile_storage::{ReadStatus, StorageRange};
+use kernel::hil::time::ConvertTicks;
+use kernel::process::{ProcessStateReporter, ShortId};
 use kernel::processbuffer::{ReadableProcessBuffer, WriteableProcessBuffer};
 use kernel::syscall::{CommandReturn, SyscallDriver};
+use kernel::utilities::buffer_pool::BufferPool;
 use kernel::utilities::cells::{OptionalCell, TakeCell};
 use kernel::{ErrorCode, ProcessId};
 
+use crate::storage_provisioning_lock::{
+    ProvisioningLock, ProvisioningLockClient, PROVISIONING_LOCK,
+};
+
 /// Syscall driver number.
 use capsules_core::driver;
 pub const DRIVER_NUM: usize = driver::NUM::NvmStorage as usize;
 
 /// IDs for subscribed upcalls.
+///
+/// All upcalls pass a status code (0 for success) as the second argument. The
+/// read and write upcalls pass the number of bytes read or written as the
+/// first argument. The read upcall passes the `ReadStatus` the storage
+/// reported for the data as the third argument: 0 if it was read without
+/// errors, 1 if errors were corrected, 2 for a failure that may go away if
+/// the read is retried (with a `BUSY` status code), and 3 for data with
+/// errors that could not be corrected (with a `FAIL` status code).
+///
+/// With sequence numbers turned on, the read and write upcalls also pass the
+/// sequence number of the completed command in the third argument, shifted
+/// left by `SEQUENCE_SHIFT`. It is 0 for an upcall about a command the app
+/// did not number.
 mod upcall {
     /// Read done callback.
     pub const READ_DONE: usize = 0;
     /// Write done callback.
     pub const WRITE_DONE: usize = 1;
+    /// Sync done callback.
+    pub const SYNC_DONE: usize = 2;
+    /// App writes may be made again after they were frozen.
+    pub const THAWED: usize = 3;
     /// Number of upcalls.
-    pub const COUNT: u8 = 2;
+    pub const COUNT: u8 = 4;
 }
 
 /// Ids for read-only allow buffers
@@ -100,25 +141,140 @@ mod rw_allow {
 
 pub const BUF_LEN: usize = 512;
 
-#[derive(Clone, Copy, PartialEq)]
+/// Set in the command number of the size, read, and write commands to access
+/// the provisioned region instead of the userspace region.
+pub const PROVISIONED_REGION: usize = 1 << 8;
+
+/// Set in the command number of the size, read, and write commands to use
+/// 64-bit sizes and offsets. The offset is passed with its low 32 bits in the
+/// first argument and its high 32 bits in the second.
+pub const WIDE_OFFSET: usize = 1 << 9;
+
+/// Position of the sequence number in the third argument of the read and
+/// write upcalls. The bits below it hold the `ReadStatus` of reads.
+pub const SEQUENCE_SHIFT: usize = 8;
+
+/// Largest sequence number. Numbers start at 1 and wrap back to 1 after this,
+/// so they fit the upcall argument next to the `ReadStatus` on 32-bit
+/// platforms.
+pub const MAX_SEQUENCE: u32 = (1 << 24) - 1;
+
+/// The most grant memory, in bytes, this driver takes from each app that uses
+/// it, for boards to budget the RAM of their apps. It does not depend on how
+/// many commands an app issues.
+pub const GRANT_BYTES_PER_APP: usize = Grant::<
+    App,
+    UpcallCount<{ upcall::COUNT }>,
+    AllowRoCount<{ ro_allow::COUNT }>,
+    AllowRwCount<{ rw_allow::COUNT }>,
+>::size();
+
+/// Timer used for the operation timeout. Implemented for every `Alarm`, so
+/// the driver does not depend on the alarm type.
+pub trait OperationTimer {
+    /// Fire the alarm `ms` milliseconds from now.
+    fn start(&self, ms: u32);
+    /// Stop the alarm if it is armed.
+    fn cancel(&self);
+}
+
+impl<'a, A: hil::time::Alarm<'a>> OperationTimer for A {
+    fn start(&self, ms: u32) {
+        self.set_alarm(self.now(), self.ticks_from_ms(ms));
+    }
+
+    fn cancel(&self) {
+        let _ = self.disarm();
+    }
+}
+
+/// What the capsules stacked below the driver need to know about its regions
+/// and the users of the storage.
+pub trait StorageUsers {
+    /// The userspace region, in absolute addresses.
+    fn userspace_range(&self) -> StorageRange;
+
+    /// The provisioned region, in absolute addresses. Empty if there is none.
+    fn provisioned_range(&self) -> StorageRange;
+
+    /// The app the operation the driver has with the storage is for, either
+    /// its own command or an `AppStorage` operation on its behalf. `None` for
+    /// the kernel, or if the storage is idle.
+    fn current_app(&self) -> Option<ProcessId>;
+
+    /// Whether `processid` has a command with the storage or queued.
+    fn has_commands(&self, processid: ProcessId) -> bool;
+
+    /// Whether `processid` may read the userspace and provisioned regions.
+    fn check_read_permission(&self, processid: ProcessId) -> Result<(), ErrorCode>;
+}
+
+/// Kind of operation the underlying storage is working on.
+#[derive(Clone, Copy, PartialEq, Debug)]
+enum Operation {
+    Read,
+    Write,
+    /// Reading or writing the provisioning lock.
+    ProvisioningLock,
+    Sync,
+}
+
+/// Completion of a kernel operation that finished before the kernel client
+/// was set.
+#[derive(Clone, Copy)]
+enum KernelCompletion {
+    Read(usize, ReadStatus),
+    Write(usize),
+    Sync(Result<(), ErrorCode>),
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
 pub enum NonvolatileCommand {
     UserspaceRead,
     UserspaceWrite,
+    /// Read from the provisioned region.
+    UserspaceProvisionedRead,
+    /// Write to the provisioned region before provisioning is locked.
+    UserspaceProvisionedWrite,
+    UserspaceSync,
     KernelRead,
     KernelWrite,
+    KernelSync,
 }
 
 #[derive(Clone, Copy)]
 pub enum NonvolatileUser {
-    App { processid: ProcessId },
+    App {
+        processid: ProcessId,
+        short_id: ShortId,
+    },
     Kernel,
+    /// A kernel service using the userspace region for an app through
+    /// `AppStorage`.
+    AppStorage(ProcessId),
 }
 
+/// Per-app state, kept in the app's grant region.
+///
+/// An app has at most one command with the storage and one queued behind it,
+/// both described by this fixed-size struct, so an app cannot use more grant
+/// memory by queueing more commands. Commands beyond that are refused with
+/// `NOMEM`, as are all commands of an app whose grant region has no room for
+/// this struct. `GRANT_BYTES_PER_APP` gives the resulting RAM cost per app.
 pub struct App {
     pending_command: bool,
     command: NonvolatileCommand,
     offset: usize,
     length: usize,
+    /// A queued command could not be started. The error and the command's
+    /// sequence number are delivered to the app from a deferred call.
+    failed_command: Option<(ErrorCode, u32)>,
+    /// Last sequence number given out, if the app turned sequence numbers on.
+    sequence: Option<u32>,
+    /// Sequence numbers of the queued command and of the command with the
+    /// storage. 0 if the command was not numbered.
+    command_sequence: u32,
+    active_sequence: u32,
 }
 
 impl Default for App {
@@ -128,8 +284,62 @@ impl Default for App {
             command: NonvolatileCommand::UserspaceRead,
             offset: 0,
             length: 0,
+            failed_command: None,
+            sequence: None,
+            command_sequence: 0,
+            active_sequence: 0,
+        }
+    }
+}
+
+impl App {
+    // Number a newly accepted read or write, if the app turned sequence
+    // numbers on.
+    fn next_sequence(&mut self) -> u32 {
+        match self.sequence {
+            Some(last) => {
+                let next = if last >= MAX_SEQUENCE { 1 } else { last + 1 };
+                self.sequence = Some(next);
+                next
+            }
+            None => 0,
         }
     }
+
+    // The upcall that reports the queued command that could not be started,
+    // if there is one. Each failure is reported once.
+    fn take_failed_upcall(&mut self) -> Option<(usize, (usize, usize, usize))> {
+        self.failed_command.take().map(|(e, sequence)| {
+            let upcall_num = match self.command {
+                NonvolatileCommand::UserspaceWrite
+                | NonvolatileCommand::UserspaceProvisionedWrite => upcall::WRITE_DONE,
+                _ => upcall::READ_DONE,
+            };
+            (
+                upcall_num,
+                (
+                    0,
+                    kernel::errorcode::into_statuscode(Err(e)),
+                    (sequence as usize) << SEQUENCE_SHIFT,
+                ),
+            )
+        })
+    }
+}
+
+// Offer `start` the apps `apps()` yields in turn, beginning at position
+// `first` and wrapping around, until it starts the command of one. Returns the
+// position of that app, so the next turn can begin after it.
+fn start_in_turn<T, I: Iterator<Item = T>>(
+    first: usize,
+    apps: impl Fn() -> I,
+    mut start: impl FnMut(T) -> bool,
+) -> Option<usize> {
+    apps()
+        .enumerate()
+        .skip(first)
+        .chain(apps().enumerate().take(first))
+        .find_map(|(index, app)| if start(app) { Some(index) } else { None })
 }
 
 pub struct NonvolatileStorage<'a> {
@@ -145,6 +355,12 @@ pub struct NonvolatileStorage<'a> {
 
     // Internal buffer for copying appslices into.
     buffer: TakeCell<'static, [u8]>,
+    // Length of `buffer`.
+    buffer_len: usize,
+    // Buffers shared with other capsules, used before `buffer`.
+    pool: OptionalCell<&'a BufferPool>,
+    // Whether the buffer of the app operation in progress came from `pool`.
+    pooled: Cell<bool>,
     // What issued the currently executing call. This can be an app or the kernel.
     current_user: OptionalCell<NonvolatileUser>,
 
@@ -152,24 +368,91 @@ pub struct NonvolatileStorage<'a> {
     userspace_start_address: usize,
     // How many bytes allocated to userspace.
     userspace_length: usize,
+    // The first byte of the region apps can only read.
+    provisioned_start_address: Cell<usize>,
+    // How many bytes apps can only read.
+    provisioned_length: Cell<usize>,
+    // Decides whether an app may write the provisioned region.
+    provisioning_lock: OptionalCell<&'a dyn ProvisioningLock<'a>>,
+    // Keeps the provisioned region locked in hardware.
+    write_protection: OptionalCell<&'a dyn hil::flash::WriteProtection>,
+    // Whether the provisioned region is unlocked for the current write.
+    unprotected: Cell<bool>,
     // The first byte that is accessible from the kernel.
     kernel_start_address: usize,
     // How many bytes allocated to kernel.
     kernel_length: usize,
+    // Whether kernel writes may touch the userspace region.
+    kernel_userspace_writes: Cell<bool>,
+    // Storage identifier of the userspace region, if apps need permission to
+    // access it.
+    userspace_storage_id: OptionalCell<u32>,
 
     // Optional client for the kernel. Only needed if the kernel intends to use
     // this nonvolatile storage.
     kernel_client: OptionalCell<&'a dyn hil::nonvolatile_storage::NonvolatileStorageClient>,
+    // Completion of a kernel operation that finished while there was no
+    // kernel client, and the buffer that goes with it.
+    kernel_completion: OptionalCell<KernelCompletion>,
+    kernel_completion_buffer: TakeCell<'static, [u8]>,
     // Whether the kernel is waiting for a read/write.
     kernel_pending_command: Cell<bool>,
     // Whether the kernel wanted a read/write.
     kernel_command: Cell<NonvolatileCommand>,
     // Holder for the buffer passed from the kernel in case we need to wait.
     kernel_buffer: TakeCell<'static, [u8]>,
+    // Position in the grant of the app whose queued command is checked first,
+    // so apps take turns.
+    next_app: Cell<usize>,
     // How many bytes to read/write from the kernel buffer.
     kernel_readwrite_length: Cell<usize>,
     // Where to read/write from the kernel request.
     kernel_readwrite_address: Cell<usize>,
+    // Result of a sync that finished without a callback from the underlying
+    // storage.
+    sync_result: OptionalCell<Result<(), ErrorCode>>,
+
+    // Gives up on operations the underlying storage does not finish.
+    timer: OptionalCell<&'a dyn OperationTimer>,
+    timeout_ms: Cell<u32>,
+    // Operation the underlying storage is working on.
+    operation: Cell<Operation>,
+    // The current operation timed out, and the storage has yet to finish or
+    // abort it. The next callback only returns the buffer.
+    timed_out: Cell<bool>,
+    // Number of operations that timed out.
+    timeouts: Cell<u32>,
+    // Where the current read or write started and how long it was.
+    access_address: Cell<usize>,
+    access_length: Cell<usize>,
+
+    // Whether app writes are refused, and queued ones held back, for the
+    // kernel.
+    writes_frozen: Cell<bool>,
+    // Whether the driver is being shut down and rejects new commands.
+    quiescing: Cell<bool>,
+    // Notified once the driver is idle after `quiesce()`.
+    quiesce_client: OptionalCell<&'a dyn hil::quiesce::QuiesceClient>,
+
+    // Client of the `AppStorage` interface.
+    app_storage_client: OptionalCell<&'a dyn hil::app_storage::AppStorageClient>,
+    // The `AppStorage` read or write that was accepted, from when it is
+    // accepted until it completes: the app, the command, the offset in the
+    // userspace region and the length.
+    app_storage_command: OptionalCell<(ProcessId, NonvolatileCommand, usize, usize)>,
+    // Whether the `AppStorage` command is still waiting for the storage.
+    app_storage_pending: Cell<bool>,
+    // The client's buffer of the `AppStorage` command. The data is copied
+    // through the driver's buffer, so the client always gets this one back.
+    app_storage_buffer: TakeCell<'static, [u8]>,
+    // An accepted `AppStorage` command that could not be started.
+    app_storage_failed: OptionalCell<ErrorCode>,
+    // Result of `AppStorage::init()`, delivered from a deferred call.
+    app_storage_init: OptionalCell<(ProcessId, Result<(), ErrorCode>)>,
+
+    // Used to report errors for queued commands that failed to start, and
+    // the result of syncs that completed immediately.
+    deferred_call: DeferredCall,
 }
 
 impl<'a> NonvolatileStorage<'a> {
@@ -190,21 +473,469 @@ impl<'a> NonvolatileStorage<'a> {
         NonvolatileStorage {
             driver,
             apps: grant,
+            buffer_len: buffer.len(),
             buffer: TakeCell::new(buffer),
+            pool: OptionalCell::empty(),
+            pooled: Cell::new(false),
             current_user: OptionalCell::empty(),
             userspace_start_address,
             userspace_length,
+            provisioned_start_address: Cell::new(0),
+            provisioned_length: Cell::new(0),
+            provisioning_lock: OptionalCell::empty(),
+            write_protection: OptionalCell::empty(),
+            unprotected: Cell::new(false),
             kernel_start_address,
             kernel_length,
+            kernel_userspace_writes: Cell::new(false),
+            userspace_storage_id: OptionalCell::empty(),
             kernel_client: OptionalCell::empty(),
+            kernel_completion: OptionalCell::empty(),
+            kernel_completion_buffer: TakeCell::empty(),
             kernel_pending_command: Cell::new(false),
             kernel_command: Cell::new(NonvolatileCommand::KernelRead),
             kernel_buffer: TakeCell::empty(),
+            next_app: Cell::new(0),
             kernel_readwrite_length: Cell::new(0),
             kernel_readwrite_address: Cell::new(0),
+            sync_result: OptionalCell::empty(),
+            timer: OptionalCell::empty(),
+            timeout_ms: Cell::new(0),
+            operation: Cell::new(Operation::Read),
+            timed_out: Cell::new(false),
+            timeouts: Cell::new(0),
+            access_address: Cell::new(0),
+            access_length: Cell::new(0),
+            writes_frozen: Cell::new(false),
+            quiescing: Cell::new(false),
+            quiesce_client: OptionalCell::empty(),
+            app_storage_client: OptionalCell::empty(),
+            app_storage_command: OptionalCell::empty(),
+            app_storage_pending: Cell::new(false),
+            app_storage_buffer: TakeCell::empty(),
+            app_storage_failed: OptionalCell::empty(),
+            app_storage_init: OptionalCell::empty(),
+            deferred_call: DeferredCall::new(),
+        }
+    }
+
+    /// Let kernel clients write to the part of the kernel region that is
+    /// also accessible to userspace.
+    pub fn allow_kernel_userspace_writes(
+        &self,
+        _capability: &dyn capabilities::StorageRegionOverlapCapability,
+    ) {
+        self.kernel_userspace_writes.set(true);
+    }
+
+    /// Refuse new app writes with `RESERVE`, and hold back the ones already
+    /// queued, until `thaw_writes()` is called.
+    pub fn freeze_writes(&self, _capability: &dyn capabilities::StorageFreezeCapability) {
+        self.writes_frozen.set(true);
+    }
+
+    /// Let apps write again after `freeze_writes()`. The writes that were
+    /// held back start, and apps get the thawed upcall.
+    pub fn thaw_writes(&self, _capability: &dyn capabilities::StorageFreezeCapability) {
+        if !self.writes_frozen.replace(false) {
+            return;
+        }
+        self.apps.each(|_, _, kernel_data| {
+            let _ = kernel_data.schedule_upcall(upcall::THAWED, (0, 0, 0));
+        });
+        self.check_queue();
+    }
+
+    /// Whether app writes are frozen.
+    pub fn writes_frozen(&self) -> bool {
+        self.writes_frozen.get()
+    }
+
+    // Whether `command` is an app write that waits while writes are frozen.
+    fn is_app_write(command: NonvolatileCommand) -> bool {
+        matches!(
+            command,
+            NonvolatileCommand::UserspaceWrite | NonvolatileCommand::UserspaceProvisionedWrite
+        )
+    }
+
+    /// Only give apps access to the userspace region if their storage
+    /// permissions include `storage_id`: read permission to read it and
+    /// modify permission to write it.
+    pub fn set_userspace_storage_id(&self, storage_id: u32) {
+        self.userspace_storage_id.set(storage_id);
+    }
+
+    /// Let apps read, but not write, `length` bytes starting at the absolute
+    /// storage address `address`.
+    ///
+    /// Returns `INVAL` if the region overlaps the userspace region, as apps
+    /// could then modify it.
+    pub fn set_provisioned_region(&self, address: usize, length: usize) -> Result<(), ErrorCode> {
+        if self.overlaps_userspace(address, length) {
+            return Err(ErrorCode::INVAL);
+        }
+        self.provisioned_start_address.set(address);
+        self.provisioned_length.set(length);
+        Ok(())
+    }
+
+    /// Check that `length` bytes at the absolute storage address `address`
+    /// can hold the data of a capsule stacked below the driver: they must be
+    /// in the kernel region, outside the regions apps can access, and outside
+    /// the provisioning lock.
+    pub fn check_private_range(&self, address: usize, length: usize) -> Result<(), ErrorCode> {
+        let overlaps_lock = self.provisioning_lock.map_or(false, |lock| {
+            StorageRange::new(lock.address(), PROVISIONING_LOCK.len()).overlaps(address, length)
+        });
+        if !self.kernel_range().contains(address, length)
+            || self.overlaps_userspace(address, length)
+            || self.provisioned_range().overlaps(address, length)
+            || overlaps_lock
+        {
+            return Err(ErrorCode::INVAL);
+        }
+        Ok(())
+    }
+
+    /// Let `lock` decide whether apps may write the provisioned region, and
+    /// read it. The lock must be in the kernel region and outside the regions
+    /// apps can access. Call this after the provisioned region is set, once
+    /// the lock is the storage of the driver and the driver is its client.
+    ///
+    /// Returns `NODEVICE` if there is no provisioned region.
+    pub fn set_provisioning_lock(
+        &self,
+        lock: &'a dyn ProvisioningLock<'a>,
+    ) -> Result<(), ErrorCode> {
+        if self.provisioned_length.get() == 0 {
+            return Err(ErrorCode::NODEVICE);
+        }
+        self.check_private_range(lock.address(), PROVISIONING_LOCK.len())?;
+        if self.current_user.is_some() {
+            return Err(ErrorCode::BUSY);
+        }
+        self.provisioning_lock.set(lock);
+        self.current_user.set(NonvolatileUser::Kernel);
+        if let Err(e) = lock.check() {
+            self.current_user.clear();
+            return Err(e);
+        }
+        self.start_timeout(Operation::ProvisioningLock);
+        Ok(())
+    }
+
+    /// Lock the provisioned region in hardware with `protection`, which must
+    /// be the flash beneath this driver. Call this after the provisioned region
+    /// is set.
+    ///
+    /// Returns `INVAL` if the lock regions covering the provisioned region
+    /// overlap the userspace region, as apps could then no longer write all of
+    /// it.
+    pub fn set_write_protection(
+        &self,
+        protection: &'a dyn hil::flash::WriteProtection,
+    ) -> Result<(), ErrorCode> {
+        let (start, length) = match Self::protected_range(
+            protection,
+            self.provisioned_start_address.get(),
+            self.provisioned_length.get(),
+        ) {
+            Some(range) => range,
+            None => return Err(ErrorCode::INVAL),
+        };
+        if self.overlaps_userspace(start, length) {
+            return Err(ErrorCode::INVAL);
+        }
+        protection.lock(start, length)?;
+        self.write_protection.set(protection);
+        Ok(())
+    }
+
+    // The `address` and `length` of a region widened to whole lock regions.
+    fn protected_range(
+        protection: &dyn hil::flash::WriteProtection,
+        address: usize,
+        length: usize,
+    ) -> Option<(usize, usize)> {
+        let region_size = protection.lock_region_size();
+        if length == 0 || region_size == 0 {
+            return None;
+        }
+        let start = address - address % region_size;
+        let end = address
+            .checked_add(length)?
+            .checked_next_multiple_of(region_size)?;
+        Some((start, end - start))
+    }
+
+    // Unlock the provisioned region if a write of `length` bytes at `address`
+    // touches one of the lock regions covering it.
+    fn unprotect_for_write(&self, address: usize, length: usize) -> Result<(), ErrorCode> {
+        self.write_protection.map_or(Ok(()), |protection| {
+            let (start, protected_length) = match Self::protected_range(
+                protection,
+                self.provisioned_start_address.get(),
+                self.provisioned_length.get(),
+            ) {
+                Some(range) => range,
+                None => return Ok(()),
+            };
+            if address >= start + protected_length || start >= address + length {
+                return Ok(());
+            }
+            protection.unlock(start, protected_length)?;
+            self.unprotected.set(true);
+            Ok(())
+        })
+    }
+
+    // Lock the provisioned region again after a write unlocked it. If the
+    // flash is still busy, the next finished write tries again.
+    fn protect(&self) {
+        if !self.unprotected.get() {
+            return;
+        }
+        self.write_protection.map(|protection| {
+            // Lock the same whole lock regions `unprotect_for_write()`
+            // unlocked.
+            let locked = match Self::protected_range(
+                protection,
+                self.provisioned_start_address.get(),
+                self.provisioned_length.get(),
+            ) {
+                Some((start, length)) => protection.lock(start, length).is_ok(),
+                None => true,
+            };
+            if locked {
+                self.unprotected.set(false);
+            }
+        });
+    }
+
+    // Lock provisioning on behalf of an app. The lock applies right away, the
+    // write upcall fires once it is stored.
+    fn lock_provisioning(&self, processid: ProcessId) -> Result<(), ErrorCode> {
+        if self.writes_frozen.get() {
+            return Err(ErrorCode::RESERVE);
+        }
+        let lock = self.provisioning_lock.get().ok_or(ErrorCode::ALREADY)?;
+        self.check_userspace_permission(NonvolatileCommand::UserspaceProvisionedWrite, processid)?;
+        if self.current_user.is_some() {
+            return Err(ErrorCode::BUSY);
+        }
+
+        self.unprotect_for_write(lock.address(), PROVISIONING_LOCK.len())?;
+        self.current_user.set(NonvolatileUser::App {
+            processid,
+            short_id: processid.short_app_id(),
+        });
+        if let Err(e) = lock.lock(processid.short_app_id()) {
+            self.protect();
+            self.current_user.clear();
+            return Err(e);
+        }
+        let _ = self.apps.enter(processid, |app, _| {
+            app.active_sequence = app.next_sequence();
+        });
+        self.start_timeout(Operation::ProvisioningLock);
+        Ok(())
+    }
+
+    /// Fail operations the underlying storage has not finished after
+    /// `timeout_ms` milliseconds. The driver must be the
+    /// timer's alarm client.
+    pub fn set_operation_timeout(&self, timer: &'a dyn OperationTimer, timeout_ms: u32) {
+        self.timer.set(timer);
+        self.timeout_ms.set(timeout_ms);
+    }
+
+    /// Borrow buffers for app reads and writes from `pool`, and only use the
+    /// driver's own buffer when none of the free ones is large enough.
+    pub fn set_buffer_pool(&self, pool: &'a BufferPool) {
+        self.pool.set(pool);
+    }
+
+    /// Length of the longest transfer a single buffer of the driver can hold.
+    pub fn max_buffer_len(&self) -> usize {
+        cmp::max(self.buffer_len, self.pool.map_or(0, |pool| pool.max_len()))
+    }
+
+    // Borrow a buffer for an app operation, from the pool if a free one holds
+    // `len` bytes. Only one app operation is in progress at a time.
+    fn take_buffer(&self, len: usize) -> Option<&'static mut [u8]> {
+        match self.pool.and_then(|pool| pool.take(len)) {
+            Some(buffer) => {
+                self.pooled.set(true);
+                Some(buffer)
+            }
+            None => {
+                self.pooled.set(false);
+                self.buffer.take()
+            }
+        }
+    }
+
+    // Give back the buffer of the app operation that finished.
+    fn return_buffer(&self, buffer: &'static mut [u8]) {
+        if self.pooled.take() {
+            self.pool.map(|pool| pool.put(buffer));
+        } else {
+            self.buffer.replace(buffer);
+        }
+    }
+
+    // Handle a command that has `WIDE_OFFSET` set, with that bit cleared from
+    // `command_num`.
+    fn wide_command(
+        &self,
+        command_num: usize,
+        offset_low: usize,
+        offset_high: usize,
+        processid: ProcessId,
+    ) -> CommandReturn {
+        match command_num {
+            1 => CommandReturn::success_u64(self.userspace_length as u64),
+            c if c == 1 | PROVISIONED_REGION => {
+                CommandReturn::success_u64(self.provisioned_length.get() as u64)
+            }
+            c if c & !PROVISIONED_REGION == 2 || c & !PROVISIONED_REGION == 3 => {
+                let offset = (offset_high as u32 as u64) << 32 | offset_low as u32 as u64;
+                let offset = match usize::try_from(offset) {
+                    Ok(offset) => offset,
+                    Err(_) => return CommandReturn::failure(ErrorCode::INVAL),
+                };
+                let length = self
+                    .apps
+                    .enter(processid, |_app, kernel_data| {
+                        if command_num & !PROVISIONED_REGION == 3 {
+                            kernel_data
+                                .get_readonly_processbuffer(ro_allow::WRITE)
+                                .map_or(0, |write| write.len())
+                        } else {
+                            kernel_data
+                                .get_readwrite_processbuffer(rw_allow::READ)
+                                .map_or(0, |read| read.len())
+                        }
+                    })
+                    .unwrap_or(0);
+                self.command(command_num, offset, length, processid)
+            }
+            _ => CommandReturn::failure(ErrorCode::NOSUPPORT),
         }
     }
 
+    // Result of a read or write command `processid` got accepted: its
+    // sequence number, if the app turned them on.
+    fn accepted(&self, processid: ProcessId) -> CommandReturn {
+        self.apps
+            .enter(processid, |app, _| app.sequence)
+            .ok()
+            .flatten()
+            .map_or(CommandReturn::success(), CommandReturn::success_u32)
+    }
+
+    /// Number of operations that timed out since boot.
+    pub fn timeouts(&self) -> u32 {
+        self.timeouts.get()
+    }
+
+    /// Whether the kernel region and the userspace region share any bytes.
+    pub fn kernel_region_overlaps_userspace(&self) -> bool {
+        self.overlaps_userspace(self.kernel_start_address, self.kernel_length)
+    }
+
+    fn check_userspace_permission(
+        &self,
+        command: NonvolatileCommand,
+        processid: ProcessId,
+    ) -> Result<(), ErrorCode> {
+        // Only the provisioning app may write the provisioned region, whatever
+        // its storage permissions.
+        if command == NonvolatileCommand::UserspaceProvisionedWrite {
+            self.provisioning_lock
+                .map_or(Err(ErrorCode::NOSUPPORT), |lock| {
+                    lock.may_write(processid.short_app_id())
+                })?;
+        }
+        self.userspace_storage_id.map_or(Ok(()), |storage_id| {
+            let perms = processid
+                .get_storage_permissions()
+                .ok_or(ErrorCode::INVAL)?;
+            let allowed = match command {
+                NonvolatileCommand::UserspaceRead
+                | NonvolatileCommand::UserspaceProvisionedRead => {
+                    perms.check_read_permission(storage_id)
+                }
+                NonvolatileCommand::UserspaceWrite
+                | NonvolatileCommand::UserspaceProvisionedWrite => {
+                    perms.check_modify_permission(storage_id)
+                }
+                _ => false,
+            };
+            if allowed {
+                Ok(())
+            } else {
+                Err(ErrorCode::NOSUPPORT)
+            }
+        })
+    }
+
+    /// Check that the userspace and kernel regions fit in the underlying
+    /// storage and can be written with its write granularity, if the storage
+    /// reports its geometry.
+    pub fn check_geometry(&self) -> Result<(), ErrorCode> {
+        let regions = [
+            (self.userspace_start_address, self.userspace_length),
+            (
+                self.provisioned_start_address.get(),
+                self.provisioned_length.get(),
+            ),
+            (self.kernel_start_address, self.kernel_length),
+        ];
+        self.driver.geometry().map_or(Ok(()), |geometry| {
+            for (start, length) in regions {
+                if length == 0 {
+                    continue;
+                }
+                if start + length > geometry.total_size {
+                    return Err(ErrorCode::SIZE);
+                }
+                if !Self::is_write_aligned(&geometry, start, length) {
+                    return Err(ErrorCode::INVAL);
+                }
+            }
+            Ok(())
+        })
+    }
+
+    fn is_write_aligned(
+        geometry: &hil::nonvolatile_storage::StorageGeometry,
+        address: usize,
+        length: usize,
+    ) -> bool {
+        let granularity = cmp::max(geometry.write_granularity, 1);
+        address % granularity == 0 && length % granularity == 0
+    }
+
+    fn overlaps_userspace(&self, address: usize, length: usize) -> bool {
+        StorageRange::new(self.userspace_start_address, self.userspace_length)
+            .overlaps(address, length)
+    }
+
+    // The kernel region, in absolute addresses.
+    fn kernel_range(&self) -> StorageRange {
+        StorageRange::new(self.kernel_start_address, self.kernel_length)
+    }
+
+    // The provisioned region, in absolute addresses. Empty if there is none.
+    fn provisioned_range(&self) -> StorageRange {
+        StorageRange::new(
+            self.provisioned_start_address.get(),
+            self.provisioned_length.get(),
+        )
+    }
+
     // Check so see if we are doing something. If not, go ahead and do this
     // command. If so, this is queued and will be run when the pending
     // command completes.
@@ -215,51 +946,99 @@ impl<'a> NonvolatileStorage<'a> {
         length: usize,
         processid: Option<ProcessId>,
     ) -> Result<(), ErrorCode> {
+        if self.quiescing.get() {
+            return Err(ErrorCode::OFF);
+        }
+        if self.writes_frozen.get() && Self::is_app_write(command) {
+            return Err(ErrorCode::RESERVE);
+        }
+
         // Do bounds check.
         match command {
             NonvolatileCommand::UserspaceRead | NonvolatileCommand::UserspaceWrite => {
                 // Userspace sees memory that starts at address 0 even if it
                 // is offset in the physical memory.
-                if offset >= self.userspace_length
-                    || length > self.userspace_length
-                    || offset + length > self.userspace_length
-                {
+                if !StorageRange::new(0, self.userspace_length).contains(offset, length) {
+                    return Err(ErrorCode::INVAL);
+                }
+            }
+            NonvolatileCommand::UserspaceProvisionedRead
+            | NonvolatileCommand::UserspaceProvisionedWrite => {
+                let provisioned_length = self.provisioned_length.get();
+                if provisioned_length == 0 {
+                    return Err(ErrorCode::NODEVICE);
+                }
+                if !StorageRange::new(0, provisioned_length).contains(offset, length) {
                     return Err(ErrorCode::INVAL);
                 }
             }
             NonvolatileCommand::KernelRead | NonvolatileCommand::KernelWrite => {
                 // Because the kernel uses the NonvolatileStorage interface,
                 // its calls are absolute addresses.
-                if offset < self.kernel_start_address
-                    || offset >= self.kernel_start_address + self.kernel_length
-                    || length > self.kernel_length
-                    || offset + length > self.kernel_start_address + self.kernel_length
+                if !self.kernel_range().contains(offset, length) {
+                    return Err(ErrorCode::INVAL);
+                }
+                // Kernel writes must not modify userspace data unless the
+                // board explicitly allowed it.
+                if command == NonvolatileCommand::KernelWrite
+                    && !self.kernel_userspace_writes.get()
+                    && self.overlaps_userspace(offset, length)
                 {
                     return Err(ErrorCode::INVAL);
                 }
             }
+            NonvolatileCommand::UserspaceSync | NonvolatileCommand::KernelSync => {
+                return Err(ErrorCode::INVAL);
+            }
+        }
+
+        // Writes the storage cannot do as requested are rejected here instead
+        // of failing somewhere in the storage stack.
+        if let NonvolatileCommand::UserspaceWrite
+        | NonvolatileCommand::UserspaceProvisionedWrite
+        | NonvolatileCommand::KernelWrite = command
+        {
+            let physical_address = match command {
+                NonvolatileCommand::UserspaceWrite => self.userspace_start_address + offset,
+                NonvolatileCommand::UserspaceProvisionedWrite => {
+                    self.provisioned_start_address.get() + offset
+                }
+                _ => offset,
+            };
+            let aligned = self.driver.geometry().map_or(true, |geometry| {
+                Self::is_write_aligned(&geometry, physical_address, length)
+            });
+            if !aligned {
+                return Err(ErrorCode::INVAL);
+            }
         }
 
         // Do very different actions if this is a call from userspace
         // or from the kernel.
         match command {
-            NonvolatileCommand::UserspaceRead | NonvolatileCommand::UserspaceWrite => {
+            NonvolatileCommand::UserspaceRead
+            | NonvolatileCommand::UserspaceWrite
+            | NonvolatileCommand::UserspaceProvisionedRead
+            | NonvolatileCommand::UserspaceProvisionedWrite => {
                 processid.map_or(Err(ErrorCode::FAIL), |processid| {
+                    self.check_userspace_permission(command, processid)?;
                     self.apps
                         .enter(processid, |app, kernel_data| {
                             // Get the length of the correct allowed buffer.
                             let allow_buf_len = match command {
-                                NonvolatileCommand::UserspaceRead => kernel_data
+                                NonvolatileCommand::UserspaceRead
+                                | NonvolatileCommand::UserspaceProvisionedRead => kernel_data
                                     .get_readwrite_processbuffer(rw_allow::READ)
                                     .map_or(0, |read| read.len()),
-                                NonvolatileCommand::UserspaceWrite => kernel_data
+                                NonvolatileCommand::UserspaceWrite
+                                | NonvolatileCommand::UserspaceProvisionedWrite => kernel_data
                                     .get_readonly_processbuffer(ro_allow::WRITE)
                                     .map_or(0, |read| read.len()),
                                 _ => 0,
                             };
 
                             // Check that it exists.
-                            if allow_buf_len == 0 || self.buffer.is_none() {
+                            if allow_buf_len == 0 || self.max_buffer_len() == 0 {
                                 return Err(ErrorCode::RESERVE);
                             }
 
@@ -272,33 +1051,27 @@ impl<'a> NonvolatileStorage<'a> {
                             if self.current_user.is_none() {
                                 // No app is currently using the underlying storage.
                                 // Mark this app as active, and then execute the command.
-                                self.current_user.set(NonvolatileUser::App { processid });
-
-                                // Need to copy bytes if this is a write!
-                                if command == NonvolatileCommand::UserspaceWrite {
-                                    let _ = kernel_data
-                                        .get_readonly_processbuffer(ro_allow::WRITE)
-                                        .and_then(|write| {
-                                            write.enter(|app_buffer| {
-                                                self.buffer.map(|kernel_buffer| {
-                                                    // Check that the internal buffer and the buffer that was
-                                                    // allowed are long enough.
-                                                    let write_len =
-                                                        cmp::min(active_len, kernel_buffer.len());
-
-                                                    let d = &app_buffer[0..write_len];
-                                                    for (i, c) in kernel_buffer[0..write_len]
-                                                        .iter_mut()
-                                                        .enumerate()
-                                                    {
-                                                        *c = d[i].get();
-                                                    }
-                                                });
-                                            })
-                                        });
-                                }
+                                self.current_user.set(NonvolatileUser::App {
+                                    processid,
+                                    short_id: processid.short_app_id(),
+                                });
 
-                                self.userspace_call_driver(command, offset, active_len)
+                                let res = self.userspace_call_driver(
+                                    kernel_data,
+                                    command,
+                                    offset,
+                                    active_len,
+                                );
+                                match res {
+                                    Ok(()) => app.active_sequence = app.next_sequence(),
+                                    Err(_) => {
+                                        // The command was not accepted, so no
+                                        // upcall will happen and nothing is in
+                                        // flight.
+                                        self.current_user.clear();
+                                    }
+                                }
+                                res
                             } else {
                                 // Some app is using the storage, we must wait.
                                 if app.pending_command {
@@ -311,6 +1084,7 @@ impl<'a> NonvolatileStorage<'a> {
                                     app.command = command;
                                     app.offset = offset;
                                     app.length = active_len;
+                                    app.command_sequence = app.next_sequence();
                                     Ok(())
                                 }
                             }
@@ -319,193 +1093,872 @@ impl<'a> NonvolatileStorage<'a> {
                 })
             }
             NonvolatileCommand::KernelRead | NonvolatileCommand::KernelWrite => {
-                self.kernel_buffer
-                    .take()
-                    .map_or(Err(ErrorCode::NOMEM), |kernel_buffer| {
-                        let active_len = cmp::min(length, kernel_buffer.len());
-
-                        // Check if there is something going on.
-                        if self.current_user.is_none() {
-                            // Nothing is using this, lets go!
-                            self.current_user.set(NonvolatileUser::Kernel);
-
-                            match command {
-                                NonvolatileCommand::KernelRead => {
-                                    self.driver.read(kernel_buffer, offset, active_len)
-                                }
-                                NonvolatileCommand::KernelWrite => {
-                                    self.driver.write(kernel_buffer, offset, active_len)
-                                }
-                                _ => Err(ErrorCode::FAIL),
-                            }
-                        } else {
-                            if self.kernel_pending_command.get() {
-                                Err(ErrorCode::NOMEM)
-                            } else {
-                                self.kernel_pending_command.set(true);
-                                self.kernel_command.set(command);
-                                self.kernel_readwrite_length.set(active_len);
-                                self.kernel_readwrite_address.set(offset);
-                                self.kernel_buffer.replace(kernel_buffer);
-                                Ok(())
-                            }
-                        }
-                    })
+                self.enqueue_kernel_command(command, offset, length)
+            }
+            NonvolatileCommand::UserspaceSync | NonvolatileCommand::KernelSync => {
+                Err(ErrorCode::INVAL)
             }
         }
     }
 
-    fn userspace_call_driver(
+    // Start the kernel read or write of the buffer in `kernel_buffer` at the
+    // absolute `address`, or queue it if the storage is busy.
+    fn enqueue_kernel_command(
         &self,
         command: NonvolatileCommand,
-        offset: usize,
+        address: usize,
         length: usize,
     ) -> Result<(), ErrorCode> {
-        // Calculate where we want to actually read from in the physical
-        // storage.
-        let physical_address = offset + self.userspace_start_address;
-
-        self.buffer
+        self.kernel_buffer
             .take()
-            .map_or(Err(ErrorCode::RESERVE), |buffer| {
-                // Check that the internal buffer and the buffer that was
-                // allowed are long enough.
-                let active_len = cmp::min(length, buffer.len());
-
-                // self.current_app.set(Some(processid));
-                match command {
-                    NonvolatileCommand::UserspaceRead => {
-                        self.driver.read(buffer, physical_address, active_len)
+            .map_or(Err(ErrorCode::NOMEM), |kernel_buffer| {
+                let active_len = cmp::min(length, kernel_buffer.len());
+
+                // Check if there is something going on.
+                if self.current_user.is_none() {
+                    // Nothing is using this, lets go!
+                    self.current_user.set(NonvolatileUser::Kernel);
+
+                    let res = match command {
+                        NonvolatileCommand::KernelRead => {
+                            self.driver_read(kernel_buffer, address, active_len)
+                        }
+                        NonvolatileCommand::KernelWrite => {
+                            self.driver_write(kernel_buffer, address, active_len)
+                        }
+                        _ => Err(ErrorCode::FAIL),
+                    };
+                    if res.is_err() {
+                        self.current_user.clear();
                     }
-                    NonvolatileCommand::UserspaceWrite => {
-                        self.driver.write(buffer, physical_address, active_len)
+                    res
+                } else {
+                    if self.kernel_pending_command.get() {
+                        Err(ErrorCode::NOMEM)
+                    } else {
+                        self.kernel_pending_command.set(true);
+                        self.kernel_command.set(command);
+                        self.kernel_readwrite_length.set(active_len);
+                        self.kernel_readwrite_address.set(address);
+                        self.kernel_buffer.replace(kernel_buffer);
+                        Ok(())
                     }
-                    _ => Err(ErrorCode::FAIL),
                 }
             })
     }
 
-    fn check_queue(&self) {
-        // Check if there are any pending events.
-        if self.kernel_pending_command.get() {
-            self.kernel_buffer.take().map(|kernel_buffer| {
-                self.kernel_pending_command.set(false);
-                self.current_user.set(NonvolatileUser::Kernel);
-
-                match self.kernel_command.get() {
-                    NonvolatileCommand::KernelRead => self.driver.read(
-                        kernel_buffer,
-                        self.kernel_readwrite_address.get(),
-                        self.kernel_readwrite_length.get(),
-                    ),
-                    NonvolatileCommand::KernelWrite => self.driver.write(
-                        kernel_buffer,
-                        self.kernel_readwrite_address.get(),
-                        self.kernel_readwrite_length.get(),
-                    ),
-                    _ => Err(ErrorCode::FAIL),
-                }
+    // Check an `AppStorage` read or write of `length` bytes at `offset` in the
+    // userspace region, with a buffer of `buffer_len` bytes.
+    fn check_app_storage_access(
+        &self,
+        command: NonvolatileCommand,
+        processid: ProcessId,
+        buffer_len: usize,
+        offset: usize,
+        length: usize,
+    ) -> Result<(), ErrorCode> {
+        if self.quiescing.get() {
+            return Err(ErrorCode::OFF);
+        }
+        self.check_userspace_permission(command, processid)?;
+        if !StorageRange::new(0, self.userspace_length).contains(offset, length)
+            || length > buffer_len
+        {
+            return Err(ErrorCode::INVAL);
+        }
+        if length > self.max_buffer_len() {
+            return Err(ErrorCode::SIZE);
+        }
+        if command == NonvolatileCommand::UserspaceWrite {
+            let aligned = self.driver.geometry().map_or(true, |geometry| {
+                Self::is_write_aligned(&geometry, self.userspace_start_address + offset, length)
             });
-        } else {
-            // If the kernel is not requesting anything, check all of the apps.
-            for cntr in self.apps.iter() {
-                let processid = cntr.processid();
-                let started_command = cntr.enter(|app, _| {
-                    if app.pending_command {
-                        app.pending_command = false;
-                        self.current_user.set(NonvolatileUser::App { processid });
-                        if let Ok(()) =
-                            self.userspace_call_driver(app.command, app.offset, app.length)
-                        {
-                            true
-                        } else {
-                            false
-                        }
-                    } else {
-                        false
-                    }
-                });
-                if started_command {
-                    break;
-                }
+            if !aligned {
+                return Err(ErrorCode::INVAL);
             }
         }
+        if self.app_storage_command.is_some() {
+            return Err(ErrorCode::BUSY);
+        }
+        Ok(())
     }
-}
 
-/// This is the callback client for the underlying physical storage driver.
-impl hil::nonvolatile_storage::NonvolatileStorageClient for NonvolatileStorage<'_> {
-    fn read_done(&self, buffer: &'static mut [u8], length: usize) {
-        // Switch on which user of this capsule generated this callback.
-        self.current_user.take().map(|user| {
-            match user {
-                NonvolatileUser::Kernel => {
-                    self.kernel_client.map(move |client| {
-                        client.read_done(buffer, length);
-                    });
-                }
-                NonvolatileUser::App { processid } => {
-                    let _ = self.apps.enter(processid, move |_, kernel_data| {
-                        // Need to copy in the contents of the buffer
-                        let _ = kernel_data
-                            .get_readwrite_processbuffer(rw_allow::READ)
-                            .and_then(|read| {
-                                read.mut_enter(|app_buffer| {
-                                    let read_len = cmp::min(app_buffer.len(), length);
-
-                                    let d = &app_buffer[0..read_len];
-                                    for (i, c) in buffer[0..read_len].iter().enumerate() {
-                                        d[i].set(*c);
-                                    }
-                                })
-                            });
+    // Accept an `AppStorage` read or write, and start it once the storage is
+    // free. Writes wait while app writes are frozen.
+    fn app_storage_access(
+        &self,
+        command: NonvolatileCommand,
+        processid: ProcessId,
+        buffer: &'static mut [u8],
+        offset: usize,
+        length: usize,
+    ) -> Result<(), (ErrorCode, &'static mut [u8])> {
+        if let Err(e) =
+            self.check_app_storage_access(command, processid, buffer.len(), offset, length)
+        {
+            return Err((e, buffer));
+        }
+        self.app_storage_buffer.replace(buffer);
+        self.app_storage_command
+            .set((processid, command, offset, length));
+        self.app_storage_pending.set(true);
+        self.check_queue();
+        Ok(())
+    }
 
-                        // Replace the buffer we used to do this read.
-                        self.buffer.replace(buffer);
+    // Start the accepted `AppStorage` command, copying the data of a write
+    // into the driver's buffer. Returns whether the storage was started.
+    fn start_app_storage(&self) -> bool {
+        let (processid, command, offset, length) = match self.app_storage_command.get() {
+            Some(accepted) if self.app_storage_pending.get() => accepted,
+            _ => return false,
+        };
+        if self.writes_frozen.get() && Self::is_app_write(command) {
+            return false;
+        }
+        self.app_storage_pending.set(false);
 
-                        // And then signal the app.
-                        kernel_data
-                            .schedule_upcall(upcall::READ_DONE, (length, 0, 0))
-                            .ok();
+        let address = self.userspace_start_address + offset;
+        let res = match self.take_buffer(length) {
+            Some(buffer) if buffer.len() >= length => {
+                self.current_user
+                    .set(NonvolatileUser::AppStorage(processid));
+                if command == NonvolatileCommand::UserspaceWrite {
+                    self.app_storage_buffer.map(|data| {
+                        buffer[..length].copy_from_slice(&data[..length]);
                     });
+                    self.driver_write(buffer, address, length)
+                } else {
+                    self.driver_read(buffer, address, length)
                 }
             }
-        });
-
-        self.check_queue();
+            Some(buffer) => {
+                self.return_buffer(buffer);
+                Err(ErrorCode::SIZE)
+            }
+            None => Err(ErrorCode::RESERVE),
+        };
+        match res {
+            Ok(()) => true,
+            Err(e) => {
+                // The command was already accepted, so the client gets its
+                // buffer back from a deferred call.
+                self.current_user.clear();
+                self.app_storage_failed.set(e);
+                self.deferred_call.set();
+                false
+            }
+        }
     }
 
-    fn write_done(&self, buffer: &'static mut [u8], length: usize) {
+    // Give the `AppStorage` client back its buffer, with the data of a read
+    // copied from `buffer`, the driver's buffer the storage used.
+    fn complete_app_storage(
+        &self,
+        processid: ProcessId,
+        buffer: Option<&'static mut [u8]>,
+        length: usize,
+        result: Result<(), ErrorCode>,
+    ) {
+        let read = matches!(
+            self.app_storage_command.take(),
+            Some((_, NonvolatileCommand::UserspaceRead, _, _))
+        );
+        let mut data = self.app_storage_buffer.take();
+        if let Some(buffer) = buffer {
+            if let (true, Some(data)) = (read, data.as_mut()) {
+                let copied = cmp::min(length, data.len());
+                data[..copied].copy_from_slice(&buffer[..copied]);
+            }
+            self.return_buffer(buffer);
+        }
+        if let Some(data) = data {
+            self.app_storage_client.map(move |client| {
+                if read {
+                    client.read_done(processid, data, length, result);
+                } else {
+                    client.write_done(processid, data, length, result);
+                }
+            });
+        }
+    }
+
+    // Count the commands in flight or queued that belong to `processid` and
+    // the ones that belong to others.
+    fn queue_status(&self, processid: ProcessId) -> (usize, usize) {
+        let (mut own, mut others) = (0, 0);
+        match self.current_user.get() {
+            Some(NonvolatileUser::App {
+                processid: current, ..
+            }) if current == processid => own += 1,
+            // Counted with the accepted `AppStorage` command below.
+            Some(NonvolatileUser::AppStorage(_)) => {}
+            Some(_) => others += 1,
+            None => {}
+        }
+        if self.kernel_pending_command.get() {
+            others += 1;
+        }
+        if self.app_storage_command.is_some() {
+            others += 1;
+        }
+        for cntr in self.apps.iter() {
+            let app_processid = cntr.processid();
+            let pending = cntr.enter(|app, _| app.pending_command);
+            if !pending {
+                continue;
+            }
+            if app_processid == processid {
+                own += 1;
+            } else {
+                others += 1;
+            }
+        }
+        (own, others)
+    }
+
+    // Queue a sync behind the reads and writes this user already has in
+    // flight or queued. Since the underlying storage is only given one
+    // command at a time, all of them have completed when the sync starts.
+    fn enqueue_sync(&self, processid: Option<ProcessId>) -> Result<(), ErrorCode> {
+        if self.quiescing.get() {
+            return Err(ErrorCode::OFF);
+        }
+        match processid {
+            Some(processid) => self
+                .apps
+                .enter(processid, |app, _| {
+                    if self.current_user.is_none() {
+                        self.current_user.set(NonvolatileUser::App {
+                            processid,
+                            short_id: processid.short_app_id(),
+                        });
+                        self.start_sync();
+                        Ok(())
+                    } else if app.pending_command {
+                        Err(ErrorCode::NOMEM)
+                    } else {
+                        app.pending_command = true;
+                        app.command = NonvolatileCommand::UserspaceSync;
+                        Ok(())
+                    }
+                })
+                .unwrap_or_else(|err| Err(err.into())),
+            None => {
+                if self.current_user.is_none() {
+                    self.current_user.set(NonvolatileUser::Kernel);
+                    self.start_sync();
+                    Ok(())
+                } else if self.kernel_pending_command.get() {
+                    Err(ErrorCode::NOMEM)
+                } else {
+                    self.kernel_pending_command.set(true);
+                    self.kernel_command.set(NonvolatileCommand::KernelSync);
+                    Ok(())
+                }
+            }
+        }
+    }
+
+    fn driver_read(
+        &self,
+        buffer: &'static mut [u8],
+        address: usize,
+        length: usize,
+    ) -> Result<(), ErrorCode> {
+        self.driver.read(buffer, address, length)?;
+        self.access_address.set(address);
+        self.access_length.set(length);
+        self.start_timeout(Operation::Read);
+        Ok(())
+    }
+
+    fn driver_write(
+        &self,
+        buffer: &'static mut [u8],
+        address: usize,
+        length: usize,
+    ) -> Result<(), ErrorCode> {
+        if let Err(e) = self.unprotect_for_write(address, length) {
+            // The storage never got the buffer. The driver's own buffers go
+            // back to where they came from.
+            if let Some(NonvolatileUser::App { .. } | NonvolatileUser::AppStorage(_)) =
+                self.current_user.get()
+            {
+                self.return_buffer(buffer);
+            }
+            return Err(e);
+        }
+        self.driver
+            .write(buffer, address, length)
+            .inspect_err(|_| {
+                self.protect();
+            })?;
+        self.access_address.set(address);
+        self.access_length.set(length);
+        self.start_timeout(Operation::Write);
+        Ok(())
+    }
+
+    // Report a finished write to its user. `timed_out` means the user was
+    // already told the write failed.
+    fn complete_write(
+        &self,
+        buffer: &'static mut [u8],
+        length: usize,
+        timed_out: bool,
+        result: Result<(), ErrorCode>,
+    ) {
+        let length = if timed_out || result.is_err() {
+            0
+        } else {
+            length
+        };
+
         // Switch on which user of this capsule generated this callback.
-        self.current_user.take().map(|user| {
-            match user {
-                NonvolatileUser::Kernel => {
-                    self.kernel_client.map(move |client| {
-                        client.write_done(buffer, length);
-                    });
+        self.current_user.take().map(|user| match user {
+            NonvolatileUser::Kernel => {
+                self.kernel_done(KernelCompletion::Write(length), Some(buffer))
+            }
+            NonvolatileUser::AppStorage(processid) => {
+                let result = if timed_out {
+                    Err(ErrorCode::FAIL)
+                } else {
+                    result
+                };
+                self.complete_app_storage(processid, Some(buffer), length, result);
+            }
+            NonvolatileUser::App {
+                processid,
+                short_id,
+            } => {
+                // Replace the buffer we used to do this write.
+                self.return_buffer(buffer);
+                if timed_out {
+                    return;
+                }
+
+                // And then signal the app.
+                let upcall_args = match result {
+                    Ok(()) => (length, 0, 0),
+                    Err(e) => (0, kernel::errorcode::into_statuscode(Err(e)), 0),
+                };
+                self.schedule_app_upcall(processid, short_id, upcall::WRITE_DONE, upcall_args);
+            }
+        });
+
+        self.check_queue();
+    }
+
+    // Report a finished kernel operation to the kernel client, or keep it
+    // until a client is set.
+    fn kernel_done(&self, completion: KernelCompletion, buffer: Option<&'static mut [u8]>) {
+        let client = match self.kernel_client.get() {
+            Some(client) => client,
+            None => {
+                self.kernel_completion.set(completion);
+                buffer.map(|buffer| self.kernel_completion_buffer.replace(buffer));
+                return;
+            }
+        };
+        match (completion, buffer) {
+            (KernelCompletion::Read(length, status), Some(buffer)) => {
+                client.read_done_status(buffer, length, status)
+            }
+            (KernelCompletion::Write(length), Some(buffer)) => client.write_done(buffer, length),
+            (KernelCompletion::Sync(result), _) => client.sync_done(result),
+            _ => {}
+        }
+    }
+
+    // Schedule an upcall for the app that issued the current operation. If
+    // that process is gone, a restarted instance of the same app gets a
+    // `CANCEL` error in the same upcall instead, so it does not wait for an
+    // operation it did not issue.
+    fn schedule_app_upcall(
+        &self,
+        processid: ProcessId,
+        short_id: ShortId,
+        upcall_num: usize,
+        upcall_args: (usize, usize, usize),
+    ) {
+        let delivered = self.apps.enter(processid, |app, kernel_data| {
+            let upcall_args = match upcall_num {
+                upcall::READ_DONE | upcall::WRITE_DONE => (
+                    upcall_args.0,
+                    upcall_args.1,
+                    upcall_args.2 | (app.active_sequence as usize) << SEQUENCE_SHIFT,
+                ),
+                _ => upcall_args,
+            };
+            kernel_data.schedule_upcall(upcall_num, upcall_args).ok();
+        });
+        if delivered.is_ok() {
+            return;
+        }
+
+        for cntr in self.apps.iter() {
+            if cntr.processid().short_app_id() == short_id {
+                cntr.enter(|_app, kernel_data| {
+                    kernel_data
+                        .schedule_upcall(
+                            upcall_num,
+                            (
+                                0,
+                                kernel::errorcode::into_statuscode(Err(ErrorCode::CANCEL)),
+                                0,
+                            ),
+                        )
+                        .ok();
+                });
+            }
+        }
+    }
+
+    fn start_timeout(&self, operation: Operation) {
+        self.operation.set(operation);
+        self.timer.map(|timer| timer.start(self.timeout_ms.get()));
+    }
+
+    // Called by the underlying storage's done callbacks. Returns whether the
+    // callback completes an operation that timed out, which has already been
+    // reported to its user.
+    fn operation_finished(&self) -> bool {
+        self.timer.map(|timer| timer.cancel());
+        self.timed_out.take()
+    }
+
+    // Ask the underlying storage to sync for `current_user`. If the storage
+    // will not call `sync_done`, the result is delivered from a deferred call
+    // instead so the user always gets exactly one callback.
+    fn start_sync(&self) {
+        let result = match self.driver.sync() {
+            Ok(()) => {
+                self.start_timeout(Operation::Sync);
+                return;
+            }
+            // Writes are durable as soon as they complete.
+            Err(ErrorCode::NOSUPPORT) => Ok(()),
+            Err(e) => Err(e),
+        };
+        self.sync_result.set(result);
+        self.deferred_call.set();
+    }
+
+    fn userspace_call_driver(
+        &self,
+        kernel_data: &GrantKernelData,
+        command: NonvolatileCommand,
+        offset: usize,
+        length: usize,
+    ) -> Result<(), ErrorCode> {
+        // Calculate where we want to actually read from in the physical
+        // storage.
+        let physical_address = match command {
+            NonvolatileCommand::UserspaceProvisionedRead
+            | NonvolatileCommand::UserspaceProvisionedWrite => {
+                offset + self.provisioned_start_address.get()
+            }
+            _ => offset + self.userspace_start_address,
+        };
+
+        // Provisioning may have been locked while this write was queued.
+        if let Some(NonvolatileUser::App { short_id, .. }) = self.current_user.get() {
+            if command == NonvolatileCommand::UserspaceProvisionedWrite {
+                self.provisioning_lock
+                    .map_or(Err(ErrorCode::NOSUPPORT), |lock| lock.may_write(short_id))?;
+            }
+        }
+
+        let buffer = self.take_buffer(length).ok_or(ErrorCode::RESERVE)?;
+        // Check that the internal buffer and the buffer that was
+        // allowed are long enough.
+        let active_len = cmp::min(length, buffer.len());
+
+        match command {
+            NonvolatileCommand::UserspaceRead | NonvolatileCommand::UserspaceProvisionedRead => {
+                self.driver_read(buffer, physical_address, active_len)
+            }
+            NonvolatileCommand::UserspaceWrite | NonvolatileCommand::UserspaceProvisionedWrite => {
+                // Need to copy bytes if this is a write! The app may have
+                // allowed a shorter buffer since the write was queued.
+                let write_len = kernel_data
+                    .get_readonly_processbuffer(ro_allow::WRITE)
+                    .and_then(|write| {
+                        write.enter(|app_buffer| {
+                            let write_len = cmp::min(active_len, app_buffer.len());
+                            app_buffer[0..write_len].copy_to_slice(&mut buffer[0..write_len]);
+                            write_len
+                        })
+                    })
+                    .unwrap_or(0);
+                if write_len == 0 {
+                    self.return_buffer(buffer);
+                    Err(ErrorCode::RESERVE)
+                } else {
+                    self.driver_write(buffer, physical_address, write_len)
                 }
-                NonvolatileUser::App { processid } => {
-                    let _ = self.apps.enter(processid, move |_app, kernel_data| {
-                        // Replace the buffer we used to do this write.
-                        self.buffer.replace(buffer);
-
-                        // And then signal the app.
-                        kernel_data
-                            .schedule_upcall(upcall::WRITE_DONE, (length, 0, 0))
-                            .ok();
+            }
+            _ => {
+                self.return_buffer(buffer);
+                Err(ErrorCode::FAIL)
+            }
+        }
+    }
+
+    fn check_queue(&self) {
+        // A client may have started a new command from its callback, in which
+        // case the queue is serviced when that command completes.
+        if self.current_user.is_some() {
+            return;
+        }
+
+        // Queued commands are dropped when shutting down, the outstanding one
+        // has finished.
+        if self.quiescing.get() {
+            self.quiesce_client.map(|client| client.quiesce_done());
+            return;
+        }
+
+        // Check if there are any pending events.
+        if self.kernel_pending_command.get()
+            && self.kernel_command.get() == NonvolatileCommand::KernelSync
+        {
+            self.kernel_pending_command.set(false);
+            self.current_user.set(NonvolatileUser::Kernel);
+            self.start_sync();
+            return;
+        }
+        if self.kernel_pending_command.get() {
+            let started_command = self.kernel_buffer.take().map_or(false, |kernel_buffer| {
+                self.kernel_pending_command.set(false);
+                self.current_user.set(NonvolatileUser::Kernel);
+
+                let res = match self.kernel_command.get() {
+                    NonvolatileCommand::KernelRead => self.driver_read(
+                        kernel_buffer,
+                        self.kernel_readwrite_address.get(),
+                        self.kernel_readwrite_length.get(),
+                    ),
+                    NonvolatileCommand::KernelWrite => self.driver_write(
+                        kernel_buffer,
+                        self.kernel_readwrite_address.get(),
+                        self.kernel_readwrite_length.get(),
+                    ),
+                    _ => Err(ErrorCode::FAIL),
+                };
+                // The HIL does not hand the buffer back on error, so there is
+                // no way to notify the kernel client. Make sure we do not get
+                // stuck waiting for a callback that will never come.
+                if res.is_err() {
+                    self.current_user.clear();
+                }
+                res.is_ok()
+            });
+            if started_command {
+                return;
+            }
+        }
+
+        if self.start_app_storage() {
+            return;
+        }
+
+        // If the kernel is not requesting anything, check the apps, starting
+        // after the app whose command was started last.
+        let started = start_in_turn(
+            self.next_app.get(),
+            || self.apps.iter(),
+            |cntr| self.start_app_command(cntr.processid()),
+        );
+        if let Some(index) = started {
+            self.next_app.set(index + 1);
+        }
+    }
+
+    // Start the queued command of `processid`, if it has one. Returns whether
+    // a command was started.
+    fn start_app_command(&self, processid: ProcessId) -> bool {
+        self.apps
+            .enter(processid, |app, kernel_data| {
+                if app.pending_command
+                    && !(self.writes_frozen.get() && Self::is_app_write(app.command))
+                {
+                    app.pending_command = false;
+                    app.active_sequence = app.command_sequence;
+                    self.current_user.set(NonvolatileUser::App {
+                        processid,
+                        short_id: processid.short_app_id(),
                     });
+                    if app.command == NonvolatileCommand::UserspaceSync {
+                        self.start_sync();
+                        return true;
+                    }
+                    let res = self.userspace_call_driver(
+                        kernel_data,
+                        app.command,
+                        app.offset,
+                        app.length,
+                    );
+                    match res {
+                        Ok(()) => true,
+                        Err(e) => {
+                            // This command was already accepted, so the app
+                            // must still get its upcall. Deliver it from a
+                            // deferred call so the upcall is not issued while
+                            // the app's command is still being handled, and
+                            // try the next app.
+                            self.current_user.clear();
+                            app.failed_command = Some((e, app.command_sequence));
+                            self.deferred_call.set();
+                            false
+                        }
+                    }
+                } else {
+                    false
+                }
+            })
+            .unwrap_or(false)
+    }
+}
+
+impl DeferredCallClient for NonvolatileStorage<'_> {
+    fn handle_deferred_call(&self) {
+        if let Some(result) = self.sync_result.take() {
+            hil::nonvolatile_storage::NonvolatileStorageClient::sync_done(self, result);
+        }
+
+        if self.kernel_client.is_some() {
+            self.kernel_completion.take().map(|completion| {
+                self.kernel_done(completion, self.kernel_completion_buffer.take())
+            });
+        }
+
+        if let Some((processid, result)) = self.app_storage_init.take() {
+            self.app_storage_client
+                .map(|client| client.init_done(processid, result));
+        }
+
+        if let Some(e) = self.app_storage_failed.take() {
+            if let Some((processid, _, _, _)) = self.app_storage_command.get() {
+                self.complete_app_storage(processid, None, 0, Err(e));
+            }
+            self.check_queue();
+        }
+
+        // Report errors for queued commands that could not be started.
+        for cntr in self.apps.iter() {
+            cntr.enter(|app, kernel_data| {
+                if let Some((upcall_num, upcall_args)) = app.take_failed_upcall() {
+                    kernel_data.schedule_upcall(upcall_num, upcall_args).ok();
                 }
+            });
+        }
+    }
+
+    fn register(&'static self) {
+        self.deferred_call.register(self);
+    }
+}
+
+/// This is the callback client for the underlying physical storage driver.
+impl hil::nonvolatile_storage::NonvolatileStorageClient for NonvolatileStorage<'_> {
+    fn read_done(&self, buffer: &'static mut [u8], length: usize) {
+        self.read_done_status(buffer, length, ReadStatus::Ok);
+    }
+
+    fn read_done_status(&self, buffer: &'static mut [u8], length: usize, status: ReadStatus) {
+        // An app whose read timed out already got its upcall, so only the
+        // buffer is put back. Data with errors is not given to apps.
+        let timed_out = self.operation_finished();
+        let length = if timed_out { 0 } else { length };
+        let app_length = if status.is_valid() { length } else { 0 };
+        let result = match status {
+            ReadStatus::Ok | ReadStatus::Corrected => Ok(()),
+            ReadStatus::Transient => Err(ErrorCode::BUSY),
+            ReadStatus::Uncorrectable => Err(ErrorCode::FAIL),
+        };
+
+        // Switch on which user of this capsule generated this callback.
+        self.current_user.take().map(|user| match user {
+            NonvolatileUser::Kernel => {
+                self.kernel_done(KernelCompletion::Read(length, status), Some(buffer))
+            }
+            NonvolatileUser::AppStorage(processid) => {
+                let result = if timed_out {
+                    Err(ErrorCode::FAIL)
+                } else {
+                    result
+                };
+                self.complete_app_storage(processid, Some(buffer), app_length, result);
+            }
+            NonvolatileUser::App { .. } if timed_out => {
+                self.return_buffer(buffer);
+            }
+            NonvolatileUser::App {
+                processid,
+                short_id,
+            } => {
+                let _ = self.apps.enter(processid, |_, kernel_data| {
+                    // Need to copy in the contents of the buffer
+                    let _ = kernel_data
+                        .get_readwrite_processbuffer(rw_allow::READ)
+                        .and_then(|read| {
+                            read.mut_enter(|app_buffer| {
+                                let read_len = cmp::min(app_buffer.len(), app_length);
+
+                                let d = &app_buffer[0..read_len];
+                                for (i, c) in buffer[0..read_len].iter().enumerate() {
+                                    d[i].set(*c);
+                                }
+                            })
+                        });
+                });
+
+                // Replace the buffer we used to do this read, even if the
+                // app is gone.
+                self.return_buffer(buffer);
+
+                // And then signal the app.
+                self.schedule_app_upcall(
+                    processid,
+                    short_id,
+                    upcall::READ_DONE,
+                    (
+                        app_length,
+                        kernel::errorcode::into_statuscode(result),
+                        status as usize,
+                    ),
+                );
             }
         });
 
         self.check_queue();
     }
+
+    fn write_done(&self, buffer: &'static mut [u8], length: usize) {
+        self.write_done_result(buffer, length, Ok(()));
+    }
+
+    fn write_done_result(
+        &self,
+        buffer: &'static mut [u8],
+        length: usize,
+        result: Result<(), ErrorCode>,
+    ) {
+        self.protect();
+        let timed_out = self.operation_finished();
+        self.complete_write(buffer, length, timed_out, result);
+    }
+
+    fn sync_done(&self, result: Result<(), ErrorCode>) {
+        if self.operation_finished() {
+            // The user was told the sync timed out.
+            self.current_user.clear();
+            self.check_queue();
+            return;
+        }
+
+        self.current_user.take().map(|user| match user {
+            NonvolatileUser::Kernel => self.kernel_done(KernelCompletion::Sync(result), None),
+            NonvolatileUser::App {
+                processid,
+                short_id,
+            } => {
+                self.schedule_app_upcall(
+                    processid,
+                    short_id,
+                    upcall::SYNC_DONE,
+                    (0, kernel::errorcode::into_statuscode(result), 0),
+                );
+            }
+            NonvolatileUser::AppStorage(_) => {}
+        });
+
+        self.check_queue();
+    }
+}
+
+/// Called by the provisioning lock once it was read at boot or written for an
+/// app.
+impl ProvisioningLockClient for NonvolatileStorage<'_> {
+    fn lock_done(&self, result: Result<(), ErrorCode>) {
+        self.protect();
+        let timed_out = self.operation_finished();
+        if let Some(NonvolatileUser::App {
+            processid,
+            short_id,
+        }) = self.current_user.take()
+        {
+            if !timed_out {
+                let upcall_args = match result {
+                    Ok(()) => (PROVISIONING_LOCK.len(), 0, 0),
+                    Err(e) => (0, kernel::errorcode::into_statuscode(Err(e)), 0),
+                };
+                self.schedule_app_upcall(processid, short_id, upcall::WRITE_DONE, upcall_args);
+            }
+        }
+        self.check_queue();
+    }
+}
+
+/// Fires when the underlying storage did not finish an operation in time.
+impl hil::time::AlarmClient for NonvolatileStorage<'_> {
+    fn alarm(&self) {
+        let user = match self.current_user.get() {
+            Some(user) if !self.timed_out.get() => user,
+            _ => return,
+        };
+        self.timeouts.set(self.timeouts.get().saturating_add(1));
+
+        let operation = self.operation.get();
+        match user {
+            NonvolatileUser::App {
+                processid,
+                short_id,
+            } => {
+                let upcall_num = match operation {
+                    Operation::Read => upcall::READ_DONE,
+                    Operation::Write | Operation::ProvisioningLock => upcall::WRITE_DONE,
+                    Operation::Sync => upcall::SYNC_DONE,
+                };
+                self.schedule_app_upcall(
+                    processid,
+                    short_id,
+                    upcall_num,
+                    (
+                        0,
+                        kernel::errorcode::into_statuscode(Err(ErrorCode::FAIL)),
+                        0,
+                    ),
+                );
+            }
+            NonvolatileUser::Kernel => {
+                // Kernel reads and writes learn about the timeout when their
+                // buffer comes back.
+                if operation == Operation::Sync {
+                    self.kernel_done(KernelCompletion::Sync(Err(ErrorCode::FAIL)), None);
+                }
+            }
+            // `AppStorage` clients get a `FAIL` error with their buffer.
+            NonvolatileUser::AppStorage(_) => {}
+        }
+
+        // Storage that cannot be reset may still be busy with the operation,
+        // so either way the driver stays busy until its callback arrives.
+        let _ = self.driver.reset();
+        self.timed_out.set(true);
+    }
 }
 
 /// Provide an interface for the kernel.
 impl<'a> hil::nonvolatile_storage::NonvolatileStorage<'a> for NonvolatileStorage<'a> {
     fn set_client(&self, client: &'a dyn hil::nonvolatile_storage::NonvolatileStorageClient) {
         self.kernel_client.set(client);
+        // Deliver a completion that arrived before the client.
+        if self.kernel_completion.is_some() {
+            self.deferred_call.set();
+        }
     }
 
     fn read(
@@ -514,6 +1967,11 @@ impl<'a> hil::nonvolatile_storage::NonvolatileStorage<'a> for NonvolatileStorage
         address: usize,
         length: usize,
     ) -> Result<(), ErrorCode> {
+        // Keep the buffer of a queued command, and of a completion the
+        // client has not been given yet.
+        if self.kernel_pending_command.get() || self.kernel_completion.is_some() {
+            return Err(ErrorCode::BUSY);
+        }
         self.kernel_buffer.replace(buffer);
         self.enqueue_command(NonvolatileCommand::KernelRead, address, length, None)
     }
@@ -524,9 +1982,191 @@ impl<'a> hil::nonvolatile_storage::NonvolatileStorage<'a> for NonvolatileStorage
         address: usize,
         length: usize,
     ) -> Result<(), ErrorCode> {
+        // Keep the buffer of a queued command, and of a completion the
+        // client has not been given yet.
+        if self.kernel_pending_command.get() || self.kernel_completion.is_some() {
+            return Err(ErrorCode::BUSY);
+        }
         self.kernel_buffer.replace(buffer);
         self.enqueue_command(NonvolatileCommand::KernelWrite, address, length, None)
     }
+
+    fn sync(&self) -> Result<(), ErrorCode> {
+        if self.kernel_completion.is_some() {
+            return Err(ErrorCode::BUSY);
+        }
+        self.enqueue_sync(None)
+    }
+
+    fn geometry(&self) -> Option<hil::nonvolatile_storage::StorageGeometry> {
+        self.driver.geometry()
+    }
+
+    /// Write `data` to the kernel region before returning, if the storage
+    /// supports it. The same checks as for `write` apply, but the write does
+    /// not wait for queued operations. Returns `BUSY` while an operation is
+    /// in flight, since it would interleave with it.
+    fn write_now(&self, address: usize, data: &[u8]) -> Result<(), ErrorCode> {
+        if !self.kernel_range().contains(address, data.len()) {
+            return Err(ErrorCode::INVAL);
+        }
+        if !self.kernel_userspace_writes.get() && self.overlaps_userspace(address, data.len()) {
+            return Err(ErrorCode::INVAL);
+        }
+        if self.current_user.is_some() {
+            return Err(ErrorCode::BUSY);
+        }
+        self.driver.write_now(address, data)
+    }
+}
+
+/// Give kernel services access to the userspace region on behalf of apps.
+/// All apps share the region, and need the same permissions as for the
+/// syscall interface. One operation is accepted at a time. Its data is copied
+/// through the driver's buffers, so it can be at most `max_buffer_len()`
+/// bytes long, and the client always gets its buffer back.
+impl<'a> hil::app_storage::AppStorage<'a> for NonvolatileStorage<'a> {
+    fn set_client(&self, client: &'a dyn hil::app_storage::AppStorageClient) {
+        self.app_storage_client.set(client);
+    }
+
+    fn isolation(&self) -> hil::app_storage::Isolation {
+        hil::app_storage::Isolation::Shared
+    }
+
+    fn init(&self, processid: ProcessId) -> Result<(), ErrorCode> {
+        if self.app_storage_init.is_some() {
+            return Err(ErrorCode::BUSY);
+        }
+        let result = self.check_userspace_permission(NonvolatileCommand::UserspaceRead, processid);
+        self.app_storage_init.set((processid, result));
+        self.deferred_call.set();
+        Ok(())
+    }
+
+    fn size(&self, processid: ProcessId) -> Result<usize, ErrorCode> {
+        self.check_userspace_permission(NonvolatileCommand::UserspaceRead, processid)?;
+        Ok(self.userspace_length)
+    }
+
+    fn read(
+        &self,
+        processid: ProcessId,
+        buffer: &'static mut [u8],
+        offset: usize,
+        length: usize,
+    ) -> Result<(), (ErrorCode, &'static mut [u8])> {
+        self.app_storage_access(
+            NonvolatileCommand::UserspaceRead,
+            processid,
+            buffer,
+            offset,
+            length,
+        )
+    }
+
+    fn write(
+        &self,
+        processid: ProcessId,
+        buffer: &'static mut [u8],
+        offset: usize,
+        length: usize,
+    ) -> Result<(), (ErrorCode, &'static mut [u8])> {
+        self.app_storage_access(
+            NonvolatileCommand::UserspaceWrite,
+            processid,
+            buffer,
+            offset,
+            length,
+        )
+    }
+}
+
+/// Tell the capsules stacked below the driver about its regions and users.
+impl StorageUsers for NonvolatileStorage<'_> {
+    fn userspace_range(&self) -> StorageRange {
+        StorageRange::new(self.userspace_start_address, self.userspace_length)
+    }
+
+    fn provisioned_range(&self) -> StorageRange {
+        NonvolatileStorage::provisioned_range(self)
+    }
+
+    fn current_app(&self) -> Option<ProcessId> {
+        match self.current_user.get() {
+            Some(NonvolatileUser::App { processid, .. }) => Some(processid),
+            Some(NonvolatileUser::AppStorage(processid)) => Some(processid),
+            _ => None,
+        }
+    }
+
+    fn has_commands(&self, processid: ProcessId) -> bool {
+        self.queue_status(processid).0 > 0
+    }
+
+    fn check_read_permission(&self, processid: ProcessId) -> Result<(), ErrorCode> {
+        self.check_userspace_permission(NonvolatileCommand::UserspaceRead, processid)
+    }
+}
+
+/// Let the board finish the outstanding storage operation before a reset.
+impl<'a> hil::quiesce::Quiesce<'a> for NonvolatileStorage<'a> {
+    fn set_quiesce_client(&self, client: &'a dyn hil::quiesce::QuiesceClient) {
+        self.quiesce_client.set(client);
+    }
+
+    fn quiesce(&self) -> Result<(), ErrorCode> {
+        self.quiescing.set(true);
+        if self.current_user.is_some() {
+            Ok(())
+        } else {
+            Err(ErrorCode::ALREADY)
+        }
+    }
+}
+
+/// Describe the storage operations of an app in process fault reports.
+impl ProcessStateReporter for NonvolatileStorage<'_> {
+    fn report_process_state(&self, processid: ProcessId, writer: &mut dyn core::fmt::Write) {
+        if StorageUsers::current_app(self) == Some(processid) {
+            let address = self.access_address.get();
+            let region = if self.overlaps_userspace(address, 1) {
+                "userspace"
+            } else if self.provisioned_range().overlaps(address, 1) {
+                "provisioned"
+            } else {
+                "kernel"
+            };
+            let _ = writer.write_fmt(format_args!(
+                " Storage: {:?} in progress at {:#010x}, {} bytes ({} region)\r\n",
+                self.operation.get(),
+                address,
+                self.access_length.get(),
+                region,
+            ));
+        }
+        // The grant cannot be entered if the process faulted while it was
+        // already entered, in which case only the state above is known.
+        let _ = self.apps.enter(processid, |app, _| {
+            if app.pending_command {
+                let region = match app.command {
+                    NonvolatileCommand::UserspaceProvisionedRead
+                    | NonvolatileCommand::UserspaceProvisionedWrite => "provisioned",
+                    _ => "userspace",
+                };
+                let _ = writer.write_fmt(format_args!(
+                    " Storage: {:?} queued at offset {:#x}, {} bytes ({} region)\r\n",
+                    app.command, app.offset, app.length, region,
+                ));
+            }
+            if let Some((e, _)) = app.failed_command {
+                let _ = writer.write_fmt(format_args!(
+                    " Storage: queued command failed to start: {:?}\r\n",
+                    e
+                ));
+            }
+        });
+    }
 }
 
 /// Provide an interface for userland.
@@ -539,8 +2179,57 @@ impl SyscallDriver for NonvolatileStorage<'_> {
     ///
     /// - `0`: Return Ok(()) if this driver is included on the platform.
     /// - `1`: Return the number of bytes available to userspace.
+    /// - `1 | PROVISIONED_REGION`: Return the size of the provisioned region.
     /// - `2`: Start a read from the nonvolatile storage.
+    /// - `2 | PROVISIONED_REGION`: Start a read from the provisioned region.
     /// - `3`: Start a write to the nonvolatile_storage.
+    /// - `3 | PROVISIONED_REGION`: Start a write to the provisioned region.
+    ///   Only allowed for the provisioning app, until provisioning is locked.
+    /// - `4`: Make all of this app's previously accepted writes durable. The
+    ///   sync upcall fires once they are.
+    /// - `5`: Return the queue status for this app: whether the storage is
+    ///   busy, how many of this app's commands are in flight or queued, and
+    ///   how many commands of others are in flight or queued. Commands of
+    ///   others may run before a new command from this app.
+    /// - `6`: Return the number of storage operations that timed out.
+    /// - `7`: Lock provisioning, making the provisioned region read-only to
+    ///   apps for good. Returns `ALREADY` if it is locked, or if the board has
+    ///   no provisioning lock, and `NOSUPPORT` for apps other than the
+    ///   provisioning app. The write upcall fires once the lock is stored.
+    /// - `9`: Return the layout of the storage, for libraries such as file
+    ///   systems that adapt to it: the write granularity, the erase block
+    ///   size, and whether apps must erase before writing. The offset and
+    ///   length of writes must be multiples of the write granularity. The
+    ///   storage below this driver erases blocks as needed, so apps never
+    ///   have to erase and the last value is always 0. Returns `NOSUPPORT` if
+    ///   the storage does not report its layout.
+    /// - `11`: Turn sequence numbers on with a nonzero first argument, or off
+    ///   with 0. While they are on, the read and write commands (`2`, `3` and
+    ///   `7`) return the sequence number of the accepted command, and the
+    ///   upcall that completes it passes the number back. Turning them on
+    ///   again keeps counting from the last number given out.
+    ///
+    /// With `WIDE_OFFSET` set, commands `1`, `2`, and `3` (with or without
+    /// `PROVISIONED_REGION`) take the offset as two 32-bit halves,
+    /// low half first, and read or write the whole allowed buffer. The size
+    /// commands return a `u64`.
+    ///
+    /// Errors commands return, besides those given for each command above:
+    ///
+    /// - `NODEVICE`: The board gave the driver no such region, for example
+    ///   the provisioned region on a board without one.
+    /// - `NOSUPPORT`: The app has no permission for the region, or the region
+    ///   is read-only to apps.
+    /// - `BUSY`: The driver cannot take the command yet and the app should
+    ///   try again later, for example while the provisioning lock is still
+    ///   being read after boot.
+    /// - `INVAL`: The offset or length is outside the region, or not aligned
+    ///   to the storage's write granularity.
+    /// - `NOMEM`: The app already has a command queued, or its grant region
+    ///   has no room for the driver's state.
+    /// - `RESERVE`: App writes are frozen while the kernel works on the
+    ///   storage. The app can retry once it gets the thawed upcall.
+    /// - `OFF`: The driver is shutting down and takes no new commands.
     fn command(
         &self,
         command_num: usize,
@@ -548,15 +2237,35 @@ impl SyscallDriver for NonvolatileStorage<'_> {
         length: usize,
         processid: ProcessId,
     ) -> CommandReturn {
+        if command_num & WIDE_OFFSET != 0 {
+            return self.wide_command(command_num & !WIDE_OFFSET, offset, length, processid);
+        }
+
         match command_num {
             0 => CommandReturn::success(),
 
             1 => {
-                // How many bytes are accessible from userspace
-                // TODO: Would break on 64-bit platforms
+                // How many bytes are accessible from userspace. Sizes that do
+                // not fit in 32 bits are read with `WIDE_OFFSET`.
                 CommandReturn::success_u32(self.userspace_length as u32)
             }
 
+            c if c == 1 | PROVISIONED_REGION => {
+                CommandReturn::success_u32(self.provisioned_length.get() as u32)
+            }
+
+            c if c == 2 | PROVISIONED_REGION => {
+                match self.enqueue_command(
+                    NonvolatileCommand::UserspaceProvisionedRead,
+                    offset,
+                    length,
+                    Some(processid),
+                ) {
+                    Ok(()) => self.accepted(processid),
+                    Err(e) => CommandReturn::failure(e),
+                }
+            }
+
             2 => {
                 // Issue a read command
                 let res = self.enqueue_command(
@@ -567,7 +2276,7 @@ impl SyscallDriver for NonvolatileStorage<'_> {
                 );
 
                 match res {
-                    Ok(()) => CommandReturn::success(),
+                    Ok(()) => self.accepted(processid),
                     Err(e) => CommandReturn::failure(e),
                 }
             }
@@ -582,11 +2291,65 @@ impl SyscallDriver for NonvolatileStorage<'_> {
                 );
 
                 match res {
-                    Ok(()) => CommandReturn::success(),
+                    Ok(()) => self.accepted(processid),
                     Err(e) => CommandReturn::failure(e),
                 }
             }
 
+            c if c == 3 | PROVISIONED_REGION => {
+                match self.enqueue_command(
+                    NonvolatileCommand::UserspaceProvisionedWrite,
+                    offset,
+                    length,
+                    Some(processid),
+                ) {
+                    Ok(()) => self.accepted(processid),
+                    Err(e) => CommandReturn::failure(e),
+                }
+            }
+
+            4 => match self.enqueue_sync(Some(processid)) {
+                Ok(()) => CommandReturn::success(),
+                Err(e) => CommandReturn::failure(e),
+            },
+
+            5 => {
+                let (own, others) = self.queue_status(processid);
+                CommandReturn::success_u32_u32_u32(
+                    self.current_user.is_some() as u32,
+                    own as u32,
+                    others as u32,
+                )
+            }
+
+            6 => CommandReturn::success_u32(self.timeouts.get()),
+
+            7 => match self.lock_provisioning(processid) {
+                Ok(()) => self.accepted(processid),
+                Err(e) => CommandReturn::failure(e),
+            },
+
+            9 => match self.driver.geometry() {
+                Some(geometry) => CommandReturn::success_u32_u32_u32(
+                    geometry.write_granularity as u32,
+                    geometry.erase_block_size as u32,
+                    0,
+                ),
+                None => CommandReturn::failure(ErrorCode::NOSUPPORT),
+            },
+
+            11 => self
+                .apps
+                .enter(processid, |app, _| {
+                    app.sequence = match (offset, app.sequence) {
+                        (0, _) => None,
+                        (_, Some(last)) => Some(last),
+                        (_, None) => Some(0),
+                    };
+                    CommandReturn::success()
+                })
+                .unwrap_or_else(|err| CommandReturn::failure(err.into())),
+
             _ => CommandReturn::failure(ErrorCode::NOSUPPORT),
         }
     }
@@ -595,3 +2358,136 @@ impl SyscallDriver for NonvolatileStorage<'_> {
         self.apps.enter(processid, |_, _| {})
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use core::cell::{Cell, RefCell};
+
+    use kernel::ErrorCode;
+
+    use super::{start_in_turn, upcall, App, NonvolatileCommand, SEQUENCE_SHIFT};
+
+    const APPS: usize = 3;
+
+    // Run one turn over `apps`, the way `check_queue()` does, and return the
+    // app that was started.
+    fn turn(next_app: &Cell<usize>, apps: &RefCell<[App; APPS]>) -> Option<usize> {
+        let started = start_in_turn(
+            next_app.get(),
+            || 0..APPS,
+            |index| {
+                let app = &mut apps.borrow_mut()[index];
+                if !app.pending_command {
+                    return false;
+                }
+                app.pending_command = false;
+                // A refused command is reported from a deferred call.
+                app.failed_command.is_none()
+            },
+        );
+        if let Some(index) = started {
+            next_app.set(index + 1);
+        }
+        started
+    }
+
+    fn queue(apps: &RefCell<[App; APPS]>, index: usize, command: NonvolatileCommand) {
+        let app = &mut apps.borrow_mut()[index];
+        app.pending_command = true;
+        app.command = command;
+    }
+
+    #[test]
+    fn apps_take_turns() {
+        let next_app = Cell::new(0);
+        let apps = RefCell::new(core::array::from_fn(|_| App::default()));
+        for index in 0..APPS {
+            queue(&apps, index, NonvolatileCommand::UserspaceWrite);
+        }
+        for round in 0..4 {
+            for index in 0..APPS {
+                assert_eq!(turn(&next_app, &apps), Some(index), "round {}", round);
+                // The app queues its next write as soon as this one starts.
+                queue(&apps, index, NonvolatileCommand::UserspaceWrite);
+            }
+        }
+    }
+
+    #[test]
+    fn queued_app_waits_at_most_one_turn_of_each_other_app() {
+        let next_app = Cell::new(0);
+        let apps = RefCell::new(core::array::from_fn(|_| App::default()));
+        // The other apps keep a write queued all the time, the last one queues
+        // a write after a few turns.
+        for index in 0..APPS - 1 {
+            queue(&apps, index, NonvolatileCommand::UserspaceWrite);
+        }
+        let mut waited = None;
+        for turns in 0..4 * APPS {
+            if turns == 5 {
+                queue(&apps, APPS - 1, NonvolatileCommand::UserspaceWrite);
+                waited = Some(0);
+            }
+            let started = turn(&next_app, &apps).unwrap();
+            if started == APPS - 1 {
+                break;
+            }
+            queue(&apps, started, NonvolatileCommand::UserspaceWrite);
+            waited = waited.map(|waited| waited + 1);
+        }
+        assert!(
+            waited.map_or(false, |waited| waited < APPS),
+            "last app waited {:?} turns",
+            waited
+        );
+    }
+
+    #[test]
+    fn refused_command_does_not_hold_up_the_next_app() {
+        let next_app = Cell::new(0);
+        let apps = RefCell::new(core::array::from_fn(|_| App::default()));
+        queue(&apps, 0, NonvolatileCommand::UserspaceRead);
+        queue(&apps, 1, NonvolatileCommand::UserspaceWrite);
+        apps.borrow_mut()[1].failed_command = Some((ErrorCode::FAIL, 7));
+
+        assert_eq!(turn(&next_app, &apps), Some(0));
+        // The storage refuses the write of the second app, so the same turn
+        // moves on, and nothing is left queued.
+        queue(&apps, 2, NonvolatileCommand::UserspaceRead);
+        assert_eq!(turn(&next_app, &apps), Some(2));
+        assert_eq!(turn(&next_app, &apps), None);
+
+        // The refused write is reported exactly once, with its sequence number.
+        let app = &mut apps.borrow_mut()[1];
+        assert_eq!(
+            app.take_failed_upcall(),
+            Some((
+                upcall::WRITE_DONE,
+                (
+                    0,
+                    kernel::errorcode::into_statuscode(Err(ErrorCode::FAIL)),
+                    7 << SEQUENCE_SHIFT,
+                ),
+            ))
+        );
+        assert_eq!(app.take_failed_upcall(), None);
+    }
+
+    #[test]
+    fn refused_read_is_reported_as_read() {
+        let mut app = App::default();
+        app.command = NonvolatileCommand::UserspaceProvisionedRead;
+        app.failed_command = Some((ErrorCode::RESERVE, 0));
+        assert_eq!(
+            app.take_failed_upcall(),
+            Some((
+                upcall::READ_DONE,
+                (
+                    0,
+                    kernel::errorcode::into_statuscode(Err(ErrorCode::RESERVE)),
+                    0,
+                ),
+            ))
+        );
+    }
+}
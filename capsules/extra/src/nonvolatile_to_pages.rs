@@ -189,6 +189,23 @@ impl<'a, F: hil::flash::Flash> hil::nonvolatile_storage::NonvolatileStorage<'a>
                 }
             })
     }
+
+    fn geometry(&self) -> Option<hil::nonvolatile_storage::StorageGeometry> {
+        // Writes of any alignment and length are turned into page writes.
+        self.driver
+            .geometry()
+            .map(|geometry| hil::nonvolatile_storage::StorageGeometry {
+                write_granularity: 1,
+                ..geometry
+            })
+    }
+
+    fn read_mapped(&self, address: usize, buffer: &mut [u8]) -> Result<(), ErrorCode> {
+        if self.state.get() != State::Idle {
+            return Err(ErrorCode::BUSY);
+        }
+        self.driver.read_mapped(address, buffer)
+    }
 }
 
 impl<F: hil::flash::Flash> hil::flash::Client<F> for NonvolatileToPages<'_, F> {
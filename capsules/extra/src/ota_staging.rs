@@ -0,0 +1,761 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2024.
+
+//! Dual-image (A/B) staging area for over-the-air updates.
+//!
+//! This capsule manages two equally sized image slots and a small boot record
+//! in nonvolatile storage. The boot record names the slot the bootloader
+//! should start. A userspace updater streams a new image into the slot that is
+//! not named by the boot record, and then provides a signature over the image.
+//! The capsule computes the digest of the image as it is written, checks the
+//! signature with a `SignatureVerify` implementation, and only if the
+//! signature is valid rewrites the boot record to point at the new slot.
+//!
+//! The running image is never modified, so an interrupted or rejected update
+//! leaves the board booting what it booted before.
+//!
+//! Boot Record
+//! -----------
+//!
+//! The boot record is `BOOT_RECORD_LEN` bytes long:
+//!
+//! ```text
+//! 0        4      5       6        8
+//! +--------+------+-------+--------+
+//! | "TOTA" | slot | !slot | unused |
+//! +--------+------+-------+--------+
+//! ```
+//!
+//! `slot` is 0 for slot A and 1 for slot B. A record with the wrong magic or a
+//! mismatched inverted copy is treated as naming slot A.
+//!
+//! If the record cannot be read, no update can start, since there is no way
+//! to tell which slot is safe to overwrite. The next `begin` command reads it
+//! again.
+//!
+//! Buffers
+//! -------
+//!
+//! The boot record and the image chunks are read and written with separate
+//! buffers. A storage that refuses an operation outright keeps the buffer it
+//! was given, so after such a refusal the operations that need that buffer
+//! fail with `RESERVE` until the board restarts. A refused record write no
+//! longer stops images from being received, and the reverse.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust,ignore
+//! let ota = components::ota_staging::OtaStagingComponent::new(
+//!     board_kernel,
+//!     capsules_extra::ota_staging::DRIVER_NUM,
+//!     ota_storage,
+//!     sha,
+//!     verifier,
+//!     0x80000, // slot A
+//!     0xA0000, // slot B
+//!     0x20000, // slot length
+//!     0xC0000, // boot record
+//! )
+//! .finalize(components::ota_staging_component_static!(Verifier, Sha, 32, 64));
+//! ```
+
+use core::cell::Cell;
+
+use kernel::errorcode::into_statuscode;
+use kernel::grant::{AllowRoCount, AllowRwCount, Grant, UpcallCount};
+use kernel::hil;
+use kernel::processbuffer::ReadableProcessBuffer;
+use kernel::syscall::{CommandReturn, SyscallDriver};
+use kernel::utilities::cells::{MapCell, OptionalCell, TakeCell};
+use kernel::utilities::leasable_buffer::{SubSlice, SubSliceMut};
+use kernel::{ErrorCode, ProcessId};
+
+/// Syscall driver number.
+use capsules_core::driver;
+pub const DRIVER_NUM: usize = driver::NUM::OtaStaging as usize;
+
+/// Size of the internal buffer. Image chunks can be at most this long.
+pub const BUF_LEN: usize = 512;
+
+/// Length of the boot record in storage.
+pub const BOOT_RECORD_LEN: usize = 8;
+
+const BOOT_RECORD_MAGIC: [u8; 4] = *b"TOTA";
+
+/// IDs for subscribed upcalls.
+mod upcall {
+    /// A chunk of the image was written.
+    pub const WRITE_DONE: usize = 0;
+    /// The image was verified and staged, or rejected.
+    pub const FINISH_DONE: usize = 1;
+    /// Number of upcalls.
+    pub const COUNT: u8 = 2;
+}
+
+/// Ids for read-only allow buffers
+mod ro_allow {
+    /// The next chunk of the image.
+    pub const DATA: usize = 0;
+    /// The signature over the whole image.
+    pub const SIGNATURE: usize = 1;
+    /// The number of allow buffers the kernel stores for this grant
+    pub const COUNT: u8 = 2;
+}
+
+/// One of the two image slots.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Slot {
+    A = 0,
+    B = 1,
+}
+
+impl Slot {
+    /// The slot that is not `self`.
+    pub fn other(self) -> Slot {
+        match self {
+            Slot::A => Slot::B,
+            Slot::B => Slot::A,
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+enum State {
+    /// The boot record has not been read, or could not be.
+    Unloaded,
+    /// Reading the boot record.
+    Loading,
+    /// No update in progress.
+    Idle,
+    /// An update is in progress and waiting for the next chunk or `finish`.
+    Receiving,
+    /// A chunk of this length is being added to the digest.
+    HashingChunk(usize),
+    /// A chunk of this length is being written to the inactive slot.
+    WritingChunk(usize),
+    /// The digest of the image is being computed.
+    Hashing,
+    /// The signature over the digest is being checked.
+    Verifying,
+    /// The boot record is being updated to point at the new image.
+    WritingRecord,
+    /// A verified image is staged and will be booted after the next reset.
+    Staged,
+}
+
+impl State {
+    /// State reported to userspace by the status command.
+    fn status_code(self) -> u32 {
+        match self {
+            State::Idle => 0,
+            State::Receiving => 1,
+            State::Staged => 3,
+            State::Unloaded => 4,
+            _ => 2,
+        }
+    }
+}
+
+/// The slot named by the first `length` bytes of `record`, or `None` if they
+/// are not a whole boot record, because the read failed.
+fn decode_boot_record(record: &[u8], length: usize) -> Option<Slot> {
+    if length < BOOT_RECORD_LEN || record.len() < BOOT_RECORD_LEN {
+        return None;
+    }
+    let slot = record[4];
+    let valid = record[..4] == BOOT_RECORD_MAGIC && slot == !record[5];
+    Some(match (valid, slot) {
+        (true, 1) => Slot::B,
+        _ => Slot::A,
+    })
+}
+
+/// Fill `record` with a boot record naming `slot`.
+fn encode_boot_record(slot: Slot, record: &mut [u8]) {
+    let slot = slot as u8;
+    record[..4].copy_from_slice(&BOOT_RECORD_MAGIC);
+    record[4] = slot;
+    record[5] = !slot;
+    record[6..BOOT_RECORD_LEN].fill(0xFF);
+}
+
+#[derive(Default)]
+pub struct App {}
+
+pub struct OtaStaging<
+    'a,
+    S: hil::public_key_crypto::signature::SignatureVerify<'a, HL, SL>,
+    H: hil::digest::DigestDataHash<'a, HL>,
+    const HL: usize,
+    const SL: usize,
+> {
+    storage: &'a dyn hil::nonvolatile_storage::NonvolatileStorage<'a>,
+    hasher: &'a H,
+    verifier: &'a S,
+    apps: Grant<
+        App,
+        UpcallCount<{ upcall::COUNT }>,
+        AllowRoCount<{ ro_allow::COUNT }>,
+        AllowRwCount<0>,
+    >,
+    slot_addresses: [usize; 2],
+    slot_length: usize,
+    record_address: usize,
+    // Image chunks.
+    buffer: TakeCell<'static, [u8]>,
+    // The boot record.
+    record_buffer: TakeCell<'static, [u8]>,
+    hash: MapCell<&'static mut [u8; HL]>,
+    signature: MapCell<&'static mut [u8; SL]>,
+    state: Cell<State>,
+    // Slot named by the boot record.
+    boot_slot: Cell<Slot>,
+    // Process running the current update.
+    owner: OptionalCell<ProcessId>,
+    image_length: Cell<usize>,
+    // Number of image bytes written so far.
+    offset: Cell<usize>,
+}
+
+impl<
+        'a,
+        S: hil::public_key_crypto::signature::SignatureVerify<'a, HL, SL>,
+        H: hil::digest::DigestDataHash<'a, HL>,
+        const HL: usize,
+        const SL: usize,
+    > OtaStaging<'a, S, H, HL, SL>
+{
+    pub fn new(
+        storage: &'a dyn hil::nonvolatile_storage::NonvolatileStorage<'a>,
+        hasher: &'a H,
+        verifier: &'a S,
+        grant: Grant<
+            App,
+            UpcallCount<{ upcall::COUNT }>,
+            AllowRoCount<{ ro_allow::COUNT }>,
+            AllowRwCount<0>,
+        >,
+        slot_a_address: usize,
+        slot_b_address: usize,
+        slot_length: usize,
+        record_address: usize,
+        buffer: &'static mut [u8],
+        record_buffer: &'static mut [u8],
+        hash_buffer: &'static mut [u8; HL],
+        signature_buffer: &'static mut [u8; SL],
+    ) -> Self {
+        Self {
+            storage,
+            hasher,
+            verifier,
+            apps: grant,
+            slot_addresses: [slot_a_address, slot_b_address],
+            slot_length,
+            record_address,
+            buffer: TakeCell::new(buffer),
+            record_buffer: TakeCell::new(record_buffer),
+            hash: MapCell::new(hash_buffer),
+            signature: MapCell::new(signature_buffer),
+            state: Cell::new(State::Unloaded),
+            boot_slot: Cell::new(Slot::A),
+            owner: OptionalCell::empty(),
+            image_length: Cell::new(0),
+            offset: Cell::new(0),
+        }
+    }
+
+    /// Read the boot record. Must be called once at boot, no update can start
+    /// until it has been read.
+    ///
+    /// If this or the read fails, the record is read again when an updater
+    /// tries to start an update.
+    pub fn load_boot_record(&self) -> Result<(), ErrorCode> {
+        let buffer = self.record_buffer.take().ok_or(ErrorCode::RESERVE)?;
+        self.state.set(State::Loading);
+        self.storage
+            .read(buffer, self.record_address, BOOT_RECORD_LEN)
+            .inspect_err(|_| self.state.set(State::Unloaded))
+    }
+
+    /// Slot that the bootloader will start after the next reset.
+    pub fn boot_slot(&self) -> Slot {
+        self.boot_slot.get()
+    }
+
+    fn inactive_slot(&self) -> Slot {
+        self.boot_slot.get().other()
+    }
+
+    fn owner_alive(&self) -> bool {
+        self.owner
+            .map_or(false, |owner| self.apps.enter(owner, |_, _| ()).is_ok())
+    }
+
+    fn begin(&self, processid: ProcessId, image_length: usize) -> Result<u32, ErrorCode> {
+        match self.state.get() {
+            State::Idle => {}
+            // An update that was never finished can be restarted by its owner,
+            // or taken over if the owner is gone.
+            State::Receiving if self.owner.contains(&processid) || !self.owner_alive() => {}
+            State::Staged => return Err(ErrorCode::ALREADY),
+            // Read the boot record again. The updater tries again once it has
+            // been read.
+            State::Unloaded => {
+                self.load_boot_record()?;
+                return Err(ErrorCode::BUSY);
+            }
+            _ => return Err(ErrorCode::BUSY),
+        }
+        if image_length == 0 || image_length > self.slot_length {
+            return Err(ErrorCode::SIZE);
+        }
+        if self.buffer.is_none() {
+            return Err(ErrorCode::RESERVE);
+        }
+
+        self.hasher.clear_data();
+        self.owner.set(processid);
+        self.image_length.set(image_length);
+        self.offset.set(0);
+        self.state.set(State::Receiving);
+        Ok(self.inactive_slot() as u32)
+    }
+
+    // Check that `processid` runs the update and nothing is in flight.
+    fn check_owner(&self, processid: ProcessId) -> Result<(), ErrorCode> {
+        if !self.owner.contains(&processid) {
+            Err(ErrorCode::RESERVE)
+        } else if self.state.get() != State::Receiving {
+            Err(ErrorCode::BUSY)
+        } else {
+            Ok(())
+        }
+    }
+
+    fn write_chunk(&self, processid: ProcessId, length: usize) -> Result<(), ErrorCode> {
+        self.check_owner(processid)?;
+        let remaining = self.image_length.get() - self.offset.get();
+        if length == 0 || length > remaining {
+            return Err(ErrorCode::SIZE);
+        }
+
+        let buffer = self.buffer.take().ok_or(ErrorCode::RESERVE)?;
+        if length > buffer.len() {
+            self.buffer.replace(buffer);
+            return Err(ErrorCode::SIZE);
+        }
+
+        let res = self
+            .apps
+            .enter(processid, |_, kernel_data| {
+                kernel_data
+                    .get_readonly_processbuffer(ro_allow::DATA)
+                    .and_then(|data| {
+                        data.enter(|data| {
+                            if data.len() < length {
+                                Err(ErrorCode::SIZE)
+                            } else {
+                                data[..length].copy_to_slice(&mut buffer[..length]);
+                                Ok(())
+                            }
+                        })
+                    })
+                    .unwrap_or(Err(ErrorCode::RESERVE))
+            })
+            .unwrap_or_else(|err| Err(err.into()));
+        if let Err(e) = res {
+            self.buffer.replace(buffer);
+            return Err(e);
+        }
+
+        // Hash the chunk first, it is written once the digest engine hands
+        // the buffer back.
+        let mut data = SubSliceMut::new(buffer);
+        data.slice(..length);
+        match self.hasher.add_mut_data(data) {
+            Ok(()) => {
+                self.state.set(State::HashingChunk(length));
+                Ok(())
+            }
+            Err((e, data)) => {
+                self.buffer.replace(data.take());
+                Err(e)
+            }
+        }
+    }
+
+    fn finish(&self, processid: ProcessId) -> Result<(), ErrorCode> {
+        self.check_owner(processid)?;
+        if self.offset.get() != self.image_length.get() {
+            return Err(ErrorCode::SIZE);
+        }
+
+        let res = self
+            .apps
+            .enter(processid, |_, kernel_data| {
+                kernel_data
+                    .get_readonly_processbuffer(ro_allow::SIGNATURE)
+                    .and_then(|signature| {
+                        signature.enter(|signature| {
+                            if signature.len() < SL {
+                                Err(ErrorCode::SIZE)
+                            } else {
+                                self.signature.map_or(Err(ErrorCode::RESERVE), |buf| {
+                                    signature[..SL].copy_to_slice(&mut buf[..]);
+                                    Ok(())
+                                })
+                            }
+                        })
+                    })
+                    .unwrap_or(Err(ErrorCode::RESERVE))
+            })
+            .unwrap_or_else(|err| Err(err.into()));
+        res?;
+
+        let hash = self.hash.take().ok_or(ErrorCode::RESERVE)?;
+        match self.hasher.run(hash) {
+            Ok(()) => {
+                self.state.set(State::Hashing);
+                Ok(())
+            }
+            Err((e, hash)) => {
+                self.hash.replace(hash);
+                Err(e)
+            }
+        }
+    }
+
+    fn abort(&self, processid: ProcessId) -> Result<(), ErrorCode> {
+        self.check_owner(processid)?;
+        self.hasher.clear_data();
+        self.owner.clear();
+        self.state.set(State::Idle);
+        Ok(())
+    }
+
+    // End the current update and tell the owner why.
+    fn fail_update(&self, upcall_num: usize, error: ErrorCode) {
+        self.state.set(State::Idle);
+        self.owner.take().map(|owner| {
+            self.notify(owner, upcall_num, Err(error), 0);
+        });
+    }
+
+    fn notify(
+        &self,
+        processid: ProcessId,
+        upcall_num: usize,
+        result: Result<(), ErrorCode>,
+        value: usize,
+    ) {
+        let _ = self.apps.enter(processid, |_, kernel_data| {
+            kernel_data
+                .schedule_upcall(upcall_num, (into_statuscode(result), value, 0))
+                .ok();
+        });
+    }
+
+    fn write_boot_record(&self) -> Result<(), ErrorCode> {
+        let buffer = self.record_buffer.take().ok_or(ErrorCode::RESERVE)?;
+        encode_boot_record(self.inactive_slot(), buffer);
+        self.storage
+            .write(buffer, self.record_address, BOOT_RECORD_LEN)
+    }
+}
+
+impl<
+        'a,
+        S: hil::public_key_crypto::signature::SignatureVerify<'a, HL, SL>,
+        H: hil::digest::DigestDataHash<'a, HL>,
+        const HL: usize,
+        const SL: usize,
+    > hil::nonvolatile_storage::NonvolatileStorageClient for OtaStaging<'a, S, H, HL, SL>
+{
+    fn read_done(&self, buffer: &'static mut [u8], length: usize) {
+        if self.state.get() == State::Loading {
+            match decode_boot_record(buffer, length) {
+                Some(slot) => {
+                    self.boot_slot.set(slot);
+                    self.state.set(State::Idle);
+                }
+                None => self.state.set(State::Unloaded),
+            }
+        }
+        // Only the boot record is ever read.
+        self.record_buffer.replace(buffer);
+    }
+
+    fn write_done(&self, buffer: &'static mut [u8], length: usize) {
+        match self.state.get() {
+            State::WritingChunk(chunk_length) => {
+                self.buffer.replace(buffer);
+                if length != chunk_length {
+                    // The digest already includes this chunk, so the update
+                    // can not continue.
+                    self.fail_update(upcall::WRITE_DONE, ErrorCode::FAIL);
+                    return;
+                }
+                self.offset.set(self.offset.get() + length);
+                self.state.set(State::Receiving);
+                self.owner.map(|owner| {
+                    self.notify(owner, upcall::WRITE_DONE, Ok(()), self.offset.get());
+                });
+            }
+            State::WritingRecord => {
+                self.record_buffer.replace(buffer);
+                if length != BOOT_RECORD_LEN {
+                    // The record may be torn, so read back what the
+                    // bootloader will see before another update starts.
+                    self.state.set(State::Unloaded);
+                    self.owner.take().map(|owner| {
+                        self.notify(owner, upcall::FINISH_DONE, Err(ErrorCode::FAIL), 0);
+                    });
+                    return;
+                }
+                let staged = self.inactive_slot();
+                self.boot_slot.set(staged);
+                self.state.set(State::Staged);
+                self.owner.take().map(|owner| {
+                    self.notify(owner, upcall::FINISH_DONE, Ok(()), staged as usize);
+                });
+            }
+            _ => {
+                self.buffer.replace(buffer);
+            }
+        }
+    }
+}
+
+impl<
+        'a,
+        S: hil::public_key_crypto::signature::SignatureVerify<'a, HL, SL>,
+        H: hil::digest::DigestDataHash<'a, HL>,
+        const HL: usize,
+        const SL: usize,
+    > hil::digest::ClientData<HL> for OtaStaging<'a, S, H, HL, SL>
+{
+    fn add_data_done(&self, _result: Result<(), ErrorCode>, _data: SubSlice<'static, u8>) {}
+
+    fn add_mut_data_done(&self, result: Result<(), ErrorCode>, data: SubSliceMut<'static, u8>) {
+        let buffer = data.take();
+        let length = match self.state.get() {
+            State::HashingChunk(length) => length,
+            _ => {
+                self.buffer.replace(buffer);
+                return;
+            }
+        };
+
+        if let Err(e) = result {
+            self.buffer.replace(buffer);
+            self.fail_update(upcall::WRITE_DONE, e);
+            return;
+        }
+
+        let address = self.slot_addresses[self.inactive_slot() as usize] + self.offset.get();
+        match self.storage.write(buffer, address, length) {
+            Ok(()) => self.state.set(State::WritingChunk(length)),
+            // The digest already includes this chunk, so the update can not
+            // continue. The storage does not give back the buffer it
+            // refused.
+            Err(e) => self.fail_update(upcall::WRITE_DONE, e),
+        }
+    }
+}
+
+impl<
+        'a,
+        S: hil::public_key_crypto::signature::SignatureVerify<'a, HL, SL>,
+        H: hil::digest::DigestDataHash<'a, HL>,
+        const HL: usize,
+        const SL: usize,
+    > hil::digest::ClientHash<HL> for OtaStaging<'a, S, H, HL, SL>
+{
+    fn hash_done(&self, result: Result<(), ErrorCode>, digest: &'static mut [u8; HL]) {
+        if let Err(e) = result {
+            self.hash.replace(digest);
+            self.fail_update(upcall::FINISH_DONE, e);
+            return;
+        }
+
+        match self.signature.take() {
+            Some(signature) => match self.verifier.verify(digest, signature) {
+                Ok(()) => self.state.set(State::Verifying),
+                Err((e, digest, signature)) => {
+                    self.hash.replace(digest);
+                    self.signature.replace(signature);
+                    self.fail_update(upcall::FINISH_DONE, e);
+                }
+            },
+            None => {
+                self.hash.replace(digest);
+                self.fail_update(upcall::FINISH_DONE, ErrorCode::RESERVE);
+            }
+        }
+    }
+}
+
+impl<
+        'a,
+        S: hil::public_key_crypto::signature::SignatureVerify<'a, HL, SL>,
+        H: hil::digest::DigestDataHash<'a, HL>,
+        const HL: usize,
+        const SL: usize,
+    > hil::public_key_crypto::signature::ClientVerify<HL, SL> for OtaStaging<'a, S, H, HL, SL>
+{
+    fn verification_done(
+        &self,
+        result: Result<bool, ErrorCode>,
+        hash: &'static mut [u8; HL],
+        signature: &'static mut [u8; SL],
+    ) {
+        self.hash.replace(hash);
+        self.signature.replace(signature);
+
+        match result {
+            Ok(true) => match self.write_boot_record() {
+                Ok(()) => self.state.set(State::WritingRecord),
+                Err(e) => self.fail_update(upcall::FINISH_DONE, e),
+            },
+            Ok(false) => self.fail_update(upcall::FINISH_DONE, ErrorCode::FAIL),
+            Err(e) => self.fail_update(upcall::FINISH_DONE, e),
+        }
+    }
+}
+
+impl<
+        'a,
+        S: hil::public_key_crypto::signature::SignatureVerify<'a, HL, SL>,
+        H: hil::digest::DigestDataHash<'a, HL>,
+        const HL: usize,
+        const SL: usize,
+    > SyscallDriver for OtaStaging<'a, S, H, HL, SL>
+{
+    /// Command interface.
+    ///
+    /// Only one process can run an update at a time.
+    ///
+    /// ### `command_num`
+    ///
+    /// - `0`: Return Ok(()) if this driver is included on the platform.
+    /// - `1`: Start an update of `arg1` bytes. Returns the slot the image
+    ///   will be written to. Fails with `ALREADY` if an image is already
+    ///   staged and with `BUSY` if another process is running an update. If
+    ///   the boot record could not be read, it is read again and this fails
+    ///   with `BUSY`.
+    /// - `2`: Write the first `arg1` bytes of the data allow buffer to the
+    ///   image. The `WRITE_DONE` upcall reports the status and the number of
+    ///   image bytes written so far. Chunks are at most `BUF_LEN` bytes.
+    /// - `3`: Check the signature allow buffer against the complete image
+    ///   and, if valid, make it the boot image. The `FINISH_DONE` upcall
+    ///   reports the status and the staged slot. An invalid signature is
+    ///   reported as `FAIL`.
+    /// - `4`: Return the slot named by the boot record and the update state:
+    ///   0 idle, 1 receiving, 2 busy, 3 staged, 4 boot record not read.
+    /// - `5`: Abandon the current update.
+    ///
+    /// Any error reported through an upcall ends the update.
+    fn command(
+        &self,
+        command_num: usize,
+        arg1: usize,
+        _arg2: usize,
+        processid: ProcessId,
+    ) -> CommandReturn {
+        match command_num {
+            0 => CommandReturn::success(),
+
+            1 => match self.begin(processid, arg1) {
+                Ok(slot) => CommandReturn::success_u32(slot),
+                Err(e) => CommandReturn::failure(e),
+            },
+
+            2 => self.write_chunk(processid, arg1).into(),
+
+            3 => self.finish(processid).into(),
+
+            4 => CommandReturn::success_u32_u32(
+                self.boot_slot.get() as u32,
+                self.state.get().status_code(),
+            ),
+
+            5 => self.abort(processid).into(),
+
+            _ => CommandReturn::failure(ErrorCode::NOSUPPORT),
+        }
+    }
+
+    fn allocate_grant(&self, processid: ProcessId) -> Result<(), kernel::process::Error> {
+        self.apps.enter(processid, |_, _| {})
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{decode_boot_record, encode_boot_record, Slot, State, BOOT_RECORD_LEN};
+
+    #[test]
+    fn boot_record_round_trip() {
+        for slot in [Slot::A, Slot::B] {
+            let mut record = [0; BOOT_RECORD_LEN];
+            encode_boot_record(slot, &mut record);
+            assert_eq!(decode_boot_record(&record, BOOT_RECORD_LEN), Some(slot));
+        }
+    }
+
+    #[test]
+    fn invalid_boot_record_names_slot_a() {
+        let mut record = [0; BOOT_RECORD_LEN];
+        encode_boot_record(Slot::B, &mut record);
+
+        // Erased storage.
+        assert_eq!(
+            decode_boot_record(&[0xFF; BOOT_RECORD_LEN], BOOT_RECORD_LEN),
+            Some(Slot::A)
+        );
+        // Wrong magic.
+        let mut bad_magic = record;
+        bad_magic[0] = b'X';
+        assert_eq!(
+            decode_boot_record(&bad_magic, BOOT_RECORD_LEN),
+            Some(Slot::A)
+        );
+        // Inverted copy does not match.
+        let mut bad_copy = record;
+        bad_copy[5] = 0;
+        assert_eq!(
+            decode_boot_record(&bad_copy, BOOT_RECORD_LEN),
+            Some(Slot::A)
+        );
+    }
+
+    #[test]
+    fn failed_record_read_leaves_slot_unknown() {
+        let mut record = [0; BOOT_RECORD_LEN];
+        encode_boot_record(Slot::B, &mut record);
+
+        assert_eq!(decode_boot_record(&record, 0), None);
+        assert_eq!(decode_boot_record(&record, BOOT_RECORD_LEN - 1), None);
+        assert_eq!(decode_boot_record(&record[..4], BOOT_RECORD_LEN), None);
+    }
+
+    #[test]
+    fn status_codes() {
+        assert_eq!(State::Idle.status_code(), 0);
+        assert_eq!(State::Receiving.status_code(), 1);
+        assert_eq!(State::Staged.status_code(), 3);
+        assert_eq!(State::Unloaded.status_code(), 4);
+        for busy in [
+            State::Loading,
+            State::HashingChunk(1),
+            State::WritingChunk(1),
+            State::Hashing,
+            State::Verifying,
+            State::WritingRecord,
+        ] {
+            assert_eq!(busy.status_code(), 2);
+        }
+    }
+}
@@ -0,0 +1,427 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2024.
+
+//! Entropy source that keeps a seed in nonvolatile storage across reboots.
+//!
+//! On some chips the TRNG needs a long time after reset before it produces
+//! its first words, so anything that wants randomness early in boot has to
+//! wait for it. `PersistentSeedEntropy` sits between the TRNG and its client
+//! and serves entropy from a seed saved in the kernel region of nonvolatile
+//! storage by the previous boot, while it gathers fresh words from the TRNG in
+//! the background.
+//!
+//! The saved seed is never used directly. At boot the capsule derives two
+//! independent keys from it with SipHash-2-4 and the boot counter stored next
+//! to it: one to generate this boot's output, and one that replaces the saved
+//! seed. Output from the saved seed is only served after the replacement is
+//! written, so a reset at any point can never make two boots produce the same
+//! output. As soon as the TRNG delivers `HW_WORDS` words they are mixed into
+//! the seed, both keys are derived again, and the new seed is saved, so every
+//! boot adds fresh hardware entropy to the seed the next boot starts from.
+//!
+//! Without a valid saved seed, for example on the first boot, `get()` waits
+//! for the TRNG like it would without this capsule.
+//!
+//! Seed Format
+//! -----------
+//!
+//! The seed is `SEED_LEN` bytes with little-endian fields:
+//!
+//! ```text
+//! 0       4       8                      24
+//! +-------+-------+----------------------+
+//! | magic | boots | key                  |
+//! +-------+-------+----------------------+
+//! ```
+//!
+//! `magic` is `SEED_MAGIC` and `boots` counts the boots the seed was derived
+//! on.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust,ignore
+//! let seeded_entropy = components::persistent_seed::PersistentSeedComponent::new(
+//!     &peripherals.trng,
+//!     nonvolatile_storage,
+//!     0x7fd0,
+//! )
+//! .finalize(components::persistent_seed_component_static!(nrf52840::trng::Trng));
+//!
+//! // `seeded_entropy` then replaces the TRNG as the source of the RNG driver.
+//! ```
+
+use core::cell::Cell;
+
+use kernel::deferred_call::{DeferredCall, DeferredCallClient};
+use kernel::hil::entropy::{Client32, Continue, Entropy32};
+use kernel::hil::nonvolatile_storage::{NonvolatileStorage, NonvolatileStorageClient};
+use kernel::utilities::cells::{OptionalCell, TakeCell};
+use kernel::ErrorCode;
+
+/// Length of the seed in storage.
+pub const SEED_LEN: usize = 24;
+
+/// First word of a valid seed, "SEED" in ASCII.
+pub const SEED_MAGIC: u32 = 0x4445_4553;
+
+/// Number of TRNG words mixed into the seed each boot.
+pub const HW_WORDS: usize = 4;
+
+/// Most words offered to the client in one `entropy_available` callback.
+const WORDS_PER_CALLBACK: usize = 16;
+
+// Domains that keep the keys derived from one seed independent.
+const DOMAIN_OUTPUT: u64 = 0;
+const DOMAIN_PERSIST: u64 = 1;
+const DOMAIN_MIX: u64 = 2;
+const DOMAIN_STREAM: u64 = 3;
+
+type Key = [u64; 2];
+
+/// SipHash-2-4 of whole 64-bit words.
+fn siphash24(key: Key, message: &[u64]) -> u64 {
+    let mut v = [
+        key[0] ^ 0x736f_6d65_7073_6575,
+        key[1] ^ 0x646f_7261_6e64_6f6d,
+        key[0] ^ 0x6c79_6765_6e65_7261,
+        key[1] ^ 0x7465_6462_7974_6573,
+    ];
+
+    fn round(v: &mut [u64; 4]) {
+        v[0] = v[0].wrapping_add(v[1]);
+        v[1] = v[1].rotate_left(13) ^ v[0];
+        v[0] = v[0].rotate_left(32);
+        v[2] = v[2].wrapping_add(v[3]);
+        v[3] = v[3].rotate_left(16) ^ v[2];
+        v[0] = v[0].wrapping_add(v[3]);
+        v[3] = v[3].rotate_left(21) ^ v[0];
+        v[2] = v[2].wrapping_add(v[1]);
+        v[1] = v[1].rotate_left(17) ^ v[2];
+        v[2] = v[2].rotate_left(32);
+    }
+
+    let last = ((message.len() as u64 * 8) & 0xff) << 56;
+    for &m in message.iter().chain(core::iter::once(&last)) {
+        v[3] ^= m;
+        round(&mut v);
+        round(&mut v);
+        v[0] ^= m;
+    }
+    v[2] ^= 0xff;
+    for _ in 0..4 {
+        round(&mut v);
+    }
+    v[0] ^ v[1] ^ v[2] ^ v[3]
+}
+
+fn derive(root: Key, domain: u64, boots: u32) -> Key {
+    [
+        siphash24(root, &[domain, boots as u64, 0]),
+        siphash24(root, &[domain, boots as u64, 1]),
+    ]
+}
+
+pub struct PersistentSeedEntropy<'a, E: Entropy32<'a>> {
+    trng: &'a E,
+    storage: &'a dyn NonvolatileStorage<'a>,
+    address: usize,
+    client: OptionalCell<&'a dyn Client32>,
+    buffer: TakeCell<'static, [u8]>,
+
+    // Key the current seed is derived from, and the boot counter saved with
+    // it.
+    root: OptionalCell<Key>,
+    boots: Cell<u32>,
+    // Key the output is generated with, and the next block to generate.
+    output_key: OptionalCell<Key>,
+    block: Cell<u64>,
+    // Output key from the saved seed, served once the replacement is written.
+    boot_key: OptionalCell<Key>,
+    // Seed waiting to be written, and whether a write is in progress.
+    unsaved: OptionalCell<(Key, u32)>,
+    writing: Cell<bool>,
+
+    // TRNG words gathered so far.
+    hw_words: Cell<[u32; HW_WORDS]>,
+    hw_count: Cell<usize>,
+    hw_failed: Cell<bool>,
+
+    // The client called `get()` and wants `entropy_available`.
+    requested: Cell<bool>,
+    deferred_call: DeferredCall,
+}
+
+impl<'a, E: Entropy32<'a>> PersistentSeedEntropy<'a, E> {
+    /// Keep the seed in the `SEED_LEN` bytes of `storage` at `address`.
+    /// `buffer` must hold at least `SEED_LEN` bytes.
+    pub fn new(
+        trng: &'a E,
+        storage: &'a dyn NonvolatileStorage<'a>,
+        address: usize,
+        buffer: &'static mut [u8],
+    ) -> Self {
+        Self {
+            trng,
+            storage,
+            address,
+            client: OptionalCell::empty(),
+            buffer: TakeCell::new(buffer),
+            root: OptionalCell::empty(),
+            boots: Cell::new(0),
+            output_key: OptionalCell::empty(),
+            block: Cell::new(0),
+            boot_key: OptionalCell::empty(),
+            unsaved: OptionalCell::empty(),
+            writing: Cell::new(false),
+            hw_words: Cell::new([0; HW_WORDS]),
+            hw_count: Cell::new(0),
+            hw_failed: Cell::new(false),
+            requested: Cell::new(false),
+            deferred_call: DeferredCall::new(),
+        }
+    }
+
+    /// Read the saved seed. Must be called once, after the capsule is the
+    /// client of the storage and of the TRNG.
+    pub fn start(&self) -> Result<(), ErrorCode> {
+        let buffer = self.buffer.take().ok_or(ErrorCode::BUSY)?;
+        if let Err(e) = self.storage.read(buffer, self.address, SEED_LEN) {
+            // Without a saved seed the TRNG is the only source.
+            self.start_trng();
+            return Err(e);
+        }
+        Ok(())
+    }
+
+    /// Whether output is currently generated from a seed, and whether
+    /// hardware entropy has been mixed into it this boot.
+    pub fn seeded(&self) -> (bool, bool) {
+        (self.output_key.is_some(), self.hw_count.get() == HW_WORDS)
+    }
+
+    fn start_trng(&self) {
+        if let Err(e) = self.trng.get() {
+            self.trng_failed(Err(e));
+        }
+    }
+
+    fn trng_failed(&self, error: Result<(), ErrorCode>) {
+        self.hw_failed.set(true);
+        // Clients waiting for the first seed would otherwise wait forever.
+        if self.output_key.is_none() && self.boot_key.is_none() && self.requested.take() {
+            self.client
+                .map(|client| client.entropy_available(&mut core::iter::empty(), error));
+        }
+    }
+
+    fn set_output_key(&self, key: Key) {
+        self.output_key.set(key);
+        self.block.set(0);
+        if self.requested.get() {
+            self.deferred_call.set();
+        }
+    }
+
+    // Derive the output key and the next seed from `root`, and save the next
+    // seed.
+    fn reseed(&self, root: Key, boots: u32) -> Key {
+        self.root.set(root);
+        self.boots.set(boots);
+        self.unsaved
+            .set((derive(root, DOMAIN_PERSIST, boots), boots.wrapping_add(1)));
+        self.save();
+        derive(root, DOMAIN_OUTPUT, boots)
+    }
+
+    fn save(&self) {
+        if self.writing.get() {
+            // `write_done` saves the newest seed.
+            return;
+        }
+        let (key, boots) = match self.unsaved.take() {
+            Some(seed) => seed,
+            None => return,
+        };
+        self.buffer.take().map(|buffer| {
+            buffer[0..4].copy_from_slice(&SEED_MAGIC.to_le_bytes());
+            buffer[4..8].copy_from_slice(&boots.to_le_bytes());
+            buffer[8..16].copy_from_slice(&key[0].to_le_bytes());
+            buffer[16..24].copy_from_slice(&key[1].to_le_bytes());
+            if self.storage.write(buffer, self.address, SEED_LEN).is_ok() {
+                self.writing.set(true);
+            }
+        });
+    }
+
+    fn parse(buffer: &[u8]) -> Option<(Key, u32)> {
+        let word =
+            |i: usize| u32::from_le_bytes([buffer[i], buffer[i + 1], buffer[i + 2], buffer[i + 3]]);
+        let dword = |i: usize| word(i) as u64 | (word(i + 4) as u64) << 32;
+        if buffer.len() < SEED_LEN || word(0) != SEED_MAGIC {
+            return None;
+        }
+        Some(([dword(8), dword(16)], word(4)))
+    }
+
+    fn next_block(&self) -> u64 {
+        let key = self.output_key.get().unwrap_or_default();
+        let block = self.block.get();
+        self.block.set(block.wrapping_add(1));
+        siphash24(key, &[DOMAIN_STREAM, block])
+    }
+}
+
+struct SeedIter<'b, 'a, E: Entropy32<'a>> {
+    seed: &'b PersistentSeedEntropy<'a, E>,
+    remaining: usize,
+    upper: Option<u32>,
+}
+
+impl<'a, E: Entropy32<'a>> Iterator for SeedIter<'_, 'a, E> {
+    type Item = u32;
+
+    fn next(&mut self) -> Option<u32> {
+        if self.remaining == 0 {
+            return None;
+        }
+        self.remaining -= 1;
+        match self.upper.take() {
+            Some(word) => Some(word),
+            None => {
+                let block = self.seed.next_block();
+                self.upper = Some((block >> 32) as u32);
+                Some(block as u32)
+            }
+        }
+    }
+}
+
+impl<'a, E: Entropy32<'a>> Entropy32<'a> for PersistentSeedEntropy<'a, E> {
+    fn get(&self) -> Result<(), ErrorCode> {
+        if self.output_key.is_none() && self.boot_key.is_none() && self.hw_failed.get() {
+            return Err(ErrorCode::FAIL);
+        }
+        self.requested.set(true);
+        if self.output_key.is_some() {
+            self.deferred_call.set();
+        }
+        Ok(())
+    }
+
+    fn cancel(&self) -> Result<(), ErrorCode> {
+        self.requested.set(false);
+        Ok(())
+    }
+
+    fn set_client(&'a self, client: &'a dyn Client32) {
+        self.client.set(client);
+    }
+}
+
+impl<'a, E: Entropy32<'a>> Client32 for PersistentSeedEntropy<'a, E> {
+    fn entropy_available(
+        &self,
+        entropy: &mut dyn Iterator<Item = u32>,
+        error: Result<(), ErrorCode>,
+    ) -> Continue {
+        if error.is_err() {
+            self.trng_failed(error);
+            return Continue::Done;
+        }
+
+        let mut words = self.hw_words.get();
+        let mut count = self.hw_count.get();
+        while count < HW_WORDS {
+            match entropy.next() {
+                Some(word) => {
+                    words[count] = word;
+                    count += 1;
+                }
+                None => break,
+            }
+        }
+        self.hw_words.set(words);
+        self.hw_count.set(count);
+        if count < HW_WORDS {
+            return Continue::More;
+        }
+
+        // Mix the TRNG words into the seed, or start a new one from them if
+        // there was no saved seed.
+        let root = self.root.get().unwrap_or_default();
+        let low = words[0] as u64 | (words[1] as u64) << 32;
+        let high = words[2] as u64 | (words[3] as u64) << 32;
+        let mixed = [
+            siphash24(root, &[DOMAIN_MIX, 0, low, high]),
+            siphash24(root, &[DOMAIN_MIX, 1, low, high]),
+        ];
+        self.hw_words.set([0; HW_WORDS]);
+
+        // Fresh hardware entropy, so the new output key is safe to serve
+        // before the new seed is saved.
+        self.boot_key.clear();
+        let key = self.reseed(mixed, self.boots.get());
+        self.set_output_key(key);
+        Continue::Done
+    }
+}
+
+impl<'a, E: Entropy32<'a>> NonvolatileStorageClient for PersistentSeedEntropy<'a, E> {
+    fn read_done(&self, buffer: &'static mut [u8], length: usize) {
+        let saved = if length == SEED_LEN {
+            Self::parse(buffer)
+        } else {
+            None
+        };
+        self.buffer.replace(buffer);
+
+        if let Some((root, boots)) = saved {
+            let key = self.reseed(root, boots);
+            if self.writing.get() {
+                self.boot_key.set(key);
+            }
+        }
+        self.start_trng();
+    }
+
+    fn write_done(&self, buffer: &'static mut [u8], _length: usize) {
+        self.buffer.replace(buffer);
+        self.writing.set(false);
+        // The saved seed has been replaced, so its output key is never
+        // derived again.
+        if let Some(key) = self.boot_key.take() {
+            if self.output_key.is_none() {
+                self.set_output_key(key);
+            }
+        }
+        self.save();
+    }
+
+    fn sync_done(&self, _result: Result<(), ErrorCode>) {}
+}
+
+impl<E: Entropy32<'static>> DeferredCallClient for PersistentSeedEntropy<'static, E> {
+    fn handle_deferred_call(&self) {
+        if self.output_key.is_none() || !self.requested.get() {
+            return;
+        }
+        let mut words = SeedIter {
+            seed: self,
+            remaining: WORDS_PER_CALLBACK,
+            upper: None,
+        };
+        let more = self.client.map_or(Continue::Done, |client| {
+            client.entropy_available(&mut words, Ok(()))
+        });
+        match more {
+            Continue::More if self.requested.get() => self.deferred_call.set(),
+            _ => self.requested.set(false),
+        }
+    }
+
+    fn register(&'static self) {
+        self.deferred_call.register(self);
+    }
+}
@@ -0,0 +1,136 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2024.
+
+//! Save a record of a power failure to nonvolatile storage before power is
+//! lost.
+//!
+//! `PowerFailRecorder` listens for the power-fail warning of the chip. When
+//! the supply voltage drops, it writes one small record, saying that power was
+//! failing, when, and which app was running, with the storage's `write_now`
+//! fast path. The write completes before `power_failing` returns, so the
+//! record is saved as long as the storage can write it before power is gone.
+//! In practice this needs storage that writes in place within microseconds,
+//! like FRAM in the kernel region of the nonvolatile storage driver. On other
+//! storage `write_now` fails and the failure is only counted.
+//!
+//! Every warning overwrites the same record, so after the next boot the
+//! kernel or a diagnostics app can read the last power failure back from the
+//! storage.
+//!
+//! Record Format
+//! -------------
+//!
+//! The record is `RECORD_LEN` bytes with little-endian fields:
+//!
+//! ```text
+//! 0       4              8       12
+//! +-------+--------------+-------+
+//! | magic | timestamp_ms | app   |
+//! +-------+--------------+-------+
+//! ```
+//!
+//! `magic` is `RECORD_MAGIC`. `timestamp_ms` is the time since boot, and
+//! wraps with the underlying timer. `app` is the fixed `ShortId` of the app
+//! the kernel last switched to, or 0 if there is none or it has no fixed
+//! `ShortId`.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust,ignore
+//! let power_fail_recorder = components::power_fail_record::PowerFailRecorderComponent::new(
+//!     board_kernel,
+//!     &peripherals.power,
+//!     nonvolatile_storage,
+//!     0x7ff0,
+//!     &base_peripherals.rtc,
+//! )
+//! .finalize(components::power_fail_recorder_component_static!(nrf52::rtc::Rtc));
+//! ```
+
+use core::cell::Cell;
+
+use kernel::capabilities::ProcessManagementCapability;
+use kernel::hil::nonvolatile_storage::NonvolatileStorage;
+use kernel::hil::power_fail::PowerFailClient;
+use kernel::hil::time::{ConvertTicks, Time};
+use kernel::introspection::KernelInfo;
+use kernel::process::ShortId;
+use kernel::ErrorCode;
+use kernel::Kernel;
+
+/// Length of the record in storage.
+pub const RECORD_LEN: usize = 12;
+
+/// First word of a valid record, "PWRF" in ASCII.
+pub const RECORD_MAGIC: u32 = 0x4652_5750;
+
+pub struct PowerFailRecorder<'a, T: Time, C: ProcessManagementCapability> {
+    kernel: &'static Kernel,
+    capability: C,
+    storage: &'a dyn NonvolatileStorage<'a>,
+    address: usize,
+    time: &'a T,
+    // Warnings whose record could not be written.
+    failed: Cell<u32>,
+    last_error: Cell<Option<ErrorCode>>,
+}
+
+impl<'a, T: Time, C: ProcessManagementCapability> PowerFailRecorder<'a, T, C> {
+    /// Write the record to the `RECORD_LEN` bytes of `storage` at `address`.
+    pub fn new(
+        kernel: &'static Kernel,
+        capability: C,
+        storage: &'a dyn NonvolatileStorage<'a>,
+        address: usize,
+        time: &'a T,
+    ) -> Self {
+        Self {
+            kernel,
+            capability,
+            storage,
+            address,
+            time,
+            failed: Cell::new(0),
+            last_error: Cell::new(None),
+        }
+    }
+
+    /// Number of power-fail warnings whose record could not be written, and
+    /// the error of the last one. Only useful if power came back.
+    pub fn failures(&self) -> (u32, Option<ErrorCode>) {
+        (self.failed.get(), self.last_error.get())
+    }
+
+    fn running_app(&self) -> u32 {
+        KernelInfo::new(self.kernel)
+            .last_scheduled_process(&self.capability)
+            .map_or(0, |processid| {
+                self.kernel.process_map_or_external(
+                    0,
+                    processid,
+                    |process| match process.short_app_id() {
+                        ShortId::Fixed(id) => id.get(),
+                        ShortId::LocallyUnique => 0,
+                    },
+                    &self.capability,
+                )
+            })
+    }
+}
+
+impl<T: Time, C: ProcessManagementCapability> PowerFailClient for PowerFailRecorder<'_, T, C> {
+    fn power_failing(&self) {
+        let timestamp_ms = self.time.ticks_to_ms(self.time.now());
+        let mut record = [0; RECORD_LEN];
+        record[0..4].copy_from_slice(&RECORD_MAGIC.to_le_bytes());
+        record[4..8].copy_from_slice(&timestamp_ms.to_le_bytes());
+        record[8..12].copy_from_slice(&self.running_app().to_le_bytes());
+
+        if let Err(e) = self.storage.write_now(self.address, &record) {
+            self.failed.set(self.failed.get().saturating_add(1));
+            self.last_error.set(Some(e));
+        }
+    }
+}
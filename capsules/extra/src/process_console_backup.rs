@@ -0,0 +1,162 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2024.
+
+//! The `backup` command of the process console, which copies storage regions
+//! to their backup area.
+//!
+//! `backup <region>` copies the region with that name, and `backup all`
+//! copies every region. The console that started the backup reports each
+//! quarter of it, and when it is done.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust,ignore
+//! let backup_cap = create_capability!(capabilities::StorageBackupCapability);
+//! components::process_console_backup::BackupCommandComponent::new(
+//!     pconsole.engine(),
+//!     storage_backup,
+//!     &backup_cap,
+//! )
+//! .finalize(components::backup_command_component_static!());
+//! ```
+
+use core::cell::Cell;
+
+use capsules_core::process_console::{CommandStatus, ConsoleCommands, ConsoleSession};
+use kernel::capabilities::StorageBackupCapability;
+use kernel::collections::list::ListLink;
+use kernel::hil::nonvolatile_storage::{StorageBackup, StorageBackupClient};
+use kernel::utilities::cells::OptionalCell;
+use kernel::ErrorCode;
+
+/// The quarter of a backup to report once `copied` of `total` bytes are
+/// copied, if it is past the `reported` quarter and not the end.
+fn quarter_to_report(copied: usize, total: usize, reported: usize) -> Option<usize> {
+    let quarters = copied * 4 / total.max(1);
+    if quarters <= reported || quarters >= 4 {
+        None
+    } else {
+        Some(quarters)
+    }
+}
+
+pub struct BackupCommand<'a> {
+    backup: &'a dyn StorageBackup<'a>,
+    /// Console the running backup reports to.
+    session: OptionalCell<&'a dyn ConsoleSession>,
+    /// Quarters of the running backup that have been reported.
+    reported: Cell<usize>,
+    next: ListLink<'a, dyn ConsoleCommands<'a>>,
+}
+
+impl<'a> BackupCommand<'a> {
+    /// Copy the regions of `backup`. The command must be the client of
+    /// `backup`.
+    pub fn new(
+        backup: &'a dyn StorageBackup<'a>,
+        _capability: &dyn StorageBackupCapability,
+    ) -> Self {
+        Self {
+            backup,
+            session: OptionalCell::empty(),
+            reported: Cell::new(0),
+            next: ListLink::empty(),
+        }
+    }
+
+    /// Start the `backup` command for the region named `region`, or for every
+    /// region if it is `all`.
+    fn start(&self, session: &'a dyn ConsoleSession, region: Option<&str>) {
+        let index = match region {
+            Some("all") => Ok(None),
+            Some(name) => (0..)
+                .map_while(|index| self.backup.region_name(index).map(|n| (index, n)))
+                .find(|(_, n)| *n == name)
+                .map(|(index, _)| Some(index))
+                .ok_or(ErrorCode::INVAL),
+            None => Err(ErrorCode::INVAL),
+        };
+
+        match index.and_then(|index| self.backup.backup(index)) {
+            Ok(()) => {
+                self.reported.set(0);
+                self.session.set(session);
+                session.write(b"Backup started.\r\n");
+            }
+            Err(ErrorCode::INVAL) => {
+                session.write(b"Usage: backup [all");
+                let mut index = 0;
+                while let Some(name) = self.backup.region_name(index) {
+                    session.print(format_args!("|{}", name));
+                    index += 1;
+                }
+                session.write(b"]\r\n");
+            }
+            Err(e) => session.print(format_args!("Backup failed to start: {:?}\r\n", e)),
+        }
+    }
+}
+
+impl<'a> ConsoleCommands<'a> for BackupCommand<'a> {
+    fn names(&self) -> &'static str {
+        "backup"
+    }
+
+    fn run(&self, session: &'a dyn ConsoleSession, command: &str) -> CommandStatus {
+        let mut words = command.split_whitespace();
+        if words.next() != Some("backup") {
+            return CommandStatus::Unknown;
+        }
+        self.start(session, words.next());
+        CommandStatus::Done
+    }
+
+    fn next_commands(&'a self) -> &'a ListLink<'a, dyn ConsoleCommands<'a>> {
+        &self.next
+    }
+}
+
+impl StorageBackupClient for BackupCommand<'_> {
+    fn backup_progress(&self, copied: usize, total: usize) {
+        if let Some(quarter) = quarter_to_report(copied, total, self.reported.get()) {
+            self.reported.set(quarter);
+            self.session.map(|session| {
+                session.print(format_args!(
+                    "Backup: {} of {} bytes copied\r\n",
+                    copied, total
+                ))
+            });
+        }
+    }
+
+    fn backup_done(&self, result: Result<(), ErrorCode>, copied: usize) {
+        self.session.take().map(|session| match result {
+            Ok(()) => session.print(format_args!("Backup finished: {} bytes copied\r\n", copied)),
+            Err(e) => session.print(format_args!(
+                "Backup failed after {} bytes: {:?}\r\n",
+                copied, e
+            )),
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::quarter_to_report;
+
+    #[test]
+    fn each_quarter_is_reported_once() {
+        assert_eq!(quarter_to_report(10, 100, 0), None);
+        assert_eq!(quarter_to_report(25, 100, 0), Some(1));
+        assert_eq!(quarter_to_report(40, 100, 1), None);
+        assert_eq!(quarter_to_report(80, 100, 1), Some(3));
+    }
+
+    #[test]
+    fn the_end_is_not_reported_as_progress() {
+        assert_eq!(quarter_to_report(100, 100, 3), None);
+        assert_eq!(quarter_to_report(0, 0, 0), None);
+    }
+}
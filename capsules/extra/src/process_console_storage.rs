@@ -0,0 +1,785 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2024.
+
+//! Process console commands that use nonvolatile storage.
+//!
+//! - `storage selftest` writes, reads back, and compares a pattern in a
+//!   scratch area the board gives up, and reports the time each access took.
+//! - `storage hexdump <address> <length>` prints a range of the storage as
+//!   hex bytes.
+//! - `storage dump <address> <length>` prints a range of the storage as Intel
+//!   HEX records, so a host script can save the contents of the device's
+//!   storage over the console without a debugger. Each record carries its
+//!   own checksum, and the dump ends with an end of file record once every
+//!   byte was read.
+//! - `source` runs the commands in a script region of the storage, one per
+//!   line. Empty lines and lines starting with `#` are skipped, and the
+//!   script ends at the end of the region or at the first `0x00` or `0xFF`
+//!   byte.
+//!
+//! The next chunk of a dump is only read once the previous one has been
+//! transmitted, so a long dump does not overflow the output queue.
+//!
+//! The commands share one storage buffer, so only one `storage` command and
+//! one script run at a time, across all sessions of the console. Each prints
+//! in the session that started it.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust,ignore
+//! components::process_console_storage::StorageCommandsComponent::new(
+//!     pconsole.engine(),
+//!     mux_alarm,
+//!     nonvolatile_storage,
+//!     scratch_start,
+//!     scratch_length,
+//! )
+//! .with_script_region(script_start, script_length)
+//! .finalize(components::storage_commands_component_static!(nrf52840::rtc::Rtc<'static>));
+//! ```
+
+use core::cell::Cell;
+use core::cmp;
+use core::fmt::{self, write};
+use core::ptr;
+
+use capsules_core::process_console::{
+    parse_number, CommandStatus, ConsoleCommands, ConsoleSession, ConsoleWriter, COMMAND_BUF_LEN,
+};
+use kernel::collections::list::ListLink;
+use kernel::hil::nonvolatile_storage::{NonvolatileStorage, NonvolatileStorageClient};
+use kernel::hil::time::{ConvertTicks, Ticks, Time};
+use kernel::utilities::cells::{OptionalCell, TakeCell};
+use kernel::ErrorCode;
+
+/// Size of the buffer used to access the storage.
+pub const BUF_LEN: usize = 64;
+/// Number of write/read/verify cycles performed by `storage selftest`.
+const SELFTEST_ROUNDS: usize = 8;
+/// Number of bytes printed on each line by `storage hexdump`, and in each
+/// record by `storage dump`.
+const HEXDUMP_LINE_LEN: usize = 16;
+
+/// Operation the commands have outstanding on the storage.
+#[derive(PartialEq, Eq, Copy, Clone)]
+enum State {
+    Idle,
+    SelfTestWrite {
+        round: usize,
+    },
+    SelfTestRead {
+        round: usize,
+    },
+    /// Reading the script line starting at `offset` in the script region.
+    ScriptRead {
+        offset: usize,
+    },
+    /// Reading the `storage hexdump` or `storage dump` chunk at `address`,
+    /// `remaining` bytes are left to dump including this chunk.
+    HexDumpRead {
+        address: usize,
+        remaining: usize,
+        format: DumpFormat,
+    },
+    /// Waiting for the previous chunk to be printed before reading the next
+    /// one at `address`.
+    HexDumpPending {
+        address: usize,
+        remaining: usize,
+        format: DumpFormat,
+    },
+}
+
+/// How storage contents are printed.
+#[derive(PartialEq, Eq, Copy, Clone)]
+enum DumpFormat {
+    /// Lines of hex bytes for people to read, from `storage hexdump`.
+    Hex,
+    /// Intel HEX records for host tools, from `storage dump`.
+    IntelHex,
+}
+
+/// Statistics accumulated while running `storage selftest`.
+#[derive(Copy, Clone, Default)]
+struct SelfTestStats {
+    /// Number of bytes written (and read back) so far.
+    bytes: usize,
+    write_us_total: u32,
+    write_us_max: u32,
+    read_us_total: u32,
+    read_us_max: u32,
+    /// Operations that completed with a different length than requested.
+    errors: usize,
+    /// Bytes that did not read back as they were written.
+    mismatches: usize,
+}
+
+/// A script `source` is running.
+#[derive(Copy, Clone)]
+struct Script<'a> {
+    /// Console the script runs in.
+    session: &'a dyn ConsoleSession,
+    /// Where the next line starts in the script region.
+    offset: usize,
+}
+
+/// Byte written at `index` in round `round` of `storage selftest`.
+fn selftest_pattern(round: usize, index: usize) -> u8 {
+    (index as u8) ^ (round as u8).wrapping_mul(0x5B) ^ 0xA5
+}
+
+/// Write one Intel HEX record. The checksum is the two's complement of the
+/// sum of the other bytes of the record.
+fn intel_hex_record(console_writer: &mut ConsoleWriter, kind: u8, address: u16, data: &[u8]) {
+    let [address_high, address_low] = address.to_be_bytes();
+    let mut sum = (data.len() as u8)
+        .wrapping_add(address_high)
+        .wrapping_add(address_low)
+        .wrapping_add(kind);
+    let _ = write(
+        console_writer,
+        format_args!(":{:02X}{:04X}{:02X}", data.len(), address, kind),
+    );
+    for b in data {
+        sum = sum.wrapping_add(*b);
+        let _ = write(console_writer, format_args!("{:02X}", b));
+    }
+    let _ = write(
+        console_writer,
+        format_args!("{:02X}\r\n", sum.wrapping_neg()),
+    );
+}
+
+/// Write `data`, read from absolute storage address `address`, as Intel HEX
+/// data records. Each chunk starts with an extended linear address record
+/// for the upper 16 bits of the address, and records are split where the
+/// upper bits change, so every chunk can be parsed on its own.
+fn intel_hex_chunk(console_writer: &mut ConsoleWriter, address: usize, data: &[u8]) {
+    let mut offset = 0;
+    while offset < data.len() {
+        let record_address = address + offset;
+        let low = record_address & 0xFFFF;
+        if offset == 0 || low == 0 {
+            let upper = (record_address >> 16) as u16;
+            intel_hex_record(console_writer, 0x04, 0, &upper.to_be_bytes());
+        }
+        let len = cmp::min(
+            cmp::min(HEXDUMP_LINE_LEN, data.len() - offset),
+            0x10000 - low,
+        );
+        intel_hex_record(
+            console_writer,
+            0x00,
+            low as u16,
+            &data[offset..offset + len],
+        );
+        offset += len;
+    }
+}
+
+/// The first line of `data`, read from `offset` in a script region of
+/// `length` bytes, and where the next line starts if the script goes on.
+/// Returns `None` if the line does not fit in a command.
+fn script_line(data: &[u8], offset: usize, length: usize) -> Option<(&[u8], Option<usize>)> {
+    let end = data
+        .iter()
+        .position(|&b| b == b'\n' || b == b'\r' || b == 0x00 || b == 0xFF);
+    let (line, next) = match end {
+        Some(pos) if data[pos] == b'\n' || data[pos] == b'\r' => {
+            (&data[..pos], Some(offset + pos + 1))
+        }
+        Some(pos) => (&data[..pos], None),
+        None if offset + data.len() >= length => (data, None),
+        None => return None,
+    };
+    if line.len() >= COMMAND_BUF_LEN {
+        return None;
+    }
+    Some((line, next))
+}
+
+/// Whether a script line holds a command, rather than being empty or a
+/// comment.
+fn is_command(line: &[u8]) -> bool {
+    line.iter()
+        .position(|b| !b.is_ascii_whitespace())
+        .map_or(false, |first| line[first] != b'#')
+}
+
+pub struct StorageCommands<'a, T: Time> {
+    time: &'a T,
+    storage: &'a dyn NonvolatileStorage<'a>,
+    buffer: TakeCell<'static, [u8]>,
+    /// Absolute address of the area `storage selftest` may overwrite.
+    scratch_address: usize,
+    /// Length of the area `storage selftest` may overwrite.
+    scratch_length: usize,
+    /// Absolute address of the storage area `source` reads commands from.
+    script_address: Cell<usize>,
+    /// Length of the storage area `source` reads commands from.
+    script_length: Cell<usize>,
+    /// Current storage operation.
+    state: Cell<State>,
+    /// Console of the running `storage` command.
+    session: OptionalCell<&'a dyn ConsoleSession>,
+    /// Script currently being run.
+    script: OptionalCell<Script<'a>>,
+    /// When the current storage operation was started.
+    op_start: Cell<T::Ticks>,
+    /// Results of the running `storage selftest`.
+    stats: Cell<SelfTestStats>,
+    next: ListLink<'a, dyn ConsoleCommands<'a>>,
+}
+
+impl<'a, T: Time> StorageCommands<'a, T> {
+    /// `scratch_address` and `scratch_length` describe an area of `storage`
+    /// that `storage selftest` is allowed to overwrite. The commands must be
+    /// the client of `storage`.
+    pub fn new(
+        time: &'a T,
+        storage: &'a dyn NonvolatileStorage<'a>,
+        scratch_address: usize,
+        scratch_length: usize,
+        buffer: &'static mut [u8],
+    ) -> Self {
+        Self {
+            time,
+            storage,
+            buffer: TakeCell::new(buffer),
+            scratch_address,
+            scratch_length,
+            script_address: Cell::new(0),
+            script_length: Cell::new(0),
+            state: Cell::new(State::Idle),
+            session: OptionalCell::empty(),
+            script: OptionalCell::empty(),
+            op_start: Cell::new(T::Ticks::from(0)),
+            stats: Cell::new(SelfTestStats::default()),
+            next: ListLink::empty(),
+        }
+    }
+
+    /// Set the area of the storage that the `source` command reads commands
+    /// from.
+    pub fn set_script_region(&self, address: usize, length: usize) {
+        self.script_address.set(address);
+        self.script_length.set(length);
+    }
+
+    /// Run a `storage` command with the words after `storage`.
+    fn storage_command<'b>(
+        &self,
+        session: &'a dyn ConsoleSession,
+        mut args: impl Iterator<Item = &'b str>,
+    ) -> CommandStatus {
+        match args.next() {
+            Some("selftest") => self.selftest(session),
+            Some(command @ ("hexdump" | "dump")) => {
+                let format = if command == "dump" {
+                    DumpFormat::IntelHex
+                } else {
+                    DumpFormat::Hex
+                };
+                let address = args.next().and_then(parse_number);
+                let length = args.next().and_then(parse_number);
+                match (address, length) {
+                    (Some(address), Some(length)) => self.hexdump(session, address, length, format),
+                    _ => {
+                        session.write(b"Usage: storage [hexdump|dump] <address> <length>\r\n");
+                        CommandStatus::Done
+                    }
+                }
+            }
+            _ => {
+                session.write(b"Usage: storage [selftest|hexdump|dump <address> <length>]\r\n");
+                CommandStatus::Done
+            }
+        }
+    }
+
+    /// Length of each access made by `storage selftest`.
+    fn selftest_access_len(&self, buffer: &[u8]) -> usize {
+        cmp::min(buffer.len(), self.scratch_length)
+    }
+
+    /// Absolute storage address used by round `round` of `storage selftest`.
+    ///
+    /// Rounds walk through the scratch area so that more than one location is
+    /// exercised when the scratch area is larger than the buffer.
+    fn selftest_address(&self, round: usize, len: usize) -> usize {
+        let slots = self.scratch_length / len;
+        self.scratch_address + (round % slots) * len
+    }
+
+    /// Start the `storage selftest` command.
+    fn selftest(&self, session: &'a dyn ConsoleSession) -> CommandStatus {
+        if self.state.get() != State::Idle {
+            session.write(b"Storage operation already in progress.\r\n");
+            return CommandStatus::Done;
+        }
+        let usable = self
+            .buffer
+            .map_or(false, |buffer| self.selftest_access_len(buffer) > 0);
+        if !usable {
+            session.write(b"No storage scratch area or buffer available.\r\n");
+            return CommandStatus::Done;
+        }
+
+        self.stats.set(SelfTestStats::default());
+        self.session.set(session);
+        match self.selftest_write(0) {
+            Ok(()) => CommandStatus::Running,
+            Err(e) => {
+                self.selftest_abort(e);
+                CommandStatus::Done
+            }
+        }
+    }
+
+    /// Fill the buffer with the pattern for `round` and write it to the scratch
+    /// area.
+    fn selftest_write(&self, round: usize) -> Result<(), ErrorCode> {
+        let buffer = self.buffer.take().ok_or(ErrorCode::NOMEM)?;
+        let len = self.selftest_access_len(buffer);
+        for (i, b) in buffer[..len].iter_mut().enumerate() {
+            *b = selftest_pattern(round, i);
+        }
+        self.state.set(State::SelfTestWrite { round });
+        self.op_start.set(self.time.now());
+        self.storage
+            .write(buffer, self.selftest_address(round, len), len)
+    }
+
+    /// Read back the area written in `round`.
+    fn selftest_read(&self, round: usize, buffer: &'static mut [u8]) -> Result<(), ErrorCode> {
+        let len = self.selftest_access_len(buffer);
+        self.state.set(State::SelfTestRead { round });
+        self.op_start.set(self.time.now());
+        self.storage
+            .read(buffer, self.selftest_address(round, len), len)
+    }
+
+    /// Microseconds since the current storage operation was started.
+    fn op_elapsed_us(&self) -> u32 {
+        let elapsed = self.time.now().wrapping_sub(self.op_start.get());
+        self.time.ticks_to_us(elapsed)
+    }
+
+    /// Stop the self-test because the storage refused an operation.
+    ///
+    /// The storage does not return the buffer when an operation fails to
+    /// start, so later `storage` commands will report that no buffer is
+    /// available.
+    fn selftest_abort(&self, error: ErrorCode) {
+        self.state.set(State::Idle);
+        self.session.map(|session| {
+            session.print(format_args!(
+                "Storage self-test failed to start an operation: {:?}\r\n",
+                error
+            ))
+        });
+    }
+
+    /// Print the results of a finished self-test.
+    fn selftest_report(&self, session: &dyn ConsoleSession) {
+        let stats = self.stats.get();
+        let rounds = SELFTEST_ROUNDS as u32;
+        session.print(format_args!(
+            "Storage self-test: {} rounds, {} bytes\r\n \
+             write: avg {}us max {}us\r\n \
+             read:  avg {}us max {}us\r\n \
+             errors: {} mismatched bytes: {}\r\n",
+            SELFTEST_ROUNDS,
+            stats.bytes,
+            stats.write_us_total / rounds,
+            stats.write_us_max,
+            stats.read_us_total / rounds,
+            stats.read_us_max,
+            stats.errors,
+            stats.mismatches,
+        ));
+    }
+
+    /// The running `storage` command is done.
+    fn command_done(&self) {
+        self.session.map(|session| session.command_done());
+    }
+
+    /// Start the `storage hexdump` or `storage dump` command for `length`
+    /// bytes starting at absolute storage address `address`.
+    fn hexdump(
+        &self,
+        session: &'a dyn ConsoleSession,
+        address: usize,
+        length: usize,
+        format: DumpFormat,
+    ) -> CommandStatus {
+        if self.state.get() != State::Idle {
+            session.write(b"Storage operation already in progress.\r\n");
+            return CommandStatus::Done;
+        }
+        if self.buffer.is_none() {
+            session.write(b"No storage buffer available.\r\n");
+            return CommandStatus::Done;
+        }
+        if length == 0 {
+            return CommandStatus::Done;
+        }
+        // Intel HEX addresses are 32 bits.
+        if format == DumpFormat::IntelHex
+            && address
+                .checked_add(length)
+                .map_or(true, |end| end - 1 > u32::MAX as usize)
+        {
+            session.write(b"Range does not fit 32-bit addresses.\r\n");
+            return CommandStatus::Done;
+        }
+        self.session.set(session);
+        self.state.set(State::HexDumpPending {
+            address,
+            remaining: length,
+            format,
+        });
+        if self.hexdump_step() {
+            CommandStatus::Running
+        } else {
+            CommandStatus::Done
+        }
+    }
+
+    /// Read the next `storage hexdump` chunk once the previous one has been
+    /// printed. Returns `false` if the read failed, which ends the command.
+    fn hexdump_step(&self) -> bool {
+        if let State::HexDumpPending {
+            address,
+            remaining,
+            format,
+        } = self.state.get()
+        {
+            let res = self.buffer.take().map_or(Err(ErrorCode::NOMEM), |buffer| {
+                let len = cmp::min(buffer.len(), remaining);
+                self.state.set(State::HexDumpRead {
+                    address,
+                    remaining,
+                    format,
+                });
+                self.storage.read(buffer, address, len)
+            });
+            if let Err(e) = res {
+                self.state.set(State::Idle);
+                self.session.map(|session| {
+                    session.print(format_args!(
+                        "Failed to read storage at {:#x}: {:?}\r\n",
+                        address, e
+                    ))
+                });
+                return false;
+            }
+        }
+        true
+    }
+
+    /// Print `data`, read from absolute storage address `address`.
+    fn hexdump_print(
+        session: &dyn ConsoleSession,
+        address: usize,
+        data: &[u8],
+        format: DumpFormat,
+    ) {
+        let mut console_writer = ConsoleWriter::new();
+        match format {
+            DumpFormat::Hex => {
+                for (i, line) in data.chunks(HEXDUMP_LINE_LEN).enumerate() {
+                    let _ = write(
+                        &mut console_writer,
+                        format_args!("{:08x}:", address + i * HEXDUMP_LINE_LEN),
+                    );
+                    for b in line {
+                        let _ = write(&mut console_writer, format_args!(" {:02x}", b));
+                    }
+                    let _ = write(&mut console_writer, format_args!("\r\n"));
+                }
+            }
+            DumpFormat::IntelHex => intel_hex_chunk(&mut console_writer, address, data),
+        }
+        session.write(console_writer.as_bytes());
+    }
+
+    /// Start the `source` command. The first line is read once the command
+    /// has finished.
+    fn source(&self, session: &'a dyn ConsoleSession) {
+        if self.script_length.get() == 0 {
+            session.write(b"No script region configured for the process console.\r\n");
+        } else if self.script.is_some() {
+            session.write(b"A script is already running.\r\n");
+        } else {
+            self.script.set(Script { session, offset: 0 });
+        }
+    }
+
+    /// Read the next line of the running script, if its console and the
+    /// storage are free.
+    fn script_step(&self) {
+        if self.state.get() != State::Idle {
+            return;
+        }
+        let script = match self.script.get() {
+            Some(script) if !script.session.is_busy() => script,
+            _ => return,
+        };
+
+        let remaining = self.script_length.get().saturating_sub(script.offset);
+        if remaining == 0 {
+            self.script.clear();
+            return;
+        }
+        let res = self.buffer.take().map_or(Err(ErrorCode::NOMEM), |buffer| {
+            let len = cmp::min(buffer.len(), remaining);
+            self.state.set(State::ScriptRead {
+                offset: script.offset,
+            });
+            self.storage
+                .read(buffer, self.script_address.get() + script.offset, len)
+        });
+        if let Err(e) = res {
+            self.state.set(State::Idle);
+            self.script_abort(format_args!("Failed to read script: {:?}\r\n", e));
+        }
+    }
+
+    /// Run the first line of `data`, read from `offset` in the script region.
+    ///
+    /// Returns `true` if the line holds no command and the script should
+    /// continue with the next line.
+    fn script_line_read(&self, offset: usize, data: &[u8]) -> bool {
+        let session = match self.script.get() {
+            // Someone started typing while the line was read. It is read
+            // again once the console is free.
+            Some(script) if !script.session.is_busy() => script.session,
+            _ => return false,
+        };
+        let (line, next) = match script_line(data, offset, self.script_length.get()) {
+            Some(parsed) => parsed,
+            None => {
+                self.script_abort(format_args!(
+                    "Script line at offset {} is too long.\r\n",
+                    offset
+                ));
+                return false;
+            }
+        };
+        match next {
+            Some(offset) => self.script.set(Script { session, offset }),
+            None => self.script.clear(),
+        }
+
+        if is_command(line) {
+            session.run_command(line);
+            false
+        } else {
+            true
+        }
+    }
+
+    /// Stop the running script and print why.
+    fn script_abort(&self, args: fmt::Arguments) {
+        self.script.take().map(|script| {
+            script.session.print(args);
+            script.session.command_done();
+        });
+    }
+}
+
+impl<'a, T: Time> ConsoleCommands<'a> for StorageCommands<'a, T> {
+    fn names(&self) -> &'static str {
+        "storage source"
+    }
+
+    fn run(&self, session: &'a dyn ConsoleSession, command: &str) -> CommandStatus {
+        let mut words = command.split_whitespace();
+        match words.next() {
+            Some("storage") => self.storage_command(session, words),
+            Some("source") => {
+                self.source(session);
+                CommandStatus::Done
+            }
+            _ => CommandStatus::Unknown,
+        }
+    }
+
+    fn output_drained(&self, session: &'a dyn ConsoleSession) {
+        // A dump only continues once its own console printed the last chunk.
+        let dumping = self
+            .session
+            .map_or(false, |dump_session| ptr::addr_eq(dump_session, session));
+        if dumping && !self.hexdump_step() {
+            self.command_done();
+        }
+        self.script_step();
+    }
+
+    fn next_commands(&'a self) -> &'a ListLink<'a, dyn ConsoleCommands<'a>> {
+        &self.next
+    }
+}
+
+impl<T: Time> NonvolatileStorageClient for StorageCommands<'_, T> {
+    fn read_done(&self, buffer: &'static mut [u8], length: usize) {
+        let elapsed = self.op_elapsed_us();
+        match self.state.get() {
+            State::SelfTestRead { round } => {
+                let len = self.selftest_access_len(buffer);
+                let mut stats = self.stats.get();
+                stats.read_us_total = stats.read_us_total.saturating_add(elapsed);
+                stats.read_us_max = cmp::max(stats.read_us_max, elapsed);
+                if length != len {
+                    stats.errors += 1;
+                }
+                stats.mismatches += buffer[..len]
+                    .iter()
+                    .enumerate()
+                    .filter(|(i, b)| **b != selftest_pattern(round, *i))
+                    .count();
+                stats.bytes += len;
+                self.stats.set(stats);
+
+                self.buffer.replace(buffer);
+                if round + 1 < SELFTEST_ROUNDS {
+                    if let Err(e) = self.selftest_write(round + 1) {
+                        self.selftest_abort(e);
+                        self.command_done();
+                    }
+                } else {
+                    self.state.set(State::Idle);
+                    self.session.map(|session| self.selftest_report(session));
+                    self.command_done();
+                }
+            }
+            State::HexDumpRead {
+                address,
+                remaining,
+                format,
+            } => {
+                let len = cmp::min(cmp::min(length, buffer.len()), remaining);
+                self.session
+                    .map(|session| Self::hexdump_print(session, address, &buffer[..len], format));
+                self.buffer.replace(buffer);
+                if len == 0 || len == remaining {
+                    self.state.set(State::Idle);
+                    // A dump that stopped early has no end of file record, so
+                    // the host can tell it is incomplete.
+                    if format == DumpFormat::IntelHex && len == remaining {
+                        self.session
+                            .map(|session| session.write(b":00000001FF\r\n"));
+                    }
+                    self.command_done();
+                } else {
+                    self.state.set(State::HexDumpPending {
+                        address: address + len,
+                        remaining: remaining - len,
+                        format,
+                    });
+                }
+            }
+            State::ScriptRead { offset } => {
+                self.state.set(State::Idle);
+                let len = cmp::min(length, buffer.len());
+                let skipped = self.script_line_read(offset, &buffer[..len]);
+                self.buffer.replace(buffer);
+                if skipped {
+                    self.script_step();
+                }
+            }
+            _ => {
+                self.buffer.replace(buffer);
+            }
+        }
+    }
+
+    fn write_done(&self, buffer: &'static mut [u8], length: usize) {
+        let elapsed = self.op_elapsed_us();
+        match self.state.get() {
+            State::SelfTestWrite { round } => {
+                let mut stats = self.stats.get();
+                stats.write_us_total = stats.write_us_total.saturating_add(elapsed);
+                stats.write_us_max = cmp::max(stats.write_us_max, elapsed);
+                if length != self.selftest_access_len(buffer) {
+                    stats.errors += 1;
+                }
+                self.stats.set(stats);
+
+                // Clear the buffer so the read has to actually fill it.
+                buffer.iter_mut().for_each(|b| *b = 0);
+                if let Err(e) = self.selftest_read(round, buffer) {
+                    self.selftest_abort(e);
+                    self.command_done();
+                }
+            }
+            _ => {
+                self.buffer.replace(buffer);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use capsules_core::process_console::ConsoleWriter;
+
+    use super::{intel_hex_chunk, intel_hex_record, is_command, script_line};
+
+    #[test]
+    fn intel_hex_records_carry_their_checksum() {
+        let mut writer = ConsoleWriter::new();
+        intel_hex_record(&mut writer, 0x00, 0x0030, &[0x02, 0x33, 0x7A]);
+        intel_hex_record(&mut writer, 0x01, 0, &[]);
+        assert_eq!(writer.as_bytes(), b":0300300002337A1E\r\n:00000001FF\r\n");
+    }
+
+    #[test]
+    fn intel_hex_chunks_restate_the_upper_address_bits() {
+        let mut writer = ConsoleWriter::new();
+        intel_hex_chunk(&mut writer, 0x1_FFFF, &[0xAA, 0xBB]);
+        assert_eq!(
+            writer.as_bytes(),
+            b":020000040001F9\r\n:01FFFF00AA57\r\n:020000040002F8\r\n:01000000BB44\r\n"
+        );
+    }
+
+    #[test]
+    fn script_lines_end_at_newlines() {
+        assert_eq!(
+            script_line(b"list\nstatus\n", 0x10, 0x100),
+            Some((&b"list"[..], Some(0x15)))
+        );
+        assert_eq!(script_line(b"\r\n", 0, 0x100), Some((&b""[..], Some(1))));
+    }
+
+    #[test]
+    fn scripts_end_at_erased_bytes_or_the_end_of_the_region() {
+        assert_eq!(
+            script_line(b"list\xff\xff", 0, 0x100),
+            Some((&b"list"[..], None))
+        );
+        assert_eq!(script_line(b"list\0", 0, 0x100), Some((&b"list"[..], None)));
+        assert_eq!(
+            script_line(b"list", 0xfc, 0x100),
+            Some((&b"list"[..], None))
+        );
+    }
+
+    #[test]
+    fn unfinished_or_too_long_lines_are_refused() {
+        assert_eq!(script_line(b"list", 0, 0x100), None);
+        assert_eq!(script_line(&[b'a'; 40], 0, 40), None);
+    }
+
+    #[test]
+    fn comments_and_blank_lines_are_skipped() {
+        assert!(is_command(b"  list"));
+        assert!(!is_command(b"   "));
+        assert!(!is_command(b" # list"));
+    }
+}
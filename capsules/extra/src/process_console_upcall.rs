@@ -0,0 +1,156 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2024.
+
+//! The `upcall` command of the process console, which schedules upcalls into
+//! processes for testing.
+//!
+//! `upcall <process> <driver> <subscribe> [r0] [r1] [r2]` schedules the upcall
+//! the process subscribed to with that driver and subscribe number, with the
+//! given arguments, as if the driver had scheduled it. It lets developers test
+//! how an app handles unusual results, such as a read done upcall with an
+//! unexpected length. It needs a capability production boards do not create.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust,ignore
+//! let upcall_cap = create_capability!(capabilities::UpcallInjectionCapability);
+//! components::process_console_upcall::UpcallCommandComponent::new(
+//!     board_kernel,
+//!     pconsole.engine(),
+//!     &upcall_cap,
+//! )
+//! .finalize(components::upcall_command_component_static!());
+//! ```
+
+use capsules_core::process_console::{
+    parse_number, CommandStatus, ConsoleCommands, ConsoleSession,
+};
+use kernel::capabilities::{ProcessManagementCapability, UpcallInjectionCapability};
+use kernel::collections::list::ListLink;
+use kernel::upcall::UpcallInjector;
+use kernel::Kernel;
+
+/// Split the arguments of the `upcall` command into the process name and the
+/// driver number, subscribe number, and upcall arguments, which default to 0.
+fn parse_upcall_args(args: &str) -> Option<(&str, [usize; 5])> {
+    let mut words = args.split_whitespace();
+    let name = words.next()?;
+    let mut numbers = [0; 5];
+    let mut count = 0;
+    for word in words {
+        *numbers.get_mut(count)? = parse_number(word)?;
+        count += 1;
+    }
+    if count < 2 {
+        return None;
+    }
+    Some((name, numbers))
+}
+
+pub struct UpcallCommand<'a, C: ProcessManagementCapability> {
+    kernel: &'static Kernel,
+    injector: UpcallInjector,
+    capability: C,
+    next: ListLink<'a, dyn ConsoleCommands<'a>>,
+}
+
+impl<'a, C: ProcessManagementCapability> UpcallCommand<'a, C> {
+    pub fn new(
+        kernel: &'static Kernel,
+        injection_capability: &dyn UpcallInjectionCapability,
+        capability: C,
+    ) -> Self {
+        Self {
+            kernel,
+            injector: kernel.upcall_injector(injection_capability),
+            capability,
+            next: ListLink::empty(),
+        }
+    }
+
+    /// Run the `upcall` command with `args`: the process name, the driver
+    /// and subscribe numbers, and up to three arguments for the upcall.
+    fn inject_upcall(&self, session: &dyn ConsoleSession, args: &str) {
+        let (name, numbers) = match parse_upcall_args(args) {
+            Some(parsed) => parsed,
+            None => {
+                session.write(b"Usage: upcall <process> <driver> <subscribe> [r0] [r1] [r2]\r\n");
+                return;
+            }
+        };
+
+        // If two processes have the same name, use the first one found.
+        let mut processid = None;
+        self.kernel
+            .process_each_capability(&self.capability, |proc| {
+                if processid.is_none() && proc.get_process_name() == name {
+                    processid = Some(proc.processid());
+                }
+            });
+
+        match processid {
+            Some(processid) => {
+                match self.injector.schedule(
+                    processid,
+                    numbers[0],
+                    numbers[1],
+                    (numbers[2], numbers[3], numbers[4]),
+                ) {
+                    Ok(()) => session.print(format_args!(
+                        "Scheduled upcall {:#x}:{} for {}.\r\n",
+                        numbers[0], numbers[1], name
+                    )),
+                    Err(e) => session.print(format_args!("Failed to schedule upcall: {:?}\r\n", e)),
+                }
+            }
+            None => session.print(format_args!("No process named {}.\r\n", name)),
+        }
+    }
+}
+
+impl<'a, C: ProcessManagementCapability + 'a> ConsoleCommands<'a> for UpcallCommand<'a, C> {
+    fn names(&self) -> &'static str {
+        "upcall"
+    }
+
+    fn run(&self, session: &'a dyn ConsoleSession, command: &str) -> CommandStatus {
+        match command.strip_prefix("upcall") {
+            Some(args) if args.is_empty() || args.starts_with(' ') => {
+                self.inject_upcall(session, args);
+                CommandStatus::Done
+            }
+            _ => CommandStatus::Unknown,
+        }
+    }
+
+    fn next_commands(&'a self) -> &'a ListLink<'a, dyn ConsoleCommands<'a>> {
+        &self.next
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::parse_upcall_args;
+
+    #[test]
+    fn arguments_default_to_zero() {
+        assert_eq!(
+            parse_upcall_args(" blink 0x2 1"),
+            Some(("blink", [2, 1, 0, 0, 0]))
+        );
+        assert_eq!(
+            parse_upcall_args(" blink 2 1 3 0x10 5"),
+            Some(("blink", [2, 1, 3, 16, 5]))
+        );
+    }
+
+    #[test]
+    fn missing_extra_or_invalid_numbers_are_refused() {
+        assert_eq!(parse_upcall_args(""), None);
+        assert_eq!(parse_upcall_args(" blink 2"), None);
+        assert_eq!(parse_upcall_args(" blink 2 1 3 4 5 6"), None);
+        assert_eq!(parse_upcall_args(" blink 2 x"), None);
+    }
+}
@@ -0,0 +1,232 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2024.
+
+//! The `watch` command of the process console, which re-runs a status command
+//! every few seconds.
+//!
+//! `watch <command> <seconds>` re-runs one of the read-only commands
+//! (`status`, `list`, `process`, `kernel`, `uptime`, or `scheduler`) every
+//! few seconds until a key is pressed, for example to follow the processes
+//! during a soak test. The next run only starts once the output of the
+//! previous one has been transmitted, and the interval counts from then, so
+//! a slow UART stretches the period instead of overflowing the output queue.
+//! A key pressed during a run stops the watch once the run is done.
+//!
+//! One command is watched at a time, in the session that started it. Keys
+//! pressed in other sessions do not stop it.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust,ignore
+//! components::process_console_watch::WatchCommandComponent::new(pconsole.engine(), mux_alarm)
+//!     .finalize(components::watch_command_component_static!(nrf52840::rtc::Rtc<'static>));
+//! ```
+
+use capsules_core::process_console::{
+    parse_number, CommandStatus, ConsoleCommands, ConsoleSession, COMMAND_BUF_LEN,
+};
+use core::ptr;
+
+use kernel::collections::list::ListLink;
+use kernel::hil::time::{Alarm, AlarmClient, ConvertTicks};
+use kernel::utilities::cells::OptionalCell;
+
+/// Commands `watch` can re-run. They only print state.
+const WATCH_COMMANDS: [&str; 6] = ["status", "list", "process", "kernel", "uptime", "scheduler"];
+
+/// Split the arguments of the `watch` command into the command to re-run and
+/// the interval in seconds.
+fn parse_watch_args(args: &str) -> Option<(&str, u32)> {
+    let (command, interval) = args.trim().rsplit_once(' ')?;
+    let command = command.trim();
+    let name = command.split_whitespace().next()?;
+    let interval_s = parse_number(interval).and_then(|s| u32::try_from(s).ok())?;
+    if WATCH_COMMANDS.contains(&name) && interval_s > 0 && command.len() < COMMAND_BUF_LEN {
+        Some((command, interval_s))
+    } else {
+        None
+    }
+}
+
+/// Progress of the command re-run by `watch`.
+#[derive(PartialEq, Eq, Copy, Clone)]
+enum WatchState {
+    /// The command runs as soon as the console is free.
+    Due,
+    /// The command is running or its output is being transmitted.
+    Running,
+    /// The alarm is set for the next run.
+    Waiting,
+}
+
+/// A command re-run by `watch`.
+#[derive(Copy, Clone)]
+struct Watch<'a> {
+    session: &'a dyn ConsoleSession,
+    command: [u8; COMMAND_BUF_LEN],
+    len: usize,
+    interval_s: u32,
+    state: WatchState,
+}
+
+pub struct WatchCommand<'a, A: Alarm<'a>> {
+    alarm: &'a A,
+    watch: OptionalCell<Watch<'a>>,
+    next: ListLink<'a, dyn ConsoleCommands<'a>>,
+}
+
+impl<'a, A: Alarm<'a>> WatchCommand<'a, A> {
+    /// The command must be the client of `alarm`.
+    pub fn new(alarm: &'a A) -> Self {
+        Self {
+            alarm,
+            watch: OptionalCell::empty(),
+            next: ListLink::empty(),
+        }
+    }
+
+    /// Start the `watch` command with `args`, the command to re-run and the
+    /// interval in seconds.
+    fn start(&self, session: &'a dyn ConsoleSession, args: &str) {
+        if self.watch.is_some() {
+            session.write(b"A command is already being watched.\r\n");
+            return;
+        }
+        match parse_watch_args(args) {
+            Some((command, interval_s)) => {
+                let mut watch = Watch {
+                    session,
+                    command: [0; COMMAND_BUF_LEN],
+                    len: command.len(),
+                    interval_s,
+                    state: WatchState::Due,
+                };
+                watch.command[..command.len()].copy_from_slice(command.as_bytes());
+                self.watch.set(watch);
+
+                session.print(format_args!(
+                    "Watching {} every {} s, press any key to stop.\r\n",
+                    command, interval_s
+                ));
+            }
+            None => session
+                .write(b"Usage: watch <status|list|process|kernel|uptime|scheduler> <seconds>\r\n"),
+        }
+    }
+
+    /// Run the watched command if it is due and its console is free, or set
+    /// the alarm for the next run once the output of the last one has been
+    /// transmitted.
+    fn step(&self) {
+        self.watch.take().map(|mut watch| {
+            match watch.state {
+                WatchState::Due => {
+                    if !watch.session.is_busy() {
+                        watch.state = WatchState::Running;
+                        watch.session.run_command(&watch.command[..watch.len]);
+                    }
+                }
+                WatchState::Running => {
+                    watch.state = WatchState::Waiting;
+                    self.alarm.set_alarm(
+                        self.alarm.now(),
+                        self.alarm.ticks_from_seconds(watch.interval_s),
+                    );
+                }
+                WatchState::Waiting => {}
+            }
+            self.watch.set(watch);
+        });
+    }
+
+    /// Whether the watched command runs in `session`.
+    fn watched_in(&self, session: &dyn ConsoleSession) -> bool {
+        self.watch
+            .map_or(false, |watch| ptr::addr_eq(watch.session, session))
+    }
+
+    /// Stop the `watch` command because a key was pressed.
+    fn stop(&self, watch: Watch<'a>) {
+        if watch.state == WatchState::Waiting {
+            let _ = self.alarm.disarm();
+        }
+        // A run in progress ends with its own prompt.
+        if watch.state != WatchState::Running {
+            watch.session.write(b"\r\nStopped watching.\r\n");
+            watch.session.command_done();
+        }
+    }
+}
+
+impl<'a, A: Alarm<'a>> ConsoleCommands<'a> for WatchCommand<'a, A> {
+    fn names(&self) -> &'static str {
+        "watch"
+    }
+
+    fn run(&self, session: &'a dyn ConsoleSession, command: &str) -> CommandStatus {
+        match command.strip_prefix("watch") {
+            Some(args) if args.is_empty() || args.starts_with(' ') => {
+                self.start(session, args);
+                CommandStatus::Done
+            }
+            _ => CommandStatus::Unknown,
+        }
+    }
+
+    fn output_drained(&self, session: &'a dyn ConsoleSession) {
+        if self.watched_in(session) {
+            self.step();
+        }
+    }
+
+    fn key_pressed(&self, session: &'a dyn ConsoleSession) -> bool {
+        if !self.watched_in(session) {
+            return false;
+        }
+        self.watch.take().map_or(false, |watch| {
+            self.stop(watch);
+            true
+        })
+    }
+
+    fn next_commands(&'a self) -> &'a ListLink<'a, dyn ConsoleCommands<'a>> {
+        &self.next
+    }
+}
+
+impl<'a, A: Alarm<'a>> AlarmClient for WatchCommand<'a, A> {
+    fn alarm(&self) {
+        if let Some(mut watch) = self.watch.get() {
+            if watch.state == WatchState::Waiting {
+                watch.state = WatchState::Due;
+                self.watch.set(watch);
+                self.step();
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::parse_watch_args;
+
+    #[test]
+    fn status_commands_are_watched() {
+        assert_eq!(parse_watch_args(" list 5"), Some(("list", 5)));
+        assert_eq!(
+            parse_watch_args(" process blink 0x10"),
+            Some(("process blink", 16))
+        );
+    }
+
+    #[test]
+    fn other_commands_and_bad_intervals_are_refused() {
+        assert_eq!(parse_watch_args(""), None);
+        assert_eq!(parse_watch_args(" list"), None);
+        assert_eq!(parse_watch_args(" list 0"), None);
+        assert_eq!(parse_watch_args(" reset 5"), None);
+        assert_eq!(parse_watch_args(" list 5000000000"), None);
+    }
+}
@@ -0,0 +1,92 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2024.
+
+//! Quiesce several devices at once before a reset.
+//!
+//! Capsules that must not be interrupted mid-operation register with a
+//! `QuiesceGroup`. When the board wants to reset, it quiesces the group and
+//! resets once the group reports that every member is idle.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust,ignore
+//! let group = static_init!(QuiesceGroup<'static, 2>, QuiesceGroup::new());
+//! group.add(nonvolatile_storage).unwrap();
+//! hil::quiesce::Quiesce::set_quiesce_client(nonvolatile_storage, group);
+//! hil::quiesce::Quiesce::set_quiesce_client(group, reset_handler);
+//!
+//! // Later, before resetting:
+//! if hil::quiesce::Quiesce::quiesce(group).is_err() {
+//!     reset();
+//! }
+//! ```
+
+use core::cell::Cell;
+
+use kernel::hil::quiesce::{Quiesce, QuiesceClient};
+use kernel::utilities::cells::OptionalCell;
+use kernel::ErrorCode;
+
+pub struct QuiesceGroup<'a, const N: usize> {
+    members: [OptionalCell<&'a dyn Quiesce<'a>>; N],
+    /// Number of members that have not finished quiescing.
+    outstanding: Cell<usize>,
+    client: OptionalCell<&'a dyn QuiesceClient>,
+}
+
+impl<'a, const N: usize> QuiesceGroup<'a, N> {
+    pub fn new() -> Self {
+        Self {
+            members: [(); N].map(|()| OptionalCell::empty()),
+            outstanding: Cell::new(0),
+            client: OptionalCell::empty(),
+        }
+    }
+
+    /// Add `member` to the group. The caller must also set the group as the
+    /// quiesce client of `member`.
+    ///
+    /// Returns `NOMEM` if the group is full.
+    pub fn add(&self, member: &'a dyn Quiesce<'a>) -> Result<(), ErrorCode> {
+        self.members
+            .iter()
+            .find(|slot| slot.is_none())
+            .map_or(Err(ErrorCode::NOMEM), |slot| {
+                slot.set(member);
+                Ok(())
+            })
+    }
+}
+
+impl<'a, const N: usize> Quiesce<'a> for QuiesceGroup<'a, N> {
+    fn set_quiesce_client(&self, client: &'a dyn QuiesceClient) {
+        self.client.set(client);
+    }
+
+    fn quiesce(&self) -> Result<(), ErrorCode> {
+        let outstanding = self
+            .members
+            .iter()
+            .filter_map(|slot| slot.get())
+            .filter(|member| member.quiesce().is_ok())
+            .count();
+        self.outstanding.set(outstanding);
+        if outstanding == 0 {
+            Err(ErrorCode::ALREADY)
+        } else {
+            Ok(())
+        }
+    }
+}
+
+impl<const N: usize> QuiesceClient for QuiesceGroup<'_, N> {
+    fn quiesce_done(&self) {
+        let outstanding = self.outstanding.get().saturating_sub(1);
+        self.outstanding.set(outstanding);
+        if outstanding == 0 {
+            self.client.map(|client| client.quiesce_done());
+        }
+    }
+}
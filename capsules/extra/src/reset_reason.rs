@@ -0,0 +1,94 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2024.
+
+//! Provides the cause of the last reset to userspace.
+//!
+//! Apps can use the cause to adjust how they recover, for example by
+//! skipping a slow calibration after a watchdog reset. The cause is read
+//! once, when the capsule is created, and the chip's record of it is then
+//! cleared so the next boot reports only its own cause.
+//!
+//! Watchdog and brown-out resets can also be recorded in the kernel event
+//! journal with `record_in_journal()`.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust,ignore
+//! let reset_reason = components::reset_reason::ResetReasonComponent::new(
+//!     &base_peripherals.pwr_clk,
+//! )
+//! .finalize(components::reset_reason_component_static!());
+//! reset_reason.record_in_journal(journal);
+//! ```
+
+/// Syscall driver number.
+use capsules_core::driver;
+pub const DRIVER_NUM: usize = driver::NUM::ResetReason as usize;
+
+use crate::event_journal::{EventKind, EventRecorder};
+use kernel::hil::reset_reason::{ResetCause, ResetReason};
+use kernel::syscall::{CommandReturn, SyscallDriver};
+use kernel::{ErrorCode, ProcessId};
+
+pub struct ResetReasonDriver {
+    cause: ResetCause,
+    flags: u32,
+}
+
+impl ResetReasonDriver {
+    pub fn new<R: ResetReason>(reset_reason: &R) -> Self {
+        let cause = reset_reason.reset_cause();
+        let flags = reset_reason.reset_flags();
+        reset_reason.clear_reset_cause();
+        Self { cause, flags }
+    }
+
+    /// Cause of the last reset.
+    pub fn reset_cause(&self) -> ResetCause {
+        self.cause
+    }
+
+    /// Record the last reset in `journal` if it was caused by the watchdog or
+    /// a brown-out. The chip-specific reset flags are stored with it.
+    pub fn record_in_journal(&self, journal: &dyn EventRecorder) {
+        let kind = match self.cause {
+            ResetCause::Watchdog => EventKind::WatchdogReset,
+            ResetCause::BrownOut => EventKind::BrownOut,
+            _ => return,
+        };
+        let _ = journal.record(kind, 0, self.flags);
+    }
+}
+
+impl SyscallDriver for ResetReasonDriver {
+    /// Read the reset cause.
+    ///
+    /// ### `command_num`
+    ///
+    /// - `0`: Driver check.
+    /// - `1`: Return the cause of the last reset and the chip-specific flags
+    ///   it was derived from. The causes are 0 unknown, 1 power-on, 2 reset
+    ///   pin, 3 watchdog, 4 software, 5 brown-out, 6 lockup and 7 wakeup from
+    ///   deep sleep.
+    fn command(
+        &self,
+        command_num: usize,
+        _data: usize,
+        _: usize,
+        _processid: ProcessId,
+    ) -> CommandReturn {
+        match command_num {
+            0 => CommandReturn::success(),
+
+            1 => CommandReturn::success_u32_u32(self.cause as u32, self.flags),
+
+            _ => CommandReturn::failure(ErrorCode::NOSUPPORT),
+        }
+    }
+
+    fn allocate_grant(&self, _processid: ProcessId) -> Result<(), kernel::process::Error> {
+        Ok(())
+    }
+}
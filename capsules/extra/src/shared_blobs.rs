@@ -0,0 +1,175 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2024.
+
+//! Publishes small read-only blobs to every app, such as firmware metadata
+//! the kernel keeps in memory.
+//!
+//! The board picks a number for each blob. Apps peek at a blob by its number,
+//! which copies it into their allowed buffer right away, without an upcall.
+//! Peeks do not touch storage, and need no storage permissions.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust,ignore
+//! static BLOBS: [SharedBlob; 1] = [SharedBlob {
+//!     id: 1,
+//!     data: b"board-rev-3",
+//! }];
+//! let shared_blobs = components::shared_blobs::SharedBlobsComponent::new(
+//!     board_kernel,
+//!     capsules_extra::shared_blobs::DRIVER_NUM,
+//!     &BLOBS,
+//! )
+//! .finalize(components::shared_blobs_component_static!());
+//! ```
+
+use core::cmp;
+
+use kernel::grant::{AllowRoCount, AllowRwCount, Grant, UpcallCount};
+use kernel::processbuffer::WriteableProcessBuffer;
+use kernel::syscall::{CommandReturn, SyscallDriver};
+use kernel::{ErrorCode, ProcessId};
+
+/// Syscall driver number.
+use capsules_core::driver;
+pub const DRIVER_NUM: usize = driver::NUM::SharedBlobs as usize;
+
+/// Ids for read-write allow buffers.
+mod rw_allow {
+    /// Buffer blobs are copied into.
+    pub const READ: usize = 0;
+    /// The number of allow buffers the kernel stores for this grant.
+    pub const COUNT: u8 = 1;
+}
+
+/// A read-only blob every app can peek at, identified by a number the board
+/// picks.
+#[derive(Clone, Copy, Debug)]
+pub struct SharedBlob {
+    pub id: u32,
+    pub data: &'static [u8],
+}
+
+/// The data of the blob `id` from `offset` on. Only the first blob with an
+/// identifier can be found.
+fn find_blob(blobs: &[SharedBlob], id: usize, offset: usize) -> Result<&'static [u8], ErrorCode> {
+    let blob = blobs
+        .iter()
+        .find(|blob| blob.id as usize == id)
+        .ok_or(ErrorCode::INVAL)?;
+    blob.data.get(offset..).ok_or(ErrorCode::INVAL)
+}
+
+pub struct SharedBlobs {
+    apps: Grant<(), UpcallCount<0>, AllowRoCount<0>, AllowRwCount<{ rw_allow::COUNT }>>,
+    blobs: &'static [SharedBlob],
+}
+
+impl SharedBlobs {
+    /// The identifiers of `blobs` should be unique.
+    pub fn new(
+        grant: Grant<(), UpcallCount<0>, AllowRoCount<0>, AllowRwCount<{ rw_allow::COUNT }>>,
+        blobs: &'static [SharedBlob],
+    ) -> Self {
+        Self { apps: grant, blobs }
+    }
+
+    // Copy the blob `id` from `offset` on into the read buffer of
+    // `processid`. Returns the number of bytes copied and the length of the
+    // blob.
+    fn peek(
+        &self,
+        id: usize,
+        offset: usize,
+        processid: ProcessId,
+    ) -> Result<(usize, usize), ErrorCode> {
+        let data = find_blob(self.blobs, id, offset)?;
+        self.apps
+            .enter(processid, |_app, kernel_data| {
+                kernel_data
+                    .get_readwrite_processbuffer(rw_allow::READ)
+                    .and_then(|read| {
+                        read.mut_enter(|app_buffer| {
+                            let copied = cmp::min(app_buffer.len(), data.len());
+                            app_buffer[..copied].copy_from_slice(&data[..copied]);
+                            copied
+                        })
+                    })
+                    .map_err(ErrorCode::from)
+            })
+            .unwrap_or_else(|err| Err(err.into()))
+            .map(|copied| (copied, offset + data.len()))
+    }
+}
+
+impl SyscallDriver for SharedBlobs {
+    /// Peek at shared blobs.
+    ///
+    /// ### `command_num`
+    ///
+    /// - `0`: Driver check.
+    /// - `1`: Copy the blob whose identifier is the first argument, from the
+    ///   offset given as the second argument, into the allowed buffer.
+    ///   Returns the number of bytes copied and the length of the blob.
+    ///   Returns `INVAL` if there is no such blob, or the offset is past its
+    ///   end.
+    fn command(
+        &self,
+        command_num: usize,
+        id: usize,
+        offset: usize,
+        processid: ProcessId,
+    ) -> CommandReturn {
+        match command_num {
+            0 => CommandReturn::success(),
+
+            1 => match self.peek(id, offset, processid) {
+                Ok((copied, length)) => {
+                    CommandReturn::success_u32_u32(copied as u32, length as u32)
+                }
+                Err(e) => CommandReturn::failure(e),
+            },
+
+            _ => CommandReturn::failure(ErrorCode::NOSUPPORT),
+        }
+    }
+
+    fn allocate_grant(&self, processid: ProcessId) -> Result<(), kernel::process::Error> {
+        self.apps.enter(processid, |_, _| {})
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use kernel::ErrorCode;
+
+    use super::{find_blob, SharedBlob};
+
+    const BLOBS: [SharedBlob; 3] = [
+        SharedBlob {
+            id: 1,
+            data: b"first",
+        },
+        SharedBlob { id: 2, data: b"" },
+        SharedBlob {
+            id: 1,
+            data: b"shadowed",
+        },
+    ];
+
+    #[test]
+    fn blobs_are_found_by_id_from_the_offset() {
+        assert_eq!(find_blob(&BLOBS, 1, 0), Ok(&b"first"[..]));
+        assert_eq!(find_blob(&BLOBS, 1, 3), Ok(&b"st"[..]));
+        assert_eq!(find_blob(&BLOBS, 1, 5), Ok(&b""[..]));
+        assert_eq!(find_blob(&BLOBS, 2, 0), Ok(&b""[..]));
+    }
+
+    #[test]
+    fn unknown_blobs_and_offsets_past_the_end_are_refused() {
+        assert_eq!(find_blob(&BLOBS, 3, 0), Err(ErrorCode::INVAL));
+        assert_eq!(find_blob(&BLOBS, 1, 6), Err(ErrorCode::INVAL));
+    }
+}
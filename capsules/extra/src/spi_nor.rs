@@ -0,0 +1,621 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2024.
+
+//! Driver for SPI NOR flash chips that describe themselves with JEDEC SFDP.
+//!
+//! Instead of hard-coding the layout of one part, `probe()` reads the JEDEC
+//! ID and the Serial Flash Discoverable Parameters (JESD216) of the chip to
+//! find its size, its page program size, and the opcode that erases a 4 KiB
+//! sector. Most serial NOR flash made in the last decade supports SFDP, so
+//! boards can use this driver for whatever part they carry.
+//!
+//! The driver implements `hil::flash::Flash` with 4 KiB pages, the smallest
+//! block the chip can erase. Writing a page erases its sector and programs it
+//! again one program page at a time, skipping program pages that stay
+//! erased. Like other flash, it can back the nonvolatile storage driver and
+//! other `NonvolatileStorage` users through `NonvolatileToPages`, for example
+//! with `NonvolatileStorageComponent`.
+//!
+//! The driver uses single-I/O commands with three address bytes, so only the
+//! first 16 MiB of larger chips is used. Chips that only accept four address
+//! bytes, or cannot erase 4 KiB sectors, are rejected by `probe()`.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust,ignore
+//! let spi_nor = components::spi_nor::SpiNorComponent::new(
+//!     &nrf52840::gpio::PORT[Pin::P0_17],
+//!     mux_alarm,
+//!     mux_spi,
+//! )
+//! .finalize(components::spi_nor_component_static!(
+//!     nrf52840::spi::SPIM,
+//!     nrf52840::rtc::Rtc
+//! ));
+//! // The flash can be used once probing finished.
+//! spi_nor.probe().unwrap();
+//! ```
+
+use core::cell::Cell;
+use core::cmp;
+use core::ops::{Index, IndexMut};
+
+use kernel::hil;
+use kernel::hil::time::ConvertTicks;
+use kernel::utilities::cells::{MapCell, OptionalCell, TakeCell};
+use kernel::utilities::leasable_buffer::SubSliceMut;
+use kernel::ErrorCode;
+
+/// Size of a page of this driver, the 4 KiB the chip erases at once.
+pub const SECTOR_SIZE: usize = 4096;
+/// Most bytes read or programmed with one SPI transfer.
+pub const TRANSFER_LEN: usize = 256;
+/// Opcode, three address bytes, and a dummy byte.
+const HEADER_LEN: usize = 5;
+
+pub const TX_BUF_LEN: usize = TRANSFER_LEN + HEADER_LEN;
+pub const RX_BUF_LEN: usize = TRANSFER_LEN + HEADER_LEN;
+
+const SPI_SPEED: u32 = 8000000;
+/// Largest size three address bytes reach.
+const MAX_SIZE: usize = 1 << 24;
+/// Number of DWORDs of the basic flash parameter table that are read.
+const PARAMETER_DWORDS: usize = 16;
+/// How often the status is checked while a sector erases, and while a page
+/// programs.
+const ERASE_POLL_MS: u32 = 5;
+const PROGRAM_POLL_US: u32 = 200;
+
+// Opcodes.
+const WRITE_ENABLE: u8 = 0x06;
+const READ_STATUS: u8 = 0x05;
+const READ: u8 = 0x03;
+const PAGE_PROGRAM: u8 = 0x02;
+const READ_ID: u8 = 0x9F;
+const READ_SFDP: u8 = 0x5A;
+
+/// Write in progress bit of the status register.
+const STATUS_BUSY: u8 = 0x01;
+
+/// A 4 KiB page of the flash.
+///
+/// ```rust,ignore
+/// static mut PAGEBUFFER: SpiNorSector = SpiNorSector::new();
+/// ```
+pub struct SpiNorSector(pub [u8; SECTOR_SIZE]);
+
+impl SpiNorSector {
+    pub const fn new() -> Self {
+        Self([0; SECTOR_SIZE])
+    }
+}
+
+impl Default for SpiNorSector {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Index<usize> for SpiNorSector {
+    type Output = u8;
+
+    fn index(&self, idx: usize) -> &u8 {
+        &self.0[idx]
+    }
+}
+
+impl IndexMut<usize> for SpiNorSector {
+    fn index_mut(&mut self, idx: usize) -> &mut u8 {
+        &mut self.0[idx]
+    }
+}
+
+impl AsMut<[u8]> for SpiNorSector {
+    fn as_mut(&mut self) -> &mut [u8] {
+        &mut self.0
+    }
+}
+
+/// What `probe()` found out about the chip.
+#[derive(Clone, Copy, Debug)]
+pub struct SpiNorInfo {
+    /// Manufacturer ID, memory type, and capacity code.
+    pub jedec_id: [u8; 3],
+    /// Usable size in bytes.
+    pub size: usize,
+    /// Bytes programmed with one page program command.
+    pub program_len: usize,
+    /// Opcode that erases a 4 KiB sector.
+    pub erase_opcode: u8,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum Operation {
+    Probe,
+    Read,
+    Write,
+    Erase,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum State {
+    Idle,
+    ProbeId,
+    ProbeHeader {
+        jedec_id: [u8; 3],
+    },
+    ProbeParameters {
+        jedec_id: [u8; 3],
+        dwords: usize,
+    },
+    Read {
+        offset: usize,
+    },
+    EraseWriteEnable,
+    Erase,
+    /// Waiting for the erase to finish.
+    EraseWait,
+    ProgramWriteEnable {
+        offset: usize,
+    },
+    Program {
+        offset: usize,
+    },
+    /// Waiting for the program page at `offset` to finish.
+    ProgramWait {
+        offset: usize,
+    },
+}
+
+pub struct SpiNor<'a, S: hil::spi::SpiMasterDevice<'a>, A: hil::time::Alarm<'a>> {
+    spi: &'a S,
+    alarm: &'a A,
+    state: Cell<State>,
+    operation: Cell<Operation>,
+    sector_index: Cell<usize>,
+    info: OptionalCell<SpiNorInfo>,
+    txbuffer: MapCell<SubSliceMut<'static, u8>>,
+    rxbuffer: MapCell<SubSliceMut<'static, u8>>,
+    client: OptionalCell<&'a dyn hil::flash::Client<SpiNor<'a, S, A>>>,
+    client_sector: TakeCell<'static, SpiNorSector>,
+}
+
+impl<'a, S: hil::spi::SpiMasterDevice<'a>, A: hil::time::Alarm<'a>> SpiNor<'a, S, A> {
+    pub fn new(
+        spi: &'a S,
+        alarm: &'a A,
+        txbuffer: &'static mut [u8],
+        rxbuffer: &'static mut [u8],
+    ) -> SpiNor<'a, S, A> {
+        SpiNor {
+            spi,
+            alarm,
+            state: Cell::new(State::Idle),
+            operation: Cell::new(Operation::Probe),
+            sector_index: Cell::new(0),
+            info: OptionalCell::empty(),
+            txbuffer: MapCell::new(txbuffer.into()),
+            rxbuffer: MapCell::new(rxbuffer.into()),
+            client: OptionalCell::empty(),
+            client_sector: TakeCell::empty(),
+        }
+    }
+
+    /// Read the JEDEC ID and SFDP tables of the chip. The flash can be used
+    /// once `info()` returns the result.
+    pub fn probe(&self) -> Result<(), ErrorCode> {
+        if self.state.get() != State::Idle {
+            return Err(ErrorCode::BUSY);
+        }
+        self.configure_spi()?;
+        self.operation.set(Operation::Probe);
+        self.transfer(State::ProbeId, 4, true, |buffer| buffer[0] = READ_ID)
+    }
+
+    /// What probing found, or `None` if probing has not finished or the chip
+    /// is not supported.
+    pub fn info(&self) -> Option<SpiNorInfo> {
+        self.info.get()
+    }
+
+    fn configure_spi(&self) -> Result<(), ErrorCode> {
+        self.spi.configure(
+            hil::spi::ClockPolarity::IdleLow,
+            hil::spi::ClockPhase::SampleLeading,
+            SPI_SPEED,
+        )
+    }
+
+    // Send the first `len` bytes of the TX buffer after `fill` sets them, and
+    // read as many bytes if `read` is set. The transfer completes in `state`.
+    // The driver is idle again if the transfer cannot start.
+    fn transfer<F: FnOnce(&mut SubSliceMut<'static, u8>)>(
+        &self,
+        state: State,
+        len: usize,
+        read: bool,
+        fill: F,
+    ) -> Result<(), ErrorCode> {
+        let mut txbuffer = self.txbuffer.take().ok_or(ErrorCode::RESERVE)?;
+        txbuffer.reset();
+        fill(&mut txbuffer);
+        txbuffer.slice(0..len);
+        let rxbuffer = if read {
+            match self.rxbuffer.take() {
+                Some(mut rxbuffer) => {
+                    rxbuffer.reset();
+                    rxbuffer.slice(0..len);
+                    Some(rxbuffer)
+                }
+                None => {
+                    self.txbuffer.replace(txbuffer);
+                    return Err(ErrorCode::RESERVE);
+                }
+            }
+        } else {
+            None
+        };
+
+        self.state.set(state);
+        if let Err((err, txbuffer, rxbuffer)) = self.spi.read_write_bytes(txbuffer, rxbuffer) {
+            self.txbuffer.replace(txbuffer);
+            if let Some(rxbuffer) = rxbuffer {
+                self.rxbuffer.replace(rxbuffer);
+            }
+            self.state.set(State::Idle);
+            return Err(err);
+        }
+        Ok(())
+    }
+
+    fn set_command(buffer: &mut SubSliceMut<'static, u8>, opcode: u8, address: usize) {
+        buffer[0] = opcode;
+        buffer[1] = (address >> 16) as u8;
+        buffer[2] = (address >> 8) as u8;
+        buffer[3] = address as u8;
+    }
+
+    // Copy the bytes starting at `offset` of what was last read into `data`.
+    fn received(&self, offset: usize, data: &mut [u8]) {
+        self.rxbuffer.map(|rxbuffer| {
+            for (i, b) in data.iter_mut().enumerate() {
+                *b = rxbuffer[offset + i];
+            }
+        });
+    }
+
+    fn dword(&self, index: usize) -> u32 {
+        let mut bytes = [0; 4];
+        self.received(HEADER_LEN + index * 4, &mut bytes);
+        u32::from_le_bytes(bytes)
+    }
+
+    fn sector_address(&self) -> usize {
+        self.sector_index.get() * SECTOR_SIZE
+    }
+
+    fn program_len(&self) -> usize {
+        self.info
+            .get()
+            .map_or(TRANSFER_LEN, |info| info.program_len)
+    }
+
+    // Check that an operation on `page_number` can start.
+    fn start(&self, operation: Operation, page_number: usize) -> Result<(), ErrorCode> {
+        if self.state.get() != State::Idle {
+            return Err(ErrorCode::BUSY);
+        }
+        let info = self.info.get().ok_or(ErrorCode::OFF)?;
+        if page_number >= info.size / SECTOR_SIZE {
+            return Err(ErrorCode::INVAL);
+        }
+        self.configure_spi()?;
+        self.operation.set(operation);
+        self.sector_index.set(page_number);
+        Ok(())
+    }
+
+    fn read_chunk(&self, offset: usize) -> Result<(), ErrorCode> {
+        let address = self.sector_address() + offset;
+        self.transfer(State::Read { offset }, 4 + TRANSFER_LEN, true, |buffer| {
+            Self::set_command(buffer, READ, address)
+        })
+    }
+
+    fn erase_sector(&self) -> Result<(), ErrorCode> {
+        self.transfer(State::EraseWriteEnable, 1, false, |buffer| {
+            buffer[0] = WRITE_ENABLE
+        })
+    }
+
+    // Program the sector from the program page at `offset` on, skipping pages
+    // that would stay erased.
+    fn program_from(&self, mut offset: usize) -> Result<(), ErrorCode> {
+        let len = self.program_len();
+        while offset < SECTOR_SIZE
+            && self.client_sector.map_or(false, |sector| {
+                sector.0[offset..offset + len].iter().all(|b| *b == 0xFF)
+            })
+        {
+            offset += len;
+        }
+        if offset >= SECTOR_SIZE {
+            self.finish(Ok(()));
+            return Ok(());
+        }
+        self.transfer(State::ProgramWriteEnable { offset }, 1, false, |buffer| {
+            buffer[0] = WRITE_ENABLE
+        })
+    }
+
+    fn poll_status(&self, state: State) -> Result<(), ErrorCode> {
+        self.transfer(state, 2, true, |buffer| buffer[0] = READ_STATUS)
+    }
+
+    // Continue after the transfer that completed in `state`.
+    fn step(&self, state: State) -> Result<(), ErrorCode> {
+        match state {
+            State::Idle => Ok(()),
+            State::ProbeId => {
+                let mut jedec_id = [0; 3];
+                self.received(1, &mut jedec_id);
+                self.transfer(
+                    State::ProbeHeader { jedec_id },
+                    HEADER_LEN + 16,
+                    true,
+                    |buffer| {
+                        Self::set_command(buffer, READ_SFDP, 0);
+                        buffer[4] = 0;
+                    },
+                )
+            }
+            State::ProbeHeader { jedec_id } => {
+                // The SFDP header is followed by the header of the first
+                // parameter table, which is always the basic flash
+                // parameter table.
+                let mut header = [0; 16];
+                self.received(HEADER_LEN, &mut header);
+                if &header[0..4] != b"SFDP" || header[8] != 0x00 || header[15] != 0xFF {
+                    return Err(ErrorCode::NOSUPPORT);
+                }
+                let dwords = cmp::min(header[11] as usize, PARAMETER_DWORDS);
+                if dwords < 2 {
+                    return Err(ErrorCode::NOSUPPORT);
+                }
+                let pointer =
+                    header[12] as usize | (header[13] as usize) << 8 | (header[14] as usize) << 16;
+                self.transfer(
+                    State::ProbeParameters { jedec_id, dwords },
+                    HEADER_LEN + dwords * 4,
+                    true,
+                    |buffer| {
+                        Self::set_command(buffer, READ_SFDP, pointer);
+                        buffer[4] = 0;
+                    },
+                )
+            }
+            State::ProbeParameters { jedec_id, dwords } => {
+                let first = self.dword(0);
+                // Bits 1:0 say whether 4 KiB erase is supported, bits 18:17
+                // whether three byte addresses are.
+                if first & 0x3 != 0x1 || (first >> 17) & 0x3 == 0x2 {
+                    return Err(ErrorCode::NOSUPPORT);
+                }
+                let density = self.dword(1);
+                let bits = if density & (1 << 31) == 0 {
+                    density as u64 + 1
+                } else {
+                    1u64.checked_shl(density & 0x7FFF_FFFF).unwrap_or(u64::MAX)
+                };
+                let size = cmp::min(bits / 8, MAX_SIZE as u64) as usize;
+                // Older chips do not give their page size, and use 256 bytes.
+                let page_size: usize = if dwords >= 11 {
+                    1 << ((self.dword(10) >> 4) & 0xF)
+                } else {
+                    256
+                };
+                self.info.set(SpiNorInfo {
+                    jedec_id,
+                    size,
+                    program_len: cmp::min(page_size, TRANSFER_LEN),
+                    erase_opcode: (first >> 8) as u8,
+                });
+                self.state.set(State::Idle);
+                Ok(())
+            }
+            State::Read { offset } => {
+                self.client_sector.map(|sector| {
+                    self.received(4, &mut sector.0[offset..offset + TRANSFER_LEN]);
+                });
+                let offset = offset + TRANSFER_LEN;
+                if offset >= SECTOR_SIZE {
+                    self.finish(Ok(()));
+                    Ok(())
+                } else {
+                    self.read_chunk(offset)
+                }
+            }
+            State::EraseWriteEnable => {
+                let opcode = self.info.get().map_or(0x20, |info| info.erase_opcode);
+                let address = self.sector_address();
+                self.transfer(State::Erase, 4, false, |buffer| {
+                    Self::set_command(buffer, opcode, address)
+                })
+            }
+            State::Erase => {
+                self.state.set(State::EraseWait);
+                self.alarm
+                    .set_alarm(self.alarm.now(), self.alarm.ticks_from_ms(ERASE_POLL_MS));
+                Ok(())
+            }
+            State::EraseWait => {
+                let mut status = [0];
+                self.received(1, &mut status);
+                if status[0] & STATUS_BUSY != 0 {
+                    self.alarm
+                        .set_alarm(self.alarm.now(), self.alarm.ticks_from_ms(ERASE_POLL_MS));
+                    Ok(())
+                } else if self.operation.get() == Operation::Write {
+                    self.program_from(0)
+                } else {
+                    self.finish(Ok(()));
+                    Ok(())
+                }
+            }
+            State::ProgramWriteEnable { offset } => {
+                let len = self.program_len();
+                let address = self.sector_address() + offset;
+                let sector = self.client_sector.take().ok_or(ErrorCode::FAIL)?;
+                let result = self.transfer(State::Program { offset }, 4 + len, false, |buffer| {
+                    Self::set_command(buffer, PAGE_PROGRAM, address);
+                    for (i, b) in sector.0[offset..offset + len].iter().enumerate() {
+                        buffer[4 + i] = *b;
+                    }
+                });
+                self.client_sector.replace(sector);
+                result
+            }
+            State::Program { offset } => {
+                self.state.set(State::ProgramWait { offset });
+                self.alarm
+                    .set_alarm(self.alarm.now(), self.alarm.ticks_from_us(PROGRAM_POLL_US));
+                Ok(())
+            }
+            State::ProgramWait { offset } => {
+                let mut status = [0];
+                self.received(1, &mut status);
+                if status[0] & STATUS_BUSY != 0 {
+                    self.alarm
+                        .set_alarm(self.alarm.now(), self.alarm.ticks_from_us(PROGRAM_POLL_US));
+                    Ok(())
+                } else {
+                    self.program_from(offset + self.program_len())
+                }
+            }
+        }
+    }
+
+    // End the current operation and tell the client.
+    fn finish(&self, result: Result<(), ErrorCode>) {
+        self.state.set(State::Idle);
+        let result = result.map_err(|_| hil::flash::Error::FlashError);
+        match self.operation.get() {
+            Operation::Probe => {}
+            Operation::Read => {
+                if let Some(sector) = self.client_sector.take() {
+                    self.client
+                        .map(move |client| client.read_complete(sector, result));
+                }
+            }
+            Operation::Write => {
+                if let Some(sector) = self.client_sector.take() {
+                    self.client
+                        .map(move |client| client.write_complete(sector, result));
+                }
+            }
+            Operation::Erase => {
+                self.client.map(|client| client.erase_complete(result));
+            }
+        }
+    }
+}
+
+impl<'a, S: hil::spi::SpiMasterDevice<'a>, A: hil::time::Alarm<'a>> hil::spi::SpiMasterClient
+    for SpiNor<'a, S, A>
+{
+    fn read_write_done(
+        &self,
+        write_buffer: SubSliceMut<'static, u8>,
+        read_buffer: Option<SubSliceMut<'static, u8>>,
+        read_write_status: Result<usize, ErrorCode>,
+    ) {
+        self.txbuffer.replace(write_buffer);
+        if let Some(read_buffer) = read_buffer {
+            self.rxbuffer.replace(read_buffer);
+        }
+        let result = read_write_status.and_then(|_| self.step(self.state.get()));
+        if let Err(e) = result {
+            self.finish(Err(e));
+        }
+    }
+}
+
+impl<'a, S: hil::spi::SpiMasterDevice<'a>, A: hil::time::Alarm<'a>> hil::time::AlarmClient
+    for SpiNor<'a, S, A>
+{
+    fn alarm(&self) {
+        // Check whether the erase or program finished.
+        if let Err(e) = self.poll_status(self.state.get()) {
+            self.finish(Err(e));
+        }
+    }
+}
+
+impl<
+        'a,
+        S: hil::spi::SpiMasterDevice<'a>,
+        A: hil::time::Alarm<'a>,
+        C: hil::flash::Client<Self>,
+    > hil::flash::HasClient<'a, C> for SpiNor<'a, S, A>
+{
+    fn set_client(&self, client: &'a C) {
+        self.client.set(client);
+    }
+}
+
+impl<'a, S: hil::spi::SpiMasterDevice<'a>, A: hil::time::Alarm<'a>> hil::flash::Flash
+    for SpiNor<'a, S, A>
+{
+    type Page = SpiNorSector;
+
+    fn read_page(
+        &self,
+        page_number: usize,
+        buf: &'static mut Self::Page,
+    ) -> Result<(), (ErrorCode, &'static mut Self::Page)> {
+        if let Err(e) = self.start(Operation::Read, page_number) {
+            return Err((e, buf));
+        }
+        match self.read_chunk(0) {
+            Ok(()) => {
+                self.client_sector.replace(buf);
+                Ok(())
+            }
+            Err(e) => Err((e, buf)),
+        }
+    }
+
+    fn write_page(
+        &self,
+        page_number: usize,
+        buf: &'static mut Self::Page,
+    ) -> Result<(), (ErrorCode, &'static mut Self::Page)> {
+        if let Err(e) = self.start(Operation::Write, page_number) {
+            return Err((e, buf));
+        }
+        match self.erase_sector() {
+            Ok(()) => {
+                self.client_sector.replace(buf);
+                Ok(())
+            }
+            Err(e) => Err((e, buf)),
+        }
+    }
+
+    fn erase_page(&self, page_number: usize) -> Result<(), ErrorCode> {
+        self.start(Operation::Erase, page_number)?;
+        self.erase_sector()
+    }
+
+    fn geometry(&self) -> Option<hil::nonvolatile_storage::StorageGeometry> {
+        self.info
+            .get()
+            .map(|info| hil::nonvolatile_storage::StorageGeometry {
+                erase_block_size: SECTOR_SIZE,
+                write_granularity: 1,
+                total_size: info.size / SECTOR_SIZE * SECTOR_SIZE,
+            })
+    }
+}
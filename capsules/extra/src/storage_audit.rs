@@ -0,0 +1,257 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2024.
+
+//! Report every read and write of nonvolatile storage to an auditor.
+//!
+//! `StorageAudit` sits between the storage and the nonvolatile storage
+//! driver, for security review builds. Once the storage finishes a read or
+//! write, the layer tells the `StorageAuditor` where it was, how long it was,
+//! its result, and which app it was for, as the driver reports through
+//! `StorageUsers`. The auditor can, for example, record accesses in the event
+//! journal or report them to a monitoring app. Operations the storage
+//! refuses are not reported, and syncs pass through unchanged.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust,ignore
+//! let audit = components::storage_audit::StorageAuditComponent::new(nv_to_page, auditor)
+//!     .finalize(components::storage_audit_component_static!());
+//!
+//! // The nonvolatile storage driver then uses `audit` as its storage, and
+//! // the layer is given the driver with `set_users()`.
+//! ```
+
+use kernel::hil::nonvolatile_storage::{
+    NonvolatileStorage, NonvolatileStorageClient, ReadStatus, StorageGeometry,
+};
+use kernel::utilities::cells::OptionalCell;
+use kernel::{ErrorCode, ProcessId};
+
+use crate::nonvolatile_storage_driver::StorageUsers;
+
+/// A finished read or write, as reported to a `StorageAuditor`.
+#[derive(Clone, Copy, Debug)]
+pub struct StorageAccess {
+    /// The app that made the access, or `None` for the kernel.
+    pub processid: Option<ProcessId>,
+    pub write: bool,
+    /// Absolute storage address of the access.
+    pub address: usize,
+    /// Number of bytes requested.
+    pub length: usize,
+    pub result: Result<(), ErrorCode>,
+}
+
+/// Observer of storage accesses.
+pub trait StorageAuditor {
+    fn accessed(&self, access: StorageAccess);
+}
+
+pub struct StorageAudit<'a> {
+    storage: &'a dyn NonvolatileStorage<'a>,
+    client: OptionalCell<&'a dyn NonvolatileStorageClient>,
+    auditor: &'a dyn StorageAuditor,
+    users: OptionalCell<&'a dyn StorageUsers>,
+    // Whether the read or write the storage is working on is a write, where
+    // it starts and how long it is.
+    access: OptionalCell<(bool, usize, usize)>,
+}
+
+impl<'a> StorageAudit<'a> {
+    pub fn new(storage: &'a dyn NonvolatileStorage<'a>, auditor: &'a dyn StorageAuditor) -> Self {
+        Self {
+            storage,
+            client: OptionalCell::empty(),
+            auditor,
+            users: OptionalCell::empty(),
+            access: OptionalCell::empty(),
+        }
+    }
+
+    /// Learn which app each access is for from `users`, usually the
+    /// nonvolatile storage driver above this layer. Without it, every access
+    /// is reported as the kernel's.
+    pub fn set_users(&self, users: &'a dyn StorageUsers) {
+        self.users.set(users);
+    }
+
+    // Report the access the storage finished.
+    fn report(&self, result: Result<(), ErrorCode>) {
+        if let Some((write, address, length)) = self.access.take() {
+            self.auditor.accessed(StorageAccess {
+                processid: self.users.and_then(|users| users.current_app()),
+                write,
+                address,
+                length,
+                result,
+            });
+        }
+    }
+}
+
+impl<'a> NonvolatileStorage<'a> for StorageAudit<'a> {
+    fn set_client(&self, client: &'a dyn NonvolatileStorageClient) {
+        self.client.set(client);
+    }
+
+    fn read(
+        &self,
+        buffer: &'static mut [u8],
+        address: usize,
+        length: usize,
+    ) -> Result<(), ErrorCode> {
+        self.storage.read(buffer, address, length)?;
+        self.access.set((false, address, length));
+        Ok(())
+    }
+
+    fn write(
+        &self,
+        buffer: &'static mut [u8],
+        address: usize,
+        length: usize,
+    ) -> Result<(), ErrorCode> {
+        self.storage.write(buffer, address, length)?;
+        self.access.set((true, address, length));
+        Ok(())
+    }
+
+    fn sync(&self) -> Result<(), ErrorCode> {
+        self.storage.sync()
+    }
+
+    fn geometry(&self) -> Option<StorageGeometry> {
+        self.storage.geometry()
+    }
+
+    fn reset(&self) -> Result<(), ErrorCode> {
+        self.storage.reset()
+    }
+
+    fn read_mapped(&self, address: usize, buffer: &mut [u8]) -> Result<(), ErrorCode> {
+        self.storage.read_mapped(address, buffer)
+    }
+
+    fn write_now(&self, address: usize, data: &[u8]) -> Result<(), ErrorCode> {
+        self.storage.write_now(address, data)
+    }
+}
+
+impl NonvolatileStorageClient for StorageAudit<'_> {
+    fn read_done(&self, buffer: &'static mut [u8], length: usize) {
+        self.read_done_status(buffer, length, ReadStatus::Ok);
+    }
+
+    fn read_done_status(&self, buffer: &'static mut [u8], length: usize, status: ReadStatus) {
+        self.report(match status {
+            ReadStatus::Ok | ReadStatus::Corrected => Ok(()),
+            ReadStatus::Transient => Err(ErrorCode::BUSY),
+            ReadStatus::Uncorrectable => Err(ErrorCode::FAIL),
+        });
+        self.client
+            .map(move |client| client.read_done_status(buffer, length, status));
+    }
+
+    fn write_done(&self, buffer: &'static mut [u8], length: usize) {
+        self.write_done_result(buffer, length, Ok(()));
+    }
+
+    fn write_done_result(
+        &self,
+        buffer: &'static mut [u8],
+        length: usize,
+        result: Result<(), ErrorCode>,
+    ) {
+        self.report(result);
+        self.client
+            .map(move |client| client.write_done_result(buffer, length, result));
+    }
+
+    fn sync_done(&self, result: Result<(), ErrorCode>) {
+        self.client.map(|client| client.sync_done(result));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use core::cell::RefCell;
+    use std::boxed::Box;
+    use std::vec::Vec;
+
+    use kernel::hil::nonvolatile_storage::{NonvolatileStorage, ReadStatus};
+    use kernel::ErrorCode;
+
+    use super::{StorageAccess, StorageAudit, StorageAuditor};
+    use crate::test::mock_storage::{buffer, Done, MockClient, MockStorage};
+
+    struct Auditor {
+        accesses: RefCell<Vec<(bool, usize, usize, Result<(), ErrorCode>)>>,
+    }
+
+    impl StorageAuditor for Auditor {
+        fn accessed(&self, access: StorageAccess) {
+            assert!(access.processid.is_none());
+            self.accesses.borrow_mut().push((
+                access.write,
+                access.address,
+                access.length,
+                access.result,
+            ));
+        }
+    }
+
+    fn stack() -> (
+        &'static MockStorage<'static>,
+        &'static StorageAudit<'static>,
+        &'static Auditor,
+        &'static MockClient,
+    ) {
+        let storage = Box::leak(Box::new(MockStorage::new()));
+        let auditor = Box::leak(Box::new(Auditor {
+            accesses: RefCell::new(Vec::new()),
+        }));
+        let audit = Box::leak(Box::new(StorageAudit::new(storage, auditor)));
+        let client = Box::leak(Box::new(MockClient::new()));
+        storage.set_client(audit);
+        audit.set_client(client);
+        (storage, audit, auditor, client)
+    }
+
+    #[test]
+    fn finished_accesses_are_reported() {
+        let (storage, audit, auditor, client) = stack();
+        audit.write(buffer(8), 0x20, 8).unwrap();
+        storage.complete();
+        audit.read(buffer(4), 0x40, 4).unwrap();
+        storage.complete_read(ReadStatus::Uncorrectable);
+        assert_eq!(
+            auditor.accesses.take(),
+            [
+                (true, 0x20, 8, Ok(())),
+                (false, 0x40, 4, Err(ErrorCode::FAIL)),
+            ]
+        );
+        assert_eq!(
+            client.take(),
+            [
+                Done::Write(8, Ok(())),
+                Done::Read(4, ReadStatus::Uncorrectable),
+            ]
+        );
+    }
+
+    #[test]
+    fn refused_accesses_and_syncs_are_not_reported() {
+        let (storage, audit, auditor, client) = stack();
+        storage.refuse_next(ErrorCode::BUSY);
+        assert_eq!(audit.write(buffer(4), 0, 4), Err(ErrorCode::BUSY));
+        audit.sync().unwrap();
+        storage.complete();
+        assert!(auditor.accesses.take().is_empty());
+        assert_eq!(client.take(), [Done::Sync(Ok(()))]);
+    }
+}
@@ -0,0 +1,235 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2024.
+
+//! Copy storage regions to a backup area on a second storage device.
+//!
+//! The board lists the regions to back up, such as the userspace or
+//! provisioned region of the nonvolatile storage driver, and where in the
+//! destination storage each copy goes. A backup reads each region in chunks
+//! of the buffer's size and writes them to the destination, reporting
+//! progress after every chunk. Once everything is copied the destination is
+//! synced, if it supports it.
+//!
+//! The source is usually a window from `MuxNonvolatileStorage` covering the
+//! regions, so the backup can run alongside the other users of the storage.
+//! Apps may write a region while it is copied, in which case the copy mixes
+//! old and new data.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust,ignore
+//! let backup = components::storage_backup::StorageBackupComponent::new(
+//!     storage_window,
+//!     backup_flash,
+//!     &[
+//!         BackupRegion { name: "apps", source_address: 0x60000, length: 0x20000, destination_address: 0 },
+//!     ],
+//! )
+//! .finalize(components::storage_backup_component_static!());
+//!
+//! let backup_cap = create_capability!(capabilities::StorageBackupCapability);
+//! components::process_console_backup::BackupCommandComponent::new(pconsole.engine(), backup, &backup_cap)
+//!     .finalize(components::backup_command_component_static!());
+//! ```
+
+use core::cell::Cell;
+use core::cmp;
+
+use kernel::hil::nonvolatile_storage::{
+    NonvolatileStorage, NonvolatileStorageClient, StorageBackup, StorageBackupClient,
+};
+use kernel::utilities::cells::{OptionalCell, TakeCell};
+use kernel::ErrorCode;
+
+/// Default length of the buffer chunks are copied through.
+pub const BUF_LEN: usize = 512;
+
+/// A region of the source storage and where its copy goes.
+#[derive(Clone, Copy, Debug)]
+pub struct BackupRegion {
+    /// Name used to pick the region, for example from the process console.
+    pub name: &'static str,
+    /// Absolute address of the region in the source storage.
+    pub source_address: usize,
+    /// Length of the region in bytes.
+    pub length: usize,
+    /// Absolute address of the copy in the destination storage.
+    pub destination_address: usize,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum State {
+    Idle,
+    Reading,
+    Writing,
+    Syncing,
+}
+
+pub struct RegionBackup<'a> {
+    source: &'a dyn NonvolatileStorage<'a>,
+    destination: &'a dyn NonvolatileStorage<'a>,
+    regions: &'a [BackupRegion],
+    buffer: TakeCell<'static, [u8]>,
+    client: OptionalCell<&'a dyn StorageBackupClient>,
+    state: Cell<State>,
+    // Region being copied, and the last region of the backup.
+    region: Cell<usize>,
+    last_region: Cell<usize>,
+    // How much of the current region is copied, and the length of the chunk
+    // being copied.
+    offset: Cell<usize>,
+    chunk: Cell<usize>,
+    copied: Cell<usize>,
+    total: Cell<usize>,
+}
+
+impl<'a> RegionBackup<'a> {
+    /// Copy `regions` from `source` to `destination`, through `buffer`. The
+    /// backup must be the client of both storages.
+    pub fn new(
+        source: &'a dyn NonvolatileStorage<'a>,
+        destination: &'a dyn NonvolatileStorage<'a>,
+        regions: &'a [BackupRegion],
+        buffer: &'static mut [u8],
+    ) -> RegionBackup<'a> {
+        RegionBackup {
+            source,
+            destination,
+            regions,
+            buffer: TakeCell::new(buffer),
+            client: OptionalCell::empty(),
+            state: Cell::new(State::Idle),
+            region: Cell::new(0),
+            last_region: Cell::new(0),
+            offset: Cell::new(0),
+            chunk: Cell::new(0),
+            copied: Cell::new(0),
+            total: Cell::new(0),
+        }
+    }
+
+    // Read the next chunk of the current region, moving on to the next region
+    // once it is copied. Syncs the destination after the last region.
+    fn copy_next(&self) -> Result<(), ErrorCode> {
+        let mut region = self.regions[self.region.get()];
+        while self.offset.get() >= region.length {
+            if self.region.get() == self.last_region.get() {
+                return self.sync();
+            }
+            self.region.set(self.region.get() + 1);
+            self.offset.set(0);
+            region = self.regions[self.region.get()];
+        }
+
+        let buffer = self.buffer.take().ok_or(ErrorCode::NOMEM)?;
+        let chunk = cmp::min(buffer.len(), region.length - self.offset.get());
+        self.chunk.set(chunk);
+        self.state.set(State::Reading);
+        self.source
+            .read(buffer, region.source_address + self.offset.get(), chunk)
+    }
+
+    fn sync(&self) -> Result<(), ErrorCode> {
+        self.state.set(State::Syncing);
+        match self.destination.sync() {
+            Err(ErrorCode::NOSUPPORT) => {
+                self.finish(Ok(()));
+                Ok(())
+            }
+            result => result,
+        }
+    }
+
+    fn finish(&self, result: Result<(), ErrorCode>) {
+        self.state.set(State::Idle);
+        self.client
+            .map(|client| client.backup_done(result, self.copied.get()));
+    }
+
+    // Go on with the backup, or report why it stopped.
+    fn continue_backup(&self) {
+        if let Err(e) = self.copy_next() {
+            self.finish(Err(e));
+        }
+    }
+}
+
+impl<'a> StorageBackup<'a> for RegionBackup<'a> {
+    fn set_client(&self, client: &'a dyn StorageBackupClient) {
+        self.client.set(client);
+    }
+
+    fn region_name(&self, index: usize) -> Option<&'static str> {
+        self.regions.get(index).map(|region| region.name)
+    }
+
+    fn backup(&self, index: Option<usize>) -> Result<(), ErrorCode> {
+        if self.state.get() != State::Idle {
+            return Err(ErrorCode::BUSY);
+        }
+        let (first, last) = match index {
+            Some(index) if index < self.regions.len() => (index, index),
+            None if !self.regions.is_empty() => (0, self.regions.len() - 1),
+            _ => return Err(ErrorCode::INVAL),
+        };
+        let total = self.regions[first..=last]
+            .iter()
+            .map(|region| region.length)
+            .sum();
+        if total == 0 {
+            return Err(ErrorCode::INVAL);
+        }
+        self.region.set(first);
+        self.last_region.set(last);
+        self.offset.set(0);
+        self.copied.set(0);
+        self.total.set(total);
+        self.copy_next()
+            .inspect_err(|_| self.state.set(State::Idle))
+    }
+}
+
+impl NonvolatileStorageClient for RegionBackup<'_> {
+    fn read_done(&self, buffer: &'static mut [u8], length: usize) {
+        if self.state.get() != State::Reading {
+            self.buffer.replace(buffer);
+            return;
+        }
+        if length != self.chunk.get() {
+            self.buffer.replace(buffer);
+            return self.finish(Err(ErrorCode::FAIL));
+        }
+        let region = self.regions[self.region.get()];
+        self.state.set(State::Writing);
+        if let Err(e) = self.destination.write(
+            buffer,
+            region.destination_address + self.offset.get(),
+            length,
+        ) {
+            self.finish(Err(e));
+        }
+    }
+
+    fn write_done(&self, buffer: &'static mut [u8], length: usize) {
+        self.buffer.replace(buffer);
+        if self.state.get() != State::Writing {
+            return;
+        }
+        if length != self.chunk.get() {
+            return self.finish(Err(ErrorCode::FAIL));
+        }
+        self.offset.set(self.offset.get() + length);
+        self.copied.set(self.copied.get() + length);
+        self.client
+            .map(|client| client.backup_progress(self.copied.get(), self.total.get()));
+        self.continue_backup();
+    }
+
+    fn sync_done(&self, result: Result<(), ErrorCode>) {
+        if self.state.get() == State::Syncing {
+            self.finish(result);
+        }
+    }
+}
@@ -0,0 +1,419 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2024.
+
+//! Writes several segments of an app's storage with one command.
+//!
+//! Apps that keep records in several places, like a log and its index, can
+//! describe all the writes in one allowed buffer, so they need a single
+//! command and a single upcall. Each segment is a `BATCH_HEADER_LEN` byte
+//! header, holding the offset and the length of the data as little-endian
+//! `u32`s, followed by the data. Nothing is written unless every segment is
+//! valid. The segments are written in order through an `AppStorage`, usually
+//! the nonvolatile storage driver, which checks the app's permissions and
+//! queues each write with those of other apps.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust,ignore
+//! let batch_writes = components::storage_batch_writes::StorageBatchWritesComponent::new(
+//!     board_kernel,
+//!     capsules_extra::storage_batch_writes::DRIVER_NUM,
+//!     nonvolatile_storage,
+//!     4, // Write granularity of the storage below the driver.
+//! )
+//! .finalize(components::storage_batch_writes_component_static!());
+//! ```
+
+use core::cell::Cell;
+use core::cmp;
+
+use kernel::grant::{AllowRoCount, AllowRwCount, Grant, GrantKernelData, UpcallCount};
+use kernel::hil::app_storage::{AppStorage, AppStorageClient};
+use kernel::processbuffer::{ReadableProcessBuffer, ReadableProcessSlice};
+use kernel::syscall::{CommandReturn, SyscallDriver};
+use kernel::utilities::cells::{OptionalCell, TakeCell};
+use kernel::{ErrorCode, ProcessId};
+
+/// Syscall driver number.
+use capsules_core::driver;
+pub const DRIVER_NUM: usize = driver::NUM::NvmStorageBatch as usize;
+
+/// Length of the header of each segment.
+pub const BATCH_HEADER_LEN: usize = 8;
+
+/// Ids for subscribe upcalls.
+mod upcall {
+    /// The batch was written, or failed.
+    pub const WRITE_DONE: usize = 0;
+    /// Number of upcalls.
+    pub const COUNT: u8 = 1;
+}
+
+/// Ids for read-only allow buffers.
+mod ro_allow {
+    /// The segments to write.
+    pub const WRITE: usize = 0;
+    /// The number of allow buffers the kernel stores for this grant.
+    pub const COUNT: u8 = 1;
+}
+
+#[derive(Default)]
+pub struct App {}
+
+/// What a segment may be: its data must fit in the app's storage and the
+/// buffer it is copied into, and be aligned to the write granularity.
+#[derive(Clone, Copy)]
+struct SegmentLimits {
+    size: usize,
+    max_length: usize,
+    write_granularity: usize,
+}
+
+impl SegmentLimits {
+    fn check(&self, offset: usize, length: usize) -> Result<(), ErrorCode> {
+        let granularity = cmp::max(self.write_granularity, 1);
+        let fits = offset
+            .checked_add(length)
+            .map_or(false, |end| end <= self.size);
+        if length == 0
+            || length > self.max_length
+            || !fits
+            || offset % granularity != 0
+            || length % granularity != 0
+        {
+            return Err(ErrorCode::INVAL);
+        }
+        Ok(())
+    }
+}
+
+// Read the segment header at `position` of a batch.
+fn batch_segment(app_buffer: &ReadableProcessSlice, position: usize) -> Option<(usize, usize)> {
+    let mut header = [0; BATCH_HEADER_LEN];
+    app_buffer
+        .get(position..position.checked_add(BATCH_HEADER_LEN)?)?
+        .copy_to_slice_or_err(&mut header)
+        .ok()?;
+    let offset = u32::from_le_bytes([header[0], header[1], header[2], header[3]]);
+    let length = u32::from_le_bytes([header[4], header[5], header[6], header[7]]);
+    Some((offset as usize, length as usize))
+}
+
+// End of the data of a segment of `length` bytes whose header is at
+// `position`, if it is within the first `end` bytes of the batch.
+fn batch_data_end(position: usize, length: usize, end: usize) -> Result<usize, ErrorCode> {
+    position
+        .checked_add(BATCH_HEADER_LEN)
+        .and_then(|data| data.checked_add(length))
+        .filter(|&data_end| data_end <= end)
+        .ok_or(ErrorCode::INVAL)
+}
+
+// Check every segment in the first `end` bytes of `app_buffer`.
+fn check_batch(
+    app_buffer: &ReadableProcessSlice,
+    end: usize,
+    limits: SegmentLimits,
+) -> Result<(), ErrorCode> {
+    if end == 0 {
+        return Err(ErrorCode::INVAL);
+    }
+    let mut position = 0;
+    while position < end {
+        let (offset, length) = batch_segment(app_buffer, position).ok_or(ErrorCode::INVAL)?;
+        let data_end = batch_data_end(position, length, end)?;
+        limits.check(offset, length)?;
+        position = data_end;
+    }
+    Ok(())
+}
+
+pub struct StorageBatchWrites<'a> {
+    storage: &'a dyn AppStorage<'a>,
+    apps: Grant<
+        App,
+        UpcallCount<{ upcall::COUNT }>,
+        AllowRoCount<{ ro_allow::COUNT }>,
+        AllowRwCount<0>,
+    >,
+    // Holds the data of the segment being written.
+    buffer: TakeCell<'static, [u8]>,
+    buffer_len: usize,
+    write_granularity: usize,
+    // The app whose batch is being written.
+    current: OptionalCell<ProcessId>,
+    // Position of the next segment header in the allowed buffer.
+    position: Cell<usize>,
+    // End of the batch in the allowed buffer.
+    end: Cell<usize>,
+    // Bytes of data written so far.
+    written: Cell<usize>,
+}
+
+impl<'a> StorageBatchWrites<'a> {
+    /// `write_granularity` is that of the storage below `storage`, so bad
+    /// segments are refused before anything is written.
+    pub fn new(
+        storage: &'a dyn AppStorage<'a>,
+        grant: Grant<
+            App,
+            UpcallCount<{ upcall::COUNT }>,
+            AllowRoCount<{ ro_allow::COUNT }>,
+            AllowRwCount<0>,
+        >,
+        buffer: &'static mut [u8],
+        write_granularity: usize,
+    ) -> Self {
+        Self {
+            storage,
+            apps: grant,
+            buffer_len: buffer.len(),
+            buffer: TakeCell::new(buffer),
+            write_granularity,
+            current: OptionalCell::empty(),
+            position: Cell::new(0),
+            end: Cell::new(0),
+            written: Cell::new(0),
+        }
+    }
+
+    fn limits(&self, processid: ProcessId) -> Result<SegmentLimits, ErrorCode> {
+        Ok(SegmentLimits {
+            size: self.storage.size(processid)?,
+            max_length: self.buffer_len,
+            write_granularity: self.write_granularity,
+        })
+    }
+
+    // Check the first `end` bytes of the allowed buffer of `processid` and
+    // start writing them.
+    fn start_batch(&self, end: usize, processid: ProcessId) -> Result<(), ErrorCode> {
+        if self.current.is_some() {
+            return Err(ErrorCode::BUSY);
+        }
+        let limits = self.limits(processid)?;
+        self.apps
+            .enter(processid, |_app, kernel_data| {
+                kernel_data
+                    .get_readonly_processbuffer(ro_allow::WRITE)
+                    .and_then(|write| {
+                        write.enter(|app_buffer| check_batch(app_buffer, end, limits))
+                    })
+                    .unwrap_or(Err(ErrorCode::RESERVE))?;
+                self.current.set(processid);
+                self.position.set(0);
+                self.end.set(end);
+                self.written.set(0);
+                self.write_segment(processid, kernel_data, limits)
+                    .inspect_err(|_| self.current.clear())
+            })
+            .unwrap_or_else(|err| Err(err.into()))
+    }
+
+    // Write the next segment of the batch in progress.
+    fn write_segment(
+        &self,
+        processid: ProcessId,
+        kernel_data: &GrantKernelData,
+        limits: SegmentLimits,
+    ) -> Result<(), ErrorCode> {
+        let position = self.position.get();
+        let buffer = self.buffer.take().ok_or(ErrorCode::BUSY)?;
+        // The app may have changed the allowed buffer since the batch was
+        // checked, so the segment is checked again.
+        let segment = kernel_data
+            .get_readonly_processbuffer(ro_allow::WRITE)
+            .map_err(ErrorCode::from)
+            .and_then(|write| {
+                write
+                    .enter(|app_buffer| {
+                        let (offset, length) =
+                            batch_segment(app_buffer, position).ok_or(ErrorCode::INVAL)?;
+                        let data_end = batch_data_end(position, length, self.end.get())?;
+                        limits.check(offset, length)?;
+                        app_buffer
+                            .get(data_end - length..data_end)
+                            .zip(buffer.get_mut(..length))
+                            .ok_or(ErrorCode::INVAL)
+                            .and_then(|(app_data, data)| app_data.copy_to_slice_or_err(data))?;
+                        Ok((offset, length, data_end))
+                    })
+                    .unwrap_or_else(|err| Err(err.into()))
+            });
+        let (offset, length, data_end) = match segment {
+            Ok(segment) => segment,
+            Err(e) => {
+                self.buffer.replace(buffer);
+                return Err(e);
+            }
+        };
+        self.position.set(data_end);
+        self.storage
+            .write(processid, buffer, offset, length)
+            .map_err(|(e, buffer)| {
+                self.buffer.replace(buffer);
+                e
+            })
+    }
+
+    // The batch of `processid` finished, or failed with `result`.
+    fn finish(&self, processid: ProcessId, result: Result<(), ErrorCode>) {
+        self.current.clear();
+        let written = self.written.get();
+        let _ = self.apps.enter(processid, |_app, kernel_data| {
+            let upcall_args = match result {
+                Ok(()) => (written, 0, 0),
+                Err(e) => (written, kernel::errorcode::into_statuscode(Err(e)), 0),
+            };
+            kernel_data
+                .schedule_upcall(upcall::WRITE_DONE, upcall_args)
+                .ok();
+        });
+    }
+}
+
+impl AppStorageClient for StorageBatchWrites<'_> {
+    fn init_done(&self, _processid: ProcessId, _result: Result<(), ErrorCode>) {}
+
+    fn read_done(
+        &self,
+        _processid: ProcessId,
+        buffer: &'static mut [u8],
+        _length: usize,
+        _result: Result<(), ErrorCode>,
+    ) {
+        self.buffer.replace(buffer);
+    }
+
+    fn write_done(
+        &self,
+        processid: ProcessId,
+        buffer: &'static mut [u8],
+        length: usize,
+        result: Result<(), ErrorCode>,
+    ) {
+        self.buffer.replace(buffer);
+        if self.current.get() != Some(processid) {
+            return;
+        }
+        if let Err(e) = result {
+            self.finish(processid, Err(e));
+            return;
+        }
+        self.written.set(self.written.get() + length);
+        if self.position.get() >= self.end.get() {
+            self.finish(processid, Ok(()));
+            return;
+        }
+        let next = self.limits(processid).and_then(|limits| {
+            self.apps
+                .enter(processid, |_app, kernel_data| {
+                    self.write_segment(processid, kernel_data, limits)
+                })
+                .unwrap_or_else(|err| Err(err.into()))
+        });
+        if let Err(e) = next {
+            self.finish(processid, Err(e));
+        }
+    }
+}
+
+impl SyscallDriver for StorageBatchWrites<'_> {
+    /// Write batches.
+    ///
+    /// ### `command_num`
+    ///
+    /// - `0`: Driver check.
+    /// - `1`: Write the segments described in the first bytes of the allowed
+    ///   write buffer, up to the length given as the first argument. The
+    ///   offsets are in the app's storage, and the offset and length of each
+    ///   segment must be multiples of the write granularity. Returns `INVAL`
+    ///   if any segment is malformed, does not fit, or is longer than the
+    ///   capsule's buffer, and `BUSY` while a batch is written. The upcall
+    ///   fires once, with the number of bytes written and the status.
+    fn command(
+        &self,
+        command_num: usize,
+        end: usize,
+        _: usize,
+        processid: ProcessId,
+    ) -> CommandReturn {
+        match command_num {
+            0 => CommandReturn::success(),
+
+            1 => match self.start_batch(end, processid) {
+                Ok(()) => CommandReturn::success(),
+                Err(e) => CommandReturn::failure(e),
+            },
+
+            _ => CommandReturn::failure(ErrorCode::NOSUPPORT),
+        }
+    }
+
+    fn allocate_grant(&self, processid: ProcessId) -> Result<(), kernel::process::Error> {
+        self.apps.enter(processid, |_, _| {})
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use std::vec::Vec;
+
+    use kernel::processbuffer::ReadableProcessSlice;
+    use kernel::ErrorCode;
+
+    use super::{check_batch, SegmentLimits};
+
+    const LIMITS: SegmentLimits = SegmentLimits {
+        size: 0x100,
+        max_length: 0x20,
+        write_granularity: 4,
+    };
+
+    fn batch(segments: &[(u32, &[u8])]) -> Vec<u8> {
+        let mut batch = Vec::new();
+        for (offset, data) in segments {
+            batch.extend_from_slice(&offset.to_le_bytes());
+            batch.extend_from_slice(&(data.len() as u32).to_le_bytes());
+            batch.extend_from_slice(data);
+        }
+        batch
+    }
+
+    fn check(batch: &[u8], end: usize) -> Result<(), ErrorCode> {
+        check_batch(<&ReadableProcessSlice>::from(batch), end, LIMITS)
+    }
+
+    #[test]
+    fn valid_batches_are_accepted() {
+        let batch = batch(&[(0, &[1; 8]), (0xf0, &[2; 0x10])]);
+        assert_eq!(check(&batch, batch.len()), Ok(()));
+        // Only the segments before the end count.
+        assert_eq!(check(&batch, 16), Ok(()));
+    }
+
+    #[test]
+    fn segments_that_do_not_fit_are_refused() {
+        let past_end = batch(&[(0, &[1; 8]), (0xf8, &[2; 0x10])]);
+        assert_eq!(check(&past_end, past_end.len()), Err(ErrorCode::INVAL));
+        let too_long = batch(&[(0, &[1; 0x24])]);
+        assert_eq!(check(&too_long, too_long.len()), Err(ErrorCode::INVAL));
+        let unaligned = batch(&[(2, &[1; 8])]);
+        assert_eq!(check(&unaligned, unaligned.len()), Err(ErrorCode::INVAL));
+        let empty = batch(&[(0, &[])]);
+        assert_eq!(check(&empty, empty.len()), Err(ErrorCode::INVAL));
+    }
+
+    #[test]
+    fn truncated_batches_are_refused() {
+        let batch = batch(&[(0, &[1; 8])]);
+        assert_eq!(check(&batch, batch.len() - 1), Err(ErrorCode::INVAL));
+        assert_eq!(check(&batch, 4), Err(ErrorCode::INVAL));
+        assert_eq!(check(&batch, batch.len() + 8), Err(ErrorCode::INVAL));
+        assert_eq!(check(&batch, 0), Err(ErrorCode::INVAL));
+    }
+}
@@ -0,0 +1,323 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2024.
+
+//! Warns apps that fill the userspace region of nonvolatile storage, like
+//! data loggers, before it runs out.
+//!
+//! `StorageLowSpace` sits between the storage and the nonvolatile storage
+//! driver, and tracks a high-water mark: the number of bytes of the
+//! userspace region before the end of the furthest successful write since
+//! boot. Apps subscribe with a threshold, and get an upcall once the mark
+//! reaches it, so they can upload or rotate their data before writes start
+//! failing. The layer learns the userspace region from the driver through
+//! `StorageUsers`.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust,ignore
+//! let low_space = components::storage_low_space::StorageLowSpaceComponent::new(
+//!     board_kernel,
+//!     capsules_extra::storage_low_space::DRIVER_NUM,
+//!     nv_to_page,
+//! )
+//! .finalize(components::storage_low_space_component_static!());
+//!
+//! // The nonvolatile storage driver then uses `low_space` as its storage,
+//! // and the layer is given the driver with `set_users()`.
+//! ```
+
+use core::cell::Cell;
+use core::cmp;
+
+use kernel::grant::{AllowRoCount, AllowRwCount, Grant, GrantKernelData, UpcallCount};
+use kernel::hil::nonvolatile_storage::{
+    NonvolatileStorage, NonvolatileStorageClient, ReadStatus, StorageGeometry, StorageRange,
+};
+use kernel::syscall::{CommandReturn, SyscallDriver};
+use kernel::utilities::cells::OptionalCell;
+use kernel::{ErrorCode, ProcessId};
+
+use crate::nonvolatile_storage_driver::StorageUsers;
+
+/// Syscall driver number.
+use capsules_core::driver;
+pub const DRIVER_NUM: usize = driver::NUM::NvmStorageLowSpace as usize;
+
+/// Ids for subscribe upcalls.
+mod upcall {
+    /// The high-water mark reached the app's threshold.
+    pub const LOW_SPACE: usize = 0;
+    /// Number of upcalls.
+    pub const COUNT: u8 = 1;
+}
+
+#[derive(Default)]
+pub struct App {
+    // Send the low space upcall once the high-water mark reaches this many
+    // bytes.
+    threshold: Option<usize>,
+}
+
+/// The high-water mark after a write of `length` bytes at `address`, if the
+/// write reaches further into `userspace` than `high_water`.
+fn raised_high_water(
+    userspace: StorageRange,
+    high_water: usize,
+    address: usize,
+    length: usize,
+) -> Option<usize> {
+    if !userspace.overlaps(address, length) {
+        return None;
+    }
+    let end = userspace.end()?;
+    let mark = cmp::min(address.checked_add(length)?, end) - userspace.start;
+    if mark > high_water {
+        Some(mark)
+    } else {
+        None
+    }
+}
+
+pub struct StorageLowSpace<'a> {
+    storage: &'a dyn NonvolatileStorage<'a>,
+    client: OptionalCell<&'a dyn NonvolatileStorageClient>,
+    users: OptionalCell<&'a dyn StorageUsers>,
+    apps: Grant<App, UpcallCount<{ upcall::COUNT }>, AllowRoCount<0>, AllowRwCount<0>>,
+    // Bytes of the userspace region before the end of the furthest write.
+    high_water: Cell<usize>,
+    // Where the write the storage is working on starts.
+    write: OptionalCell<usize>,
+}
+
+impl<'a> StorageLowSpace<'a> {
+    pub fn new(
+        storage: &'a dyn NonvolatileStorage<'a>,
+        grant: Grant<App, UpcallCount<{ upcall::COUNT }>, AllowRoCount<0>, AllowRwCount<0>>,
+    ) -> Self {
+        Self {
+            storage,
+            client: OptionalCell::empty(),
+            users: OptionalCell::empty(),
+            apps: grant,
+            high_water: Cell::new(0),
+            write: OptionalCell::empty(),
+        }
+    }
+
+    /// Learn the userspace region from `users`, usually the nonvolatile
+    /// storage driver above this layer. Without it, the mark never rises.
+    pub fn set_users(&self, users: &'a dyn StorageUsers) {
+        self.users.set(users);
+    }
+
+    /// Bytes of the userspace region before the end of the furthest write
+    /// since boot.
+    pub fn high_water(&self) -> usize {
+        self.high_water.get()
+    }
+
+    // Send the app the low space upcall once the high-water mark reaches
+    // `threshold` bytes.
+    fn set_threshold(&self, threshold: usize, processid: ProcessId) -> Result<(), ErrorCode> {
+        let length = self.users.map_or(0, |users| users.userspace_range().length);
+        if threshold > length {
+            return Err(ErrorCode::INVAL);
+        }
+        self.apps.enter(processid, |app, kernel_data| {
+            let high_water = self.high_water.get();
+            if threshold == 0 {
+                app.threshold = None;
+            } else if high_water >= threshold {
+                app.threshold = None;
+                Self::notify(kernel_data, high_water, threshold);
+            } else {
+                app.threshold = Some(threshold);
+            }
+        })?;
+        Ok(())
+    }
+
+    // Raise the high-water mark after a successful write of `length` bytes at
+    // `address`, and notify apps whose threshold it reached.
+    fn update_high_water(&self, address: usize, length: usize) {
+        let userspace = match self.users.get() {
+            Some(users) => users.userspace_range(),
+            None => return,
+        };
+        let high_water = match raised_high_water(userspace, self.high_water.get(), address, length)
+        {
+            Some(high_water) => high_water,
+            None => return,
+        };
+        self.high_water.set(high_water);
+        self.apps.each(|_, app, kernel_data| {
+            if let Some(threshold) = app.threshold {
+                if high_water >= threshold {
+                    app.threshold = None;
+                    Self::notify(kernel_data, high_water, threshold);
+                }
+            }
+        });
+    }
+
+    fn notify(kernel_data: &GrantKernelData, high_water: usize, threshold: usize) {
+        let _ = kernel_data.schedule_upcall(upcall::LOW_SPACE, (high_water, threshold, 0));
+    }
+}
+
+impl<'a> NonvolatileStorage<'a> for StorageLowSpace<'a> {
+    fn set_client(&self, client: &'a dyn NonvolatileStorageClient) {
+        self.client.set(client);
+    }
+
+    fn read(
+        &self,
+        buffer: &'static mut [u8],
+        address: usize,
+        length: usize,
+    ) -> Result<(), ErrorCode> {
+        self.storage.read(buffer, address, length)
+    }
+
+    fn write(
+        &self,
+        buffer: &'static mut [u8],
+        address: usize,
+        length: usize,
+    ) -> Result<(), ErrorCode> {
+        self.storage.write(buffer, address, length)?;
+        self.write.set(address);
+        Ok(())
+    }
+
+    fn sync(&self) -> Result<(), ErrorCode> {
+        self.storage.sync()
+    }
+
+    fn geometry(&self) -> Option<StorageGeometry> {
+        self.storage.geometry()
+    }
+
+    fn reset(&self) -> Result<(), ErrorCode> {
+        self.storage.reset()
+    }
+
+    fn read_mapped(&self, address: usize, buffer: &mut [u8]) -> Result<(), ErrorCode> {
+        self.storage.read_mapped(address, buffer)
+    }
+
+    fn write_now(&self, address: usize, data: &[u8]) -> Result<(), ErrorCode> {
+        self.storage.write_now(address, data)?;
+        self.update_high_water(address, data.len());
+        Ok(())
+    }
+}
+
+impl NonvolatileStorageClient for StorageLowSpace<'_> {
+    fn read_done(&self, buffer: &'static mut [u8], length: usize) {
+        self.read_done_status(buffer, length, ReadStatus::Ok);
+    }
+
+    fn read_done_status(&self, buffer: &'static mut [u8], length: usize, status: ReadStatus) {
+        self.client
+            .map(move |client| client.read_done_status(buffer, length, status));
+    }
+
+    fn write_done(&self, buffer: &'static mut [u8], length: usize) {
+        self.write_done_result(buffer, length, Ok(()));
+    }
+
+    fn write_done_result(
+        &self,
+        buffer: &'static mut [u8],
+        length: usize,
+        result: Result<(), ErrorCode>,
+    ) {
+        if let Some(address) = self.write.take() {
+            if result.is_ok() {
+                self.update_high_water(address, length);
+            }
+        }
+        self.client
+            .map(move |client| client.write_done_result(buffer, length, result));
+    }
+
+    fn sync_done(&self, result: Result<(), ErrorCode>) {
+        self.client.map(|client| client.sync_done(result));
+    }
+}
+
+impl SyscallDriver for StorageLowSpace<'_> {
+    /// Subscribe to the low space upcall.
+    ///
+    /// ### `command_num`
+    ///
+    /// - `0`: Driver check.
+    /// - `1`: Set the threshold to the first argument. The low space upcall
+    ///   fires once the high-water mark, the number of bytes of the userspace
+    ///   region before the end of the furthest write since boot, reaches the
+    ///   threshold. The upcall gets the high-water mark and the threshold,
+    ///   and fires right away if the mark is already there. It fires once per
+    ///   threshold set. A threshold of 0 cancels it. Returns `INVAL` if the
+    ///   threshold is larger than the userspace region.
+    /// - `2`: Return the high-water mark.
+    fn command(
+        &self,
+        command_num: usize,
+        threshold: usize,
+        _: usize,
+        processid: ProcessId,
+    ) -> CommandReturn {
+        match command_num {
+            0 => CommandReturn::success(),
+
+            1 => match self.set_threshold(threshold, processid) {
+                Ok(()) => CommandReturn::success(),
+                Err(e) => CommandReturn::failure(e),
+            },
+
+            2 => CommandReturn::success_u32(self.high_water.get() as u32),
+
+            _ => CommandReturn::failure(ErrorCode::NOSUPPORT),
+        }
+    }
+
+    fn allocate_grant(&self, processid: ProcessId) -> Result<(), kernel::process::Error> {
+        self.apps.enter(processid, |_, _| {})
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use kernel::hil::nonvolatile_storage::StorageRange;
+
+    use super::raised_high_water;
+
+    const USERSPACE: StorageRange = StorageRange::new(0x100, 0x100);
+
+    #[test]
+    fn writes_in_the_userspace_region_raise_the_mark() {
+        assert_eq!(raised_high_water(USERSPACE, 0, 0x100, 0x10), Some(0x10));
+        assert_eq!(raised_high_water(USERSPACE, 0x10, 0x180, 0x10), Some(0x90));
+    }
+
+    #[test]
+    fn writes_before_the_mark_leave_it() {
+        assert_eq!(raised_high_water(USERSPACE, 0x90, 0x100, 0x10), None);
+        assert_eq!(raised_high_water(USERSPACE, 0x90, 0x180, 0x10), None);
+    }
+
+    #[test]
+    fn writes_outside_the_userspace_region_leave_the_mark() {
+        assert_eq!(raised_high_water(USERSPACE, 0, 0x0, 0x100), None);
+        assert_eq!(raised_high_water(USERSPACE, 0, 0x200, 0x10), None);
+    }
+
+    #[test]
+    fn writes_past_the_end_stop_at_the_end() {
+        assert_eq!(raised_high_water(USERSPACE, 0, 0x1f0, 0x40), Some(0x100));
+        assert_eq!(raised_high_water(USERSPACE, 0, usize::MAX, 2), None);
+    }
+}
@@ -0,0 +1,452 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2024.
+
+//! Lets apps read nonvolatile storage that is mapped into memory without
+//! waiting for the storage, like internal flash whose storage addresses are
+//! flash addresses.
+//!
+//! `StorageMappedReads` sits between the storage and the nonvolatile storage
+//! driver. Apps can read up to `SYNC_READ_MAX_LEN` bytes of the userspace or
+//! provisioned region within the command, or map the userspace region
+//! read-only into their address space and read it with plain loads. Writes
+//! still go through the driver, and once a write of another app or the
+//! kernel to the userspace region finishes, apps that mapped it get an
+//! upcall with the part that changed, so they can drop anything they derived
+//! from the old data. The layer learns the regions, the app each write is
+//! for, and the apps' permissions from the driver through `StorageUsers`.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust,ignore
+//! let mapping_cap = create_capability!(capabilities::ReadOnlyMappingCapability);
+//! let mapped_reads = components::storage_mapped_reads::StorageMappedReadsComponent::new(
+//!     board_kernel,
+//!     capsules_extra::storage_mapped_reads::DRIVER_NUM,
+//!     nv_to_page,
+//!     &mapping_cap,
+//! )
+//! .finalize(components::storage_mapped_reads_component_static!());
+//!
+//! // The nonvolatile storage driver then uses `mapped_reads` as its
+//! // storage, and the layer is given the driver with `set_users()`.
+//! ```
+
+use core::cell::Cell;
+use core::cmp;
+
+use kernel::grant::{AllowRoCount, AllowRwCount, Grant, UpcallCount};
+use kernel::hil::nonvolatile_storage::{
+    NonvolatileStorage, NonvolatileStorageClient, ReadStatus, StorageGeometry, StorageRange,
+};
+use kernel::platform::mpu;
+use kernel::process::ReadOnlyMapper;
+use kernel::processbuffer::WriteableProcessBuffer;
+use kernel::syscall::{CommandReturn, SyscallDriver};
+use kernel::utilities::cells::OptionalCell;
+use kernel::{ErrorCode, ProcessId};
+
+use crate::nonvolatile_storage_driver::{StorageUsers, PROVISIONED_REGION};
+
+/// Syscall driver number.
+use capsules_core::driver;
+pub const DRIVER_NUM: usize = driver::NUM::NvmStorageMapped as usize;
+
+/// The most bytes command `1` reads.
+pub const SYNC_READ_MAX_LEN: usize = 32;
+
+/// Ids for subscribe upcalls.
+mod upcall {
+    /// A write changed the mapped userspace region.
+    pub const MAPPED_WRITE: usize = 0;
+    /// Number of upcalls.
+    pub const COUNT: u8 = 1;
+}
+
+/// Ids for read-write allow buffers.
+mod rw_allow {
+    /// Buffer command `1` reads into.
+    pub const READ: usize = 0;
+    /// The number of allow buffers the kernel stores for this grant.
+    pub const COUNT: u8 = 1;
+}
+
+#[derive(Default)]
+pub struct App {
+    // The MPU region the userspace region is mapped with.
+    mapping: Option<mpu::Region>,
+}
+
+/// The absolute address of `length` bytes at `offset` of `region`.
+fn region_address(region: StorageRange, offset: usize, length: usize) -> Result<usize, ErrorCode> {
+    if region.length == 0 {
+        return Err(ErrorCode::NODEVICE);
+    }
+    if offset >= region.length || length > region.length - offset {
+        return Err(ErrorCode::INVAL);
+    }
+    Ok(region.start + offset)
+}
+
+/// The offset and length of the part of `userspace` a write of `length` bytes
+/// at `address` changed, if any.
+fn changed_part(userspace: StorageRange, address: usize, length: usize) -> Option<(usize, usize)> {
+    if !userspace.overlaps(address, length) {
+        return None;
+    }
+    let start = cmp::max(address, userspace.start);
+    let end = cmp::min(address.saturating_add(length), userspace.end()?);
+    Some((start - userspace.start, end - start))
+}
+
+pub struct StorageMappedReads<'a> {
+    storage: &'a dyn NonvolatileStorage<'a>,
+    client: OptionalCell<&'a dyn NonvolatileStorageClient>,
+    users: OptionalCell<&'a dyn StorageUsers>,
+    apps: Grant<
+        App,
+        UpcallCount<{ upcall::COUNT }>,
+        AllowRoCount<0>,
+        AllowRwCount<{ rw_allow::COUNT }>,
+    >,
+    mapper: ReadOnlyMapper,
+    // Whether the storage is working on a read, write or sync.
+    busy: Cell<bool>,
+    // Where the write the storage is working on starts, and the app it is
+    // for, if any.
+    write: OptionalCell<(usize, Option<ProcessId>)>,
+}
+
+impl<'a> StorageMappedReads<'a> {
+    pub fn new(
+        storage: &'a dyn NonvolatileStorage<'a>,
+        grant: Grant<
+            App,
+            UpcallCount<{ upcall::COUNT }>,
+            AllowRoCount<0>,
+            AllowRwCount<{ rw_allow::COUNT }>,
+        >,
+        mapper: ReadOnlyMapper,
+    ) -> Self {
+        Self {
+            storage,
+            client: OptionalCell::empty(),
+            users: OptionalCell::empty(),
+            apps: grant,
+            mapper,
+            busy: Cell::new(false),
+            write: OptionalCell::empty(),
+        }
+    }
+
+    /// Learn the regions and the apps' permissions from `users`, usually the
+    /// nonvolatile storage driver above this layer. Until then, apps cannot
+    /// read or map anything.
+    pub fn set_users(&self, users: &'a dyn StorageUsers) {
+        self.users.set(users);
+    }
+
+    // Read `length` bytes at `offset` of the userspace or provisioned region
+    // into the read buffer of `processid` right away. Returns the number of
+    // bytes copied.
+    fn read_now(
+        &self,
+        provisioned: bool,
+        offset: usize,
+        length: usize,
+        processid: ProcessId,
+    ) -> Result<usize, ErrorCode> {
+        let users = self.users.get().ok_or(ErrorCode::NODEVICE)?;
+        if length > SYNC_READ_MAX_LEN {
+            return Err(ErrorCode::SIZE);
+        }
+        let region = if provisioned {
+            users.provisioned_range()
+        } else {
+            users.userspace_range()
+        };
+        let address = region_address(region, offset, length)?;
+        users.check_read_permission(processid)?;
+        // Writes of this app that are still queued must land before it reads
+        // the same bytes, so it has to use the driver's read until they have.
+        if self.busy.get() || users.has_commands(processid) {
+            return Err(ErrorCode::BUSY);
+        }
+
+        let mut data = [0; SYNC_READ_MAX_LEN];
+        self.storage.read_mapped(address, &mut data[..length])?;
+        self.apps
+            .enter(processid, |_app, kernel_data| {
+                kernel_data
+                    .get_readwrite_processbuffer(rw_allow::READ)
+                    .and_then(|read| {
+                        read.mut_enter(|app_buffer| {
+                            let copied = cmp::min(app_buffer.len(), length);
+                            app_buffer[..copied].copy_from_slice(&data[..copied]);
+                            copied
+                        })
+                    })
+                    .map_err(ErrorCode::from)
+            })
+            .unwrap_or_else(|err| Err(err.into()))
+    }
+
+    // Map the userspace region read-only into `processid`, and return the
+    // address and length of the mapping.
+    fn map_userspace(&self, processid: ProcessId) -> Result<(usize, usize), ErrorCode> {
+        let users = self.users.get().ok_or(ErrorCode::NODEVICE)?;
+        let userspace = users.userspace_range();
+        if userspace.length == 0 {
+            return Err(ErrorCode::NODEVICE);
+        }
+        users.check_read_permission(processid)?;
+        if self.busy.get() {
+            return Err(ErrorCode::BUSY);
+        }
+        // Only storage that is mapped into memory can be read with loads.
+        let mut probe = [0; 1];
+        self.storage.read_mapped(userspace.start, &mut probe)?;
+        self.apps
+            .enter(processid, |app, _| {
+                let region = match app.mapping {
+                    Some(region) => region,
+                    None => {
+                        let region = self.mapper.map(
+                            processid,
+                            userspace.start as *const u8,
+                            userspace.length,
+                        )?;
+                        app.mapping = Some(region);
+                        region
+                    }
+                };
+                Ok((region.start_address() as usize, region.size()))
+            })
+            .unwrap_or_else(|err| Err(err.into()))
+    }
+
+    // Remove the mapping of the userspace region from `processid`.
+    fn unmap_userspace(&self, processid: ProcessId) -> Result<(), ErrorCode> {
+        self.apps
+            .enter(processid, |app, _| match app.mapping.take() {
+                Some(region) => self.mapper.unmap(processid, region),
+                None => Err(ErrorCode::ALREADY),
+            })
+            .unwrap_or_else(|err| Err(err.into()))
+    }
+
+    // Tell the apps that mapped the userspace region, other than `writer`,
+    // which part of it a finished write changed.
+    fn notify_mapped_write(&self, address: usize, length: usize, writer: Option<ProcessId>) {
+        let part = self
+            .users
+            .and_then(|users| changed_part(users.userspace_range(), address, length));
+        let (offset, length) = match part {
+            Some(part) => part,
+            None => return,
+        };
+        for cntr in self.apps.iter() {
+            if writer == Some(cntr.processid()) {
+                continue;
+            }
+            cntr.enter(|app, kernel_data| {
+                if app.mapping.is_some() {
+                    kernel_data
+                        .schedule_upcall(upcall::MAPPED_WRITE, (offset, length, 0))
+                        .ok();
+                }
+            });
+        }
+    }
+}
+
+impl<'a> NonvolatileStorage<'a> for StorageMappedReads<'a> {
+    fn set_client(&self, client: &'a dyn NonvolatileStorageClient) {
+        self.client.set(client);
+    }
+
+    fn read(
+        &self,
+        buffer: &'static mut [u8],
+        address: usize,
+        length: usize,
+    ) -> Result<(), ErrorCode> {
+        self.storage.read(buffer, address, length)?;
+        self.busy.set(true);
+        Ok(())
+    }
+
+    fn write(
+        &self,
+        buffer: &'static mut [u8],
+        address: usize,
+        length: usize,
+    ) -> Result<(), ErrorCode> {
+        self.storage.write(buffer, address, length)?;
+        self.busy.set(true);
+        self.write
+            .set((address, self.users.and_then(|users| users.current_app())));
+        Ok(())
+    }
+
+    fn sync(&self) -> Result<(), ErrorCode> {
+        self.storage.sync()?;
+        self.busy.set(true);
+        Ok(())
+    }
+
+    fn geometry(&self) -> Option<StorageGeometry> {
+        self.storage.geometry()
+    }
+
+    fn reset(&self) -> Result<(), ErrorCode> {
+        self.storage.reset()
+    }
+
+    fn read_mapped(&self, address: usize, buffer: &mut [u8]) -> Result<(), ErrorCode> {
+        self.storage.read_mapped(address, buffer)
+    }
+
+    fn write_now(&self, address: usize, data: &[u8]) -> Result<(), ErrorCode> {
+        self.storage.write_now(address, data)?;
+        self.notify_mapped_write(address, data.len(), None);
+        Ok(())
+    }
+}
+
+impl NonvolatileStorageClient for StorageMappedReads<'_> {
+    fn read_done(&self, buffer: &'static mut [u8], length: usize) {
+        self.read_done_status(buffer, length, ReadStatus::Ok);
+    }
+
+    fn read_done_status(&self, buffer: &'static mut [u8], length: usize, status: ReadStatus) {
+        self.busy.set(false);
+        self.client
+            .map(move |client| client.read_done_status(buffer, length, status));
+    }
+
+    fn write_done(&self, buffer: &'static mut [u8], length: usize) {
+        self.write_done_result(buffer, length, Ok(()));
+    }
+
+    fn write_done_result(
+        &self,
+        buffer: &'static mut [u8],
+        length: usize,
+        result: Result<(), ErrorCode>,
+    ) {
+        self.busy.set(false);
+        if let Some((address, writer)) = self.write.take() {
+            if result.is_ok() {
+                self.notify_mapped_write(address, length, writer);
+            }
+        }
+        self.client
+            .map(move |client| client.write_done_result(buffer, length, result));
+    }
+
+    fn sync_done(&self, result: Result<(), ErrorCode>) {
+        self.busy.set(false);
+        self.client.map(|client| client.sync_done(result));
+    }
+}
+
+impl SyscallDriver for StorageMappedReads<'_> {
+    /// Read mapped storage.
+    ///
+    /// ### `command_num`
+    ///
+    /// - `0`: Driver check.
+    /// - `1`: Read the number of bytes given as the second argument, at most
+    ///   `SYNC_READ_MAX_LEN`, at the offset given as the first argument of
+    ///   the userspace region, into the allowed read buffer right away,
+    ///   without an upcall. Returns the number of bytes copied. Returns
+    ///   `NOSUPPORT` if the storage is not mapped into memory, and `BUSY`
+    ///   while the storage is working or this app has commands queued with
+    ///   the nonvolatile storage driver. Apps then fall back to its read
+    ///   command.
+    /// - `1 | PROVISIONED_REGION`: The same, from the provisioned region.
+    /// - `2`: Map the userspace region read-only into the app's address
+    ///   space, so it can be read with plain loads. Returns the address and
+    ///   length of the mapping. Once a write of another app or the kernel to
+    ///   the region finishes, the mapped write upcall gives its offset and
+    ///   length. Returns `NOSUPPORT` if the storage is not mapped into
+    ///   memory, `BUSY` while the storage is working, and `NOMEM` if the MPU
+    ///   has no room for the mapping. The mapping lasts until command `3` or
+    ///   the app restarts.
+    /// - `3`: Remove the mapping made with command `2`. Returns `ALREADY` if
+    ///   there is none.
+    fn command(
+        &self,
+        command_num: usize,
+        offset: usize,
+        length: usize,
+        processid: ProcessId,
+    ) -> CommandReturn {
+        match command_num {
+            0 => CommandReturn::success(),
+
+            1 => match self.read_now(false, offset, length, processid) {
+                Ok(copied) => CommandReturn::success_u32(copied as u32),
+                Err(e) => CommandReturn::failure(e),
+            },
+
+            c if c == 1 | PROVISIONED_REGION => {
+                match self.read_now(true, offset, length, processid) {
+                    Ok(copied) => CommandReturn::success_u32(copied as u32),
+                    Err(e) => CommandReturn::failure(e),
+                }
+            }
+
+            2 => match self.map_userspace(processid) {
+                Ok((address, length)) => {
+                    CommandReturn::success_u32_u32(address as u32, length as u32)
+                }
+                Err(e) => CommandReturn::failure(e),
+            },
+
+            3 => match self.unmap_userspace(processid) {
+                Ok(()) => CommandReturn::success(),
+                Err(e) => CommandReturn::failure(e),
+            },
+
+            _ => CommandReturn::failure(ErrorCode::NOSUPPORT),
+        }
+    }
+
+    fn allocate_grant(&self, processid: ProcessId) -> Result<(), kernel::process::Error> {
+        self.apps.enter(processid, |_, _| {})
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use kernel::hil::nonvolatile_storage::StorageRange;
+    use kernel::ErrorCode;
+
+    use super::{changed_part, region_address};
+
+    const USERSPACE: StorageRange = StorageRange::new(0x100, 0x100);
+
+    #[test]
+    fn reads_must_fit_in_the_region() {
+        assert_eq!(region_address(USERSPACE, 0x10, 0x20), Ok(0x110));
+        assert_eq!(region_address(USERSPACE, 0xf0, 0x10), Ok(0x1f0));
+        assert_eq!(region_address(USERSPACE, 0xf0, 0x11), Err(ErrorCode::INVAL));
+        assert_eq!(region_address(USERSPACE, 0x100, 0), Err(ErrorCode::INVAL));
+        assert_eq!(
+            region_address(StorageRange::new(0x100, 0), 0, 1),
+            Err(ErrorCode::NODEVICE)
+        );
+    }
+
+    #[test]
+    fn only_the_changed_part_of_the_userspace_region_is_reported() {
+        assert_eq!(changed_part(USERSPACE, 0x110, 0x10), Some((0x10, 0x10)));
+        assert_eq!(changed_part(USERSPACE, 0xf0, 0x20), Some((0, 0x10)));
+        assert_eq!(changed_part(USERSPACE, 0x1f0, 0x20), Some((0xf0, 0x10)));
+        assert_eq!(changed_part(USERSPACE, 0x200, 0x10), None);
+        assert_eq!(changed_part(USERSPACE, 0, 0x100), None);
+    }
+}
@@ -0,0 +1,254 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2024.
+
+//! Flash pages on a region of nonvolatile storage.
+//!
+//! `StoragePages` presents a region of a `NonvolatileStorage` as flash pages
+//! of `PAGE_SIZE` bytes, so capsules written for the flash HIL can keep their
+//! data in it. The main user is TicKV: a `TicKVSystem` on the pages of one
+//! region gives a key-value store with TicKV's wear leveling and crash
+//! safety, without leaving the region.
+//!
+//! Page 0 is the start of the region, and pages past its end are rejected
+//! with `INVAL`. Boards that keep one region of the storage per app, for
+//! example through a window of `MuxNonvolatileStorage`, create one
+//! `StoragePages` and one TicKV instance per region, so each app's keys stay
+//! in that app's region.
+//!
+//! Erasing a page writes `0xFF` to all of its bytes, which is what TicKV
+//! expects of erased flash. Storage that has to erase before writing erases
+//! as part of the write.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust,ignore
+//! let pages = components::storage_pages::StoragePagesComponent::new(
+//!     app_storage_window,
+//!     0x60000,
+//!     0x8000,
+//! )
+//! .finalize(components::storage_pages_component_static!(512));
+//!
+//! let tickv = components::tickv::TicKVDedicatedFlashComponent::new(
+//!     sip_hash,
+//!     pages,
+//!     0,
+//!     0x8000,
+//!     tickv_page_buffer,
+//! )
+//! .finalize(components::tickv_dedicated_flash_component_static!(
+//!     capsules_extra::storage_pages::StoragePages<'static, 512>,
+//!     capsules_extra::sip_hash::SipHasher24,
+//!     512,
+//! ));
+//! ```
+
+use core::cell::Cell;
+
+use kernel::hil;
+use kernel::hil::nonvolatile_storage::{NonvolatileStorage, StorageGeometry};
+use kernel::utilities::cells::{OptionalCell, TakeCell};
+use kernel::ErrorCode;
+
+/// A page of `StoragePages`.
+pub struct StoragePage<const PAGE_SIZE: usize>(pub [u8; PAGE_SIZE]);
+
+impl<const PAGE_SIZE: usize> Default for StoragePage<PAGE_SIZE> {
+    fn default() -> Self {
+        Self([0; PAGE_SIZE])
+    }
+}
+
+impl<const PAGE_SIZE: usize> AsMut<[u8]> for StoragePage<PAGE_SIZE> {
+    fn as_mut(&mut self) -> &mut [u8] {
+        &mut self.0
+    }
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum Operation {
+    Idle,
+    Read,
+    Write,
+    Erase,
+}
+
+pub struct StoragePages<'a, const PAGE_SIZE: usize> {
+    storage: &'a dyn NonvolatileStorage<'a>,
+    // Absolute storage address of page 0.
+    start_address: usize,
+    // Number of pages in the region.
+    pages: usize,
+    // Holds the data of a page while the storage works on it. The storage
+    // keeps it if it rejects an operation, after which every operation fails
+    // with `NOMEM`.
+    buffer: TakeCell<'static, [u8]>,
+    // The client's page, while it is being read or written.
+    page: TakeCell<'static, StoragePage<PAGE_SIZE>>,
+    operation: Cell<Operation>,
+    client: OptionalCell<&'a dyn hil::flash::Client<StoragePages<'a, PAGE_SIZE>>>,
+}
+
+impl<'a, const PAGE_SIZE: usize> StoragePages<'a, PAGE_SIZE> {
+    /// Use the `length` bytes of `storage` starting at the absolute address
+    /// `start_address` as pages. A partial page at the end is not used.
+    /// `buffer` must hold at least a page.
+    pub fn new(
+        storage: &'a dyn NonvolatileStorage<'a>,
+        start_address: usize,
+        length: usize,
+        buffer: &'static mut [u8],
+    ) -> StoragePages<'a, PAGE_SIZE> {
+        StoragePages {
+            storage,
+            start_address,
+            pages: length / PAGE_SIZE,
+            buffer: TakeCell::new(buffer),
+            page: TakeCell::empty(),
+            operation: Cell::new(Operation::Idle),
+            client: OptionalCell::empty(),
+        }
+    }
+
+    // Check that a new operation on `page_number` can start, and take the
+    // buffer for it.
+    fn start(&self, page_number: usize) -> Result<&'static mut [u8], ErrorCode> {
+        if self.operation.get() != Operation::Idle {
+            return Err(ErrorCode::BUSY);
+        }
+        if page_number >= self.pages {
+            return Err(ErrorCode::INVAL);
+        }
+        match self.buffer.take() {
+            Some(buffer) if buffer.len() >= PAGE_SIZE => Ok(buffer),
+            Some(buffer) => {
+                self.buffer.replace(buffer);
+                Err(ErrorCode::NOMEM)
+            }
+            None => Err(ErrorCode::NOMEM),
+        }
+    }
+
+    fn address(&self, page_number: usize) -> usize {
+        self.start_address + page_number * PAGE_SIZE
+    }
+
+    fn result(length: usize) -> Result<(), hil::flash::Error> {
+        if length == PAGE_SIZE {
+            Ok(())
+        } else {
+            Err(hil::flash::Error::FlashError)
+        }
+    }
+}
+
+impl<const PAGE_SIZE: usize> hil::flash::Flash for StoragePages<'_, PAGE_SIZE> {
+    type Page = StoragePage<PAGE_SIZE>;
+
+    fn read_page(
+        &self,
+        page_number: usize,
+        buf: &'static mut Self::Page,
+    ) -> Result<(), (ErrorCode, &'static mut Self::Page)> {
+        let buffer = match self.start(page_number) {
+            Ok(buffer) => buffer,
+            Err(e) => return Err((e, buf)),
+        };
+        match self
+            .storage
+            .read(buffer, self.address(page_number), PAGE_SIZE)
+        {
+            Ok(()) => {
+                self.page.replace(buf);
+                self.operation.set(Operation::Read);
+                Ok(())
+            }
+            Err(e) => Err((e, buf)),
+        }
+    }
+
+    fn write_page(
+        &self,
+        page_number: usize,
+        buf: &'static mut Self::Page,
+    ) -> Result<(), (ErrorCode, &'static mut Self::Page)> {
+        let buffer = match self.start(page_number) {
+            Ok(buffer) => buffer,
+            Err(e) => return Err((e, buf)),
+        };
+        buffer[..PAGE_SIZE].copy_from_slice(&buf.0);
+        match self
+            .storage
+            .write(buffer, self.address(page_number), PAGE_SIZE)
+        {
+            Ok(()) => {
+                self.page.replace(buf);
+                self.operation.set(Operation::Write);
+                Ok(())
+            }
+            Err(e) => Err((e, buf)),
+        }
+    }
+
+    fn erase_page(&self, page_number: usize) -> Result<(), ErrorCode> {
+        let buffer = self.start(page_number)?;
+        buffer[..PAGE_SIZE].fill(0xFF);
+        self.storage
+            .write(buffer, self.address(page_number), PAGE_SIZE)?;
+        self.operation.set(Operation::Erase);
+        Ok(())
+    }
+
+    fn geometry(&self) -> Option<StorageGeometry> {
+        Some(StorageGeometry {
+            erase_block_size: PAGE_SIZE,
+            write_granularity: PAGE_SIZE,
+            total_size: self.pages * PAGE_SIZE,
+        })
+    }
+}
+
+impl<'a, C: hil::flash::Client<Self>, const PAGE_SIZE: usize> hil::flash::HasClient<'a, C>
+    for StoragePages<'a, PAGE_SIZE>
+{
+    fn set_client(&'a self, client: &'a C) {
+        self.client.set(client);
+    }
+}
+
+impl<const PAGE_SIZE: usize> hil::nonvolatile_storage::NonvolatileStorageClient
+    for StoragePages<'_, PAGE_SIZE>
+{
+    fn read_done(&self, buffer: &'static mut [u8], length: usize) {
+        let operation = self.operation.replace(Operation::Idle);
+        if let Some(page) = self.page.take() {
+            if operation == Operation::Read && length == PAGE_SIZE {
+                page.0.copy_from_slice(&buffer[..PAGE_SIZE]);
+            }
+            self.buffer.replace(buffer);
+            self.client
+                .map(move |client| client.read_complete(page, Self::result(length)));
+        } else {
+            self.buffer.replace(buffer);
+        }
+    }
+
+    fn write_done(&self, buffer: &'static mut [u8], length: usize) {
+        self.buffer.replace(buffer);
+        match self.operation.replace(Operation::Idle) {
+            Operation::Write => {
+                if let Some(page) = self.page.take() {
+                    self.client
+                        .map(move |client| client.write_complete(page, Self::result(length)));
+                }
+            }
+            Operation::Erase => {
+                self.client
+                    .map(|client| client.erase_complete(Self::result(length)));
+            }
+            Operation::Idle | Operation::Read => {}
+        }
+    }
+}
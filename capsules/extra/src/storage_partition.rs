@@ -0,0 +1,69 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2024.
+
+//! Usage of a storage volume split between a kernel log and apps.
+//!
+//! Boards that keep a kernel [`Log`](crate::log::Log) and the userspace
+//! nonvolatile storage driver in one storage volume give each a fixed quota of
+//! the volume. The log gets the start of the volume and the driver's userspace
+//! region gets the rest, so neither can write into the other's part.
+//! `StoragePartition` records the split and reports how much of each part is
+//! in use.
+//!
+//! Usually created by `components::storage_partition::StoragePartitionComponent`.
+//!
+//! The log's usage is the space taken by the entries it still holds, including
+//! their headers. Userspace storage is not allocated, so apps are counted as
+//! using their whole quota.
+
+use kernel::hil::log::LogRead;
+
+/// How the volume is split, and how much of each part is used.
+#[derive(Clone, Copy, Debug)]
+pub struct PartitionUsage {
+    /// Bytes of the volume reserved for the log.
+    pub log_quota: usize,
+    /// Approximate number of bytes the log can hold.
+    pub log_capacity: usize,
+    /// Bytes currently taken by log entries.
+    pub log_used: usize,
+    /// Absolute address of the userspace region.
+    pub userspace_start: usize,
+    /// Bytes of the volume reserved for apps.
+    pub userspace_quota: usize,
+}
+
+pub struct StoragePartition<'a, L: LogRead<'a, EntryID = usize>> {
+    log: &'a L,
+    log_quota: usize,
+    userspace_start: usize,
+    userspace_quota: usize,
+}
+
+impl<'a, L: LogRead<'a, EntryID = usize>> StoragePartition<'a, L> {
+    pub fn new(
+        log: &'a L,
+        log_quota: usize,
+        userspace_start: usize,
+        userspace_quota: usize,
+    ) -> StoragePartition<'a, L> {
+        StoragePartition {
+            log,
+            log_quota,
+            userspace_start,
+            userspace_quota,
+        }
+    }
+
+    /// Report the quotas and current usage of the volume.
+    pub fn usage(&self) -> PartitionUsage {
+        PartitionUsage {
+            log_quota: self.log_quota,
+            log_capacity: self.log.get_size(),
+            log_used: self.log.log_end().saturating_sub(self.log.log_start()),
+            userspace_start: self.userspace_start,
+            userspace_quota: self.userspace_quota,
+        }
+    }
+}
@@ -0,0 +1,524 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2024.
+
+//! Provisioning lock for the provisioned region of the nonvolatile storage
+//! driver.
+//!
+//! For factory flows that provision the region from an app, the board names
+//! the one app that provisions the device by its fixed `ShortId`, and keeps a
+//! lock in the kernel region. Until the lock is set, that app may also write
+//! the provisioned region; other apps, and apps without a fixed `ShortId`,
+//! never may. Once the app sets the lock, the region is read-only to apps
+//! again. The lock cannot be cleared through the driver.
+//!
+//! `StorageProvisioningLock` sits between the storage and the nonvolatile
+//! storage driver, and reads and writes the lock with its own buffer, so a
+//! storage that rejects an operation on the lock cannot take the driver's
+//! buffers. If reading the lock fails, the region stays read-only until the
+//! next boot. The driver only reads or writes the lock through the
+//! `ProvisioningLock` interface while it has no other operation with the
+//! storage.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust,ignore
+//! let provisioning_lock =
+//!     components::storage_provisioning_lock::StorageProvisioningLockComponent::new(
+//!         nv_to_page,
+//!         0x7effc,
+//!         ShortId::Fixed(NonZeroU32::new(0x5).unwrap()),
+//!     )
+//!     .finalize(components::storage_provisioning_lock_component_static!());
+//!
+//! // The nonvolatile storage driver then uses `provisioning_lock` as its
+//! // storage, and `ProvisioningLockDriverComponent` gives it the lock.
+//! ```
+
+use core::cell::Cell;
+
+use kernel::hil::nonvolatile_storage::{
+    NonvolatileStorage, NonvolatileStorageClient, ReadStatus, StorageGeometry,
+};
+use kernel::process::ShortId;
+use kernel::utilities::cells::{OptionalCell, TakeCell};
+use kernel::ErrorCode;
+
+/// Value of the provisioning lock once provisioning is complete. Any other
+/// value, including erased storage, leaves provisioning open.
+pub const PROVISIONING_LOCK: [u8; 4] = *b"PLCK";
+
+/// A lock that makes the provisioned region read-only to apps for good.
+pub trait ProvisioningLock<'a> {
+    fn set_client(&self, client: &'a dyn ProvisioningLockClient);
+
+    /// Absolute storage address of the lock.
+    fn address(&self) -> usize;
+
+    /// Read the lock to find out whether provisioning is still open.
+    /// `lock_done` is called once it was read.
+    fn check(&self) -> Result<(), ErrorCode>;
+
+    /// Whether the app with `short_id` may write the provisioned region:
+    /// `BUSY` while the lock is being read, and `NOSUPPORT` once it is set or
+    /// for apps other than the provisioner.
+    fn may_write(&self, short_id: ShortId) -> Result<(), ErrorCode>;
+
+    /// Set the lock for the app with `short_id`. The lock applies right away,
+    /// and `lock_done` is called once it is stored. Returns `ALREADY` if it is
+    /// set, `BUSY` while it is being read, and `NOSUPPORT` for apps other than
+    /// the provisioner.
+    fn lock(&self, short_id: ShortId) -> Result<(), ErrorCode>;
+}
+
+/// Client interface for [`ProvisioningLock`].
+pub trait ProvisioningLockClient {
+    /// The lock was read or stored, or could not be.
+    fn lock_done(&self, result: Result<(), ErrorCode>);
+}
+
+/// Whether apps may write the provisioned region.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Provisioning {
+    /// The provisioned region is read-only to apps.
+    Locked,
+    /// The lock is being read.
+    Checking,
+    /// The provisioner may write the provisioned region until it is locked.
+    Open,
+}
+
+pub struct StorageProvisioningLock<'a> {
+    storage: &'a dyn NonvolatileStorage<'a>,
+    client: OptionalCell<&'a dyn NonvolatileStorageClient>,
+    lock_client: OptionalCell<&'a dyn ProvisioningLockClient>,
+    // Holds the lock while it is read or written.
+    buffer: TakeCell<'static, [u8]>,
+    address: usize,
+    // The only app that may write the provisioned region and lock it.
+    provisioner: ShortId,
+    provisioning: Cell<Provisioning>,
+    // Whether the storage is reading or writing the lock.
+    busy: Cell<bool>,
+    // Whether the storage is working on an operation of the client.
+    passing: Cell<bool>,
+}
+
+impl<'a> StorageProvisioningLock<'a> {
+    /// Keep the lock at the absolute storage address `address`, reading and
+    /// writing it with `buffer`, which must hold `PROVISIONING_LOCK.len()`
+    /// bytes. Only the app with the fixed `ShortId` `provisioner` may write
+    /// the provisioned region and set the lock; with
+    /// `ShortId::LocallyUnique` no app may.
+    pub fn new(
+        storage: &'a dyn NonvolatileStorage<'a>,
+        address: usize,
+        provisioner: ShortId,
+        buffer: &'static mut [u8],
+    ) -> Self {
+        Self {
+            storage,
+            client: OptionalCell::empty(),
+            lock_client: OptionalCell::empty(),
+            buffer: TakeCell::new(buffer),
+            address,
+            provisioner,
+            provisioning: Cell::new(Provisioning::Locked),
+            busy: Cell::new(false),
+            passing: Cell::new(false),
+        }
+    }
+
+    fn is_provisioner(&self, short_id: ShortId) -> bool {
+        matches!(self.provisioner, ShortId::Fixed(_)) && short_id == self.provisioner
+    }
+
+    // Take the buffer for an operation on the lock, if the storage is free.
+    fn take_buffer(&self) -> Result<&'static mut [u8], ErrorCode> {
+        if self.busy.get() || self.passing.get() {
+            return Err(ErrorCode::BUSY);
+        }
+        // The buffer is only missing if the storage kept it when it rejected
+        // an earlier operation.
+        match self.buffer.take() {
+            Some(buffer) if buffer.len() >= PROVISIONING_LOCK.len() => Ok(buffer),
+            Some(buffer) => {
+                self.buffer.replace(buffer);
+                Err(ErrorCode::SIZE)
+            }
+            None => Err(ErrorCode::FAIL),
+        }
+    }
+}
+
+impl<'a> ProvisioningLock<'a> for StorageProvisioningLock<'a> {
+    fn set_client(&self, client: &'a dyn ProvisioningLockClient) {
+        self.lock_client.set(client);
+    }
+
+    fn address(&self) -> usize {
+        self.address
+    }
+
+    fn check(&self) -> Result<(), ErrorCode> {
+        let buffer = self.take_buffer()?;
+        self.provisioning.set(Provisioning::Checking);
+        if let Err(e) = self
+            .storage
+            .read(buffer, self.address, PROVISIONING_LOCK.len())
+        {
+            // Storage that cannot be read leaves the region read-only.
+            self.provisioning.set(Provisioning::Locked);
+            return Err(e);
+        }
+        self.busy.set(true);
+        Ok(())
+    }
+
+    fn may_write(&self, short_id: ShortId) -> Result<(), ErrorCode> {
+        match self.provisioning.get() {
+            Provisioning::Checking => Err(ErrorCode::BUSY),
+            Provisioning::Open if self.is_provisioner(short_id) => Ok(()),
+            _ => Err(ErrorCode::NOSUPPORT),
+        }
+    }
+
+    fn lock(&self, short_id: ShortId) -> Result<(), ErrorCode> {
+        match self.provisioning.get() {
+            Provisioning::Locked => return Err(ErrorCode::ALREADY),
+            Provisioning::Checking => return Err(ErrorCode::BUSY),
+            Provisioning::Open => {}
+        }
+        if !self.is_provisioner(short_id) {
+            return Err(ErrorCode::NOSUPPORT);
+        }
+        let buffer = self.take_buffer()?;
+        let length = PROVISIONING_LOCK.len();
+        buffer[..length].copy_from_slice(&PROVISIONING_LOCK);
+        self.provisioning.set(Provisioning::Locked);
+        // If the storage rejects the write it keeps the buffer, so the lock
+        // cannot be stored until the next boot. It still applies until then.
+        self.storage.write(buffer, self.address, length)?;
+        self.busy.set(true);
+        Ok(())
+    }
+}
+
+impl<'a> NonvolatileStorage<'a> for StorageProvisioningLock<'a> {
+    fn set_client(&self, client: &'a dyn NonvolatileStorageClient) {
+        self.client.set(client);
+    }
+
+    fn read(
+        &self,
+        buffer: &'static mut [u8],
+        address: usize,
+        length: usize,
+    ) -> Result<(), ErrorCode> {
+        if self.busy.get() {
+            return Err(ErrorCode::BUSY);
+        }
+        self.storage.read(buffer, address, length)?;
+        self.passing.set(true);
+        Ok(())
+    }
+
+    fn write(
+        &self,
+        buffer: &'static mut [u8],
+        address: usize,
+        length: usize,
+    ) -> Result<(), ErrorCode> {
+        if self.busy.get() {
+            return Err(ErrorCode::BUSY);
+        }
+        self.storage.write(buffer, address, length)?;
+        self.passing.set(true);
+        Ok(())
+    }
+
+    fn sync(&self) -> Result<(), ErrorCode> {
+        if self.busy.get() {
+            return Err(ErrorCode::BUSY);
+        }
+        self.storage.sync()?;
+        self.passing.set(true);
+        Ok(())
+    }
+
+    fn geometry(&self) -> Option<StorageGeometry> {
+        self.storage.geometry()
+    }
+
+    fn reset(&self) -> Result<(), ErrorCode> {
+        self.storage.reset()
+    }
+
+    fn read_mapped(&self, address: usize, buffer: &mut [u8]) -> Result<(), ErrorCode> {
+        self.storage.read_mapped(address, buffer)
+    }
+
+    fn write_now(&self, address: usize, data: &[u8]) -> Result<(), ErrorCode> {
+        self.storage.write_now(address, data)
+    }
+}
+
+impl NonvolatileStorageClient for StorageProvisioningLock<'_> {
+    fn read_done(&self, buffer: &'static mut [u8], length: usize) {
+        self.read_done_status(buffer, length, ReadStatus::Ok);
+    }
+
+    fn read_done_status(&self, buffer: &'static mut [u8], length: usize, status: ReadStatus) {
+        if !self.busy.get() {
+            self.passing.set(false);
+            self.client
+                .map(move |client| client.read_done_status(buffer, length, status));
+            return;
+        }
+
+        // The lock was read at boot. Storage that cannot be read leaves the
+        // region read-only.
+        self.busy.set(false);
+        let read = status.is_valid() && length == PROVISIONING_LOCK.len();
+        let open = read && buffer[..length] != PROVISIONING_LOCK;
+        self.provisioning.set(if open {
+            Provisioning::Open
+        } else {
+            Provisioning::Locked
+        });
+        self.buffer.replace(buffer);
+        self.lock_client
+            .map(|client| client.lock_done(if read { Ok(()) } else { Err(ErrorCode::FAIL) }));
+    }
+
+    fn write_done(&self, buffer: &'static mut [u8], length: usize) {
+        self.write_done_result(buffer, length, Ok(()));
+    }
+
+    fn write_done_result(
+        &self,
+        buffer: &'static mut [u8],
+        length: usize,
+        result: Result<(), ErrorCode>,
+    ) {
+        if !self.busy.get() {
+            self.passing.set(false);
+            self.client
+                .map(move |client| client.write_done_result(buffer, length, result));
+            return;
+        }
+
+        // The lock was written for the provisioner.
+        self.busy.set(false);
+        self.buffer.replace(buffer);
+        let result = match result {
+            Ok(()) if length == PROVISIONING_LOCK.len() => Ok(()),
+            Ok(()) => Err(ErrorCode::FAIL),
+            Err(e) => Err(e),
+        };
+        self.lock_client.map(|client| client.lock_done(result));
+    }
+
+    fn sync_done(&self, result: Result<(), ErrorCode>) {
+        self.passing.set(false);
+        self.client.map(|client| client.sync_done(result));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use core::cell::RefCell;
+    use core::num::NonZeroU32;
+    use std::boxed::Box;
+    use std::vec::Vec;
+
+    use kernel::hil::nonvolatile_storage::{NonvolatileStorage, ReadStatus};
+    use kernel::process::ShortId;
+    use kernel::ErrorCode;
+
+    use super::{
+        ProvisioningLock, ProvisioningLockClient, StorageProvisioningLock, PROVISIONING_LOCK,
+    };
+    use crate::test::mock_storage::{buffer, Done, MockClient, MockStorage, Op};
+
+    const ADDRESS: usize = 0x3fc;
+
+    struct LockClient {
+        done: RefCell<Vec<Result<(), ErrorCode>>>,
+    }
+
+    impl ProvisioningLockClient for LockClient {
+        fn lock_done(&self, result: Result<(), ErrorCode>) {
+            self.done.borrow_mut().push(result);
+        }
+    }
+
+    fn provisioner() -> ShortId {
+        ShortId::Fixed(NonZeroU32::new(5).unwrap())
+    }
+
+    fn other_app() -> ShortId {
+        ShortId::Fixed(NonZeroU32::new(6).unwrap())
+    }
+
+    fn stack() -> (
+        &'static MockStorage<'static>,
+        &'static StorageProvisioningLock<'static>,
+        &'static LockClient,
+    ) {
+        let storage = Box::leak(Box::new(MockStorage::new()));
+        let lock = Box::leak(Box::new(StorageProvisioningLock::new(
+            storage,
+            ADDRESS,
+            provisioner(),
+            buffer(PROVISIONING_LOCK.len()),
+        )));
+        let lock_client = Box::leak(Box::new(LockClient {
+            done: RefCell::new(Vec::new()),
+        }));
+        let client = Box::leak(Box::new(MockClient::new()));
+        storage.set_client(lock);
+        NonvolatileStorage::set_client(lock, client);
+        ProvisioningLock::set_client(lock, lock_client);
+        (storage, lock, lock_client)
+    }
+
+    // Read the lock from storage that was never written.
+    fn open() -> (
+        &'static MockStorage<'static>,
+        &'static StorageProvisioningLock<'static>,
+        &'static LockClient,
+    ) {
+        let (storage, lock, lock_client) = stack();
+        lock.check().unwrap();
+        assert_eq!(lock.may_write(provisioner()), Err(ErrorCode::BUSY));
+        storage.complete();
+        assert_eq!(lock_client.done.take(), [Ok(())]);
+        (storage, lock, lock_client)
+    }
+
+    #[test]
+    fn erased_lock_leaves_provisioning_open_to_the_provisioner() {
+        let (_, lock, _) = open();
+        assert_eq!(lock.may_write(provisioner()), Ok(()));
+        assert_eq!(lock.may_write(other_app()), Err(ErrorCode::NOSUPPORT));
+        assert_eq!(lock.lock(other_app()), Err(ErrorCode::NOSUPPORT));
+    }
+
+    #[test]
+    fn stored_lock_keeps_the_region_read_only() {
+        let (storage, lock, lock_client) = stack();
+        storage.data.borrow_mut()[ADDRESS..].copy_from_slice(&PROVISIONING_LOCK);
+        lock.check().unwrap();
+        storage.complete();
+        assert_eq!(lock_client.done.take(), [Ok(())]);
+        assert_eq!(lock.may_write(provisioner()), Err(ErrorCode::NOSUPPORT));
+        assert_eq!(lock.lock(provisioner()), Err(ErrorCode::ALREADY));
+    }
+
+    #[test]
+    fn unreadable_lock_keeps_the_region_read_only() {
+        let (storage, lock, lock_client) = stack();
+        lock.check().unwrap();
+        storage.complete_read(ReadStatus::Uncorrectable);
+        assert_eq!(lock_client.done.take(), [Err(ErrorCode::FAIL)]);
+        assert_eq!(lock.may_write(provisioner()), Err(ErrorCode::NOSUPPORT));
+    }
+
+    #[test]
+    fn refused_read_keeps_the_region_read_only() {
+        let (storage, lock, lock_client) = stack();
+        storage.refuse_next(ErrorCode::FAIL);
+        assert_eq!(lock.check(), Err(ErrorCode::FAIL));
+        assert!(lock_client.done.take().is_empty());
+        assert_eq!(lock.may_write(provisioner()), Err(ErrorCode::NOSUPPORT));
+        assert_eq!(lock.lock(provisioner()), Err(ErrorCode::ALREADY));
+
+        // The storage kept only the lock's buffer, and is free for the
+        // driver.
+        assert_eq!(storage.kept(), 1);
+        let client = Box::leak(Box::new(MockClient::new()));
+        NonvolatileStorage::set_client(lock, client);
+        lock.read(buffer(4), 0, 4).unwrap();
+        storage.complete();
+        assert_eq!(client.take(), [Done::Read(4, ReadStatus::Ok)]);
+    }
+
+    #[test]
+    fn lock_is_stored_and_applies_right_away() {
+        let (storage, lock, lock_client) = open();
+        lock.lock(provisioner()).unwrap();
+        assert_eq!(lock.may_write(provisioner()), Err(ErrorCode::NOSUPPORT));
+        assert_eq!(
+            storage.pending(),
+            Some(Op::Write(ADDRESS, PROVISIONING_LOCK.len()))
+        );
+        storage.complete();
+        assert_eq!(lock_client.done.take(), [Ok(())]);
+        assert_eq!(storage.data.borrow()[ADDRESS..], PROVISIONING_LOCK);
+        assert_eq!(lock.lock(provisioner()), Err(ErrorCode::ALREADY));
+    }
+
+    #[test]
+    fn refused_write_keeps_the_lock_for_this_boot() {
+        let (storage, lock, lock_client) = open();
+        storage.refuse_next(ErrorCode::BUSY);
+        assert_eq!(lock.lock(provisioner()), Err(ErrorCode::BUSY));
+        assert!(lock_client.done.take().is_empty());
+        assert_eq!(lock.may_write(provisioner()), Err(ErrorCode::NOSUPPORT));
+        assert_eq!(lock.lock(provisioner()), Err(ErrorCode::ALREADY));
+        assert_eq!(storage.data.borrow()[ADDRESS..], [0xff; 4]);
+    }
+
+    #[test]
+    fn failed_write_is_reported_and_keeps_the_lock_for_this_boot() {
+        let (storage, lock, lock_client) = open();
+        lock.lock(provisioner()).unwrap();
+        storage.complete_write(Err(ErrorCode::FAIL));
+        assert_eq!(lock_client.done.take(), [Err(ErrorCode::FAIL)]);
+        assert_eq!(lock.may_write(provisioner()), Err(ErrorCode::NOSUPPORT));
+        assert_eq!(lock.lock(provisioner()), Err(ErrorCode::ALREADY));
+        assert_eq!(storage.data.borrow()[ADDRESS..], [0xff; 4]);
+
+        // The lock got its buffer back, and client operations pass again.
+        let client = Box::leak(Box::new(MockClient::new()));
+        NonvolatileStorage::set_client(lock, client);
+        lock.write(buffer(4), 0, 4).unwrap();
+        storage.complete();
+        assert_eq!(client.take(), [Done::Write(4, Ok(()))]);
+    }
+
+    #[test]
+    fn client_operations_wait_for_the_lock() {
+        let (storage, lock, _) = stack();
+        lock.check().unwrap();
+        assert_eq!(lock.write(buffer(4), 0, 4), Err(ErrorCode::BUSY));
+        storage.complete();
+
+        lock.write(buffer(4), 0, 4).unwrap();
+        assert_eq!(lock.lock(provisioner()), Err(ErrorCode::BUSY));
+        storage.complete();
+        lock.lock(provisioner()).unwrap();
+    }
+
+    #[test]
+    fn locally_unique_provisioner_never_opens_provisioning() {
+        let storage = Box::leak(Box::new(MockStorage::new()));
+        let lock = Box::leak(Box::new(StorageProvisioningLock::new(
+            storage,
+            ADDRESS,
+            ShortId::LocallyUnique,
+            buffer(PROVISIONING_LOCK.len()),
+        )));
+        storage.set_client(lock);
+        lock.check().unwrap();
+        storage.complete();
+        assert_eq!(
+            lock.may_write(ShortId::LocallyUnique),
+            Err(ErrorCode::NOSUPPORT)
+        );
+    }
+}
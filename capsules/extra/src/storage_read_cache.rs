@@ -0,0 +1,308 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2024.
+
+//! Cache of small reads from nonvolatile storage.
+//!
+//! `StorageReadCache` sits between a `NonvolatileStorage` and its client,
+//! usually between external storage like SPI flash and the nonvolatile
+//! storage driver. It keeps the data of the last `ENTRIES` reads of at most
+//! `CHUNK_LEN` bytes. A later read that falls inside one of them is served
+//! from RAM, without touching the storage, which helps apps that read the same
+//! small configuration structures over and over. Storage that is mapped into
+//! memory does not need it.
+//!
+//! Entries are kept by absolute storage address, so the reads of every region
+//! above the cache share its entries. When the cache is full, the entry used
+//! least recently is replaced. A write drops every entry it overlaps before it
+//! is passed to the storage, so the cache never returns stale data. Larger
+//! reads, writes, and syncs go to the storage unchanged.
+//!
+//! Reads served from the cache complete from a deferred call, like reads from
+//! the storage. Only data the storage reported as valid is cached. Writes
+//! made with `write_now` drop the entries they overlap too.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust,ignore
+//! let read_cache = components::storage_read_cache::StorageReadCacheComponent::new(spi_nor)
+//!     .finalize(components::storage_read_cache_component_static!(8, 64));
+//!
+//! // The nonvolatile storage driver then uses `read_cache` as its storage.
+//! ```
+
+use core::cell::Cell;
+
+use kernel::deferred_call::{DeferredCall, DeferredCallClient};
+use kernel::hil::nonvolatile_storage::{
+    NonvolatileStorage, NonvolatileStorageClient, ReadStatus, StorageGeometry,
+};
+use kernel::utilities::cells::{MapCell, OptionalCell, TakeCell};
+use kernel::ErrorCode;
+
+/// Data of one earlier read.
+#[derive(Clone, Copy)]
+struct Entry<const CHUNK_LEN: usize> {
+    address: usize,
+    /// Number of bytes cached. 0 for an unused entry.
+    length: usize,
+    /// Value of the cache's clock when the entry was last used.
+    last_used: u32,
+    data: [u8; CHUNK_LEN],
+}
+
+impl<const CHUNK_LEN: usize> Entry<CHUNK_LEN> {
+    const EMPTY: Self = Self {
+        address: 0,
+        length: 0,
+        last_used: 0,
+        data: [0; CHUNK_LEN],
+    };
+
+    fn contains(&self, address: usize, length: usize) -> bool {
+        self.length > 0
+            && address >= self.address
+            && address
+                .checked_add(length)
+                .map_or(false, |end| end <= self.address + self.length)
+    }
+
+    fn overlaps(&self, address: usize, length: usize) -> bool {
+        self.length > 0
+            && address < self.address + self.length
+            && self.address < address.saturating_add(length)
+    }
+}
+
+pub struct StorageReadCache<'a, const ENTRIES: usize, const CHUNK_LEN: usize> {
+    storage: &'a dyn NonvolatileStorage<'a>,
+    client: OptionalCell<&'a dyn NonvolatileStorageClient>,
+    entries: MapCell<[Entry<CHUNK_LEN>; ENTRIES]>,
+    // Counts accesses, to find the entry used least recently.
+    clock: Cell<u32>,
+    // Address and length of the read the storage is working on, if it can be
+    // cached.
+    fill: OptionalCell<(usize, usize)>,
+    // A read served from the cache, waiting for the deferred call.
+    hit_buffer: TakeCell<'static, [u8]>,
+    hit_length: Cell<usize>,
+    // Number of reads served from the cache and from the storage.
+    hits: Cell<u32>,
+    misses: Cell<u32>,
+    deferred_call: DeferredCall,
+}
+
+impl<'a, const ENTRIES: usize, const CHUNK_LEN: usize> StorageReadCache<'a, ENTRIES, CHUNK_LEN> {
+    pub fn new(storage: &'a dyn NonvolatileStorage<'a>) -> Self {
+        Self {
+            storage,
+            client: OptionalCell::empty(),
+            entries: MapCell::new([Entry::EMPTY; ENTRIES]),
+            clock: Cell::new(0),
+            fill: OptionalCell::empty(),
+            hit_buffer: TakeCell::empty(),
+            hit_length: Cell::new(0),
+            hits: Cell::new(0),
+            misses: Cell::new(0),
+            deferred_call: DeferredCall::new(),
+        }
+    }
+
+    /// Number of reads served from the cache, and of reads passed to the
+    /// storage, since boot.
+    pub fn stats(&self) -> (u32, u32) {
+        (self.hits.get(), self.misses.get())
+    }
+
+    /// Drop every cached entry.
+    pub fn invalidate(&self) {
+        self.entries.map(|entries| {
+            entries.iter_mut().for_each(|entry| entry.length = 0);
+        });
+    }
+
+    fn tick(&self) -> u32 {
+        let now = self.clock.get().wrapping_add(1);
+        self.clock.set(now);
+        now
+    }
+
+    // Copy a cached read into `buffer`. Returns whether it was cached.
+    fn lookup(&self, buffer: &mut [u8], address: usize, length: usize) -> bool {
+        let now = self.tick();
+        self.entries
+            .map(|entries| {
+                entries
+                    .iter_mut()
+                    .find(|entry| entry.contains(address, length))
+                    .map_or(false, |entry| {
+                        let start = address - entry.address;
+                        buffer[..length].copy_from_slice(&entry.data[start..start + length]);
+                        entry.last_used = now;
+                        true
+                    })
+            })
+            .unwrap_or(false)
+    }
+
+    // Keep the data of a finished read in the entry used least recently.
+    fn insert(&self, buffer: &[u8], address: usize, length: usize) {
+        let now = self.tick();
+        self.entries.map(|entries| {
+            let oldest = entries.iter_mut().min_by_key(|entry| {
+                if entry.length == 0 {
+                    0
+                } else {
+                    // Age, so the order survives the clock wrapping.
+                    u32::MAX - now.wrapping_sub(entry.last_used)
+                }
+            });
+            if let Some(entry) = oldest {
+                entry.address = address;
+                entry.length = length;
+                entry.last_used = now;
+                entry.data[..length].copy_from_slice(&buffer[..length]);
+            }
+        });
+    }
+
+    // Drop every entry that overlaps a write.
+    fn drop_overlapping(&self, address: usize, length: usize) {
+        self.entries.map(|entries| {
+            entries
+                .iter_mut()
+                .filter(|entry| entry.overlaps(address, length))
+                .for_each(|entry| entry.length = 0);
+        });
+    }
+
+    // Finish a read the storage did, caching it if it was valid.
+    fn fill_done(&self, buffer: &[u8], length: usize, status: ReadStatus) {
+        if let Some((address, requested)) = self.fill.take() {
+            if status.is_valid() && length == requested {
+                self.insert(buffer, address, length);
+            }
+        }
+    }
+}
+
+impl<'a, const ENTRIES: usize, const CHUNK_LEN: usize> NonvolatileStorage<'a>
+    for StorageReadCache<'a, ENTRIES, CHUNK_LEN>
+{
+    fn set_client(&self, client: &'a dyn NonvolatileStorageClient) {
+        self.client.set(client);
+    }
+
+    fn read(
+        &self,
+        buffer: &'static mut [u8],
+        address: usize,
+        length: usize,
+    ) -> Result<(), ErrorCode> {
+        if self.hit_buffer.is_some() {
+            return Err(ErrorCode::BUSY);
+        }
+        let cacheable = ENTRIES > 0 && length > 0 && length <= CHUNK_LEN && buffer.len() >= length;
+        if cacheable && self.lookup(buffer, address, length) {
+            self.hits.set(self.hits.get().wrapping_add(1));
+            self.hit_length.set(length);
+            self.hit_buffer.replace(buffer);
+            self.deferred_call.set();
+            return Ok(());
+        }
+        self.storage.read(buffer, address, length)?;
+        self.misses.set(self.misses.get().wrapping_add(1));
+        if cacheable {
+            self.fill.set((address, length));
+        }
+        Ok(())
+    }
+
+    fn write(
+        &self,
+        buffer: &'static mut [u8],
+        address: usize,
+        length: usize,
+    ) -> Result<(), ErrorCode> {
+        if self.hit_buffer.is_some() {
+            return Err(ErrorCode::BUSY);
+        }
+        // Drop the overlapping entries even if the write fails, as the
+        // storage may have changed part of the range.
+        self.drop_overlapping(address, length);
+        self.storage.write(buffer, address, length)
+    }
+
+    fn sync(&self) -> Result<(), ErrorCode> {
+        self.storage.sync()
+    }
+
+    fn geometry(&self) -> Option<StorageGeometry> {
+        self.storage.geometry()
+    }
+
+    fn reset(&self) -> Result<(), ErrorCode> {
+        self.storage.reset()
+    }
+
+    fn read_mapped(&self, address: usize, buffer: &mut [u8]) -> Result<(), ErrorCode> {
+        self.storage.read_mapped(address, buffer)
+    }
+
+    fn write_now(&self, address: usize, data: &[u8]) -> Result<(), ErrorCode> {
+        self.drop_overlapping(address, data.len());
+        self.storage.write_now(address, data)
+    }
+}
+
+impl<const ENTRIES: usize, const CHUNK_LEN: usize> NonvolatileStorageClient
+    for StorageReadCache<'_, ENTRIES, CHUNK_LEN>
+{
+    fn read_done(&self, buffer: &'static mut [u8], length: usize) {
+        self.fill_done(buffer, length, ReadStatus::Ok);
+        self.client
+            .map(move |client| client.read_done(buffer, length));
+    }
+
+    fn read_done_status(&self, buffer: &'static mut [u8], length: usize, status: ReadStatus) {
+        self.fill_done(buffer, length, status);
+        self.client
+            .map(move |client| client.read_done_status(buffer, length, status));
+    }
+
+    fn write_done(&self, buffer: &'static mut [u8], length: usize) {
+        self.client
+            .map(move |client| client.write_done(buffer, length));
+    }
+
+    fn write_done_result(
+        &self,
+        buffer: &'static mut [u8],
+        length: usize,
+        result: Result<(), ErrorCode>,
+    ) {
+        self.client
+            .map(move |client| client.write_done_result(buffer, length, result));
+    }
+
+    fn sync_done(&self, result: Result<(), ErrorCode>) {
+        self.client.map(|client| client.sync_done(result));
+    }
+}
+
+impl<const ENTRIES: usize, const CHUNK_LEN: usize> DeferredCallClient
+    for StorageReadCache<'_, ENTRIES, CHUNK_LEN>
+{
+    fn handle_deferred_call(&self) {
+        if let Some(buffer) = self.hit_buffer.take() {
+            let length = self.hit_length.get();
+            self.client
+                .map(move |client| client.read_done(buffer, length));
+        }
+    }
+
+    fn register(&'static self) {
+        self.deferred_call.register(self);
+    }
+}
@@ -0,0 +1,216 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2024.
+
+//! One-shot nonvolatile storage operations that are polled for completion.
+//!
+//! Kernel code that reads or writes storage once in a while, for example to
+//! load a configuration block at boot or store a counter, would otherwise
+//! implement `NonvolatileStorageClient` and keep track of which of its
+//! operations the callback belongs to. A `StorageRequest` is the storage's
+//! client instead. Its user starts an operation, and later calls `poll()`,
+//! which returns `Poll::Pending` until the operation is done and then hands
+//! back the buffer and the result, like polling a future.
+//!
+//! Users that run as a state machine on a deferred call give the request
+//! their `DeferredCall` with `set_deferred_call()`. The request sets it when
+//! the operation completes, so the user's `handle_deferred_call()` polls
+//! only once there is something to collect.
+//!
+//! A request handles one operation at a time. Users that share a storage
+//! device each need their own request on their own virtualized storage, such
+//! as a window of `MuxNonvolatileStorage`.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust,ignore
+//! let request = static_init!(
+//!     capsules_extra::storage_request::StorageRequest<'static>,
+//!     capsules_extra::storage_request::StorageRequest::new(storage_window)
+//! );
+//! storage_window.set_client(request);
+//! request.set_deferred_call(&config_loader.deferred_call);
+//!
+//! // In the user's state machine:
+//! request.read(buffer, CONFIG_ADDRESS, CONFIG_LEN)?;
+//! // ...
+//! if let Poll::Ready(Some(Completion::Read { buffer, length })) = request.poll() {
+//!     // Use the data.
+//! }
+//! ```
+
+use core::cell::Cell;
+use core::task::Poll;
+
+use kernel::deferred_call::DeferredCall;
+use kernel::hil::nonvolatile_storage::{NonvolatileStorage, NonvolatileStorageClient};
+use kernel::utilities::cells::{OptionalCell, TakeCell};
+use kernel::ErrorCode;
+
+/// A finished operation, returned by `StorageRequest::poll()`.
+pub enum Completion {
+    /// A read finished. `length` is the number of bytes read into `buffer`,
+    /// and is 0 if the read failed.
+    Read {
+        buffer: &'static mut [u8],
+        length: usize,
+    },
+    /// A write finished. `length` is the number of bytes written, and is 0
+    /// if the write failed.
+    Write {
+        buffer: &'static mut [u8],
+        length: usize,
+    },
+    /// A sync finished.
+    Sync(Result<(), ErrorCode>),
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum State {
+    Idle,
+    Read,
+    Write,
+    Sync,
+    Done,
+}
+
+pub struct StorageRequest<'a> {
+    storage: &'a dyn NonvolatileStorage<'a>,
+    state: Cell<State>,
+    // Buffer and length of a finished read or write, until it is polled.
+    buffer: TakeCell<'static, [u8]>,
+    length: Cell<usize>,
+    sync_result: Cell<Result<(), ErrorCode>>,
+    // Operation that finished, until it is polled.
+    done: Cell<State>,
+    deferred_call: OptionalCell<&'a DeferredCall>,
+}
+
+impl<'a> StorageRequest<'a> {
+    /// The request must be the client of `storage`.
+    pub fn new(storage: &'a dyn NonvolatileStorage<'a>) -> StorageRequest<'a> {
+        StorageRequest {
+            storage,
+            state: Cell::new(State::Idle),
+            buffer: TakeCell::empty(),
+            length: Cell::new(0),
+            sync_result: Cell::new(Ok(())),
+            done: Cell::new(State::Idle),
+            deferred_call: OptionalCell::empty(),
+        }
+    }
+
+    /// Set `deferred_call` whenever an operation completes.
+    pub fn set_deferred_call(&self, deferred_call: &'a DeferredCall) {
+        self.deferred_call.set(deferred_call);
+    }
+
+    /// Start reading `length` bytes at absolute address `address` into
+    /// `buffer`. Returns `BUSY` until the previous operation has been polled.
+    pub fn read(
+        &self,
+        buffer: &'static mut [u8],
+        address: usize,
+        length: usize,
+    ) -> Result<(), ErrorCode> {
+        self.start(State::Read)?;
+        self.storage
+            .read(buffer, address, length)
+            .inspect_err(|_| self.state.set(State::Idle))
+    }
+
+    /// Start writing `length` bytes of `buffer` at absolute address
+    /// `address`. Returns `BUSY` until the previous operation has been
+    /// polled.
+    pub fn write(
+        &self,
+        buffer: &'static mut [u8],
+        address: usize,
+        length: usize,
+    ) -> Result<(), ErrorCode> {
+        self.start(State::Write)?;
+        self.storage
+            .write(buffer, address, length)
+            .inspect_err(|_| self.state.set(State::Idle))
+    }
+
+    /// Start making completed writes durable. Returns `BUSY` until the
+    /// previous operation has been polled.
+    pub fn sync(&self) -> Result<(), ErrorCode> {
+        self.start(State::Sync)?;
+        self.storage
+            .sync()
+            .inspect_err(|_| self.state.set(State::Idle))
+    }
+
+    /// Whether an operation was started and has not been polled to
+    /// completion yet.
+    pub fn is_busy(&self) -> bool {
+        self.state.get() != State::Idle
+    }
+
+    /// Check on the operation. Returns `Poll::Pending` while it runs, and
+    /// `Poll::Ready(Some(..))` once with its result, after which a new
+    /// operation can start. Returns `Poll::Ready(None)` if no operation was
+    /// started.
+    pub fn poll(&self) -> Poll<Option<Completion>> {
+        match self.state.get() {
+            State::Idle => Poll::Ready(None),
+            State::Done => {
+                self.state.set(State::Idle);
+                let completion = match self.done.replace(State::Idle) {
+                    State::Sync => Some(Completion::Sync(self.sync_result.get())),
+                    State::Read => self.buffer.take().map(|buffer| Completion::Read {
+                        buffer,
+                        length: self.length.get(),
+                    }),
+                    State::Write => self.buffer.take().map(|buffer| Completion::Write {
+                        buffer,
+                        length: self.length.get(),
+                    }),
+                    State::Idle | State::Done => None,
+                };
+                Poll::Ready(completion)
+            }
+            State::Read | State::Write | State::Sync => Poll::Pending,
+        }
+    }
+
+    fn start(&self, operation: State) -> Result<(), ErrorCode> {
+        if self.state.get() != State::Idle {
+            return Err(ErrorCode::BUSY);
+        }
+        self.state.set(operation);
+        Ok(())
+    }
+
+    // Hold on to the result of `operation` until it is polled, if that is
+    // the operation running.
+    fn complete(&self, operation: State) {
+        if self.state.get() == operation {
+            self.done.set(operation);
+            self.state.set(State::Done);
+            self.deferred_call.map(|deferred_call| deferred_call.set());
+        }
+    }
+}
+
+impl NonvolatileStorageClient for StorageRequest<'_> {
+    fn read_done(&self, buffer: &'static mut [u8], length: usize) {
+        self.length.set(length);
+        self.buffer.replace(buffer);
+        self.complete(State::Read);
+    }
+
+    fn write_done(&self, buffer: &'static mut [u8], length: usize) {
+        self.length.set(length);
+        self.buffer.replace(buffer);
+        self.complete(State::Write);
+    }
+
+    fn sync_done(&self, result: Result<(), ErrorCode>) {
+        self.sync_result.set(result);
+        self.complete(State::Sync);
+    }
+}
@@ -0,0 +1,304 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2024.
+
+//! Check every write to nonvolatile storage by reading it back.
+//!
+//! `StorageVerify` sits between a `NonvolatileStorage` and its client,
+//! usually right below the nonvolatile storage driver. Once the storage
+//! finishes a write, the layer reads the written range back into its own
+//! buffer, in pieces as long as that buffer, and compares it with the data
+//! written. The client then gets `write_done_result()`, with `NOACK` if the
+//! data differs or cannot be read back. Reads and syncs pass through
+//! unchanged.
+//!
+//! The nonvolatile storage driver reports `NOACK` to the app that made the
+//! write, and gives kernel clients their buffer back with a length of 0.
+//! Capsules stacked between this layer and the driver must pass
+//! `write_done_result()` on.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust,ignore
+//! let verify = components::storage_verify::StorageVerifyComponent::new(nv_to_page)
+//!     .finalize(components::storage_verify_component_static!(64));
+//!
+//! // The nonvolatile storage driver then uses `verify` as its storage.
+//! ```
+
+use core::cell::Cell;
+use core::cmp;
+
+use kernel::hil::nonvolatile_storage::{
+    NonvolatileStorage, NonvolatileStorageClient, ReadStatus, StorageGeometry,
+};
+use kernel::utilities::cells::{OptionalCell, TakeCell};
+use kernel::ErrorCode;
+
+pub struct StorageVerify<'a> {
+    storage: &'a dyn NonvolatileStorage<'a>,
+    client: OptionalCell<&'a dyn NonvolatileStorageClient>,
+    // Holds the data read back.
+    buffer: TakeCell<'static, [u8]>,
+    // Buffer of the write being checked.
+    written: TakeCell<'static, [u8]>,
+    // Where the current write started and how many bytes it wrote.
+    address: Cell<usize>,
+    length: Cell<usize>,
+    // How many bytes of the current write were read back and matched.
+    verified: Cell<usize>,
+    // Whether the storage is reading back a write.
+    verifying: Cell<bool>,
+}
+
+impl<'a> StorageVerify<'a> {
+    /// Check writes to `storage`, reading them back into `buffer`.
+    pub fn new(storage: &'a dyn NonvolatileStorage<'a>, buffer: &'static mut [u8]) -> Self {
+        Self {
+            storage,
+            client: OptionalCell::empty(),
+            buffer: TakeCell::new(buffer),
+            written: TakeCell::empty(),
+            address: Cell::new(0),
+            length: Cell::new(0),
+            verified: Cell::new(0),
+            verifying: Cell::new(false),
+        }
+    }
+
+    // Read back the next part of the write that just finished.
+    fn verify_next(&self) -> Result<(), ErrorCode> {
+        // The buffer is only missing if the storage kept it when it rejected
+        // an earlier read.
+        let buffer = self.buffer.take().ok_or(ErrorCode::FAIL)?;
+        let verified = self.verified.get();
+        let length = cmp::min(self.length.get() - verified, buffer.len());
+        self.storage
+            .read(buffer, self.address.get() + verified, length)?;
+        self.verifying.set(true);
+        Ok(())
+    }
+
+    // Report the write being checked to the client.
+    fn finish(&self, result: Result<(), ErrorCode>) {
+        self.verifying.set(false);
+        let length = self.length.get();
+        self.written.take().map(|buffer| {
+            self.client
+                .map(move |client| client.write_done_result(buffer, length, result))
+        });
+    }
+}
+
+impl<'a> NonvolatileStorage<'a> for StorageVerify<'a> {
+    fn set_client(&self, client: &'a dyn NonvolatileStorageClient) {
+        self.client.set(client);
+    }
+
+    fn read(
+        &self,
+        buffer: &'static mut [u8],
+        address: usize,
+        length: usize,
+    ) -> Result<(), ErrorCode> {
+        if self.written.is_some() {
+            return Err(ErrorCode::BUSY);
+        }
+        self.storage.read(buffer, address, length)
+    }
+
+    fn write(
+        &self,
+        buffer: &'static mut [u8],
+        address: usize,
+        length: usize,
+    ) -> Result<(), ErrorCode> {
+        if self.written.is_some() {
+            return Err(ErrorCode::BUSY);
+        }
+        self.storage.write(buffer, address, length)?;
+        self.address.set(address);
+        Ok(())
+    }
+
+    fn sync(&self) -> Result<(), ErrorCode> {
+        self.storage.sync()
+    }
+
+    fn geometry(&self) -> Option<StorageGeometry> {
+        self.storage.geometry()
+    }
+
+    fn reset(&self) -> Result<(), ErrorCode> {
+        self.storage.reset()
+    }
+
+    fn read_mapped(&self, address: usize, buffer: &mut [u8]) -> Result<(), ErrorCode> {
+        self.storage.read_mapped(address, buffer)
+    }
+
+    /// Writes made with `write_now` are not read back, as they must finish
+    /// before the call returns.
+    fn write_now(&self, address: usize, data: &[u8]) -> Result<(), ErrorCode> {
+        self.storage.write_now(address, data)
+    }
+}
+
+impl NonvolatileStorageClient for StorageVerify<'_> {
+    fn read_done(&self, buffer: &'static mut [u8], length: usize) {
+        self.read_done_status(buffer, length, ReadStatus::Ok);
+    }
+
+    fn read_done_status(&self, buffer: &'static mut [u8], length: usize, status: ReadStatus) {
+        if !self.verifying.get() {
+            self.client
+                .map(move |client| client.read_done_status(buffer, length, status));
+            return;
+        }
+
+        // Compare the data read back with the part of the write it was read
+        // from, and go on with the next part or finish the write.
+        let verified = self.verified.get();
+        let matches = status.is_valid()
+            && length > 0
+            && self.written.map_or(false, |written| {
+                match (
+                    written.get(verified..verified + length),
+                    buffer.get(..length),
+                ) {
+                    (Some(written), Some(read)) => written == read,
+                    _ => false,
+                }
+            });
+        self.buffer.replace(buffer);
+        if !matches {
+            return self.finish(Err(ErrorCode::NOACK));
+        }
+        self.verified.set(verified + length);
+        if self.verified.get() >= self.length.get() {
+            return self.finish(Ok(()));
+        }
+        if let Err(e) = self.verify_next() {
+            self.finish(Err(e));
+        }
+    }
+
+    fn write_done(&self, buffer: &'static mut [u8], length: usize) {
+        self.write_done_result(buffer, length, Ok(()));
+    }
+
+    fn write_done_result(
+        &self,
+        buffer: &'static mut [u8],
+        length: usize,
+        result: Result<(), ErrorCode>,
+    ) {
+        if result.is_err() || length == 0 {
+            self.client
+                .map(move |client| client.write_done_result(buffer, length, result));
+            return;
+        }
+        self.written.replace(buffer);
+        self.length.set(length);
+        self.verified.set(0);
+        if let Err(e) = self.verify_next() {
+            self.finish(Err(e));
+        }
+    }
+
+    fn sync_done(&self, result: Result<(), ErrorCode>) {
+        self.client.map(|client| client.sync_done(result));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use std::boxed::Box;
+
+    use kernel::hil::nonvolatile_storage::{NonvolatileStorage, ReadStatus};
+    use kernel::ErrorCode;
+
+    use super::StorageVerify;
+    use crate::test::mock_storage::{buffer, Done, MockClient, MockStorage, Op};
+
+    fn stack() -> (
+        &'static MockStorage<'static>,
+        &'static StorageVerify<'static>,
+        &'static MockClient,
+    ) {
+        let storage = Box::leak(Box::new(MockStorage::new()));
+        let verify = Box::leak(Box::new(StorageVerify::new(storage, buffer(8))));
+        let client = Box::leak(Box::new(MockClient::new()));
+        storage.set_client(verify);
+        verify.set_client(client);
+        (storage, verify, client)
+    }
+
+    #[test]
+    fn write_is_read_back_in_pieces() {
+        let (storage, verify, client) = stack();
+        let data = buffer(20);
+        data.copy_from_slice(&[0x5a; 20]);
+        verify.write(data, 0x10, 20).unwrap();
+        storage.complete();
+        for (address, length) in [(0x10, 8), (0x18, 8), (0x20, 4)] {
+            assert!(client.take().is_empty());
+            assert_eq!(storage.pending(), Some(Op::Read(address, length)));
+            storage.complete();
+        }
+        assert_eq!(client.take(), [Done::Write(20, Ok(()))]);
+        assert_eq!(client.buffer()[..20], [0x5a; 20]);
+    }
+
+    #[test]
+    fn write_that_reads_back_differently_is_not_acknowledged() {
+        let (storage, verify, client) = stack();
+        verify.write(buffer(4), 0, 4).unwrap();
+        storage.complete();
+        // Another writer changes the data before it is read back.
+        storage.data.borrow_mut()[2] = 1;
+        storage.complete();
+        assert_eq!(client.take(), [Done::Write(4, Err(ErrorCode::NOACK))]);
+    }
+
+    #[test]
+    fn data_that_cannot_be_read_back_is_not_acknowledged() {
+        let (storage, verify, client) = stack();
+        verify.write(buffer(4), 0, 4).unwrap();
+        storage.complete();
+        storage.complete_read(ReadStatus::Uncorrectable);
+        assert_eq!(client.take(), [Done::Write(4, Err(ErrorCode::NOACK))]);
+
+        // The buffer for reading back came back, so the next write is
+        // checked too.
+        verify.write(buffer(4), 0, 4).unwrap();
+        storage.complete();
+        assert_eq!(storage.pending(), Some(Op::Read(0, 4)));
+    }
+
+    #[test]
+    fn refused_read_back_fails_the_write() {
+        let (storage, verify, client) = stack();
+        verify.write(buffer(4), 0, 4).unwrap();
+        storage.refuse_next(ErrorCode::BUSY);
+        storage.complete();
+        assert_eq!(client.take(), [Done::Write(4, Err(ErrorCode::BUSY))]);
+
+        // The storage kept the buffer for reading back, so later writes
+        // fail instead of going unchecked.
+        verify.write(buffer(4), 0, 4).unwrap();
+        storage.complete();
+        assert_eq!(client.take(), [Done::Write(4, Err(ErrorCode::FAIL))]);
+    }
+
+    #[test]
+    fn reads_pass_through() {
+        let (storage, verify, client) = stack();
+        verify.read(buffer(4), 0, 4).unwrap();
+        storage.complete_read(ReadStatus::Corrected);
+        assert_eq!(client.take(), [Done::Read(4, ReadStatus::Corrected)]);
+    }
+}
@@ -0,0 +1,588 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2024.
+
+//! Counts the bytes read from and written to each region of nonvolatile
+//! storage, so fleet tooling can estimate how worn the storage is.
+//!
+//! `StorageWear` sits between the storage and the nonvolatile storage
+//! driver, and counts every read and write the storage accepts towards the
+//! userspace, provisioned, or kernel region of the driver it starts in. It
+//! learns the regions from the driver through `StorageUsers`; until then
+//! everything counts towards the kernel region. Apps read the counters with
+//! this capsule's syscall driver, and the kernel with `wear_counters()`.
+//!
+//! With `set_counters()` the board gives the counters a place in the kernel
+//! region, so they are kept across reboots. They are read after the first
+//! operation that finishes, and then stored after the operation that brings
+//! the bytes written since they were last stored to the batch size. To limit
+//! the wear of keeping them, the writes of the last batch before a reset are
+//! lost. The completion of that operation is passed on once the counters are
+//! read or stored. Until stored counters were read successfully, they are
+//! read again instead of being overwritten.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust,ignore
+//! let wear = components::storage_wear::StorageWearComponent::new(nv_to_page)
+//!     .finalize(components::storage_wear_component_static!());
+//!
+//! // The nonvolatile storage driver then uses `wear` as its storage, and
+//! // `StorageWearCountersComponent` gives the layer the driver and a place
+//! // for the counters.
+//! ```
+
+use core::cell::Cell;
+
+use kernel::hil::nonvolatile_storage::{
+    NonvolatileStorage, NonvolatileStorageClient, ReadStatus, StorageGeometry,
+};
+use kernel::syscall::{CommandReturn, SyscallDriver};
+use kernel::utilities::cells::{OptionalCell, TakeCell};
+use kernel::{ErrorCode, ProcessId};
+
+use crate::nonvolatile_storage_driver::StorageUsers;
+
+/// Syscall driver number.
+use capsules_core::driver;
+pub const DRIVER_NUM: usize = driver::NUM::NvmStorageWear as usize;
+
+/// Length of the wear counters in storage: `WEAR_COUNTERS_MAGIC`, 4 reserved
+/// bytes, and then the bytes read and written of the userspace, provisioned
+/// and kernel regions, each as a little-endian `u64`.
+pub const WEAR_COUNTERS_LEN: usize = 56;
+
+/// Marks stored wear counters. Anything else, including erased storage, is
+/// taken as no counters stored yet.
+pub const WEAR_COUNTERS_MAGIC: [u8; 4] = *b"WEAR";
+
+/// Bytes read from and written to a region of the storage.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct RegionWear {
+    pub read: u64,
+    pub written: u64,
+}
+
+/// Bytes read from and written to each region of the storage, since the
+/// counters were first stored.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct WearCounters {
+    pub userspace: RegionWear,
+    pub provisioned: RegionWear,
+    pub kernel: RegionWear,
+}
+
+impl WearCounters {
+    fn regions(&self) -> [RegionWear; 3] {
+        [self.userspace, self.provisioned, self.kernel]
+    }
+
+    fn encode(&self, buffer: &mut [u8]) {
+        buffer[..4].copy_from_slice(&WEAR_COUNTERS_MAGIC);
+        buffer[4..8].fill(0);
+        for (region, chunk) in self.regions().iter().zip(buffer[8..].chunks_exact_mut(16)) {
+            chunk[..8].copy_from_slice(&region.read.to_le_bytes());
+            chunk[8..].copy_from_slice(&region.written.to_le_bytes());
+        }
+    }
+
+    fn decode(buffer: &[u8]) -> Option<WearCounters> {
+        if buffer.get(..4)? != WEAR_COUNTERS_MAGIC {
+            return None;
+        }
+        let mut regions = [RegionWear::default(); 3];
+        for (region, chunk) in regions.iter_mut().zip(buffer.get(8..)?.chunks_exact(16)) {
+            region.read = u64::from_le_bytes(chunk[..8].try_into().ok()?);
+            region.written = u64::from_le_bytes(chunk[8..].try_into().ok()?);
+        }
+        Some(WearCounters {
+            userspace: regions[0],
+            provisioned: regions[1],
+            kernel: regions[2],
+        })
+    }
+
+    fn add(&mut self, other: &WearCounters) {
+        for (region, other) in [
+            (&mut self.userspace, other.userspace),
+            (&mut self.provisioned, other.provisioned),
+            (&mut self.kernel, other.kernel),
+        ] {
+            region.read = region.read.saturating_add(other.read);
+            region.written = region.written.saturating_add(other.written);
+        }
+    }
+}
+
+/// Completion of the client's operation, held while the counters are read or
+/// stored.
+#[derive(Clone, Copy)]
+enum Held {
+    Read(usize, ReadStatus),
+    Write(usize, Result<(), ErrorCode>),
+}
+
+pub struct StorageWear<'a> {
+    storage: &'a dyn NonvolatileStorage<'a>,
+    client: OptionalCell<&'a dyn NonvolatileStorageClient>,
+    users: OptionalCell<&'a dyn StorageUsers>,
+    // Bytes read and written in each region.
+    counters: Cell<WearCounters>,
+    // Holds the counters while they are read or stored.
+    buffer: TakeCell<'static, [u8]>,
+    // Absolute address the counters are stored at.
+    address: OptionalCell<usize>,
+    // Store the counters once this many bytes were written since they were
+    // last stored.
+    batch: Cell<usize>,
+    // Bytes written since the counters were last read or stored.
+    unstored: Cell<usize>,
+    // Whether the stored counters were read, and the counters may be stored.
+    loaded: Cell<bool>,
+    // Whether the storage is reading or storing the counters.
+    busy: Cell<bool>,
+    // The client's completion, and its buffer, while `busy`.
+    held: OptionalCell<Held>,
+    held_buffer: TakeCell<'static, [u8]>,
+}
+
+impl<'a> StorageWear<'a> {
+    pub fn new(storage: &'a dyn NonvolatileStorage<'a>, buffer: &'static mut [u8]) -> Self {
+        Self {
+            storage,
+            client: OptionalCell::empty(),
+            users: OptionalCell::empty(),
+            counters: Cell::new(WearCounters::default()),
+            buffer: TakeCell::new(buffer),
+            address: OptionalCell::empty(),
+            batch: Cell::new(0),
+            unstored: Cell::new(0),
+            loaded: Cell::new(false),
+            busy: Cell::new(false),
+            held: OptionalCell::empty(),
+            held_buffer: TakeCell::empty(),
+        }
+    }
+
+    /// Learn the regions of the nonvolatile storage driver above this layer.
+    pub fn set_users(&self, users: &'a dyn StorageUsers) {
+        self.users.set(users);
+    }
+
+    /// Keep the counters at the absolute storage address `address`, and store
+    /// them every `batch` bytes written. The address must be in a part of the
+    /// kernel region no one else uses, and aligned to the write granularity
+    /// of the storage. The buffer given to `new()` must hold
+    /// `WEAR_COUNTERS_LEN` bytes.
+    pub fn set_counters(&self, address: usize, batch: usize) -> Result<(), ErrorCode> {
+        let aligned = self.storage.geometry().map_or(true, |geometry| {
+            let granularity = core::cmp::max(geometry.write_granularity, 1);
+            address % granularity == 0 && WEAR_COUNTERS_LEN % granularity == 0
+        });
+        if !aligned || batch == 0 {
+            return Err(ErrorCode::INVAL);
+        }
+        if self.buffer.map_or(0, |buffer| buffer.len()) < WEAR_COUNTERS_LEN {
+            return Err(ErrorCode::SIZE);
+        }
+        self.address.set(address);
+        self.batch.set(batch);
+        self.loaded.set(false);
+        // Read the stored counters after the next operation.
+        self.unstored.set(batch);
+        Ok(())
+    }
+
+    /// Bytes read from and written to each region of the storage.
+    pub fn wear_counters(&self) -> WearCounters {
+        self.counters.get()
+    }
+
+    // Count a read or write the storage accepted towards the region it
+    // starts in.
+    fn count(&self, address: usize, length: usize, write: bool) {
+        let mut counters = self.counters.get();
+        let (userspace, provisioned) = self.users.map_or((false, false), |users| {
+            (
+                users.userspace_range().overlaps(address, 1),
+                users.provisioned_range().overlaps(address, 1),
+            )
+        });
+        let region = if userspace {
+            &mut counters.userspace
+        } else if provisioned {
+            &mut counters.provisioned
+        } else {
+            &mut counters.kernel
+        };
+        if write {
+            region.written = region.written.saturating_add(length as u64);
+            self.unstored
+                .set(self.unstored.get().saturating_add(length));
+        } else {
+            region.read = region.read.saturating_add(length as u64);
+        }
+        self.counters.set(counters);
+    }
+
+    // Read the stored counters, or store them if enough was written since
+    // they were last stored. Returns whether the storage was started.
+    fn start_counters(&self) -> bool {
+        let address = match self.address.get() {
+            Some(address) => address,
+            None => return false,
+        };
+        if self.unstored.get() < self.batch.get() {
+            return false;
+        }
+        // The storage keeps the buffer if it rejects the operation, so the
+        // counters are only tried once per batch, and not at all once the
+        // buffer is gone.
+        let buffer = match self.buffer.take() {
+            Some(buffer) => buffer,
+            None => return false,
+        };
+        self.unstored.set(0);
+        let result = if self.loaded.get() {
+            self.counters.get().encode(buffer);
+            self.storage.write(buffer, address, WEAR_COUNTERS_LEN)
+        } else {
+            self.storage.read(buffer, address, WEAR_COUNTERS_LEN)
+        };
+        self.busy.set(result.is_ok());
+        result.is_ok()
+    }
+
+    // The counters were read or stored. Stored counters that were read are
+    // added to the ones counted since boot. Storage that was never written
+    // holds no counters, which still counts as a successful read.
+    fn counters_done(&self, buffer: &'static mut [u8], read: bool, valid: bool, length: usize) {
+        self.busy.set(false);
+        if read && valid && length == WEAR_COUNTERS_LEN {
+            self.loaded.set(true);
+            if let Some(stored) = WearCounters::decode(&buffer[..length]) {
+                let mut counters = self.counters.get();
+                counters.add(&stored);
+                self.counters.set(counters);
+            }
+        }
+        self.buffer.replace(buffer);
+
+        if let (Some(held), Some(buffer)) = (self.held.take(), self.held_buffer.take()) {
+            self.forward(held, buffer);
+        }
+    }
+
+    // Pass a completion of the client's operation on, unless the counters are
+    // read or stored first.
+    fn complete(&self, held: Held, buffer: &'static mut [u8]) {
+        if self.start_counters() {
+            self.held.set(held);
+            self.held_buffer.replace(buffer);
+        } else {
+            self.forward(held, buffer);
+        }
+    }
+
+    fn forward(&self, held: Held, buffer: &'static mut [u8]) {
+        self.client.map(move |client| match held {
+            Held::Read(length, status) => client.read_done_status(buffer, length, status),
+            Held::Write(length, result) => client.write_done_result(buffer, length, result),
+        });
+    }
+}
+
+impl<'a> NonvolatileStorage<'a> for StorageWear<'a> {
+    fn set_client(&self, client: &'a dyn NonvolatileStorageClient) {
+        self.client.set(client);
+    }
+
+    fn read(
+        &self,
+        buffer: &'static mut [u8],
+        address: usize,
+        length: usize,
+    ) -> Result<(), ErrorCode> {
+        if self.busy.get() {
+            return Err(ErrorCode::BUSY);
+        }
+        self.storage.read(buffer, address, length)?;
+        self.count(address, length, false);
+        Ok(())
+    }
+
+    fn write(
+        &self,
+        buffer: &'static mut [u8],
+        address: usize,
+        length: usize,
+    ) -> Result<(), ErrorCode> {
+        if self.busy.get() {
+            return Err(ErrorCode::BUSY);
+        }
+        self.storage.write(buffer, address, length)?;
+        self.count(address, length, true);
+        Ok(())
+    }
+
+    fn sync(&self) -> Result<(), ErrorCode> {
+        if self.busy.get() {
+            return Err(ErrorCode::BUSY);
+        }
+        self.storage.sync()
+    }
+
+    fn geometry(&self) -> Option<StorageGeometry> {
+        self.storage.geometry()
+    }
+
+    fn reset(&self) -> Result<(), ErrorCode> {
+        self.storage.reset()
+    }
+
+    fn read_mapped(&self, address: usize, buffer: &mut [u8]) -> Result<(), ErrorCode> {
+        self.storage.read_mapped(address, buffer)?;
+        self.count(address, buffer.len(), false);
+        Ok(())
+    }
+
+    fn write_now(&self, address: usize, data: &[u8]) -> Result<(), ErrorCode> {
+        self.storage.write_now(address, data)?;
+        self.count(address, data.len(), true);
+        Ok(())
+    }
+}
+
+impl NonvolatileStorageClient for StorageWear<'_> {
+    fn read_done(&self, buffer: &'static mut [u8], length: usize) {
+        self.read_done_status(buffer, length, ReadStatus::Ok);
+    }
+
+    fn read_done_status(&self, buffer: &'static mut [u8], length: usize, status: ReadStatus) {
+        if self.busy.get() {
+            self.counters_done(buffer, true, status.is_valid(), length);
+        } else {
+            self.complete(Held::Read(length, status), buffer);
+        }
+    }
+
+    fn write_done(&self, buffer: &'static mut [u8], length: usize) {
+        self.write_done_result(buffer, length, Ok(()));
+    }
+
+    fn write_done_result(
+        &self,
+        buffer: &'static mut [u8],
+        length: usize,
+        result: Result<(), ErrorCode>,
+    ) {
+        if self.busy.get() {
+            self.counters_done(buffer, false, result.is_ok(), length);
+        } else {
+            self.complete(Held::Write(length, result), buffer);
+        }
+    }
+
+    fn sync_done(&self, result: Result<(), ErrorCode>) {
+        self.client.map(|client| client.sync_done(result));
+    }
+}
+
+impl SyscallDriver for StorageWear<'_> {
+    /// Read the wear counters.
+    ///
+    /// ### `command_num`
+    ///
+    /// - `0`: Driver check.
+    /// - `1`: Return how many bytes were read from (with a second argument of
+    ///   0) or written to (with 1) the region given as the first argument, as
+    ///   a `u64`: 0 for the userspace region, 1 for the provisioned region,
+    ///   and 2 for the kernel region. The counts cover every app and the
+    ///   kernel, since the counters were first stored, or since boot if the
+    ///   board does not store them.
+    fn command(
+        &self,
+        command_num: usize,
+        region: usize,
+        written: usize,
+        _processid: ProcessId,
+    ) -> CommandReturn {
+        match command_num {
+            0 => CommandReturn::success(),
+
+            1 => {
+                let counters = self.counters.get();
+                match (counters.regions().get(region), written) {
+                    (Some(region), 0) => CommandReturn::success_u64(region.read),
+                    (Some(region), 1) => CommandReturn::success_u64(region.written),
+                    _ => CommandReturn::failure(ErrorCode::INVAL),
+                }
+            }
+
+            _ => CommandReturn::failure(ErrorCode::NOSUPPORT),
+        }
+    }
+
+    fn allocate_grant(&self, _processid: ProcessId) -> Result<(), kernel::process::Error> {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use std::boxed::Box;
+
+    use kernel::hil::nonvolatile_storage::{NonvolatileStorage, ReadStatus};
+    use kernel::ErrorCode;
+
+    use super::{RegionWear, StorageWear, WearCounters, WEAR_COUNTERS_LEN};
+    use crate::test::mock_storage::{buffer, Done, MockClient, MockStorage, Op};
+
+    const ADDRESS: usize = 0x300;
+
+    fn stack() -> (
+        &'static MockStorage<'static>,
+        &'static StorageWear<'static>,
+        &'static MockClient,
+    ) {
+        let storage = Box::leak(Box::new(MockStorage::new()));
+        let wear = Box::leak(Box::new(StorageWear::new(
+            storage,
+            buffer(WEAR_COUNTERS_LEN),
+        )));
+        let client = Box::leak(Box::new(MockClient::new()));
+        storage.set_client(wear);
+        wear.set_client(client);
+        (storage, wear, client)
+    }
+
+    fn stored(counters: &WearCounters) -> [u8; WEAR_COUNTERS_LEN] {
+        let mut data = [0; WEAR_COUNTERS_LEN];
+        counters.encode(&mut data);
+        data
+    }
+
+    fn kernel(read: u64, written: u64) -> WearCounters {
+        WearCounters {
+            kernel: RegionWear { read, written },
+            ..WearCounters::default()
+        }
+    }
+
+    #[test]
+    fn stored_counters_are_added_after_the_first_operation() {
+        let (storage, wear, client) = stack();
+        storage.data.borrow_mut()[ADDRESS..ADDRESS + WEAR_COUNTERS_LEN]
+            .copy_from_slice(&stored(&kernel(100, 200)));
+        wear.set_counters(ADDRESS, 64).unwrap();
+
+        wear.read(buffer(4), 0, 4).unwrap();
+        storage.complete();
+        // The read is passed on once the counters were read.
+        assert!(client.take().is_empty());
+        assert_eq!(
+            storage.pending(),
+            Some(Op::Read(ADDRESS, WEAR_COUNTERS_LEN))
+        );
+        storage.complete();
+        assert_eq!(client.take(), [Done::Read(4, ReadStatus::Ok)]);
+        assert_eq!(wear.wear_counters(), kernel(104, 200));
+    }
+
+    #[test]
+    fn counters_are_stored_every_batch() {
+        let (storage, wear, client) = stack();
+        wear.set_counters(ADDRESS, 16).unwrap();
+        wear.write(buffer(8), 0, 8).unwrap();
+        storage.complete();
+        // Erased storage holds no counters, which counts as read.
+        storage.complete();
+        assert_eq!(client.take(), [Done::Write(8, Ok(()))]);
+
+        wear.write(buffer(8), 0, 8).unwrap();
+        storage.complete();
+        assert!(storage.pending().is_none());
+        wear.write(buffer(8), 0, 8).unwrap();
+        storage.complete();
+        assert_eq!(
+            storage.pending(),
+            Some(Op::Write(ADDRESS, WEAR_COUNTERS_LEN))
+        );
+        storage.complete();
+        assert_eq!(
+            client.take(),
+            [Done::Write(8, Ok(())), Done::Write(8, Ok(()))]
+        );
+        assert_eq!(
+            storage.data.borrow()[ADDRESS..ADDRESS + WEAR_COUNTERS_LEN],
+            stored(&kernel(0, 24))
+        );
+    }
+
+    #[test]
+    fn unreadable_counters_are_read_again_instead_of_overwritten() {
+        let (storage, wear, _) = stack();
+        storage.data.borrow_mut()[ADDRESS..ADDRESS + WEAR_COUNTERS_LEN]
+            .copy_from_slice(&stored(&kernel(100, 200)));
+        wear.set_counters(ADDRESS, 8).unwrap();
+        wear.write(buffer(8), 0, 8).unwrap();
+        storage.complete();
+        storage.complete_read(ReadStatus::Uncorrectable);
+
+        // The next batch reads them again, and only then are they stored.
+        wear.write(buffer(8), 0, 8).unwrap();
+        storage.complete();
+        assert_eq!(
+            storage.pending(),
+            Some(Op::Read(ADDRESS, WEAR_COUNTERS_LEN))
+        );
+        storage.complete();
+        assert_eq!(wear.wear_counters(), kernel(100, 216));
+        wear.write(buffer(8), 0, 8).unwrap();
+        storage.complete();
+        assert_eq!(
+            storage.pending(),
+            Some(Op::Write(ADDRESS, WEAR_COUNTERS_LEN))
+        );
+    }
+
+    #[test]
+    fn refused_read_of_the_counters_passes_the_completion_on() {
+        let (storage, wear, client) = stack();
+        wear.set_counters(ADDRESS, 8).unwrap();
+        wear.write(buffer(8), 0, 8).unwrap();
+        storage.refuse_next(ErrorCode::BUSY);
+        storage.complete();
+        assert_eq!(client.take(), [Done::Write(8, Ok(()))]);
+
+        // The storage kept the counters' buffer, so they are never stored,
+        // but the storage stays usable.
+        wear.write(buffer(8), 0, 8).unwrap();
+        storage.complete();
+        assert!(storage.pending().is_none());
+        assert_eq!(client.take(), [Done::Write(8, Ok(()))]);
+        assert_eq!(wear.wear_counters(), kernel(0, 16));
+    }
+
+    #[test]
+    fn operations_wait_for_the_counters() {
+        let (storage, wear, _) = stack();
+        wear.set_counters(ADDRESS, 8).unwrap();
+        wear.write(buffer(8), 0, 8).unwrap();
+        storage.complete();
+        assert_eq!(wear.read(buffer(4), 0, 4), Err(ErrorCode::BUSY));
+        assert_eq!(wear.sync(), Err(ErrorCode::BUSY));
+    }
+
+    #[test]
+    fn counters_that_do_not_fit_are_refused() {
+        let storage = Box::leak(Box::new(MockStorage::new()));
+        let wear = StorageWear::new(storage, buffer(WEAR_COUNTERS_LEN - 1));
+        assert_eq!(wear.set_counters(ADDRESS, 8), Err(ErrorCode::SIZE));
+        assert_eq!(wear.set_counters(ADDRESS + 2, 8), Err(ErrorCode::INVAL));
+        assert_eq!(wear.set_counters(ADDRESS, 0), Err(ErrorCode::INVAL));
+    }
+}
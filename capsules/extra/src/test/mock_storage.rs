@@ -0,0 +1,270 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2024.
+
+//! RAM-backed nonvolatile storage and a recording client, for the unit tests
+//! of capsules stacked on a `NonvolatileStorage`.
+//!
+//! `MockStorage` keeps each operation until the test finishes it with
+//! `complete()`, the way an interrupt would, and can be told to refuse the
+//! next operation. A refused read or write keeps the buffer, as storage that
+//! rejects an operation may. `MockClient` records every callback it gets and
+//! keeps the buffers handed back.
+
+extern crate std;
+
+use core::cell::{Cell, RefCell};
+use std::boxed::Box;
+use std::vec::Vec;
+
+use kernel::hil::nonvolatile_storage::{
+    NonvolatileStorage, NonvolatileStorageClient, ReadStatus, StorageGeometry,
+};
+use kernel::utilities::cells::{OptionalCell, TakeCell};
+use kernel::ErrorCode;
+
+/// Size of the storage, in bytes.
+pub const SIZE: usize = 0x400;
+
+/// An operation the storage was given.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Op {
+    Read(usize, usize),
+    Write(usize, usize),
+    Sync,
+}
+
+/// A callback the client got.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Done {
+    Read(usize, ReadStatus),
+    Write(usize, Result<(), ErrorCode>),
+    Sync(Result<(), ErrorCode>),
+}
+
+/// A `&'static mut` buffer of `len` bytes for a test.
+pub fn buffer(len: usize) -> &'static mut [u8] {
+    Box::leak(std::vec![0; len].into_boxed_slice())
+}
+
+pub struct MockStorage<'a> {
+    pub data: RefCell<[u8; SIZE]>,
+    client: OptionalCell<&'a dyn NonvolatileStorageClient>,
+    pending: Cell<Option<Op>>,
+    buffer: TakeCell<'static, [u8]>,
+    // Buffers of operations that were refused.
+    kept: RefCell<Vec<&'static mut [u8]>>,
+    refuse: Cell<Option<ErrorCode>>,
+    // Every operation the storage accepted, in order.
+    pub ops: RefCell<Vec<Op>>,
+    pub resets: Cell<usize>,
+}
+
+impl<'a> MockStorage<'a> {
+    pub fn new() -> Self {
+        MockStorage {
+            data: RefCell::new([0xff; SIZE]),
+            client: OptionalCell::empty(),
+            pending: Cell::new(None),
+            buffer: TakeCell::empty(),
+            kept: RefCell::new(Vec::new()),
+            refuse: Cell::new(None),
+            ops: RefCell::new(Vec::new()),
+            resets: Cell::new(0),
+        }
+    }
+
+    /// Refuse the next operation with `error`.
+    pub fn refuse_next(&self, error: ErrorCode) {
+        self.refuse.set(Some(error));
+    }
+
+    /// The operation the storage is working on.
+    pub fn pending(&self) -> Option<Op> {
+        self.pending.get()
+    }
+
+    /// Number of buffers the storage kept from refused operations.
+    pub fn kept(&self) -> usize {
+        self.kept.borrow().len()
+    }
+
+    /// Finish the pending operation.
+    pub fn complete(&self) {
+        self.complete_read(ReadStatus::Ok)
+    }
+
+    /// Finish the pending operation, reporting `status` if it is a read.
+    pub fn complete_read(&self, status: ReadStatus) {
+        let op = self.pending.take().expect("an operation to complete");
+        match op {
+            Op::Read(address, length) => {
+                let buffer = self.buffer.take().unwrap();
+                buffer[..length].copy_from_slice(&self.data.borrow()[address..address + length]);
+                self.client
+                    .map(move |client| client.read_done_status(buffer, length, status));
+            }
+            Op::Write(address, length) => {
+                let buffer = self.buffer.take().unwrap();
+                self.data.borrow_mut()[address..address + length]
+                    .copy_from_slice(&buffer[..length]);
+                self.client
+                    .map(move |client| client.write_done(buffer, length));
+            }
+            Op::Sync => {
+                self.client.map(|client| client.sync_done(Ok(())));
+            }
+        }
+    }
+
+    /// Finish the pending write with `result`, storing the data only if it
+    /// succeeded.
+    pub fn complete_write(&self, result: Result<(), ErrorCode>) {
+        match self.pending.take() {
+            Some(Op::Write(address, length)) => {
+                let buffer = self.buffer.take().unwrap();
+                if result.is_ok() {
+                    self.data.borrow_mut()[address..address + length]
+                        .copy_from_slice(&buffer[..length]);
+                }
+                self.client
+                    .map(move |client| client.write_done_result(buffer, length, result));
+            }
+            op => panic!("expected a write to complete, not {:?}", op),
+        }
+    }
+
+    fn start(
+        &self,
+        op: Op,
+        buffer: Option<&'static mut [u8]>,
+        length: usize,
+    ) -> Result<(), ErrorCode> {
+        let error = match self.refuse.take() {
+            Some(error) => Some(error),
+            None if self.pending.get().is_some() => Some(ErrorCode::BUSY),
+            None if buffer
+                .as_ref()
+                .map_or(false, |buffer| buffer.len() < length) =>
+            {
+                Some(ErrorCode::SIZE)
+            }
+            None => None,
+        };
+        if let Some(error) = error {
+            buffer.map(|buffer| self.kept.borrow_mut().push(buffer));
+            return Err(error);
+        }
+        buffer.map(|buffer| self.buffer.replace(buffer));
+        self.pending.set(Some(op));
+        self.ops.borrow_mut().push(op);
+        Ok(())
+    }
+}
+
+impl<'a> NonvolatileStorage<'a> for MockStorage<'a> {
+    fn set_client(&self, client: &'a dyn NonvolatileStorageClient) {
+        self.client.set(client);
+    }
+
+    fn read(
+        &self,
+        buffer: &'static mut [u8],
+        address: usize,
+        length: usize,
+    ) -> Result<(), ErrorCode> {
+        self.start(Op::Read(address, length), Some(buffer), length)
+    }
+
+    fn write(
+        &self,
+        buffer: &'static mut [u8],
+        address: usize,
+        length: usize,
+    ) -> Result<(), ErrorCode> {
+        self.start(Op::Write(address, length), Some(buffer), length)
+    }
+
+    fn sync(&self) -> Result<(), ErrorCode> {
+        self.start(Op::Sync, None, 0)
+    }
+
+    fn geometry(&self) -> Option<StorageGeometry> {
+        Some(StorageGeometry {
+            write_granularity: 4,
+            erase_block_size: 0x100,
+            total_size: SIZE,
+        })
+    }
+
+    fn reset(&self) -> Result<(), ErrorCode> {
+        self.resets.set(self.resets.get() + 1);
+        Ok(())
+    }
+
+    fn read_mapped(&self, address: usize, buffer: &mut [u8]) -> Result<(), ErrorCode> {
+        let data = self.data.borrow();
+        let data = data
+            .get(address..address + buffer.len())
+            .ok_or(ErrorCode::INVAL)?;
+        buffer.copy_from_slice(data);
+        Ok(())
+    }
+}
+
+/// Records the callbacks of the storage below it.
+pub struct MockClient {
+    pub done: RefCell<Vec<Done>>,
+    pub buffers: RefCell<Vec<&'static mut [u8]>>,
+}
+
+impl MockClient {
+    pub fn new() -> Self {
+        MockClient {
+            done: RefCell::new(Vec::new()),
+            buffers: RefCell::new(Vec::new()),
+        }
+    }
+
+    /// Take the callbacks recorded so far.
+    pub fn take(&self) -> Vec<Done> {
+        self.done.take()
+    }
+
+    /// Take a buffer handed back to the client.
+    pub fn buffer(&self) -> &'static mut [u8] {
+        self.buffers
+            .borrow_mut()
+            .pop()
+            .expect("a buffer handed back")
+    }
+}
+
+impl NonvolatileStorageClient for MockClient {
+    fn read_done(&self, buffer: &'static mut [u8], length: usize) {
+        self.read_done_status(buffer, length, ReadStatus::Ok);
+    }
+
+    fn read_done_status(&self, buffer: &'static mut [u8], length: usize, status: ReadStatus) {
+        self.done.borrow_mut().push(Done::Read(length, status));
+        self.buffers.borrow_mut().push(buffer);
+    }
+
+    fn write_done(&self, buffer: &'static mut [u8], length: usize) {
+        self.write_done_result(buffer, length, Ok(()));
+    }
+
+    fn write_done_result(
+        &self,
+        buffer: &'static mut [u8],
+        length: usize,
+        result: Result<(), ErrorCode>,
+    ) {
+        self.done.borrow_mut().push(Done::Write(length, result));
+        self.buffers.borrow_mut().push(buffer);
+    }
+
+    fn sync_done(&self, result: Result<(), ErrorCode>) {
+        self.done.borrow_mut().push(Done::Sync(result));
+    }
+}
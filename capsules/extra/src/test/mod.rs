@@ -8,6 +8,10 @@ pub mod aes_gcm;
 pub mod crc;
 pub mod hmac_sha256;
 pub mod kv_system;
+#[cfg(test)]
+pub mod mock_storage;
+pub mod nonvolatile_storage;
+pub mod nonvolatile_storage_latency;
 pub mod sha256;
 pub mod siphash24;
 pub mod udp;
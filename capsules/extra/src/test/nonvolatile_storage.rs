@@ -0,0 +1,326 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2024.
+
+//! Test how the nonvolatile storage driver handles operations that the
+//! underlying storage refuses to start.
+//!
+//! The driver is stacked on top of `FaultyRamStorage`, a RAM-backed storage
+//! that can be told to refuse its next operation. The test checks that:
+//!
+//! 1. A kernel write refused immediately by the storage returns the error and
+//!    does not leave the driver waiting for a callback, so the next write
+//!    starts right away.
+//! 2. A queued kernel read that the storage refuses when the driver starts it
+//!    does not block later operations.
+
+use core::cell::Cell;
+
+use crate::nonvolatile_storage_driver::NonvolatileStorage;
+use capsules_core::test::capsule_test::{CapsuleTest, CapsuleTestClient, CapsuleTestError};
+use kernel::debug;
+use kernel::deferred_call::{DeferredCall, DeferredCallClient};
+use kernel::hil::nonvolatile_storage::{self, NonvolatileStorageClient};
+use kernel::utilities::cells::{OptionalCell, TakeCell};
+use kernel::ErrorCode;
+
+#[derive(Clone, Copy, PartialEq)]
+enum Operation {
+    Read { address: usize, length: usize },
+    Write { address: usize, length: usize },
+}
+
+/// RAM-backed nonvolatile storage that can refuse operations on request.
+///
+/// Operations complete from a deferred call. Buffers passed to refused
+/// operations are kept and can be retrieved with `take_refused_buffer()`,
+/// since the nonvolatile storage HIL has no way to return them.
+pub struct FaultyRamStorage<'a> {
+    memory: TakeCell<'static, [u8]>,
+    client: OptionalCell<&'a dyn NonvolatileStorageClient>,
+    buffer: TakeCell<'static, [u8]>,
+    operation: OptionalCell<Operation>,
+    fail_next: OptionalCell<ErrorCode>,
+    refused_buffer: TakeCell<'static, [u8]>,
+    deferred_call: DeferredCall,
+}
+
+impl<'a> FaultyRamStorage<'a> {
+    pub fn new(memory: &'static mut [u8]) -> Self {
+        FaultyRamStorage {
+            memory: TakeCell::new(memory),
+            client: OptionalCell::empty(),
+            buffer: TakeCell::empty(),
+            operation: OptionalCell::empty(),
+            fail_next: OptionalCell::empty(),
+            refused_buffer: TakeCell::empty(),
+            deferred_call: DeferredCall::new(),
+        }
+    }
+
+    /// Refuse the next read or write with `error`.
+    pub fn fail_next(&self, error: ErrorCode) {
+        self.fail_next.set(error);
+    }
+
+    /// Get back the buffer of the last refused operation.
+    pub fn take_refused_buffer(&self) -> Option<&'static mut [u8]> {
+        self.refused_buffer.take()
+    }
+
+    /// Run `f` on the contents of the storage.
+    pub fn map_memory<R, F: FnOnce(&mut [u8]) -> R>(&self, f: F) -> Option<R> {
+        self.memory.map(f)
+    }
+
+    fn start(&self, buffer: &'static mut [u8], operation: Operation) -> Result<(), ErrorCode> {
+        if let Some(error) = self.fail_next.take() {
+            self.refused_buffer.replace(buffer);
+            return Err(error);
+        }
+        if self.operation.is_some() {
+            self.refused_buffer.replace(buffer);
+            return Err(ErrorCode::BUSY);
+        }
+        self.buffer.replace(buffer);
+        self.operation.set(operation);
+        self.deferred_call.set();
+        Ok(())
+    }
+}
+
+impl<'a> nonvolatile_storage::NonvolatileStorage<'a> for FaultyRamStorage<'a> {
+    fn set_client(&self, client: &'a dyn NonvolatileStorageClient) {
+        self.client.set(client);
+    }
+
+    fn read(
+        &self,
+        buffer: &'static mut [u8],
+        address: usize,
+        length: usize,
+    ) -> Result<(), ErrorCode> {
+        self.start(buffer, Operation::Read { address, length })
+    }
+
+    fn write(
+        &self,
+        buffer: &'static mut [u8],
+        address: usize,
+        length: usize,
+    ) -> Result<(), ErrorCode> {
+        self.start(buffer, Operation::Write { address, length })
+    }
+}
+
+impl DeferredCallClient for FaultyRamStorage<'_> {
+    fn handle_deferred_call(&self) {
+        let operation = self.operation.take();
+        let buffer = self.buffer.take();
+        if let (Some(operation), Some(buffer)) = (operation, buffer) {
+            match operation {
+                Operation::Read { address, length } => {
+                    self.memory.map(|memory| {
+                        buffer[..length].copy_from_slice(&memory[address..address + length]);
+                    });
+                    self.client
+                        .map(move |client| client.read_done(buffer, length));
+                }
+                Operation::Write { address, length } => {
+                    self.memory.map(|memory| {
+                        memory[address..address + length].copy_from_slice(&buffer[..length]);
+                    });
+                    self.client
+                        .map(move |client| client.write_done(buffer, length));
+                }
+            }
+        }
+    }
+
+    fn register(&'static self) {
+        self.deferred_call.register(self);
+    }
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum TestState {
+    Idle,
+    Write,
+    ReadBeforeQueuedFailure,
+    ReadAfterQueuedFailure,
+}
+
+/// Address of the test data. The driver's kernel region must include it.
+const TEST_ADDRESS: usize = 0;
+/// Length of the test data.
+const TEST_LENGTH: usize = 16;
+
+pub struct TestNonvolatileStorageErrors<'a> {
+    driver: &'a NonvolatileStorage<'a>,
+    storage: &'a FaultyRamStorage<'a>,
+    buffer1: TakeCell<'static, [u8]>,
+    buffer2: TakeCell<'static, [u8]>,
+    state: Cell<TestState>,
+    deferred_call: DeferredCall,
+    client: OptionalCell<&'static dyn CapsuleTestClient>,
+}
+
+impl<'a> TestNonvolatileStorageErrors<'a> {
+    pub fn new(
+        driver: &'a NonvolatileStorage<'a>,
+        storage: &'a FaultyRamStorage<'a>,
+        buffer1: &'static mut [u8],
+        buffer2: &'static mut [u8],
+    ) -> Self {
+        TestNonvolatileStorageErrors {
+            driver,
+            storage,
+            buffer1: TakeCell::new(buffer1),
+            buffer2: TakeCell::new(buffer2),
+            state: Cell::new(TestState::Idle),
+            deferred_call: DeferredCall::new(),
+            client: OptionalCell::empty(),
+        }
+    }
+
+    pub fn run(&self) {
+        let buffer = self.buffer1.take().unwrap();
+        for (i, b) in buffer[..TEST_LENGTH].iter_mut().enumerate() {
+            *b = i as u8;
+        }
+
+        // A write refused synchronously must be reported to the caller...
+        self.storage.fail_next(ErrorCode::BUSY);
+        let res = nonvolatile_storage::NonvolatileStorage::write(
+            self.driver,
+            buffer,
+            TEST_ADDRESS,
+            TEST_LENGTH,
+        );
+        if res != Err(ErrorCode::BUSY) {
+            self.fail("refused write did not return BUSY", res);
+            return;
+        }
+
+        // ...and the next write must start immediately instead of being
+        // queued behind an operation that will never complete.
+        let buffer = self.storage.take_refused_buffer().unwrap();
+        self.state.set(TestState::Write);
+        let res = nonvolatile_storage::NonvolatileStorage::write(
+            self.driver,
+            buffer,
+            TEST_ADDRESS,
+            TEST_LENGTH,
+        );
+        if res.is_err() {
+            self.fail("write after refused write failed", res);
+        }
+    }
+
+    fn fail(&self, message: &str, res: Result<(), ErrorCode>) {
+        debug!(
+            "NonvolatileStorageErrors test FAILED: {} ({:?})",
+            message, res
+        );
+        self.client.map(|client| {
+            client.done(Err(CapsuleTestError::IncorrectResult));
+        });
+    }
+
+    fn check_contents(&self, buffer: &[u8]) -> bool {
+        buffer[..TEST_LENGTH]
+            .iter()
+            .enumerate()
+            .all(|(i, b)| *b == i as u8)
+    }
+}
+
+impl CapsuleTest for TestNonvolatileStorageErrors<'_> {
+    fn set_client(&self, client: &'static dyn CapsuleTestClient) {
+        self.client.set(client);
+    }
+}
+
+impl NonvolatileStorageClient for TestNonvolatileStorageErrors<'_> {
+    fn read_done(&self, buffer: &'static mut [u8], _length: usize) {
+        if !self.check_contents(buffer) {
+            self.fail("read back wrong data", Ok(()));
+            return;
+        }
+        match self.state.get() {
+            TestState::ReadBeforeQueuedFailure => {
+                // The queued read is started by the driver after this
+                // callback returns, and it will be refused. Continue once
+                // that has happened.
+                self.buffer1.replace(buffer);
+                self.deferred_call.set();
+            }
+            TestState::ReadAfterQueuedFailure => {
+                self.buffer2.replace(buffer);
+                self.state.set(TestState::Idle);
+                debug!("NonvolatileStorageErrors test passed");
+                self.client.map(|client| client.done(Ok(())));
+            }
+            _ => self.fail("unexpected read_done", Ok(())),
+        }
+    }
+
+    fn write_done(&self, buffer: &'static mut [u8], length: usize) {
+        if self.state.get() != TestState::Write || length != TEST_LENGTH {
+            self.fail("unexpected write_done", Ok(()));
+            return;
+        }
+
+        // Start one read and queue a second one behind it. The storage will
+        // refuse the second one when the driver tries to start it.
+        self.state.set(TestState::ReadBeforeQueuedFailure);
+        let res = nonvolatile_storage::NonvolatileStorage::read(
+            self.driver,
+            buffer,
+            TEST_ADDRESS,
+            TEST_LENGTH,
+        );
+        if res.is_err() {
+            self.fail("first read failed", res);
+            return;
+        }
+        let res = nonvolatile_storage::NonvolatileStorage::read(
+            self.driver,
+            self.buffer2.take().unwrap(),
+            TEST_ADDRESS,
+            TEST_LENGTH,
+        );
+        if res.is_err() {
+            self.fail("queueing second read failed", res);
+            return;
+        }
+        self.storage.fail_next(ErrorCode::FAIL);
+    }
+}
+
+impl DeferredCallClient for TestNonvolatileStorageErrors<'_> {
+    fn handle_deferred_call(&self) {
+        // The refused queued read must not block a new read.
+        let buffer = match self.storage.take_refused_buffer() {
+            Some(buffer) => buffer,
+            None => {
+                self.fail("queued read was not refused", Ok(()));
+                return;
+            }
+        };
+        self.state.set(TestState::ReadAfterQueuedFailure);
+        let res = nonvolatile_storage::NonvolatileStorage::read(
+            self.driver,
+            buffer,
+            TEST_ADDRESS,
+            TEST_LENGTH,
+        );
+        if res.is_err() {
+            self.fail("read after refused queued read failed", res);
+        }
+    }
+
+    fn register(&'static self) {
+        self.deferred_call.register(self);
+    }
+}
@@ -0,0 +1,488 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2024.
+
+//! Test the order in which the nonvolatile storage driver completes
+//! operations when the underlying storage is slow.
+//!
+//! The driver is stacked on top of `SimulatedStorage`, a RAM-backed storage
+//! that finishes each operation after a scripted latency in simulated time.
+//! Simulated time advances by one millisecond per deferred call while
+//! anything is outstanding, so the results do not depend on the speed of the
+//! board. The storage is also the driver's operation timer, in the same
+//! simulated time.
+//!
+//! The test checks that:
+//!
+//! 1. A write completes after exactly its scripted latency.
+//! 2. A read queued behind a slow write completes after the write.
+//! 3. The queued read returns the data of the write.
+//! 4. An operation slower than the operation timeout fails, and is counted.
+//! 5. An operation after a timeout completes normally.
+//! 6. A sync queued behind a write completes after the write.
+//!
+//! Results are printed in the Test Anything Protocol (TAP) format. Fairness
+//! between apps needs processes, which the test kernel does not load, so only
+//! kernel operations are covered.
+
+use core::cell::Cell;
+
+use crate::nonvolatile_storage_driver::{NonvolatileStorage, OperationTimer};
+use capsules_core::test::capsule_test::{CapsuleTest, CapsuleTestClient, CapsuleTestError};
+use kernel::debug;
+use kernel::deferred_call::{DeferredCall, DeferredCallClient};
+use kernel::hil::nonvolatile_storage::{self, NonvolatileStorageClient};
+use kernel::hil::time::AlarmClient;
+use kernel::utilities::cells::{OptionalCell, TakeCell};
+use kernel::ErrorCode;
+
+#[derive(Clone, Copy, PartialEq)]
+enum Operation {
+    Read { address: usize, length: usize },
+    Write { address: usize, length: usize },
+    Sync,
+}
+
+/// RAM-backed nonvolatile storage with scripted latencies.
+///
+/// Each operation takes the next latency from the script, in milliseconds of
+/// simulated time. Once the script runs out, operations complete in 1 ms.
+pub struct SimulatedStorage<'a> {
+    memory: TakeCell<'static, [u8]>,
+    client: OptionalCell<&'a dyn NonvolatileStorageClient>,
+    timer_client: OptionalCell<&'a dyn AlarmClient>,
+    latencies: Cell<&'static [u32]>,
+    buffer: TakeCell<'static, [u8]>,
+    operation: OptionalCell<Operation>,
+    // Simulated time in milliseconds, and when the operation and the timer
+    // are due.
+    now: Cell<u32>,
+    done_at: Cell<u32>,
+    timer_at: OptionalCell<u32>,
+    // The operation was reset and completes without doing anything.
+    aborted: Cell<bool>,
+    deferred_call: DeferredCall,
+}
+
+impl<'a> SimulatedStorage<'a> {
+    pub fn new(memory: &'static mut [u8]) -> Self {
+        SimulatedStorage {
+            memory: TakeCell::new(memory),
+            client: OptionalCell::empty(),
+            timer_client: OptionalCell::empty(),
+            latencies: Cell::new(&[]),
+            buffer: TakeCell::empty(),
+            operation: OptionalCell::empty(),
+            now: Cell::new(0),
+            done_at: Cell::new(0),
+            timer_at: OptionalCell::empty(),
+            aborted: Cell::new(false),
+            deferred_call: DeferredCall::new(),
+        }
+    }
+
+    /// Use `latencies` for the next operations, in order.
+    pub fn set_latencies(&self, latencies: &'static [u32]) {
+        self.latencies.set(latencies);
+    }
+
+    /// Client called when the operation timer fires.
+    pub fn set_timer_client(&self, client: &'a dyn AlarmClient) {
+        self.timer_client.set(client);
+    }
+
+    /// Current simulated time in milliseconds.
+    pub fn now(&self) -> u32 {
+        self.now.get()
+    }
+
+    fn start_operation(
+        &self,
+        buffer: Option<&'static mut [u8]>,
+        operation: Operation,
+    ) -> Result<(), ErrorCode> {
+        if self.operation.is_some() {
+            return Err(ErrorCode::BUSY);
+        }
+        let latencies = self.latencies.get();
+        let latency = match latencies.split_first() {
+            Some((latency, rest)) => {
+                self.latencies.set(rest);
+                *latency
+            }
+            None => 1,
+        };
+        if let Some(buffer) = buffer {
+            self.buffer.replace(buffer);
+        }
+        self.operation.set(operation);
+        self.done_at.set(self.now.get() + latency);
+        self.deferred_call.set();
+        Ok(())
+    }
+
+    fn complete(&self, operation: Operation) {
+        let aborted = self.aborted.take();
+        match operation {
+            Operation::Read { address, length } => {
+                if let Some(buffer) = self.buffer.take() {
+                    let length = if aborted { 0 } else { length };
+                    self.memory.map(|memory| {
+                        buffer[..length].copy_from_slice(&memory[address..address + length]);
+                    });
+                    self.client
+                        .map(move |client| client.read_done(buffer, length));
+                }
+            }
+            Operation::Write { address, length } => {
+                if let Some(buffer) = self.buffer.take() {
+                    let length = if aborted { 0 } else { length };
+                    self.memory.map(|memory| {
+                        memory[address..address + length].copy_from_slice(&buffer[..length]);
+                    });
+                    self.client
+                        .map(move |client| client.write_done(buffer, length));
+                }
+            }
+            Operation::Sync => {
+                let result = if aborted {
+                    Err(ErrorCode::FAIL)
+                } else {
+                    Ok(())
+                };
+                self.client.map(|client| client.sync_done(result));
+            }
+        }
+    }
+}
+
+impl<'a> nonvolatile_storage::NonvolatileStorage<'a> for SimulatedStorage<'a> {
+    fn set_client(&self, client: &'a dyn NonvolatileStorageClient) {
+        self.client.set(client);
+    }
+
+    fn read(
+        &self,
+        buffer: &'static mut [u8],
+        address: usize,
+        length: usize,
+    ) -> Result<(), ErrorCode> {
+        self.start_operation(Some(buffer), Operation::Read { address, length })
+    }
+
+    fn write(
+        &self,
+        buffer: &'static mut [u8],
+        address: usize,
+        length: usize,
+    ) -> Result<(), ErrorCode> {
+        self.start_operation(Some(buffer), Operation::Write { address, length })
+    }
+
+    fn sync(&self) -> Result<(), ErrorCode> {
+        self.start_operation(None, Operation::Sync)
+    }
+
+    fn reset(&self) -> Result<(), ErrorCode> {
+        if self.operation.is_none() {
+            return Err(ErrorCode::OFF);
+        }
+        // Complete the aborted operation on the next step.
+        self.aborted.set(true);
+        self.done_at.set(self.now.get() + 1);
+        self.deferred_call.set();
+        Ok(())
+    }
+}
+
+impl OperationTimer for SimulatedStorage<'_> {
+    fn start(&self, ms: u32) {
+        self.timer_at.set(self.now.get() + ms);
+        self.deferred_call.set();
+    }
+
+    fn cancel(&self) {
+        self.timer_at.clear();
+    }
+}
+
+impl DeferredCallClient for SimulatedStorage<'_> {
+    fn handle_deferred_call(&self) {
+        let now = self.now.get() + 1;
+        self.now.set(now);
+
+        // An operation due at the same time as the timer completes first.
+        if self.operation.is_some() && self.done_at.get() <= now {
+            if let Some(operation) = self.operation.take() {
+                self.complete(operation);
+            }
+        }
+        if self.timer_at.map_or(false, |at| at <= now) {
+            self.timer_at.clear();
+            self.timer_client.map(|client| client.alarm());
+        }
+
+        if self.operation.is_some() || self.timer_at.is_some() {
+            self.deferred_call.set();
+        }
+    }
+
+    fn register(&'static self) {
+        self.deferred_call.register(self);
+    }
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum TestState {
+    Idle,
+    Write,
+    QueuedWrite,
+    QueuedRead,
+    Timeout,
+    AfterTimeout,
+    SyncWrite,
+    Sync,
+}
+
+/// Address of the test data. The driver's kernel region must include it.
+const TEST_ADDRESS: usize = 0;
+/// Length of the test data.
+const TEST_LENGTH: usize = 16;
+/// Operation timeout of the driver, in milliseconds of simulated time.
+pub const TIMEOUT_MS: u32 = 10;
+/// Number of checks the test makes.
+const TEST_COUNT: usize = 6;
+
+/// Latencies of the operations the test issues, in order.
+const LATENCIES: [u32; 7] = [3, 5, 1, 2 * TIMEOUT_MS, 2, 4, 2];
+
+pub struct TestNonvolatileStorageLatency<'a> {
+    driver: &'a NonvolatileStorage<'a>,
+    storage: &'a SimulatedStorage<'a>,
+    buffer1: TakeCell<'static, [u8]>,
+    buffer2: TakeCell<'static, [u8]>,
+    state: Cell<TestState>,
+    // When the current step started, in simulated time.
+    started_at: Cell<u32>,
+    // Whether the operation ahead of a queued one has completed.
+    ahead_done: Cell<bool>,
+    checks: Cell<usize>,
+    failures: Cell<usize>,
+    client: OptionalCell<&'static dyn CapsuleTestClient>,
+}
+
+impl<'a> TestNonvolatileStorageLatency<'a> {
+    pub fn new(
+        driver: &'a NonvolatileStorage<'a>,
+        storage: &'a SimulatedStorage<'a>,
+        buffer1: &'static mut [u8],
+        buffer2: &'static mut [u8],
+    ) -> Self {
+        TestNonvolatileStorageLatency {
+            driver,
+            storage,
+            buffer1: TakeCell::new(buffer1),
+            buffer2: TakeCell::new(buffer2),
+            state: Cell::new(TestState::Idle),
+            started_at: Cell::new(0),
+            ahead_done: Cell::new(false),
+            checks: Cell::new(0),
+            failures: Cell::new(0),
+            client: OptionalCell::empty(),
+        }
+    }
+
+    pub fn run(&self) {
+        debug!("1..{}", TEST_COUNT);
+        self.storage.set_latencies(&LATENCIES);
+
+        let buffer = self.buffer1.take().unwrap();
+        for (i, b) in buffer[..TEST_LENGTH].iter_mut().enumerate() {
+            *b = i as u8;
+        }
+        self.start_step(TestState::Write);
+        let res = self.write(buffer);
+        self.check_started("write", res);
+    }
+
+    fn write(&self, buffer: &'static mut [u8]) -> Result<(), ErrorCode> {
+        nonvolatile_storage::NonvolatileStorage::write(
+            self.driver,
+            buffer,
+            TEST_ADDRESS,
+            TEST_LENGTH,
+        )
+    }
+
+    fn read(&self, buffer: &'static mut [u8]) -> Result<(), ErrorCode> {
+        nonvolatile_storage::NonvolatileStorage::read(
+            self.driver,
+            buffer,
+            TEST_ADDRESS,
+            TEST_LENGTH,
+        )
+    }
+
+    fn start_step(&self, state: TestState) {
+        self.state.set(state);
+        self.started_at.set(self.storage.now());
+        self.ahead_done.set(false);
+    }
+
+    // Milliseconds of simulated time since the current step started.
+    fn elapsed(&self) -> u32 {
+        self.storage.now() - self.started_at.get()
+    }
+
+    // Report one check as a TAP line.
+    fn check(&self, passed: bool, description: &str) {
+        let number = self.checks.get() + 1;
+        self.checks.set(number);
+        if passed {
+            debug!("ok {} - {}", number, description);
+        } else {
+            self.failures.set(self.failures.get() + 1);
+            debug!("not ok {} - {}", number, description);
+        }
+    }
+
+    // An operation the test relies on could not be started, so the rest of
+    // the test cannot run.
+    fn check_started(&self, operation: &str, res: Result<(), ErrorCode>) {
+        if let Err(e) = res {
+            debug!("Bail out! {} could not be started ({:?})", operation, e);
+            self.state.set(TestState::Idle);
+            self.client
+                .map(|client| client.done(Err(CapsuleTestError::ErrorCode(e))));
+        }
+    }
+
+    fn finish(&self) {
+        self.state.set(TestState::Idle);
+        let failures = self.failures.get();
+        debug!(
+            "# NonvolatileStorageLatency: {} of {} checks passed",
+            self.checks.get() - failures,
+            self.checks.get()
+        );
+        self.client.map(|client| {
+            client.done(if failures == 0 {
+                Ok(())
+            } else {
+                Err(CapsuleTestError::IncorrectResult)
+            })
+        });
+    }
+
+    fn check_contents(&self, buffer: &[u8]) -> bool {
+        buffer[..TEST_LENGTH]
+            .iter()
+            .enumerate()
+            .all(|(i, b)| *b == i as u8)
+    }
+}
+
+impl CapsuleTest for TestNonvolatileStorageLatency<'_> {
+    fn set_client(&self, client: &'static dyn CapsuleTestClient) {
+        self.client.set(client);
+    }
+}
+
+impl NonvolatileStorageClient for TestNonvolatileStorageLatency<'_> {
+    fn read_done(&self, buffer: &'static mut [u8], length: usize) {
+        match self.state.get() {
+            TestState::QueuedWrite | TestState::QueuedRead => {
+                self.check(
+                    self.ahead_done.get() && self.elapsed() == LATENCIES[1] + LATENCIES[2],
+                    "read queued behind a slow write completes after it",
+                );
+                self.check(
+                    length == TEST_LENGTH && self.check_contents(buffer),
+                    "queued read returns the written data",
+                );
+
+                // Slower than the timeout.
+                self.buffer2.replace(buffer);
+                self.start_step(TestState::Timeout);
+                let res = self.write(self.buffer1.take().unwrap());
+                self.check_started("slow write", res);
+            }
+            TestState::AfterTimeout => {
+                self.check(
+                    length == TEST_LENGTH && self.check_contents(buffer),
+                    "read after a timeout completes",
+                );
+
+                // Queue a sync behind a write.
+                self.buffer2.replace(buffer);
+                self.start_step(TestState::SyncWrite);
+                let res = self
+                    .write(self.buffer1.take().unwrap())
+                    .and_then(|()| nonvolatile_storage::NonvolatileStorage::sync(self.driver));
+                self.check_started("write and sync", res);
+            }
+            _ => {
+                self.buffer2.replace(buffer);
+                self.check(false, "unexpected read_done");
+            }
+        }
+    }
+
+    fn write_done(&self, buffer: &'static mut [u8], length: usize) {
+        match self.state.get() {
+            TestState::Write => {
+                self.check(
+                    length == TEST_LENGTH && self.elapsed() == LATENCIES[0],
+                    "write completes after its latency",
+                );
+
+                // Queue a fast read behind a slow write.
+                self.start_step(TestState::QueuedWrite);
+                let res = self
+                    .write(buffer)
+                    .and_then(|()| self.read(self.buffer2.take().unwrap()));
+                self.check_started("write and queued read", res);
+            }
+            TestState::QueuedWrite => {
+                self.buffer1.replace(buffer);
+                self.ahead_done.set(length == TEST_LENGTH);
+                self.state.set(TestState::QueuedRead);
+            }
+            TestState::Timeout => {
+                self.check(
+                    length == 0 && self.driver.timeouts() == 1,
+                    "operation slower than the timeout fails",
+                );
+
+                self.buffer1.replace(buffer);
+                self.start_step(TestState::AfterTimeout);
+                let res = self.read(self.buffer2.take().unwrap());
+                self.check_started("read after timeout", res);
+            }
+            TestState::SyncWrite => {
+                self.buffer1.replace(buffer);
+                self.ahead_done.set(length == TEST_LENGTH);
+                self.state.set(TestState::Sync);
+            }
+            _ => {
+                self.buffer1.replace(buffer);
+                self.check(false, "unexpected write_done");
+            }
+        }
+    }
+
+    fn sync_done(&self, result: Result<(), ErrorCode>) {
+        match self.state.get() {
+            TestState::SyncWrite | TestState::Sync => {
+                self.check(
+                    result.is_ok()
+                        && self.ahead_done.get()
+                        && self.elapsed() == LATENCIES[5] + LATENCIES[6],
+                    "sync queued behind a write completes after it",
+                );
+                self.finish();
+            }
+            _ => self.check(false, "unexpected sync_done"),
+        }
+    }
+}
@@ -4,19 +4,31 @@
 
 //! Tools for displaying process state.
 
+use core::cell::Cell;
 use core::fmt::Write;
 
 use kernel::process::Process;
-use kernel::process::{ProcessPrinter, ProcessPrinterContext};
+use kernel::process::{ProcessPrinter, ProcessPrinterContext, ProcessStateReporter};
 use kernel::utilities::binary_write::BinaryWrite;
 use kernel::utilities::binary_write::WriteToBinaryOffsetWrapper;
 
 /// A Process Printer that displays a process as a human-readable string.
-pub struct ProcessPrinterText {}
+pub struct ProcessPrinterText {
+    // Capsules whose state for the process is printed after the overview.
+    reporters: Cell<&'static [&'static dyn ProcessStateReporter]>,
+}
 
 impl ProcessPrinterText {
     pub fn new() -> ProcessPrinterText {
-        ProcessPrinterText {}
+        ProcessPrinterText {
+            reporters: Cell::new(&[]),
+        }
+    }
+
+    /// Print the state each of `reporters` keeps for the process at the end
+    /// of the overview, for example the storage operation it is waiting on.
+    pub fn set_state_reporters(&self, reporters: &'static [&'static dyn ProcessStateReporter]) {
+        self.reporters.set(reporters);
     }
 }
 
@@ -226,6 +238,12 @@ impl ProcessPrinter for ProcessPrinterText {
             ));
         }
 
+        if !bww.bytes_remaining() {
+            for reporter in self.reporters.get() {
+                reporter.report_process_state(process.processid(), &mut bww);
+            }
+        }
+
         if bww.bytes_remaining() {
             // The underlying writer is indicating there are still bytes
             // remaining to be sent. That means we want to return a context so
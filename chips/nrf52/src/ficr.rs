@@ -328,6 +328,11 @@ impl Ficr {
         }
     }
 
+    /// Size of the code flash in bytes.
+    pub fn code_size(&self) -> usize {
+        self.registers.codesize.get() as usize * self.registers.codepagesize.get() as usize
+    }
+
     fn part(&self) -> Part {
         match self.registers.info_part.get() {
             0x52832 => Part::N52832,
@@ -389,6 +389,36 @@ impl hil::flash::Flash for Nvmc {
     fn erase_page(&self, page_number: usize) -> Result<(), ErrorCode> {
         self.erase_page(page_number)
     }
+
+    fn geometry(&self) -> Option<hil::nonvolatile_storage::StorageGeometry> {
+        Some(hil::nonvolatile_storage::StorageGeometry {
+            erase_block_size: PAGE_SIZE,
+            // The NVMC programs flash one word at a time.
+            write_granularity: 4,
+            total_size: crate::ficr::Ficr::new().code_size(),
+        })
+    }
+
+    fn read_mapped(&self, address: usize, buffer: &mut [u8]) -> Result<(), ErrorCode> {
+        if self.state.get() != FlashState::Ready {
+            return Err(ErrorCode::BUSY);
+        }
+        let size = crate::ficr::Ficr::new().code_size();
+        match address.checked_add(buffer.len()) {
+            Some(end) if end <= size => {}
+            _ => return Err(ErrorCode::INVAL),
+        }
+
+        // Flash is mapped at address 0, so read it directly.
+        let mut byte: *const u8 = address as *const u8;
+        unsafe {
+            for b in buffer.iter_mut() {
+                *b = *byte;
+                byte = byte.offset(1);
+            }
+        }
+        Ok(())
+    }
 }
 
 impl DeferredCallClient for Nvmc {
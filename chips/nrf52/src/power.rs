@@ -3,13 +3,21 @@
 // Copyright Tock Contributors 2022.
 
 //! Power management
-
+//!
+//! The power-fail comparator warns through the `PowerFailWarning` interface
+//! when the supply drops below the threshold set with
+//! `set_power_fail_threshold()`, 1.7 V unless the board sets one.
+
+use core::cell::Cell;
+use kernel::hil::power_fail::{PowerFailClient, PowerFailWarning};
+use kernel::hil::reset_reason::{ResetCause, ResetReason as ResetReasonHil};
 use kernel::utilities::cells::OptionalCell;
 use kernel::utilities::registers::interfaces::{Readable, Writeable};
 use kernel::utilities::registers::{
     register_bitfields, register_structs, ReadOnly, ReadWrite, WriteOnly,
 };
 use kernel::utilities::StaticRef;
+use kernel::ErrorCode;
 
 const POWER_BASE: StaticRef<PowerRegisters> =
     unsafe { StaticRef::new(0x40000000 as *const PowerRegisters) };
@@ -243,6 +251,12 @@ pub struct Power<'a> {
     registers: StaticRef<PowerRegisters>,
     /// A client to which to notify USB plug-in/plug-out/power-ready events.
     usb_client: OptionalCell<&'a dyn PowerClient>,
+    /// A client to warn when the supply voltage is failing.
+    power_fail_client: OptionalCell<&'a dyn PowerFailClient>,
+    /// The THRESHOLD field of POFCON to use once the warning is enabled.
+    power_fail_threshold: Cell<u32>,
+    /// Whether the client waits for the next power-fail warning.
+    power_fail_enabled: Cell<bool>,
 }
 
 pub enum MainVoltage {
@@ -272,6 +286,9 @@ impl<'a> Power<'a> {
         Power {
             registers: POWER_BASE,
             usb_client: OptionalCell::empty(),
+            power_fail_client: OptionalCell::empty(),
+            power_fail_threshold: Cell::new(4),
+            power_fail_enabled: Cell::new(false),
         }
     }
 
@@ -300,6 +317,13 @@ impl<'a> Power<'a> {
                 .map(|client| client.handle_power_event(PowerEvent::UsbPowerReady));
         }
 
+        if self.power_fail_enabled.get() && self.registers.event_pofwarn.is_set(Event::READY) {
+            // The warning fires once, the client may enable it again.
+            self.power_fail_enabled.set(false);
+            self.registers.pofcon.write(PowerFailure::POF::Disabled);
+            self.power_fail_client.map(|client| client.power_failing());
+        }
+
         // Clearing unused events
         self.registers.event_pofwarn.write(Event::READY::CLEAR);
         self.registers.event_sleepenter.write(Event::READY::CLEAR);
@@ -312,6 +336,9 @@ impl<'a> Power<'a> {
         self.registers.intenset.write(
             Interrupt::USBDETECTED::SET + Interrupt::USBREMOVED::SET + Interrupt::USBPWRRDY::SET,
         );
+        if self.power_fail_enabled.get() {
+            self.registers.intenset.write(Interrupt::POFWARN::SET);
+        }
     }
 
     pub fn enable_interrupt(&self, intr: u32) {
@@ -368,4 +395,83 @@ impl<'a> Power<'a> {
     pub fn set_gpregret(&self, val: u8) {
         self.registers.gpregret.write(Byte::VALUE.val(val as u32));
     }
+
+    /// Set the supply voltage, in millivolts, below which the power-fail
+    /// warning fires. The comparator supports 1700 to 2800 mV in steps of
+    /// 100 mV; other values are rounded down to the next supported one.
+    ///
+    /// Returns `INVAL` below 1700 mV.
+    pub fn set_power_fail_threshold(&self, millivolts: u32) -> Result<(), ErrorCode> {
+        if millivolts < 1700 {
+            return Err(ErrorCode::INVAL);
+        }
+        // THRESHOLD counts from 4 for 1.7 V up to 15 for 2.8 V.
+        let threshold = core::cmp::min((millivolts - 1700) / 100, 11) + 4;
+        self.power_fail_threshold.set(threshold);
+        if self.power_fail_enabled.get() {
+            self.registers
+                .pofcon
+                .write(PowerFailure::POF::Enabled + PowerFailure::THRESHOLD.val(threshold));
+        }
+        Ok(())
+    }
+}
+
+impl<'a> PowerFailWarning<'a> for Power<'a> {
+    fn set_client(&self, client: &'a dyn PowerFailClient) {
+        self.power_fail_client.set(client);
+    }
+
+    fn enable(&self) -> Result<(), ErrorCode> {
+        self.registers.event_pofwarn.write(Event::READY::CLEAR);
+        self.power_fail_enabled.set(true);
+        self.registers.pofcon.write(
+            PowerFailure::POF::Enabled
+                + PowerFailure::THRESHOLD.val(self.power_fail_threshold.get()),
+        );
+        self.registers.intenset.write(Interrupt::POFWARN::SET);
+        Ok(())
+    }
+
+    fn disable(&self) -> Result<(), ErrorCode> {
+        self.power_fail_enabled.set(false);
+        self.registers.intenclr.write(Interrupt::POFWARN::SET);
+        self.registers.pofcon.write(PowerFailure::POF::Disabled);
+        Ok(())
+    }
+}
+
+impl ResetReasonHil for Power<'_> {
+    /// The nRF52 does not record power-on and brown-out resets, so both are
+    /// reported as `PowerOn`.
+    fn reset_cause(&self) -> ResetCause {
+        let resetreas = self.registers.resetreas.extract();
+        if resetreas.is_set(ResetReason::DOG) {
+            ResetCause::Watchdog
+        } else if resetreas.is_set(ResetReason::LOCKUP) {
+            ResetCause::Lockup
+        } else if resetreas.is_set(ResetReason::SREQ) {
+            ResetCause::Software
+        } else if resetreas.is_set(ResetReason::RESETPIN) {
+            ResetCause::ResetPin
+        } else if resetreas.is_set(ResetReason::OFF)
+            || resetreas.is_set(ResetReason::LPCOMP)
+            || resetreas.is_set(ResetReason::DIF)
+            || resetreas.is_set(ResetReason::NFC)
+            || resetreas.is_set(ResetReason::VBUS)
+        {
+            ResetCause::Wakeup
+        } else {
+            ResetCause::PowerOn
+        }
+    }
+
+    fn reset_flags(&self) -> u32 {
+        self.registers.resetreas.get()
+    }
+
+    /// The RESETREAS flags are cleared by writing 1 to them.
+    fn clear_reset_cause(&self) {
+        self.registers.resetreas.set(self.registers.resetreas.get());
+    }
 }
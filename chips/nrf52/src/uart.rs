@@ -20,8 +20,14 @@ use kernel::utilities::StaticRef;
 use kernel::ErrorCode;
 use nrf5x::pinmux;
 
+/// Longest DMA transfer every nRF52 UARTE supports. `TXD.MAXCNT` and
+/// `RXD.MAXCNT` are 8 bits wide on the nRF52832.
 const UARTE_MAX_BUFFER_SIZE: u32 = 0xff;
 
+/// Longest DMA transfer the UARTE supports on chips with 16 bit wide
+/// `TXD.MAXCNT` and `RXD.MAXCNT` registers, like the nRF52833 and nRF52840.
+pub const UARTE_MAX_BUFFER_SIZE_16BIT: usize = 0xffff;
+
 static mut BYTE: u8 = 0;
 
 pub const UARTE0_BASE: StaticRef<UarteRegisters> =
@@ -173,6 +179,7 @@ pub struct Uarte<'a> {
     rx_remaining_bytes: Cell<usize>,
     rx_abort_in_progress: Cell<bool>,
     offset: Cell<usize>,
+    max_transmit_len: Cell<u32>,
 }
 
 #[derive(Copy, Clone)]
@@ -180,6 +187,39 @@ pub struct UARTParams {
     pub baud_rate: u32,
 }
 
+/// Polled transmitter for panic output, from [`Uarte::panic_takeover`].
+pub struct UartePanicWriter<'b, 'a> {
+    uart: &'b Uarte<'a>,
+}
+
+impl UartePanicWriter<'_, '_> {
+    /// Send `buf`, waiting for each byte to go out. If a byte does not go
+    /// out within `PANIC_SPIN_LIMIT` polls, for example because flow control
+    /// holds the transmitter, the rest of `buf` is dropped so the panic
+    /// handler can go on.
+    pub fn write(&self, buf: &[u8]) {
+        for &c in buf {
+            unsafe {
+                self.uart.send_byte(c);
+            }
+            if !spin_until(|| self.uart.tx_ready()) {
+                return;
+            }
+        }
+    }
+}
+
+/// How many times the panic writer polls the UARTE for an event before it
+/// gives up. This is several milliseconds even at the highest clock speed,
+/// longer than a byte takes at any usual baud rate.
+const PANIC_SPIN_LIMIT: usize = 1_000_000;
+
+/// Poll `ready` until it returns true, at most `PANIC_SPIN_LIMIT` times.
+/// Returns whether it did.
+fn spin_until<F: Fn() -> bool>(ready: F) -> bool {
+    (0..PANIC_SPIN_LIMIT).any(|_| ready())
+}
+
 impl<'a> Uarte<'a> {
     /// Constructor
     // This should only be constructed once
@@ -195,9 +235,23 @@ impl<'a> Uarte<'a> {
             rx_remaining_bytes: Cell::new(0),
             rx_abort_in_progress: Cell::new(false),
             offset: Cell::new(0),
+            max_transmit_len: Cell::new(UARTE_MAX_BUFFER_SIZE),
         }
     }
 
+    /// Set the longest single transmit DMA transfer, which must not exceed
+    /// what the chip's `TXD.MAXCNT` register can hold.
+    ///
+    /// Buffers longer than this are sent in several transfers, each needing
+    /// an interrupt to start the next one. Chips with a 16 bit `TXD.MAXCNT`
+    /// can use `UARTE_MAX_BUFFER_SIZE_16BIT` to send large buffers, like a
+    /// backlog of debug output, in a single transfer.
+    pub fn set_max_transmit_len(&self, len: usize) {
+        let len = min(len, UARTE_MAX_BUFFER_SIZE_16BIT) as u32;
+        self.max_transmit_len
+            .set(if len == 0 { UARTE_MAX_BUFFER_SIZE } else { len });
+    }
+
     /// Configure which pins the UART should use for txd, rxd, cts and rts
     pub fn initialize(
         &self,
@@ -315,7 +369,7 @@ impl<'a> Uarte<'a> {
                 self.set_tx_dma_pointer_to_buffer();
                 self.registers
                     .txd_maxcnt
-                    .write(Counter::COUNTER.val(min(rem as u32, UARTE_MAX_BUFFER_SIZE)));
+                    .write(Counter::COUNTER.val(min(rem as u32, self.max_transmit_len.get())));
                 self.registers.task_starttx.write(Task::ENABLE::SET);
                 self.enable_tx_interrupts();
             }
@@ -401,6 +455,46 @@ impl<'a> Uarte<'a> {
         self.registers.event_endtx.is_set(Event::READY)
     }
 
+    /// Take over the UARTE to write panic output by polling.
+    ///
+    /// The driver's interrupts are disabled and a transmission the kernel
+    /// started is stopped, and its buffer abandoned, so the returned writer
+    /// owns the hardware. The pins and baud rate the board configured are
+    /// kept. If the board never enabled the UARTE, it is enabled at 115200
+    /// baud. Calling this again, for example for every write of the panic
+    /// handler, keeps the takeover in place.
+    ///
+    /// This works on the board's own instance of the driver as well as on a
+    /// second instance over the same registers, since everything is done
+    /// through the registers. Waiting for the transmitter to stop is bounded,
+    /// so a stuck UARTE cannot hang the panic handler.
+    ///
+    /// # Safety
+    ///
+    /// Only call this from a panic handler. Clients waiting for a transmit or
+    /// receive callback never get one, so the driver must not be used for
+    /// anything but panic output afterwards.
+    pub unsafe fn panic_takeover(&self) -> UartePanicWriter<'_, 'a> {
+        self.disable_tx_interrupts();
+        self.disable_rx_interrupts();
+
+        self.tx_buffer.take();
+        self.tx_remaining_bytes.set(0);
+
+        if self.registers.enable.matches_all(Uart::ENABLE::ON) {
+            // Stop a transmission the kernel may have started. The
+            // transmitter reports TXSTOPPED even if it was idle.
+            self.registers.event_txstopped.write(Event::READY::CLEAR);
+            self.registers.task_stoptx.write(Task::ENABLE::SET);
+            spin_until(|| self.registers.event_txstopped.is_set(Event::READY));
+        } else {
+            self.set_baud_rate(115200);
+            self.enable_uart();
+        }
+
+        UartePanicWriter { uart: self }
+    }
+
     /// Check if either the rx_buffer is full or the UART has timed out
     pub fn rx_ready(&self) -> bool {
         self.registers.event_endrx.is_set(Event::READY)
@@ -432,7 +526,7 @@ impl<'a> Uarte<'a> {
 
         self.registers
             .txd_maxcnt
-            .write(Counter::COUNTER.val(min(tx_len as u32, UARTE_MAX_BUFFER_SIZE)));
+            .write(Counter::COUNTER.val(min(tx_len as u32, self.max_transmit_len.get())));
         self.registers.task_starttx.write(Task::ENABLE::SET);
 
         self.enable_tx_interrupts();
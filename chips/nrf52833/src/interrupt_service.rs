@@ -29,6 +29,9 @@ impl<'a> Nrf52833DefaultPeripherals<'a> {
         self.ieee802154_radio.set_timer_ref(&self.nrf52.timer0);
         self.nrf52.timer0.set_alarm_client(&self.ieee802154_radio);
         kernel::deferred_call::DeferredCallClient::register(&self.ieee802154_radio);
+        self.nrf52
+            .uarte0
+            .set_max_transmit_len(nrf52::uart::UARTE_MAX_BUFFER_SIZE_16BIT);
         self.nrf52.init();
     }
 }
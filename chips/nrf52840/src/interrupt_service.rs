@@ -35,6 +35,9 @@ impl<'a> Nrf52840DefaultPeripherals<'a> {
         self.nrf52.pwr_clk.set_usb_client(&self.usbd);
         self.usbd.set_power_ref(&self.nrf52.pwr_clk);
         kernel::deferred_call::DeferredCallClient::register(&self.ieee802154_radio);
+        self.nrf52
+            .uarte0
+            .set_max_transmit_len(nrf52::uart::UARTE_MAX_BUFFER_SIZE_16BIT);
         self.nrf52.init();
     }
 }
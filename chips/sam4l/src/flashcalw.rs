@@ -21,6 +21,11 @@
 //! Almost all of the flash controller functionality is implemented (except for
 //! general purpose fuse bits, and more granular control of the cache).
 //!
+//! Writes and erases unlock the page's lock region first, unless the region
+//! was locked through `hil::flash::WriteProtection`. Those regions stay locked
+//! until they are unlocked through the same interface, so the hardware rejects
+//! writes to them.
+//!
 //! - Author:  Kevin Baichoo <kbaichoo@cs.stanford.edu>
 //! - Date: July 27, 2016
 
@@ -416,12 +421,17 @@ pub struct FLASHCALW {
     client: OptionalCell<&'static dyn hil::flash::Client<FLASHCALW>>,
     current_state: Cell<FlashState>,
     buffer: TakeCell<'static, Sam4lPage>,
+    // Lock regions that writes and erases must not unlock, one bit each.
+    protected: Cell<u16>,
     deferred_call: DeferredCall,
 }
 
 // Few constants relating to module configuration.
 const PAGE_SIZE: u32 = 512;
 
+/// The flash is divided into this many lock regions of equal size.
+const LOCK_REGIONS: usize = 16;
+
 const FREQ_PS2_FWS_0_MAX_FREQ: u32 = 24000000;
 
 impl FLASHCALW {
@@ -434,6 +444,7 @@ impl FLASHCALW {
             client: OptionalCell::empty(),
             current_state: Cell::new(FlashState::Unconfigured),
             buffer: TakeCell::empty(),
+            protected: Cell::new(0),
             deferred_call: DeferredCall::new(),
         }
     }
@@ -667,6 +678,62 @@ impl FLASHCALW {
         }
     }
 
+    /// Lock or unlock the lock region containing `page_number`, and wait for
+    /// the flash to finish.
+    fn lock_page_region_sync(&self, page_number: i32, lock: bool) {
+        pm::enable_clock(self.pb_clock);
+        let command = if lock { FlashCMD::LP } else { FlashCMD::UP };
+        self.registers.fcmd.write(
+            FlashCommand::KEY.val(0xA5)
+                + FlashCommand::CMD.val(command as u32)
+                + FlashCommand::PAGEN.val(page_number as u32),
+        );
+        while !self.registers.fsr.is_set(FlashStatus::FRDY) {}
+    }
+
+    fn lock_region_pages(&self) -> usize {
+        self.get_flash_size() as usize / LOCK_REGIONS / PAGE_SIZE as usize
+    }
+
+    // Whether the lock region containing `page_number` must stay locked.
+    fn is_page_protected(&self, page_number: i32) -> bool {
+        let region = page_number as usize / self.lock_region_pages();
+        region < LOCK_REGIONS && self.protected.get() & (1 << region) != 0
+    }
+
+    // Lock or unlock the lock regions containing the `length` bytes at
+    // `address`.
+    fn set_write_protection(
+        &self,
+        address: usize,
+        length: usize,
+        lock: bool,
+    ) -> Result<(), ErrorCode> {
+        match self.current_state.get() {
+            FlashState::Unconfigured => self.configure(),
+            FlashState::Ready => {}
+            _ => return Err(ErrorCode::BUSY),
+        }
+        let end = match address.checked_add(length) {
+            Some(end) if length > 0 && end <= self.get_flash_size() as usize => end,
+            _ => return Err(ErrorCode::INVAL),
+        };
+        let region_size = self.lock_region_pages() * PAGE_SIZE as usize;
+        for region in address / region_size..end.div_ceil(region_size) {
+            self.lock_page_region_sync((region * self.lock_region_pages()) as i32, lock);
+            let mask = 1 << region;
+            self.protected.set(if lock {
+                self.protected.get() | mask
+            } else {
+                self.protected.get() & !mask
+            });
+        }
+        if self.is_error() {
+            return Err(ErrorCode::FAIL);
+        }
+        Ok(())
+    }
+
     /// Flashcalw Access to Flash Pages
     fn clear_page_buffer(&self) {
         self.issue_command(FlashCMD::CPB, -1);
@@ -829,9 +896,16 @@ impl FLASHCALW {
         // Save the buffer for the future write.
         self.buffer.replace(data);
 
-        self.current_state
-            .set(FlashState::WriteUnlocking { page: page_num });
-        self.lock_page_region(page_num, false);
+        if self.is_page_protected(page_num) {
+            // The page stays locked, so the flash reports an error.
+            self.current_state
+                .set(FlashState::WriteErasing { page: page_num });
+            self.flashcalw_erase_page(page_num);
+        } else {
+            self.current_state
+                .set(FlashState::WriteUnlocking { page: page_num });
+            self.lock_page_region(page_num, false);
+        }
         Ok(())
     }
 
@@ -846,9 +920,15 @@ impl FLASHCALW {
             _ => return Err(ErrorCode::BUSY),
         }
 
-        self.current_state
-            .set(FlashState::EraseUnlocking { page: page_num });
-        self.lock_page_region(page_num, false);
+        if self.is_page_protected(page_num) {
+            // The page stays locked, so the flash reports an error.
+            self.current_state.set(FlashState::EraseErasing);
+            self.flashcalw_erase_page(page_num);
+        } else {
+            self.current_state
+                .set(FlashState::EraseUnlocking { page: page_num });
+            self.lock_page_region(page_num, false);
+        }
         Ok(())
     }
 }
@@ -892,3 +972,17 @@ impl hil::flash::Flash for FLASHCALW {
         self.erase_page(page_number as i32)
     }
 }
+
+impl hil::flash::WriteProtection for FLASHCALW {
+    fn lock_region_size(&self) -> usize {
+        self.lock_region_pages() * PAGE_SIZE as usize
+    }
+
+    fn lock(&self, address: usize, length: usize) -> Result<(), ErrorCode> {
+        self.set_write_protection(address, length, true)
+    }
+
+    fn unlock(&self, address: usize, length: usize) -> Result<(), ErrorCode> {
+        self.set_write_protection(address, length, false)
+    }
+}
@@ -11,6 +11,7 @@ use crate::gpio;
 use crate::scif;
 use core::cell::Cell;
 use core::sync::atomic::Ordering;
+use kernel::hil::reset_reason::{ResetCause as ResetCauseHil, ResetReason};
 use kernel::platform::chip::ClockInterface;
 use kernel::utilities::registers::interfaces::{Readable, Writeable};
 use kernel::utilities::registers::{
@@ -1168,3 +1169,28 @@ pub fn is_clock_enabled(clock: Clock) -> bool {
         Clock::PBD(v) => get_clock!(PBD_MASK_OFFSET: pbdmask & (1 << (v as u32))),
     }
 }
+
+impl ResetReason for PowerManager {
+    fn reset_cause(&self) -> ResetCauseHil {
+        let rcause = PM_REGS.rcause.extract();
+        if rcause.is_set(ResetCause::WDT) {
+            ResetCauseHil::Watchdog
+        } else if rcause.is_set(ResetCause::BOD) || rcause.is_set(ResetCause::BOD33) {
+            ResetCauseHil::BrownOut
+        } else if rcause.is_set(ResetCause::OCDRST) {
+            ResetCauseHil::Software
+        } else if rcause.is_set(ResetCause::EXT) {
+            ResetCauseHil::ResetPin
+        } else if rcause.is_set(ResetCause::BKUP) {
+            ResetCauseHil::Wakeup
+        } else if rcause.is_set(ResetCause::POR) || rcause.is_set(ResetCause::POR33) {
+            ResetCauseHil::PowerOn
+        } else {
+            ResetCauseHil::Unknown
+        }
+    }
+
+    fn reset_flags(&self) -> u32 {
+        PM_REGS.rcause.get()
+    }
+}
@@ -211,6 +211,11 @@ pub struct SpiHw<'a> {
 const SPI_BASE: StaticRef<SpiRegisters> =
     unsafe { StaticRef::new(0x40008000 as *const SpiRegisters) };
 
+/// How many times `write_bytes_now()` reads the status register while waiting
+/// for the controller before it gives up. At 48 MHz this is tens of
+/// milliseconds, far longer than a byte takes at any usable SPI clock.
+const WRITE_NOW_SPIN_LIMIT: usize = 200_000;
+
 impl PeripheralManagement<pm::Clock> for SpiHw<'_> {
     type RegisterType = SpiRegisters;
 
@@ -629,6 +634,49 @@ impl<'a> spi::SpiMaster<'a> for SpiHw<'a> {
         csr.modify(ChipSelectParams::CSAAT::InactiveAfterTransfer);
     }
 
+    fn write_bytes_now(&self, data: &[u8]) -> Result<(), ErrorCode> {
+        if self.is_busy() {
+            return Err(ErrorCode::BUSY);
+        }
+        let spi = &SpiRegisterManager::new(self);
+        self.enable();
+
+        // Keep the chip select asserted between bytes, and raise it after the
+        // byte written with LASTXFER.
+        let csr = self.get_active_csr(spi);
+        csr.modify(ChipSelectParams::CSAAT::ActiveAfterTransfer);
+        // Poll the status register for `flag`, giving up after
+        // `WRITE_NOW_SPIN_LIMIT` reads so a stalled controller cannot hang the
+        // caller.
+        let wait_for = |flag| (0..WRITE_NOW_SPIN_LIMIT).any(|_| spi.registers.sr.is_set(flag));
+        let mut result = Ok(());
+        for (i, byte) in data.iter().enumerate() {
+            let last = if i + 1 == data.len() {
+                TransmitData::LASTXFER::SET
+            } else {
+                TransmitData::LASTXFER::CLEAR
+            };
+            if !wait_for(Status::TDRE) {
+                result = Err(ErrorCode::FAIL);
+                break;
+            }
+            spi.registers
+                .tdr
+                .write(TransmitData::TD.val(*byte as u32) + last);
+        }
+        if result.is_ok() && !wait_for(Status::TXEMPTY) {
+            result = Err(ErrorCode::FAIL);
+        }
+        csr.modify(ChipSelectParams::CSAAT::InactiveAfterTransfer);
+
+        // Drop the last byte read, and the overrun of the ones before it, so
+        // the next transfer starts clean.
+        let _ = spi.registers.rdr.get();
+        let _ = spi.registers.sr.get();
+        self.disable();
+        result
+    }
+
     fn specify_chip_select(&self, cs: Self::ChipSelect) -> Result<(), ErrorCode> {
         // Slave cannot set active peripheral
         if self.role.get() == SpiRole::SpiMaster {
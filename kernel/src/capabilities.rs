@@ -109,3 +109,43 @@ pub unsafe trait CreatePortTableCapability {}
 /// of the networking stack. A capsule would never hold this capability although
 /// it may hold capabilities created via this capability.
 pub unsafe trait NetworkCapabilityCreationCapability {}
+
+/// The `StorageRegionOverlapCapability` capability allows the holder to let
+/// kernel clients of a storage driver write to storage that is also accessible
+/// to userspace. Without it, such writes are rejected so that a misconfigured
+/// kernel region can not corrupt application data.
+pub unsafe trait StorageRegionOverlapCapability {}
+
+/// The `StoragePriorityCapability` capability allows the holder to let a kernel
+/// client of shared storage, such as a crash dump, have its operations run
+/// before those of other clients. Without it, a client in a failing system
+/// could wait behind a long queue of unimportant writes.
+pub unsafe trait StoragePriorityCapability {}
+
+/// The `StorageFreezeCapability` capability allows the holder to stop apps
+/// from writing to storage for a while, for example while an update rewrites
+/// the flash underneath them.
+pub unsafe trait StorageFreezeCapability {}
+
+/// The `ProcessControlCapability` capability allows the holder to let a
+/// debugging interface, such as the process console, stop, fault, terminate,
+/// and restart processes. Production boards can leave it out so that these
+/// commands are refused.
+pub unsafe trait ProcessControlCapability {}
+
+/// The `StorageBackupCapability` capability allows the holder to let a
+/// debugging interface, such as the process console, copy storage regions to
+/// their backup area. Copying overwrites the previous backup.
+pub unsafe trait StorageBackupCapability {}
+
+/// The `UpcallInjectionCapability` capability allows the holder to schedule
+/// upcalls into processes as if a driver had scheduled them, to test how apps
+/// handle unusual upcalls. Production boards should not create it, as it lets
+/// the holder feed apps results no driver produced.
+pub unsafe trait UpcallInjectionCapability {}
+
+/// The `ReadOnlyMappingCapability` capability allows the holder to let
+/// processes read memory outside of their own regions, such as memory-mapped
+/// storage, by adding read-only regions to their MPU configuration. The holder
+/// is responsible for mapping only memory the process may read.
+pub unsafe trait ReadOnlyMappingCapability {}
@@ -37,7 +37,7 @@ impl<'a, T: Copy> RingBuffer<'a, T> {
     /// - `(Some(left), Some(right))` if the head is after the tail. In that case, the logical
     /// contents of the buffer is `[left, right].concat()` (although physically the "left" slice is
     /// stored after the "right" slice).
-    pub fn as_slices(&'a self) -> (Option<&'a [T]>, Option<&'a [T]>) {
+    pub fn as_slices(&self) -> (Option<&[T]>, Option<&[T]>) {
         if self.head < self.tail {
             (Some(&self.ring[self.head..self.tail]), None)
         } else if self.head > self.tail {
@@ -66,8 +66,8 @@ use crate::hil;
 use crate::platform::chip::Chip;
 use crate::process::Process;
 use crate::process::ProcessPrinter;
-use crate::processbuffer::ReadableProcessSlice;
-use crate::utilities::binary_write::BinaryToWriteWrapper;
+use crate::processbuffer::{ReadableProcessSlice, WriteableProcessSlice};
+use crate::utilities::binary_write::{BinaryToWriteWrapper, IoWriteToBinaryWrapper};
 use crate::utilities::cells::NumericCellExt;
 use crate::utilities::cells::{MapCell, TakeCell};
 use crate::ErrorCode;
@@ -165,6 +165,35 @@ pub unsafe fn panic<L: hil::led::Led, W: Write + IoWrite, C: Chip, PP: ProcessPr
     panic_blink_forever(leds)
 }
 
+/// Tock panic routine that blinks a code identifying the kind of failure.
+///
+/// **NOTE:** The supplied `writer` must be synchronous.
+///
+/// This behaves like [`panic`], but instead of the generic panic pattern the
+/// LEDs blink the code from `codes` that matches the [`PanicCategory`] of this
+/// panic. This lets headless devices communicate the class of failure without
+/// a serial connection.
+pub unsafe fn panic_with_blink_codes<
+    L: hil::led::Led,
+    W: Write + IoWrite,
+    C: Chip,
+    PP: ProcessPrinter,
+>(
+    leds: &mut [&L],
+    writer: &mut W,
+    panic_info: &PanicInfo,
+    nop: &dyn Fn(),
+    processes: &'static [Option<&'static dyn Process>],
+    chip: &'static Option<&'static C>,
+    process_printer: &'static Option<&'static PP>,
+    codes: &PanicBlinkCodes,
+) -> ! {
+    panic_print(writer, panic_info, nop, processes, chip, process_printer);
+
+    let category = panic_category(panic_info);
+    panic_blink_code_forever(leds, codes.code_for(category))
+}
+
 /// Generic panic entry.
 ///
 /// This opaque method should always be called at the beginning of a board's
@@ -227,6 +256,30 @@ pub unsafe fn panic_process_info<PP: ProcessPrinter, W: Write>(
     });
 }
 
+/// Print the state of all processes to any `IoWrite` sink.
+///
+/// This prints the same process information as [`panic_process_info`], but
+/// `writer` does not have to be a console. Boards can use it to save process
+/// state, for example to nonvolatile storage, when no console is attached.
+///
+/// **NOTE:** The supplied `writer` must be synchronous.
+pub fn print_process_info<PP: ProcessPrinter, W: IoWrite>(
+    procs: &[Option<&dyn Process>],
+    process_printer: &PP,
+    writer: &mut W,
+) {
+    let mut writer = IoWriteToBinaryWrapper::new(writer);
+    let _ = writer.write_fmt(format_args!("\r\n---| App Status |---\r\n"));
+    for proc in procs {
+        proc.map(|process| {
+            // The writer is synchronous, so the overview is printed in one
+            // call.
+            process_printer.print_overview(process, &mut writer, None);
+            process.print_full_process(&mut writer);
+        });
+    }
+}
+
 /// Blinks a recognizable pattern forever.
 ///
 /// The LED will blink "sporadically" in a somewhat irregular pattern. This
@@ -414,6 +467,8 @@ pub struct DebugWriter {
     internal_buffer: TakeCell<'static, RingBuffer<'static, u8>>,
     // Number of debug!() calls.
     count: Cell<usize>,
+    // Number of bytes taken from `internal_buffer` to be transmitted.
+    published: Cell<usize>,
 }
 
 /// Static variable that holds the kernel's reference to the debug tool.
@@ -454,6 +509,7 @@ impl DebugWriter {
             output_buffer: TakeCell::new(out_buffer),
             internal_buffer: TakeCell::new(internal_buffer),
             count: Cell::new(0), // how many debug! calls
+            published: Cell::new(0),
         }
     }
 
@@ -486,6 +542,7 @@ impl DebugWriter {
                     }
                 }
 
+                self.published.set(self.published.get().wrapping_add(count));
                 if count != 0 {
                     // Transmit the data in the output buffer.
                     if let Err((_err, buf)) = self.uart.transmit_buffer(out_buffer, count) {
@@ -508,6 +565,53 @@ impl DebugWriter {
     fn available_len(&self) -> usize {
         self.internal_buffer.map_or(0, |rb| rb.available_len())
     }
+
+    /// Copy the queued output into `dest`, oldest byte first, without
+    /// removing it. Returns the number of bytes copied.
+    fn snapshot(&self, dest: &WriteableProcessSlice) -> usize {
+        self.internal_buffer.map_or(0, |ring_buffer| {
+            let (left, right) = ring_buffer.as_slices();
+            let queued = left
+                .unwrap_or(&[])
+                .iter()
+                .chain(right.unwrap_or(&[]).iter());
+            let mut count = 0;
+            for (dst, src) in dest.iter().zip(queued) {
+                dst.set(*src);
+                count += 1;
+            }
+            count
+        })
+    }
+
+    /// Copy the queued output into `dest` without removing it, starting with
+    /// the byte at `position` in all output so far, or with the oldest queued
+    /// byte if that one was already transmitted. Returns the position after
+    /// the last byte copied and the number of bytes copied.
+    fn snapshot_at(&self, position: usize, dest: &mut [u8]) -> (usize, usize) {
+        self.internal_buffer.map_or((position, 0), |ring_buffer| {
+            let published = self.published.get();
+            let skip = position.wrapping_sub(published);
+            // Bytes before `published` are no longer queued.
+            let (start, skip) = if skip > ring_buffer.len() {
+                (published, 0)
+            } else {
+                (position, skip)
+            };
+            let (left, right) = ring_buffer.as_slices();
+            let queued = left
+                .unwrap_or(&[])
+                .iter()
+                .chain(right.unwrap_or(&[]).iter())
+                .skip(skip);
+            let mut count = 0;
+            for (dst, src) in dest.iter_mut().zip(queued) {
+                *dst = *src;
+                count += 1;
+            }
+            (start.wrapping_add(count), count)
+        })
+    }
 }
 
 impl hil::uart::TransmitClient for DebugWriter {
@@ -553,6 +657,15 @@ impl DebugWriterWrapper {
         self.dw
             .map_or(0, |dw| dw.available_len().saturating_sub(FULL_MSG.len()))
     }
+
+    fn snapshot(&self, dest: &WriteableProcessSlice) -> usize {
+        self.dw.map_or(0, |dw| dw.snapshot(dest))
+    }
+
+    fn snapshot_at(&self, position: usize, dest: &mut [u8]) -> (usize, usize) {
+        self.dw
+            .map_or((position, 0), |dw| dw.snapshot_at(position, dest))
+    }
 }
 
 impl IoWrite for DebugWriterWrapper {
@@ -631,6 +744,28 @@ pub fn debug_available_len() -> usize {
     writer.available_len()
 }
 
+/// Copy the debug output that has not been transmitted yet into `dest`,
+/// oldest byte first, without removing it from the debug buffer.
+///
+/// Returns the number of bytes copied, which is 0 if the board has not set up
+/// a debug writer.
+pub fn debug_snapshot(dest: &WriteableProcessSlice) -> usize {
+    unsafe { try_get_debug_writer() }.map_or(0, |writer| writer.snapshot(dest))
+}
+
+/// Copy debug output that has not been transmitted yet into `dest`, without
+/// removing it from the debug buffer, so it can be read out in pieces.
+///
+/// Copying starts with the byte at `position` in all debug output so far,
+/// counting from 0. If that byte was already transmitted, it starts with the
+/// oldest byte that was not. Returns the position to pass to read the next
+/// piece, and the number of bytes copied, which is 0 once all queued output
+/// was read or if the board has not set up a debug writer.
+pub fn debug_snapshot_at(position: usize, dest: &mut [u8]) -> (usize, usize) {
+    unsafe { try_get_debug_writer() }
+        .map_or((position, 0), |writer| writer.snapshot_at(position, dest))
+}
+
 fn write_header(writer: &mut DebugWriterWrapper, (file, line): &(&'static str, u32)) -> Result {
     writer.increment_count();
     let count = writer.get_count();
@@ -771,3 +906,178 @@ pub unsafe fn flush<W: Write + IoWrite>(writer: &mut W) {
         );
     }
 }
+
+/// Class of failure that caused a panic.
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum PanicCategory {
+    /// A `panic!()` in kernel code that does not fall in another category.
+    KernelPanic,
+    /// The CPU took a fault while executing kernel code.
+    HardFault,
+    /// The kernel ran out of memory.
+    OutOfMemory,
+    /// Data on nonvolatile storage was found to be corrupted.
+    StorageCorruption,
+}
+
+impl PanicCategory {
+    /// Guess the category of a panic from its message.
+    ///
+    /// Only the start of the message is examined, so the keyword should
+    /// appear early in the panic message.
+    fn from_message(message: &[u8]) -> PanicCategory {
+        let contains = |needle: &[u8]| {
+            message
+                .windows(needle.len())
+                .any(|w| w.eq_ignore_ascii_case(needle))
+        };
+        if contains(b"hardfault") || contains(b"hard fault") {
+            PanicCategory::HardFault
+        } else if contains(b"out of memory") {
+            PanicCategory::OutOfMemory
+        } else if contains(b"storage corrupt") {
+            PanicCategory::StorageCorruption
+        } else {
+            PanicCategory::KernelPanic
+        }
+    }
+}
+
+/// Category recorded with [`set_panic_category`].
+static mut PANIC_CATEGORY: Option<PanicCategory> = None;
+
+/// Record the category of a panic that is about to happen.
+///
+/// Code that detects a failure of a specific kind can call this right before
+/// `panic!()` so that the panic handler does not have to guess the category
+/// from the panic message.
+pub unsafe fn set_panic_category(category: PanicCategory) {
+    PANIC_CATEGORY = Some(category);
+}
+
+/// Determine the category of the current panic.
+///
+/// Uses the category recorded with [`set_panic_category`] if there is one,
+/// otherwise looks for keywords in the panic message.
+pub unsafe fn panic_category(panic_info: &PanicInfo) -> PanicCategory {
+    if let Some(category) = *core::ptr::addr_of!(PANIC_CATEGORY) {
+        return category;
+    }
+
+    /// Keeps the first bytes of the formatted panic message.
+    struct MessagePrefix {
+        buf: [u8; 96],
+        len: usize,
+    }
+
+    impl Write for MessagePrefix {
+        fn write_str(&mut self, s: &str) -> Result {
+            let n = core::cmp::min(s.len(), self.buf.len() - self.len);
+            self.buf[self.len..self.len + n].copy_from_slice(&s.as_bytes()[..n]);
+            self.len += n;
+            Ok(())
+        }
+    }
+
+    let mut prefix = MessagePrefix {
+        buf: [0; 96],
+        len: 0,
+    };
+    let _ = prefix.write_fmt(format_args!("{}", panic_info));
+    PanicCategory::from_message(&prefix.buf[..prefix.len])
+}
+
+/// LED blink pattern identifying a [`PanicCategory`].
+///
+/// The pattern is `long` long flashes followed by `short` short flashes and a
+/// pause, repeated forever.
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub struct PanicBlinkCode {
+    pub long: u8,
+    pub short: u8,
+}
+
+/// Blink codes for each [`PanicCategory`].
+///
+/// Boards can use [`PanicBlinkCodes::default()`] or provide their own codes in
+/// their panic handler.
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub struct PanicBlinkCodes {
+    pub kernel_panic: PanicBlinkCode,
+    pub hard_fault: PanicBlinkCode,
+    pub out_of_memory: PanicBlinkCode,
+    pub storage_corruption: PanicBlinkCode,
+}
+
+impl PanicBlinkCodes {
+    pub fn code_for(&self, category: PanicCategory) -> PanicBlinkCode {
+        match category {
+            PanicCategory::KernelPanic => self.kernel_panic,
+            PanicCategory::HardFault => self.hard_fault,
+            PanicCategory::OutOfMemory => self.out_of_memory,
+            PanicCategory::StorageCorruption => self.storage_corruption,
+        }
+    }
+}
+
+impl Default for PanicBlinkCodes {
+    fn default() -> Self {
+        PanicBlinkCodes {
+            kernel_panic: PanicBlinkCode { long: 1, short: 1 },
+            hard_fault: PanicBlinkCode { long: 1, short: 2 },
+            out_of_memory: PanicBlinkCode { long: 1, short: 3 },
+            storage_corruption: PanicBlinkCode { long: 2, short: 1 },
+        }
+    }
+}
+
+/// Blinks `code` forever.
+///
+/// As with [`panic_blink_forever`], timing is based on busy loops and is
+/// therefore only approximate.
+pub fn panic_blink_code_forever<L: hil::led::Led>(leds: &mut [&L], code: PanicBlinkCode) -> ! {
+    fn flash<L: hil::led::Led>(leds: &mut [&L], on: usize, off: usize) {
+        for _ in 0..on {
+            leds.iter_mut().for_each(|led| led.on());
+        }
+        for _ in 0..off {
+            leds.iter_mut().for_each(|led| led.off());
+        }
+    }
+
+    leds.iter_mut().for_each(|led| led.init());
+    loop {
+        for _ in 0..code.long {
+            flash(leds, 1000000, 300000);
+        }
+        for _ in 0..code.short {
+            flash(leds, 200000, 300000);
+        }
+        flash(leds, 0, 2000000);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::PanicCategory;
+
+    #[test]
+    fn panic_category_from_message() {
+        assert_eq!(
+            PanicCategory::from_message(b"Kernel HardFault.\r\n"),
+            PanicCategory::HardFault
+        );
+        assert_eq!(
+            PanicCategory::from_message(b"grant region out of memory"),
+            PanicCategory::OutOfMemory
+        );
+        assert_eq!(
+            PanicCategory::from_message(b"Storage corrupted at 0x1000"),
+            PanicCategory::StorageCorruption
+        );
+        assert_eq!(
+            PanicCategory::from_message(b"Process Console forced a kernel panic."),
+            PanicCategory::KernelPanic
+        );
+    }
+}
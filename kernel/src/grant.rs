@@ -126,7 +126,6 @@
 //! the closure.               ▼
 //! ```
 
-use core::cmp;
 use core::marker::PhantomData;
 use core::mem::{align_of, size_of};
 use core::ops::{Deref, DerefMut};
@@ -344,7 +343,7 @@ impl<'a> EnteredGrantKernelManagedLayout<'a> {
     /// Returns the entire grant size including the kernel owned memory,
     /// padding, and data for T. Requires that grant_t_align be a power of 2,
     /// which is guaranteed from align_of rust calls.
-    fn grant_size(
+    const fn grant_size(
         upcalls_num: UpcallItems,
         allow_ro_num: AllowRoItems,
         allow_rw_num: AllowRwItems,
@@ -368,11 +367,15 @@ impl<'a> EnteredGrantKernelManagedLayout<'a> {
 
     /// Returns the alignment of the entire grant region based on the alignment
     /// of data T.
-    fn grant_align(grant_t_align: GrantDataAlign) -> usize {
+    const fn grant_align(grant_t_align: GrantDataAlign) -> usize {
         // The kernel owned memory all aligned to usize. We need to use the
         // higher of the two alignment to ensure our padding calculations work
         // for any alignment of T.
-        cmp::max(align_of::<usize>(), grant_t_align.0)
+        if grant_t_align.0 > align_of::<usize>() {
+            grant_t_align.0
+        } else {
+            align_of::<usize>()
+        }
     }
 
     /// Returns the offset for the grant data t within the entire grant region.
@@ -837,6 +840,41 @@ pub(crate) fn subscribe(
     }
 }
 
+/// Schedule the upcall the process subscribed with `upcall_id`, as if the
+/// driver had scheduled it. Used to test how apps handle upcalls.
+pub(crate) fn schedule_subscribed_upcall(
+    process: &dyn Process,
+    upcall_id: UpcallId,
+    r: (usize, usize, usize),
+) -> Result<(), ErrorCode> {
+    // Enter grant and keep it open until `layout` goes out of scope.
+    let mut layout = enter_grant_kernel_managed(process, upcall_id.driver_num)?;
+
+    // # Safety
+    //
+    // As in `subscribe()`, the grant region is valid and initialized, and
+    // held open while the slice is used.
+    let upcall = layout
+        .get_upcalls_slice()
+        .get(upcall_id.subscribe_num)
+        .map(|saved_upcall| {
+            Upcall::new(
+                process.processid(),
+                upcall_id,
+                saved_upcall.appdata,
+                saved_upcall.fn_ptr,
+            )
+        })
+        .ok_or(ErrorCode::NOSUPPORT)?;
+    upcall
+        .schedule(process, r.0, r.1, r.2)
+        .map_err(|err| match err {
+            UpcallError::InvalidSubscribeNum => ErrorCode::NOSUPPORT,
+            UpcallError::QueueFull => ErrorCode::NOMEM,
+            UpcallError::KernelError => ErrorCode::FAIL,
+        })
+}
+
 /// Stores the specified read-only process buffer in the kernel managed grant
 /// region for this process and driver. The previous read-only process buffer
 /// stored at the same allow_num id is returned.
@@ -1695,6 +1733,25 @@ impl<T: Default, Upcalls: UpcallSize, AllowROs: AllowRoSize, AllowRWs: AllowRwSi
         }
     }
 
+    /// The most bytes of a process's grant region this grant takes once it is
+    /// allocated for the process: the kernel-managed upcall and allow slots,
+    /// `T`, and padding to align them.
+    ///
+    /// Capsules can use this to tell boards how much RAM each process needs
+    /// for them. The grant pointer table, whose size depends only on the
+    /// number of grants the board creates, is not included.
+    pub const fn size() -> usize {
+        let grant_t_align = GrantDataAlign(align_of::<T>());
+        EnteredGrantKernelManagedLayout::grant_size(
+            UpcallItems(Upcalls::COUNT),
+            AllowRoItems(AllowROs::COUNT),
+            AllowRwItems(AllowRWs::COUNT),
+            GrantDataSize(size_of::<T>()),
+            grant_t_align,
+        ) + EnteredGrantKernelManagedLayout::grant_align(grant_t_align)
+            - 1
+    }
+
     /// Enter the grant for a specific process.
     ///
     /// This creates a [`ProcessGrant`] which is a handle for a grant allocated
@@ -1804,3 +1861,414 @@ impl<'a, T: Default, Upcalls: UpcallSize, AllowROs: AllowRoSize, AllowRWs: Allow
             .find_map(|process| ProcessGrant::new_if_allocated(grant, process))
     }
 }
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use super::*;
+    use core::cell::Cell;
+    use core::fmt::Write;
+    use std::boxed::Box;
+    use std::vec;
+
+    use crate::platform::mpu;
+    use crate::process::{
+        BinaryVersion, FunctionCall, ProcessAddresses, ProcessSizes, ShortId, State, Task,
+    };
+    use crate::process_checker::AcceptedCredential;
+    use crate::storage_permissions::StoragePermissions;
+    use crate::syscall::{ContextSwitchReason, Syscall, SyscallReturn};
+    use tock_tbf::types::CommandPermissions;
+
+    const NUM_GRANTS: usize = 2;
+
+    /// A process with only a grant region, which is allocated from the top
+    /// down like `ProcessStandard` does. Allocations that would go below
+    /// `start` fail, as when the process's memory is used up.
+    struct GrantOnlyProcess {
+        processid: Cell<Option<ProcessId>>,
+        start: usize,
+        kernel_break: Cell<usize>,
+        grants: [Cell<Option<usize>>; NUM_GRANTS],
+        entered: [Cell<bool>; NUM_GRANTS],
+    }
+
+    /// A kernel with one `GrantOnlyProcess` whose grant region holds
+    /// `budget` bytes below an address that is `misalignment` bytes past a
+    /// 16-byte boundary.
+    fn process_with_grant_region(
+        budget: usize,
+        misalignment: usize,
+    ) -> (&'static Kernel, &'static GrantOnlyProcess) {
+        let memory: &'static mut [u128] = Box::leak(vec![0; 64].into_boxed_slice());
+        let top = memory.as_ptr() as usize + 32 * 16 + misalignment;
+        let process: &'static GrantOnlyProcess = Box::leak(Box::new(GrantOnlyProcess {
+            processid: Cell::new(None),
+            start: top - budget,
+            kernel_break: Cell::new(top),
+            grants: Default::default(),
+            entered: Default::default(),
+        }));
+        let processes: &'static [Option<&'static dyn Process>] =
+            Box::leak(Box::new([Some(process as &dyn Process)]));
+        let kernel: &'static Kernel = Box::leak(Box::new(Kernel::new(processes)));
+        process.processid.set(Some(ProcessId::new(kernel, 0, 0)));
+        (kernel, process)
+    }
+
+    impl Process for GrantOnlyProcess {
+        fn processid(&self) -> ProcessId {
+            self.processid.get().unwrap()
+        }
+        fn allocate_grant(
+            &self,
+            grant_num: usize,
+            _driver_num: usize,
+            size: usize,
+            align: usize,
+        ) -> Result<(), ()> {
+            let slot = self.grants.get(grant_num).ok_or(())?;
+            if slot.get().is_some() {
+                return Err(());
+            }
+            let new_break = self
+                .kernel_break
+                .get()
+                .checked_sub(size)
+                .map(|address| address & !(align - 1))
+                .filter(|address| *address >= self.start)
+                .ok_or(())?;
+            self.kernel_break.set(new_break);
+            slot.set(Some(new_break));
+            Ok(())
+        }
+        fn grant_is_allocated(&self, grant_num: usize) -> Option<bool> {
+            self.grants.get(grant_num).map(|slot| slot.get().is_some())
+        }
+        fn enter_grant(&self, grant_num: usize) -> Result<NonNull<u8>, Error> {
+            let address = self.grants[grant_num].get().ok_or(Error::OutOfMemory)?;
+            if self.entered[grant_num].replace(true) {
+                return Err(Error::AlreadyInUse);
+            }
+            NonNull::new(address as *mut u8).ok_or(Error::OutOfMemory)
+        }
+        unsafe fn leave_grant(&self, grant_num: usize) {
+            self.entered[grant_num].set(false);
+        }
+        fn grant_allocated_count(&self) -> Option<usize> {
+            Some(
+                self.grants
+                    .iter()
+                    .filter(|slot| slot.get().is_some())
+                    .count(),
+            )
+        }
+
+        // Nothing else is used by grants.
+        fn short_app_id(&self) -> ShortId {
+            unimplemented!()
+        }
+        fn binary_version(&self) -> Option<BinaryVersion> {
+            unimplemented!()
+        }
+        fn get_credential(&self) -> Option<AcceptedCredential> {
+            unimplemented!()
+        }
+        fn get_restart_count(&self) -> usize {
+            unimplemented!()
+        }
+        fn get_process_name(&self) -> &'static str {
+            unimplemented!()
+        }
+        fn has_tasks(&self) -> bool {
+            unimplemented!()
+        }
+        fn pending_tasks(&self) -> usize {
+            unimplemented!()
+        }
+        fn enqueue_task(&self, _task: Task) -> Result<(), ErrorCode> {
+            unimplemented!()
+        }
+        fn dequeue_task(&self) -> Option<Task> {
+            unimplemented!()
+        }
+        fn remove_upcall(&self, _upcall_id: UpcallId) -> Option<Task> {
+            unimplemented!()
+        }
+        fn remove_pending_upcalls(&self, _upcall_id: UpcallId) {
+            unimplemented!()
+        }
+        fn get_state(&self) -> State {
+            unimplemented!()
+        }
+        fn ready(&self) -> bool {
+            unimplemented!()
+        }
+        fn is_running(&self) -> bool {
+            unimplemented!()
+        }
+        fn set_yielded_state(&self) {
+            unimplemented!()
+        }
+        fn set_yielded_for_state(&self, _upcall_id: UpcallId) {
+            unimplemented!()
+        }
+        fn stop(&self) {
+            unimplemented!()
+        }
+        fn resume(&self) {
+            unimplemented!()
+        }
+        fn set_fault_state(&self) {
+            unimplemented!()
+        }
+        fn start(&self, _cap: &dyn crate::capabilities::ProcessStartCapability) {
+            unimplemented!()
+        }
+        fn try_restart(&self, _completion_code: Option<u32>) {
+            unimplemented!()
+        }
+        fn terminate(&self, _completion_code: Option<u32>) {
+            unimplemented!()
+        }
+        fn get_completion_code(&self) -> Option<Option<u32>> {
+            unimplemented!()
+        }
+        fn brk(&self, _new_break: *const u8) -> Result<*const u8, Error> {
+            unimplemented!()
+        }
+        fn sbrk(&self, _increment: isize) -> Result<*const u8, Error> {
+            unimplemented!()
+        }
+        fn number_writeable_flash_regions(&self) -> usize {
+            unimplemented!()
+        }
+        fn get_writeable_flash_region(&self, _region_index: usize) -> (u32, u32) {
+            unimplemented!()
+        }
+        fn update_stack_start_pointer(&self, _stack_pointer: *const u8) {
+            unimplemented!()
+        }
+        fn update_heap_start_pointer(&self, _heap_pointer: *const u8) {
+            unimplemented!()
+        }
+        fn build_readwrite_process_buffer(
+            &self,
+            _buf_start_addr: *mut u8,
+            _size: usize,
+        ) -> Result<ReadWriteProcessBuffer, ErrorCode> {
+            unimplemented!()
+        }
+        fn build_readonly_process_buffer(
+            &self,
+            _buf_start_addr: *const u8,
+            _size: usize,
+        ) -> Result<ReadOnlyProcessBuffer, ErrorCode> {
+            unimplemented!()
+        }
+        unsafe fn set_byte(&self, _addr: *mut u8, _value: u8) -> bool {
+            unimplemented!()
+        }
+        fn get_command_permissions(
+            &self,
+            _driver_num: usize,
+            _offset: usize,
+        ) -> CommandPermissions {
+            unimplemented!()
+        }
+        fn get_storage_permissions(&self) -> StoragePermissions {
+            unimplemented!()
+        }
+        fn setup_mpu(&self) {
+            unimplemented!()
+        }
+        fn add_mpu_region(
+            &self,
+            _unallocated_memory_start: *const u8,
+            _unallocated_memory_size: usize,
+            _min_region_size: usize,
+        ) -> Option<mpu::Region> {
+            unimplemented!()
+        }
+        fn add_readonly_mpu_region(
+            &self,
+            _start: *const u8,
+            _size: usize,
+            _min_region_size: usize,
+        ) -> Option<mpu::Region> {
+            unimplemented!()
+        }
+        fn remove_mpu_region(&self, _region: mpu::Region) -> Result<(), ErrorCode> {
+            unimplemented!()
+        }
+        fn allocate_custom_grant(
+            &self,
+            _size: usize,
+            _align: usize,
+        ) -> Result<(ProcessCustomGrantIdentifier, NonNull<u8>), ()> {
+            unimplemented!()
+        }
+        fn enter_custom_grant(
+            &self,
+            _identifier: ProcessCustomGrantIdentifier,
+        ) -> Result<*mut u8, Error> {
+            unimplemented!()
+        }
+        fn lookup_grant_from_driver_num(&self, _driver_num: usize) -> Result<usize, Error> {
+            unimplemented!()
+        }
+        fn is_valid_upcall_function_pointer(&self, _upcall_fn: NonNull<()>) -> bool {
+            unimplemented!()
+        }
+        fn set_syscall_return_value(&self, _return_value: SyscallReturn) {
+            unimplemented!()
+        }
+        fn set_process_function(&self, _callback: FunctionCall) {
+            unimplemented!()
+        }
+        fn switch_to(&self) -> Option<ContextSwitchReason> {
+            unimplemented!()
+        }
+        fn get_addresses(&self) -> ProcessAddresses {
+            unimplemented!()
+        }
+        fn get_sizes(&self) -> ProcessSizes {
+            unimplemented!()
+        }
+        fn get_stored_state(&self, _out: &mut [u8]) -> Result<usize, ErrorCode> {
+            unimplemented!()
+        }
+        fn print_full_process(&self, _writer: &mut dyn Write) {
+            unimplemented!()
+        }
+        fn debug_syscall_count(&self) -> usize {
+            unimplemented!()
+        }
+        fn debug_dropped_upcall_count(&self) -> usize {
+            unimplemented!()
+        }
+        fn debug_timeslice_expiration_count(&self) -> usize {
+            unimplemented!()
+        }
+        fn debug_timeslice_expired(&self) {
+            unimplemented!()
+        }
+        fn debug_syscall_called(&self, _last_syscall: Syscall) {
+            unimplemented!()
+        }
+        fn debug_syscall_last(&self) -> Option<Syscall> {
+            unimplemented!()
+        }
+    }
+
+    type SmallGrant = Grant<usize, UpcallCount<1>, AllowRoCount<1>, AllowRwCount<0>>;
+    type AlignedGrant = Grant<Aligned, UpcallCount<2>, AllowRoCount<0>, AllowRwCount<1>>;
+
+    #[test]
+    fn size_is_enough_at_any_alignment() {
+        for misalignment in 0..16 {
+            let (kernel, process) = process_with_grant_region(AlignedGrant::size(), misalignment);
+            let grant = AlignedGrant::new(kernel, 0x1000, 0);
+            assert_eq!(grant.enter(process.processid(), |_, _| ()), Ok(()));
+
+            let (kernel, process) = process_with_grant_region(SmallGrant::size(), misalignment);
+            let grant = SmallGrant::new(kernel, 0x1000, 0);
+            assert_eq!(grant.enter(process.processid(), |_, _| ()), Ok(()));
+        }
+    }
+
+    #[test]
+    fn enter_fails_when_grant_region_is_exhausted() {
+        // One byte short of the worst-case padding the allocation needs.
+        let (kernel, process) = process_with_grant_region(AlignedGrant::size() - 1, 15);
+        let grant = AlignedGrant::new(kernel, 0x1000, 0);
+        assert_eq!(
+            grant.enter(process.processid(), |_, _| ()),
+            Err(Error::OutOfMemory)
+        );
+
+        // The failed allocation is not recorded, so it is tried again and
+        // fails again.
+        assert_eq!(process.grant_is_allocated(0), Some(false));
+        assert_eq!(
+            grant.enter(process.processid(), |_, _| ()),
+            Err(Error::OutOfMemory)
+        );
+    }
+
+    #[test]
+    fn exhaustion_leaves_allocated_grants_usable() {
+        let (kernel, process) = process_with_grant_region(SmallGrant::size() + 8, 0);
+        let first = SmallGrant::new(kernel, 0x1000, 0);
+        let second = SmallGrant::new(kernel, 0x2000, 1);
+
+        assert_eq!(
+            first.enter(process.processid(), |data, _| **data = 42),
+            Ok(())
+        );
+        assert_eq!(
+            second.enter(process.processid(), |_, _| ()),
+            Err(Error::OutOfMemory)
+        );
+        assert_eq!(process.grant_allocated_count(), Some(1));
+
+        // The first grant keeps its data, and does not need more memory.
+        assert_eq!(first.enter(process.processid(), |data, _| **data), Ok(42));
+    }
+
+    // Only the size and alignment of the type matter, the field is never read.
+    #[allow(dead_code)]
+    #[repr(align(16))]
+    #[derive(Default)]
+    struct Aligned([u8; 16]);
+
+    #[test]
+    fn grant_size_counts_kernel_managed_slots() {
+        let size = EnteredGrantKernelManagedLayout::grant_size(
+            UpcallItems(2),
+            AllowRoItems(1),
+            AllowRwItems(3),
+            GrantDataSize(size_of::<usize>()),
+            GrantDataAlign(align_of::<usize>()),
+        );
+        assert_eq!(
+            size,
+            size_of::<usize>()
+                + 2 * size_of::<SavedUpcall>()
+                + size_of::<SavedAllowRo>()
+                + 3 * size_of::<SavedAllowRw>()
+                + size_of::<usize>()
+        );
+    }
+
+    #[test]
+    fn grant_size_pads_data_to_its_alignment() {
+        let size = EnteredGrantKernelManagedLayout::grant_size(
+            UpcallItems(0),
+            AllowRoItems(0),
+            AllowRwItems(0),
+            GrantDataSize(size_of::<Aligned>()),
+            GrantDataAlign(align_of::<Aligned>()),
+        );
+        // The counters word is padded up to the alignment of the data.
+        assert_eq!(size, 16 + 16);
+    }
+
+    #[test]
+    fn size_includes_allocation_padding() {
+        assert_eq!(
+            Grant::<usize, UpcallCount<1>, AllowRoCount<1>, AllowRwCount<0>>::size(),
+            size_of::<usize>()
+                + size_of::<SavedUpcall>()
+                + size_of::<SavedAllowRo>()
+                + size_of::<usize>()
+                + align_of::<usize>()
+                - 1
+        );
+        assert_eq!(
+            Grant::<Aligned, UpcallCount<0>, AllowRoCount<0>, AllowRwCount<0>>::size(),
+            16 + 16 + 16 - 1
+        );
+    }
+}
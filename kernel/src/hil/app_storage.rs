@@ -0,0 +1,91 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2024.
+
+//! Interface for nonvolatile storage kept on behalf of apps.
+//!
+//! Unlike [`NonvolatileStorage`](super::nonvolatile_storage::NonvolatileStorage),
+//! which works on absolute addresses of one device, an `AppStorage` gives
+//! each app a storage area addressed from 0 and checks the app may use it.
+//! Kernel services that store data for apps can use any backend, such as a
+//! region of flash, a key-value store or an EEPROM, through this interface.
+//!
+//! Operations name the app by its `ProcessId`. Backends that isolate apps
+//! from each other key each app's storage by its `ShortId`, so the data
+//! stays with the app across restarts. Backends that share one area between
+//! apps report [`Isolation::Shared`], and rely on storage permissions to
+//! decide which apps may read and write it.
+
+use crate::process::ProcessId;
+use crate::ErrorCode;
+
+/// How an `AppStorage` keeps apps' data apart.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Isolation {
+    /// Each `ShortId` has its own storage, which other apps cannot access.
+    PerApp,
+    /// Apps allowed to use the storage all see the same data.
+    Shared,
+}
+
+/// Storage for apps, each addressed from offset 0.
+pub trait AppStorage<'a> {
+    fn set_client(&self, client: &'a dyn AppStorageClient);
+
+    /// How the storage keeps apps' data apart.
+    fn isolation(&self) -> Isolation;
+
+    /// Prepare the storage of `processid` for use. `init_done` is called
+    /// once it is ready, or with the reason the app cannot use the storage.
+    fn init(&self, processid: ProcessId) -> Result<(), ErrorCode>;
+
+    /// Number of bytes of storage `processid` can use.
+    fn size(&self, processid: ProcessId) -> Result<usize, ErrorCode>;
+
+    /// Read `length` bytes at `offset` in the storage of `processid` into
+    /// `buffer`, which must be at least `length` bytes long. On error the
+    /// buffer is handed back.
+    fn read(
+        &self,
+        processid: ProcessId,
+        buffer: &'static mut [u8],
+        offset: usize,
+        length: usize,
+    ) -> Result<(), (ErrorCode, &'static mut [u8])>;
+
+    /// Write `length` bytes from `buffer` at `offset` in the storage of
+    /// `processid`. On error the buffer is handed back.
+    fn write(
+        &self,
+        processid: ProcessId,
+        buffer: &'static mut [u8],
+        offset: usize,
+        length: usize,
+    ) -> Result<(), (ErrorCode, &'static mut [u8])>;
+}
+
+/// Client interface for app storage.
+pub trait AppStorageClient {
+    /// The storage of `processid` was prepared.
+    fn init_done(&self, processid: ProcessId, result: Result<(), ErrorCode>);
+
+    /// A read finished. `length` is the number of bytes read into `buffer`,
+    /// and is 0 if the read failed.
+    fn read_done(
+        &self,
+        processid: ProcessId,
+        buffer: &'static mut [u8],
+        length: usize,
+        result: Result<(), ErrorCode>,
+    );
+
+    /// A write finished. `length` is the number of bytes written, and is 0
+    /// if the write failed.
+    fn write_done(
+        &self,
+        processid: ProcessId,
+        buffer: &'static mut [u8],
+        length: usize,
+        result: Result<(), ErrorCode>,
+    );
+}
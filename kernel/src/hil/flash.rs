@@ -94,6 +94,7 @@
 //! }
 //! ```
 
+use crate::hil::nonvolatile_storage::StorageGeometry;
 use crate::ErrorCode;
 
 /// Flash errors returned in the callbacks.
@@ -133,6 +134,41 @@ pub trait Flash {
 
     /// Erase a page of flash by setting every byte to 0xFF.
     fn erase_page(&self, page_number: usize) -> Result<(), ErrorCode>;
+
+    /// Return the layout of the flash, or `None` if it is not known. The
+    /// erase block size is the page size.
+    fn geometry(&self) -> Option<StorageGeometry> {
+        None
+    }
+
+    /// Copy `buffer.len()` bytes of flash starting at byte `address` into
+    /// `buffer` before returning, without a callback.
+    ///
+    /// Only flash that is mapped into memory can do this. Other flash, and
+    /// flash that is busy with an operation, returns `NOSUPPORT` or `BUSY`.
+    fn read_mapped(&self, _address: usize, _buffer: &mut [u8]) -> Result<(), ErrorCode> {
+        Err(ErrorCode::NOSUPPORT)
+    }
+}
+
+/// Flash that can lock regions of itself against writes and erases in
+/// hardware.
+///
+/// Locks cover whole lock regions of `lock_region_size()` bytes, which may be
+/// larger than a page. Writes and erases of a page in a locked region fail
+/// with a `FlashError`. Locking and unlocking complete before the call
+/// returns, and fail with `BUSY` while a flash operation is in progress.
+pub trait WriteProtection {
+    /// Number of bytes covered by each lock.
+    fn lock_region_size(&self) -> usize;
+
+    /// Lock the lock regions that contain any of the `length` bytes starting
+    /// at flash address `address`.
+    fn lock(&self, address: usize, length: usize) -> Result<(), ErrorCode>;
+
+    /// Unlock the lock regions that contain any of the `length` bytes
+    /// starting at flash address `address`.
+    fn unlock(&self, address: usize, length: usize) -> Result<(), ErrorCode>;
 }
 
 /// Implement `Client` to receive callbacks from `Flash`.
@@ -6,6 +6,7 @@
 
 pub mod adc;
 pub mod analog_comparator;
+pub mod app_storage;
 pub mod ble_advertising;
 pub mod bus8080;
 pub mod buzzer;
@@ -26,9 +27,12 @@ pub mod kv;
 pub mod led;
 pub mod log;
 pub mod nonvolatile_storage;
+pub mod power_fail;
 pub mod public_key_crypto;
 pub mod pwm;
+pub mod quiesce;
 pub mod radio;
+pub mod reset_reason;
 pub mod rng;
 pub mod screen;
 pub mod sensors;
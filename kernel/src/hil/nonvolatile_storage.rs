@@ -6,6 +6,89 @@
 
 use crate::errorcode::ErrorCode;
 
+/// Physical layout of a storage device.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct StorageGeometry {
+    /// Number of bytes the device erases at once. Writing fewer bytes may
+    /// still cost erasing (and wearing) a whole block.
+    pub erase_block_size: usize,
+    /// Writes must start at a multiple of this many bytes and have a length
+    /// that is a multiple of it.
+    pub write_granularity: usize,
+    /// Size of the address space of the device in bytes.
+    pub total_size: usize,
+}
+
+/// `length` bytes of storage starting at `start`, for checking that an access
+/// stays inside a region.
+///
+/// An access must start inside the range: an access of any length, even 0,
+/// that starts at the end of the range is outside it, and nothing is inside
+/// an empty range. Ranges and accesses that wrap around the address space
+/// are outside too.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct StorageRange {
+    pub start: usize,
+    pub length: usize,
+}
+
+impl StorageRange {
+    pub const fn new(start: usize, length: usize) -> Self {
+        Self { start, length }
+    }
+
+    /// The first address past the range, or `None` if the range wraps around
+    /// the address space.
+    pub fn end(&self) -> Option<usize> {
+        self.start.checked_add(self.length)
+    }
+
+    /// Whether the `length` bytes starting at `address` are all inside the
+    /// range.
+    pub fn contains(&self, address: usize, length: usize) -> bool {
+        match (self.end(), address.checked_add(length)) {
+            (Some(end), Some(access_end)) => {
+                address >= self.start && address < end && access_end <= end
+            }
+            _ => false,
+        }
+    }
+
+    /// Whether any of the `length` bytes starting at `address` is inside the
+    /// range.
+    pub fn overlaps(&self, address: usize, length: usize) -> bool {
+        length > 0
+            && self.length > 0
+            && address < self.start.saturating_add(self.length)
+            && self.start < address.saturating_add(length)
+    }
+}
+
+/// Integrity of the data returned by a read.
+///
+/// Storage with error detection or correction, such as ECC-protected flash,
+/// reports it with [`NonvolatileStorageClient::read_done_status`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ReadStatus {
+    /// The data was read without errors.
+    Ok = 0,
+    /// Errors in the data were found and corrected. The data is correct, but
+    /// the storage may be wearing out and the data should be rewritten.
+    Corrected = 1,
+    /// The read failed, but may succeed if it is tried again, for example
+    /// after a bus error.
+    Transient = 2,
+    /// The data has errors that could not be corrected.
+    Uncorrectable = 3,
+}
+
+impl ReadStatus {
+    /// Whether the data read can be used.
+    pub fn is_valid(&self) -> bool {
+        matches!(self, ReadStatus::Ok | ReadStatus::Corrected)
+    }
+}
+
 /// Simple interface for reading and writing nonvolatile memory. It is expected
 /// that drivers for nonvolatile memory would implement this trait.
 pub trait NonvolatileStorage<'a> {
@@ -30,6 +113,53 @@ pub trait NonvolatileStorage<'a> {
         address: usize,
         length: usize,
     ) -> Result<(), ErrorCode>;
+
+    /// Make all writes that have completed with `write_done` durable on the
+    /// physical medium, for example by flushing a write cache. `sync_done`
+    /// is called once they are.
+    ///
+    /// Storage that has already made a write durable by the time it calls
+    /// `write_done` does not need to implement this, and returns `NOSUPPORT`.
+    fn sync(&self) -> Result<(), ErrorCode> {
+        Err(ErrorCode::NOSUPPORT)
+    }
+
+    /// Return the layout of the storage, or `None` if it is not known.
+    fn geometry(&self) -> Option<StorageGeometry> {
+        None
+    }
+
+    /// Abort the operation in progress, if any, and return the storage to an
+    /// idle state, for example after the operation never completed.
+    ///
+    /// If this returns `Ok(())`, the done callback of an aborted operation is
+    /// still called afterwards, for example to return its buffer. Storage
+    /// that cannot be reset returns `NOSUPPORT`.
+    fn reset(&self) -> Result<(), ErrorCode> {
+        Err(ErrorCode::NOSUPPORT)
+    }
+
+    /// Copy `buffer.len()` bytes starting at address `address` into `buffer`
+    /// before returning, without calling `read_done`.
+    ///
+    /// Only storage that is mapped into memory can do this. Other storage,
+    /// and storage that is busy with an operation, returns `NOSUPPORT` or
+    /// `BUSY`, and the data has to be read with `read` instead.
+    fn read_mapped(&self, _address: usize, _buffer: &mut [u8]) -> Result<(), ErrorCode> {
+        Err(ErrorCode::NOSUPPORT)
+    }
+
+    /// Write `data` starting at address `address` before returning, without
+    /// calling `write_done`, and make it durable.
+    ///
+    /// This is a fast path for saving a few bytes when there is no time to
+    /// wait for a callback, for example when power is failing. Only storage
+    /// that writes small amounts of data in place within microseconds, like
+    /// FRAM, can do this. Other storage, and storage that is busy with an
+    /// operation, returns `NOSUPPORT` or `BUSY`.
+    fn write_now(&self, _address: usize, _data: &[u8]) -> Result<(), ErrorCode> {
+        Err(ErrorCode::NOSUPPORT)
+    }
 }
 
 /// Client interface for nonvolatile storage.
@@ -39,8 +169,106 @@ pub trait NonvolatileStorageClient {
     /// were actually read.
     fn read_done(&self, buffer: &'static mut [u8], length: usize);
 
+    /// Called instead of `read_done` by storage that checks the integrity of
+    /// the data it reads. `status` says whether the data in `buffer` can be
+    /// used.
+    ///
+    /// Clients that do not implement this get `read_done`, with a `length`
+    /// of 0 if the data cannot be used.
+    fn read_done_status(&self, buffer: &'static mut [u8], length: usize, status: ReadStatus) {
+        let length = if status.is_valid() { length } else { 0 };
+        self.read_done(buffer, length);
+    }
+
     /// `write_done` is called when the implementor is finished writing from the
     /// buffer. The callback returns the buffer and the number of bytes that
     /// were actually written.
     fn write_done(&self, buffer: &'static mut [u8], length: usize);
+
+    /// Called instead of `write_done` by storage that can tell why a write
+    /// failed, for example because the data read back differs from the data
+    /// written.
+    ///
+    /// Clients that do not implement this get `write_done`, with a `length`
+    /// of 0 if the write failed.
+    fn write_done_result(
+        &self,
+        buffer: &'static mut [u8],
+        length: usize,
+        result: Result<(), ErrorCode>,
+    ) {
+        let length = if result.is_ok() { length } else { 0 };
+        self.write_done(buffer, length);
+    }
+
+    /// `sync_done` is called when a `sync` started by this client finishes.
+    fn sync_done(&self, _result: Result<(), ErrorCode>) {}
+}
+
+/// Copies regions of nonvolatile storage to a backup area, usually on another
+/// storage device, for example before an update that could corrupt them.
+///
+/// The regions and where their copies go are fixed by the board. Each region
+/// has a name, so that it can be picked from a console.
+pub trait StorageBackup<'a> {
+    fn set_client(&self, client: &'a dyn StorageBackupClient);
+
+    /// Name of the region with index `index`, or `None` if there are fewer
+    /// regions.
+    fn region_name(&self, index: usize) -> Option<&'static str>;
+
+    /// Copy the region with index `index`, or every region if `index` is
+    /// `None`. Returns `BUSY` if a backup is in progress, and `INVAL` if there
+    /// is no such region.
+    fn backup(&self, index: Option<usize>) -> Result<(), ErrorCode>;
+}
+
+/// Client interface for [`StorageBackup`].
+pub trait StorageBackupClient {
+    /// Called after each part of a backup is copied, with the number of bytes
+    /// copied so far and the number of bytes the backup copies in total.
+    fn backup_progress(&self, copied: usize, total: usize);
+
+    /// Called once the backup finished or failed, with the number of bytes
+    /// copied.
+    fn backup_done(&self, result: Result<(), ErrorCode>, copied: usize);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_contains_bounds() {
+        let range = StorageRange::new(0x100, 0x100);
+        assert!(range.contains(0x100, 0x100));
+        assert!(range.contains(0x100, 0));
+        assert!(range.contains(0x1ff, 1));
+        assert!(!range.contains(0x1ff, 2));
+        assert!(!range.contains(0xff, 1));
+        assert!(!range.contains(0x200, 0));
+        assert!(!range.contains(0x200, 1));
+    }
+
+    #[test]
+    fn test_contains_empty_and_wrapping() {
+        assert!(!StorageRange::new(0x100, 0).contains(0x100, 0));
+        assert!(!StorageRange::new(usize::MAX, 2).contains(usize::MAX, 1));
+        let range = StorageRange::new(0, usize::MAX);
+        assert!(range.contains(usize::MAX - 1, 1));
+        assert!(!range.contains(usize::MAX - 1, 2));
+        assert!(!range.contains(1, usize::MAX));
+    }
+
+    #[test]
+    fn test_overlaps() {
+        let range = StorageRange::new(0x100, 0x100);
+        assert!(range.overlaps(0xff, 2));
+        assert!(range.overlaps(0x1ff, 0x1000));
+        assert!(!range.overlaps(0xff, 1));
+        assert!(!range.overlaps(0x200, 1));
+        assert!(!range.overlaps(0x180, 0));
+        assert!(!StorageRange::new(0x100, 0).overlaps(0, 0x1000));
+        assert!(StorageRange::new(0x100, 0x100).overlaps(0, usize::MAX));
+    }
 }
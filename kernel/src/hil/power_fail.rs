@@ -0,0 +1,37 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2024.
+
+//! Interface for early warning that the supply voltage is failing.
+//!
+//! Many chips can compare their supply voltage against a threshold and raise
+//! an interrupt when it drops below, for example a power-fail comparator or a
+//! brown-out detector that warns before it resets the chip. Between the
+//! warning and the loss of power there is usually only time for a few small
+//! writes to storage that does not need to be erased first, like FRAM.
+
+use crate::ErrorCode;
+
+/// A supply voltage monitor that warns its client when power is failing.
+pub trait PowerFailWarning<'a> {
+    fn set_client(&self, client: &'a dyn PowerFailClient);
+
+    /// Start watching the supply voltage. `power_failing` is called the next
+    /// time it drops below the threshold the chip or board configured.
+    ///
+    /// Returns `NOSUPPORT` if the chip cannot watch its supply voltage.
+    fn enable(&self) -> Result<(), ErrorCode>;
+
+    /// Stop watching the supply voltage.
+    fn disable(&self) -> Result<(), ErrorCode>;
+}
+
+pub trait PowerFailClient {
+    /// The supply voltage dropped below the threshold.
+    ///
+    /// This is called from the interrupt bottom half as soon as the kernel
+    /// services the interrupt, and power may be lost at any moment after.
+    /// Clients must only do work that completes before returning, and must
+    /// not wait for callbacks.
+    fn power_failing(&self);
+}
@@ -0,0 +1,32 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2024.
+
+//! Interface for bringing a device to a safe state before a reset.
+//!
+//! Boards that reset or reboot on purpose can first ask devices with state
+//! that must not be cut off mid-operation, like nonvolatile storage with a
+//! write in progress, to finish what they are doing.
+
+use crate::ErrorCode;
+
+/// A device that can stop accepting new operations and report once the ones
+/// it already started have finished.
+pub trait Quiesce<'a> {
+    fn set_quiesce_client(&self, client: &'a dyn QuiesceClient);
+
+    /// Stop accepting new operations and finish or abort the outstanding
+    /// ones. Once `quiesce` has been called, the device rejects new
+    /// operations until the system is reset.
+    ///
+    /// Returns `Ok(())` if `quiesce_done` will be called once the device is
+    /// idle, or `Err(ErrorCode::ALREADY)` if it already is, in which case
+    /// there is no callback.
+    fn quiesce(&self) -> Result<(), ErrorCode>;
+}
+
+pub trait QuiesceClient {
+    /// All operations that were outstanding when `quiesce` was called have
+    /// finished or been aborted.
+    fn quiesce_done(&self);
+}
@@ -0,0 +1,43 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2024.
+
+//! Interface for finding out why the chip last reset.
+
+/// Cause of the last reset.
+///
+/// When the hardware records several causes, the one that best explains the
+/// reset is reported, for example a watchdog reset over a reset pin press.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ResetCause {
+    /// The chip does not record a cause it can be mapped to.
+    Unknown = 0,
+    /// Power was applied.
+    PowerOn = 1,
+    /// The reset pin was pulled low.
+    ResetPin = 2,
+    /// The watchdog expired.
+    Watchdog = 3,
+    /// Software requested a reset, for example through `SYSRESETREQ`.
+    Software = 4,
+    /// The supply voltage dropped below the brown-out threshold.
+    BrownOut = 5,
+    /// The CPU locked up.
+    Lockup = 6,
+    /// The chip woke from its deepest sleep mode, which resets it.
+    Wakeup = 7,
+}
+
+/// Hardware that records why the chip last reset.
+pub trait ResetReason {
+    /// Return the cause of the last reset.
+    fn reset_cause(&self) -> ResetCause;
+
+    /// Return the chip-specific register value the cause was derived from,
+    /// for example to tell which supply a brown-out was detected on.
+    fn reset_flags(&self) -> u32;
+
+    /// Clear the recorded causes. Chips whose reset cause flags accumulate
+    /// across resets need this to report only the cause of the next one.
+    fn clear_reset_cause(&self) {}
+}
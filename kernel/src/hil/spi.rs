@@ -430,6 +430,25 @@ pub trait SpiMaster<'a> {
     /// Raise the chip select line after a [`SpiMaster::read_write_bytes`]
     /// completes. This will complete the SPI operation.
     fn release_low(&self);
+
+    /// Synchronously write `data` as one transfer, with the chip select
+    /// asserted for all of it and raised after the last byte, and discard what
+    /// is read. Not for general use because it is blocking: intended for the
+    /// few bytes that must be written before returning, for example when
+    /// power is failing.
+    ///
+    /// ### Return values
+    ///
+    /// - `Ok(())`: the bytes were written
+    /// - `Err(BUSY)`: the SPI bus is busy with a
+    ///   [`SpiMaster::read_write_bytes`] operation whose callback hasn't been
+    ///   called yet.
+    /// - `Err(FAIL)`: the controller did not take or send the bytes in time,
+    ///   and the write was abandoned
+    /// - `Err(NOSUPPORT)`: the controller cannot write synchronously
+    fn write_bytes_now(&self, _data: &[u8]) -> Result<(), ErrorCode> {
+        Err(ErrorCode::NOSUPPORT)
+    }
 }
 
 /// A chip-select-specific interface to the SPI Controller hardware, such that a
@@ -475,6 +494,12 @@ pub trait SpiMasterDevice<'a> {
 
     /// Get the current bus phase for the current chip select.
     fn get_phase(&self) -> ClockPhase;
+
+    /// Same as [`SpiMaster::write_bytes_now`]. Also returns `BUSY` while
+    /// another device on the bus is transferring.
+    fn write_bytes_now(&self, _data: &[u8]) -> Result<(), ErrorCode> {
+        Err(ErrorCode::NOSUPPORT)
+    }
 }
 
 /// Trait for SPI peripherals (slaves) to receive callbacks when the
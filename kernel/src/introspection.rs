@@ -156,4 +156,14 @@ impl KernelInfo {
         });
         count.get()
     }
+
+    /// Returns the process the kernel last switched to, if any. While the
+    /// kernel services an interrupt, this is the process the interrupt
+    /// stopped, or the one that ran last before the kernel went idle.
+    pub fn last_scheduled_process(
+        &self,
+        _capability: &dyn ProcessManagementCapability,
+    ) -> Option<ProcessId> {
+        self.kernel.last_process()
+    }
 }
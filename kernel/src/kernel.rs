@@ -33,7 +33,7 @@ use crate::syscall::{ContextSwitchReason, SyscallReturn};
 use crate::syscall::{Syscall, YieldCall};
 use crate::syscall_driver::CommandReturn;
 use crate::upcall::{Upcall, UpcallId};
-use crate::utilities::cells::NumericCellExt;
+use crate::utilities::cells::{NumericCellExt, OptionalCell};
 
 /// Threshold in microseconds to consider a process's timeslice to be exhausted.
 /// That is, Tock will skip re-scheduling a process if its remaining timeslice
@@ -59,6 +59,9 @@ pub struct Kernel {
     /// created and the data structures for grants have already been
     /// established.
     grants_finalized: Cell<bool>,
+
+    /// The process the kernel last switched to.
+    last_process: OptionalCell<ProcessId>,
 }
 
 /// Represents the different outcomes when trying to allocate a grant region
@@ -93,6 +96,7 @@ impl Kernel {
             process_identifier_max: Cell::new(0),
             grant_counter: Cell::new(0),
             grants_finalized: Cell::new(false),
+            last_process: OptionalCell::empty(),
         }
     }
 
@@ -167,6 +171,24 @@ impl Kernel {
         }
     }
 
+    /// Get an [`UpcallInjector`](crate::upcall::UpcallInjector), which
+    /// schedules upcalls into processes as if a driver had scheduled them.
+    pub fn upcall_injector(
+        &'static self,
+        _capability: &dyn capabilities::UpcallInjectionCapability,
+    ) -> crate::upcall::UpcallInjector {
+        crate::upcall::UpcallInjector { kernel: self }
+    }
+
+    /// Get a [`ReadOnlyMapper`](crate::process::ReadOnlyMapper), which maps
+    /// memory outside of a process's own regions read-only into the process.
+    pub fn readonly_mapper(
+        &'static self,
+        _capability: &dyn capabilities::ReadOnlyMappingCapability,
+    ) -> crate::process::ReadOnlyMapper {
+        crate::process::ReadOnlyMapper { kernel: self }
+    }
+
     /// Run a closure on every valid process. This will iterate the array of
     /// processes and call the closure on every process that exists.
     pub(crate) fn process_each<F>(&self, mut closure: F)
@@ -315,6 +337,12 @@ impl Kernel {
         self.get_grant_count_and_finalize()
     }
 
+    /// The process the kernel last switched to, which is the process that
+    /// was running if an interrupt is being serviced.
+    pub(crate) fn last_process(&self) -> Option<ProcessId> {
+        self.last_process.get()
+    }
+
     /// Create a new unique identifier for a process and return the identifier.
     ///
     /// Typically we just choose a larger number than we have used for any
@@ -551,6 +579,7 @@ impl Kernel {
                     process.setup_mpu();
                     chip.mpu().enable_app_mpu();
                     scheduler_timer.arm();
+                    self.last_process.set(process.processid());
                     let context_switch_reason = process.switch_to();
                     scheduler_timer.disarm();
                     chip.mpu().disable_app_mpu();
@@ -4,6 +4,8 @@
 
 //! Interfaces for implementing boards in Tock.
 
+use core::cell::Cell;
+
 use crate::errorcode;
 use crate::platform::chip::Chip;
 use crate::platform::scheduler_timer;
@@ -12,6 +14,7 @@ use crate::process;
 use crate::scheduler::Scheduler;
 use crate::syscall;
 use crate::syscall_driver::SyscallDriver;
+use crate::utilities::cells::NumericCellExt;
 use tock_tbf::types::CommandPermissions;
 
 /// Combination trait that boards provide to the kernel that includes all of
@@ -284,3 +287,29 @@ pub trait ContextSwitchCallback {
 impl ContextSwitchCallback for () {
     fn context_switch_hook(&self, _process: &dyn process::Process) {}
 }
+
+/// A ContextSwitchCallback that counts how many times the kernel switched to
+/// a process. Boards that want to report the count use this as their
+/// `ContextSwitchCallback`; other boards do not pay for the counting.
+pub struct ContextSwitchCounter {
+    count: Cell<usize>,
+}
+
+impl ContextSwitchCounter {
+    pub const fn new() -> Self {
+        Self {
+            count: Cell::new(0),
+        }
+    }
+
+    /// Number of times the kernel switched to a process since boot.
+    pub fn count(&self) -> usize {
+        self.count.get()
+    }
+}
+
+impl ContextSwitchCallback for ContextSwitchCounter {
+    fn context_switch_hook(&self, _process: &dyn process::Process) {
+        self.count.increment();
+    }
+}
@@ -30,7 +30,7 @@ pub use crate::process_loading::ProcessLoadError;
 pub use crate::process_loading::SequentialProcessLoaderMachine;
 pub use crate::process_loading::{ProcessLoadingAsync, ProcessLoadingAsyncClient};
 pub use crate::process_policies::{ProcessFaultPolicy, ProcessStandardStoragePermissionsPolicy};
-pub use crate::process_printer::{ProcessPrinter, ProcessPrinterContext};
+pub use crate::process_printer::{ProcessPrinter, ProcessPrinterContext, ProcessStateReporter};
 pub use crate::process_standard::ProcessStandard;
 pub use crate::process_standard::{ProcessStandardDebug, ProcessStandardDebugFull};
 
@@ -325,6 +325,46 @@ impl BinaryVersion {
     }
 }
 
+/// Maps memory outside of a process's own regions, such as memory-mapped
+/// storage, read-only into the process's address space.
+///
+/// Obtained with [`Kernel::readonly_mapper`], which requires the
+/// `ReadOnlyMappingCapability`.
+#[derive(Clone, Copy)]
+pub struct ReadOnlyMapper {
+    pub(crate) kernel: &'static Kernel,
+}
+
+impl ReadOnlyMapper {
+    /// Let `processid` read the `size` bytes starting at `start`, and return
+    /// the MPU region that covers them.
+    ///
+    /// Returns `INVAL` if the process does not exist, and `NOMEM` if the MPU
+    /// or the process has no room for another region, or the MPU cannot cover
+    /// exactly these bytes, for example because of its alignment rules.
+    pub fn map(
+        &self,
+        processid: ProcessId,
+        start: *const u8,
+        size: usize,
+    ) -> Result<mpu::Region, ErrorCode> {
+        self.kernel
+            .process_map_or(Err(ErrorCode::INVAL), processid, |process| {
+                process
+                    .add_readonly_mpu_region(start, size, size)
+                    .ok_or(ErrorCode::NOMEM)
+            })
+    }
+
+    /// Remove a region returned by `map()` again.
+    pub fn unmap(&self, processid: ProcessId, region: mpu::Region) -> Result<(), ErrorCode> {
+        self.kernel
+            .process_map_or(Err(ErrorCode::INVAL), processid, |process| {
+                process.remove_mpu_region(region)
+            })
+    }
+}
+
 /// This trait represents a generic process that the Tock scheduler can
 /// schedule.
 pub trait Process {
@@ -681,8 +721,22 @@ pub trait Process {
         min_region_size: usize,
     ) -> Option<mpu::Region>;
 
+    /// Allocate a new MPU region that lets the process read, but not write or
+    /// execute, memory outside of its own regions, such as memory-mapped
+    /// storage. The region is at least `min_region_size` bytes and lies
+    /// within the `size` bytes starting at `start`.
+    ///
+    /// It is not valid to call this function when the process is inactive (i.e.
+    /// the process will not run again).
+    fn add_readonly_mpu_region(
+        &self,
+        start: *const u8,
+        size: usize,
+        min_region_size: usize,
+    ) -> Option<mpu::Region>;
+
     /// Removes an MPU region from the process that has been previously added
-    /// with `add_mpu_region`.
+    /// with `add_mpu_region` or `add_readonly_mpu_region`.
     ///
     /// It is not valid to call this function when the process is inactive (i.e.
     /// the process will not run again).
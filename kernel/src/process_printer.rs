@@ -4,7 +4,9 @@
 
 //! Tools for displaying process state.
 
-use crate::process::Process;
+use core::fmt::Write;
+
+use crate::process::{Process, ProcessId};
 use crate::utilities::binary_write::BinaryWrite;
 
 /// A context token that the caller must pass back to us. This allows us to
@@ -59,3 +61,19 @@ pub trait ProcessPrinter {
         context: Option<ProcessPrinterContext>,
     ) -> Option<ProcessPrinterContext>;
 }
+
+/// Trait for capsules that can describe the state they keep for a process,
+/// such as an operation the process started and is waiting on.
+///
+/// Process printers can include this state in the report of a process, so
+/// that when a process faults while using a capsule, the report shows what
+/// the capsule was doing for it.
+pub trait ProcessStateReporter {
+    /// Write the state kept for the process `processid` to `writer`, as lines
+    /// that each start with a space and end with `"\r\n"`. Write nothing if
+    /// there is no state for the process.
+    ///
+    /// This may be called from the panic handler, so it must be synchronous
+    /// and must not change any state.
+    fn report_process_state(&self, processid: ProcessId, writer: &mut dyn Write);
+}
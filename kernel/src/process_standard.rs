@@ -784,25 +784,21 @@ impl<C: Chip, D: 'static + ProcessStandardDebug> Process for ProcessStandard<'_,
         unallocated_memory_size: usize,
         min_region_size: usize,
     ) -> Option<mpu::Region> {
-        self.mpu_config.and_then(|config| {
-            let new_region = self.chip.mpu().allocate_region(
-                unallocated_memory_start,
-                unallocated_memory_size,
-                min_region_size,
-                mpu::Permissions::ReadWriteOnly,
-                config,
-            )?;
-
-            for region in self.mpu_regions.iter() {
-                if region.get().is_none() {
-                    region.set(Some(new_region));
-                    return Some(new_region);
-                }
-            }
+        self.allocate_mpu_region(
+            unallocated_memory_start,
+            unallocated_memory_size,
+            min_region_size,
+            mpu::Permissions::ReadWriteOnly,
+        )
+    }
 
-            // Not enough room in Process struct to store the MPU region.
-            None
-        })
+    fn add_readonly_mpu_region(
+        &self,
+        start: *const u8,
+        size: usize,
+        min_region_size: usize,
+    ) -> Option<mpu::Region> {
+        self.allocate_mpu_region(start, size, min_region_size, mpu::Permissions::ReadOnly)
     }
 
     fn remove_mpu_region(&self, region: mpu::Region) -> Result<(), ErrorCode> {
@@ -1981,6 +1977,35 @@ impl<C: 'static + Chip, D: 'static + ProcessStandardDebug> ProcessStandard<'_, C
         Ok((Some(process), unused_memory))
     }
 
+    /// Allocate an MPU region with `permissions` and remember it in
+    /// `mpu_regions`, so it can be removed again.
+    fn allocate_mpu_region(
+        &self,
+        start: *const u8,
+        size: usize,
+        min_region_size: usize,
+        permissions: mpu::Permissions,
+    ) -> Option<mpu::Region> {
+        self.mpu_config.and_then(|config| {
+            // Find a free slot first, so a region is never left allocated in
+            // the MPU without the Process struct knowing about it.
+            let slot = self
+                .mpu_regions
+                .iter()
+                .find(|region| region.get().is_none())?;
+
+            let new_region = self.chip.mpu().allocate_region(
+                start,
+                size,
+                min_region_size,
+                permissions,
+                config,
+            )?;
+            slot.set(Some(new_region));
+            Some(new_region)
+        })
+    }
+
     /// Reset the process, resetting all of its state and re-initializing it so
     /// it can start running. Assumes the process is not running but is still in
     /// flash and still has its memory region allocated to it.
@@ -2014,6 +2039,11 @@ impl<C: 'static + Chip, D: 'static + ProcessStandardDebug> ProcessStandard<'_, C
         // number of available MPU configurations.
         let mut mpu_config = self.mpu_config.take().ok_or(ErrorCode::FAIL)?;
         self.chip.mpu().reset_config(&mut mpu_config);
+        // The regions added with `add_mpu_region()` and
+        // `add_readonly_mpu_region()` are gone with the old configuration.
+        for region in self.mpu_regions.iter() {
+            region.set(None);
+        }
 
         // Allocate MPU region for flash.
         let app_mpu_flash = self.chip.mpu().allocate_region(
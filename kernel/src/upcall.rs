@@ -8,6 +8,8 @@ use core::ptr::NonNull;
 
 use crate::config;
 use crate::debug;
+use crate::grant;
+use crate::kernel::Kernel;
 use crate::process;
 use crate::process::ProcessId;
 use crate::syscall::SyscallReturn;
@@ -220,3 +222,39 @@ impl Upcall {
         }
     }
 }
+
+/// Schedules upcalls into processes as if the driver they subscribed to had
+/// scheduled them, so developers can test how apps handle unusual upcalls.
+///
+/// Obtained with [`Kernel::upcall_injector`], which requires the
+/// `UpcallInjectionCapability`.
+#[derive(Clone, Copy)]
+pub struct UpcallInjector {
+    pub(crate) kernel: &'static Kernel,
+}
+
+impl UpcallInjector {
+    /// Schedule the upcall `processid` subscribed to with `subscribe_num` of
+    /// driver `driver_num`, with the arguments `r`.
+    ///
+    /// Returns `INVAL` if the process does not exist, `NOSUPPORT` if the
+    /// driver has no such upcall, and `NOMEM` if the process has no grant for
+    /// the driver or its task queue is full.
+    pub fn schedule(
+        &self,
+        processid: ProcessId,
+        driver_num: usize,
+        subscribe_num: usize,
+        r: (usize, usize, usize),
+    ) -> Result<(), ErrorCode> {
+        let process = self.kernel.get_process(processid).ok_or(ErrorCode::INVAL)?;
+        grant::schedule_subscribed_upcall(
+            process,
+            UpcallId {
+                driver_num,
+                subscribe_num,
+            },
+            r,
+        )
+    }
+}
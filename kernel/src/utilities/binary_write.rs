@@ -167,3 +167,34 @@ impl<'a> BinaryWrite for BinaryToWriteWrapper<'a> {
         Ok(buffer.len())
     }
 }
+
+/// Provide `BinaryWrite` and `core::fmt::Write` interfaces on top of a
+/// synchronous [`IoWrite`](crate::debug::IoWrite) sink.
+///
+/// This lets a [`ProcessPrinter`](crate::process::ProcessPrinter) and
+/// `print_full_process()` write process state to any sink, for example a
+/// buffer that is saved to nonvolatile storage, not just to a console.
+pub struct IoWriteToBinaryWrapper<'a> {
+    writer: &'a mut dyn crate::debug::IoWrite,
+}
+
+impl<'a> IoWriteToBinaryWrapper<'a> {
+    pub fn new(writer: &'a mut dyn crate::debug::IoWrite) -> IoWriteToBinaryWrapper<'a> {
+        IoWriteToBinaryWrapper { writer }
+    }
+}
+
+impl BinaryWrite for IoWriteToBinaryWrapper<'_> {
+    fn write_buffer(&mut self, buffer: &[u8]) -> Result<usize, ()> {
+        Ok(self.writer.write(buffer))
+    }
+}
+
+impl core::fmt::Write for IoWriteToBinaryWrapper<'_> {
+    fn write_str(&mut self, s: &str) -> core::fmt::Result {
+        // Output that does not fit in the sink is dropped, as it would be by
+        // a console.
+        self.writer.write(s.as_bytes());
+        Ok(())
+    }
+}
@@ -0,0 +1,118 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2024.
+
+//! A pool of static buffers that several capsules borrow from.
+//!
+//! Capsules that only need a buffer while an operation is in progress, like
+//! storage drivers, can share a pool instead of each holding a buffer sized
+//! for their largest transfer. The board chooses how many buffers the pool
+//! holds and how large each one is.
+//!
+//! ```ignore
+//! let buffers = static_init!(
+//!     [TakeCell<'static, [u8]>; 2],
+//!     [
+//!         TakeCell::new(static_init!([u8; 256], [0; 256])),
+//!         TakeCell::new(static_init!([u8; 2048], [0; 2048])),
+//!     ]
+//! );
+//! let pool = static_init!(BufferPool, BufferPool::new(buffers));
+//! ```
+
+use crate::utilities::cells::TakeCell;
+
+/// Lends out a fixed set of static buffers.
+pub struct BufferPool {
+    buffers: &'static [TakeCell<'static, [u8]>],
+    max_len: usize,
+}
+
+impl BufferPool {
+    /// Create a pool of `buffers`, which must all be present.
+    pub fn new(buffers: &'static [TakeCell<'static, [u8]>]) -> BufferPool {
+        let max_len = buffers
+            .iter()
+            .filter_map(|buffer| buffer.map(|buffer| buffer.len()))
+            .max()
+            .unwrap_or(0);
+        BufferPool { buffers, max_len }
+    }
+
+    /// Borrow the smallest free buffer that holds at least `len` bytes.
+    /// Returns `None` if no free buffer is that large.
+    pub fn take(&self, len: usize) -> Option<&'static mut [u8]> {
+        let mut best: Option<(usize, usize)> = None;
+        for (index, buffer) in self.buffers.iter().enumerate() {
+            let buffer_len = match buffer.map(|buffer| buffer.len()) {
+                Some(buffer_len) if buffer_len >= len => buffer_len,
+                _ => continue,
+            };
+            if best.map_or(true, |(_, best_len)| buffer_len < best_len) {
+                best = Some((index, buffer_len));
+            }
+        }
+        best.and_then(|(index, _)| self.buffers[index].take())
+    }
+
+    /// Return a borrowed buffer to the pool.
+    ///
+    /// The buffer takes the first empty slot. A buffer that did not come
+    /// from this pool is dropped if the pool has no empty slot for it.
+    pub fn put(&self, buffer: &'static mut [u8]) {
+        if let Some(slot) = self.buffers.iter().find(|slot| slot.is_none()) {
+            slot.replace(buffer);
+        }
+    }
+
+    /// Length of the largest buffer in the pool, whether or not it is lent
+    /// out right now.
+    pub fn max_len(&self) -> usize {
+        self.max_len
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use super::BufferPool;
+    use crate::utilities::cells::TakeCell;
+    use std::boxed::Box;
+    use std::vec;
+
+    fn pool(lengths: &[usize]) -> BufferPool {
+        let buffers: std::vec::Vec<TakeCell<'static, [u8]>> = lengths
+            .iter()
+            .map(|&len| TakeCell::new(Box::leak(vec![0; len].into_boxed_slice())))
+            .collect();
+        BufferPool::new(Box::leak(buffers.into_boxed_slice()))
+    }
+
+    #[test]
+    fn test_take_smallest_fitting() {
+        let pool = pool(&[512, 64, 2048]);
+        assert_eq!(pool.take(100).map(|buffer| buffer.len()), Some(512));
+        assert_eq!(pool.take(100).map(|buffer| buffer.len()), Some(2048));
+        assert!(pool.take(100).is_none());
+        assert_eq!(pool.take(64).map(|buffer| buffer.len()), Some(64));
+    }
+
+    #[test]
+    fn test_take_none_if_none_fits() {
+        let pool = pool(&[64, 256, 128]);
+        assert!(pool.take(1000).is_none());
+        assert_eq!(pool.take(256).map(|buffer| buffer.len()), Some(256));
+        assert!(pool.take(256).is_none());
+    }
+
+    #[test]
+    fn test_put_back() {
+        let pool = pool(&[64, 256]);
+        let buffer = pool.take(200).unwrap();
+        assert!(pool.take(200).is_none());
+        pool.put(buffer);
+        assert_eq!(pool.take(200).map(|buffer| buffer.len()), Some(256));
+        assert_eq!(pool.max_len(), 256);
+    }
+}